@@ -0,0 +1,125 @@
+//! Compares the evaluation of transition conditions and \<assign\> with and without precompilation,
+//! for the ECMAScript and the rFSM expression datamodel.\
+//! Without precompilation the scripts are parsed on each execution (as for scripts without source id).\
+//! Run with "cargo bench --bench precompile".
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use rfsm::datamodel::{create_global_data_arc, Data, Datamodel, SourceCode};
+use rfsm::executable_content::{Assign, ExecutableContent, TYPE_ASSIGN};
+use rfsm::fsm::{create_datamodel, Fsm};
+use rfsm::scxml_reader;
+
+const ITERATIONS: u32 = 20_000;
+
+const CONDITION: &str = "count &lt; 1000000000 &amp;&amp; (count % 2 == 0 || count &gt;= 0)";
+
+fn document(datamodel: &str) -> String {
+    format!(
+        r##"<scxml initial='Main' datamodel='{datamodel}'>
+          <datamodel><data id='count' expr='0'/></datamodel>
+          <state id='Main'>
+            <transition cond='{CONDITION}'>
+              <assign location='count' expr='count * 3 % 7 + (count + 1) - count * 3 % 7'/>
+            </transition>
+          </state>
+        </scxml>"##
+    )
+}
+
+/// Removes the source id, so the datamodel doesn't use the compiled form.
+fn without_source_id(data: &Data) -> Data {
+    match data {
+        Data::Source(source) => Data::Source(SourceCode::new(&source.source, 0)),
+        other => other.clone(),
+    }
+}
+
+struct Bench {
+    fsm: Box<Fsm>,
+    datamodel: Box<dyn Datamodel>,
+    condition: Data,
+    assign: Assign,
+}
+
+impl Bench {
+    fn new(datamodel: &str, precompiled: bool) -> Bench {
+        let mut fsm = scxml_reader::parse_from_xml(document(datamodel)).unwrap();
+        let mut datamodel = create_datamodel(&fsm.datamodel, create_global_data_arc(), &HashMap::new());
+        let root = fsm.pseudo_root;
+        datamodel.initializeDataModel(&mut fsm, root, true);
+
+        let transition = fsm
+            .transitions
+            .values()
+            .find(|t| !t.cond.is_empty())
+            .unwrap();
+        let assign = fsm
+            .executableContent
+            .values()
+            .flatten()
+            .find(|ec| ec.get_type() == TYPE_ASSIGN)
+            .and_then(|ec| ec.as_any().downcast_ref::<Assign>())
+            .unwrap()
+            .clone();
+        let (condition, assign) = if precompiled {
+            datamodel.precompile(&fsm);
+            (transition.cond.clone(), assign)
+        } else {
+            let mut uncompiled = assign.clone();
+            uncompiled.location = without_source_id(&assign.location);
+            uncompiled.expr = without_source_id(&assign.expr);
+            (without_source_id(&transition.cond), uncompiled)
+        };
+        Bench {
+            fsm,
+            datamodel,
+            condition,
+            assign,
+        }
+    }
+
+    fn conditions(&mut self) -> Duration {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            assert_eq!(self.datamodel.execute_condition(&self.condition), Ok(true));
+        }
+        start.elapsed()
+    }
+
+    fn assigns(&mut self) -> Duration {
+        let start = Instant::now();
+        for _ in 0..ITERATIONS {
+            assert!(self.assign.execute(self.datamodel.as_mut(), &self.fsm));
+        }
+        start.elapsed()
+    }
+}
+
+fn report(name: &str, parsed: Duration, compiled: Duration) {
+    println!(
+        "{:<32} parsed {:>8.2}us  precompiled {:>8.2}us  speedup {:>5.1}x",
+        name,
+        parsed.as_secs_f64() * 1e6 / ITERATIONS as f64,
+        compiled.as_secs_f64() * 1e6 / ITERATIONS as f64,
+        parsed.as_secs_f64() / compiled.as_secs_f64()
+    );
+}
+
+fn main() {
+    for datamodel in ["ecmascript", "rfsm-expression"] {
+        let mut parsed = Bench::new(datamodel, false);
+        let mut compiled = Bench::new(datamodel, true);
+        report(
+            &format!("{} conditionMatch", datamodel),
+            parsed.conditions(),
+            compiled.conditions(),
+        );
+        report(
+            &format!("{} <assign>", datamodel),
+            parsed.assigns(),
+            compiled.assigns(),
+        );
+    }
+}
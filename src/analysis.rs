@@ -0,0 +1,361 @@
+//! Static analysis of a FSM model, in addition to [crate::validation].\
+//! Reports findings that are legal SCXML, but probably not intended:
+//! + States that can't be entered from the initial configuration.
+//! + Transitions that can never be selected, because an earlier transition of the same state
+//!   without condition matches the same events.
+//! + Event descriptors of transitions that match no event that is raised or sent by the document.
+//!
+//! The datamodel is not executed, all conditions are treated as possibly true or false.
+//! Events that are generated by the platform ("done.\*" and "error.\*") are always matched.
+//! Events that are sent to the session from outside are not known, so they are also reported
+//! as unmatched. Use [analyze] to get the [AnalysisReport].
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::executable_content::{Raise, SendParameters, TYPE_RAISE, TYPE_SEND};
+use crate::fsm::{event_descriptor_matches, Fsm, HistoryType, StateId, Transition, TransitionId};
+
+/// Prefixes of the events that are generated by the platform.
+const PLATFORM_EVENT_PREFIXES: &[&str] = &["done", "error"];
+
+/// Result of [analyze].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AnalysisReport {
+    /// States that can't be entered, in document order.
+    pub unreachable_states: Vec<StateId>,
+    /// Transitions that can never be selected, in document order.
+    pub shadowed_transitions: Vec<TransitionId>,
+    /// Event descriptors of transitions that match no raised or sent event, sorted.
+    pub unmatched_events: Vec<String>,
+    /// Descriptions of the reported states and transitions, used for [Display].
+    state_descriptions: HashMap<StateId, String>,
+    transition_descriptions: HashMap<TransitionId, String>,
+}
+
+impl AnalysisReport {
+    /// True if nothing was found.
+    pub fn is_empty(&self) -> bool {
+        self.unreachable_states.is_empty() && self.shadowed_transitions.is_empty() && self.unmatched_events.is_empty()
+    }
+}
+
+impl Display for AnalysisReport {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No findings");
+        }
+        for sid in &self.unreachable_states {
+            writeln!(
+                f,
+                "Unreachable state {}",
+                self.state_descriptions
+                    .get(sid)
+                    .cloned()
+                    .unwrap_or_default()
+            )?;
+        }
+        for tid in &self.shadowed_transitions {
+            writeln!(
+                f,
+                "Shadowed transition {}",
+                self.transition_descriptions
+                    .get(tid)
+                    .cloned()
+                    .unwrap_or_default()
+            )?;
+        }
+        for event in &self.unmatched_events {
+            writeln!(f, "Event '{}' is never raised or sent", event)?;
+        }
+        Ok(())
+    }
+}
+
+/// Collects the states that can be entered.
+struct Reachability<'a> {
+    fsm: &'a Fsm,
+    shadowed: &'a HashSet<TransitionId>,
+    reachable: HashSet<StateId>,
+    /// States whose default entry was already followed.
+    default_entered: HashSet<StateId>,
+}
+
+impl Reachability<'_> {
+    fn is_ancestor(&self, ancestor: StateId, state: StateId) -> bool {
+        let mut current = state;
+        while current != 0 {
+            if current == ancestor {
+                return true;
+            }
+            current = self.fsm.get_state_by_id(current).parent;
+        }
+        false
+    }
+
+    /// Marks the state as reachable and follows its transitions.
+    fn mark(&mut self, sid: StateId) {
+        if !self.reachable.insert(sid) {
+            return;
+        }
+        let fsm = self.fsm;
+        for tid in fsm.get_state_by_id(sid).transitions.iterator() {
+            if !self.shadowed.contains(tid) {
+                self.enter_targets(fsm.get_transition_by_id(*tid));
+            }
+        }
+    }
+
+    fn enter_targets(&mut self, transition: &Transition) {
+        for target in &transition.target {
+            self.enter(*target);
+        }
+    }
+
+    /// The state is the target of a transition: the state and its ancestors are entered, other
+    /// regions of parallel ancestors get their default entry.
+    fn enter(&mut self, sid: StateId) {
+        let fsm = self.fsm;
+        let state = fsm.get_state_by_id(sid);
+        if state.history_type != HistoryType::None {
+            // States that are restored by the history are already reachable, only the default
+            // transition can add new states.
+            for tid in state.transitions.iterator() {
+                self.enter_targets(fsm.get_transition_by_id(*tid));
+            }
+            return;
+        }
+        self.default_enter(sid);
+        let mut child = sid;
+        let mut ancestor = state.parent;
+        while ancestor != 0 {
+            self.mark(ancestor);
+            let ancestor_state = fsm.get_state_by_id(ancestor);
+            if ancestor_state.is_parallel {
+                for region in &ancestor_state.states {
+                    if !self.is_ancestor(*region, child) {
+                        self.default_enter(*region);
+                    }
+                }
+            }
+            child = ancestor;
+            ancestor = ancestor_state.parent;
+        }
+    }
+
+    /// The state is entered without explicit target inside: compound states enter their initial
+    /// states, parallel states all regions.
+    fn default_enter(&mut self, sid: StateId) {
+        self.mark(sid);
+        if !self.default_entered.insert(sid) {
+            return;
+        }
+        let fsm = self.fsm;
+        let state = fsm.get_state_by_id(sid);
+        if state.is_parallel {
+            for region in &state.states {
+                self.default_enter(*region);
+            }
+        } else if state.initial != 0 {
+            self.enter_targets(fsm.get_transition_by_id(state.initial));
+        }
+    }
+}
+
+/// True if every event that selects "later" also selects "earlier".
+fn covers(earlier: &Transition, later: &Transition) -> bool {
+    let eventless = |t: &Transition| !t.wildcard && t.events.is_empty();
+    if eventless(earlier) || eventless(later) {
+        return eventless(earlier) && eventless(later);
+    }
+    earlier.wildcard
+        || (!later.wildcard
+            && later.events.iter().all(|name| {
+                earlier
+                    .events
+                    .iter()
+                    .any(|descriptor| event_descriptor_matches(descriptor, name))
+            }))
+}
+
+fn find_shadowed_transitions(fsm: &Fsm) -> Vec<(TransitionId, TransitionId)> {
+    let mut shadowed = Vec::new();
+    for state in &fsm.states {
+        let mut transitions: Vec<&Transition> = state
+            .transitions
+            .iterator()
+            .map(|tid| fsm.get_transition_by_id(*tid))
+            .collect();
+        transitions.sort_by_key(|t| t.doc_id);
+        for (idx, later) in transitions.iter().enumerate() {
+            if let Some(earlier) = transitions[..idx]
+                .iter()
+                .find(|earlier| earlier.cond.is_empty() && covers(earlier, later))
+            {
+                shadowed.push((later.id, earlier.id));
+            }
+        }
+    }
+    shadowed
+}
+
+fn find_unmatched_events(fsm: &Fsm) -> Vec<String> {
+    let mut produced = Vec::new();
+    for content in fsm.executableContent.values() {
+        for ec in content {
+            let any = ec.as_ref().as_any();
+            match ec.get_type() {
+                TYPE_RAISE => produced.extend(any.downcast_ref::<Raise>().map(|r| r.event.clone())),
+                TYPE_SEND => produced.extend(
+                    any.downcast_ref::<SendParameters>()
+                        .filter(|s| !s.event.is_empty())
+                        .map(|s| s.event.to_string()),
+                ),
+                _ => {}
+            }
+        }
+    }
+    let mut unmatched: Vec<String> = fsm
+        .transitions
+        .values()
+        .flat_map(|t| t.events.iter())
+        .filter(|descriptor| descriptor.as_str() != "*")
+        .filter(|descriptor| {
+            !PLATFORM_EVENT_PREFIXES
+                .iter()
+                .any(|prefix| event_descriptor_matches(prefix, descriptor))
+        })
+        .filter(|descriptor| {
+            !produced
+                .iter()
+                .any(|name| event_descriptor_matches(descriptor, name))
+        })
+        .cloned()
+        .collect::<HashSet<String>>()
+        .into_iter()
+        .collect();
+    unmatched.sort();
+    unmatched
+}
+
+/// Analyzes the FSM, see [crate::analysis].
+pub fn analyze(fsm: &Fsm) -> AnalysisReport {
+    let mut report = AnalysisReport::default();
+    let state_description = |sid: StateId| {
+        let state = fsm.get_state_by_id(sid);
+        format!("'{}' (line {})", state.name, state.source_line)
+    };
+
+    let shadowed = find_shadowed_transitions(fsm);
+    let mut shadowed_transitions: Vec<&Transition> = shadowed
+        .iter()
+        .map(|(tid, _)| fsm.get_transition_by_id(*tid))
+        .collect();
+    shadowed_transitions.sort_by_key(|t| t.doc_id);
+    for (tid, by) in &shadowed {
+        let transition = fsm.get_transition_by_id(*tid);
+        report.transition_descriptions.insert(
+            *tid,
+            format!(
+                "of {} (line {}), shadowed by line {}",
+                state_description(transition.source),
+                transition.source_line,
+                fsm.get_transition_by_id(*by).source_line
+            ),
+        );
+    }
+    report.shadowed_transitions = shadowed_transitions.iter().map(|t| t.id).collect();
+
+    if fsm.pseudo_root != 0 {
+        let shadowed_ids: HashSet<TransitionId> = report.shadowed_transitions.iter().cloned().collect();
+        let mut reachability = Reachability {
+            fsm,
+            shadowed: &shadowed_ids,
+            reachable: HashSet::new(),
+            default_entered: HashSet::new(),
+        };
+        reachability.default_enter(fsm.pseudo_root);
+        let mut unreachable: Vec<StateId> = fsm
+            .states
+            .iter()
+            .filter(|s| s.doc_id != 0 && s.history_type == HistoryType::None)
+            .filter(|s| !reachability.reachable.contains(&s.id))
+            .map(|s| s.id)
+            .collect();
+        unreachable.sort_by_key(|sid| fsm.get_state_by_id(*sid).doc_id);
+        for sid in &unreachable {
+            report
+                .state_descriptions
+                .insert(*sid, state_description(*sid));
+        }
+        report.unreachable_states = unreachable;
+    }
+
+    report.unmatched_events = find_unmatched_events(fsm);
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use crate::analysis::analyze;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn unreachable_states_and_shadowed_transitions_are_reported() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A' datamodel='null'>
+              <state id='A'>
+                <onentry><raise event='go'/><raise event='go.fast'/><send event='stop.now'/></onentry>
+                <transition event='go' target='P'/>
+                <transition event='go.fast' target='Hidden'/>
+                <transition event='stop' cond='false' target='Done'/>
+                <transition event='stop' target='Done'/>
+                <transition event='error' target='Done'/>
+              </state>
+              <parallel id='P'>
+                <state id='R1'><transition event='goo' target='Done'/></state>
+                <state id='R2' initial='R2b'>
+                  <state id='R2a'/>
+                  <state id='R2b'/>
+                </state>
+              </parallel>
+              <state id='Hidden'/>
+              <state id='Unused'>
+                <transition target='Done'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let report = analyze(&fsm);
+        let names = |ids: &[u32]| -> Vec<String> {
+            ids.iter()
+                .map(|id| fsm.get_state_by_id(*id).name.clone())
+                .collect()
+        };
+        assert_eq!(
+            names(&report.unreachable_states),
+            vec!["R2a", "Hidden", "Unused"]
+        );
+
+        assert_eq!(report.shadowed_transitions.len(), 1);
+        let shadowed = fsm.get_transition_by_id(report.shadowed_transitions[0]);
+        assert_eq!(shadowed.events, vec!["go.fast"]);
+        assert_eq!(report.unmatched_events, vec!["goo"]);
+
+        let text = report.to_string();
+        assert!(text.contains("Unreachable state 'Hidden'"), "{}", text);
+        assert!(text.contains("Shadowed transition of 'A'"), "{}", text);
+        assert!(
+            text.contains("Event 'goo' is never raised or sent"),
+            "{}",
+            text
+        );
+    }
+}
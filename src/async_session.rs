@@ -0,0 +1,144 @@
+//! Async facade of [ScxmlSession] for tokio based applications.\
+//! The FSM itself is still executed by its own thread (or by the thread pool of the executor),
+//! this module only bridges the std channels of the session to tokio without blocking the runtime.
+//!
+//! ```ignore
+//! let session = AsyncScxmlSession::new(start_fsm(fsm, actions, executor));
+//! let mut updates = session.configuration_updates(100);
+//! session.send(Box::new(Event::new_simple("go"))).await?;
+//! let result = session.wait_done().await;
+//! ```
+
+use std::sync::mpsc::sync_channel;
+use std::thread;
+
+use tokio::sync::mpsc;
+
+use crate::fsm::{ConfigurationUpdate, Event, ScxmlSession, SessionId, SessionResult};
+
+/// Wraps a started [ScxmlSession].
+#[derive(Debug)]
+pub struct AsyncScxmlSession {
+    session: ScxmlSession,
+}
+
+impl AsyncScxmlSession {
+    pub fn new(session: ScxmlSession) -> AsyncScxmlSession {
+        AsyncScxmlSession { session }
+    }
+
+    pub fn session_id(&self) -> SessionId {
+        self.session.session_id
+    }
+
+    /// The wrapped session, e.g. to take snapshots.
+    pub fn session(&self) -> &ScxmlSession {
+        &self.session
+    }
+
+    /// Puts the event into the external queue of the session.\
+    /// The external queue is unbounded, so this never waits.
+    /// Returns an error if the session has gone.
+    pub async fn send(&self, event: Box<Event>) -> Result<(), String> {
+        self.session
+            .sender
+            .send(event)
+            .map_err(|e| format!("Failed to send event: {}", e))
+    }
+
+    /// Waits until the FSM has finished.\
+    /// The final configuration is only available if the session was started with
+    /// [crate::fsm::FinishMode::KEEP_CONFIGURATION].
+    pub async fn wait_done(&self) -> SessionResult {
+        self.session.completion.wait_async().await
+    }
+
+    /// Subscribes to changes of the active configuration, see [ScxmlSession::on_configuration_change].\
+    /// Up to "capacity" updates are buffered, further updates are dropped by the interpreter.
+    /// The stream ends after the FSM has finished.
+    pub fn configuration_updates(&self, capacity: usize) -> mpsc::Receiver<ConfigurationUpdate> {
+        let capacity = capacity.max(1);
+        let (std_sender, std_receiver) = sync_channel(capacity);
+        let (sender, receiver) = mpsc::channel(capacity);
+        self.session.on_configuration_change(std_sender);
+        thread::Builder::new()
+            .name(format!("fsm_updates_{}", self.session.session_id))
+            .spawn(move || {
+                // Ends if the FSM has finished or the receiver was dropped.
+                while let Ok(update) = std_receiver.recv() {
+                    if sender.blocking_send(update).is_err() {
+                        break;
+                    }
+                }
+            })
+            .unwrap();
+        receiver
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::async_session::AsyncScxmlSession;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, FinishMode};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[tokio::test]
+    #[cfg(feature = "xml")]
+    async fn session_is_driven_to_completion() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Idle' datamodel='null'>
+              <state id='Idle'>
+                <transition event='start' target='Running'/>
+              </state>
+              <state id='Running'>
+                <transition event='stop' target='Done'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = AsyncScxmlSession::new(start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        ));
+        let mut updates = session.configuration_updates(10);
+
+        session
+            .send(Box::new(Event::new_simple("start")))
+            .await
+            .unwrap();
+        session
+            .send(Box::new(Event::new_simple("stop")))
+            .await
+            .unwrap();
+
+        let result = session.wait_done().await;
+        assert!(result.final_state_reached);
+        assert_eq!(result.final_configuration, Some(vec!["Done".to_string()]));
+
+        let mut entered = Vec::new();
+        while let Some(update) = updates.recv().await {
+            entered.extend(update.entered);
+        }
+        // The subscription starts after the FSM was started, "Idle" may or may not be included.
+        assert!(
+            entered.ends_with(&["Running".to_string(), "Done".to_string()]),
+            "{:?}",
+            entered
+        );
+
+        // A finished session can still be awaited.
+        assert!(session.wait_done().await.final_state_reached);
+    }
+}
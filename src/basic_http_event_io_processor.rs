@@ -14,6 +14,7 @@ use std::sync::mpsc;
 use std::sync::mpsc::channel;
 use std::sync::{atomic::AtomicBool, Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 #[cfg(test)]
 use std::{println as debug, println as info, println as error};
 
@@ -25,14 +26,41 @@ use hyper::{Request, Response};
 use hyper_util::rt::TokioIo;
 #[cfg(not(test))]
 use log::{debug, error, info};
+use serde_json::{Map, Number, Value};
 use tokio::net::TcpListener;
 
-use crate::datamodel::{GlobalDataArc, BASIC_HTTP_EVENT_PROCESSOR};
+use crate::datamodel::{create_data_arc, data_to_string, Data, Datamodel, GlobalDataArc, BASIC_HTTP_EVENT_PROCESSOR};
 use crate::event_io_processor::{EventIOProcessor, EventIOProcessorHandle};
-use crate::fsm::{Event, SessionId};
+use crate::fsm::{Event, EventSender, Fsm, ParamPair, SessionId};
+use crate::get_global;
 
 pub const SCXML_EVENT_NAME: &str = "_scxmleventname";
 
+/// Reserved \<param\> name to select the encoding of a \<send\>.
+/// If the value is [CONTENT_TYPE_JSON], the event data is posted as JSON object,
+/// otherwise the default "application/x-www-form-urlencoded" is used.\
+/// The parameter itself is not sent.
+pub const SCXML_CONTENT_TYPE: &str = "_scxmlcontenttype";
+
+/// Member of JSON bodies that contains the \<content\> of the event.
+pub const SCXML_CONTENT: &str = "_scxmlcontent";
+
+pub const CONTENT_TYPE_JSON: &str = "application/json";
+pub const CONTENT_TYPE_FORM: &str = "application/x-www-form-urlencoded";
+
+/// Default name of the event that reports the response of a \<send\>, see [BasicHTTPEventIOProcessor::response_event].
+pub const RESPONSE_EVENT: &str = "HTTP.response";
+
+/// Parameter of the response event and of error.communication with the http status code.
+pub const RESPONSE_PARAM_STATUS: &str = "status";
+
+/// Parameter of the response event and of error.communication with the body of the response.\
+/// JSON bodies are converted to data, other bodies are passed as string.
+pub const RESPONSE_PARAM_BODY: &str = "body";
+
+/// Default timeout of outgoing requests.
+pub const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
 /// IO Processor to server basic http request. \
 /// See /doc/W3C_SCXML_2024_07_13/index.html#BasicHTTPEventProcessor \
 /// If the feature is active, this IO Processor is automatically added by FsmExecutor.
@@ -41,20 +69,81 @@ pub struct BasicHTTPEventIOProcessor {
     pub terminate_flag: Arc<AtomicBool>,
     pub state: Arc<Mutex<BasicHTTPEventIOProcessorServerData>>,
     pub handle: EventIOProcessorHandle,
+    /// If set, the response of a successful \<send\> is put as event with this name into the external
+    /// queue of the sending session. The event has the sendid of the \<send\> and the parameters
+    /// [RESPONSE_PARAM_STATUS] and [RESPONSE_PARAM_BODY]. Not set by default.
+    pub response_event: Option<String>,
+    /// Timeout of outgoing requests. If elapsed, error.communication is raised.
+    pub timeout: Duration,
 }
 
 #[derive(Debug, Clone)]
 pub struct BasicHTTPEventIOProcessorServerData {
     pub location: String,
     pub local_adr: SocketAddr,
+    /// The sessions that can be addressed by requests. Key: session id.
+    pub fsms: HashMap<SessionId, EventSender>,
 }
 
 /// The parsed payload of a http request
 #[derive(Debug, Clone)]
-#[allow(unused)]
 struct Message {
     pub event: String,
     pub session: String,
+    pub params: Vec<ParamPair>,
+    pub content: Option<Data>,
+}
+
+impl Message {
+    fn to_event(&self) -> Event {
+        let mut event = Event::new_simple(self.event.as_str());
+        event.origin_type = Some(BASIC_HTTP_EVENT_PROCESSOR.to_string());
+        if !self.params.is_empty() {
+            event.param_values = Some(self.params.clone());
+        }
+        event.content = self.content.as_ref().map(|c| create_data_arc(c.clone()));
+        event
+    }
+}
+
+/// Converts a JSON value to data.
+pub fn json_to_data(value: &Value) -> Data {
+    match value {
+        Value::Null => Data::Null(),
+        Value::Bool(b) => Data::Boolean(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => Data::Integer(i),
+            None => Data::Double(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => Data::String(s.clone()),
+        Value::Array(a) => Data::Array(a.iter().map(|v| create_data_arc(json_to_data(v))).collect()),
+        Value::Object(o) => Data::Map(
+            o.iter()
+                .map(|(k, v)| (k.clone(), create_data_arc(json_to_data(v))))
+                .collect(),
+        ),
+    }
+}
+
+/// Converts data to a JSON value.
+pub fn data_to_json(data: &Data) -> Value {
+    match data {
+        Data::Integer(i) => Value::Number(Number::from(*i)),
+        Data::Double(d) => match Number::from_f64(*d) {
+            Some(n) => Value::Number(n),
+            None => Value::Null,
+        },
+        Data::String(s) => Value::String(s.clone()),
+        Data::Boolean(b) => Value::Bool(*b),
+        Data::Array(a) => Value::Array(a.iter().map(|v| data_to_json(&v.lock().unwrap())).collect()),
+        Data::Map(m) => Value::Object(
+            m.iter()
+                .map(|(k, v)| (k.clone(), data_to_json(&v.lock().unwrap())))
+                .collect(),
+        ),
+        Data::Source(s) => Value::String(s.as_str().to_string()),
+        Data::Null() | Data::None() | Data::Error(_) => Value::Null,
+    }
 }
 
 /// Event processed by the message thread of the processor.
@@ -84,16 +173,35 @@ impl BasicHTTPEvent {
             return Err(hyper::StatusCode::BAD_REQUEST);
         }
 
-        let query_params: HashMap<Cow<str>, Cow<str>>;
+        let mut query_params: HashMap<Cow<str>, Cow<str>> = HashMap::new();
+        let mut json_params: Map<String, Value> = Map::new();
         let db;
 
         match parts.method {
             hyper::Method::POST => {
                 // Mandatory POST implementation
+                let is_json = match parts.headers.get(hyper::header::CONTENT_TYPE) {
+                    None => false,
+                    Some(ct) => ct
+                        .to_str()
+                        .is_ok_and(|ct| ct.starts_with(CONTENT_TYPE_JSON)),
+                };
                 match body.collect().await {
                     Ok(data) => {
                         db = data.to_bytes();
-                        query_params = form_urlencoded::parse(db.as_ref()).collect();
+                        if is_json {
+                            match serde_json::from_slice::<Value>(db.as_ref()) {
+                                Ok(Value::Object(o)) => {
+                                    json_params = o;
+                                }
+                                _ => {
+                                    error!("Body is not a JSON object");
+                                    return Err(hyper::StatusCode::BAD_REQUEST);
+                                }
+                            }
+                        } else {
+                            query_params = form_urlencoded::parse(db.as_ref()).collect();
+                        }
                     }
                     Err(_e) => {
                         return Err(hyper::StatusCode::BAD_REQUEST);
@@ -113,18 +221,36 @@ impl BasicHTTPEvent {
         }
 
         debug!("Query Parameters {:?}", query_params);
+        debug!("JSON Parameters {:?}", json_params);
 
         let event_name = match query_params.get(SCXML_EVENT_NAME) {
-            None => "",
-            Some(event_name) => {
-                debug!("Event Name {:?}", event_name);
-                event_name
-            }
+            None => match json_params.remove(SCXML_EVENT_NAME) {
+                Some(Value::String(event_name)) => event_name,
+                _ => "".to_string(),
+            },
+            Some(event_name) => event_name.to_string(),
         };
+        debug!("Event Name {:?}", event_name);
+
+        let content = json_params.remove(SCXML_CONTENT).map(|c| json_to_data(&c));
+        let mut params = Vec::new();
+        for (name, value) in &query_params {
+            if name.ne(SCXML_EVENT_NAME) {
+                params.push(ParamPair::new_moved(
+                    name.to_string(),
+                    Data::String(value.to_string()),
+                ));
+            }
+        }
+        for (name, value) in &json_params {
+            params.push(ParamPair::new_moved(name.clone(), json_to_data(value)));
+        }
 
         let msg = Message {
-            event: event_name.to_string(),
+            event: event_name,
             session: path,
+            params,
+            content,
         };
         Ok(BasicHTTPEvent::Message(msg))
     }
@@ -172,7 +298,14 @@ impl BasicHTTPEventIOProcessor {
 
         info!("HTTP server starting");
 
+        let state = Arc::new(Mutex::new(BasicHTTPEventIOProcessorServerData {
+            location: format!("http://{}:{}", location_name, port),
+            local_adr: addr,
+            fsms: HashMap::new(),
+        }));
+
         let inner_terminate_flag = terminate_flag.clone();
+        let inner_state = state.clone();
         let (sender, receiver_server) = channel::<Box<BasicHTTPEvent>>();
 
         let _thread_message_server = thread::spawn(move || {
@@ -182,14 +315,25 @@ impl BasicHTTPEventIOProcessor {
                 let event_opt = receiver_server.recv();
                 c += 1;
                 match event_opt {
-                    Ok(event) => {
-                        match event.deref() {
-                            BasicHTTPEvent::Message(message) => {
-                                debug!("BasicHTTPEvent:Message #{} {:?}", c, message);
-                                // TODO: Sending event to session
+                    Ok(event) => match event.deref() {
+                        BasicHTTPEvent::Message(message) => {
+                            debug!("BasicHTTPEvent:Message #{} {:?}", c, message);
+                            let session_sender = match message.session.parse::<SessionId>() {
+                                Ok(session_id) => inner_state.lock().unwrap().fsms.get(&session_id).cloned(),
+                                Err(_) => None,
+                            };
+                            match session_sender {
+                                None => {
+                                    error!("Unknown session '{}'", message.session);
+                                }
+                                Some(session_sender) => {
+                                    if let Err(err) = session_sender.send(Box::new(message.to_event())) {
+                                        error!("Failed to send to session '{}': {}", message.session, err);
+                                    }
+                                }
                             }
                         }
-                    }
+                    },
                     Err(_err) => {
                         debug!("Message server channel disconnected");
                         break;
@@ -231,14 +375,90 @@ impl BasicHTTPEventIOProcessor {
 
         debug!("BasicHTTPServer at {:?}", addr);
 
-        let state = BasicHTTPEventIOProcessorServerData {
-            location: format!("https://{}:{}", location_name, port),
-            local_adr: addr,
-        };
         BasicHTTPEventIOProcessor {
             terminate_flag,
-            state: Arc::new(Mutex::new(state)),
+            state,
             handle: EventIOProcessorHandle::new(),
+            response_event: None,
+            timeout: DEFAULT_SEND_TIMEOUT,
+        }
+    }
+
+    /// Posts the event to the target url, see [EventIOProcessor::send] for the encoding.
+    pub(crate) fn post_event(
+        target: &str,
+        event: &Event,
+        timeout: Duration,
+    ) -> reqwest::Result<reqwest::blocking::Response> {
+        let (body, content_type) = Self::encode_event(event);
+        debug!("POST {} {}: {}", target, content_type, body);
+        reqwest::blocking::Client::new()
+            .post(target)
+            .header(reqwest::header::CONTENT_TYPE, content_type)
+            .timeout(timeout)
+            .body(body)
+            .send()
+    }
+
+    /// Creates the parameters of the response event from the status and the body.
+    pub(crate) fn response_params(response: reqwest::blocking::Response) -> Vec<ParamPair> {
+        let status = response.status().as_u16() as i64;
+        let is_json = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .is_some_and(|ct| ct.starts_with(CONTENT_TYPE_JSON));
+        let text = response.text().unwrap_or_default();
+        let body = match serde_json::from_str::<Value>(text.as_str()) {
+            Ok(value) if is_json => json_to_data(&value),
+            _ => Data::String(text),
+        };
+        vec![
+            ParamPair::new_moved(RESPONSE_PARAM_STATUS.to_string(), Data::Integer(status)),
+            ParamPair::new_moved(RESPONSE_PARAM_BODY.to_string(), body),
+        ]
+    }
+
+    /// Creates the request body and the content type for the event.
+    fn encode_event(event: &Event) -> (String, &'static str) {
+        let mut json = false;
+        let mut params = Vec::new();
+        if let Some(param_values) = &event.param_values {
+            for pair in param_values {
+                if pair.name.eq(SCXML_CONTENT_TYPE) {
+                    json = data_to_string(&pair.value).is_ok_and(|ct| ct.eq(CONTENT_TYPE_JSON));
+                } else {
+                    params.push(pair);
+                }
+            }
+        }
+
+        if json {
+            let mut object = Map::new();
+            object.insert(
+                SCXML_EVENT_NAME.to_string(),
+                Value::String(event.name.clone()),
+            );
+            for pair in params {
+                object.insert(pair.name.clone(), data_to_json(&pair.value));
+            }
+            if let Some(content) = &event.content {
+                object.insert(
+                    SCXML_CONTENT.to_string(),
+                    data_to_json(&content.lock().unwrap()),
+                );
+            }
+            (Value::Object(object).to_string(), CONTENT_TYPE_JSON)
+        } else {
+            let mut serializer = form_urlencoded::Serializer::new(String::new());
+            serializer.append_pair(SCXML_EVENT_NAME, event.name.as_str());
+            for pair in params {
+                serializer.append_pair(
+                    pair.name.as_str(),
+                    data_to_string(&pair.value).unwrap_or_default().as_str(),
+                );
+            }
+            (serializer.finish(), CONTENT_TYPE_FORM)
         }
     }
 }
@@ -259,20 +479,76 @@ impl EventIOProcessor for BasicHTTPEventIOProcessor {
         &mut self.handle
     }
 
+    fn add_fsm(&mut self, _fsm: &Fsm, datamodel: &mut dyn Datamodel) {
+        let global = get_global!(datamodel);
+        let sender = global.externalQueue.sender.clone();
+        self.state
+            .lock()
+            .unwrap()
+            .fsms
+            .insert(global.session_id, sender.clone());
+        self.handle.fsms.insert(global.session_id, sender);
+    }
+
     fn get_copy(&self) -> Box<dyn EventIOProcessor> {
         let b = BasicHTTPEventIOProcessor {
             terminate_flag: self.terminate_flag.clone(),
             state: self.state.clone(),
             handle: self.handle.clone(),
+            response_event: self.response_event.clone(),
+            timeout: self.timeout,
         };
         Box::new(b)
     }
 
-    fn send(&mut self, _global: &GlobalDataArc, _target: &str, _event: Event) -> bool {
+    /// Posts the event to the target url.\
+    /// The event data is form encoded or, if param [SCXML_CONTENT_TYPE] is "application/json", a JSON object.\
+    /// Transport failures, timeouts and responses with a status other than 2xx raise error.communication,
+    /// see [BasicHTTPEventIOProcessor::response_event] for successful responses.
+    fn send(&mut self, global: &GlobalDataArc, target: &str, event: Event) -> bool {
         // W3C basic html processor:
         // If neither the 'target' nor the 'targetexpr' attribute is specified, the SCXML Processor must add the event error.communication to the internal event queue of the sending session.
-
-        todo!()
+        if target.is_empty() {
+            error!("Missing target for BasicHTTP send");
+            global
+                .lock()
+                .unwrap()
+                .enqueue_internal(Event::error_communication(&event));
+            return false;
+        }
+        match Self::post_event(target, &event, self.timeout) {
+            Ok(response) if response.status().is_success() => {
+                if let Some(response_event_name) = &self.response_event {
+                    let mut response_event = Event::new_simple(response_event_name.as_str());
+                    response_event.sendid.clone_from(&event.sendid);
+                    response_event.origin = Some(target.to_string());
+                    response_event.origin_type = Some(BASIC_HTTP_EVENT_PROCESSOR.to_string());
+                    response_event.param_values = Some(Self::response_params(response));
+                    global
+                        .lock()
+                        .unwrap()
+                        .externalQueue
+                        .enqueue(Box::new(response_event));
+                }
+                true
+            }
+            Ok(response) => {
+                error!("Send to {} failed: {}", target, response.status());
+                let mut error_event = Event::error_communication(&event);
+                error_event.param_values = Some(Self::response_params(response));
+                global.lock().unwrap().enqueue_internal(error_event);
+                false
+            }
+            Err(err) => {
+                // Includes the timeout.
+                error!("Send to {} failed: {}", target, err);
+                global
+                    .lock()
+                    .unwrap()
+                    .enqueue_internal(Event::error_communication(&event));
+                false
+            }
+        }
     }
 
     fn shutdown(&mut self) {
@@ -282,3 +558,205 @@ impl EventIOProcessor for BasicHTTPEventIOProcessor {
         self.handle.shutdown();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use std::convert::Infallible;
+    use std::net::SocketAddr;
+    use std::thread;
+
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::{Request, Response};
+    use hyper_util::rt::TokioIo;
+    use tokio::net::TcpListener;
+
+    use crate::basic_http_event_io_processor::{
+        BasicHTTPEventIOProcessor, CONTENT_TYPE_JSON, RESPONSE_EVENT, RESPONSE_PARAM_BODY, RESPONSE_PARAM_STATUS,
+        SCXML_CONTENT_TYPE,
+    };
+    use crate::datamodel::{create_data_arc, create_global_data_arc, Data, BASIC_HTTP_EVENT_PROCESSOR};
+    use crate::event_io_processor::EventIOProcessor;
+    use crate::fsm::{Event, ParamPair};
+
+    /// Sends an event from processor "a" to a session registered at processor "b".
+    async fn round_trip(port_a: u16, port_b: u16, event: Event) -> Event {
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        let mut a = BasicHTTPEventIOProcessor::new(localhost, "localhost", port_a).await;
+        let mut b = BasicHTTPEventIOProcessor::new(localhost, "localhost", port_b).await;
+
+        let (sender, receiver) = channel();
+        b.state.lock().unwrap().fsms.insert(7, sender.into());
+        let target = b.get_location(7);
+
+        // "send" is blocking and must not run on the runtime.
+        let mut a_copy = a.get_copy();
+        let sent = tokio::task::spawn_blocking(move || a_copy.send(&create_global_data_arc(), target.as_str(), event))
+            .await
+            .unwrap();
+        assert!(sent, "Event shall be sent");
+
+        let received = receiver.recv_timeout(Duration::from_millis(2000));
+        a.shutdown();
+        b.shutdown();
+        *received.expect("Event shall be received")
+    }
+
+    fn get_param(event: &Event, name: &str) -> Option<Data> {
+        event
+            .param_values
+            .as_ref()
+            .and_then(|pv| pv.iter().find(|p| p.name == name))
+            .map(|p| p.value.clone())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn form_encoded_round_trip() {
+        let mut event = Event::new_simple("form.event");
+        event.param_values = Some(vec![ParamPair::new("a", &Data::Integer(1))]);
+
+        let received = round_trip(5601, 5602, event).await;
+        assert_eq!(received.name, "form.event");
+        assert_eq!(
+            received.origin_type,
+            Some(BASIC_HTTP_EVENT_PROCESSOR.to_string())
+        );
+        // Form encoding transports strings only.
+        assert_eq!(
+            get_param(&received, "a"),
+            Some(Data::String("1".to_string()))
+        );
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn json_round_trip() {
+        let mut event = Event::new_simple("json.event");
+        event.param_values = Some(vec![
+            ParamPair::new(
+                SCXML_CONTENT_TYPE,
+                &Data::String(CONTENT_TYPE_JSON.to_string()),
+            ),
+            ParamPair::new("a", &Data::Integer(1)),
+            ParamPair::new("b", &Data::Boolean(true)),
+        ]);
+        event.content = Some(create_data_arc(Data::String("Hello".to_string())));
+
+        let received = round_trip(5603, 5604, event).await;
+        assert_eq!(received.name, "json.event");
+        assert_eq!(get_param(&received, "a"), Some(Data::Integer(1)));
+        assert_eq!(get_param(&received, "b"), Some(Data::Boolean(true)));
+        assert_eq!(get_param(&received, SCXML_CONTENT_TYPE), None);
+        assert_eq!(
+            received.content.map(|c| c.lock().unwrap().clone()),
+            Some(Data::String("Hello".to_string()))
+        );
+    }
+
+    /// Serves "/ok" with a JSON body, "/slow" after one second and all other paths with status 500.
+    async fn start_test_server(port: u16) {
+        let listener = TcpListener::bind(SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)),
+            port,
+        ))
+        .await
+        .unwrap();
+        tokio::task::spawn(async move {
+            loop {
+                let (stream, _addr) = listener.accept().await.unwrap();
+                tokio::task::spawn(async move {
+                    let service = service_fn(|request: Request<hyper::body::Incoming>| async move {
+                        let (status, body) = match request.uri().path() {
+                            "/ok" => (200, r#"{"answer":42}"#),
+                            "/slow" => {
+                                let _ = tokio::task::spawn_blocking(|| thread::sleep(Duration::from_secs(1))).await;
+                                (200, "{}")
+                            }
+                            _ => (500, r#"{"reason":"broken"}"#),
+                        };
+                        Ok::<_, Infallible>(
+                            Response::builder()
+                                .status(status)
+                                .header(hyper::header::CONTENT_TYPE, CONTENT_TYPE_JSON)
+                                .body(Full::new(Bytes::from(body)))
+                                .unwrap(),
+                        )
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn responses_are_mapped_to_events() {
+        let localhost = IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1));
+        start_test_server(5606).await;
+        let mut processor = BasicHTTPEventIOProcessor::new(localhost, "localhost", 5605).await;
+        processor.response_event = Some(RESPONSE_EVENT.to_string());
+        processor.timeout = Duration::from_millis(300);
+
+        let mut processor_copy = processor.get_copy();
+        let global = create_global_data_arc();
+        let global_copy = global.clone();
+        let sent: Vec<bool> = tokio::task::spawn_blocking(move || {
+            ["ok", "fail", "slow"]
+                .iter()
+                .map(|path| {
+                    let mut event = Event::new_simple("request");
+                    event.sendid = Some(format!("send.{}", path));
+                    processor_copy.send(
+                        &global_copy,
+                        format!("http://127.0.0.1:5606/{}", path).as_str(),
+                        event,
+                    )
+                })
+                .collect()
+        })
+        .await
+        .unwrap();
+        processor.shutdown();
+        assert_eq!(sent, vec![true, false, false]);
+
+        let mut global = global.lock().unwrap();
+        let response = global
+            .externalQueue
+            .receiver
+            .try_recv()
+            .expect("Response event");
+        assert_eq!(response.name, RESPONSE_EVENT);
+        assert_eq!(response.sendid, Some("send.ok".to_string()));
+        assert_eq!(
+            get_param(&response, RESPONSE_PARAM_STATUS),
+            Some(Data::Integer(200))
+        );
+        assert_eq!(
+            get_param(&response, RESPONSE_PARAM_BODY),
+            Some(Data::Map(
+                [("answer".to_string(), create_data_arc(Data::Integer(42)))].into()
+            ))
+        );
+        assert!(global.externalQueue.receiver.try_recv().is_none());
+
+        let error = global
+            .internalQueue
+            .dequeue()
+            .expect("Error for status 500");
+        assert_eq!(error.name, "error.communication");
+        assert_eq!(error.sendid, Some("send.fail".to_string()));
+        assert_eq!(
+            get_param(&error, RESPONSE_PARAM_STATUS),
+            Some(Data::Integer(500))
+        );
+        let timeout = global.internalQueue.dequeue().expect("Error for timeout");
+        assert_eq!(timeout.name, "error.communication");
+        assert_eq!(timeout.sendid, Some("send.slow".to_string()));
+    }
+}
@@ -0,0 +1,233 @@
+//! Implementation of the BasicHTTP Invoke Handler.\
+//! Handles \<invoke\> type "<http://www.w3.org/TR/scxml/#BasicHTTPEventProcessor>" (or short-cut "http").
+//! Included if feature "BasicHttpEventIOProcessor" is enabled.\
+//! The invoked service is the http server given by 'src'. Events that the invoking session sends to
+//! the service (target "#_\<invokeid\>", or by 'autoforward') are posted to 'src', encoded as by
+//! [BasicHTTPEventIOProcessor]. The values of 'namelist' and \<param\> are added to each request.\
+//! The response of a request is sent as event [RESPONSE_EVENT] to the invoking session, failed requests
+//! and responses with a status other than 2xx as "error.communication". Both have the invokeid of the service.
+//! The service runs until the invoke is cancelled.
+
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(feature = "Debug")]
+use log::debug;
+use log::error;
+
+use crate::basic_http_event_io_processor::{BasicHTTPEventIOProcessor, DEFAULT_SEND_TIMEOUT, RESPONSE_EVENT};
+use crate::datamodel::BASIC_HTTP_EVENT_PROCESSOR;
+use crate::fsm::{Event, ScxmlSession, EVENT_CANCEL_SESSION};
+use crate::fsm_executor::FsmExecutor;
+use crate::invoke_handler::{InvokeError, InvokeHandler, InvokeParameters};
+
+const TYPES: &[&str] = &[BASIC_HTTP_EVENT_PROCESSOR, "http"];
+
+#[derive(Debug, Clone)]
+pub struct BasicHTTPInvokeHandler {
+    /// Timeout of the requests. If elapsed, error.communication is sent.
+    pub timeout: Duration,
+}
+
+impl Default for BasicHTTPInvokeHandler {
+    fn default() -> Self {
+        BasicHTTPInvokeHandler::new()
+    }
+}
+
+impl BasicHTTPInvokeHandler {
+    pub fn new() -> BasicHTTPInvokeHandler {
+        BasicHTTPInvokeHandler {
+            timeout: DEFAULT_SEND_TIMEOUT,
+        }
+    }
+}
+
+impl InvokeHandler for BasicHTTPInvokeHandler {
+    fn get_types(&self) -> &[&str] {
+        TYPES
+    }
+
+    fn invoke(
+        &mut self,
+        executor: &mut FsmExecutor,
+        parameters: InvokeParameters,
+    ) -> Result<ScxmlSession, InvokeError> {
+        if parameters.src.is_empty() {
+            return Err(InvokeError::Execution(
+                "BasicHTTP invoke needs the url of the service as 'src'".to_string(),
+            ));
+        }
+        let parent = match executor.get_session_sender(parameters.parent_session_id) {
+            None => {
+                return Err(InvokeError::Execution(format!(
+                    "Invoking session #{} is not available",
+                    parameters.parent_session_id
+                )))
+            }
+            Some(parent) => parent,
+        };
+        let (sender, receiver) = channel::<Box<Event>>();
+        let timeout = self.timeout;
+        let InvokeParameters {
+            invoke_id,
+            src,
+            params,
+            ..
+        } = parameters;
+
+        thread::Builder::new()
+            .name(format!("invoke_http_{}", invoke_id))
+            .spawn(move || {
+                #[cfg(feature = "Debug")]
+                debug!("BasicHTTP service '{}' at {} started", invoke_id, src);
+                while let Ok(mut event) = receiver.recv() {
+                    if event.name == EVENT_CANCEL_SESSION {
+                        break;
+                    }
+                    if !params.is_empty() {
+                        let mut values = params.clone();
+                        values.extend(event.param_values.take().unwrap_or_default());
+                        event.param_values = Some(values);
+                    }
+                    let (mut reply, params) = match BasicHTTPEventIOProcessor::post_event(&src, &event, timeout) {
+                        Ok(response) if response.status().is_success() => (
+                            Event::new_simple(RESPONSE_EVENT),
+                            Some(BasicHTTPEventIOProcessor::response_params(response)),
+                        ),
+                        Ok(response) => {
+                            error!("Post to {} failed: {}", src, response.status());
+                            (
+                                Event::error_communication_for_invoke(&invoke_id),
+                                Some(BasicHTTPEventIOProcessor::response_params(response)),
+                            )
+                        }
+                        Err(err) => {
+                            // Includes the timeout.
+                            error!("Post to {} failed: {}", src, err);
+                            (Event::error_communication_for_invoke(&invoke_id), None)
+                        }
+                    };
+                    reply.invoke_id = Some(invoke_id.clone());
+                    reply.sendid.clone_from(&event.sendid);
+                    reply.origin = Some(src.clone());
+                    reply.origin_type = Some(BASIC_HTTP_EVENT_PROCESSOR.to_string());
+                    reply.param_values = params;
+                    if parent.send(Box::new(reply)).is_err() {
+                        // The invoking session has gone.
+                        break;
+                    }
+                }
+                #[cfg(feature = "Debug")]
+                debug!("BasicHTTP service '{}' stopped", invoke_id);
+            })
+            .map_err(|err| InvokeError::Execution(format!("Failed to start service thread: {}", err)))?;
+
+        Ok(ScxmlSession::new_without_join_handle(0, sender.into()))
+    }
+
+    fn cancel(&mut self, session: &ScxmlSession) {
+        // The service may already be gone, nothing to do in this case.
+        let _ = session
+            .sender
+            .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+    }
+
+    fn get_copy(&self) -> Box<dyn InvokeHandler> {
+        Box::new(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::sync::mpsc::channel;
+    use std::time::Duration;
+
+    use crate::basic_http_event_io_processor::BasicHTTPEventIOProcessor;
+    use crate::datamodel::Data;
+    use crate::event_io_processor::EventIOProcessor;
+    use crate::fsm::Event;
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::test::run_test_manual_with_executor;
+    #[cfg(feature = "Trace")]
+    use crate::tracer::TraceMode;
+
+    fn get_param(event: &Event, name: &str) -> Option<Data> {
+        event
+            .param_values
+            .as_ref()
+            .and_then(|pv| pv.iter().find(|p| p.name == name))
+            .map(|p| p.value.clone())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    async fn events_are_posted_to_the_invoked_service() {
+        // The service is a session behind another BasicHTTP processor.
+        let mut service =
+            BasicHTTPEventIOProcessor::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), "localhost", 5611).await;
+        let (sender, receiver) = channel();
+        service.state.lock().unwrap().fsms.insert(7, sender.into());
+
+        let sm = scxml_reader::parse_from_xml(format!(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry><send event='go'/></onentry>
+                <invoke id='service' type='http://www.w3.org/TR/scxml/#BasicHTTPEventProcessor' src='{}'>
+                  <param name='a' expr='1'/>
+                </invoke>
+                <transition event='go'>
+                  <send event='hello' target='#_service'><param name='b' expr='2'/></send>
+                </transition>
+                <transition event='HTTP.response' cond="_event.invokeid == 'service' &amp;&amp; _event.data.status == 200"
+                    target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+            service.get_location(7)
+        ));
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        // The session blocks, it must not run on the runtime.
+        let passed = tokio::task::spawn_blocking(move || {
+            run_test_manual_with_executor(
+                "events_are_posted_to_the_invoked_service",
+                FsmExecutor::new_without_io_processor(),
+                sm.unwrap(),
+                #[cfg(feature = "Trace")]
+                TraceMode::STATES,
+                2000u64,
+                &vec!["pass".to_string()],
+                |_sender| {},
+            )
+        })
+        .await
+        .unwrap();
+        service.shutdown();
+        assert!(passed);
+
+        let received = receiver
+            .recv_timeout(Duration::from_millis(2000))
+            .expect("Event shall be posted");
+        assert_eq!(received.name, "hello");
+        // Form encoding transports strings only.
+        assert_eq!(
+            get_param(&received, "a"),
+            Some(Data::String("1".to_string()))
+        );
+        assert_eq!(
+            get_param(&received, "b"),
+            Some(Data::String("2".to_string()))
+        );
+    }
+}
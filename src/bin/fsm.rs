@@ -5,12 +5,14 @@ extern crate core;
 
 use log::error;
 #[cfg(feature = "ECMAScript")]
-use rfsm::ecma_script_datamodel::ECMA_STRICT_ARGUMENT;
+use rfsm::ecma_script_datamodel::{ECMA_SHARED_CONTEXT_ARGUMENT, ECMA_STRICT_ARGUMENT};
+#[cfg(feature = "RfsmExpressionModel")]
+use rfsm::expression_engine::datamodel::RFSM_EXPRESSION_LENIENT_ARGUMENT;
 use std::io::{stdout, Write};
 use std::{io, process, thread, time};
 
 use rfsm::actions::ActionWrapper;
-use rfsm::fsm::{Event, EventType};
+use rfsm::fsm::Event;
 use rfsm::fsm_executor::FsmExecutor;
 #[cfg(feature = "Trace")]
 use rfsm::handle_trace;
@@ -32,6 +34,10 @@ async fn main() {
         &INCLUDE_PATH_ARGUMENT_OPTION,
         #[cfg(feature = "ECMAScript")]
         &ECMA_STRICT_ARGUMENT,
+        #[cfg(feature = "ECMAScript")]
+        &ECMA_SHARED_CONTEXT_ARGUMENT,
+        #[cfg(feature = "RfsmExpressionModel")]
+        &RFSM_EXPRESSION_LENIENT_ARGUMENT,
     ]);
 
     #[cfg(feature = "Trace")]
@@ -90,7 +96,7 @@ async fn main() {
             Some(thread) => {
                 if thread.is_finished() {
                     println!("\nSM finished!");
-                    executor.shutdown();
+                    executor.shutdown(time::Duration::from_secs(1));
                     // TODO: dump data from the "finish"
                     break;
                 }
@@ -115,17 +121,7 @@ async fn main() {
                     #[cfg(feature = "Trace")]
                     handle_trace(&mut session.sender, &line_lc[6..], false);
                 } else if !line_lc.eq("help") && !line.is_empty() {
-                    let event = Box::new(Event {
-                        name: line.clone(),
-                        etype: EventType::platform,
-                        sendid: None,
-                        origin: None,
-                        origin_type: None,
-                        invoke_id: None,
-                        param_values: None,
-                        content: None,
-                    });
-                    match session.sender.send(event) {
+                    match session.send_event(Event::builder(&line)) {
                         Ok(_r) => {
                             // ok
                         }
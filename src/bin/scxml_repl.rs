@@ -0,0 +1,258 @@
+//! Interactive exploration of a state machine.\
+//! Usage:
+//!    scxml_repl \<scxml-file\> \[-datamodel name\] \[-includePaths paths\] \[-trace flag\]
+//!
+//! Loads the FSM, starts a session and reads commands from stdin, enter "help" for a list.\
+//! Events that the FSM sends with type "repl" (e.g. \<send type="repl" event="result"/\>) are printed.
+extern crate core;
+
+use std::io::{stdout, BufRead, Write};
+use std::path::Path;
+use std::time::Duration;
+use std::{io, process};
+
+use log::error;
+use serde_json::Value;
+
+use rfsm::actions::ActionWrapper;
+use rfsm::datamodel::{create_data_arc, json_to_data, GlobalDataArc};
+#[cfg(feature = "ECMAScript")]
+use rfsm::ecma_script_datamodel::{ECMA_SHARED_CONTEXT_ARGUMENT, ECMA_STRICT_ARGUMENT};
+use rfsm::event_io_processor::{EventIOProcessor, EventIOProcessorHandle};
+#[cfg(feature = "RfsmExpressionModel")]
+use rfsm::expression_engine::datamodel::RFSM_EXPRESSION_LENIENT_ARGUMENT;
+use rfsm::fsm::{
+    start_fsm_with_data_and_finish_mode, Event, FinishMode, ScxmlSession, SessionId, EVENT_CANCEL_SESSION,
+};
+use rfsm::fsm_executor::FsmExecutor;
+use rfsm::scxml_reader::{include_path_from_arguments, INCLUDE_PATH_ARGUMENT_OPTION};
+#[cfg(feature = "Trace")]
+use rfsm::tracer::{TraceMode, TRACE_ARGUMENT_OPTION};
+use rfsm::{init_logging, scxml_reader, ArgOption};
+
+pub static DATAMODEL_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "datamodel",
+    with_value: true,
+    required: false,
+};
+
+/// Type of the I/O processor that prints the events.
+pub const REPL_EVENT_PROCESSOR: &str = "repl";
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+const USAGE: &str = r#"Commands:
+  send <event> [json-data]  Sends an external event, e.g. send go {"x":1}
+  config                    Prints the active states
+  data <expression>         Evaluates the expression in the datamodel
+  trace <mode> on|off       Enables or disables a trace mode, e.g. trace events on
+  quit                      Cancels the session and exits
+  help                      Prints this information"#;
+
+/// Prints all events that are sent to it.
+#[derive(Debug, Default)]
+struct ReplEventIOProcessor {
+    handle: EventIOProcessorHandle,
+}
+
+impl EventIOProcessor for ReplEventIOProcessor {
+    fn get_location(&self, id: SessionId) -> String {
+        format!("{}:{}", REPL_EVENT_PROCESSOR, id)
+    }
+
+    fn get_types(&self) -> &[&str] {
+        &[REPL_EVENT_PROCESSOR]
+    }
+
+    fn get_handle(&mut self) -> &mut EventIOProcessorHandle {
+        &mut self.handle
+    }
+
+    fn get_copy(&self) -> Box<dyn EventIOProcessor> {
+        Box::new(ReplEventIOProcessor {
+            handle: self.handle.clone(),
+        })
+    }
+
+    fn send(&mut self, _global: &GlobalDataArc, target: &str, event: Event) -> bool {
+        let mut line = format!("<< {}", event.name);
+        if !target.is_empty() {
+            line.push_str(format!(" target:{}", target).as_str());
+        }
+        if let Some(params) = &event.param_values {
+            for param in params {
+                line.push_str(format!(" {}:{}", param.name, param.value).as_str());
+            }
+        }
+        if let Some(content) = &event.content {
+            line.push_str(format!(" content:{}", content).as_str());
+        }
+        println!("{}", line);
+        true
+    }
+
+    fn shutdown(&mut self) {
+        self.handle.shutdown();
+    }
+}
+
+fn send_event(session: &ScxmlSession, arguments: &str) {
+    let (name, data) = match arguments.split_once(char::is_whitespace) {
+        None => (arguments, ""),
+        Some((name, data)) => (name, data.trim()),
+    };
+    if name.is_empty() {
+        println!("Missing event name");
+        return;
+    }
+    let mut event = Event::new_simple(name);
+    if !data.is_empty() {
+        match serde_json::from_str::<Value>(data) {
+            Ok(value) => event.content = Some(create_data_arc(json_to_data(&value))),
+            Err(err) => {
+                println!("Invalid JSON data: {}", err);
+                return;
+            }
+        }
+    }
+    if let Err(err) = session.send_and_sync(Box::new(event), TIMEOUT) {
+        println!("{}", err);
+    }
+}
+
+#[cfg(feature = "Trace")]
+fn set_trace(session: &ScxmlSession, arguments: &str) {
+    let (mode, enable) = match arguments.split_once(char::is_whitespace) {
+        None => (arguments, "on"),
+        Some((mode, enable)) => (mode, enable.trim()),
+    };
+    let enable = match enable {
+        "on" => true,
+        "off" => false,
+        _ => {
+            println!("Use 'on' or 'off'");
+            return;
+        }
+    };
+    match mode.parse::<TraceMode>() {
+        Ok(mode) => {
+            if let Err(err) = session.set_trace_mode(mode, enable) {
+                println!("{}", err);
+            }
+        }
+        Err(_) => {
+            println!("Unknown trace mode. Use one of: methods, states, events, arguments, results, transitions, all")
+        }
+    }
+}
+
+/// Loads the specified FSM and reads commands.
+#[tokio::main(flavor = "multi_thread")]
+async fn main() {
+    init_logging();
+
+    let (named_opt, final_args) = rfsm::get_arguments(&[
+        &DATAMODEL_ARGUMENT_OPTION,
+        &INCLUDE_PATH_ARGUMENT_OPTION,
+        #[cfg(feature = "Trace")]
+        &TRACE_ARGUMENT_OPTION,
+        #[cfg(feature = "ECMAScript")]
+        &ECMA_STRICT_ARGUMENT,
+        #[cfg(feature = "ECMAScript")]
+        &ECMA_SHARED_CONTEXT_ARGUMENT,
+        #[cfg(feature = "RfsmExpressionModel")]
+        &RFSM_EXPRESSION_LENIENT_ARGUMENT,
+    ]);
+
+    if final_args.is_empty() {
+        println!("Missing argument. Please specify a scxml file");
+        process::exit(1);
+    }
+
+    let include_paths = include_path_from_arguments(&named_opt);
+    let mut fsm = match scxml_reader::parse_from_xml_file(Path::new(final_args[0].as_str()), &include_paths) {
+        Ok(fsm) => fsm,
+        Err(err) => {
+            error!("Failed to load {}: {}", final_args[0], err);
+            process::exit(1);
+        }
+    };
+    if let Some(datamodel) = named_opt.get(DATAMODEL_ARGUMENT_OPTION.name) {
+        fsm.datamodel.clone_from(datamodel);
+    }
+
+    let mut executor = FsmExecutor::new_without_io_processor();
+    executor.set_include_paths(&include_paths);
+    executor.set_global_options_from_arguments(&named_opt);
+    executor.add_event_io_processor(Box::new(ReplEventIOProcessor::default()));
+
+    let session = start_fsm_with_data_and_finish_mode(
+        fsm,
+        ActionWrapper::new(),
+        Box::new(executor.clone()),
+        &Vec::new(),
+        FinishMode::KEEP_CONFIGURATION,
+    );
+
+    #[cfg(feature = "Trace")]
+    if named_opt.contains_key(TRACE_ARGUMENT_OPTION.name) {
+        let _ = session.set_trace_mode(TraceMode::from_arguments(&named_opt), true);
+    }
+
+    println!("{}", USAGE);
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        if session.wait_for_completion(Duration::ZERO).is_some() {
+            break;
+        }
+        print!("> ");
+        let _ = stdout().flush();
+        let line = match lines.next() {
+            None => break,
+            Some(Ok(line)) => line,
+            Some(Err(err)) => {
+                eprintln!("Error: {}. aborting...", err);
+                break;
+            }
+        };
+        let line = line.trim();
+        let (command, arguments) = match line.split_once(char::is_whitespace) {
+            None => (line, ""),
+            Some((command, arguments)) => (command, arguments.trim()),
+        };
+        match command {
+            "" => {}
+            "send" => send_event(&session, arguments),
+            "config" => match session.take_snapshot(TIMEOUT) {
+                Ok(snapshot) => println!("{:?}", snapshot.configuration),
+                Err(err) => println!("{}", err),
+            },
+            "data" => match session.evaluate(arguments, TIMEOUT) {
+                Ok(value) => println!("{}", value),
+                Err(err) => println!("{}", err),
+            },
+            #[cfg(feature = "Trace")]
+            "trace" => set_trace(&session, arguments),
+            "quit" | "exit" => break,
+            "help" => println!("{}", USAGE),
+            _ => println!("Unknown command '{}', enter 'help' for a list", command),
+        }
+    }
+
+    let _ = session
+        .sender
+        .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+    if let Some(result) = session.wait_for_completion(TIMEOUT) {
+        println!(
+            "Session {}, final configuration: {:?}",
+            if result.final_state_reached {
+                "finished"
+            } else {
+                "cancelled"
+            },
+            result.final_configuration.unwrap_or_default()
+        );
+    }
+    executor.shutdown(Duration::from_secs(1));
+}
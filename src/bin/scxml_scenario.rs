@@ -0,0 +1,96 @@
+//! Executes a scripted scenario on a state machine, see [rfsm::scenario].\
+//! Usage:
+//!    scxml_scenario \[\<fsm-file\>\] \<scenario-file\> \[-includePaths paths\] \[-trace flag\]
+//!
+//! The fsm file can be omitted if the scenario contains a "file" entry.\
+//! Exits with 0 if all expectations are met. Otherwise, a diff-style report is printed and the exit code is 1.
+extern crate core;
+
+use std::path::Path;
+use std::process;
+
+use rfsm::fsm_executor::FsmExecutor;
+use rfsm::init_logging;
+use rfsm::scenario::{load_scenario, run_scenario};
+#[cfg(feature = "xml")]
+use rfsm::scxml_reader::{include_path_from_arguments, INCLUDE_PATH_ARGUMENT_OPTION};
+use rfsm::test::{abort_test, load_fsm};
+#[cfg(feature = "Trace")]
+use rfsm::tracer::{TraceMode, TRACE_ARGUMENT_OPTION};
+
+fn main() {
+    init_logging();
+
+    #[allow(unused_variables)]
+    let (named_opt, final_args) = rfsm::get_arguments(&[
+        #[cfg(feature = "Trace")]
+        &TRACE_ARGUMENT_OPTION,
+        #[cfg(feature = "xml")]
+        &INCLUDE_PATH_ARGUMENT_OPTION,
+    ]);
+
+    #[cfg(feature = "xml")]
+    let include_paths = include_path_from_arguments(&named_opt);
+    #[cfg(not(feature = "xml"))]
+    let include_paths = Vec::new();
+
+    let mut fsm_file: Option<String> = None;
+    let mut scenario_file: Option<String> = None;
+    for arg in &final_args {
+        let ext = Path::new(arg.as_str())
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "json" | "yaml" | "yml" => scenario_file = Some(arg.clone()),
+            "rfsm" | "scxml" | "xml" => fsm_file = Some(arg.clone()),
+            _ => abort_test(format!("File '{}' has unsupported extension.", arg)),
+        }
+    }
+
+    let Some(scenario_file) = scenario_file else {
+        abort_test("Missing argument. Please specify a scenario file".to_string());
+    };
+    let scenario = match load_scenario(scenario_file.as_str()) {
+        Ok(scenario) => scenario,
+        Err(err) => abort_test(err),
+    };
+
+    let fsm_file = match (fsm_file, &scenario.file) {
+        (Some(fsm_file), _) => fsm_file,
+        (None, Some(file)) => Path::new(scenario_file.as_str())
+            .parent()
+            .unwrap_or(Path::new(""))
+            .join(file)
+            .to_string_lossy()
+            .to_string(),
+        (None, None) => abort_test("No FSM given. Please specify a fsm file".to_string()),
+    };
+
+    #[allow(unused_mut)]
+    let mut fsm = match load_fsm(fsm_file.as_str(), &include_paths) {
+        Ok(fsm) => fsm,
+        Err(err) => abort_test(format!("Failed to load fsm '{}'. {}", fsm_file, err)),
+    };
+    #[cfg(feature = "Trace")]
+    fsm.tracer
+        .enable_trace(TraceMode::from_arguments(&named_opt));
+
+    let mut executor = FsmExecutor::new_without_io_processor();
+    executor.include_paths = include_paths;
+    executor.set_global_options_from_arguments(&named_opt);
+
+    let report = run_scenario(fsm, &scenario, executor);
+    if report.passed() {
+        println!(
+            "[{}] ==> Passed. Final configuration: {}",
+            scenario_file,
+            report.final_configuration.join(",")
+        );
+        process::exit(0);
+    } else {
+        println!("[{}] ==> Failed", scenario_file);
+        print!("{}", report.diff());
+        process::exit(1);
+    }
+}
@@ -3,11 +3,8 @@ extern crate core;
 use log::error;
 use rfsm::scxml_reader::include_path_from_arguments;
 use rfsm::scxml_reader::INCLUDE_PATH_ARGUMENT_OPTION;
-use rfsm::serializer::default_protocol_writer::DefaultProtocolWriter;
-use rfsm::serializer::fsm_writer::FsmWriter;
+use rfsm::serializer::fsm_writer::save_precompiled;
 use rfsm::{init_logging, scxml_reader};
-use std::fs::File;
-use std::io::BufWriter;
 use std::process;
 
 #[tokio::main(flavor = "multi_thread")]
@@ -27,19 +24,13 @@ async fn main() {
     let include_paths = include_path_from_arguments(&named_opt);
     println!("Reading from {}", source_file);
     match scxml_reader::parse_from_uri(source_file, &include_paths) {
-        Ok(fsm) => match File::create(target_file.clone()) {
-            Ok(f) => {
-                println!("Writing to {}", &target_file);
-                let protocol = DefaultProtocolWriter::new(BufWriter::new(f));
-                let mut writer = FsmWriter::new(Box::new(protocol));
-                writer.write(&fsm);
-                writer.close();
-            }
-            Err(err) => {
-                error!("Failed to open output: {}", err);
+        Ok(fsm) => {
+            println!("Writing to {}", &target_file);
+            if let Err(err) = save_precompiled(&fsm, &target_file) {
+                error!("Failed to write output: {}", err);
                 process::exit(2);
             }
-        },
+        }
         Err(err) => {
             error!("Failed to load SCXML:{}", err);
             process::exit(2);
@@ -0,0 +1,166 @@
+//! Executes the W3C SCXML tests (see "test/w3c") and reports the results.\
+//! Usage: w3c_test_runner \[options\] (test-directory|test-file)...
+//!
+//! Options:
+//! + -config \<file\>: Test specification with timeout, final configuration and options, e.g. "test_config.json".
+//! + -timeout \<ms\>: Timeout per test in milliseconds.
+//! + -threads \<n\>: Number of tests to execute in parallel.
+//! + -tests \<list\>: Comma separated numbers of the tests to execute.
+//! + -knownFailures \<file\>: File with the numbers of tests that are known to fail.
+//! + -report \<file\>: Writes the results as JSON to the file.
+//! + -includePaths \<paths\>: Include paths for the tests.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+
+use rfsm::get_arguments;
+use rfsm::init_logging;
+#[cfg(feature = "xml")]
+use rfsm::scxml_reader::{include_path_from_arguments, INCLUDE_PATH_ARGUMENT_OPTION};
+use rfsm::test::{
+    abort_test, load_json_config, parse_w3c_test_numbers, run_w3c_tests, w3c_summary_table, W3cTestRunConfig,
+};
+use rfsm::ArgOption;
+
+static CONFIG_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "config",
+    with_value: true,
+    required: false,
+};
+
+static TIMEOUT_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "timeout",
+    with_value: true,
+    required: false,
+};
+
+static THREADS_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "threads",
+    with_value: true,
+    required: false,
+};
+
+static TESTS_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "tests",
+    with_value: true,
+    required: false,
+};
+
+static KNOWN_FAILURES_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "knownFailures",
+    with_value: true,
+    required: false,
+};
+
+static REPORT_ARGUMENT_OPTION: ArgOption = ArgOption {
+    name: "report",
+    with_value: true,
+    required: false,
+};
+
+/// Collects the test files. Directories are scanned (not recursive) for "scxml" and "rfsm" files.
+fn collect_test_files(args: &[String]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for arg in args {
+        let path = Path::new(arg.as_str());
+        if path.is_dir() {
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        let file = entry.path();
+                        let extension = file
+                            .extension()
+                            .map(|e| e.to_string_lossy().to_lowercase())
+                            .unwrap_or_default();
+                        if file.is_file() && (extension == "scxml" || extension == "rfsm") {
+                            files.push(file);
+                        }
+                    }
+                }
+                Err(err) => abort_test(format!("Failed to read directory '{}'. {}", arg, err)),
+            }
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    files
+}
+
+fn parse_number(option: &ArgOption, value: &str) -> u64 {
+    match value.parse::<u64>() {
+        Ok(v) => v,
+        Err(_) => abort_test(format!(
+            "Illegal value '{}' for option '{}'",
+            value, option.name
+        )),
+    }
+}
+
+fn main() {
+    init_logging();
+
+    let (named_opt, final_args) = get_arguments(&[
+        &CONFIG_ARGUMENT_OPTION,
+        &TIMEOUT_ARGUMENT_OPTION,
+        &THREADS_ARGUMENT_OPTION,
+        &TESTS_ARGUMENT_OPTION,
+        &KNOWN_FAILURES_ARGUMENT_OPTION,
+        &REPORT_ARGUMENT_OPTION,
+        #[cfg(feature = "xml")]
+        &INCLUDE_PATH_ARGUMENT_OPTION,
+    ]);
+
+    if final_args.is_empty() {
+        abort_test("Missing argument. Please specify the test directory or test files".to_string());
+    }
+
+    let mut config = W3cTestRunConfig::default();
+    #[cfg(feature = "xml")]
+    {
+        config.include_paths = include_path_from_arguments(&named_opt);
+    }
+    if let Some(file) = named_opt.get(CONFIG_ARGUMENT_OPTION.name) {
+        config.apply_specification(&load_json_config(file.as_str()));
+    }
+    if let Some(timeout) = named_opt.get(TIMEOUT_ARGUMENT_OPTION.name) {
+        config.timeout = Duration::from_millis(parse_number(&TIMEOUT_ARGUMENT_OPTION, timeout));
+    }
+    if let Some(threads) = named_opt.get(THREADS_ARGUMENT_OPTION.name) {
+        config.threads = parse_number(&THREADS_ARGUMENT_OPTION, threads) as usize;
+    }
+    if let Some(tests) = named_opt.get(TESTS_ARGUMENT_OPTION.name) {
+        config.selected = parse_w3c_test_numbers(tests).unwrap_or_else(|err| abort_test(err));
+    }
+    if let Some(file) = named_opt.get(KNOWN_FAILURES_ARGUMENT_OPTION.name) {
+        match fs::read_to_string(file) {
+            Ok(list) => {
+                config.known_failures = parse_w3c_test_numbers(list.as_str()).unwrap_or_else(|err| abort_test(err));
+            }
+            Err(err) => abort_test(format!("Failed to read '{}'. {}", file, err)),
+        }
+    }
+
+    let files = collect_test_files(&final_args);
+    let results = run_w3c_tests(&files, &config);
+
+    println!("{}", w3c_summary_table(&results));
+
+    if let Some(file) = named_opt.get(REPORT_ARGUMENT_OPTION.name) {
+        match serde_json::to_string_pretty(&results) {
+            Ok(json) => {
+                if let Err(err) = fs::write(file, json) {
+                    abort_test(format!("Failed to write report '{}'. {}", file, err));
+                }
+            }
+            Err(err) => abort_test(format!("Failed to serialize report. {}", err)),
+        }
+    }
+
+    if results.iter().all(|r| r.is_expected()) {
+        process::exit(0);
+    } else {
+        process::exit(1);
+    }
+}
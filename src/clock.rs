@@ -0,0 +1,311 @@
+//! Time source of the FSM, used for delayed \<send\> and for the "now_ms" action.\
+//! The default [TimerClock] uses wall-clock time. [TestClock] uses virtual time that is advanced
+//! explicitly, so tests and co-simulations can control when delayed events are delivered.
+//!
+//! Delayed sends are scheduled by the executor of the session, see [crate::fsm_executor::FsmExecutor::schedule].
+//! All sessions of an executor share one clock. Set it via [crate::fsm_executor::FsmExecutor::set_clock].
+
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Callback that is executed by the clock after the delay.
+pub type ClockCallback = Box<dyn FnMut() + Send>;
+
+/// A time source that can execute callbacks after a delay.
+pub trait Clock: Send + Sync {
+    /// Calls the callback after the delay.\
+    /// Dropping the returned guard cancels the callback, see [ClockGuard::ignore].
+    fn schedule(&self, delay_ms: i64, cb: ClockCallback) -> ClockGuard;
+
+    /// Current time in milliseconds since the UNIX epoch.
+    fn now_ms(&self) -> i64;
+}
+
+/// Guard of a scheduled callback.\
+/// The callback is cancelled if the guard is dropped.
+pub struct ClockGuard {
+    timer_guard: Option<timer::Guard>,
+    cancelled: Option<Arc<AtomicBool>>,
+}
+
+impl ClockGuard {
+    /// Creates a guard that sets the flag if the callback shall be cancelled.
+    pub fn new(cancelled: Arc<AtomicBool>) -> ClockGuard {
+        ClockGuard {
+            timer_guard: None,
+            cancelled: Some(cancelled),
+        }
+    }
+
+    /// Drops the guard without cancelling the callback.
+    pub fn ignore(mut self) {
+        if let Some(guard) = self.timer_guard.take() {
+            guard.ignore();
+        }
+        self.cancelled = None;
+    }
+}
+
+impl From<timer::Guard> for ClockGuard {
+    fn from(guard: timer::Guard) -> Self {
+        ClockGuard {
+            timer_guard: Some(guard),
+            cancelled: None,
+        }
+    }
+}
+
+impl Drop for ClockGuard {
+    fn drop(&mut self) {
+        if let Some(cancelled) = &self.cancelled {
+            cancelled.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Debug for ClockGuard {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClockGuard").finish()
+    }
+}
+
+/// Default clock, backed by [timer::Timer] and the system time.\
+/// The timer (and its thread) is created on the first call to [Clock::schedule]. All callbacks
+/// are executed by this single thread.
+pub struct TimerClock {
+    timer: Mutex<Option<timer::Timer>>,
+}
+
+impl TimerClock {
+    pub fn new() -> TimerClock {
+        TimerClock {
+            timer: Mutex::new(None),
+        }
+    }
+}
+
+impl Default for TimerClock {
+    fn default() -> Self {
+        TimerClock::new()
+    }
+}
+
+impl Clock for TimerClock {
+    fn schedule(&self, delay_ms: i64, cb: ClockCallback) -> ClockGuard {
+        self.timer
+            .lock()
+            .unwrap()
+            .get_or_insert_with(timer::Timer::new)
+            .schedule_with_delay(chrono::Duration::milliseconds(delay_ms), cb)
+            .into()
+    }
+
+    fn now_ms(&self) -> i64 {
+        chrono::Utc::now().timestamp_millis()
+    }
+}
+
+struct PendingCallback {
+    due_ms: i64,
+    cancelled: Arc<AtomicBool>,
+    cb: ClockCallback,
+}
+
+#[derive(Default)]
+struct TestClockState {
+    now_ms: i64,
+    /// Ordered by due time, callbacks with the same due time in order of scheduling.
+    pending: Vec<PendingCallback>,
+}
+
+/// Clock with virtual time.\
+/// Callbacks are only executed by [TestClock::advance], on the calling thread.
+/// Clones share the same time.
+#[derive(Clone, Default)]
+pub struct TestClock {
+    state: Arc<Mutex<TestClockState>>,
+}
+
+impl TestClock {
+    /// Creates a clock that starts at the given time.
+    pub fn new(start_ms: i64) -> TestClock {
+        let clock = TestClock::default();
+        clock.state.lock().unwrap().now_ms = start_ms;
+        clock
+    }
+
+    /// Advances the virtual time and executes all callbacks that are due, in order of their due time.\
+    /// Callbacks that are scheduled by other callbacks are also executed if they are due.
+    pub fn advance(&self, delta_ms: i64) {
+        let target_ms = self.state.lock().unwrap().now_ms + delta_ms;
+        loop {
+            let next = {
+                let mut state = self.state.lock().unwrap();
+                state
+                    .pending
+                    .retain(|p| !p.cancelled.load(Ordering::Relaxed));
+                match state.pending.first() {
+                    Some(p) if p.due_ms <= target_ms => {
+                        let next = state.pending.remove(0);
+                        state.now_ms = next.due_ms.max(state.now_ms);
+                        next
+                    }
+                    _ => {
+                        state.now_ms = target_ms;
+                        return;
+                    }
+                }
+            };
+            // Executed without lock, the callback may schedule again.
+            let mut cb = next.cb;
+            cb();
+        }
+    }
+
+    /// Number of callbacks that are scheduled and not cancelled.
+    pub fn pending(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .pending
+            .iter()
+            .filter(|p| !p.cancelled.load(Ordering::Relaxed))
+            .count()
+    }
+}
+
+impl Clock for TestClock {
+    fn schedule(&self, delay_ms: i64, cb: ClockCallback) -> ClockGuard {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut state = self.state.lock().unwrap();
+        let due_ms = state.now_ms + delay_ms;
+        let idx = state.pending.partition_point(|p| p.due_ms <= due_ms);
+        state.pending.insert(
+            idx,
+            PendingCallback {
+                due_ms,
+                cancelled: cancelled.clone(),
+                cb,
+            },
+        );
+        ClockGuard::new(cancelled)
+    }
+
+    fn now_ms(&self) -> i64 {
+        self.state.lock().unwrap().now_ms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::sync::Arc;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::thread;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::clock::{Clock, TestClock};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, FinishMode, ScxmlSession};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn start_with_clock(clock: &TestClock) -> ScxmlSession {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Wait' datamodel='ecmascript'>
+              <state id='Wait'>
+                <onentry>
+                  <send id='timeout' event='tick' delay='5s'/>
+                </onentry>
+                <transition event='stop'>
+                  <cancel sendid='timeout'/>
+                </transition>
+                <transition event='tick' target='Ticked'/>
+                <transition event='check' target='Checked'/>
+              </state>
+              <final id='Ticked'/>
+              <final id='Checked'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_clock(Arc::new(clock.clone()));
+        let session = start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        // Wait until the send is scheduled.
+        for _ in 0..200 {
+            if clock.pending() > 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(clock.pending(), 1);
+        session
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn delayed_send_uses_virtual_time() {
+        let clock = TestClock::new(1000);
+        let session = start_with_clock(&clock);
+
+        clock.advance(4999);
+        assert_eq!(clock.pending(), 1);
+        assert_eq!(clock.now_ms(), 5999);
+        clock.advance(1);
+        assert_eq!(clock.pending(), 0);
+
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["Ticked".to_string()])
+        );
+
+        // Cancel before the time is advanced.
+        let clock = TestClock::new(0);
+        let session = start_with_clock(&clock);
+        session
+            .send_and_sync(
+                Box::new(Event::new_simple("stop")),
+                Duration::from_millis(2000),
+            )
+            .unwrap();
+        assert_eq!(clock.pending(), 0);
+        clock.advance(5000);
+        session
+            .sender
+            .send(Box::new(Event::new_simple("check")))
+            .unwrap();
+
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["Checked".to_string()])
+        );
+    }
+}
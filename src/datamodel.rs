@@ -1,33 +1,34 @@
 //! Defines the API used to access the data models.
 
 use std::any::Any;
+use std::cmp::Ordering;
 use std::collections::hash_map::Entry;
 use std::collections::HashMap;
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
 use std::ops::Deref;
+use std::sync::mpsc::TrySendError;
 use std::sync::{Arc, LockResult, Mutex, MutexGuard};
 
-#[cfg(all(feature = "Debug", feature = "EnvLog"))]
-use log::warn;
-
-#[cfg(all(feature = "Debug", not(feature = "EnvLog")))]
-use std::println as warn;
-
 #[cfg(not(feature = "EnvLog"))]
-use std::{println as info, println as debug, println as error};
+use std::{println as debug, println as error};
 
 #[cfg(feature = "EnvLog")]
-use log::{debug, error, info};
+use log::{debug, error};
 
 use crate::expression_engine::lexer::{ExpressionLexer, Token};
 use crate::fsm::{
-    vec_to_string, CommonContent, Event, ExecutableContentId, Fsm, GlobalData, InvokeId, ParamPair, Parameter, State,
-    StateId,
+    vec_to_string, CommonContent, ContentKind, Event, ExecutableContentId, Fsm, GlobalData, InvokeId, ParamPair,
+    Parameter, State, StateId,
 };
 
 use crate::actions::ActionMap;
 use crate::event_io_processor::EventIOProcessor;
+use crate::executable_content::{
+    execute_with_source_line, Assign, Cancel, ForEach, If, Log, SendParameters, TYPE_ASSIGN, TYPE_CANCEL,
+    TYPE_EXPRESSION, TYPE_FOREACH, TYPE_IF, TYPE_LOG, TYPE_SEND,
+};
+use crate::log_sink::log_message;
 
 pub const DATAMODEL_OPTION_PREFIX: &str = "datamodel:";
 
@@ -49,7 +50,8 @@ pub const BASIC_HTTP_EVENT_PROCESSOR: &str = "http://www.w3.org/TR/scxml/#BasicH
 /// *W3C says*:\
 /// The SCXML Processor MUST bind the variable _sessionid at load time to the system-generated id
 /// for the current SCXML session. (This is of type NMTOKEN.) The Processor MUST keep the variable
-/// bound to this value until the session terminates.
+/// bound to this value until the session terminates.\
+/// The value is the [crate::fsm::SessionId] as string.
 pub const SESSION_ID_VARIABLE_NAME: &str = "_sessionid";
 
 /// Name of system variable "_name".
@@ -59,6 +61,27 @@ pub const SESSION_ID_VARIABLE_NAME: &str = "_sessionid";
 /// value until the session terminates.
 pub const SESSION_NAME_VARIABLE_NAME: &str = "_name";
 
+/// Name of system variable "_x" for platform specific data.
+/// *W3C says*:\
+/// The name '_x' is reserved. Platforms may use it to store platform-specific information
+/// (system variables that are not specified in this document).
+pub const PLATFORM_VARIABLE_NAME: &str = "_x";
+
+/// Name of field "session_id" of system variable "_x"
+pub const PLATFORM_VARIABLE_FIELD_SESSION_ID: &str = "session_id";
+
+/// Name of field "parent_session_id" of system variable "_x". Null if the session was not invoked.
+pub const PLATFORM_VARIABLE_FIELD_PARENT_SESSION_ID: &str = "parent_session_id";
+
+/// Name of field "invoke_id" of system variable "_x". Null if the session was not invoked.
+pub const PLATFORM_VARIABLE_FIELD_INVOKE_ID: &str = "invoke_id";
+
+/// Name of field "ioprocessors" of system variable "_x", contains the types of the registered I/O processors.
+pub const PLATFORM_VARIABLE_FIELD_IO_PROCESSORS: &str = "ioprocessors";
+
+/// Name of field "version" of system variable "_x", contains the version of rFSM.
+pub const PLATFORM_VARIABLE_FIELD_VERSION: &str = "version";
+
 /// Name of system variable "_event" for events
 pub const EVENT_VARIABLE_NAME: &str = "_event";
 
@@ -89,6 +112,18 @@ pub trait DatamodelFactory: Send {
     fn create(&mut self, global_data: GlobalDataArc, options: &HashMap<String, String>) -> Box<dyn Datamodel>;
 }
 
+/// Collects the datamodel options from the program arguments, e.g. "-datamodel:ecma:strict".\
+/// The [DATAMODEL_OPTION_PREFIX] is removed from the names. All other arguments are ignored.
+pub fn datamodel_options_from_arguments(named_arguments: &HashMap<&str, String>) -> HashMap<String, String> {
+    named_arguments
+        .iter()
+        .filter_map(|(name, value)| {
+            name.strip_prefix(DATAMODEL_OPTION_PREFIX)
+                .map(|option| (option.to_string(), value.clone()))
+        })
+        .collect()
+}
+
 /// Gets the global data store from datamodel.
 #[macro_export]
 macro_rules! get_global {
@@ -108,6 +143,67 @@ pub fn create_global_data_arc() -> GlobalDataArc {
     GlobalDataArc::new(Mutex::from(crate::fsm::GlobalData::new()))
 }
 
+fn filter_sources(data: Vec<&Data>) -> Vec<&SourceCode> {
+    data.into_iter()
+        .filter_map(|d| match d {
+            Data::Source(source) if source.source_id != 0 => Some(source),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Collects the conditions of transitions and of \<if\>/\<elseif\>, see [Datamodel::execute_condition].\
+/// Used by data models to implement [Datamodel::precompile].
+pub fn collect_conditions(fsm: &Fsm) -> Vec<&SourceCode> {
+    let mut data: Vec<&Data> = fsm.transitions.values().map(|t| &t.cond).collect();
+    for content in fsm.executableContent.values() {
+        for ec in content {
+            if ec.get_type() == TYPE_IF {
+                data.extend(
+                    ec.as_ref()
+                        .as_any()
+                        .downcast_ref::<If>()
+                        .map(|e| &e.condition),
+                );
+            }
+        }
+    }
+    filter_sources(data)
+}
+
+/// Collects the expressions and locations of the executable content, see [Datamodel::execute].\
+/// Used by data models to implement [Datamodel::precompile].
+pub fn collect_scripts(fsm: &Fsm) -> Vec<&SourceCode> {
+    let mut data: Vec<&Data> = Vec::new();
+    for content in fsm.executableContent.values() {
+        for ec in content {
+            let any = ec.as_ref().as_any();
+            match ec.get_type() {
+                TYPE_EXPRESSION => data.extend(
+                    any.downcast_ref::<crate::executable_content::Expression>()
+                        .map(|e| &e.content),
+                ),
+                TYPE_LOG => data.extend(any.downcast_ref::<Log>().map(|e| &e.expression)),
+                TYPE_FOREACH => data.extend(any.downcast_ref::<ForEach>().map(|e| &e.array)),
+                TYPE_ASSIGN => {
+                    if let Some(e) = any.downcast_ref::<Assign>() {
+                        data.push(&e.location);
+                        data.push(&e.expr);
+                    }
+                }
+                TYPE_SEND => {
+                    if let Some(e) = any.downcast_ref::<SendParameters>() {
+                        data.extend([&e.event_expr, &e.target_expr, &e.type_expr, &e.delay_expr]);
+                    }
+                }
+                TYPE_CANCEL => data.extend(any.downcast_ref::<Cancel>().map(|e| &e.send_id_expr)),
+                _ => {}
+            }
+        }
+    }
+    filter_sources(data)
+}
+
 /// Data model interface trait.\
 /// *W3C says*:\
 /// The Data Model offers the capability of storing, reading, and modifying a set of data that is internal to the state machine.
@@ -137,6 +233,13 @@ pub trait Datamodel {
     /// sets '_ioprocessors'.
     fn set_ioprocessors(&mut self);
 
+    /// Called once the FSM is assigned to the data model, before any script is executed.\
+    /// Data models can prepare the scripts of the FSM here (see [collect_conditions] and [collect_scripts]), e.g. parse them
+    /// into an engine-specific form that is used instead of the source code later.\
+    /// Errors must not be reported here: a script that can't be compiled shall fail
+    /// (with "error.execution") at the time it is executed.
+    fn precompile(&mut self, _fsm: &Fsm) {}
+
     /// Initialize the data model for one data-store.
     /// This method is called for the global data and for the data of each state.
     #[allow(non_snake_case)]
@@ -144,6 +247,11 @@ pub trait Datamodel {
         let state_obj: &State = fsm.get_state_by_id_mut(state);
         // Set all (simple) global variables.
         self.set_from_state_data(&state_obj.data, set_data);
+        if set_data {
+            for (name, location) in &state_obj.data_src {
+                self.set_from_data_source(name, location);
+            }
+        }
         if state == fsm.pseudo_root {
             let ds = self.global().lock().unwrap().environment.clone();
             self.set_from_state_data(&ds, true);
@@ -155,6 +263,34 @@ pub trait Datamodel {
     /// set_data - if true set the data, otherwise just initialize the variables.
     fn set_from_state_data(&mut self, data: &HashMap<String, DataArc>, set_data: bool);
 
+    /// Fetches the content of a \<data\> element with a 'src' attribute and assigns it.\
+    /// JSON content is evaluated by the datamodel, any other content is assigned as string.\
+    /// *W3C says*:\
+    /// If the 'src' attribute is present, the Platform must fetch the specified object
+    /// at the time specified by the 'binding' attribute of \<scxml\> and must assign it as
+    /// the value of the data element.
+    fn set_from_data_source(&mut self, name: &str, location: &str) {
+        let value = match load_data_source(location) {
+            Ok((content, true)) => str_to_source(format!("({})", content).as_str()),
+            Ok((content, false)) => Data::String(content),
+            Err(err) => {
+                error!("Error on Initialize '{}': {}", name, err);
+                // W3C says:
+                // If the value specified for a <data> element (by 'src', children, or
+                // the environment) is not a legal data value, the SCXML Processor MUST
+                // raise place error.execution in the internal event queue and MUST
+                // create an empty data element in the data model with the specified id.
+                self.set(name, Data::None(), true);
+                self.internal_error_execution();
+                return;
+            }
+        };
+        self.set_from_state_data(
+            &HashMap::from([(name.to_string(), create_data_arc(value))]),
+            true,
+        );
+    }
+
     /// Initialize a global read-only variable.
     fn initialize_read_only(&mut self, name: &str, value: Data) {
         self.initialize_read_only_arc(name, create_data_arc(value));
@@ -215,19 +351,53 @@ pub trait Datamodel {
     fn send(&mut self, ioc_processor: &str, target: &Data, event: Event) -> bool {
         let ioc = self.get_io_processor(ioc_processor);
         if let Some(ic) = ioc {
-            let mut icg = ic.lock().unwrap();
-            icg.send(self.global(), target.to_string().as_str(), event)
+            self.send_with_processor(&ic, target.to_string().as_str(), event)
         } else {
             false
         }
     }
 
+    /// Sends an event via the io-processor.\
+    /// The processor is shared by all sessions of the executor. Events for full queues of other sessions
+    /// that wait for space (see [crate::fsm::OverflowPolicy::Block]) are sent after the processor and the
+    /// global data are unlocked, so the receiving sessions can send meanwhile.
+    fn send_with_processor(
+        &mut self,
+        processor: &Arc<Mutex<Box<dyn EventIOProcessor>>>,
+        target: &str,
+        event: Event,
+    ) -> bool {
+        let sent = processor.lock().unwrap().send(self.global(), target, event);
+        let blocked_sends = std::mem::take(&mut get_global!(self).blocked_sends);
+        for (sender, event) in blocked_sends {
+            if let Err(TrySendError::Disconnected(event) | TrySendError::Full(event)) = sender.send(event) {
+                error!("Can't send '{}' to the session.", event.name);
+                get_global!(self).enqueue_internal(Event::error_communication(&event));
+            }
+        }
+        sent
+    }
+
     /// Clear all data.
     fn clear(&mut self);
 
     /// "log" function, use for \<log\> content.
     fn log(&mut self, msg: &str) {
-        info!("{}", msg);
+        self.log_with_label("", msg);
+    }
+
+    /// Logs a message with the label of a \<log\> element.\
+    /// The message is passed to the [crate::log_sink::LogSink] of the executor or, if none is set, to the log crate.
+    /// See [crate::log_sink].
+    fn log_with_label(&mut self, label: &str, msg: &str) {
+        let (session_id, sink) = {
+            let global = self.global().lock().unwrap();
+            (
+                global.session_id,
+                global.executor.as_ref().and_then(|e| e.log_sink.clone()),
+            )
+        };
+        log_message(sink.as_deref(), session_id, label, msg);
     }
 
     /// Executes a script.\
@@ -286,23 +456,39 @@ pub trait Datamodel {
         get_global!(self).enqueue_internal(Event::error_communication(event));
     }
 
+    /// Evaluates structured static content (JSON objects or arrays).\
+    /// Shall not put "error.execution" in the queue if the content is not valid.
+    fn evaluate_structured_content(&mut self, content: &str) -> Result<DataArc, String> {
+        self.execute(&str_to_source(format!("({})", content).as_str()))
+    }
+
     /// Evaluates a content element.\
     /// Returns the static content or executes the expression.
+    /// Static JSON objects and arrays are evaluated by the datamodel, other static content that is
+    /// not numeric is returned as string. Markup content is always returned as string.
     fn evaluate_content(&mut self, content: &Option<CommonContent>) -> Option<DataArc> {
         match content {
             None => None,
             Some(ct) => {
                 match &ct.content_expr {
-                    None => ct
-                        .content
-                        .as_ref()
-                        .map(|ct_content| match ct_content.parse::<f64>() {
+                    None => ct.content.as_ref().map(|ct_content| {
+                        if ct.content_kind == ContentKind::Markup {
+                            return create_data_arc(Data::String(ct_content.clone()));
+                        }
+                        if ct_content.starts_with('{') || ct_content.starts_with('[') {
+                            // Content that can't be interpreted is used as string.
+                            if let Ok(value) = self.evaluate_structured_content(ct_content) {
+                                return value;
+                            }
+                        }
+                        match ct_content.parse::<f64>() {
                             Ok(value) => match numeric_to_integer(&Data::Double(value)) {
                                 Some(i) => create_data_arc(Data::Integer(i)),
                                 None => create_data_arc(Data::Double(value)),
                             },
                             Err(_) => create_data_arc(Data::String(ct_content.clone())),
-                        }),
+                        }
+                    }),
                     Some(expr) => {
                         match self.execute(&str_to_source(expr.as_str())) {
                             Err(msg) => {
@@ -479,12 +665,15 @@ impl Datamodel for NullDatamodel {
 
     fn clear(self: &mut NullDatamodel) {}
 
-    fn log(self: &mut NullDatamodel, msg: &str) {
-        println!("{}", msg);
-    }
-
-    fn execute(&mut self, _script: &Data) -> Result<DataArc, String> {
-        Err("unimplemented".to_string())
+    /// There is no scripting language. An empty script (e.g. a \<log\> with label only) results in an
+    /// empty string, all other scripts put "error.execution" in the internal queue.
+    fn execute(&mut self, script: &Data) -> Result<DataArc, String> {
+        if script.is_empty() {
+            Ok(create_data_arc(Data::String(String::new())))
+        } else {
+            self.internal_error_execution();
+            Err(format!("Null datamodel can't execute '{}'", script))
+        }
     }
 
     fn execute_for_each(
@@ -501,36 +690,42 @@ impl Datamodel for NullDatamodel {
     /// *W3C says*:
     /// The boolean expression language consists of the In predicate only.
     /// It has the form 'In(id)', where id is the id of a state in the enclosing state machine.
-    /// The predicate must return 'true' if and only if that state is in the current state configuration.
+    /// The predicate must return 'true' if and only if that state is in the current state configuration.\
+    /// Any other condition is an error, the caller puts "error.execution" in the internal queue.
     fn execute_condition(&mut self, script: &Data) -> Result<bool, String> {
         let mut lexer = ExpressionLexer::new(script.to_string());
         if lexer.next_token() == Token::Identifier("In".to_string()) && lexer.next_token() == Token::Bracket('(') {
-            match lexer.next_token() {
-                Token::TString(state_name) | Token::Identifier(state_name) => {
-                    if lexer.next_token() != Token::Bracket(')') {
-                        return Err("Matching ')' is missing".to_string());
-                    } else {
-                        return match self.state_name_to_id.get(&state_name) {
-                            None => Err(format!("Illegal state name '{}'", state_name)),
-                            Some(state_id) => Ok(self
-                                .global
-                                .lock()
-                                .unwrap()
-                                .configuration
-                                .data
-                                .contains(state_id)),
-                        };
-                    }
+            if let Token::TString(state_name) = lexer.next_token() {
+                if lexer.next_token() != Token::Bracket(')') {
+                    return Err("Matching ')' is missing".to_string());
+                } else if lexer.next_token() != Token::EOE {
+                    return Err("Only a single 'In' predicate is supported".to_string());
+                } else {
+                    return match self.state_name_to_id.get(&state_name) {
+                        None => Err(format!("Illegal state name '{}'", state_name)),
+                        Some(state_id) => Ok(self
+                            .global
+                            .lock()
+                            .unwrap()
+                            .configuration
+                            .data
+                            .contains(state_id)),
+                    };
                 }
-                _ => {}
             }
         }
         Err("Syntax error".to_string())
     }
 
     #[allow(non_snake_case)]
-    fn executeContent(&mut self, _fsm: &Fsm, _content_id: ExecutableContentId) -> bool {
-        // Nothing
+    fn executeContent(&mut self, fsm: &Fsm, content_id: ExecutableContentId) -> bool {
+        if let Some(ec) = fsm.executableContent.get(&content_id) {
+            for e in ec.iter() {
+                if !execute_with_source_line(e.as_ref(), self, fsm) {
+                    return false;
+                }
+            }
+        }
         true
     }
 }
@@ -694,13 +889,22 @@ pub fn operation_multiply(left: &Data, right: &Data) -> Data {
     }
 }
 
-/// Implements a ":" operation on Data items.
+/// Implements a "/" operation on Data items.\
+/// The division of two integers results in an integer if the division has no remainder.
+/// Otherwise, the result is a double, e.g. "6/4" is 1.5.
 pub fn operation_divide(left: &Data, right: &Data) -> Data {
     if left.is_numeric() && right.is_numeric() {
+        if let (Data::Integer(i1), Data::Integer(i2)) = (left, right) {
+            if let Some(0) = i1.checked_rem(*i2) {
+                if let Some(r) = i1.checked_div(*i2) {
+                    return Data::Integer(r);
+                }
+            }
+        }
         let right_value = right.as_number();
         let r = left.as_number() / right_value;
         if r.is_nan() {
-            // This covers also division by 0.
+            // This covers also 0/0.
             Data::Error("Result of '/' is NaN".to_string())
         } else {
             Data::Double(r)
@@ -717,7 +921,10 @@ pub fn operation_modulus(left: &Data, right: &Data) -> Data {
             (Data::Double(d1), Data::Double(d2)) => Data::Double(d1 % d2),
             (Data::Integer(d1), Data::Double(d2)) => Data::Double((*d1 as f64) % d2),
             (Data::Double(d1), Data::Integer(d2)) => Data::Double((*d1) % (*d2 as f64)),
-            (Data::Integer(i1), Data::Integer(i2)) => Data::Integer(i1 % i2),
+            (Data::Integer(i1), Data::Integer(i2)) => match i1.checked_rem(*i2) {
+                Some(r) => Data::Integer(r),
+                None => Data::Error("Integer division by zero in '%'".to_string()),
+            },
             _ => Data::Error("Internal Error in '%' operation".to_string()),
         }
     } else {
@@ -725,82 +932,113 @@ pub fn operation_modulus(left: &Data, right: &Data) -> Data {
     }
 }
 
-/// Implements a "<" (less) operation on Data items.
-pub fn operation_less(left: &crate::datamodel::Data, right: &crate::datamodel::Data) -> crate::datamodel::Data {
-    if left.is_numeric() && right.is_numeric() {
-        Data::Boolean(left.as_number() < right.as_number())
+/// Compares an integer with a double by value.\
+/// Works also for integers beyond 2^53, that can't be converted to f64 without loss.
+/// Returns None if the double is NaN.
+fn compare_integer_double(i: i64, d: f64) -> Option<Ordering> {
+    // 2^63 is exactly representable as f64.
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if d.is_nan() {
+        None
+    } else if d >= LIMIT {
+        Some(Ordering::Less)
+    } else if d < -LIMIT {
+        Some(Ordering::Greater)
     } else {
-        match (left, right) {
-            (Data::String(_) | Data::Source(_), Data::String(_) | Data::Source(_)) => {
-                Data::Boolean(left.to_string() < right.to_string())
-            }
-            _ => {
-                #[cfg(feature = "Debug")]
-                warn!("'<' supports only numeric or string types");
-                Data::Boolean(false)
-            }
+        let truncated = d.trunc();
+        match i.cmp(&(truncated as i64)) {
+            Ordering::Equal => 0f64.partial_cmp(&(d - truncated)),
+            o => Some(o),
         }
     }
 }
 
-/// Implements a "<=" (less or equal) operation on Data items.
-pub fn operation_less_equal(left: &crate::datamodel::Data, right: &crate::datamodel::Data) -> crate::datamodel::Data {
-    if left.is_numeric() && right.is_numeric() {
-        Data::Boolean(left.as_number() <= right.as_number())
-    } else {
-        match (left, right) {
-            (Data::String(_) | Data::Source(_), Data::String(_) | Data::Source(_)) => {
-                Data::Boolean(left.to_string() <= right.to_string())
-            }
-            _ => {
-                #[cfg(feature = "Debug")]
-                warn!("'<=' supports only numeric or string types");
-                Data::Boolean(false)
-            }
-        }
+/// Compares two numeric Data items by value.\
+/// Integers are compared exactly, also if compared with a Double.
+/// Returns None if one side is NaN.
+fn compare_numeric(left: &Data, right: &Data) -> Option<Ordering> {
+    match (left, right) {
+        (Data::Integer(i1), Data::Integer(i2)) => Some(i1.cmp(i2)),
+        (Data::Integer(i), Data::Double(d)) => compare_integer_double(*i, *d),
+        (Data::Double(d), Data::Integer(i)) => compare_integer_double(*i, *d).map(|o| o.reverse()),
+        _ => left.as_number().partial_cmp(&right.as_number()),
     }
 }
 
-/// Implements a ">" (greater) operation on Data items.
-pub fn operation_greater(left: &crate::datamodel::Data, right: &crate::datamodel::Data) -> crate::datamodel::Data {
-    if left.is_numeric() && right.is_numeric() {
-        Data::Boolean(left.as_number() > right.as_number())
-    } else {
-        match (left, right) {
-            (Data::String(_) | Data::Source(_), Data::String(_) | Data::Source(_)) => {
-                Data::Boolean(left.to_string() > right.to_string())
-            }
-            _ => Data::Error("'>' supports only numeric or string types".to_string()),
-        }
+fn is_text(data: &Data) -> bool {
+    matches!(data, Data::String(_) | Data::Source(_))
+}
+
+fn is_number(data: &Data) -> bool {
+    matches!(data, Data::Integer(_) | Data::Double(_))
+}
+
+/// Implements the ordering operations on Data items.\
+/// Numeric values are compared by value, strings lexicographically.
+/// All other combinations, e.g. strings with numbers or booleans, result in an error.
+fn operation_compare(left: &Data, right: &Data, operator: &str, accept: fn(Ordering) -> bool) -> Data {
+    match (left, right) {
+        (_, Data::Error(err)) | (Data::Error(err), _) => Data::Error(err.clone()),
+        _ if left.is_numeric() && right.is_numeric() => Data::Boolean(compare_numeric(left, right).is_some_and(accept)),
+        _ if is_text(left) && is_text(right) => Data::Boolean(accept(left.to_string().cmp(&right.to_string()))),
+        _ => Data::Error(format!(
+            "'{}' supports only numeric or string types",
+            operator
+        )),
     }
 }
 
+/// Implements a "<" (less) operation on Data items.
+pub fn operation_less(left: &Data, right: &Data) -> Data {
+    operation_compare(left, right, "<", |o| o < Ordering::Equal)
+}
+
+/// Implements a "<=" (less or equal) operation on Data items.
+pub fn operation_less_equal(left: &Data, right: &Data) -> Data {
+    operation_compare(left, right, "<=", |o| o <= Ordering::Equal)
+}
+
+/// Implements a ">" (greater) operation on Data items.
+pub fn operation_greater(left: &Data, right: &Data) -> Data {
+    operation_compare(left, right, ">", |o| o > Ordering::Equal)
+}
+
 /// Implements a ">=" (greater or equal) operation on Data items.
 pub fn operation_greater_equal(left: &Data, right: &Data) -> Data {
-    if left.is_numeric() && right.is_numeric() {
-        Data::Boolean(left.as_number() >= right.as_number())
-    } else {
-        match (left, right) {
-            (Data::String(_) | Data::Source(_), Data::String(_) | Data::Source(_)) => {
-                Data::Boolean(left.to_string() >= right.to_string())
-            }
-            _ => {
-                #[cfg(feature = "Debug")]
-                warn!("'>=' supports only numeric or string types");
-                Data::Boolean(false)
-            }
-        }
+    operation_compare(left, right, ">=", |o| o >= Ordering::Equal)
+}
+
+/// Checks two Data items for equality.\
+/// Numbers are compared by value, so "1 == 1.0". Strings are not converted to numbers,
+/// a comparison of a string with a number is an error. All other types are equal only to
+/// the same type with equal content.
+fn data_equals(left: &Data, right: &Data, operator: &str) -> Result<bool, String> {
+    match (left, right) {
+        (_, Data::Error(err)) | (Data::Error(err), _) => Err(err.clone()),
+        _ if is_number(left) && is_number(right) => Ok(compare_numeric(left, right) == Some(Ordering::Equal)),
+        _ if is_text(left) && is_text(right) => Ok(left.to_string() == right.to_string()),
+        _ if (is_text(left) && is_number(right)) || (is_number(left) && is_text(right)) => Err(format!(
+            "Can't compare string and number with '{}'",
+            operator
+        )),
+        _ => Ok(left.eq(right)),
     }
 }
 
 /// Implements a "==" (equal) operation on Data items.
 pub fn operation_equal(left: &Data, right: &Data) -> Data {
-    Data::Boolean(left.eq(right))
+    match data_equals(left, right, "==") {
+        Ok(b) => Data::Boolean(b),
+        Err(err) => Data::Error(err),
+    }
 }
 
 /// Implements a "!=" (not equal) operation on Data items.
 pub fn operation_not_equal(left: &Data, right: &Data) -> Data {
-    Data::Boolean(!left.eq(right))
+    match data_equals(left, right, "!=") {
+        Ok(b) => Data::Boolean(!b),
+        Err(err) => Data::Error(err),
+    }
 }
 
 pub trait ToAny: 'static {
@@ -882,6 +1120,94 @@ pub enum Data {
 
 /// Create a Data::Source from a str with invalid id.\
 /// Should be used for calculated script source, that is not part of FSM definition.
+/// Loads the content of a data source, as given by the 'src' attribute of \<data\>.\
+/// The location is a file path, a "file:" URL or (if build with "xml" or "BasicHttpEventIOProcessor")
+/// a "http(s):" URL.\
+/// Returns the content and true if the content is JSON, either indicated by the file extension resp.
+/// content type or (with "json-config") detected from the content itself.
+pub fn load_data_source(location: &str) -> Result<(String, bool), String> {
+    let location_lc = location.to_ascii_lowercase();
+    if location_lc.starts_with("http:") || location_lc.starts_with("https:") {
+        #[cfg(all(
+            any(feature = "xml", feature = "BasicHttpEventIOProcessor"),
+            not(target_arch = "wasm32")
+        ))]
+        {
+            let response =
+                reqwest::blocking::get(location).map_err(|e| format!("Failed to download {}. {}", location, e))?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "Failed to download {}. Status {}",
+                    location,
+                    response.status()
+                ));
+            }
+            let json_type = response
+                .headers()
+                .get(reqwest::header::CONTENT_TYPE)
+                .and_then(|ct| ct.to_str().ok())
+                .map(|ct| ct.to_ascii_lowercase().contains("json"))
+                .unwrap_or(false);
+            let content = response
+                .text()
+                .map_err(|e| format!("Failed to decode from {}. {}", location, e))?;
+            let is_json = json_type || is_json_content(content.as_str());
+            Ok((content, is_json))
+        }
+        #[cfg(any(
+            not(any(feature = "xml", feature = "BasicHttpEventIOProcessor")),
+            target_arch = "wasm32"
+        ))]
+        Err(format!(
+            "Can't load '{}'. HTTP is not supported in this build",
+            location
+        ))
+    } else {
+        let path = if location_lc.starts_with("file:") {
+            &location[5..]
+        } else {
+            location
+        };
+        match std::fs::read_to_string(path) {
+            Ok(content) => {
+                let is_json = path.to_ascii_lowercase().ends_with(".json") || is_json_content(content.as_str());
+                Ok((content, is_json))
+            }
+            Err(e) => Err(format!("Can't read data source '{}'. {}", location, e)),
+        }
+    }
+}
+
+#[cfg(feature = "json-config")]
+fn is_json_content(content: &str) -> bool {
+    serde_json::from_str::<serde_json::Value>(content).is_ok()
+}
+
+/// Converts a JSON value to data. Objects are converted to maps.
+#[cfg(feature = "json-config")]
+pub fn json_to_data(value: &serde_json::Value) -> Data {
+    match value {
+        serde_json::Value::Null => Data::Null(),
+        serde_json::Value::Bool(b) => Data::Boolean(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Data::Integer(i),
+            None => Data::Double(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Data::String(s.clone()),
+        serde_json::Value::Array(a) => Data::Array(a.iter().map(|v| create_data_arc(json_to_data(v))).collect()),
+        serde_json::Value::Object(o) => Data::Map(
+            o.iter()
+                .map(|(k, v)| (k.clone(), create_data_arc(json_to_data(v))))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(not(feature = "json-config"))]
+fn is_json_content(_content: &str) -> bool {
+    false
+}
+
 pub fn str_to_source(str: &str) -> Data {
     Data::Source(SourceCode::new(str, 0))
 }
@@ -915,10 +1241,11 @@ impl PartialEq for Data {
             true
         } else {
             match (self, other) {
-                (Data::Integer(a), Data::Double(b)) => (*a as f64) == *b,
+                (Data::Integer(a), Data::Double(b)) | (Data::Double(b), Data::Integer(a)) => {
+                    compare_integer_double(*a, *b) == Some(Ordering::Equal)
+                }
                 (Data::Integer(a), Data::Integer(b)) => *a == *b,
                 (Data::Double(a), Data::Double(b)) => *a == *b,
-                (Data::Double(a), Data::Integer(b)) => *a == (*b as f64),
                 (Data::String(a), Data::String(b)) => *a == *b,
                 (Data::Boolean(a), Data::Boolean(b)) => *a == *b,
                 (Data::Array(a), Data::Array(b)) => {
@@ -1131,6 +1458,23 @@ impl DataArc {
             self.flags &= !DATA_FLAG_READONLY;
         }
     }
+
+    /// Creates a copy that shares no values with this instance, also not the members of arrays and maps.
+    pub fn deep_copy(&self) -> DataArc {
+        let copy = match self.lock().unwrap().deref() {
+            Data::Map(map) => Data::Map(
+                map.iter()
+                    .map(|(key, value)| (key.clone(), value.deep_copy()))
+                    .collect(),
+            ),
+            Data::Array(array) => Data::Array(array.iter().map(|value| value.deep_copy()).collect()),
+            other => other.clone(),
+        };
+        DataArc {
+            arc: Arc::new(Mutex::from(copy)),
+            flags: self.flags,
+        }
+    }
 }
 
 impl PartialEq for DataArc {
@@ -1241,3 +1585,112 @@ impl DataStore {
         debug!("--------------------")
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[cfg(feature = "xml")]
+    fn run_null_datamodel(xml: &str) -> Option<Vec<String>> {
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        session
+            .wait_for_completion(Duration::from_millis(2000))
+            .and_then(|result| result.final_configuration)
+    }
+
+    /// Like W3C test 436: In() is true only for active states.
+    #[test]
+    #[cfg(feature = "xml")]
+    fn null_datamodel_in_predicate() {
+        let xml = r##"<scxml initial='P' datamodel='null'>
+              <parallel id='P'>
+                <state id='PS0'>
+                  <transition cond="In('S1')" target='fail'/>
+                  <transition cond="In('PS1')" target='pass'/>
+                  <transition target='fail'/>
+                </state>
+                <state id='PS1'/>
+              </parallel>
+              <state id='S1'/>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##;
+        assert_eq!(run_null_datamodel(xml), Some(vec!["pass".to_string()]));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn null_datamodel_rejects_other_conditions() {
+        for cond in [
+            "true",
+            "In(Main)",
+            "In('Main') &amp;&amp; In('Main')",
+            "In('Unknown')",
+        ] {
+            let xml = format!(
+                r##"<scxml initial='Main' datamodel='null'>
+                  <state id='Main'>
+                    <transition cond="{}" target='fail'/>
+                    <transition event='error.execution' target='pass'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##,
+                cond
+            );
+            assert_eq!(
+                run_null_datamodel(xml.as_str()),
+                Some(vec!["pass".to_string()]),
+                "Condition {}",
+                cond
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn null_datamodel_executes_log_raise_and_send() {
+        let xml = r##"<scxml initial='Main' datamodel='null'>
+              <state id='Main'>
+                <onentry>
+                  <log label='entered'/>
+                  <raise event='raised'/>
+                  <send event='sent' target='#_internal'/>
+                </onentry>
+                <transition event='raised' target='Raised'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Raised'>
+                <transition event='sent' target='Sent'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Sent'>
+                <onentry>
+                  <log label='error' expr='1 + 1'/>
+                </onentry>
+                <transition event='error.execution' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##;
+        assert_eq!(run_null_datamodel(xml), Some(vec!["pass".to_string()]));
+    }
+}
@@ -0,0 +1,211 @@
+//! Cache for documents that are started by location, e.g. by \<invoke src="child.scxml"\>.\
+//! The first start reads the document and stores the model as [FsmTemplate], further starts of the same
+//! document execute a copy of the template without reading the document again.\
+//! Documents are identified by their canonical path. The content of the file is hashed on each start,
+//! so a modified document is read again. Documents that are included via XInclude are not checked.
+//! Only local files are cached.\
+//! The cache is disabled by default, see [crate::fsm_executor::FsmExecutor::set_document_cache_enabled].
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::PathBuf;
+
+#[cfg(feature = "Debug")]
+use log::debug;
+
+use crate::fsm::{uri_scheme, Fsm};
+
+/// The model of a document that is shared by all sessions of the document.
+pub struct FsmTemplate {
+    fsm: Box<Fsm>,
+}
+
+impl FsmTemplate {
+    pub fn new(fsm: Box<Fsm>) -> FsmTemplate {
+        FsmTemplate { fsm }
+    }
+
+    /// Creates a FSM that can be executed by a new session, see [Fsm::copy_model].\
+    /// Returns None if the model can't be copied.
+    pub fn instantiate(&self) -> Option<Box<Fsm>> {
+        self.fsm.copy_model()
+    }
+}
+
+/// Statistics of a [DocumentCache].
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DocumentCacheStats {
+    /// Number of starts that used a cached template.
+    pub hits: usize,
+    /// Number of starts that had to read the document.
+    pub misses: usize,
+    /// Number of cached documents.
+    pub documents: usize,
+}
+
+/// Identifies a document in the [DocumentCache], see [DocumentCache::key].
+pub struct DocumentKey {
+    path: PathBuf,
+    hash: u64,
+}
+
+pub struct DocumentCache {
+    enabled: bool,
+    /// Templates by canonical path, with the hash of the content they were read from.
+    templates: HashMap<PathBuf, (u64, FsmTemplate)>,
+    stats: DocumentCacheStats,
+}
+
+impl Default for DocumentCache {
+    fn default() -> Self {
+        DocumentCache::new()
+    }
+}
+
+impl DocumentCache {
+    pub fn new() -> DocumentCache {
+        DocumentCache {
+            enabled: false,
+            templates: HashMap::new(),
+            stats: DocumentCacheStats::default(),
+        }
+    }
+
+    /// Enables or disables the cache. If disabled, the cached documents are removed.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.clear();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Removes all cached documents. The statistics are kept.
+    pub fn clear(&mut self) {
+        self.templates.clear();
+        self.stats.documents = 0;
+    }
+
+    pub fn stats(&self) -> DocumentCacheStats {
+        self.stats
+    }
+
+    /// Gets the key of the document at "uri".\
+    /// Returns None if the cache is disabled or the document is not a local file that can be read.
+    /// The file is read to hash its content, so call it without holding the lock of the cache.
+    pub fn key(enabled: bool, uri: &str) -> Option<DocumentKey> {
+        if !enabled {
+            return None;
+        }
+        let path = match uri.strip_prefix("file://") {
+            Some(path) => path,
+            None if uri_scheme(uri).is_none() => uri,
+            None => return None,
+        };
+        // Errors are reported (or the include paths are searched) when the document is loaded.
+        let path = fs::canonicalize(path).ok()?;
+        let content = fs::read(&path).ok()?;
+        let mut hasher = DefaultHasher::new();
+        content.hash(&mut hasher);
+        Some(DocumentKey {
+            path,
+            hash: hasher.finish(),
+        })
+    }
+
+    /// Gets a FSM for the cached document.\
+    /// Returns None if the document is not cached (or the file has changed), use [DocumentCache::insert]
+    /// after the document was loaded.
+    pub fn get(&mut self, key: &DocumentKey) -> Option<Box<Fsm>> {
+        if let Some((template_hash, template)) = self.templates.get(&key.path) {
+            if *template_hash == key.hash {
+                self.stats.hits += 1;
+                return template.instantiate();
+            }
+        }
+        #[cfg(feature = "Debug")]
+        debug!("Document {:?} is not cached", key.path);
+        self.stats.misses += 1;
+        None
+    }
+
+    /// Stores the loaded document and returns a FSM for the session that starts it.\
+    /// Documents whose model can't be copied are not stored.
+    pub fn insert(&mut self, key: DocumentKey, fsm: Box<Fsm>) -> Box<Fsm> {
+        if !self.enabled {
+            return fsm;
+        }
+        let copy = match fsm.copy_model() {
+            Some(copy) => copy,
+            None => {
+                #[cfg(feature = "Debug")]
+                debug!("Document {:?} can't be copied and is not cached", key.path);
+                return fsm;
+            }
+        };
+        self.templates
+            .insert(key.path, (key.hash, FsmTemplate::new(fsm)));
+        self.stats.documents = self.templates.len();
+        copy
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::datamodel::Datamodel;
+    use crate::document_cache::{DocumentCache, DocumentKey};
+    use crate::executable_content::{ExecutableContent, ExecutableContentTracer};
+    use crate::fsm::{Fsm, SourceLine};
+
+    /// Executable content of an application that doesn't implement "get_copy".
+    #[derive(Debug)]
+    struct Custom {}
+
+    impl ExecutableContent for Custom {
+        fn execute(&self, _datamodel: &mut dyn Datamodel, _fsm: &Fsm) -> bool {
+            true
+        }
+
+        fn get_type(&self) -> u8 {
+            u8::MAX
+        }
+
+        fn trace(&self, _tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {}
+
+        fn get_source_line(&self) -> SourceLine {
+            0
+        }
+
+        fn set_source_line(&mut self, _line: SourceLine) {}
+    }
+
+    #[test]
+    fn documents_that_cant_be_copied_are_not_cached() {
+        let key = || DocumentKey {
+            path: PathBuf::from("child.scxml"),
+            hash: 1,
+        };
+        let mut cache = DocumentCache::new();
+        cache.set_enabled(true);
+
+        cache.insert(key(), Box::new(Fsm::new()));
+        assert_eq!(cache.stats().documents, 1);
+        assert!(cache.get(&key()).is_some());
+
+        cache.clear();
+        let mut fsm = Box::new(Fsm::new());
+        fsm.executableContent.insert(1, vec![Box::new(Custom {})]);
+        // The loaded model is used by the session, but not cached.
+        let fsm = cache.insert(key(), fsm);
+        assert_eq!(fsm.executableContent.len(), 1);
+        assert_eq!(cache.stats().documents, 0);
+        assert!(cache.get(&key()).is_none());
+    }
+}
@@ -2,9 +2,13 @@
 //! Included if feature "ECMAScript" is enabled.\
 //! See [W3C:The ECMAScript Data Model](/doc/W3C_SCXML_2024_07_13/index.html#ecma-profile).\
 //! See [GitHub:Boa Engine](https://github.com/boa-dev/boa).
+//!
+//! Each session creates its own Boa [Context], including sessions started by \<invoke\>.
+//! With option "ecma:shared-context" invoked sessions share the context of the invoking session,
+//! see [crate::ecma_script_shared_context].
 
-use std::collections::HashMap;
-use std::ops::Deref;
+use std::collections::{HashMap, HashSet};
+use std::ops::{Deref, DerefMut};
 use std::string::ToString;
 
 #[cfg(test)]
@@ -28,24 +32,29 @@ use log::{error, info};
 use crate::ArgOption;
 use boa_engine::context::ContextBuilder;
 use boa_engine::object::builtins::{JsArray, JsMap};
-use boa_engine::object::ObjectInitializer;
+use boa_engine::object::{IntegrityLevel, JsObject, ObjectInitializer};
 use boa_engine::property::{Attribute, PropertyDescriptor, PropertyKey};
+use boa_engine::realm::Realm;
 use boa_engine::value::Type;
-use boa_engine::{js_string, native_function::NativeFunction, Context, JsBigInt, JsError, JsValue, Source};
+use boa_engine::{js_string, native_function::NativeFunction, Context, JsBigInt, JsError, JsValue, Script, Source};
 use boa_engine::{JsArgs, JsData, JsResult};
 use boa_gc::{empty_trace, Finalize, Trace};
 
 use crate::datamodel::{
-    create_data_arc, str_to_source, Data, DataArc, Datamodel, DatamodelFactory, GlobalDataArc,
-    EVENT_VARIABLE_FIELD_DATA, EVENT_VARIABLE_FIELD_INVOKE_ID, EVENT_VARIABLE_FIELD_NAME, EVENT_VARIABLE_FIELD_ORIGIN,
-    EVENT_VARIABLE_FIELD_ORIGIN_TYPE, EVENT_VARIABLE_FIELD_SEND_ID, EVENT_VARIABLE_FIELD_TYPE, EVENT_VARIABLE_NAME,
+    collect_conditions, collect_scripts, create_data_arc, str_to_source, Data, DataArc, Datamodel, DatamodelFactory,
+    GlobalDataArc, SourceCode, SourceId, EVENT_VARIABLE_FIELD_DATA, EVENT_VARIABLE_FIELD_INVOKE_ID,
+    EVENT_VARIABLE_FIELD_NAME, EVENT_VARIABLE_FIELD_ORIGIN, EVENT_VARIABLE_FIELD_ORIGIN_TYPE,
+    EVENT_VARIABLE_FIELD_SEND_ID, EVENT_VARIABLE_FIELD_TYPE, EVENT_VARIABLE_NAME,
 };
 use crate::event_io_processor::SYS_IO_PROCESSORS;
 
 #[cfg(feature = "Trace")]
 use crate::executable_content::DefaultExecutableContentTracer;
 
-use crate::executable_content::{ExecutableContent, ExecutableContentTracer};
+use crate::ecma_script_shared_context::SharedContextDatamodel;
+use crate::executable_content::{
+    execute_with_source_line, Assign, ExecutableContent, ExecutableContentTracer, INDEX_TEMP, TYPE_ASSIGN,
+};
 use crate::fsm::{ExecutableContentId, Fsm, StateId};
 
 pub const ECMA_SCRIPT: &str = "ECMAScript";
@@ -54,7 +63,13 @@ pub const ECMA_SCRIPT_LC: &str = "ecmascript";
 pub const ECMA_OPTION_INFIX: &str = "ecma:";
 pub const ECMA_OPTION_STRICT_POSTFIX: &str = "strict";
 
+pub const ECMA_OPTION_SHARED_CONTEXT_POSTFIX: &str = "shared-context";
+
 pub const ECMA_STRICT_OPTION: &str = "datamodel:ecma:strict";
+pub const ECMA_SHARED_CONTEXT_OPTION: &str = "datamodel:ecma:shared-context";
+
+/// Temporary global that holds the value of an \<assign\> with child content or of a \<foreach\> item.
+const ASSIGN_VALUE_VARIABLE_NAME: &str = "__assign_value";
 
 pub static ECMA_STRICT_ARGUMENT: ArgOption = ArgOption {
     name: ECMA_STRICT_OPTION,
@@ -62,17 +77,69 @@ pub static ECMA_STRICT_ARGUMENT: ArgOption = ArgOption {
     required: false,
 };
 
+pub static ECMA_SHARED_CONTEXT_ARGUMENT: ArgOption = ArgOption {
+    name: ECMA_SHARED_CONTEXT_OPTION,
+    with_value: false,
+    required: false,
+};
+
+/// The Boa context of a datamodel.\
+/// A datamodel in a shared context (see [crate::ecma_script_shared_context]) holds the context only
+/// while it executes a request.
+pub struct ContextSlot(Option<Context>);
+
+impl Deref for ContextSlot {
+    type Target = Context;
+
+    fn deref(&self) -> &Context {
+        self.0.as_ref().expect("ECMAScript context is not attached")
+    }
+}
+
+impl DerefMut for ContextSlot {
+    fn deref_mut(&mut self) -> &mut Context {
+        self.0.as_mut().expect("ECMAScript context is not attached")
+    }
+}
+
+/// A \<foreach\> loop that is currently executed.
+struct ForEachLoop {
+    item: String,
+    index: String,
+    /// Copy of the items, the body may modify the collection.
+    items: Vec<JsValue>,
+    /// Values of item and index of an enclosing loop that uses the same variables.
+    enclosing_item: Option<JsValue>,
+    enclosing_index: Option<JsValue>,
+}
+
 pub struct ECMAScriptDatamodel {
     pub global_data: GlobalDataArc,
-    pub context: Context,
+    pub context: ContextSlot,
+    /// Realm of the session, if the context is shared.
+    realm: Option<Realm>,
     pub tracer: Option<Box<dyn ExecutableContentTracer>>,
     pub strict_mode: bool,
+    /// Names of the read-only system variables.
+    read_only: HashSet<String>,
+    /// The \<foreach\> loops that are currently executed, the innermost loop is the last.
+    for_each_loops: Vec<ForEachLoop>,
+    /// Parsed scripts of the FSM by source id, see [Datamodel::precompile].
+    scripts: HashMap<SourceId, Script>,
+    /// Parsed "location=expr" scripts of \<assign\>, by the source ids of location and expression.
+    assignments: HashMap<(SourceId, SourceId), Script>,
 }
 
 pub struct ECMAScriptDatamodelFactory {}
 
 impl DatamodelFactory for ECMAScriptDatamodelFactory {
     fn create(&mut self, global_data: GlobalDataArc, options: &HashMap<String, String>) -> Box<dyn Datamodel> {
+        if options
+            .keys()
+            .any(|name| name.strip_prefix(ECMA_OPTION_INFIX) == Some(ECMA_OPTION_SHARED_CONTEXT_POSTFIX))
+        {
+            return Box::new(SharedContextDatamodel::new(global_data, options));
+        }
         let mut ecma = Box::new(ECMAScriptDatamodel::new(global_data));
         for (key, value) in options {
             ecma.set_option(key.as_str(), value.as_str());
@@ -81,6 +148,30 @@ impl DatamodelFactory for ECMAScriptDatamodelFactory {
     }
 }
 
+/// Collects location and expression of all \<assign\> elements with source code.
+pub(crate) fn collect_assignments(fsm: &Fsm) -> Vec<(&SourceCode, &SourceCode)> {
+    let mut assignments = Vec::new();
+    for content in fsm.executableContent.values().flatten() {
+        if content.get_type() != TYPE_ASSIGN {
+            continue;
+        }
+        if let Some(Assign {
+            location: Data::Source(location),
+            expr: Data::Source(expr),
+            ..
+        }) = content.as_any().downcast_ref::<Assign>()
+        {
+            assignments.push((location, expr));
+        }
+    }
+    assignments
+}
+
+/// Wraps a conditional expression, so that the result is converted to a boolean value.
+fn to_boolean(condition: &str) -> String {
+    format!("({})?true:false", condition)
+}
+
 fn js_to_string(jv: &JsValue, ctx: &mut Context) -> String {
     match jv.to_string(ctx) {
         Ok(s) => s.to_std_string().unwrap().clone(),
@@ -108,18 +199,52 @@ fn data_value_to_js(data: &Data, context: &mut Context) -> JsValue {
             JsValue::from(js_array)
         }
         Data::Map(v) => {
-            let js_map = JsMap::new(context);
+            // Plain object, so that members can be accessed via "a.b".
+            let mut properties = Vec::with_capacity(v.len());
             for (key, d) in v {
-                let djs = data_value_to_js(&d.lock().unwrap(), context);
-                let _ = js_map.set(js_string!(key.clone()), djs, context);
+                properties.push((
+                    js_string!(key.clone()),
+                    data_value_to_js(&d.lock().unwrap(), context),
+                ));
             }
-            JsValue::from(js_map)
+            let mut object_initializer = ObjectInitializer::new(context);
+            for (key, djs) in properties {
+                object_initializer.property(key, djs, Attribute::all());
+            }
+            JsValue::from(object_initializer.build())
         }
         Data::Error(_error) => JsValue::Null,
         Data::Source(source) => JsValue::String(js_string!(source.source.clone())),
     }
 }
 
+/// Converts the data to JS. Maps are converted to plain objects and all objects are frozen.
+fn data_value_to_read_only_js(data: &Data, context: &mut Context) -> JsValue {
+    let object = match data {
+        Data::Array(v) => {
+            let js_array = JsArray::new(context);
+            for data in v {
+                let djs = data_value_to_read_only_js(&data.lock().unwrap(), context);
+                let _ = js_array.push(djs, context);
+            }
+            JsObject::from(js_array)
+        }
+        Data::Map(v) => {
+            let js_object = JsObject::with_object_proto(context.intrinsics());
+            for (key, d) in v {
+                let djs = data_value_to_read_only_js(&d.lock().unwrap(), context);
+                let _ = js_object.create_data_property(js_string!(key.clone()), djs, context);
+            }
+            js_object
+        }
+        _ => {
+            return data_value_to_js(data, context);
+        }
+    };
+    let _ = object.set_integrity_level(IntegrityLevel::Frozen, context);
+    JsValue::from(object)
+}
+
 fn option_to_js_value(val: &Option<String>) -> JsValue {
     match val {
         Some(s) => JsValue::from(js_string!(s.clone())),
@@ -150,17 +275,55 @@ impl FsmJSWrapper {
 
 impl ECMAScriptDatamodel {
     pub fn new(global_data: GlobalDataArc) -> ECMAScriptDatamodel {
+        Self::new_with_context(
+            global_data,
+            Some(ContextBuilder::new().build().unwrap()),
+            None,
+        )
+    }
+
+    /// Creates a datamodel with its own realm in a shared context, see [crate::ecma_script_shared_context].\
+    /// The context needs to be attached before the datamodel is used.
+    pub(crate) fn new_in_realm(global_data: GlobalDataArc, context: &mut Context) -> ECMAScriptDatamodel {
+        let realm = context.create_realm().unwrap();
+        Self::new_with_context(global_data, None, Some(realm))
+    }
+
+    fn new_with_context(
+        global_data: GlobalDataArc,
+        context: Option<Context>,
+        realm: Option<Realm>,
+    ) -> ECMAScriptDatamodel {
         ECMAScriptDatamodel {
             global_data,
-            context: ContextBuilder::new().build().unwrap(),
+            context: ContextSlot(context),
+            realm,
             #[cfg(feature = "Trace")]
             tracer: Some(Box::new(DefaultExecutableContentTracer::new())),
             #[cfg(not(feature = "Trace"))]
             tracer: None,
             strict_mode: false,
+            read_only: HashSet::new(),
+            for_each_loops: Vec::new(),
+            scripts: HashMap::new(),
+            assignments: HashMap::new(),
         }
     }
 
+    /// Attaches the shared context and enters the realm of the datamodel.
+    pub(crate) fn attach_context(&mut self, mut context: Context) {
+        if let Some(realm) = &self.realm {
+            context.enter_realm(realm.clone());
+        }
+        context.strict(self.strict_mode);
+        self.context.0 = Some(context);
+    }
+
+    /// Detaches the shared context.
+    pub(crate) fn detach_context(&mut self) -> Option<Context> {
+        self.context.0.take()
+    }
+
     pub fn js_to_data_value(value: &JsValue, ctx: &mut Context) -> Result<Data, String> {
         #[cfg(feature = "Debug")]
         debug!("js2d {:?} -> {:?}", value, value.get_type());
@@ -236,7 +399,11 @@ impl ECMAScriptDatamodel {
                     self.strict_mode = true;
                     self.context.strict(true);
                 }
-                &_ => {}
+                // Handled by the factory.
+                ECMA_OPTION_SHARED_CONTEXT_POSTFIX => {}
+                _ => {
+                    warn!("Unknown ECMAScript option '{}' ignored", name);
+                }
             }
         }
     }
@@ -270,7 +437,12 @@ impl ECMAScriptDatamodel {
             }
             Err(e) => {
                 // Pretty print the error
-                let msg = format!("Script Error:  {} => {} ", script, e);
+                let msg = format!(
+                    "Script Error{}:  {} => {} ",
+                    self.global_data.lock().unwrap().source_line_info(),
+                    script,
+                    e
+                );
                 error!("{}", msg);
                 Err(msg)
             }
@@ -284,11 +456,38 @@ impl ECMAScriptDatamodel {
             }
             None => {}
         }
-        e.execute(self, fsm)
+        execute_with_source_line(e, self, fsm)
     }
 
     fn eval(&mut self, source: &Data) -> JsResult<JsValue> {
-        self.context.eval(Source::from_bytes(&source.as_script()))
+        match source {
+            Data::Source(source) if source.source_id != 0 => {
+                let script = self.parse_cached(source.source_id, &source.source)?;
+                script.evaluate(&mut self.context)
+            }
+            _ => self.context.eval(Source::from_bytes(&source.as_script())),
+        }
+    }
+
+    /// Gets the parsed script from the cache or parses and caches it.\
+    /// Scripts that can't be parsed are not cached, so the error is reported on each execution.
+    fn parse_cached(&mut self, source_id: SourceId, source: &str) -> JsResult<Script> {
+        if let Some(script) = self.scripts.get(&source_id) {
+            return Ok(script.clone());
+        }
+        let script = Script::parse(Source::from_bytes(source), None, &mut self.context)?;
+        self.scripts.insert(source_id, script.clone());
+        Ok(script)
+    }
+
+    /// Gets the parsed assignment from the cache or parses and caches it, see [ECMAScriptDatamodel::parse_cached].
+    fn parse_assignment(&mut self, source_ids: (SourceId, SourceId), assignment: &str) -> JsResult<Script> {
+        if let Some(script) = self.assignments.get(&source_ids) {
+            return Ok(script.clone());
+        }
+        let script = Script::parse(Source::from_bytes(assignment), None, &mut self.context)?;
+        self.assignments.insert(source_ids, script.clone());
+        Ok(script)
     }
 
     fn set_js_property<V>(&mut self, name: &str, value: V)
@@ -301,12 +500,50 @@ impl ECMAScriptDatamodel {
             .set(js_string!(name), value, false, &mut self.context);
     }
 
-    fn assign_internal(&mut self, left_expr: &str, right_expr: &str, allow_undefined: bool) -> bool {
+    /// Checks if the location references a read-only system variable or one of its members.
+    fn is_read_only_location(&self, location: &str) -> bool {
+        let name: String = location
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_alphanumeric() || *c == '_' || *c == '$')
+            .collect();
+        self.read_only.contains(&name)
+    }
+
+    /// Assigns the expression to the location.\
+    /// "source_ids" are the ids of location and expression, used to cache the parsed assignment.
+    fn assign_internal(
+        &mut self,
+        left_expr: &str,
+        right_expr: &str,
+        allow_undefined: bool,
+        source_ids: Option<(SourceId, SourceId)>,
+    ) -> bool {
+        if self.is_read_only_location(left_expr) {
+            // W3C says:\
+            // If the location expression does not denote a valid location in the data model ...
+            // the SCXML Processor must place the error 'error.execution' in the internal event queue.
+            self.log(
+                format!(
+                    "Could not assign {}={}, '{}' is read-only.",
+                    left_expr, right_expr, left_expr
+                )
+                .as_str(),
+            );
+            self.internal_error_execution();
+            return false;
+        }
         let exp = format!("{}={}", left_expr, right_expr);
         if allow_undefined && self.strict_mode {
             self.context.strict(false);
         }
-        let r = match self.eval(&str_to_source(exp.as_str())) {
+        let result = match source_ids {
+            None => self.context.eval(Source::from_bytes(&exp)),
+            Some(ids) => self
+                .parse_assignment(ids, &exp)
+                .and_then(|script| script.evaluate(&mut self.context)),
+        };
+        let r = match result {
             Ok(_) => true,
             Err(error) => {
                 // W3C says:\
@@ -332,6 +569,121 @@ impl ECMAScriptDatamodel {
         r
     }
 
+    /// Assigns a value to the location via a temporary global.
+    fn assign_js_value(&mut self, location: &str, value: JsValue, allow_undefined: bool) -> bool {
+        self.set_js_property(ASSIGN_VALUE_VARIABLE_NAME, value);
+        let r = self.assign_internal(location, ASSIGN_VALUE_VARIABLE_NAME, allow_undefined, None);
+        self.delete_js_property(ASSIGN_VALUE_VARIABLE_NAME);
+        r
+    }
+
+    fn delete_js_property(&mut self, name: &str) {
+        let _ = self
+            .context
+            .global_object()
+            .delete_property_or_throw(js_string!(name), &mut self.context);
+    }
+
+    /// Current value of a variable that is bound by an enclosing \<foreach\>.
+    fn enclosing_for_each_value(&mut self, name: &str) -> Option<JsValue> {
+        if self
+            .for_each_loops
+            .iter()
+            .any(|l| l.item == name || l.index == name)
+        {
+            self.context.eval(Source::from_bytes(name)).ok()
+        } else {
+            None
+        }
+    }
+
+    /// Starts a \<foreach\> loop: evaluates the array and declares the item variable.\
+    /// Returns the number of items or, if the loop can't be executed, the result of the \<foreach\>.
+    pub(crate) fn begin_for_each(
+        &mut self,
+        array_expression: &Data,
+        item_name: &str,
+        index: &str,
+    ) -> Result<usize, bool> {
+        #[cfg(feature = "Debug")]
+        debug!("ForEach: array: {}", array_expression);
+        let items = match self.eval(array_expression) {
+            Ok(r) => match r.as_object() {
+                Some(obj) => {
+                    let ob = obj.borrow();
+                    ob.properties()
+                        .index_property_values()
+                        // Skip the last "length" element
+                        .filter(|item_prop| item_prop.enumerable().unwrap_or(false))
+                        .map(|item_prop| item_prop.value().cloned().unwrap_or_default())
+                        .collect::<Vec<JsValue>>()
+                }
+                None => {
+                    self.log("Resulting value is not a supported collection.");
+                    self.internal_error_execution();
+                    return Err(true);
+                }
+            },
+            Err(e) => {
+                self.log(&e.to_string());
+                return Err(false);
+            }
+        };
+
+        // W3C says:\
+        // If 'item' does not already exist, the Processor must declare it.\
+        // The variables keep their last value after the loop. Only nested loops that re-use
+        // the variables of an enclosing loop restore the values of the enclosing loop.
+        let enclosing_item = self.enclosing_for_each_value(item_name);
+        let enclosing_index = self.enclosing_for_each_value(index);
+        if !self.assign_internal(item_name, "null", true, None) {
+            return Err(true);
+        }
+        let len = items.len();
+        self.for_each_loops.push(ForEachLoop {
+            item: item_name.to_string(),
+            index: index.to_string(),
+            items,
+            enclosing_item,
+            enclosing_index,
+        });
+        Ok(len)
+    }
+
+    /// Sets item and index of the innermost \<foreach\> loop.
+    pub(crate) fn set_for_each_item(&mut self, idx: usize) -> bool {
+        let (item_name, index, item) = match self.for_each_loops.last() {
+            Some(l) => (l.item.clone(), l.index.clone(), l.items[idx].clone()),
+            None => return false,
+        };
+        #[cfg(feature = "Debug")]
+        debug!("ForEach: #{} {}={:?}", idx, item_name, item);
+        if !self.assign_js_value(&item_name, item, true) {
+            return false;
+        }
+        if !index.is_empty() {
+            self.set_js_property(&index, idx as i64);
+        }
+        true
+    }
+
+    /// Ends the innermost \<foreach\> loop and restores the variables of an enclosing loop.
+    pub(crate) fn end_for_each(&mut self) {
+        if let Some(l) = self.for_each_loops.pop() {
+            if let Some(value) = l.enclosing_item {
+                self.assign_js_value(&l.item, value, true);
+            }
+            match l.enclosing_index {
+                Some(value) => self.set_js_property(&l.index, value),
+                None => {
+                    if l.index == INDEX_TEMP {
+                        self.delete_js_property(INDEX_TEMP);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn data_arc_to_js(&mut self, data: &DataArc) -> JsValue {
         match data.lock() {
             Ok(l) => self.data_value_to_js(l.deref()),
@@ -343,6 +695,84 @@ impl ECMAScriptDatamodel {
         data_value_to_js(data, &mut self.context)
     }
 
+    /// Adds the "In" and other functions, see [Datamodel::add_functions].
+    pub(crate) fn add_functions_for_states(&mut self, state_name_to_id: HashMap<String, StateId>) {
+        let mut functions = String::new();
+        for name in self.global_s().lock().unwrap().actions.lock().keys() {
+            functions.push_str(
+                format!(
+                    "function {}(){{ return __action('{}', Array.from(arguments)); }}\n",
+                    name, name
+                )
+                .as_str(),
+            );
+        }
+
+        let ctx = &mut self.context;
+
+        // Implement "action" function.
+        let _ = ctx.register_global_callable(
+            js_string!("__action"),
+            2,
+            NativeFunction::from_copy_closure(Self::call_action),
+        );
+
+        let r = ctx.eval(Source::from_bytes(functions.as_str()));
+        if let Err(err) = r {
+            error!("Failed to add actions: {}", err);
+        }
+
+        // Implement "In" function.
+        let _ = ctx.register_global_callable(
+            js_string!("__In"),
+            1,
+            NativeFunction::from_copy_closure(Self::in_configuration),
+        );
+
+        let mut fw = FsmJSWrapper::new(self.global_data.clone());
+        fw.state_name_to_id = state_name_to_id;
+        // Stored in the realm, so that sessions in a shared context have their own wrapper.
+        ctx.realm().host_defined_mut().insert(fw);
+
+        let _ = ctx.eval(Source::from_bytes(
+            r##"
+                function In(state) {
+                   return __In( state );
+                }
+            "##,
+        ));
+
+        // Implement "log" function.
+        let _ = ctx.register_global_callable(
+            js_string!("log"),
+            1,
+            NativeFunction::from_copy_closure(Self::log_js),
+        );
+    }
+
+    /// Parses the conditions, scripts and assignments, see [Datamodel::precompile].
+    pub(crate) fn precompile_sources(
+        &mut self,
+        conditions: Vec<&SourceCode>,
+        scripts: Vec<&SourceCode>,
+        assignments: Vec<(&SourceCode, &SourceCode)>,
+    ) {
+        self.scripts.clear();
+        for source in conditions {
+            let _ = self.parse_cached(source.source_id, &to_boolean(&source.source));
+        }
+        for source in scripts {
+            let _ = self.parse_cached(source.source_id, &source.source);
+        }
+        self.assignments.clear();
+        for (location, expr) in assignments {
+            if location.source_id != 0 && expr.source_id != 0 && !self.is_read_only_location(&location.source) {
+                let assignment = format!("{}={}", location.source, expr.source);
+                let _ = self.parse_assignment((location.source_id, expr.source_id), &assignment);
+            }
+        }
+    }
+
     fn call_action(_this: &JsValue, args: &[JsValue], ctx: &mut Context) -> JsResult<JsValue> {
         let mut arg_list = Vec::<Data>::with_capacity(0);
         let action_name = js_to_string(args.get_or_undefined(0), ctx);
@@ -367,7 +797,8 @@ impl ECMAScriptDatamodel {
                 error!("Arguments must be specified as Array: {:?}", arguments);
             }
         }
-        let r = if let Some(fsm) = ctx.get_data::<FsmJSWrapper>() {
+        let realm = ctx.realm().clone();
+        let r = if let Some(fsm) = realm.host_defined().get::<FsmJSWrapper>() {
             let global_data = fsm.global_data.lock().unwrap();
             global_data
                 .actions
@@ -385,7 +816,9 @@ impl ECMAScriptDatamodel {
         let state = args.get_or_undefined(0);
 
         if let Ok(name) = state.to_string(context) {
-            let fsm = context.get_data::<FsmJSWrapper>().unwrap();
+            let realm = context.realm().clone();
+            let host_defined = realm.host_defined();
+            let fsm = host_defined.get::<FsmJSWrapper>().unwrap();
             let loc = fsm.state_name_to_id.get(&name.to_std_string().unwrap());
             match loc {
                 None => {}
@@ -429,58 +862,19 @@ impl Datamodel for ECMAScriptDatamodel {
     }
 
     fn add_functions(&mut self, fsm: &mut Fsm) {
-        let mut functions = String::new();
-        for name in self.global_s().lock().unwrap().actions.lock().keys() {
-            functions.push_str(
-                format!(
-                    "function {}(){{ return __action('{}', Array.from(arguments)); }}\n",
-                    name, name
-                )
-                .as_str(),
-            );
-        }
-
-        let ctx = &mut self.context;
-
-        // Implement "action" function.
-        let _ = ctx.register_global_callable(
-            js_string!("__action"),
-            2,
-            NativeFunction::from_copy_closure(Self::call_action),
-        );
-
-        let r = ctx.eval(Source::from_bytes(functions.as_str()));
-        if let Err(err) = r {
-            error!("Failed to add actions: {}", err);
-        }
-
-        // Implement "In" function.
-        let _ = ctx.register_global_callable(
-            js_string!("__In"),
-            1,
-            NativeFunction::from_copy_closure(Self::in_configuration),
-        );
-
-        let mut fw = FsmJSWrapper::new(self.global_data.clone());
-        for state in fsm.states.as_slice() {
-            fw.state_name_to_id.insert(state.name.clone(), state.id);
-        }
-
-        let _ = ctx.insert_data(fw);
-
-        let _ = ctx.eval(Source::from_bytes(
-            r##"
-                function In(state) {
-                   return __In( state );
-                }
-            "##,
-        ));
+        let state_name_to_id = fsm
+            .states
+            .iter()
+            .map(|state| (state.name.clone(), state.id))
+            .collect();
+        self.add_functions_for_states(state_name_to_id);
+    }
 
-        // Implement "log" function.
-        let _ = ctx.register_global_callable(
-            js_string!("log"),
-            1,
-            NativeFunction::from_copy_closure(Self::log_js),
+    fn precompile(&mut self, fsm: &Fsm) {
+        self.precompile_sources(
+            collect_conditions(fsm),
+            collect_scripts(fsm),
+            collect_assignments(fsm),
         );
     }
 
@@ -549,13 +943,15 @@ impl Datamodel for ECMAScriptDatamodel {
     }
 
     fn initialize_read_only_arc(&mut self, name: &str, value: DataArc) {
+        self.read_only.insert(name.to_string());
+        let js_value = data_value_to_read_only_js(value.lock().unwrap().deref(), &mut self.context);
         let r = self.context.global_object().define_property_or_throw(
             js_string!(name),
             PropertyDescriptor::builder()
                 .configurable(true)
                 .enumerable(false)
                 .writable(false)
-                .value(self.data_arc_to_js(&value)),
+                .value(js_value),
             &mut self.context,
         );
         if let Err(error) = r {
@@ -670,11 +1066,27 @@ impl Datamodel for ECMAScriptDatamodel {
     }
 
     fn assign(self: &mut ECMAScriptDatamodel, left_expr: &Data, right_expr: &Data) -> bool {
-        self.assign_internal(
-            left_expr.as_script().as_str(),
-            right_expr.as_script().as_str(),
-            false,
-        )
+        match right_expr {
+            Data::Source(expr) => {
+                let source_ids = match left_expr {
+                    Data::Source(location) if location.source_id != 0 && expr.source_id != 0 => {
+                        Some((location.source_id, expr.source_id))
+                    }
+                    _ => None,
+                };
+                self.assign_internal(
+                    left_expr.as_script().as_str(),
+                    right_expr.as_script().as_str(),
+                    false,
+                    source_ids,
+                )
+            }
+            value => {
+                // Values (e.g. from child content) are not converted to source code.
+                let js_value = self.data_value_to_js(value);
+                self.assign_js_value(left_expr.as_script().as_str(), js_value, false)
+            }
+        }
     }
 
     fn get_by_location(self: &mut ECMAScriptDatamodel, location: &str) -> Result<DataArc, String> {
@@ -700,63 +1112,19 @@ impl Datamodel for ECMAScriptDatamodel {
         index: &str,
         execute_body: &mut dyn FnMut(&mut dyn Datamodel) -> bool,
     ) -> bool {
-        #[cfg(feature = "Debug")]
-        debug!("ForEach: array: {}", array_expression);
-        match self
-            .context
-            .eval(Source::from_bytes(&array_expression.as_script()))
-        {
-            Ok(r) => {
-                match r.get_type() {
-                    Type::Object => {
-                        let obj = r.as_object().unwrap();
-                        // Iterate through all members
-                        let ob = obj.borrow();
-                        let p = ob.properties();
-                        let mut idx: i64 = 0;
-
-                        if self.assign_internal(item_name, "null", true) {
-                            for item_prop in p.index_property_values() {
-                                // Skip the last "length" element
-                                if item_prop.enumerable().is_some() && item_prop.enumerable().unwrap() {
-                                    match item_prop.value() {
-                                        Some(item) => {
-                                            #[cfg(feature = "Debug")]
-                                            debug!("ForEach: #{} {}={:?}", idx, item_name, item);
-                                            let str = js_to_string(item, &mut self.context);
-                                            if self.assign(&str_to_source(item_name), &str_to_source(str.as_str())) {
-                                                if !index.is_empty() {
-                                                    self.set_js_property(index, idx);
-                                                }
-                                                if !execute_body(self) {
-                                                    return false;
-                                                }
-                                            } else {
-                                                return false;
-                                            }
-                                        }
-                                        None => {
-                                            warn!("ForEach: #{} - failed to get value", idx,);
-                                            return false;
-                                        }
-                                    }
-                                    idx += 1;
-                                }
-                            }
-                        }
-                    }
-                    _ => {
-                        self.log("Resulting value is not a supported collection.");
-                        self.internal_error_execution();
-                    }
-                }
-                true
-            }
-            Err(e) => {
-                self.log(&e.to_string());
-                false
+        let len = match self.begin_for_each(array_expression, item_name, index) {
+            Ok(len) => len,
+            Err(result) => return result,
+        };
+        let mut result = true;
+        for idx in 0..len {
+            if !self.set_for_each_item(idx) || !execute_body(self) {
+                result = false;
+                break;
             }
         }
+        self.end_for_each();
+        result
     }
 
     fn execute_condition(&mut self, script: &Data) -> Result<bool, String> {
@@ -764,8 +1132,12 @@ impl Datamodel for ECMAScriptDatamodel {
         // B.2.3 Conditional Expressions
         //   The Processor must convert ECMAScript expressions used in conditional expressions into their effective boolean value using the ToBoolean operator
         //   as described in Section 9.2 of [ECMASCRIPT-262].
-        let to_boolean_expression = format!("({})?true:false", script.as_script());
-        match self.execute_internal(&str_to_source(to_boolean_expression.as_str()), false) {
+        let source_id = match script {
+            Data::Source(source) => source.source_id,
+            _ => 0,
+        };
+        let to_boolean_expression = Data::Source(SourceCode::new(&to_boolean(&script.as_script()), source_id));
+        match self.execute_internal(&to_boolean_expression, false) {
             Ok(val) => match val.lock().unwrap().deref() {
                 Data::Boolean(b) => Ok(*b),
                 _ => Ok(false),
@@ -851,4 +1223,349 @@ mod tests {
             &final_expected_configuration,
         ));
     }
+
+    #[test]
+    fn assign_with_content() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel><data id='cfg'/><data id='fragment'/></datamodel>
+              <state id='Main'>
+                <onentry>
+                  <assign location='cfg'> {"a": 1, "b": [1,2], "c": {"d": "x"}} </assign>
+                  <assign location='fragment'><item id="1">text</item></assign>
+                  <script>cfg.b.push(3);</script>
+                  <raise event='check'/>
+                </onentry>
+                <transition event='check'
+                  cond="cfg.a == 1 &amp;&amp; cfg.b.length == 3 &amp;&amp; cfg.c.d == 'x' &amp;&amp; fragment == '&lt;item id=&quot;1&quot;&gt;text&lt;/item&gt;'"
+                  target='Errors'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Errors'>
+                <onentry>
+                  <assign location='cfg' expr='2'>{"a": 2}</assign>
+                  <raise event='next'/>
+                </onentry>
+                <transition event='error.execution' target='Neither'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Neither'>
+                <onentry>
+                  <assign location='cfg'/>
+                  <raise event='next'/>
+                </onentry>
+                <transition event='error.execution' target='ReadOnly'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='ReadOnly'>
+                <onentry>
+                  <assign location='_sessionid'>{"a": 3}</assign>
+                  <raise event='next'/>
+                </onentry>
+                <transition event='error.execution' cond="cfg.a == 1 &amp;&amp; typeof __assign_value == 'undefined'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "assign_with_content",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    fn session_variables_are_read_only() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript' name='parentMachine'>
+              <datamodel>
+                <data id='sessionId'/>
+                <data id='name'/>
+              </datamodel>
+              <script>
+                sessionId = _sessionid;
+                name = _name;
+              </script>
+              <state id='Main'>
+                <transition cond="typeof sessionId == 'string' &amp;&amp; sessionId == _sessionid &amp;&amp; name == 'parentMachine'"
+                            target='AssignId'/>
+                <transition target='fail'/>
+              </state>
+              <state id='AssignId'>
+                <onentry>
+                  <assign location='_sessionid' expr="'other'"/>
+                </onentry>
+                <transition event='error.execution' cond='_sessionid == sessionId' target='AssignName'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='AssignName'>
+                <onentry>
+                  <assign location='_name' expr="'other'"/>
+                </onentry>
+                <transition event='error.execution' cond="_name == 'parentMachine'" target='Child'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Child'>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='ChildMain' datamodel='ecmascript' name='childMachine'>
+                      <final id='ChildMain'>
+                        <onentry>
+                          <send target='#_parent' event='childIds'>
+                            <param name='sessionId' expr='_sessionid'/>
+                            <param name='name' expr='_name'/>
+                          </send>
+                        </onentry>
+                      </final>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='childIds'
+                            cond="_event.data.name == 'childMachine' &amp;&amp; _event.data.sessionId != _sessionid"
+                            target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "session_variables_are_read_only",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    fn nested_event_data_in_conditions() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel><data id='saved'/></datamodel>
+              <state id='Main'>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='ChildMain' datamodel='ecmascript'>
+                      <final id='ChildMain'>
+                        <donedata>
+                          <param name='result' expr="({code: 42, items: [1, 2], inner: {name: 'x'}})"/>
+                        </donedata>
+                      </final>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='done.invoke.child'
+                  cond="_event.data.result.code == 42 &amp;&amp; _event.data.result.items[1] == 2 &amp;&amp; _event.data.result.inner.name == 'x'"
+                  target='Saved'>
+                  <assign location='saved' expr='_event.data.result'/>
+                </transition>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Saved'>
+                <transition cond="saved.code == 42 &amp;&amp; saved.inner.name == 'x'" target='pass'/>
+                <transition target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "nested_event_data_in_conditions",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    fn precompiled_scripts_are_executed_repeatedly_and_fail_at_runtime() {
+        // Conditions and expressions are executed more than once from the cache.
+        // Invalid scripts don't fail on load, but raise "error.execution" each time they are executed.
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Loop' datamodel='ecmascript'>
+              <datamodel>
+                <data id='count' expr='0'/>
+                <data id='sum' expr='0'/>
+                <data id='errors' expr='0'/>
+              </datamodel>
+              <state id='Loop'>
+                <transition cond='count &lt; 3' target='Loop'>
+                  <assign location='count' expr='count + 1'/>
+                  <foreach array='[1, 2]' item='item'>
+                    <assign location='sum' expr='sum + item'/>
+                  </foreach>
+                </transition>
+                <transition cond='count == 3 &amp;&amp; sum == 9' target='Invalid'/>
+                <transition target='fail'/>
+              </state>
+              <state id='Invalid'>
+                <onentry>
+                  <if cond='count ==='><assign location='count' expr='0'/></if>
+                </onentry>
+                <transition cond='count ===' target='fail'/>
+                <transition event='error.execution' cond='errors &lt; 1' target='Invalid'>
+                  <assign location='errors' expr='errors + 1'/>
+                </transition>
+                <transition event='error.execution' cond='errors == 1 &amp;&amp; count == 3' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "precompiled_scripts_are_executed_repeatedly_and_fail_at_runtime",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    fn nested_foreach_scopes_loop_variables() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='matrix' expr='[[1, 2], [3, 4, 5]]'/>
+                <data id='sum' expr='0'/>
+                <data id='text' expr='""'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <foreach array='matrix' item='row' index='i'>
+                    <foreach array='row' item='cell'>
+                      <assign location='sum' expr='sum + cell'/>
+                    </foreach>
+                    <foreach array='[["a", "b"]]' item='row'>
+                      <assign location='text' expr='text + row.join("")'/>
+                    </foreach>
+                    <assign location='text' expr='text + row.length + i + ";"'/>
+                  </foreach>
+                </onentry>
+                <transition cond='sum == 15 &amp;&amp; text == "ab20;ab31;"' target='Check'/>
+                <transition target='fail'/>
+              </state>
+              <state id='Check'>
+                <transition cond='row[2] == 5 &amp;&amp; cell == 5 &amp;&amp; i == 1 &amp;&amp; typeof __$index == "undefined"' target='pass'/>
+                <transition target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "nested_foreach_scopes_loop_variables",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn session_options_select_strict_mode() {
+        use crate::actions::ActionWrapper;
+        use crate::fsm::{start_fsm_with_options, FinishMode};
+        use crate::fsm_executor::FsmExecutor;
+        use std::time::Duration;
+
+        // In strict mode the assignment to an undeclared variable fails.
+        // The invoked child inherits the mode of the parent.
+        let xml = r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='mode' expr="'lax'"/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <assign location='undeclared' expr='1'/>
+                </onentry>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='C' datamodel='ecmascript'>
+                      <state id='C'>
+                        <onentry>
+                          <assign location='undeclared' expr='1'/>
+                          <send target='#_parent' event='child.lax'/>
+                        </onentry>
+                        <transition event='error.execution'>
+                          <send target='#_parent' event='child.strict'/>
+                        </transition>
+                      </state>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='error.execution'>
+                  <assign location='mode' expr="'strict'"/>
+                </transition>
+                <transition event='child.strict' cond="mode == 'strict'" target='strict'/>
+                <transition event='child.lax' cond="mode == 'lax'" target='lax'/>
+                <transition event='child' target='mixed'/>
+              </state>
+              <final id='strict'/>
+              <final id='lax'/>
+              <final id='mixed'/>
+            </scxml>"##;
+
+        let executor = FsmExecutor::new_without_io_processor();
+        for (options, expected) in [
+            (
+                HashMap::from([("ecma:strict".to_string(), "".to_string())]),
+                "strict",
+            ),
+            (HashMap::new(), "lax"),
+        ] {
+            let session = start_fsm_with_options(
+                scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                &options,
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(2000));
+            assert!(result.is_some(), "Session shall finish");
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec![expected.to_string()])
+            );
+        }
+    }
 }
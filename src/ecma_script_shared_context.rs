@@ -0,0 +1,432 @@
+//! Shares one Boa [Context] between an ECMAScript session and the sessions it invokes.\
+//! Enabled by the datamodel option "ecma:shared-context" (see [ECMA_OPTION_SHARED_CONTEXT_POSTFIX]), which
+//! is passed on to the invoked sessions. Sessions without an invoking session in a shared context start a
+//! new shared context.
+//!
+//! A context is bound to the thread that created it (the garbage collector of Boa is thread local), but each
+//! session is executed by its own thread or by any worker of a thread pool. So the shared context is owned by
+//! a dedicated thread and the datamodels of the sessions send their requests through a channel.\
+//! Each session gets its own realm with its own global object, so the variables of the sessions
+//! (including system variables like "_event" and "_sessionid") don't collide.
+//!
+//! The requests of all sessions are executed one after another: the sessions can't execute scripts
+//! concurrently, and each call of the datamodel costs a round trip to the thread of the context.\
+//! A realm still has its own built-in objects. With Boa 0.19, an additional context needs about 0.5 MB,
+//! a realm about 0.3 MB.
+
+use std::collections::HashMap;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::mpsc::{channel, Sender};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+
+#[cfg(test)]
+use std::println as error;
+
+#[cfg(all(not(test), not(feature = "EnvLog")))]
+use std::println as error;
+
+#[cfg(all(not(test), feature = "EnvLog"))]
+use log::error;
+
+use boa_engine::context::ContextBuilder;
+use boa_engine::Context;
+use lazy_static::lazy_static;
+
+use crate::datamodel::{collect_conditions, collect_scripts, Data, DataArc, Datamodel, GlobalDataArc, SourceCode};
+#[cfg(doc)]
+use crate::ecma_script_datamodel::ECMA_OPTION_SHARED_CONTEXT_POSTFIX;
+use crate::ecma_script_datamodel::{collect_assignments, ECMAScriptDatamodel, ECMA_SCRIPT};
+#[cfg(feature = "Trace")]
+use crate::executable_content::DefaultExecutableContentTracer;
+use crate::executable_content::{execute_with_source_line, ExecutableContentTracer};
+use crate::fsm::{Event, ExecutableContentId, Fsm, SessionId};
+
+/// A request, executed by the thread that owns the context.
+type Request = Box<dyn FnOnce(&mut ContextOwner) + Send>;
+
+static DATAMODEL_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
+
+lazy_static! {
+    /// The shared contexts by the id of the sessions that use them.
+    /// Invoked sessions find the context of the invoking session here.
+    static ref shared_contexts: Mutex<HashMap<SessionId, Weak<SharedContext>>> = Mutex::new(HashMap::new());
+}
+
+/// State of the thread that owns the context.
+struct ContextOwner {
+    /// The context, None while it is attached to a datamodel.
+    context: Option<Context>,
+    datamodels: HashMap<u32, ECMAScriptDatamodel>,
+}
+
+impl ContextOwner {
+    fn add_datamodel(&mut self, id: u32, global_data: GlobalDataArc, options: &HashMap<String, String>) {
+        if let Some(context) = self.context.as_mut() {
+            let datamodel = ECMAScriptDatamodel::new_in_realm(global_data, context);
+            self.datamodels.insert(id, datamodel);
+            self.execute(id, |datamodel| {
+                for (key, value) in options {
+                    datamodel.set_option(key.as_str(), value.as_str());
+                }
+            });
+        }
+    }
+
+    /// Executes the request with the context attached to the datamodel.\
+    /// Returns None if the datamodel doesn't exist or the request panicked.
+    fn execute<R>(&mut self, id: u32, request: impl FnOnce(&mut ECMAScriptDatamodel) -> R) -> Option<R> {
+        let datamodel = self.datamodels.get_mut(&id)?;
+        datamodel.attach_context(self.context.take()?);
+        let result = catch_unwind(AssertUnwindSafe(|| request(datamodel)));
+        self.context = datamodel.detach_context();
+        result.ok()
+    }
+}
+
+/// Handle of a shared context. The thread of the context ends if the last handle is dropped.
+pub(crate) struct SharedContext {
+    sender: Sender<Request>,
+}
+
+impl SharedContext {
+    fn start() -> Arc<SharedContext> {
+        let (sender, receiver) = channel::<Request>();
+        thread::Builder::new()
+            .name("ecma_shared_context".to_string())
+            .spawn(move || {
+                let mut owner = ContextOwner {
+                    context: Some(ContextBuilder::new().build().unwrap()),
+                    datamodels: HashMap::new(),
+                };
+                while let Ok(request) = receiver.recv() {
+                    request(&mut owner);
+                }
+            })
+            .unwrap();
+        Arc::new(SharedContext { sender })
+    }
+
+    fn send(&self, request: Request) {
+        if self.sender.send(request).is_err() {
+            error!("The thread of the shared ECMAScript context has ended");
+        }
+    }
+}
+
+/// ECMAScript datamodel of a session in a shared context.\
+/// The datamodel forwards all calls to the thread of the context. Executable content is executed by the
+/// thread of the session.
+pub struct SharedContextDatamodel {
+    pub global_data: GlobalDataArc,
+    pub tracer: Option<Box<dyn ExecutableContentTracer>>,
+    context: Arc<SharedContext>,
+    session_id: SessionId,
+    /// Id of the datamodel in the context.
+    id: u32,
+}
+
+impl SharedContextDatamodel {
+    /// Creates the datamodel in the shared context of the invoking session or, if it has none, in a new
+    /// shared context.\
+    /// The session id and the id of the invoking session need to be set in the global data.
+    pub fn new(global_data: GlobalDataArc, options: &HashMap<String, String>) -> SharedContextDatamodel {
+        let (session_id, parent_session_id) = {
+            let global = global_data.lock().unwrap();
+            (global.session_id, global.parent_session_id)
+        };
+        let context = {
+            let mut contexts = shared_contexts.lock().unwrap();
+            let context = parent_session_id
+                .and_then(|parent| contexts.get(&parent))
+                .and_then(Weak::upgrade)
+                .unwrap_or_else(SharedContext::start);
+            contexts.insert(session_id, Arc::downgrade(&context));
+            context
+        };
+        let id = DATAMODEL_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let global_data_clone = global_data.clone();
+        let options = options.clone();
+        context.send(Box::new(move |owner| {
+            owner.add_datamodel(id, global_data_clone, &options)
+        }));
+        SharedContextDatamodel {
+            global_data,
+            #[cfg(feature = "Trace")]
+            tracer: Some(Box::new(DefaultExecutableContentTracer::new())),
+            #[cfg(not(feature = "Trace"))]
+            tracer: None,
+            context,
+            session_id,
+            id,
+        }
+    }
+
+    /// Executes the request by the thread of the context and waits for the result.
+    fn call<R>(&self, request: impl FnOnce(&mut ECMAScriptDatamodel) -> R + Send + 'static) -> R
+    where
+        R: Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        let id = self.id;
+        self.context.send(Box::new(move |owner| {
+            if let Some(result) = owner.execute(id, request) {
+                let _ = sender.send(result);
+            }
+        }));
+        receiver
+            .recv()
+            .expect("Request to the shared ECMAScript context failed")
+    }
+}
+
+impl Drop for SharedContextDatamodel {
+    fn drop(&mut self) {
+        let id = self.id;
+        self.context.send(Box::new(move |owner| {
+            owner.datamodels.remove(&id);
+        }));
+        let mut contexts = shared_contexts.lock().unwrap();
+        if contexts
+            .get(&self.session_id)
+            .is_some_and(|context| Weak::ptr_eq(context, &Arc::downgrade(&self.context)))
+        {
+            contexts.remove(&self.session_id);
+        }
+    }
+}
+
+impl Datamodel for SharedContextDatamodel {
+    fn global(&mut self) -> &mut GlobalDataArc {
+        &mut self.global_data
+    }
+
+    fn global_s(&self) -> &GlobalDataArc {
+        &self.global_data
+    }
+
+    fn get_name(&self) -> &str {
+        ECMA_SCRIPT
+    }
+
+    fn add_functions(&mut self, fsm: &mut Fsm) {
+        let state_name_to_id = fsm
+            .states
+            .iter()
+            .map(|state| (state.name.clone(), state.id))
+            .collect();
+        self.call(move |datamodel| datamodel.add_functions_for_states(state_name_to_id));
+    }
+
+    fn set_ioprocessors(&mut self) {
+        self.call(|datamodel| datamodel.set_ioprocessors());
+    }
+
+    fn precompile(&mut self, fsm: &Fsm) {
+        let conditions: Vec<SourceCode> = collect_conditions(fsm).into_iter().cloned().collect();
+        let scripts: Vec<SourceCode> = collect_scripts(fsm).into_iter().cloned().collect();
+        let assignments: Vec<(SourceCode, SourceCode)> = collect_assignments(fsm)
+            .into_iter()
+            .map(|(location, expr)| (location.clone(), expr.clone()))
+            .collect();
+        self.call(move |datamodel| {
+            datamodel.precompile_sources(
+                conditions.iter().collect(),
+                scripts.iter().collect(),
+                assignments
+                    .iter()
+                    .map(|(location, expr)| (location, expr))
+                    .collect(),
+            )
+        });
+    }
+
+    fn set_from_state_data(&mut self, data: &HashMap<String, DataArc>, set_data: bool) {
+        let data = data.clone();
+        self.call(move |datamodel| datamodel.set_from_state_data(&data, set_data));
+    }
+
+    fn initialize_read_only_arc(&mut self, name: &str, value: DataArc) {
+        let name = name.to_string();
+        self.call(move |datamodel| datamodel.initialize_read_only_arc(&name, value));
+    }
+
+    fn set_arc(&mut self, name: &str, data: DataArc, allow_undefined: bool) {
+        let name = name.to_string();
+        self.call(move |datamodel| datamodel.set_arc(&name, data, allow_undefined));
+    }
+
+    fn set_event(&mut self, event: &Event) {
+        let event = event.clone();
+        self.call(move |datamodel| datamodel.set_event(&event));
+    }
+
+    fn assign(&mut self, left_expr: &Data, right_expr: &Data) -> bool {
+        let left_expr = left_expr.clone();
+        let right_expr = right_expr.clone();
+        self.call(move |datamodel| datamodel.assign(&left_expr, &right_expr))
+    }
+
+    fn get_by_location(&mut self, location: &str) -> Result<DataArc, String> {
+        let location = location.to_string();
+        self.call(move |datamodel| datamodel.get_by_location(&location))
+    }
+
+    fn clear(&mut self) {
+        self.call(|datamodel| datamodel.clear());
+    }
+
+    fn execute(&mut self, script: &Data) -> Result<DataArc, String> {
+        let script = script.clone();
+        self.call(move |datamodel| datamodel.execute(&script))
+    }
+
+    fn execute_for_each(
+        &mut self,
+        array_expression: &Data,
+        item_name: &str,
+        index: &str,
+        execute_body: &mut dyn FnMut(&mut dyn Datamodel) -> bool,
+    ) -> bool {
+        let array_expression = array_expression.clone();
+        let item_name = item_name.to_string();
+        let index = index.to_string();
+        let len = match self.call(move |datamodel| datamodel.begin_for_each(&array_expression, &item_name, &index)) {
+            Ok(len) => len,
+            Err(result) => return result,
+        };
+        // The body is executed by the thread of the session, as it needs the FSM.
+        let mut result = true;
+        for idx in 0..len {
+            if !self.call(move |datamodel| datamodel.set_for_each_item(idx)) || !execute_body(self) {
+                result = false;
+                break;
+            }
+        }
+        self.call(|datamodel| datamodel.end_for_each());
+        result
+    }
+
+    fn execute_condition(&mut self, script: &Data) -> Result<bool, String> {
+        let script = script.clone();
+        self.call(move |datamodel| datamodel.execute_condition(&script))
+    }
+
+    #[allow(non_snake_case)]
+    fn executeContent(&mut self, fsm: &Fsm, content_id: ExecutableContentId) -> bool {
+        let ec = fsm.executableContent.get(&content_id);
+        for e in ec.unwrap().iter() {
+            if let Some(t) = &mut self.tracer {
+                e.trace(t.as_mut(), fsm);
+            }
+            if !execute_with_source_line(e.as_ref(), self, fsm) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    use crate::datamodel::create_global_data_arc;
+    use crate::ecma_script_shared_context::SharedContextDatamodel;
+    use std::sync::Arc;
+
+    #[test]
+    fn invoked_datamodel_joins_the_context_of_the_parent() {
+        let options = HashMap::from([("ecma:shared-context".to_string(), "".to_string())]);
+        let parent_data = create_global_data_arc();
+        parent_data.lock().unwrap().session_id = 1_000_001;
+        let parent = SharedContextDatamodel::new(parent_data, &options);
+
+        let child_data = create_global_data_arc();
+        child_data.lock().unwrap().session_id = 1_000_002;
+        child_data.lock().unwrap().parent_session_id = Some(1_000_001);
+        let child = SharedContextDatamodel::new(child_data, &options);
+
+        let other = SharedContextDatamodel::new(create_global_data_arc(), &options);
+
+        assert!(Arc::ptr_eq(&parent.context, &child.context));
+        assert!(!Arc::ptr_eq(&parent.context, &other.context));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn invoked_sessions_have_their_own_variables() {
+        use crate::actions::ActionWrapper;
+        use crate::fsm::{start_fsm_with_options, FinishMode};
+        use crate::fsm_executor::FsmExecutor;
+        use crate::scxml_reader;
+
+        // All sessions use a variable "value". Each child appends "!" to the value given by the parent
+        // and reports it, after all children have reported, each child reports its value again.
+        let child = r##"<scxml initial='C' datamodel='ecmascript'>
+                      <datamodel>
+                        <data id='value' expr="'unset'"/>
+                      </datamodel>
+                      <state id='C'>
+                        <onentry>
+                          <assign location='value' expr="value + '!'"/>
+                          <send target='#_parent' event='child.value' namelist='value'/>
+                        </onentry>
+                        <transition event='check'>
+                          <send target='#_parent' event='child.checked' namelist='value'/>
+                        </transition>
+                      </state>
+                    </scxml>"##;
+        let invoke = |id: &str| {
+            format!("<invoke id='{id}'><param name='value' expr=\"'{id}'\"/><content>{child}</content></invoke>")
+        };
+        let xml = format!(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='value' expr="'parent'"/>
+                <data id='reported' expr='0'/>
+                <data id='checked' expr='0'/>
+              </datamodel>
+              <state id='Main'>
+                {}{}{}
+                <transition event='child.value' cond="_event.data.value == _event.invokeid + '!'">
+                  <assign location='reported' expr='reported + 1'/>
+                  <if cond='reported == 3'>
+                    <send target='#_c1' event='check'/>
+                    <send target='#_c2' event='check'/>
+                    <send target='#_c3' event='check'/>
+                  </if>
+                </transition>
+                <transition event='child.checked' cond="_event.data.value == _event.invokeid + '!'">
+                  <assign location='checked' expr='checked + 1'/>
+                </transition>
+                <transition cond="checked == 3 &amp;&amp; value == 'parent'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+            invoke("c1"),
+            invoke("c2"),
+            invoke("c3")
+        );
+
+        let session = start_fsm_with_options(
+            scxml_reader::parse_from_xml(xml).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            &HashMap::from([("ecma:shared-context".to_string(), "".to_string())]),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(5000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+}
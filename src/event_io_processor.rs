@@ -3,14 +3,13 @@
 
 use std::collections::HashMap;
 use std::fmt::Debug;
-use std::sync::mpsc::Sender;
 
 #[cfg(feature = "Debug")]
 use log::debug;
 
 use crate::datamodel::{Datamodel, GlobalDataArc, ToAny};
 use crate::fsm::SessionId;
-use crate::fsm::{Event, Fsm, EVENT_CANCEL_SESSION};
+use crate::fsm::{Event, EventSender, Fsm, EVENT_CANCEL_SESSION};
 use crate::get_global;
 
 pub const SYS_IO_PROCESSORS: &str = "_ioprocessors";
@@ -18,7 +17,7 @@ pub const SYS_IO_PROCESSORS: &str = "_ioprocessors";
 #[derive(Debug, Clone, Default)]
 pub struct EventIOProcessorHandle {
     /// The FSMs that are connected to this IO Processor
-    pub fsms: HashMap<u32, Sender<Box<Event>>>,
+    pub fsms: HashMap<u32, EventSender>,
 }
 
 impl EventIOProcessorHandle {
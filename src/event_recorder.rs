@@ -0,0 +1,428 @@
+//! Flight recorder of sessions, to reproduce issues of the field.\
+//! If [crate::fsm::Fsm::event_recorder] is set, the session adds all external events it processes
+//! (with the time relative to the start of the session), the states it enters and the end of the
+//! session to the [EventRecorder]. With feature "json-config" the entries can be stored as
+//! JSON lines, see [EventRecorder::with_writer] and [EventRecorder::to_json_lines].
+//!
+//! [start_replay] starts a new session of the same document and sends the recorded events again.
+//! The entered states of the replay are recorded the same way and can be compared with
+//! [verify_replay].
+//!
+//! Not recorded are events that the replay creates again by itself: control events of the platform
+//! (e.g. to synchronize or to trace the session), events of invoked sessions and events the session
+//! has sent to itself (e.g. delayed sends). A cancel of the session is recorded as normal event.
+
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+#[cfg(feature = "json-config")]
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+
+#[cfg(feature = "json-config")]
+use log::error;
+#[cfg(feature = "json-config")]
+use serde::{Deserialize, Serialize};
+
+use crate::actions::ActionWrapper;
+use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, FinishMode, Fsm, ScxmlSession};
+use crate::fsm_executor::FsmExecutor;
+use crate::fsm_snapshot::SnapshotEvent;
+
+/// One entry of a recording.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-config", derive(Serialize, Deserialize))]
+pub enum RecordedEntry {
+    /// An external event that was processed by the session. The source of the event is given by
+    /// its "origin" and "origin_type".
+    Event { at_ms: i64, event: SnapshotEvent },
+    /// A state was entered.
+    EnterState { at_ms: i64, name: String },
+    /// The session has finished, either by a top-level final state or by a cancel.
+    End { at_ms: i64 },
+}
+
+impl RecordedEntry {
+    /// Time in milliseconds since the start of the session.
+    pub fn at_ms(&self) -> i64 {
+        match self {
+            RecordedEntry::Event { at_ms, .. }
+            | RecordedEntry::EnterState { at_ms, .. }
+            | RecordedEntry::End { at_ms } => *at_ms,
+        }
+    }
+}
+
+#[derive(Default)]
+struct RecorderState {
+    start_ms: i64,
+    entries: Vec<RecordedEntry>,
+    #[cfg(feature = "json-config")]
+    writer: Option<Box<dyn Write + Send>>,
+}
+
+/// Collects the [RecordedEntry]s of a session.\
+/// Clones share the same entries, so a clone can be kept to access the entries after
+/// the recorder was moved into the FSM.
+#[derive(Clone, Default)]
+pub struct EventRecorder {
+    state: Arc<Mutex<RecorderState>>,
+}
+
+impl Debug for EventRecorder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventRecorder")
+            .field("entries", &self.state.lock().unwrap().entries.len())
+            .finish()
+    }
+}
+
+impl EventRecorder {
+    pub fn new() -> EventRecorder {
+        EventRecorder::default()
+    }
+
+    /// Creates a recorder that additionally writes each entry as one line of JSON, e.g. to a file.
+    #[cfg(feature = "json-config")]
+    pub fn with_writer(writer: Box<dyn Write + Send>) -> EventRecorder {
+        let recorder = EventRecorder::new();
+        recorder.state.lock().unwrap().writer = Some(writer);
+        recorder
+    }
+
+    /// Returns a copy of the recorded entries.
+    pub fn entries(&self) -> Vec<RecordedEntry> {
+        self.state.lock().unwrap().entries.clone()
+    }
+
+    /// The names of the entered states, in order of entry.
+    pub fn entered_states(&self) -> Vec<String> {
+        entered_states(&self.state.lock().unwrap().entries)
+    }
+
+    /// Returns the recorded entries as JSON lines.
+    #[cfg(feature = "json-config")]
+    pub fn to_json_lines(&self) -> String {
+        let mut lines = String::new();
+        for entry in &self.state.lock().unwrap().entries {
+            lines.push_str(serde_json::to_string(entry).unwrap().as_str());
+            lines.push('\n');
+        }
+        lines
+    }
+
+    /// Reads entries from JSON lines, as written by [EventRecorder::to_json_lines] or [EventRecorder::with_writer].
+    /// Empty lines are ignored.
+    #[cfg(feature = "json-config")]
+    pub fn from_json_lines(lines: &str) -> Result<Vec<RecordedEntry>, String> {
+        lines
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| serde_json::from_str(line).map_err(|err| format!("Illegal recording. {}", err)))
+            .collect()
+    }
+
+    /// Called when the session is started, the time of the entries is relative to this time.
+    pub(crate) fn start(&self, now_ms: i64) {
+        self.state.lock().unwrap().start_ms = now_ms;
+    }
+
+    pub(crate) fn event(&self, now_ms: i64, event: &Event) {
+        self.add(now_ms, |at_ms| RecordedEntry::Event {
+            at_ms,
+            event: SnapshotEvent::from_event(event),
+        });
+    }
+
+    pub(crate) fn enter_state(&self, now_ms: i64, name: &str) {
+        self.add(now_ms, |at_ms| RecordedEntry::EnterState {
+            at_ms,
+            name: name.to_string(),
+        });
+    }
+
+    pub(crate) fn end(&self, now_ms: i64) {
+        self.add(now_ms, |at_ms| RecordedEntry::End { at_ms });
+    }
+
+    fn add(&self, now_ms: i64, create: impl FnOnce(i64) -> RecordedEntry) {
+        let mut state = self.state.lock().unwrap();
+        let entry = create(now_ms - state.start_ms);
+        #[cfg(feature = "json-config")]
+        if let Some(writer) = &mut state.writer {
+            let line = serde_json::to_string(&entry).unwrap();
+            if let Err(err) = writeln!(writer, "{}", line).and_then(|_| writer.flush()) {
+                error!("Failed to write recording: {}", err);
+            }
+        }
+        state.entries.push(entry);
+    }
+}
+
+/// Timing of the events of a replay, see [start_replay].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplayTiming {
+    /// Each event is sent at its recorded time, using the clock of the executor.
+    /// With a [crate::clock::TestClock] the replay is controlled by the virtual time.
+    Original,
+    /// All events are sent at once, in the recorded order.
+    AsFastAsPossible,
+}
+
+/// Starts a new session of the FSM and sends the recorded events to it.\
+/// The FSM shall be created from the same document as the recorded session. The session keeps the
+/// final configuration.\
+/// Returns the session and the recorder of the replay, see [verify_replay].
+pub fn start_replay(
+    mut fsm: Box<Fsm>,
+    actions: ActionWrapper,
+    executor: FsmExecutor,
+    recording: &[RecordedEntry],
+    timing: ReplayTiming,
+) -> (ScxmlSession, EventRecorder) {
+    let recorder = EventRecorder::new();
+    fsm.event_recorder = Some(recorder.clone());
+    let clock = executor.clock();
+    let session = start_fsm_with_data_and_finish_mode(
+        fsm,
+        actions,
+        Box::new(executor),
+        &Vec::new(),
+        FinishMode::KEEP_CONFIGURATION,
+    );
+    for entry in recording {
+        if let RecordedEntry::Event { at_ms, event } = entry {
+            let event = Box::new(event.to_event());
+            match timing {
+                ReplayTiming::AsFastAsPossible => {
+                    let _ = session.sender.send(event);
+                }
+                ReplayTiming::Original => {
+                    let sender = session.sender.clone();
+                    let mut event = Some(event);
+                    clock
+                        .schedule(
+                            *at_ms,
+                            Box::new(move || {
+                                if let Some(event) = event.take() {
+                                    let _ = sender.send(event);
+                                }
+                            }),
+                        )
+                        .ignore();
+                }
+            }
+        }
+    }
+    (session, recorder)
+}
+
+/// Compares the entered states and the end of a replay with the recording.\
+/// Returns a description of the first difference.
+pub fn verify_replay(recording: &[RecordedEntry], replay: &[RecordedEntry]) -> Result<(), String> {
+    let expected = entered_states(recording);
+    let actual = entered_states(replay);
+    if let Some(idx) = (0..expected.len().max(actual.len())).find(|idx| expected.get(*idx) != actual.get(*idx)) {
+        return Err(format!(
+            "Entered state #{} differs: recorded {:?}, replayed {:?}",
+            idx,
+            expected.get(idx),
+            actual.get(idx)
+        ));
+    }
+    let ended = |entries: &[RecordedEntry]| {
+        entries
+            .iter()
+            .any(|e| matches!(e, RecordedEntry::End { .. }))
+    };
+    if ended(recording) != ended(replay) {
+        return Err(format!(
+            "Recorded session {}, replayed session {}",
+            if ended(recording) {
+                "has finished"
+            } else {
+                "is running"
+            },
+            if ended(replay) {
+                "has finished"
+            } else {
+                "is running"
+            }
+        ));
+    }
+    Ok(())
+}
+
+fn entered_states(entries: &[RecordedEntry]) -> Vec<String> {
+    entries
+        .iter()
+        .filter_map(|e| match e {
+            RecordedEntry::EnterState { name, .. } => Some(name.clone()),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use std::sync::Arc;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use std::time::Duration;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::clock::TestClock;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::event_recorder::{start_replay, verify_replay, EventRecorder, RecordedEntry, ReplayTiming};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, EventType, FinishMode, Fsm};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    use crate::scxml_reader;
+
+    /// The FSM of test "fsm_shall_exit".
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    fn create_fsm() -> Box<Fsm> {
+        scxml_reader::parse_from_xml(
+            r"<scxml initial='Main' datamodel='ecmascript'>
+              <script>
+                log('Hello World', ' again ');
+              </script>
+              <state id='Main'>
+                <initial>
+                  <transition target='MainA'/>
+                </initial>
+                <state id='MainA'>
+                  <transition event='a ab abc' cond='true' type='internal' target='finalMe'/>
+                </state>
+                <state id='MainB'>
+                </state>
+                <final id='finalMe'>
+                  <onentry>
+                    <log label='info' expr='Date.now()'/>
+                  </onentry>
+                </final>
+                <transition event='exit' cond='true' type='internal' target='OuterFinal'/>
+              </state>
+              <final id='OuterFinal'>
+              </final>
+            </scxml>"
+                .to_string(),
+        )
+        .unwrap()
+    }
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    fn executor_with_clock(clock: &TestClock) -> FsmExecutor {
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_clock(Arc::new(clock.clone()));
+        executor
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "json-config")]
+    fn recorded_session_can_be_replayed() {
+        let timeout = Duration::from_millis(2000);
+        let clock = TestClock::new(1000);
+        let recorder = EventRecorder::new();
+        let mut fsm = create_fsm();
+        fsm.event_recorder = Some(recorder.clone());
+        let session = start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(executor_with_clock(&clock)),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        clock.advance(100);
+        let mut event = Event::new_simple("ab");
+        event.etype = EventType::platform;
+        session.send_and_sync(Box::new(event), timeout).unwrap();
+        clock.advance(50);
+        session
+            .sender
+            .send(Box::new(Event::new_simple("exit")))
+            .unwrap();
+        let recorded_final = session
+            .wait_for_completion(timeout)
+            .unwrap()
+            .final_configuration;
+        assert_eq!(recorded_final, Some(vec!["OuterFinal".to_string()]));
+
+        // The synchronization is not part of the recording.
+        let recording = EventRecorder::from_json_lines(recorder.to_json_lines().as_str()).unwrap();
+        assert_eq!(recording, recorder.entries());
+        let events: Vec<(i64, String)> = recording
+            .iter()
+            .filter_map(|e| match e {
+                RecordedEntry::Event { at_ms, event } => Some((*at_ms, event.name.clone())),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![(100, "ab".to_string()), (150, "exit".to_string())]
+        );
+        assert_eq!(
+            recorder.entered_states(),
+            vec!["Main", "MainA", "finalMe", "OuterFinal"]
+        );
+        assert!(matches!(
+            recording.last(),
+            Some(RecordedEntry::End { at_ms: 150 })
+        ));
+
+        for timing in [ReplayTiming::Original, ReplayTiming::AsFastAsPossible] {
+            let clock = TestClock::new(0);
+            let (session, replay) = start_replay(
+                create_fsm(),
+                ActionWrapper::new(),
+                executor_with_clock(&clock),
+                &recording,
+                timing,
+            );
+            clock.advance(200);
+            let result = session.wait_for_completion(timeout).unwrap();
+            assert_eq!(result.final_configuration, recorded_final);
+            verify_replay(&recording, &replay.entries()).unwrap();
+        }
+
+        // A replay that is missing the last event doesn't finish.
+        let clock = TestClock::new(0);
+        let (session, replay) = start_replay(
+            create_fsm(),
+            ActionWrapper::new(),
+            executor_with_clock(&clock),
+            &recording[..recording.len() - 3],
+            ReplayTiming::AsFastAsPossible,
+        );
+        assert!(session
+            .wait_for_completion(Duration::from_millis(100))
+            .is_none());
+        assert!(verify_replay(&recording, &replay.entries()).is_err());
+    }
+}
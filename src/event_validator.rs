@@ -0,0 +1,128 @@
+//! Validation of external events before they are processed by the FSM.\
+//! Used if events come from untrusted producers, so that malformed payloads are rejected before
+//! conditions and executable content try to use them.
+//!
+//! Set a validator for all sessions of an executor via [crate::fsm_executor::FsmExecutor::set_event_validator]
+//! or for a single FSM via [crate::fsm::Fsm::event_validator].\
+//! Events that are rejected are dropped. Instead, "error.platform" is put into the internal queue,
+//! with the name of the rejected event in parameter "event" and the reason in parameter "message".
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+use crate::datamodel::Data;
+use crate::fsm::{event_descriptor_matches, strip_event_descriptor, Event};
+
+/// Checks external events before transitions are selected.
+pub trait EventValidator: Send + Sync {
+    /// Returns an error message if the event shall not be processed.
+    fn validate(&self, event: &Event) -> Result<(), String>;
+}
+
+/// Validator that checks that events contain the required parameters.\
+/// The required names are configured per event descriptor, e.g. "order" for "order.created" and
+/// "order.deleted". A parameter is present if a \<param\> with the name exists or if the content
+/// is a map with the name as key.
+#[derive(Debug, Default, Clone)]
+pub struct RequiredParamsValidator {
+    required: HashMap<String, Vec<String>>,
+}
+
+impl RequiredParamsValidator {
+    /// Creates a validator from a map of event descriptors to required parameter names.
+    pub fn new(required: HashMap<String, Vec<String>>) -> RequiredParamsValidator {
+        RequiredParamsValidator {
+            required: required
+                .into_iter()
+                .map(|(descriptor, names)| {
+                    (
+                        strip_event_descriptor(descriptor.as_str()).to_string(),
+                        names,
+                    )
+                })
+                .collect(),
+        }
+    }
+
+    /// Adds required parameter names for events that match the descriptor.
+    pub fn require(&mut self, descriptor: &str, names: &[&str]) {
+        self.required
+            .entry(strip_event_descriptor(descriptor).to_string())
+            .or_default()
+            .extend(names.iter().map(|n| n.to_string()));
+    }
+
+    fn has_param(event: &Event, name: &str) -> bool {
+        if let Some(params) = &event.param_values {
+            if params.iter().any(|p| p.name == name) {
+                return true;
+            }
+        }
+        if let Some(content) = &event.content {
+            if let Data::Map(map) = content.lock().unwrap().deref() {
+                return map.contains_key(name);
+            }
+        }
+        false
+    }
+}
+
+impl EventValidator for RequiredParamsValidator {
+    fn validate(&self, event: &Event) -> Result<(), String> {
+        let mut descriptors: Vec<&String> = self
+            .required
+            .keys()
+            .filter(|descriptor| event_descriptor_matches(descriptor, event.name.as_str()))
+            .collect();
+        descriptors.sort();
+        for descriptor in descriptors {
+            for name in &self.required[descriptor] {
+                if !Self::has_param(event, name) {
+                    return Err(format!(
+                        "Event '{}' misses required parameter '{}'",
+                        event.name, name
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::datamodel::{create_data_arc, Data};
+    use crate::event_validator::{EventValidator, RequiredParamsValidator};
+    use crate::fsm::{Event, ParamPair};
+
+    #[test]
+    fn required_params_are_checked() {
+        let validator = RequiredParamsValidator::new(HashMap::from([(
+            "order.*".to_string(),
+            vec!["id".to_string()],
+        )]));
+
+        let mut event = Event::new_simple("order.created");
+        assert_eq!(
+            validator.validate(&event),
+            Err("Event 'order.created' misses required parameter 'id'".to_string())
+        );
+        event.param_values = Some(vec![ParamPair::new_moved(
+            "id".to_string(),
+            Data::Integer(1),
+        )]);
+        assert!(validator.validate(&event).is_ok());
+
+        let mut event = Event::new_simple("order");
+        event.content = Some(create_data_arc(Data::Map(HashMap::from([(
+            "id".to_string(),
+            create_data_arc(Data::Integer(1)),
+        )]))));
+        assert!(validator.validate(&event).is_ok());
+
+        // Descriptors match only complete tokens.
+        assert!(validator.validate(&Event::new_simple("orders")).is_ok());
+    }
+}
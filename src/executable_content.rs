@@ -3,6 +3,7 @@
 
 use std::fmt;
 use std::fmt::{Debug, Display, Formatter};
+use std::mem;
 use std::sync::atomic::Ordering;
 #[cfg(test)]
 use std::{println as info, println as warn};
@@ -16,12 +17,11 @@ use log::debug;
 use log::{info, warn};
 
 use crate::datamodel::{str_to_source, Data, Datamodel, ToAny, SCXML_EVENT_PROCESSOR};
-use crate::expression_engine::lexer::ExpressionLexer;
 use crate::fsm::{
-    opt_vec_to_string, vec_to_string, CommonContent, ExecutableContentId, Fsm, ParamPair, Parameter,
-    PLATFORM_ID_COUNTER,
+    find_duplicate_param_name, opt_vec_to_string, vec_to_string, CommonContent, DelayedSend, ExecutableContentId, Fsm,
+    ParamPair, Parameter, SourceLine, PLATFORM_ID_COUNTER,
 };
-use crate::scxml_event_io_processor::SCXML_TARGET_INTERNAL;
+use crate::scxml_event_io_processor::{SCXML_EVENT_PROCESSOR_SHORT_TYPE, SCXML_TARGET_INTERNAL};
 use crate::{get_global, Event, EventType};
 
 pub const TARGET_SCXML_EVENT_PROCESSOR: &str = "http://www.w3.org/TR/scxml/#SCXMLEventProcessor";
@@ -52,6 +52,41 @@ pub trait ExecutableContent: ToAny + Debug + Send {
     fn execute(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool;
     fn get_type(&self) -> u8;
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, fsm: &Fsm);
+    /// Line of the element in the source document. 0 if not known.
+    fn get_source_line(&self) -> SourceLine;
+    fn set_source_line(&mut self, line: SourceLine);
+    /// Replacement for a generic "clone".\
+    /// Returns None if the element can't be copied, documents with such elements are not cached,
+    /// see [crate::document_cache].
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        None
+    }
+}
+
+/// Executes the element.\
+/// "error.execution" events that are raised during the execution get the source line of the element,
+/// see [crate::fsm::GlobalData::error_source_line].
+pub fn execute_with_source_line(ec: &dyn ExecutableContent, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool {
+    let line = ec.get_source_line();
+    let previous_line = mem::replace(&mut get_global!(datamodel).error_source_line, line);
+    let r = ec.execute(datamodel, fsm);
+    get_global!(datamodel).error_source_line = previous_line;
+    r
+}
+
+/// Handles executable content that misses required values, e.g. read by
+/// [crate::scxml_reader::parse_from_xml_lenient].\
+/// Places "error.execution" in the internal queue and returns false, so that the remaining elements of the
+/// block are not executed.
+fn missing_value(datamodel: &mut dyn Datamodel, tag: &str, message: &str) -> bool {
+    error!(
+        "<{}>{}: {}",
+        tag,
+        get_global!(datamodel).source_line_info(),
+        message
+    );
+    datamodel.internal_error_execution();
+    false
 }
 
 pub fn get_safe_executable_content_as<T: 'static>(ec: &mut dyn ExecutableContent) -> &mut T {
@@ -80,14 +115,15 @@ pub trait ExecutableContentTracer {
     fn print_sub_content(&mut self, name: &str, fsm: &Fsm, content: ExecutableContentId);
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Cancel {
     pub send_id: String,
     pub send_id_expr: Data,
+    pub source_line: SourceLine,
 }
 
 /// Holds all parameters of a \<send\> call.
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct SendParameters {
     /// SCXML \<send\> attribute 'idlocation'
     pub name_location: String,
@@ -116,6 +152,7 @@ pub struct SendParameters {
     /// \<param\> children
     pub params: Option<Vec<Parameter>>,
     pub content: Option<CommonContent>,
+    pub source_line: SourceLine,
 }
 
 impl SendParameters {
@@ -135,13 +172,32 @@ impl SendParameters {
             name_list: Vec::new(),
             params: None,
             content: None,
+            source_line: 0,
         }
     }
 }
 
+impl SendParameters {
+    /// True if the event name is missing. The SCXML I/O Processor needs 'event', 'eventexpr' or \<content\>,
+    /// other processors (e.g. the Basic HTTP Event I/O Processor) may send events without name.
+    pub fn misses_event(&self) -> bool {
+        let scxml_type = match &self.type_value {
+            Data::None() => self.type_expr.is_empty(),
+            Data::Source(source) => {
+                [SCXML_EVENT_PROCESSOR, SCXML_EVENT_PROCESSOR_SHORT_TYPE].contains(&source.as_str())
+            }
+            _ => false,
+        };
+        scxml_type && self.event.is_empty() && self.event_expr.is_empty() && self.content.is_none()
+    }
+}
+
 impl Debug for SendParameters {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Send").field("name", &self.name).finish()
+        f.debug_struct("Send")
+            .field("name", &self.name)
+            .field("line", &self.source_line)
+            .finish()
     }
 }
 
@@ -150,6 +206,7 @@ impl Cancel {
         Cancel {
             send_id: String::new(),
             send_id_expr: Data::None(),
+            source_line: 0,
         }
     }
 }
@@ -159,39 +216,45 @@ impl Debug for Cancel {
         f.debug_struct("Cancel")
             .field("send_id", &self.send_id)
             .field("send_id_expr", &self.send_id_expr)
+            .field("line", &self.source_line)
             .finish()
     }
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Script {
     pub content: Vec<ExecutableContentId>,
+    pub source_line: SourceLine,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Expression {
     pub content: Data,
+    pub source_line: SourceLine,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Log {
     pub label: String,
     pub expression: Data,
+    pub source_line: SourceLine,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct If {
     pub condition: Data,
     pub content: ExecutableContentId,
     pub else_content: ExecutableContentId,
+    pub source_line: SourceLine,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct ForEach {
     pub array: Data,
     pub item: String,
     pub index: String,
     pub content: ExecutableContentId,
+    pub source_line: SourceLine,
 }
 
 /// *W3C says*:
@@ -203,15 +266,19 @@ pub struct ForEach {
 /// generated by \<raise\> will not be processed until ec1 and ec2 have finished execution and e1 has been
 /// processed.
 ///
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Raise {
     pub event: String,
+    pub source_line: SourceLine,
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Assign {
     pub location: Data,
     pub expr: Data,
+    /// Child content, used as value if 'expr' is not given.
+    pub content: Option<CommonContent>,
+    pub source_line: SourceLine,
 }
 
 impl Assign {
@@ -219,6 +286,8 @@ impl Assign {
         Assign {
             location: Data::None(),
             expr: Data::None(),
+            content: None,
+            source_line: 0,
         }
     }
 }
@@ -228,25 +297,64 @@ impl Debug for Assign {
         f.debug_struct("Assign")
             .field("location", &self.location)
             .field("expr", &self.expr)
+            .field("content", &self.content)
+            .field("line", &self.source_line)
             .finish()
     }
 }
 
 impl ExecutableContent for Assign {
     fn execute(&self, datamodel: &mut dyn Datamodel, _fsm: &Fsm) -> bool {
-        datamodel.assign(&self.location, &self.expr)
+        if self.location.is_empty() {
+            return missing_value(datamodel, "assign", "'location' is missing");
+        }
+        match (self.expr.is_empty(), &self.content) {
+            (false, None) => datamodel.assign(&self.location, &self.expr),
+            (true, Some(_)) => match datamodel.evaluate_content(&self.content) {
+                // evaluate_content already added "error.execution"
+                None => false,
+                Some(value) => {
+                    let value = value.lock().unwrap().clone();
+                    datamodel.assign(&self.location, &value)
+                }
+            },
+            _ => {
+                // W3C says:\
+                // A conformant SCXML document MUST NOT specify both the 'expr' attribute and child
+                // content.
+                error!(
+                    "Assign: '{}' needs either 'expr' or child content, but not both",
+                    self.location
+                );
+                datamodel.internal_error_execution();
+                false
+            }
+        }
     }
 
     fn get_type(&self) -> u8 {
         TYPE_ASSIGN
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         tracer.print_name_and_attributes(
             self,
             &[
                 ("location", &self.location.to_string()),
                 ("expr", &self.expr.to_string()),
+                ("content", &format!("{:?}", self.content)),
             ],
         );
     }
@@ -256,18 +364,25 @@ impl Raise {
     pub fn new() -> Raise {
         Raise {
             event: String::new(),
+            source_line: 0,
         }
     }
 }
 
 impl Debug for Raise {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.debug_struct("Raise").field("event", &self.event).finish()
+        f.debug_struct("Raise")
+            .field("event", &self.event)
+            .field("line", &self.source_line)
+            .finish()
     }
 }
 
 impl ExecutableContent for Raise {
     fn execute(&self, datamodel: &mut dyn Datamodel, _fsm: &Fsm) -> bool {
+        if self.event.is_empty() {
+            return missing_value(datamodel, "raise", "'event' is missing");
+        }
         let event = Event::new("", &self.event, None, None, EventType::internal);
         get_global!(datamodel).enqueue_internal(event);
         true
@@ -277,6 +392,18 @@ impl ExecutableContent for Raise {
         TYPE_RAISE
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         tracer.print_name_and_attributes(self, &[("event", &self.event)]);
     }
@@ -286,6 +413,7 @@ impl Script {
     pub fn new() -> Script {
         Script {
             content: Vec::new(),
+            source_line: 0,
         }
     }
 }
@@ -304,6 +432,18 @@ impl ExecutableContent for Script {
         TYPE_SCRIPT
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         // TODO: Shall we print any sub-content?
         tracer.print_name_and_attributes(self, &[("content", &vec_to_string(&self.content))]);
@@ -314,6 +454,7 @@ impl Expression {
     pub fn new() -> Expression {
         Expression {
             content: str_to_source(""),
+            source_line: 0,
         }
     }
 }
@@ -328,6 +469,18 @@ impl ExecutableContent for Expression {
         TYPE_EXPRESSION
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         tracer.print_name_and_attributes(self, &[("content", &self.content.to_string())]);
     }
@@ -338,6 +491,7 @@ impl Log {
         Log {
             label: label.unwrap_or(&"".to_string()).clone(),
             expression,
+            source_line: 0,
         }
     }
 }
@@ -346,7 +500,8 @@ impl ExecutableContent for Log {
     fn execute(&self, datamodel: &mut dyn Datamodel, _fsm: &Fsm) -> bool {
         match &datamodel.execute(&self.expression) {
             Ok(msg) => {
-                datamodel.log(msg.lock().unwrap().to_string().as_str());
+                let msg = msg.lock().unwrap().to_string();
+                datamodel.log_with_label(self.label.as_str(), msg.as_str());
                 true
             }
             Err(_msg) => false,
@@ -357,8 +512,26 @@ impl ExecutableContent for Log {
         TYPE_LOG
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
-        tracer.print_name_and_attributes(self, &[("expression", &self.expression.to_string())]);
+        tracer.print_name_and_attributes(
+            self,
+            &[
+                ("label", &self.label),
+                ("expression", &self.expression.to_string()),
+            ],
+        );
     }
 }
 
@@ -368,29 +541,36 @@ impl If {
             condition,
             content: 0,
             else_content: 0,
+            source_line: 0,
         }
     }
 }
 
 impl ExecutableContent for If {
     fn execute(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool {
+        if self.condition.is_empty() {
+            return missing_value(datamodel, "if", "'cond' is missing");
+        }
         let r = datamodel
             .execute_condition(&self.condition)
             .unwrap_or_else(|e| {
-                warn!("Condition {} can't be evaluated. {}", self.condition, e);
+                warn!(
+                    "Condition '{}' (line {}) can't be evaluated. {}",
+                    self.condition, self.source_line, e
+                );
                 false
             });
         if r {
             if self.content != 0 {
                 for e in fsm.executableContent.get(&self.content).unwrap() {
-                    if !e.execute(datamodel, fsm) {
+                    if !execute_with_source_line(e.as_ref(), datamodel, fsm) {
                         return false;
                     }
                 }
             }
         } else if self.else_content != 0 {
             for e in fsm.executableContent.get(&self.else_content).unwrap() {
-                if !e.execute(datamodel, fsm) {
+                if !execute_with_source_line(e.as_ref(), datamodel, fsm) {
                     return false;
                 }
             }
@@ -402,6 +582,18 @@ impl ExecutableContent for If {
         TYPE_IF
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, fsm: &Fsm) {
         tracer.print_name_and_attributes(self, &[("condition", &self.condition.to_string())]);
         tracer.print_sub_content("then", fsm, self.content);
@@ -418,12 +610,16 @@ impl ForEach {
             item: String::new(),
             index: String::new(),
             content: 0,
+            source_line: 0,
         }
     }
 }
 
 impl ExecutableContent for ForEach {
     fn execute(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool {
+        if self.array.is_empty() || self.item.is_empty() {
+            return missing_value(datamodel, "foreach", "'array' or 'item' is missing");
+        }
         let idx = if self.index.is_empty() {
             INDEX_TEMP.to_string()
         } else {
@@ -437,7 +633,7 @@ impl ExecutableContent for ForEach {
         datamodel.execute_for_each(&self.array, &self.item, &idx, &mut |datamodel| -> bool {
             if self.content != 0 {
                 for e in fsm.executableContent.get(&self.content).unwrap() {
-                    if !e.execute(datamodel, fsm) {
+                    if !execute_with_source_line(e.as_ref(), datamodel, fsm) {
                         return false;
                     }
                 }
@@ -450,6 +646,18 @@ impl ExecutableContent for ForEach {
         TYPE_FOREACH
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, fsm: &Fsm) {
         tracer.print_name_and_attributes(
             self,
@@ -469,6 +677,7 @@ impl Parameter {
             name: "".to_string(),
             expr: "".to_string(),
             location: "".to_string(),
+            write_back: false,
         }
     }
 }
@@ -477,8 +686,8 @@ impl Display for Parameter {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "Parameter{{name:{} expr:{} location:{}}}",
-            self.name, self.expr, self.location
+            "Parameter{{name:{} expr:{} location:{} write_back:{}}}",
+            self.name, self.expr, self.location, self.write_back
         )
     }
 }
@@ -491,12 +700,14 @@ impl ExecutableContent for Cancel {
     /// the specified id. Note, however, that it can not be guaranteed to succeed, for example if
     /// the event has already been delivered by the time the \<cancel> tag executes.
     fn execute(&self, datamodel: &mut dyn Datamodel, _fsm: &Fsm) -> bool {
+        if self.send_id.is_empty() && self.send_id_expr.is_empty() {
+            return missing_value(datamodel, "cancel", "'sendid' or 'sendidexpr' is missing");
+        }
         if let Ok(send_id) =
             datamodel.get_expression_alternative_value(&str_to_source(self.send_id.as_str()), &self.send_id_expr)
         {
-            get_global!(datamodel)
-                .delayed_send
-                .remove(&send_id.lock().unwrap().to_string());
+            let send_id = send_id.lock().unwrap().to_string();
+            get_global!(datamodel).cancel_delayed_send(send_id.as_str());
         };
         true
     }
@@ -505,6 +716,18 @@ impl ExecutableContent for Cancel {
         TYPE_CANCEL
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         tracer.print_name_and_attributes(
             self,
@@ -516,28 +739,11 @@ impl ExecutableContent for Cancel {
     }
 }
 
-/// Implements the execution of \<send\> element.
-impl ExecutableContent for SendParameters {
-    /// If unable to dispatch, place "error.communication" in internal queue
-    /// If target is not supported, place "error.execution" in internal queue
-    fn execute(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool {
-        let target = match datamodel.get_expression_alternative_value(&self.target, &self.target_expr) {
-            Ok(value) => value,
-            Err(_) => {
-                // Error -> abort
-                return false;
-            }
-        };
-
-        let event_name = match datamodel.get_expression_alternative_value(&self.event, &self.event_expr) {
-            Ok(value) => value,
-            Err(_) => {
-                // Error -> abort
-                return false;
-            }
-        };
-
-        let send_id = if self.name_location.is_empty() {
+impl SendParameters {
+    /// Gets the sendid, either from attribute "id" or generated if "idlocation" is given.\
+    /// The generated id is stored by [SendParameters::store_send_id].
+    fn get_send_id(&self) -> Option<String> {
+        if self.name_location.is_empty() {
             if self.name.is_empty() {
                 None
             } else {
@@ -552,19 +758,42 @@ impl ExecutableContent for SendParameters {
             // The SCXML processor MAY generate all other ids in any format, as long as they are unique.
             //
             // Implementation: we do it the same as for invoke
-
-            let generated_id = format!(
+            Some(format!(
                 "{}.{}",
                 &self.parent_state_name,
                 PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
-            );
+            ))
+        }
+    }
 
-            datamodel.set(
-                self.name_location.as_str(),
-                Data::String(generated_id.clone()),
-                true,
-            );
-            Some(generated_id)
+    /// Stores the generated sendid in the location given by "idlocation", before any other
+    /// argument is evaluated.\
+    /// If the location is not valid, the datamodel puts "error.execution" in the internal queue.
+    fn store_send_id(&self, datamodel: &mut dyn Datamodel, send_id: &Option<String>) -> bool {
+        match send_id {
+            Some(send_id) if !self.name_location.is_empty() => datamodel.assign(
+                &str_to_source(self.name_location.as_str()),
+                &Data::String(send_id.clone()),
+            ),
+            _ => true,
+        }
+    }
+
+    /// Evaluates the element and sends the event.\
+    /// Returns an error if the element can't be evaluated, the caller puts "error.execution" in the internal queue.
+    /// Errors of the I/O processors are handled by the processors, these result in Ok(false).
+    fn send(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm, send_id: Option<String>) -> Result<bool, String> {
+        let target = match datamodel.get_expression_alternative_value(&self.target, &self.target_expr) {
+            Ok(value) => value,
+            // W3C says:
+            // If the evaluation of <send>'s arguments produces an error, the Processor must discard
+            // the message and raise error.execution.
+            Err(err) => return Err(format!("failed to evaluate the target: {}", err)),
+        };
+
+        let event_name = match datamodel.get_expression_alternative_value(&self.event, &self.event_expr) {
+            Ok(value) => value,
+            Err(err) => return Err(format!("failed to evaluate the event: {}", err)),
         };
 
         let mut data_vec = Vec::new();
@@ -572,16 +801,22 @@ impl ExecutableContent for SendParameters {
         let mut content = None;
 
         // A conformant document MUST NOT specify "namelist" or <param> with <content>.
+        if self.content.is_some() && !(self.name_list.is_empty() && self.params.is_none()) {
+            return Err("'namelist' or <param> can't be used together with <content>".to_string());
+        }
+        if let Some(name) = find_duplicate_param_name(&self.name_list, &self.params) {
+            return Err(format!(
+                "'{}' is given more than once in 'namelist' or <param>",
+                name
+            ));
+        }
         if self.content.is_some() {
             content = datamodel.evaluate_content(&self.content);
         } else {
             datamodel.evaluate_params(&self.params, &mut data_vec);
             for name in self.name_list.as_slice() {
                 match datamodel.get_by_location(name) {
-                    Err(_msg) => {
-                        // Error -> Abort
-                        return false;
-                    }
+                    Err(err) => return Err(format!("invalid location '{}' in namelist: {}", name, err)),
                     Ok(value) => {
                         data_vec.push(ParamPair::new(name.as_str(), &value.lock().unwrap()));
                     }
@@ -591,39 +826,29 @@ impl ExecutableContent for SendParameters {
 
         let delay_ms = if !self.delay_expr.is_empty() {
             match datamodel.execute(&self.delay_expr) {
-                Err(_msg) => {
-                    // Error -> Abort
-                    return false;
+                Err(err) => return Err(format!("failed to evaluate the delay: {}", err)),
+                Ok(delay) => {
+                    let delay = delay.lock().unwrap().to_string();
+                    match parse_duration_to_milliseconds(&delay) {
+                        Err(err) => return Err(format!("{} from delayexpr '{}'", err, self.delay_expr)),
+                        Ok(delay_ms) => delay_ms,
+                    }
                 }
-                Ok(delay) => parse_duration_to_milliseconds(&delay.lock().unwrap().to_string()),
             }
         } else {
-            self.delay_ms as i64
+            self.delay_ms
         };
 
-        if delay_ms < 0 {
-            // Delay is invalid -> Abort
-            error!("Send: delay {} is negative", self.delay_expr);
-            datamodel.internal_error_execution_for_event(&send_id, &fsm.caller_invoke_id);
-            return false;
-        }
-
         let target_guard = target.lock().unwrap();
         if delay_ms > 0 && target_guard.to_string().eq(SCXML_TARGET_INTERNAL) {
             // Can't send via internal queue
-            error!("Send: illegal delay for target {}", target_guard);
-            datamodel.internal_error_execution_for_event(&send_id, &fsm.caller_invoke_id);
-            return false;
+            return Err(format!("illegal delay for target {}", target_guard));
         }
         let type_result = datamodel.get_expression_alternative_value(&self.type_value, &self.type_expr);
 
         let type_val = match type_result {
             Ok(val) => val,
-            Err(err) => {
-                error!("Failed to evaluate send type: {}", err);
-                datamodel.internal_error_execution_for_event(&send_id, &fsm.caller_invoke_id);
-                return false;
-            }
+            Err(err) => return Err(format!("failed to evaluate the type: {}", err)),
         };
 
         let type_val_string = if type_val.lock().unwrap().is_empty() {
@@ -648,51 +873,91 @@ impl ExecutableContent for SendParameters {
             content,
         };
 
-        let result = if delay_ms > 0 {
-            let iop_opt = datamodel.get_io_processor(type_val_str);
-            if let Some(iop) = iop_opt {
-                let iopc = iop.clone();
-                #[cfg(feature = "Debug")]
-                debug!("schedule '{}' for {}", event, delay_ms);
-                let global_clone = datamodel.global_s().clone();
-                let send_id_clone = send_id.clone();
-                let target_str = target_guard.to_string();
-                let tg = fsm.schedule(delay_ms, move || {
-                    if let Some(sid) = &send_id_clone {
-                        global_clone.lock().unwrap().delayed_send.remove(sid);
-                    }
-                    iopc.lock()
-                        .unwrap()
-                        .send(&global_clone, target_str.as_str(), event.clone());
-                });
-                if let Some(g) = tg {
-                    if let Some(sid) = &send_id {
-                        datamodel
-                            .global()
-                            .lock()
-                            .unwrap()
-                            .delayed_send
-                            .insert(sid.clone(), g);
-                    } else {
-                        g.ignore();
-                    }
-                };
-                true
-            } else {
-                error!("Unknown io-processor {}", type_val_str);
-                false
-            }
+        let iop = match datamodel.get_io_processor(type_val_str) {
+            // W3C:  If the SCXML Processor does not support the type that is specified,
+            // it must place the event error.execution on the internal event queue.
+            None => return Err(format!("unknown io-processor {}", type_val_str)),
+            Some(iop) => iop,
+        };
+
+        // The io-processors place errors on the internal queue themselves.
+        if delay_ms > 0 {
+            #[cfg(feature = "Debug")]
+            debug!("schedule '{}' for {}", event, delay_ms);
+            // The executor passes the send to the session, the session passes the event to the
+            // I/O processor. If the session terminates before the delay has elapsed, the send is cancelled.
+            let (session_id, executor) = {
+                let global = get_global!(datamodel);
+                (global.session_id, global.executor.clone())
+            };
+            let executor = match executor {
+                Some(executor) => executor,
+                None => return Err("delayed send without executor".to_string()),
+            };
+            let timer_number = PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            let guard = executor.schedule(
+                session_id,
+                delay_ms as i64,
+                DelayedSend {
+                    send_id: send_id.clone(),
+                    timer_number,
+                    target: target_guard.to_string(),
+                    event,
+                    io_processor: iop,
+                },
+            );
+            get_global!(datamodel).add_delayed_send(send_id.as_deref(), timer_number, guard);
+            Ok(true)
         } else {
             #[cfg(feature = "Debug")]
             debug!("send '{}' to '{}'", event, target_guard);
-            datamodel.send(type_val_str, &target_guard, event.clone())
-        };
+            // Events for the internal queue are offered to the subscribers when they are enqueued.
+            if !target_guard.to_string().eq(SCXML_TARGET_INTERNAL) {
+                get_global!(datamodel).notify_event_subscribers(&event);
+            }
+            Ok(datamodel.send_with_processor(&iop, target_guard.to_string().as_str(), event))
+        }
+    }
+}
 
-        if !result {
-            // W3C:  If the SCXML Processor does not support the type that is specified,
-            // it must place the event error.execution on the internal event queue.
-            datamodel.internal_error_execution_for_event(&send_id, &fsm.caller_invoke_id);
+/// Implements the execution of \<send\> element.
+impl ExecutableContent for SendParameters {
+    /// If unable to dispatch, place "error.communication" in internal queue
+    /// If target is not supported, place "error.execution" in internal queue
+    /// W3C says:\
+    /// The sendid of the \<send\> element that triggered an error event is set in the "sendid" field of the event.
+    fn execute(&self, datamodel: &mut dyn Datamodel, fsm: &Fsm) -> bool {
+        if self.misses_event() {
+            return missing_value(
+                datamodel,
+                "send",
+                "'event', 'eventexpr' or <content> is missing",
+            );
+        }
+        let send_id = self.get_send_id();
+        // Errors raised while the element is evaluated (also by the datamodel) get the sendid.
+        let execution_errors = {
+            let mut global = get_global!(datamodel);
+            global.error_send_id.clone_from(&send_id);
+            global.execution_errors
+        };
+        let result = if self.store_send_id(datamodel, &send_id) {
+            self.send(datamodel, fsm, send_id)
+        } else {
+            Err(format!("invalid idlocation '{}'", self.name_location))
         };
+        let result = match result {
+            Ok(sent) => sent,
+            Err(err) => {
+                error!("Send{}: {}", get_global!(datamodel).source_line_info(), err);
+                // Some errors are already reported by the datamodel, "error.execution" is raised only once.
+                if get_global!(datamodel).execution_errors == execution_errors {
+                    datamodel.internal_error_execution();
+                }
+                false
+            }
+        };
+        get_global!(datamodel).error_send_id = None;
         result
     }
 
@@ -700,6 +965,18 @@ impl ExecutableContent for SendParameters {
         TYPE_SEND
     }
 
+    fn get_copy(&self) -> Option<Box<dyn ExecutableContent>> {
+        Some(Box::new(self.clone()))
+    }
+
+    fn get_source_line(&self) -> SourceLine {
+        self.source_line
+    }
+
+    fn set_source_line(&mut self, line: SourceLine) {
+        self.source_line = line;
+    }
+
     fn trace(&self, tracer: &mut dyn ExecutableContentTracer, _fsm: &Fsm) {
         tracer.print_name_and_attributes(
             self,
@@ -724,59 +1001,523 @@ impl ExecutableContent for SendParameters {
 
 #[cfg(test)]
 mod tests {
-    use crate::executable_content::parse_duration_to_milliseconds;
+    use crate::executable_content::{parse_duration_to_milliseconds, ParseError};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::test::run_test_manual;
+    #[cfg(feature = "Trace")]
+    use crate::tracer::TraceMode;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::collections::HashMap;
 
     #[test]
     fn delay_parse() {
-        assert_eq!(parse_duration_to_milliseconds("6.7s"), 6700);
-        assert_eq!(parse_duration_to_milliseconds("0.5d"), 12 * 60 * 60 * 1000);
-        assert_eq!(parse_duration_to_milliseconds("1m"), 60 * 1000);
-        assert_eq!(parse_duration_to_milliseconds("0.001s"), 1);
-        assert_eq!(parse_duration_to_milliseconds("6.7S"), 6700);
-        assert_eq!(parse_duration_to_milliseconds("0.5D"), 12 * 60 * 60 * 1000);
-        assert_eq!(parse_duration_to_milliseconds("1M"), 60 * 1000);
-        assert_eq!(parse_duration_to_milliseconds("0.001S"), 1);
+        assert_eq!(parse_duration_to_milliseconds("6.7s"), Ok(6700));
+        assert_eq!(
+            parse_duration_to_milliseconds("0.5d"),
+            Ok(12 * 60 * 60 * 1000)
+        );
+        assert_eq!(parse_duration_to_milliseconds("1m"), Ok(60 * 1000));
+        assert_eq!(parse_duration_to_milliseconds("0.001s"), Ok(1));
+        assert_eq!(parse_duration_to_milliseconds("6.7S"), Ok(6700));
+        assert_eq!(
+            parse_duration_to_milliseconds("0.5D"),
+            Ok(12 * 60 * 60 * 1000)
+        );
+        assert_eq!(parse_duration_to_milliseconds("1M"), Ok(60 * 1000));
+        assert_eq!(parse_duration_to_milliseconds("0.001S"), Ok(1));
+        assert_eq!(parse_duration_to_milliseconds("500ms"), Ok(500));
+        assert_eq!(parse_duration_to_milliseconds("1.5ms"), Ok(2));
+        assert_eq!(parse_duration_to_milliseconds(".5s"), Ok(500));
+        assert_eq!(parse_duration_to_milliseconds(""), Ok(0));
+        assert_eq!(parse_duration_to_milliseconds("0"), Ok(0));
+        assert_eq!(parse_duration_to_milliseconds("0.0"), Ok(0));
+
+        // ISO 8601
+        assert_eq!(parse_duration_to_milliseconds("PT1.5S"), Ok(1500));
+        assert_eq!(parse_duration_to_milliseconds("PT2M"), Ok(2 * 60 * 1000));
+        assert_eq!(
+            parse_duration_to_milliseconds("P1DT1H1M1S"),
+            Ok(((24 + 1) * 60 + 1) * 60 * 1000 + 1000)
+        );
+        assert_eq!(parse_duration_to_milliseconds("pt0.0005s"), Ok(1));
+
+        assert_eq!(
+            parse_duration_to_milliseconds("x1S"),
+            Err(ParseError::Invalid("x1S".to_string()))
+        );
+        assert_eq!(
+            parse_duration_to_milliseconds("1Sx"),
+            Err(ParseError::Invalid("1Sx".to_string()))
+        );
+        assert_eq!(
+            parse_duration_to_milliseconds("5"),
+            Err(ParseError::Invalid("5".to_string()))
+        );
+        assert_eq!(
+            parse_duration_to_milliseconds("-1s"),
+            Err(ParseError::Negative("-1s".to_string()))
+        );
+        for invalid in ["P", "PT", "PT1", "PTS", "PT1S2M", "PT1M1M", "P1M", "1.2.3s"] {
+            assert_eq!(
+                parse_duration_to_milliseconds(invalid),
+                Err(ParseError::Invalid(invalid.to_string())),
+                "{}",
+                invalid
+            );
+        }
+    }
 
-        assert_eq!(parse_duration_to_milliseconds("x1S"), -1);
-        assert_eq!(parse_duration_to_milliseconds("1Sx"), -1);
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_namelist_is_forwarded() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='var1' expr='5'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <send event='ev' namelist='var1'/>
+                </onentry>
+                <transition event='ev' cond='_event.data.var1==5' target='Conflict'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Conflict'>
+                <onentry>
+                  <!-- namelist and content are mutually exclusive -->
+                  <send event='ev' namelist='var1'>
+                    <content expr='1'/>
+                  </send>
+                  <send event='noError'/>
+                </onentry>
+                <transition event='error.execution' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "send_namelist_is_forwarded",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_namelist_with_invalid_location_raises_error() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <send event='ev' namelist='undefinedVar'/>
+                </onentry>
+                <transition event='error.execution' target='Sent'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Sent'>
+                <onentry>
+                  <send event='noEvent' delay='500ms'/>
+                </onentry>
+                <!-- The event with the invalid namelist shall not be sent -->
+                <transition event='noEvent' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "send_namelist_with_invalid_location_raises_error",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_errors_carry_sendid() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='generated'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <!-- Evaluation of the target fails inside the datamodel -->
+                  <send id='badTarget' event='ev' targetexpr='undefined.field'/>
+                </onentry>
+                <transition event='error.execution' cond="_event.sendid == 'badTarget'" target='Delay'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Delay'>
+                <onentry>
+                  <send idlocation='generated' event='ev' delayexpr="'abc'"/>
+                </onentry>
+                <transition event='error.execution' cond='_event.sendid == generated' target='Invoke'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Invoke'>
+                <invoke id='badInvoke' typeexpr='undefined.field'/>
+                <transition event='error.execution' cond="_event.invokeid == 'badInvoke'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "send_errors_carry_sendid",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn dynamic_send_errors_raise_one_error() {
+        // Each <onentry> is a separate block, so each failing send has its own chance to raise errors.
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='errors' expr='[]'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry><send id='delay' event='ev' target='#_internal' delayexpr="'1s'"/></onentry>
+                <onentry><send id='namelist' event='ev' namelist='undefined.field'/></onentry>
+                <onentry><send id='event' eventexpr='undefined.field'/></onentry>
+                <onentry><send id='type' event='ev' typeexpr="'unknown'"/></onentry>
+                <onentry><raise event='check'/></onentry>
+                <transition event='error.execution'>
+                  <assign location='errors' expr='errors.concat([_event.sendid])'/>
+                </transition>
+                <transition event='check' cond="errors.join() == 'delay,namelist,event,type'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "dynamic_send_errors_raise_one_error",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn generated_sendid_cancels_delayed_send() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='ids' expr='({})'/>
+                <data id='other'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <send idlocation='ids.delayed' event='delayed' delay='200ms'/>
+                  <send idlocation='other' event='other' delay='1ms'/>
+                  <send event='timeout' delay='500ms'/>
+                  <cancel sendidexpr='ids.delayed'/>
+                </onentry>
+                <transition event='other' cond='_event.sendid == other &amp;&amp; other != ids.delayed' target='Wait'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Wait'>
+                <transition event='timeout' target='ReadOnly'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='ReadOnly'>
+                <onentry>
+                  <!-- The id can't be stored, so the event is not sent -->
+                  <send idlocation='_sessionid' event='notSent'/>
+                </onentry>
+                <transition event='error.execution' cond="typeof _event.sendid == 'string'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "generated_sendid_cancels_delayed_send",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn mixed_delayed_and_immediate_sends() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Loop' datamodel='ecmascript'>
+              <datamodel>
+                <data id='rounds' expr='0'/>
+                <data id='immediate' expr='0'/>
+                <data id='external' expr='0'/>
+              </datamodel>
+              <state id='Loop'>
+                <onentry>
+                  <assign location='rounds' expr='rounds + 1'/>
+                  <send event='external' delay='1ms'/>
+                  <send event='delayed' delay='1ms'/>
+                  <send id='cancelled' event='cancelled' delay='1ms'/>
+                  <send event='immediate' target='#_internal'/>
+                  <send event='immediate' target='#_internal'/>
+                  <cancel sendid='cancelled'/>
+                </onentry>
+                <transition event='immediate'>
+                  <assign location='immediate' expr='immediate + 1'/>
+                </transition>
+                <transition event='external'>
+                  <assign location='external' expr='external + 1'/>
+                </transition>
+                <transition event='delayed' cond='rounds &lt; 300' target='Loop'/>
+                <transition event='delayed' target='Drain'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Drain'>
+                <onentry>
+                  <send event='done' delay='50ms'/>
+                </onentry>
+                <transition event='external'>
+                  <assign location='external' expr='external + 1'/>
+                </transition>
+                <transition event='done' cond='immediate == 600 &amp;&amp; external == 300' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "mixed_delayed_and_immediate_sends",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            20000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn cancel_removes_delayed_send() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <send id='foo' event='delayed' delay='2s'/>
+                  <send id='foo' event='delayed' delay='2s'/>
+                  <send event='timeout' delay='2.5s'/>
+                  <cancel sendid='foo'/>
+                  <!-- Unknown send-ids shall be ignored -->
+                  <cancel sendid='unknown'/>
+                </onentry>
+                <transition event='timeout' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "cancel_removes_delayed_send",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            5000u64,
+            &vec!["pass".to_string()],
+        ));
     }
 }
 
-/// a duration.
-/// RegExp: "\\d*(\\.\\d+)?(ms|s|m|h|d))").
-pub fn parse_duration_to_milliseconds(d: &str) -> i64 {
-    if d.is_empty() {
-        0
-    } else {
-        let mut exp = ExpressionLexer::new(d.to_string());
-        let value_result = exp.next_number();
-        if value_result.is_err() {
-            return -1;
+/// Error of [parse_duration_to_milliseconds].
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The value is not a duration.
+    Invalid(String),
+    /// The value is a duration, but negative.
+    Negative(String),
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Invalid(value) => write!(f, "Illegal duration '{}'", value),
+            ParseError::Negative(value) => write!(f, "Negative duration '{}'", value),
         }
-        let Ok(unit) = exp.next_name() else {
-            return 0;
-        };
+    }
+}
 
-        let mut v = value_result.unwrap().as_double();
-        match unit.as_str() {
-            "D" | "d" => {
-                v *= 24.0 * 60.0 * 60.0 * 1000.0;
-            }
-            "H" | "h" => {
-                v *= 60.0 * 60.0 * 1000.0;
-            }
-            "M" | "m" => {
-                v *= 60000.0;
-            }
-            "S" | "s" => {
-                v *= 1000.0;
+const MS_PER_SECOND: f64 = 1000.0;
+const MS_PER_MINUTE: f64 = 60.0 * MS_PER_SECOND;
+const MS_PER_HOUR: f64 = 60.0 * MS_PER_MINUTE;
+const MS_PER_DAY: f64 = 24.0 * MS_PER_HOUR;
+
+/// Splits the leading decimal number ("\\d*(\\.\\d+)?") from the remaining text.
+fn split_decimal(d: &str) -> (&str, &str) {
+    let end = d
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(d.len());
+    d.split_at(end)
+}
+
+/// Parses the components of an ISO 8601 duration, e.g. "PT1.5S" or "P1DT2H".\
+/// Years, months and weeks are not supported as their length is not fixed.
+fn parse_iso_duration(d: &str) -> Option<f64> {
+    let rest = d.strip_prefix(['P', 'p'])?;
+    let (date, time) = match rest.split_once(['T', 't']) {
+        None => (rest, None),
+        Some((date, time)) => (date, Some(time)),
+    };
+    if time == Some("") {
+        return None;
+    }
+    let date_units: &[(char, f64)] = &[('D', MS_PER_DAY)];
+    let time_units: &[(char, f64)] = &[
+        ('H', MS_PER_HOUR),
+        ('M', MS_PER_MINUTE),
+        ('S', MS_PER_SECOND),
+    ];
+
+    let mut ms = 0.0;
+    let mut components = 0;
+    for (part, units) in [(date, date_units), (time.unwrap_or(""), time_units)] {
+        let mut remaining = part;
+        let mut unit_idx = 0;
+        while !remaining.is_empty() {
+            let (number, tail) = split_decimal(remaining);
+            let designator = tail.chars().next()?.to_ascii_uppercase();
+            // The designators have to be in order and must not repeat.
+            let idx = unit_idx
+                + units[unit_idx..]
+                    .iter()
+                    .position(|(u, _)| *u == designator)?;
+            ms += number.parse::<f64>().ok()? * units[idx].1;
+            unit_idx = idx + 1;
+            components += 1;
+            remaining = &tail[1..];
+        }
+    }
+    if components == 0 {
+        None
+    } else {
+        Some(ms)
+    }
+}
+
+/// Parses a duration to milliseconds.\
+/// Supported are CSS2 durations ("\\d*(\\.\\d+)?(ms|s|m|h|d)", case-insensitive), a plain "0"
+/// and ISO 8601 durations with days, hours, minutes and seconds, e.g. "PT1.5S".\
+/// An empty string is a duration of 0. Fractions of milliseconds are rounded.
+pub fn parse_duration_to_milliseconds(d: &str) -> Result<u64, ParseError> {
+    let d = d.trim();
+    if d.is_empty() {
+        return Ok(0);
+    }
+    let (negative, unsigned) = match d.strip_prefix('-') {
+        None => (false, d),
+        Some(unsigned) => (true, unsigned),
+    };
+    let ms = if unsigned.starts_with(['P', 'p']) {
+        parse_iso_duration(unsigned)
+    } else {
+        let (number, unit) = split_decimal(unsigned);
+        number.parse::<f64>().ok().and_then(|v| {
+            match unit.to_ascii_lowercase().as_str() {
+                "d" => Some(v * MS_PER_DAY),
+                "h" => Some(v * MS_PER_HOUR),
+                "m" => Some(v * MS_PER_MINUTE),
+                "s" => Some(v * MS_PER_SECOND),
+                "ms" => Some(v),
+                // Only zero may be given without unit.
+                "" if v == 0.0 => Some(0.0),
+                _ => None,
             }
-            "MS" | "ms" => {}
-            _ => {
-                return -1;
+        })
+    };
+    match ms {
+        Some(ms) if ms.is_finite() && ms <= u64::MAX as f64 => {
+            let ms = ms.round() as u64;
+            if negative && ms > 0 {
+                Err(ParseError::Negative(d.to_string()))
+            } else {
+                Ok(ms)
             }
         }
-        v.round() as i64
+        _ => Err(ParseError::Invalid(d.to_string())),
     }
 }
 
@@ -3,31 +3,51 @@
 use crate::actions::{Action, ActionWrapper};
 use log::error;
 use std::collections::HashMap;
+use std::fmt::Write;
 use std::ops::Deref;
 
 #[cfg(feature = "Debug")]
 use log::debug;
 
+#[cfg(feature = "json-config")]
+use crate::datamodel::json_to_data;
 use crate::datamodel::{
-    create_data_arc, data_to_string, str_to_source, Data, DataArc, Datamodel, DatamodelFactory, GlobalDataArc,
-    SourceCode, EVENT_VARIABLE_FIELD_DATA, EVENT_VARIABLE_FIELD_INVOKE_ID, EVENT_VARIABLE_FIELD_NAME,
-    EVENT_VARIABLE_FIELD_ORIGIN, EVENT_VARIABLE_FIELD_ORIGIN_TYPE, EVENT_VARIABLE_FIELD_SEND_ID,
-    EVENT_VARIABLE_FIELD_TYPE, EVENT_VARIABLE_NAME,
+    collect_conditions, collect_scripts, create_data_arc, data_to_string, numeric_to_integer, str_to_source, Data,
+    DataArc, Datamodel, DatamodelFactory, GlobalDataArc, SourceCode, EVENT_VARIABLE_FIELD_DATA,
+    EVENT_VARIABLE_FIELD_INVOKE_ID, EVENT_VARIABLE_FIELD_NAME, EVENT_VARIABLE_FIELD_ORIGIN,
+    EVENT_VARIABLE_FIELD_ORIGIN_TYPE, EVENT_VARIABLE_FIELD_SEND_ID, EVENT_VARIABLE_FIELD_TYPE, EVENT_VARIABLE_NAME,
 };
 use crate::event_io_processor::SYS_IO_PROCESSORS;
+use crate::executable_content::{execute_with_source_line, parse_duration_to_milliseconds};
 use crate::expression_engine::expressions::{
     Expression, ExpressionAssign, ExpressionAssignUndefined, ExpressionConstant,
 };
 use crate::expression_engine::parser::ExpressionParser;
 use crate::fsm::{Event, ExecutableContentId, Fsm, GlobalData, StateId};
+use crate::ArgOption;
 
 pub const RFSM_EXPRESSION_DATAMODEL: &str = "RFSM-EXPRESSION";
 pub const RFSM_EXPRESSION_DATAMODEL_LC: &str = "rfsm-expression";
 
+pub const RFSM_EXPRESSION_OPTION_INFIX: &str = "expression:";
+pub const RFSM_EXPRESSION_OPTION_LENIENT_POSTFIX: &str = "lenient";
+
+pub const RFSM_EXPRESSION_LENIENT_OPTION: &str = "datamodel:expression:lenient";
+
+/// Option to let \<assign\> create locations that were not declared.
+pub static RFSM_EXPRESSION_LENIENT_ARGUMENT: ArgOption = ArgOption {
+    name: RFSM_EXPRESSION_LENIENT_OPTION,
+    with_value: false,
+    required: false,
+};
+
 pub struct RFsmExpressionDatamodel {
     pub global_data: GlobalDataArc,
     null_data: DataArc,
     compilations: HashMap<usize, Box<dyn Expression>>,
+    /// If true, \<assign\> creates undeclared locations instead of raising "error.execution".\
+    /// Not W3C conform, but the behaviour of former versions.
+    pub lenient_assign: bool,
 }
 
 impl RFsmExpressionDatamodel {
@@ -36,6 +56,15 @@ impl RFsmExpressionDatamodel {
             global_data,
             null_data: create_data_arc(Data::Null()),
             compilations: HashMap::new(),
+            lenient_assign: false,
+        }
+    }
+
+    pub fn set_option(&mut self, name: &str, _value: &str) {
+        if name.strip_prefix(RFSM_EXPRESSION_OPTION_INFIX) == Some(RFSM_EXPRESSION_OPTION_LENIENT_POSTFIX) {
+            #[cfg(feature = "Debug")]
+            debug!("Running rFSM Expressions with lenient assign");
+            self.lenient_assign = true;
         }
     }
 
@@ -129,7 +158,12 @@ impl RFsmExpressionDatamodel {
                         if let Data::Null() = value.deref() {
                             Ok(val.clone())
                         } else if let Data::Error(err) = value.deref() {
-                            let msg = format!("Script Error: {} => {}", source, err);
+                            let msg = format!(
+                                "Script Error{}: {} => {}",
+                                self.global_data.lock().unwrap().source_line_info(),
+                                source,
+                                err
+                            );
                             error!("{}", msg);
                             if handle_error {
                                 self.internal_error_execution();
@@ -141,7 +175,12 @@ impl RFsmExpressionDatamodel {
                     }
                     Err(e) => {
                         // Pretty print the error
-                        let msg = format!("Script Error:  {} => {} ", source, e);
+                        let msg = format!(
+                            "Script Error{}:  {} => {} ",
+                            self.global_data.lock().unwrap().source_line_info(),
+                            source,
+                            e
+                        );
                         error!("{}", msg);
                         Err(msg)
                     }
@@ -165,6 +204,14 @@ impl RFsmExpressionDatamodel {
         actions.add_action("isDefined", Box::new(IsDefinedAction {}));
         actions.add_action("abs", Box::new(AbsAction {}));
         actions.add_action("toString", Box::new(ToStringAction {}));
+        actions.add_action("contains", Box::new(ContainsAction {}));
+        actions.add_action("substring", Box::new(SubstringAction {}));
+        actions.add_action("toUpperCase", Box::new(ToUpperCaseAction {}));
+        actions.add_action("toLowerCase", Box::new(ToLowerCaseAction {}));
+        actions.add_action("push", Box::new(PushAction {}));
+        actions.add_action("now_ms", Box::new(NowMsAction {}));
+        actions.add_action("format_time", Box::new(FormatTimeAction {}));
+        actions.add_action("parse_duration", Box::new(ParseDurationAction {}));
     }
 
     pub fn add_internal_fsm_functions(&mut self, fsm: &mut Fsm) {
@@ -185,8 +232,12 @@ impl RFsmExpressionDatamodel {
 pub struct RFsmExpressionDatamodelFactory {}
 
 impl DatamodelFactory for RFsmExpressionDatamodelFactory {
-    fn create(&mut self, global_data: GlobalDataArc, _options: &HashMap<String, String>) -> Box<dyn Datamodel> {
-        Box::new(RFsmExpressionDatamodel::new(global_data))
+    fn create(&mut self, global_data: GlobalDataArc, options: &HashMap<String, String>) -> Box<dyn Datamodel> {
+        let mut datamodel = Box::new(RFsmExpressionDatamodel::new(global_data));
+        for (key, value) in options {
+            datamodel.set_option(key.as_str(), value.as_str());
+        }
+        datamodel
     }
 }
 
@@ -197,6 +248,52 @@ fn option_to_data_value(val: &Option<String>) -> Data {
     }
 }
 
+/// Creates a deep copy of the data with all members read-only.\
+/// Used for "_event", so that assignments to fields of the event data fail and the data of the event is not
+/// modified.
+fn read_only_copy(data: &Data) -> DataArc {
+    let copy = match data {
+        Data::Map(map) => Data::Map(
+            map.iter()
+                .map(|(key, value)| (key.clone(), read_only_copy(value.lock().unwrap().deref())))
+                .collect(),
+        ),
+        Data::Array(array) => Data::Array(
+            array
+                .iter()
+                .map(|value| read_only_copy(value.lock().unwrap().deref()))
+                .collect(),
+        ),
+        other => other.clone(),
+    };
+    let mut arc = create_data_arc(copy);
+    arc.set_readonly(true);
+    arc
+}
+
+/// Parses string content that looks like a JSON object or array, e.g. received from outside.
+#[cfg(feature = "json-config")]
+fn parse_json_content(data: &Data) -> Option<Data> {
+    match data {
+        Data::String(content) => {
+            let content = content.trim();
+            if content.starts_with('{') || content.starts_with('[') {
+                serde_json::from_str::<serde_json::Value>(content)
+                    .ok()
+                    .map(|value| json_to_data(&value))
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+#[cfg(not(feature = "json-config"))]
+fn parse_json_content(_data: &Data) -> Option<Data> {
+    None
+}
+
 /// Action to implement the mandatory SCXML-Datamodel function "In".
 #[derive(Clone)]
 pub struct InAction {
@@ -267,14 +364,20 @@ impl Action for IndexOfAction {
         if arguments.len() == 2 {
             match (&arguments[0], &arguments[1]) {
                 (Data::String(s1), Data::String(s2)) => {
+                    // Index in characters, not in bytes.
                     let r = match s1.find(s2) {
                         None => -1,
-                        Some(idx) => idx as i64,
+                        Some(idx) => s1[..idx].chars().count() as i64,
                     };
                     #[cfg(feature = "Debug")]
                     debug!("indexOf({},{}) -> {}", s1, s2, r);
                     Ok(Data::Integer(r))
                 }
+                (Data::Array(a), value) => Ok(Data::Integer(
+                    a.iter()
+                        .position(|item| item.lock().unwrap().deref() == value)
+                        .map_or(-1, |idx| idx as i64),
+                )),
                 (_, _) => Err("Illegal argument types for 'indexOf'".to_string()),
             }
         } else {
@@ -293,7 +396,7 @@ impl Action for LengthAction {
     fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
         if arguments.len() == 1 {
             let r = match &arguments[0] {
-                Data::String(s) => s.len(),
+                Data::String(s) => s.chars().count(),
                 Data::Array(a) => a.len(),
                 Data::Map(m) => m.len(),
                 Data::Source(s) => s.len(),
@@ -351,6 +454,224 @@ impl Action for IsDefinedAction {
     }
 }
 
+/// Implements "contains(haystack, needle)".\
+/// Checks for a sub-string in a string, for an item in an array or for a key in a map.
+#[derive(Clone)]
+pub struct ContainsAction {}
+impl Action for ContainsAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 2 {
+            match (&arguments[0], &arguments[1]) {
+                (Data::String(s1), Data::String(s2)) => Ok(Data::Boolean(s1.contains(s2.as_str()))),
+                (Data::Array(a), value) => Ok(Data::Boolean(
+                    a.iter().any(|item| item.lock().unwrap().deref() == value),
+                )),
+                (Data::Map(m), Data::String(key)) => Ok(Data::Boolean(m.contains_key(key))),
+                (_, _) => Err("Illegal argument types for 'contains'".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'contains'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+/// Implements "substring(s, start \[, end\])" like ECMAScript: indices are in characters,
+/// negative indices are handled as 0, indices beyond the end as the length and
+/// if start is greater than end, both are swapped.
+#[derive(Clone)]
+pub struct SubstringAction {}
+impl Action for SubstringAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 2 || arguments.len() == 3 {
+            match &arguments[0] {
+                Data::String(s) => {
+                    let length = s.chars().count() as i64;
+                    let mut indices = Vec::with_capacity(2);
+                    for arg in &arguments[1..] {
+                        match numeric_to_integer(arg) {
+                            None => {
+                                return Err("Illegal index for 'substring'".to_string());
+                            }
+                            Some(idx) => {
+                                indices.push(idx.clamp(0, length) as usize);
+                            }
+                        }
+                    }
+                    let start = indices[0];
+                    let end = indices.get(1).copied().unwrap_or(length as usize);
+                    let (start, end) = if start > end {
+                        (end, start)
+                    } else {
+                        (start, end)
+                    };
+                    Ok(Data::String(
+                        s.chars().skip(start).take(end - start).collect(),
+                    ))
+                }
+                _ => Err("Wrong argument type for 'substring'.".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'substring'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct ToUpperCaseAction {}
+impl Action for ToUpperCaseAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 1 {
+            match &arguments[0] {
+                Data::String(s) => Ok(Data::String(s.to_uppercase())),
+                _ => Err("Wrong argument type for 'toUpperCase'.".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'toUpperCase'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+#[derive(Clone)]
+pub struct ToLowerCaseAction {}
+impl Action for ToLowerCaseAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 1 {
+            match &arguments[0] {
+                Data::String(s) => Ok(Data::String(s.to_lowercase())),
+                _ => Err("Wrong argument type for 'toLowerCase'.".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'toLowerCase'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+/// Implements "push(array, value...)".\
+/// As actions get copies of their arguments, the array is not modified. Instead, a new array
+/// with the values appended is returned, that can be assigned, e.g. "list = list.push(1)".
+#[derive(Clone)]
+pub struct PushAction {}
+impl Action for PushAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() >= 2 {
+            match &arguments[0] {
+                Data::Array(a) => {
+                    let mut r = a.clone();
+                    for value in &arguments[1..] {
+                        if let Data::Error(err) = value {
+                            return Err(err.clone());
+                        }
+                        r.push(create_data_arc(value.clone()));
+                    }
+                    Ok(Data::Array(r))
+                }
+                _ => Err("Wrong argument type for 'push'.".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'push'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+/// Returns the current time of the session clock as milliseconds since the UNIX epoch.\
+/// Usage: "now_ms()"
+#[derive(Clone)]
+pub struct NowMsAction {}
+
+impl Action for NowMsAction {
+    fn execute(&self, arguments: &[Data], global: &GlobalData) -> Result<Data, String> {
+        if arguments.is_empty() {
+            Ok(Data::Integer(match &global.clock {
+                Some(clock) => clock.now_ms(),
+                None => chrono::Utc::now().timestamp_millis(),
+            }))
+        } else {
+            Err("Wrong number of arguments for 'now_ms'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+/// Formats a timestamp (milliseconds since the UNIX epoch) as UTC time.\
+/// The pattern uses the "strftime" syntax of chrono, e.g. "%Y-%m-%d %H:%M:%S%.3f".\
+/// Usage: "format_time(now_ms(), '%H:%M')"
+#[derive(Clone)]
+pub struct FormatTimeAction {}
+
+impl Action for FormatTimeAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 2 {
+            let time = chrono::DateTime::from_timestamp_millis(
+                numeric_to_integer(&arguments[0]).ok_or("Wrong argument type for 'format_time'.".to_string())?,
+            )
+            .ok_or("Timestamp out of range for 'format_time'.".to_string())?;
+            let pattern = match &arguments[1] {
+                Data::String(pattern) => pattern,
+                _ => return Err("Wrong argument type for 'format_time'.".to_string()),
+            };
+            let mut r = String::new();
+            match write!(r, "{}", time.format(pattern)) {
+                Ok(_) => Ok(Data::String(r)),
+                Err(_) => Err(format!("Illegal pattern '{}' for 'format_time'.", pattern)),
+            }
+        } else {
+            Err("Wrong number of arguments for 'format_time'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
+/// Parses a duration in the format of the "delay" attribute of \<send\> to milliseconds.\
+/// Usage: "parse_duration('1.5s')"
+#[derive(Clone)]
+pub struct ParseDurationAction {}
+
+impl Action for ParseDurationAction {
+    fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+        if arguments.len() == 1 {
+            match &arguments[0] {
+                Data::String(duration) => match parse_duration_to_milliseconds(duration) {
+                    Err(err) => Err(format!("{} for 'parse_duration'.", err)),
+                    Ok(ms) => Ok(Data::Integer(ms as i64)),
+                },
+                _ => Err("Wrong argument type for 'parse_duration'.".to_string()),
+            }
+        } else {
+            Err("Wrong number of arguments for 'parse_duration'.".to_string())
+        }
+    }
+
+    fn get_copy(&self) -> Box<dyn Action> {
+        Box::new(self.clone())
+    }
+}
+
 impl Datamodel for RFsmExpressionDatamodel {
     fn global(&mut self) -> &mut GlobalDataArc {
         &mut self.global_data
@@ -367,16 +688,30 @@ impl Datamodel for RFsmExpressionDatamodel {
         self.add_internal_fsm_functions(fsm);
     }
 
+    fn precompile(&mut self, fsm: &Fsm) {
+        self.compilations.clear();
+        for source in collect_conditions(fsm)
+            .into_iter()
+            .chain(collect_scripts(fsm))
+        {
+            // Failed compilations are not cached and fail again on execution.
+            let _ = self.compile(source);
+        }
+    }
+
     fn set_ioprocessors(&mut self) {
         let session_id = self.global_s().lock().unwrap().session_id;
         let mut io_processors_dings = HashMap::new();
         for (name, processor) in &self.global_data.lock().unwrap().io_processors {
             let mut processor_data = HashMap::new();
-            let location = create_data_arc(Data::String(
+            let mut location = create_data_arc(Data::String(
                 processor.lock().unwrap().get_location(session_id),
             ));
+            location.set_readonly(true);
             processor_data.insert("location".to_string(), location);
-            io_processors_dings.insert(name.clone(), create_data_arc(Data::Map(processor_data)));
+            let mut processor_arc = create_data_arc(Data::Map(processor_data));
+            processor_arc.set_readonly(true);
+            io_processors_dings.insert(name.clone(), processor_arc);
         }
         let mut data_arc = create_data_arc(Data::Map(io_processors_dings));
         data_arc.set_readonly(true);
@@ -451,7 +786,10 @@ impl Datamodel for RFsmExpressionDatamodel {
                     let cd_guard = c.lock().unwrap();
                     let cd = cd_guard.deref();
                     match self.resolve_source_data(cd) {
-                        Ok(val) => val,
+                        Ok(val) => {
+                            let parsed = parse_json_content(val.lock().unwrap().deref());
+                            parsed.map(create_data_arc).unwrap_or(val)
+                        }
                         Err(err) => {
                             error!("Can't eval event content '{}': {}", cd, err);
                             self.null_data.clone()
@@ -508,15 +846,23 @@ impl Datamodel for RFsmExpressionDatamodel {
 
         let mut ds = self.global_data.lock().unwrap();
         let event_name = EVENT_VARIABLE_NAME.to_string();
-        // READONLY
-        let mut event_arc = create_data_arc(Data::Map(event_props));
-        event_arc.set_readonly(true);
+        // READONLY, including all fields and the members of the event data.
+        let event_arc = read_only_copy(&Data::Map(event_props));
         ds.data.map.remove(&event_name);
         ds.data.set_undefined_arc(event_name, event_arc);
     }
 
     fn assign(&mut self, left_expr: &Data, right_expr: &Data) -> bool {
-        self.assign_internal(left_expr, right_expr, false)
+        // W3C says:\
+        // If the location expression does not denote a valid location in the data model [...]
+        // the SCXML Processor must place the error 'error.execution' in the internal event queue.\
+        // Locations are created only by \<data\> and \<foreach\>, except in lenient mode.
+        self.assign_internal(left_expr, right_expr, self.lenient_assign)
+    }
+
+    fn evaluate_structured_content(&mut self, content: &str) -> Result<DataArc, String> {
+        // "execute" doesn't accept maps and arrays as result.
+        self.execute_internal(&str_to_source(format!("({})", content).as_str()), false)
     }
 
     fn get_by_location(&mut self, location: &str) -> Result<DataArc, String> {
@@ -654,7 +1000,7 @@ impl Datamodel for RFsmExpressionDatamodel {
     fn executeContent(&mut self, fsm: &Fsm, content_id: ExecutableContentId) -> bool {
         let ec = fsm.executableContent.get(&content_id);
         for e in ec.unwrap().iter() {
-            if !e.execute(self, fsm) {
+            if !execute_with_source_line(e.as_ref(), self, fsm) {
                 return false;
             }
         }
@@ -669,6 +1015,13 @@ mod tests {
     use crate::expression_engine::expressions::ExpressionResult;
     use crate::expression_engine::parser::ExpressionParser;
     use crate::init_logging;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "xml")]
+    use crate::test::run_test_manual;
+    #[cfg(feature = "Trace")]
+    #[cfg(feature = "xml")]
+    use crate::tracer::TraceMode;
     use std::collections::HashMap;
 
     #[test]
@@ -738,6 +1091,128 @@ mod tests {
         println!("{:?}", rs);
     }
 
+    #[test]
+    fn contains_works() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+
+        for (source, expected) in [
+            ("contains('abcdef', 'cd')", true),
+            ("'abcdef'.contains('x')", false),
+            ("'abc'.contains('')", true),
+            ("[1,'a',true].contains('a')", true),
+            ("[1,2,3].contains(4)", false),
+            ("[].contains(1)", false),
+            ("{'a':1}.contains('a')", true),
+            ("{'a':1}.contains('b')", false),
+        ] {
+            let rs = ExpressionParser::execute_str(source, &mut gd.lock().unwrap());
+            assert_eq!(
+                rs,
+                Ok(create_data_arc(Data::Boolean(expected))),
+                "{}",
+                source
+            );
+        }
+        assert!(ExpressionParser::execute_str("contains(1, 1)", &mut gd.lock().unwrap()).is_err());
+        assert!(ExpressionParser::execute_str("contains('a')", &mut gd.lock().unwrap()).is_err());
+    }
+
+    #[test]
+    fn index_of_works_on_arrays_and_characters() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+
+        for (source, expected) in [
+            ("[1,2,3].indexOf(3)", 2),
+            ("['a','b'].indexOf('c')", -1),
+            ("[].indexOf(1)", -1),
+            ("'\u{e4}bc'.indexOf('c')", 2),
+            ("'\u{e4}bc'.length()", 3),
+        ] {
+            let rs = ExpressionParser::execute_str(source, &mut gd.lock().unwrap());
+            assert_eq!(
+                rs,
+                Ok(create_data_arc(Data::Integer(expected))),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn substring_works() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+
+        for (source, expected) in [
+            ("substring('abcdef', 1, 3)", "bc"),
+            ("'abcdef'.substring(2)", "cdef"),
+            // Negative indices are handled as 0.
+            ("'abcdef'.substring(-2, 2)", "ab"),
+            // Indices beyond the end are handled as the length.
+            ("'abcdef'.substring(4, 100)", "ef"),
+            // start > end are swapped.
+            ("'abcdef'.substring(3, 1)", "bc"),
+            ("'abcdef'.substring(3, 3)", ""),
+            ("''.substring(0, 1)", ""),
+            ("'\u{e4}bc'.substring(0, 2)", "\u{e4}b"),
+        ] {
+            let rs = ExpressionParser::execute_str(source, &mut gd.lock().unwrap());
+            assert_eq!(
+                rs,
+                Ok(create_data_arc(Data::String(expected.to_string()))),
+                "{}",
+                source
+            );
+        }
+        assert!(ExpressionParser::execute_str("substring('abc', 'x')", &mut gd.lock().unwrap()).is_err());
+        assert!(ExpressionParser::execute_str("substring([1], 0)", &mut gd.lock().unwrap()).is_err());
+        assert!(ExpressionParser::execute_str("substring('abc')", &mut gd.lock().unwrap()).is_err());
+    }
+
+    #[test]
+    fn case_conversion_works() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+
+        let rs = ExpressionParser::execute_str("'aBc'.toUpperCase()", &mut gd.lock().unwrap());
+        assert_eq!(rs, Ok(create_data_arc(Data::String("ABC".to_string()))));
+        let rs = ExpressionParser::execute_str("toLowerCase('aBc')", &mut gd.lock().unwrap());
+        assert_eq!(rs, Ok(create_data_arc(Data::String("abc".to_string()))));
+        let rs = ExpressionParser::execute_str("''.toUpperCase()", &mut gd.lock().unwrap());
+        assert_eq!(rs, Ok(create_data_arc(Data::String("".to_string()))));
+        assert!(ExpressionParser::execute_str("toUpperCase(1)", &mut gd.lock().unwrap()).is_err());
+    }
+
+    #[test]
+    fn push_works() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+
+        let rs = ExpressionParser::execute_str("[].push(1)", &mut gd.lock().unwrap());
+        assert_eq!(
+            rs,
+            Ok(create_data_arc(Data::Array(vec![create_data_arc(
+                Data::Integer(1)
+            )])))
+        );
+
+        let rs = ExpressionParser::execute_str(
+            "list ?= [1]; list = list.push(2, 'a'); list.length()",
+            &mut gd.lock().unwrap(),
+        );
+        assert_eq!(rs, Ok(create_data_arc(Data::Integer(3))));
+
+        assert!(ExpressionParser::execute_str("push('a', 1)", &mut gd.lock().unwrap()).is_err());
+        assert!(ExpressionParser::execute_str("push([])", &mut gd.lock().unwrap()).is_err());
+    }
+
     #[test]
     fn abs_of_works() {
         init_logging();
@@ -776,4 +1251,551 @@ mod tests {
         let rs = ExpressionParser::execute("'abcdef'.toString()".to_string(), &mut gd.lock().unwrap());
         assert_eq!(rs, Ok(create_data_arc(Data::String("abcdef".to_string()))));
     }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn in_function() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <state id='Main'>
+                <onentry>
+                   <if cond='In("Main")'>
+                      <raise event='MainIsIn'/>
+                   </if>
+                </onentry>
+                <transition event="MainIsIn" cond='In("Unknown")' target="fail"/>
+                <transition event="MainIsIn" cond='In("Main")' target="pass_1"/>
+                <transition event="*" target="fail"/>
+              </state>
+              <state id='pass_1'>
+                <onentry>
+                   <if cond='In("Main")'>
+                      <log expr='"Still in main?"'/>
+                   <elseif cond='!In("Main")'/>
+                      <raise event='MainIsNotIn'/>
+                   </if>
+                </onentry>
+                <transition event="MainIsNotIn" target="pass"/>
+                <transition event="*" target="fail"/>
+              </state>
+              <final id='pass'>
+                <onentry>
+                  <log label='Outcome' expr='"pass"'/>
+                </onentry>
+              </final>
+              <final id="fail">
+                <onentry>
+                  <log label="Outcome" expr="'fail'"/>
+                </onentry>
+              </final>
+            </scxml>"##
+                .to_string(),
+        );
+
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "In_function",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn session_variables_are_read_only() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression' name='parentMachine'>
+              <datamodel>
+                <data id='sessionId'/>
+                <data id='name'/>
+              </datamodel>
+              <script>
+                sessionId = _sessionid;
+                name = _name
+              </script>
+              <state id='Main'>
+                <transition cond="sessionId == _sessionid &amp;&amp; name == 'parentMachine'"
+                            target='AssignId'/>
+                <transition target='fail'/>
+              </state>
+              <state id='AssignId'>
+                <onentry>
+                  <assign location='_sessionid' expr="'other'"/>
+                </onentry>
+                <transition event='error.execution' cond='_sessionid == sessionId' target='AssignName'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='AssignName'>
+                <onentry>
+                  <assign location='_name' expr="'other'"/>
+                </onentry>
+                <transition event='error.execution' cond="_name == 'parentMachine'" target='Child'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Child'>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='ChildMain' datamodel='rfsm-expression' name='childMachine'>
+                      <final id='ChildMain'>
+                        <onentry>
+                          <send target='#_parent' event='childIds'>
+                            <param name='sessionId' expr='_sessionid'/>
+                            <param name='name' expr='_name'/>
+                          </send>
+                        </onentry>
+                      </final>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='childIds'
+                            cond="_event.data.name == 'childMachine' &amp;&amp; _event.data.sessionId != _sessionid"
+                            target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "session_variables_are_read_only",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn send_to_ioprocessor_location() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <state id='Main'>
+                <onentry>
+                  <send event='toSelf' targetexpr="_ioprocessors['scxml'].location"/>
+                  <send event='toSelfLong'
+                        targetexpr="_ioprocessors['http://www.w3.org/TR/scxml/#SCXMLEventProcessor'].location"/>
+                  <assign location="_ioprocessors['scxml'].location" expr="'#_parent'"/>
+                </onentry>
+                <transition event='error.execution' target='Receive'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Receive'>
+                <transition event='toSelf' target='ReceiveLong'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='ReceiveLong'>
+                <transition event='toSelfLong' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "send_to_ioprocessor_location",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    #[cfg(feature = "json-config")]
+    fn event_fields_are_readable_and_read_only() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <state id='Main'>
+                <onentry>
+                  <send id='s1' event='ev'><content>{"outer": {"inner": {"value": 3}}}</content></send>
+                </onentry>
+                <transition event='ev'
+                            cond="_event.name == 'ev' &amp;&amp; _event.type == 'external' &amp;&amp; _event.sendid == 's1'
+                                  &amp;&amp; _event.origin == _ioprocessors['scxml'].location
+                                  &amp;&amp; _event.origintype == 'http://www.w3.org/TR/scxml/#SCXMLEventProcessor'
+                                  &amp;&amp; _event.invokeid == null &amp;&amp; _event.data.outer.inner.value == 3"
+                            target='Assign'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Assign'>
+                <onentry>
+                  <assign location='_event.data.outer.inner.value' expr='4'/>
+                </onentry>
+                <transition event='error.execution' target='Json'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Json'>
+                <onentry>
+                  <send event='json'><content expr="'{&quot;a&quot;: {&quot;b&quot;: 7}}'"/></send>
+                  <raise event='internal'/>
+                </onentry>
+                <transition event='internal' cond="_event.type == 'internal' &amp;&amp; _event.sendid == null" target='Json2'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Json2'>
+                <transition event='json' cond='_event.data.a.b == 7' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "event_fields_are_readable_and_read_only",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn conditional_and_short_circuit_in_conditions() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <datamodel>
+                <data id='x' expr='null'/>
+                <data id='level' expr='2'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry><raise event='check'/></onentry>
+                <transition event='check' cond='x != null &amp;&amp; x.field == 1' target='fail'/>
+                <transition event='check' cond='x == null || x.field == 1' target='Level'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Level'>
+                <onentry>
+                  <assign location='x' expr="level > 1 ? (level > 2 ? 'high' : 'medium') : 'low'"/>
+                  <raise event='check'/>
+                </onentry>
+                <transition event='check' cond="x == 'medium' ? true : unknown" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "conditional_and_short_circuit_in_conditions",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn data_src_is_loaded_at_initialization() {
+        init_logging();
+        let dir = std::env::temp_dir().join(format!("rfsm_data_src_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("data")).unwrap();
+        std::fs::write(
+            dir.join("data").join("config.json"),
+            r#"{ "limit": 3, "name": "rfsm", "modes": ["fast", "slow"] }"#,
+        )
+        .unwrap();
+        std::fs::write(dir.join("data").join("greeting.txt"), "hello").unwrap();
+        let document = dir.join("main.scxml");
+        std::fs::write(
+            &document,
+            r##"<scxml initial='Main' datamodel='rfsm-expression' binding='late'>
+              <state id='Main'>
+                <datamodel>
+                  <data id='greeting' src='data/greeting.txt'/>
+                  <data id='missing' src='data/missing.json'/>
+                </datamodel>
+                <transition event='error.execution' target='Loaded'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Loaded'>
+                <datamodel>
+                  <data id='config' src='data/config.json'/>
+                </datamodel>
+                <onentry><raise event='check'/></onentry>
+                <transition event='check'
+                  cond="config.limit == 3 &amp;&amp; config.name == 'rfsm' &amp;&amp; greeting == 'hello'"
+                  target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+        )
+        .unwrap();
+
+        let sm = scxml_reader::parse_from_xml_file(&document, &[]);
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        // The data is fetched when the states are entered, the files need to exist until then.
+        let result = run_test_manual(
+            "data_src_is_loaded_at_initialization",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(result);
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn array_methods_in_conditions() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <datamodel><data id='list' expr='[]'/></datamodel>
+              <state id='Main'>
+                <onentry>
+                  <assign location='list' expr="list.push('a', 'b')"/>
+                  <raise event='values'/>
+                </onentry>
+                <transition event='values' cond="list.contains('c')" target='fail'/>
+                <transition event='values' cond="list.contains('b') &amp;&amp; list.length() == 2" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "array_methods_in_conditions",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    fn assign_with_content() {
+        init_logging();
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <datamodel><data id='cfg'/><data id='text'/></datamodel>
+              <state id='Main'>
+                <onentry>
+                  <assign location='cfg'> {"a": 1, "b": [1,2], "c": {"d": "x"}} </assign>
+                  <assign location='text'>some text</assign>
+                  <raise event='check'/>
+                </onentry>
+                <transition event='check'
+                  cond="cfg.a == 1 &amp;&amp; cfg.b.length() == 2 &amp;&amp; cfg.b.contains(2) &amp;&amp; cfg.c.d == 'x' &amp;&amp; text == 'some text'"
+                  target='Errors'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Errors'>
+                <onentry>
+                  <assign location='cfg' expr='2'>{"a": 2}</assign>
+                  <raise event='next'/>
+                </onentry>
+                <transition event='error.execution' target='Neither'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Neither'>
+                <onentry>
+                  <assign location='cfg'/>
+                  <raise event='next'/>
+                </onentry>
+                <transition event='error.execution' cond='cfg.a == 1' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual(
+            "assign_with_content",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn assign_to_undeclared_location() {
+        use crate::actions::ActionWrapper;
+        use crate::fsm::{start_fsm_with_options, FinishMode};
+        use crate::fsm_executor::FsmExecutor;
+        use std::time::Duration;
+
+        // By default the assignment fails and the datamodel is not changed.
+        // <data> and <foreach> still create their variables.
+        // The error aborts the block, so the check is raised in a separate block.
+        let xml = r##"<scxml initial='Main' datamodel='rfsm-expression'>
+              <datamodel>
+                <data id='items' expr='[1,2]'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <foreach array='items' item='item' index='idx'/>
+                  <assign location='undeclared' expr='1'/>
+                </onentry>
+                <onentry>
+                  <raise event='check'/>
+                </onentry>
+                <transition event='error.execution' target='Failed'/>
+                <transition event='check' cond='undeclared == 1 &amp;&amp; item == 2' target='lenient'/>
+                <transition event='check' target='fail'/>
+              </state>
+              <state id='Failed'>
+                <transition event='check' cond='undeclared == 1' target='fail'/>
+                <transition event='check' cond='item == 2 &amp;&amp; idx == 1' target='strict'/>
+                <transition event='check' target='fail'/>
+              </state>
+              <final id='strict'/>
+              <final id='lenient'/>
+              <final id='fail'/>
+            </scxml>"##;
+
+        let executor = FsmExecutor::new_without_io_processor();
+        for (options, expected) in [
+            (HashMap::new(), "strict"),
+            (
+                HashMap::from([("expression:lenient".to_string(), "".to_string())]),
+                "lenient",
+            ),
+        ] {
+            let session = start_fsm_with_options(
+                scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                &options,
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(2000));
+            assert!(result.is_some(), "Session shall finish");
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec![expected.to_string()])
+            );
+        }
+    }
+
+    #[test]
+    fn time_functions_work() {
+        init_logging();
+        let gd = create_global_data_arc();
+        RFsmExpressionDatamodel::add_internal_functions_to_wrapper(&mut gd.lock().unwrap().actions);
+        let mut guard = gd.lock().unwrap();
+
+        let now = ExpressionParser::execute_str("now_ms()", &mut guard).unwrap();
+        assert!(matches!(*now.lock().unwrap(), Data::Integer(ms) if ms > 1_600_000_000_000));
+
+        guard
+            .data
+            .set_undefined("start".to_string(), Data::Integer(0));
+        assert_eq!(
+            ExpressionParser::execute_str("now_ms() - start > 500", &mut guard),
+            Ok(create_data_arc(Data::Boolean(true)))
+        );
+        assert_eq!(
+            ExpressionParser::execute_str("now_ms() - now_ms() < 500.5", &mut guard),
+            Ok(create_data_arc(Data::Boolean(true)))
+        );
+        // Integers beyond the precision of f64 are compared exactly.
+        assert_eq!(
+            ExpressionParser::execute_str("9007199254740993 > 9007199254740992", &mut guard),
+            Ok(create_data_arc(Data::Boolean(true)))
+        );
+        assert_eq!(
+            ExpressionParser::execute_str("2 >= 1.5", &mut guard),
+            Ok(create_data_arc(Data::Boolean(true)))
+        );
+
+        assert_eq!(
+            ExpressionParser::execute_str("format_time(0, '%Y-%m-%d %H:%M:%S')", &mut guard),
+            Ok(create_data_arc(Data::String(
+                "1970-01-01 00:00:00".to_string()
+            )))
+        );
+        assert_eq!(
+            ExpressionParser::execute_str("format_time(1500.0, '%S%.3f')", &mut guard),
+            Ok(create_data_arc(Data::String("01.500".to_string())))
+        );
+        assert_eq!(
+            ExpressionParser::execute_str("format_time(-1000, '%H:%M:%S')", &mut guard),
+            Ok(create_data_arc(Data::String("23:59:59".to_string())))
+        );
+        assert!(ExpressionParser::execute_str("format_time(0, '%Q')", &mut guard).is_err());
+        assert!(ExpressionParser::execute_str("format_time(9223372036854775807, '%Y')", &mut guard).is_err());
+        assert!(ExpressionParser::execute_str("format_time('x', '%Y')", &mut guard).is_err());
+        assert!(ExpressionParser::execute_str("format_time(0)", &mut guard).is_err());
+
+        assert_eq!(
+            ExpressionParser::execute_str("parse_duration('1.5s')", &mut guard),
+            Ok(create_data_arc(Data::Integer(1500)))
+        );
+        assert_eq!(
+            ExpressionParser::execute_str("parse_duration('2m') + parse_duration('10ms')", &mut guard),
+            Ok(create_data_arc(Data::Integer(120010)))
+        );
+        assert!(ExpressionParser::execute_str("parse_duration('5x')", &mut guard).is_err());
+        assert!(ExpressionParser::execute_str("parse_duration(5)", &mut guard).is_err());
+    }
 }
@@ -485,6 +485,63 @@ impl Expression for ExpressionAssignUndefined {
     }
 }
 
+/// Compound assignment like "a.b += 1".\
+/// The location is evaluated only once, the operation is applied in place.
+#[derive(Debug)]
+pub struct ExpressionCompoundAssign {
+    /// The operation, e.g. [Operator::Plus] for "+=".
+    pub operator: Operator,
+    pub left: Box<dyn Expression>,
+    pub right: Box<dyn Expression>,
+}
+
+impl ExpressionCompoundAssign {
+    pub fn new(operator: Operator, left: Box<dyn Expression>, right: Box<dyn Expression>) -> ExpressionCompoundAssign {
+        ExpressionCompoundAssign {
+            operator,
+            left,
+            right,
+        }
+    }
+}
+
+impl Expression for ExpressionCompoundAssign {
+    fn execute(&self, context: &mut GlobalDataLock, _allow_undefined: bool) -> ExpressionResult {
+        if !self.left.is_assignable() {
+            return Err("Can't assign to that".to_string());
+        }
+        let right_arc = self.right.execute(context, false)?;
+        // The location needs to exist, there is no value to apply the operation to otherwise.
+        let left_arc = self.left.execute(context, false)?;
+        if left_arc.is_readonly() {
+            return Err(format!("Can't set read-only {left_arc}"));
+        }
+        let right_data = right_arc.lock().unwrap().clone();
+        let mut left_guard = left_arc.lock().unwrap();
+        match ExpressionOperator::operation(left_guard.deref(), &self.operator, &right_data) {
+            Data::Error(err) => Err(err),
+            Data::None() => Err(format!("Can't assign from '{}'", Data::None())),
+            result => {
+                *left_guard = result;
+                drop(left_guard);
+                Ok(left_arc)
+            }
+        }
+    }
+
+    fn is_assignable(&self) -> bool {
+        false
+    }
+
+    fn get_copy(&self) -> Box<dyn Expression> {
+        Box::new(ExpressionCompoundAssign::new(
+            self.operator.clone(),
+            self.left.get_copy(),
+            self.right.get_copy(),
+        ))
+    }
+}
+
 #[derive(Debug)]
 pub struct ExpressionOperator {
     pub operator: Operator,
@@ -516,7 +573,14 @@ impl ExpressionOperator {
             Operator::Equal => operation_equal(left, right),
             Operator::NotEqual => operation_not_equal(left, right),
             Operator::Modulus => operation_modulus(left, right),
-            Operator::Assign | Operator::AssignUndefined | Operator::Not => {
+            Operator::Assign
+            | Operator::AssignUndefined
+            | Operator::PlusAssign
+            | Operator::MinusAssign
+            | Operator::MultiplyAssign
+            | Operator::DivideAssign
+            | Operator::Not
+            | Operator::Conditional => {
                 // These "operation" are handled by explicit Expression-implementations
                 // and this line should never be reached.
                 Data::Error("Internal Error".to_string())
@@ -538,6 +602,14 @@ impl Expression for ExpressionOperator {
             }
             Ok(val) => val.clone(),
         };
+        // Short-circuit evaluation: the right side is only evaluated if needed.
+        if let Data::Boolean(left_value) = left_result.lock().unwrap().deref() {
+            match self.operator {
+                Operator::And if !*left_value => return Ok(create_data_arc(Data::Boolean(false))),
+                Operator::Or if *left_value => return Ok(create_data_arc(Data::Boolean(true))),
+                _ => {}
+            }
+        }
         let right_result = match self.right.execute(context, allow_undefined) {
             Err(err) => {
                 return Err(err);
@@ -580,6 +652,62 @@ impl Expression for ExpressionOperator {
     }
 }
 
+/// The conditional operator "condition ? a : b".\
+/// Only the selected side is evaluated.
+#[derive(Debug)]
+pub struct ExpressionConditional {
+    pub condition: Box<dyn Expression>,
+    pub if_true: Box<dyn Expression>,
+    pub if_false: Box<dyn Expression>,
+}
+
+impl ExpressionConditional {
+    pub fn new(
+        condition: Box<dyn Expression>,
+        if_true: Box<dyn Expression>,
+        if_false: Box<dyn Expression>,
+    ) -> ExpressionConditional {
+        ExpressionConditional {
+            condition,
+            if_true,
+            if_false,
+        }
+    }
+}
+
+impl Expression for ExpressionConditional {
+    fn execute(&self, context: &mut GlobalDataLock, allow_undefined: bool) -> ExpressionResult {
+        let condition = match self.condition.execute(context, allow_undefined) {
+            Err(err) => {
+                return Err(err);
+            }
+            Ok(val) => match val.lock().unwrap().deref() {
+                Data::Boolean(bool_val) => *bool_val,
+                _ => {
+                    return Err("'?' can only be applied on boolean expressions.".to_string());
+                }
+            },
+        };
+        if condition {
+            self.if_true.execute(context, allow_undefined)
+        } else {
+            self.if_false.execute(context, allow_undefined)
+        }
+    }
+
+    fn is_assignable(&self) -> bool {
+        false
+    }
+
+    fn get_copy(&self) -> Box<dyn Expression> {
+        Box::new(ExpressionConditional::new(
+            self.condition.get_copy(),
+            self.if_true.get_copy(),
+            self.if_false.get_copy(),
+        ))
+    }
+}
+
 #[derive(Debug)]
 pub struct ExpressionNot {
     pub right: Box<dyn Expression>,
@@ -654,6 +782,7 @@ mod tests {
     use crate::expression_engine::parser::ExpressionParser;
     use crate::init_logging;
     use std::collections::HashMap;
+    use std::ops::Deref;
 
     #[test]
     fn can_assign_members() {
@@ -814,6 +943,164 @@ mod tests {
         assert_eq!(rs, data_true);
     }
 
+    #[test]
+    fn comparisons_follow_coercion_rules() {
+        let ec = RFsmExpressionDatamodel::new(create_global_data_arc());
+        let context = &mut ec.global_data.lock().unwrap();
+        let _ = ExpressionParser::execute_str("x ?= 1.0; big ?= 9007199254740993", context);
+
+        for (source, expected) in [
+            // Numbers are compared by value.
+            ("x == 1", true),
+            ("1 == x", true),
+            ("x != 1", false),
+            ("1 < 1.5", true),
+            ("1.5 <= 1", false),
+            ("2 > 1.5", true),
+            ("1 >= 1.0", true),
+            ("null < 1", true),
+            // Integers beyond 2^53 are not rounded.
+            ("big == 9007199254740992.0", false),
+            ("big > 9007199254740992.0", true),
+            ("9007199254740992 == 9007199254740992.0", true),
+            // Strings are compared lexicographically.
+            ("'abc' == 'abc'", true),
+            ("'abc' < 'abd'", true),
+            ("'b' >= 'a'", true),
+            // Booleans only participate in equality.
+            ("true == true", true),
+            ("true != false", true),
+            ("true == 1", false),
+            ("null == 0", false),
+            ("null == null", true),
+            ("[1,2] == [1,2.0]", true),
+            ("{'a':1} == {'a':1.0}", true),
+        ] {
+            assert_eq!(
+                ExpressionParser::execute_str(source, context),
+                Ok(create_data_arc(Data::Boolean(expected))),
+                "{}",
+                source
+            );
+        }
+
+        for source in [
+            "'1' == 1",
+            "1 != '1'",
+            "'1' < 2",
+            "2 >= '1'",
+            "true < false",
+            "true >= 1",
+            "[1] < [2]",
+            "{} > null",
+        ] {
+            assert!(
+                matches!(
+                    ExpressionParser::execute_str(source, context)
+                        .unwrap()
+                        .lock()
+                        .unwrap()
+                        .deref(),
+                    Data::Error(_)
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn division_rules() {
+        let ec = RFsmExpressionDatamodel::new(create_global_data_arc());
+        let context = &mut ec.global_data.lock().unwrap();
+
+        for (source, expected) in [
+            ("6 / 3", Data::Integer(2)),
+            ("6 / 4", Data::Double(1.5)),
+            ("-7 / 2", Data::Double(-3.5)),
+            ("6.0 / 3", Data::Double(2.0)),
+            ("1 / 0", Data::Double(f64::INFINITY)),
+            ("7 % 3", Data::Integer(1)),
+        ] {
+            let result = ExpressionParser::execute_str(source, context).unwrap();
+            let guard = result.lock().unwrap();
+            // Data::eq doesn't distinguish Integer and Double, so check the type explicitly.
+            assert_eq!(
+                std::mem::discriminant(guard.deref()),
+                std::mem::discriminant(&expected),
+                "{}",
+                source
+            );
+            assert_eq!(*guard, expected, "{}", source);
+        }
+
+        for source in ["0 / 0", "1 % 0"] {
+            assert!(
+                matches!(
+                    ExpressionParser::execute_str(source, context)
+                        .unwrap()
+                        .lock()
+                        .unwrap()
+                        .deref(),
+                    Data::Error(_)
+                ),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn compound_assignments_work() {
+        let ec = RFsmExpressionDatamodel::new(create_global_data_arc());
+        let context = &mut ec.global_data.lock().unwrap();
+
+        let _ = ExpressionParser::execute_str(
+            "retries ?= 1; d ?= 1.5; s ?= 'a'; m ?= {'a':{'b':1}}",
+            context,
+        );
+
+        for (source, expected) in [
+            ("retries += 1", Data::Integer(2)),
+            ("retries -= 5", Data::Integer(-3)),
+            ("retries *= -2", Data::Integer(6)),
+            ("retries /= 4", Data::Double(1.5)),
+            ("d += 1", Data::Double(2.5)),
+            ("d *= 2", Data::Double(5.0)),
+            ("s += 'b' + 1", Data::String("ab1".to_string())),
+            ("m.a.b += 41", Data::Integer(42)),
+            ("m.a.b", Data::Integer(42)),
+            // Right side is evaluated before the operation is applied.
+            ("d -= d", Data::Double(0.0)),
+        ] {
+            assert_eq!(
+                ExpressionParser::execute_str(source, context),
+                Ok(create_data_arc(expected)),
+                "{}",
+                source
+            );
+        }
+
+        assert!(ExpressionParser::execute_str("s -= 'b'", context).is_err());
+        assert!(ExpressionParser::execute_str("unknown += 1", context).is_err());
+        assert!(ExpressionParser::execute_str("1 += 1", context).is_err());
+        assert_eq!(
+            ExpressionParser::execute_str("s", context),
+            Ok(create_data_arc(Data::String("ab1".to_string())))
+        );
+
+        let mut read_only = create_data_arc(Data::Integer(1));
+        read_only.set_readonly(true);
+        context
+            .data
+            .set_undefined_arc("constant".to_string(), read_only);
+        assert!(ExpressionParser::execute_str("constant += 1", context).is_err());
+        assert_eq!(
+            ExpressionParser::execute_str("constant", context),
+            Ok(create_data_arc(Data::Integer(1)))
+        );
+    }
+
     #[test]
     fn sequence_work() {
         init_logging();
@@ -16,6 +16,11 @@ pub enum Operator {
     GreaterEqual,
     Assign,
     AssignUndefined,
+    /// Compound assignments "+=", "-=", "*=" and "/=".
+    PlusAssign,
+    MinusAssign,
+    MultiplyAssign,
+    DivideAssign,
     Equal,
     NotEqual,
     And,
@@ -24,6 +29,8 @@ pub enum Operator {
     /// C-like modulus (mathematically the remainder) function.
     Modulus,
     Not,
+    /// The '?' of the conditional operator "condition ? a : b".
+    Conditional,
 }
 
 /// Numeric types.
@@ -209,13 +216,36 @@ impl ExpressionLexer {
 
     /// Read (possible combined) operators
     fn read_operator(&mut self, first: char) -> Token {
+        if matches!(first, '-' | '+' | '*' | '/') {
+            let second = self.next_char();
+            if second == '=' {
+                return Token::Operator(match first {
+                    '-' => Operator::MinusAssign,
+                    '+' => Operator::PlusAssign,
+                    '*' => Operator::MultiplyAssign,
+                    _ => Operator::DivideAssign,
+                });
+            } else if second != '\0' {
+                self.push_back();
+            }
+        }
         Token::Operator(match first {
             '-' => Operator::Minus,
             '+' => Operator::Plus,
             '*' => Operator::Multiply,
             ':' | '/' => Operator::Divide,
-            '&' => Operator::And,
-            '|' => Operator::Or,
+            '&' | '|' => {
+                // "&&" and "||" are handled like "&" and "|".
+                let second = self.next_char();
+                if second != first && second != '\0' {
+                    self.push_back();
+                }
+                if first == '&' {
+                    Operator::And
+                } else {
+                    Operator::Or
+                }
+            }
             '%' => Operator::Modulus,
             _ => {
                 let second = self.next_char();
@@ -232,8 +262,11 @@ impl ExpressionLexer {
                         }
                     }
                 } else {
-                    self.push_back();
+                    if second != '\0' {
+                        self.push_back();
+                    }
                     match first {
+                        '?' => Operator::Conditional,
                         '<' => Operator::Less,
                         '>' => Operator::Greater,
                         '=' => Operator::Assign,
@@ -391,6 +424,9 @@ impl ExpressionLexer {
 
         // Start chars for a legal Number ('+' and "." NOT in JSON):
         if Self::is_digit(c) || c == '-' || c == '+' || c == '.' {
+            if (c == '-' || c == '+') && self.has_next() && self.text[self.pos] == '=' {
+                return self.read_operator(c);
+            }
             return self.read_number(c);
         }
         loop {
@@ -706,4 +742,51 @@ mod tests {
         println!(" {:?}", n);
         assert_eq!(n, Token::EOE);
     }
+
+    #[test]
+    fn lexer_can_parse_compound_assignments() {
+        let mut l = ExpressionLexer::new("a+=1 b -= -2 c*=d/=e - =".to_string());
+        let expected = [
+            Token::Identifier("a".to_string()),
+            Token::Operator(Operator::PlusAssign),
+            Token::Number(NumericToken::Integer(1)),
+            Token::Identifier("b".to_string()),
+            Token::Operator(Operator::MinusAssign),
+            Token::Number(NumericToken::Integer(-2)),
+            Token::Identifier("c".to_string()),
+            Token::Operator(Operator::MultiplyAssign),
+            Token::Identifier("d".to_string()),
+            Token::Operator(Operator::DivideAssign),
+            Token::Identifier("e".to_string()),
+            Token::Operator(Operator::Minus),
+            Token::Operator(Operator::Assign),
+            Token::EOE,
+        ];
+        for e in expected {
+            assert_eq!(l.next_token(), e);
+        }
+    }
+
+    #[test]
+    fn lexer_can_parse_logical_and_conditional_operators() {
+        let mut l = ExpressionLexer::new("a&&b||c ? 1 : 2 ?= &|".to_string());
+        let expected = [
+            Token::Identifier("a".to_string()),
+            Token::Operator(Operator::And),
+            Token::Identifier("b".to_string()),
+            Token::Operator(Operator::Or),
+            Token::Identifier("c".to_string()),
+            Token::Operator(Operator::Conditional),
+            Token::Number(NumericToken::Integer(1)),
+            Token::Operator(Operator::Divide),
+            Token::Number(NumericToken::Integer(2)),
+            Token::Operator(Operator::AssignUndefined),
+            Token::Operator(Operator::And),
+            Token::Operator(Operator::Or),
+            Token::EOE,
+        ];
+        for e in expected {
+            assert_eq!(l.next_token(), e);
+        }
+    }
 }
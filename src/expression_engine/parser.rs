@@ -2,9 +2,10 @@
 
 use crate::datamodel::{Data, GlobalDataLock};
 use crate::expression_engine::expressions::{
-    get_expression_as, Expression, ExpressionArray, ExpressionAssign, ExpressionAssignUndefined, ExpressionConstant,
-    ExpressionIndex, ExpressionMap, ExpressionMemberAccess, ExpressionMethod, ExpressionNot, ExpressionOperator,
-    ExpressionResult, ExpressionSequence, ExpressionVariable,
+    get_expression_as, Expression, ExpressionArray, ExpressionAssign, ExpressionAssignUndefined,
+    ExpressionCompoundAssign, ExpressionConditional, ExpressionConstant, ExpressionIndex, ExpressionMap,
+    ExpressionMemberAccess, ExpressionMethod, ExpressionNot, ExpressionOperator, ExpressionResult, ExpressionSequence,
+    ExpressionVariable,
 };
 use crate::expression_engine::lexer::{ExpressionLexer, NumericToken, Operator, Token};
 
@@ -24,6 +25,8 @@ pub struct ExpressionParser {}
 enum ExpressionParserItem {
     SToken(Token),
     SExpression(Box<dyn Expression>),
+    /// The "? a :" part of a conditional operator, with the already parsed "a".
+    Conditional(Box<dyn Expression>),
 }
 
 impl Display for ExpressionParserItem {
@@ -31,6 +34,7 @@ impl Display for ExpressionParserItem {
         match self {
             ExpressionParserItem::SToken(t) => Debug::fmt(t, f),
             ExpressionParserItem::SExpression(e) => Debug::fmt(e, f),
+            ExpressionParserItem::Conditional(e) => write!(f, "? {:?} :", e),
         }
     }
 }
@@ -179,6 +183,18 @@ impl ExpressionParser {
                 Token::Identifier(_) => {
                     stack.push(ExpressionParserItem::SToken(t));
                 }
+                Token::Operator(Operator::Conditional) => {
+                    // The true-branch ends at the ':'. The false-branch is parsed as part of the current expression.
+                    let (stop_c, if_true) = Self::parse_sub_expression(lexer, &[':'])?;
+                    match if_true {
+                        Some(if_true) if stop_c == ':' => {
+                            stack.push(ExpressionParserItem::Conditional(if_true));
+                        }
+                        _ => {
+                            return Err("Missing ':' in conditional expression".to_string());
+                        }
+                    }
+                }
                 Token::Operator(_) => {
                     stack.push(ExpressionParserItem::SToken(t));
                 }
@@ -227,6 +243,13 @@ impl ExpressionParser {
                                 ExpressionParserItem::SExpression(_) => {
                                     return Result::Err(format!("Unexpected '{}'", br));
                                 }
+                                ExpressionParserItem::Conditional(_) => {
+                                    stack.push(si);
+                                    let (_, se) = Self::parse_sub_expression(lexer, &[')'])?;
+                                    if let Some(sev) = se {
+                                        stack.push(ExpressionParserItem::SExpression(sev));
+                                    }
+                                }
                             },
                         }
                     }
@@ -276,6 +299,11 @@ impl ExpressionParser {
                                     }
                                     Box::new(ExpressionIndex::new(expression, v.remove(0)))
                                 }
+                                ExpressionParserItem::Conditional(_) => {
+                                    stack.push(si);
+                                    let v = Self::parse_argument_list(lexer, ']')?;
+                                    Box::new(ExpressionArray::new(v))
+                                }
                             },
                         };
                         stack.push(ExpressionParserItem::SExpression(new_stack_item));
@@ -390,11 +418,9 @@ impl ExpressionParser {
                     Token::Operator(operator) => {
                         let prio = match operator {
                             Operator::Not => 3u8,
-                            Operator::And => 5,
                             Operator::Multiply => 5,
                             Operator::Divide => 5,
                             Operator::Modulus => 5,
-                            Operator::Or => 6,
                             Operator::Plus => 6,
                             Operator::Minus => 6,
                             Operator::Less => 9,
@@ -403,8 +429,15 @@ impl ExpressionParser {
                             Operator::GreaterEqual => 9,
                             Operator::Equal => 10,
                             Operator::NotEqual => 10,
+                            Operator::And => 11,
+                            Operator::Or => 12,
+                            Operator::Conditional => 15,
                             Operator::Assign => 16,
                             Operator::AssignUndefined => 16,
+                            Operator::PlusAssign => 16,
+                            Operator::MinusAssign => 16,
+                            Operator::MultiplyAssign => 16,
+                            Operator::DivideAssign => 16,
                         };
                         if prio <= best_idx_prio {
                             best_idx = si;
@@ -416,10 +449,38 @@ impl ExpressionParser {
                     }
                 },
                 ExpressionParserItem::SExpression(_) => {}
+                ExpressionParserItem::Conditional(_) => {
+                    if 15 <= best_idx_prio {
+                        best_idx = si;
+                        best_idx_prio = 15;
+                    }
+                }
             }
             si += 1;
         }
         if best_idx_prio < 0xffu8 {
+            if let ExpressionParserItem::Conditional(_) = &stack[best_idx] {
+                if best_idx > 0 && (best_idx + 1) < stack.len() {
+                    let if_false = stack.remove(best_idx + 1);
+                    let if_true = stack.remove(best_idx);
+                    let condition = stack.remove(best_idx - 1);
+                    if let (
+                        ExpressionParserItem::SExpression(condition),
+                        ExpressionParserItem::Conditional(if_true),
+                        ExpressionParserItem::SExpression(if_false),
+                    ) = (condition, if_true, if_false)
+                    {
+                        stack.insert(
+                            best_idx - 1,
+                            ExpressionParserItem::SExpression(Box::new(ExpressionConditional::new(
+                                condition, if_true, if_false,
+                            ))),
+                        );
+                        return Self::stack_to_expression(stack);
+                    }
+                }
+                return Err("Failed to parse conditional expression".to_string());
+            }
             let mut op = None;
             let si = stack.get(best_idx).unwrap();
             if let ExpressionParserItem::SToken(Token::Operator(op_t)) = si {
@@ -472,6 +533,33 @@ impl ExpressionParser {
                             return Self::stack_to_expression(stack);
                         }
                     }
+                    Operator::PlusAssign
+                    | Operator::MinusAssign
+                    | Operator::MultiplyAssign
+                    | Operator::DivideAssign => {
+                        let operation = match op {
+                            Operator::PlusAssign => Operator::Plus,
+                            Operator::MinusAssign => Operator::Minus,
+                            Operator::MultiplyAssign => Operator::Multiply,
+                            _ => Operator::Divide,
+                        };
+                        if Self::fold_stack_at(
+                            stack,
+                            best_idx,
+                            |le: Box<dyn Expression>, re: Box<dyn Expression>| -> Result<Box<dyn Expression>, String> {
+                                Ok(Box::new(ExpressionCompoundAssign::new(
+                                    operation.clone(),
+                                    le,
+                                    re,
+                                )))
+                            },
+                        ) {
+                            return Self::stack_to_expression(stack);
+                        }
+                    }
+                    Operator::Conditional => {
+                        // Handled as ExpressionParserItem::Conditional, never put on the stack as token.
+                    }
                     Operator::Not => {
                         if (best_idx + 1) < stack.len() {
                             stack.remove(best_idx);
@@ -694,4 +782,99 @@ mod tests {
         println!("Parsed: {:?}", r);
         assert!(get_expression_as::<ExpressionIndex>(r.deref()).is_some());
     }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        let global_data = create_global_data_arc();
+        global_data
+            .lock()
+            .unwrap()
+            .data
+            .set_undefined("x".to_string(), Data::Null());
+
+        // Right sides would fail, as "unknown" is not defined and "x" has no members.
+        for (source, expected) in [
+            ("false && unknown", false),
+            ("true || unknown", true),
+            ("x != null && x.field == 1", false),
+            ("x == null || x.field == 1", true),
+            ("1 < 2 && 2 < 3", true),
+            ("1 > 2 || 2 > 3", false),
+        ] {
+            let r = ExpressionParser::parse(source.to_string()).unwrap();
+            assert_eq!(
+                r.execute(&mut global_data.lock().unwrap(), false),
+                ExpressionResult::Ok(create_data_arc(Data::Boolean(expected))),
+                "{}",
+                source
+            );
+        }
+
+        // Without short-circuit the error is reported.
+        let r = ExpressionParser::parse("true && unknown".to_string()).unwrap();
+        assert!(r.execute(&mut global_data.lock().unwrap(), false).is_err());
+    }
+
+    #[test]
+    fn conditional_operator() {
+        let global_data = create_global_data_arc();
+        global_data
+            .lock()
+            .unwrap()
+            .data
+            .set_undefined("a".to_string(), Data::Integer(2));
+
+        for (source, expected) in [
+            ("true ? 1 : 2", Data::Integer(1)),
+            ("a > 5 ? 'big' : 'small'", Data::String("small".to_string())),
+            // Only the selected branch is evaluated.
+            ("a == 2 ? a * 10 : unknown", Data::Integer(20)),
+            // Nested in the false branch (right associative).
+            (
+                "a == 1 ? 'one' : a == 2 ? 'two' : 'other'",
+                Data::String("two".to_string()),
+            ),
+            // Nested in the true branch.
+            (
+                "a > 0 ? a > 1 ? 'gt1' : 'one' : 'negative'",
+                Data::String("gt1".to_string()),
+            ),
+            ("(a > 0 ? 3 : 4) + 1", Data::Integer(4)),
+            (
+                "a > 0 && a < 5 ? [1, 2] : [3]",
+                Data::Array(vec![
+                    create_data_arc(Data::Integer(1)),
+                    create_data_arc(Data::Integer(2)),
+                ]),
+            ),
+        ] {
+            let r = ExpressionParser::parse(source.to_string()).unwrap();
+            assert_eq!(
+                r.execute(&mut global_data.lock().unwrap(), false),
+                ExpressionResult::Ok(create_data_arc(expected)),
+                "{}",
+                source
+            );
+        }
+
+        let r = ExpressionParser::parse("b = a < 0 ? 0 : a".to_string()).unwrap();
+        assert!(r.execute(&mut global_data.lock().unwrap(), true).is_ok());
+        assert_eq!(
+            global_data
+                .lock()
+                .unwrap()
+                .data
+                .get("b")
+                .unwrap()
+                .lock()
+                .unwrap()
+                .deref(),
+            &Data::Integer(2)
+        );
+
+        assert!(ExpressionParser::parse("a ? 1".to_string()).is_err());
+        assert!(ExpressionParser::parse("a ?".to_string()).is_err());
+        let r = ExpressionParser::parse("a ? 1 : 2".to_string()).unwrap();
+        assert!(r.execute(&mut global_data.lock().unwrap(), false).is_err());
+    }
 }
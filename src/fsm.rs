@@ -10,24 +10,33 @@ use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::Hash;
 use std::ops::DerefMut;
+use std::panic::AssertUnwindSafe;
+use std::path::Path;
 #[cfg(test)]
 use std::println as error;
 #[cfg(test)]
+use std::println as warn;
+#[cfg(test)]
 #[cfg(feature = "Debug")]
 use std::println as debug;
 use std::slice::Iter;
 use std::str::FromStr;
 use std::string::ToString;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::{Arc, Mutex};
-use std::thread::JoinHandle;
-use std::{fmt, thread};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, SendError, Sender, SyncSender, TrySendError};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{JoinHandle, ThreadId};
+use std::time::Duration;
+#[cfg(feature = "Metrics")]
+use std::time::Instant;
+use std::{fmt, mem, panic, thread};
 
 #[cfg(not(test))]
-use log::error;
+use log::{error, warn};
 
 use crate::actions::{Action, ActionWrapper};
+use crate::clock::{Clock, ClockGuard};
+use crate::event_recorder::EventRecorder;
 
 #[cfg(all(not(test), feature = "Debug", not(feature = "EnvLog")))]
 use std::println as debug;
@@ -35,12 +44,14 @@ use std::println as debug;
 #[cfg(all(not(test), feature = "Debug", feature = "EnvLog"))]
 use log::debug;
 
-use timer::Guard;
-
+#[cfg(feature = "json-config")]
+use crate::datamodel::json_to_data;
 use crate::datamodel::{
-    create_data_arc, Data, DataArc, DataStore, Datamodel, DatamodelFactory, GlobalDataArc, NullDatamodelFactory,
-    NULL_DATAMODEL, NULL_DATAMODEL_LC, SCXML_INVOKE_TYPE, SCXML_INVOKE_TYPE_SHORT, SESSION_ID_VARIABLE_NAME,
-    SESSION_NAME_VARIABLE_NAME,
+    create_data_arc, str_to_source, Data, DataArc, DataStore, Datamodel, DatamodelFactory, GlobalDataArc,
+    NullDatamodelFactory, NULL_DATAMODEL, NULL_DATAMODEL_LC, PLATFORM_VARIABLE_FIELD_INVOKE_ID,
+    PLATFORM_VARIABLE_FIELD_IO_PROCESSORS, PLATFORM_VARIABLE_FIELD_PARENT_SESSION_ID,
+    PLATFORM_VARIABLE_FIELD_SESSION_ID, PLATFORM_VARIABLE_FIELD_VERSION, PLATFORM_VARIABLE_NAME, SCXML_EVENT_PROCESSOR,
+    SCXML_INVOKE_TYPE, SCXML_INVOKE_TYPE_SHORT, SESSION_ID_VARIABLE_NAME, SESSION_NAME_VARIABLE_NAME,
 };
 #[cfg(feature = "ECMAScript")]
 use crate::ecma_script_datamodel::ECMAScriptDatamodelFactory;
@@ -48,23 +59,57 @@ use crate::ecma_script_datamodel::ECMAScriptDatamodelFactory;
 use crate::ecma_script_datamodel::ECMA_SCRIPT_LC;
 
 use crate::event_io_processor::EventIOProcessor;
+use crate::event_validator::EventValidator;
 use crate::executable_content::ExecutableContent;
 
 #[cfg(feature = "RfsmExpressionModel")]
 use crate::expression_engine::datamodel::{RFsmExpressionDatamodelFactory, RFSM_EXPRESSION_DATAMODEL_LC};
 
 use crate::fsm::BindingType::{Early, Late};
-use crate::fsm_executor::FsmExecutor;
+use crate::fsm_executor::{FsmExecutor, SessionInfo};
+use crate::fsm_snapshot::{FsmSnapshot, SnapshotData, SnapshotEvent};
+use crate::fsm_thread_pool::{PooledTask, SessionWaker};
 use crate::get_global;
+use crate::invoke_handler::InvokeParameters;
+use crate::invoke_handler::{InvokeError, InvokeHandler};
+#[cfg(feature = "Metrics")]
+use crate::metrics::{FsmMetrics, MetricsSnapshot};
 use crate::scxml_event_io_processor::{SCXML_EVENT_PROCESSOR_SHORT_TYPE, SCXML_TARGET_SESSION_ID_PREFIX};
+use crate::validation::validate;
 
 #[cfg(feature = "Trace")]
 use crate::tracer::create_tracer;
 #[cfg(feature = "Trace")]
-use crate::tracer::{TraceMode, Tracer};
+use crate::tracer::{TraceMode, TraceRecord, Tracer};
 
+/// Error event for errors during the execution of the document, e.g. on expression evaluation.
+pub const EVENT_ERROR_EXECUTION: &str = "error.execution";
+/// Platform specific event that is sent to the parent session if an invoked session failed.
+pub const EVENT_ERROR_PLATFORM: &str = "error.platform";
 /// Platform specific event to cancel the current session.
 pub const EVENT_CANCEL_SESSION: &str = "error.platform.cancel";
+/// Platform specific event to acknowledge a [ScxmlSession::send_and_sync] call.
+/// The "sendid" of the event identifies the waiting caller.
+pub const EVENT_SYNC_SESSION: &str = "platform.sync";
+/// Platform specific event to request a snapshot, see [ScxmlSession::take_snapshot].
+/// The "sendid" of the event identifies the waiting caller.
+pub const EVENT_SNAPSHOT_SESSION: &str = "platform.snapshot";
+/// Platform specific event to evaluate an expression in the datamodel, see [ScxmlSession::evaluate].
+/// The "sendid" of the event identifies the waiting caller.
+pub const EVENT_EVALUATE_SESSION: &str = "platform.evaluate";
+/// Platform specific event to replace the model of a running session, see [ScxmlSession::reload].
+pub const EVENT_RELOAD_SESSION: &str = "platform.reload";
+/// Platform specific event to change the trace modes, see [ScxmlSession::set_trace_mode].
+/// Each parameter names a trace mode, the boolean value enables or disables it.
+pub const EVENT_TRACE_SESSION: &str = "platform.trace";
+/// Platform specific event to clear the stored history of all history states, see [ScxmlSession::clear_history].
+pub const EVENT_CLEAR_HISTORY_SESSION: &str = "platform.clearhistory";
+/// Platform specific event that wakes up the session after the delay of a \<send\> has elapsed.
+/// The pending events are taken from [GlobalData::delayedQueue].
+pub const EVENT_DELAYED_SEND: &str = "platform.delayed";
+/// Platform specific event that wakes up the session if a priority event (a cancel or trace event) was sent.
+/// The event is dropped by the external queue and is never processed.
+pub const EVENT_WAKE_UP_SESSION: &str = "platform.wakeup";
 pub const EVENT_DONE_INVOKE_PREFIX: &str = "done.invoke.";
 
 pub static PLATFORM_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
@@ -87,54 +132,133 @@ pub fn start_fsm_with_data(
 }
 
 pub fn start_fsm_with_data_and_finish_mode(
-    mut sm: Box<Fsm>,
+    sm: Box<Fsm>,
     actions: ActionWrapper,
     executor: Box<FsmExecutor>,
     data: &[ParamPair],
     finish_mode: FinishMode,
 ) -> ScxmlSession {
-    #![allow(non_snake_case)]
-    let externalQueue: BlockingQueue<Box<Event>> = BlockingQueue::new();
-    let sender = externalQueue.sender.clone();
+    start_fsm_internal(
+        sm,
+        actions,
+        executor,
+        data,
+        &HashMap::new(),
+        finish_mode,
+        None,
+    )
+}
 
-    let data_copy = data.to_vec();
-    let session_id: SessionId = SESSION_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
-    let mut session = ScxmlSession::new_without_join_handle(session_id, sender.clone());
+/// Starts the FSM like [start_fsm_with_data_and_finish_mode] with datamodel options for this session,
+/// e.g. "ecma:strict". The options override the global options of the executor
+/// (see [FsmExecutor::set_global_options_from_arguments]) and are inherited by invoked sessions.
+pub fn start_fsm_with_options(
+    sm: Box<Fsm>,
+    actions: ActionWrapper,
+    executor: Box<FsmExecutor>,
+    data: &[ParamPair],
+    options: &HashMap<String, String>,
+    finish_mode: FinishMode,
+) -> ScxmlSession {
+    start_fsm_internal(sm, actions, executor, data, options, finish_mode, None)
+}
 
-    match finish_mode {
-        FinishMode::DISPOSE => {}
-        FinishMode::KEEP_CONFIGURATION => {
-            // FSM shall enter the final configuration during exct.
-            let _ = session
-                .global_data
-                .lock()
-                .unwrap()
-                .final_configuration
-                .insert(Vec::new());
+/// Starts the FSM inside a worker thread and resumes it from the snapshot.\
+/// See [crate::fsm_snapshot] for details.
+/// Returns an error if the snapshot references states that are not part of the FSM.
+pub fn start_fsm_from_snapshot(
+    sm: Box<Fsm>,
+    actions: ActionWrapper,
+    executor: Box<FsmExecutor>,
+    snapshot: FsmSnapshot,
+    finish_mode: FinishMode,
+) -> Result<ScxmlSession, String> {
+    let history_states = snapshot.history.iter().map(|(name, _)| name);
+    let history_values = snapshot.history.iter().flat_map(|(_, states)| states);
+    for name in snapshot
+        .configuration
+        .iter()
+        .chain(snapshot.entered_states.iter())
+        .chain(history_states)
+        .chain(history_values)
+    {
+        if !sm.statesNames.contains_key(name) {
+            return Err(format!("Snapshot references unknown state '{}'", name));
         }
-        FinishMode::NOTHING => {}
     }
+    Ok(start_fsm_internal(
+        sm,
+        actions,
+        executor,
+        &Vec::new(),
+        &HashMap::new(),
+        finish_mode,
+        Some(snapshot),
+    ))
+}
 
-    let options = {
-        let mut execute_state = executor.state.lock().unwrap();
-
-        execute_state.sessions.insert(session_id, session.clone());
-
-        execute_state.datamodel_options.clone()
-    };
-
-    let global_data = session.global_data.clone();
+fn start_fsm_internal(
+    mut sm: Box<Fsm>,
+    actions: ActionWrapper,
+    executor: Box<FsmExecutor>,
+    data: &[ParamPair],
+    session_options: &HashMap<String, String>,
+    finish_mode: FinishMode,
+    snapshot: Option<FsmSnapshot>,
+) -> ScxmlSession {
+    #![allow(non_snake_case)]
+    let (mut session, externalQueue, mut options) = create_session(
+        actions,
+        &executor,
+        finish_mode,
+        executor.thread_pool.is_some(),
+        &sm,
+    );
+    options.extend(session_options.iter().map(|(k, v)| (k.clone(), v.clone())));
+    #[cfg(feature = "Metrics")]
     {
-        let mut gc = global_data.lock().unwrap();
-        gc.actions = actions;
-        let executor_state_lock = executor.state.lock();
-        let guard = executor_state_lock.unwrap();
-        for p in &guard.processors {
-            let pg = p.lock().unwrap();
-            for t in pg.get_types() {
-                gc.io_processors.insert(t.to_string(), p.clone());
-            }
-        }
+        sm.metrics = session.metrics.clone();
+    }
+    #[cfg(feature = "Trace")]
+    {
+        session.global_data.lock().unwrap().trace_modes = sm.tracer.trace_modes();
+    }
+    let session_id = session.session_id;
+    let global_data = session.global_data.clone();
+    let completion = session.completion.clone();
+    let data_copy = data.to_vec();
+    let abort = SessionAbort {
+        session_id,
+        parent_session_id: sm.parent_session_id,
+        caller_invoke_id: sm.caller_invoke_id.clone(),
+        executor: executor.as_ref().clone(),
+        completion: completion.clone(),
+    };
+    // The datamodel needs to be created by the thread that executes the session.
+    let create_datamodel: DatamodelCreator = Box::new(move |sm: &mut Fsm| {
+        create_session_datamodel(
+            sm,
+            global_data,
+            &options,
+            externalQueue,
+            session_id,
+            executor,
+            data_copy,
+        )
+    });
+
+    if let Some(waker) = session.sender.waker().cloned() {
+        waker.start(Box::new(move || {
+            Box::new(PooledFsm {
+                fsm: sm,
+                create_datamodel: Some(create_datamodel),
+                datamodel: None,
+                snapshot,
+                completion,
+                abort,
+            })
+        }));
+        return session;
     }
 
     let thread = thread::Builder::new()
@@ -145,42 +269,327 @@ pub fn start_fsm_with_data_and_finish_mode(
         .spawn(move || {
             #[cfg(feature = "Debug")]
             debug!("SM starting...");
-            {
-                let mut datamodel = create_datamodel(sm.datamodel.as_str(), global_data, &options);
-                {
-                    let mut global = get_global!(datamodel);
-                    global.externalQueue = externalQueue;
-                    global.session_id = session_id;
-                    global.caller_invoke_id = Option::map(sm.caller_invoke_id.as_ref(), |x| x.clone());
-                    global.parent_session_id = sm.parent_session_id;
-                    global.executor = Some(executor);
-
-                    // W3C:
-                    // If the value of a key ... matches the 'id' of a <data> element
-                    // in the top-level data model of the invoked session, the SCXML Processor
-                    // MUST use the value of the key as the initial value of the corresponding
-                    // <data> element.
-                    if !data_copy.is_empty() {
-                        let root_state = sm.get_state_by_id_mut(sm.pseudo_root);
-                        for val in data_copy {
-                            if root_state.data.get_mut(&val.name).is_some() {
-                                root_state
-                                    .data
-                                    .insert(val.name, create_data_arc(val.value.clone()));
-                            }
-                        }
-                    }
+            let result = panic::catch_unwind(AssertUnwindSafe(move || {
+                let mut datamodel = create_datamodel(&mut sm);
+                match &snapshot {
+                    None => sm.interpret(datamodel.deref_mut()),
+                    Some(snapshot) => sm.interpret_from_snapshot(datamodel.deref_mut(), snapshot),
                 }
-                sm.interpret(datamodel.deref_mut());
+                complete_session(datamodel.deref_mut(), &completion);
+            }));
+            if result.is_err() {
+                abort.abort();
             }
             #[cfg(feature = "Debug")]
             debug!("SM finished");
+            abort.completion.set_exited();
         });
 
     let _ = session.thread.insert(thread.unwrap());
     session
 }
 
+type DatamodelCreator = Box<dyn FnOnce(&mut Fsm) -> Box<dyn Datamodel> + Send>;
+
+/// Finishes a session that was aborted by a panic.
+struct SessionAbort {
+    session_id: SessionId,
+    parent_session_id: Option<SessionId>,
+    caller_invoke_id: Option<InvokeId>,
+    executor: FsmExecutor,
+    completion: Arc<SessionCompletion>,
+}
+
+impl SessionAbort {
+    /// Publishes an empty final configuration and informs the parent session.\
+    /// The global data of the session may be poisoned and is not used.
+    fn abort(&self) {
+        error!("Session #{} aborted", self.session_id);
+        let result = SessionResult {
+            session_id: self.session_id,
+            final_configuration: Some(Vec::new()),
+            final_state_reached: false,
+        };
+        self.executor.clone().session_finished(&result);
+        self.completion.complete(result);
+        notify_parent_of_failure(
+            &self.executor,
+            self.parent_session_id,
+            &self.caller_invoke_id,
+        );
+    }
+}
+
+/// Sends "error.platform" to the parent, if the failed session was invoked.
+fn notify_parent_of_failure(
+    executor: &FsmExecutor,
+    parent_session_id: Option<SessionId>,
+    caller_invoke_id: &Option<InvokeId>,
+) {
+    if let (Some(parent_session_id), Some(invoke_id)) = (parent_session_id, caller_invoke_id) {
+        let _ = executor.send_to_session(parent_session_id, Event::error_platform(invoke_id));
+    }
+}
+
+/// A session that is executed by the thread pool of the executor.\
+/// Runs the same steps as [Fsm::interpret], but parks instead of waiting for external events.
+struct PooledFsm {
+    fsm: Box<Fsm>,
+    create_datamodel: Option<DatamodelCreator>,
+    /// Created by the first run.
+    datamodel: Option<Box<dyn Datamodel>>,
+    snapshot: Option<FsmSnapshot>,
+    completion: Arc<SessionCompletion>,
+    abort: SessionAbort,
+}
+
+impl PooledFsm {
+    fn run_steps(&mut self) -> bool {
+        let datamodel = match &mut self.datamodel {
+            Some(datamodel) => datamodel.deref_mut(),
+            None => {
+                let create_datamodel = self.create_datamodel.take().unwrap();
+                let datamodel = self
+                    .datamodel
+                    .insert(create_datamodel(&mut self.fsm))
+                    .deref_mut();
+                let entered = match self.snapshot.take() {
+                    None => self.fsm.enter_initial_configuration(datamodel),
+                    Some(snapshot) => self.fsm.enter_snapshot_configuration(datamodel, &snapshot),
+                };
+                if entered {
+                    self.fsm.complete_macrostep(datamodel);
+                } else {
+                    get_global!(datamodel).running = false;
+                }
+                datamodel
+            }
+        };
+        while get_global!(datamodel).running {
+            match self.fsm.try_dequeue_external_event(datamodel) {
+                None => return false,
+                Some(event) => {
+                    self.fsm.process_external_event(datamodel, event);
+                    self.fsm.complete_macrostep(datamodel);
+                }
+            }
+        }
+        self.fsm.exit_session(datamodel);
+        complete_session(datamodel, &self.completion);
+        true
+    }
+}
+
+impl PooledTask for PooledFsm {
+    fn run(&mut self) -> bool {
+        let finished = match panic::catch_unwind(AssertUnwindSafe(|| self.run_steps())) {
+            Ok(finished) => finished,
+            Err(_) => {
+                self.abort.abort();
+                true
+            }
+        };
+        if finished {
+            self.completion.set_exited();
+        }
+        finished
+    }
+}
+
+/// Control events must not get lost, they are accepted by bounded external queues in any case.
+#[allow(clippy::borrowed_box)]
+fn is_control_event(event: &Box<Event>) -> bool {
+    event.etype == EventType::platform || event.name == EVENT_CANCEL_SESSION
+}
+
+/// Priority events are processed before all pending external events, so that a session with a long backlog
+/// can be canceled or traced immediately. The order of all other events is not changed.
+#[allow(clippy::borrowed_box)]
+fn is_priority_event(event: &Box<Event>) -> bool {
+    event.name == EVENT_CANCEL_SESSION || (event.etype == EventType::platform && event.name == EVENT_TRACE_SESSION)
+}
+
+fn create_wake_up_event() -> Box<Event> {
+    let mut event = Event::new_simple(EVENT_WAKE_UP_SESSION);
+    event.etype = EventType::platform;
+    Box::new(event)
+}
+
+#[allow(clippy::borrowed_box)]
+fn is_wake_up_event(event: &Box<Event>) -> bool {
+    event.etype == EventType::platform && event.name == EVENT_WAKE_UP_SESSION
+}
+
+/// Creates a new session for the FSM and registers it at the executor.\
+/// If "pooled" is set and the executor has a thread pool, the session is bound to a worker of the pool.\
+/// The external queue is bounded by the limit of the FSM or else by the limit of the executor.\
+/// Returns the session, the external queue and the datamodel options.
+#[allow(non_snake_case)]
+pub(crate) fn create_session(
+    actions: ActionWrapper,
+    executor: &FsmExecutor,
+    finish_mode: FinishMode,
+    pooled: bool,
+    sm: &Fsm,
+) -> (
+    ScxmlSession,
+    BlockingQueue<Box<Event>>,
+    HashMap<String, String>,
+) {
+    let session_id: SessionId = SESSION_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let externalQueue: BlockingQueue<Box<Event>> = match sm.queue_limit.or(executor.queue_limit) {
+        None => BlockingQueue::new(),
+        Some(limit) => BlockingQueue::new_bounded(limit, is_control_event),
+    }
+    .with_priority_lane(is_priority_event, create_wake_up_event, is_wake_up_event);
+    let externalQueue = match &executor.thread_pool {
+        Some(pool) if pooled => externalQueue.with_waker(pool.register(session_id)),
+        _ => externalQueue,
+    };
+    let sender = externalQueue.sender.clone();
+
+    let session = ScxmlSession::new_without_join_handle(session_id, sender.clone());
+    if let Some(recorder) = &sm.event_recorder {
+        recorder.start(executor.clock().now_ms());
+    }
+
+    if let FinishMode::KEEP_CONFIGURATION = finish_mode {
+        // FSM shall enter the final configuration during exct.
+        let _ = session
+            .global_data
+            .lock()
+            .unwrap()
+            .final_configuration
+            .insert(Vec::new());
+    }
+
+    let options = {
+        let mut execute_state = executor.state.lock().unwrap();
+
+        execute_state.sessions.insert(session_id, session.clone());
+        execute_state.session_infos.insert(
+            session_id,
+            SessionInfo {
+                session_id,
+                parent: sm.parent_session_id,
+                invoke_id: sm.caller_invoke_id.clone().filter(|id| !id.is_empty()),
+                finish_mode,
+                running: true,
+                result: None,
+            },
+        );
+
+        execute_state.datamodel_options.clone()
+    };
+
+    {
+        // The processors are locked without the executor state, as they lock the state while they send.
+        let processors = executor.processors();
+        let mut gc = session.global_data.lock().unwrap();
+        gc.actions = actions;
+        for p in processors {
+            let types: Vec<String> = p
+                .lock()
+                .unwrap()
+                .get_types()
+                .iter()
+                .map(|t| t.to_string())
+                .collect();
+            for t in types {
+                gc.io_processors.insert(t, p.clone());
+            }
+        }
+    }
+    (session, externalQueue, options)
+}
+
+/// Creates the datamodel of a session, sets the initial data and registers the
+/// session at the I/O processors.\
+/// Needs to be called from the thread that executes the FSM.
+#[allow(non_snake_case)]
+pub(crate) fn create_session_datamodel(
+    sm: &mut Fsm,
+    global_data: GlobalDataArc,
+    options: &HashMap<String, String>,
+    externalQueue: BlockingQueue<Box<Event>>,
+    session_id: SessionId,
+    executor: Box<FsmExecutor>,
+    data: Vec<ParamPair>,
+) -> Box<dyn Datamodel> {
+    {
+        // Set before the datamodel is created, so the factory can use the ids of the session.
+        let mut global = global_data.lock().unwrap();
+        global.externalQueue = externalQueue;
+        global.session_id = session_id;
+        #[cfg(feature = "Trace")]
+        sm.tracer.set_session_id(session_id);
+        global.caller_invoke_id = Option::map(sm.caller_invoke_id.as_ref(), |x| x.clone());
+        global.parent_session_id = sm.parent_session_id;
+        global.clock = Some(executor.clock());
+        if sm.event_validator.is_none() {
+            sm.event_validator.clone_from(&executor.event_validator);
+        }
+        global.options.clone_from(options);
+        executor.register_delayed_queue(
+            session_id,
+            global.delayedQueue.sender.clone(),
+            global.externalQueue.sender.clone(),
+        );
+        global.executor = Some(executor);
+        global
+            .configuration_listeners
+            .append(&mut sm.configuration_listeners);
+
+        // W3C:
+        // If the value of a key ... matches the 'id' of a <data> element
+        // in the top-level data model of the invoked session, the SCXML Processor
+        // MUST use the value of the key as the initial value of the corresponding
+        // <data> element.
+        if !data.is_empty() {
+            let root_state = sm.get_state_by_id_mut(sm.pseudo_root);
+            for val in data {
+                if root_state.data.get_mut(&val.name).is_some() {
+                    root_state.data_src.remove(&val.name);
+                    root_state
+                        .data
+                        .insert(val.name, create_data_arc(val.value.clone()));
+                }
+            }
+        }
+    }
+    let mut datamodel = create_datamodel(sm.datamodel.as_str(), global_data, options);
+    // Register the session at the I/O processors, so they can deliver external events.
+    let processors: Vec<Arc<Mutex<Box<dyn EventIOProcessor>>>> = get_global!(datamodel)
+        .io_processors
+        .values()
+        .cloned()
+        .collect();
+    for processor in processors {
+        processor.lock().unwrap().add_fsm(sm, datamodel.deref_mut());
+    }
+    datamodel
+}
+
+/// Informs the executor that the session has finished and publishes the result.\
+/// The executor is informed first, so the registry is up-to-date for anyone waiting for the result.
+pub(crate) fn complete_session(datamodel: &mut dyn Datamodel, completion: &SessionCompletion) {
+    let (result, executor) = {
+        let global = get_global!(datamodel);
+        (
+            SessionResult {
+                session_id: global.session_id,
+                final_configuration: global.final_configuration.clone(),
+                final_state_reached: global.final_state_reached,
+            },
+            global.executor.clone(),
+        )
+    };
+    if let Some(mut executor) = executor {
+        executor.session_finished(&result);
+    }
+    completion.complete(result);
+}
+
 ////////////////////////////////////////////////////////////////////////////////
 // ## General Purpose Data types
 // Structs and methods are designed to match the signatures in the W3c-Pseudo-code.
@@ -221,8 +630,10 @@ impl<T: Clone + PartialEq> List<T> {
     }
 
     /// *W3C says:* Returns the head of the list
-    pub fn head(&self) -> &T {
-        self.data.first().unwrap()
+    /// #Actual implementation:
+    /// Returns None if the list is empty.
+    pub fn head(&self) -> Option<&T> {
+        self.data.first()
     }
 
     /// *W3C says*:
@@ -502,8 +913,10 @@ impl<T> Queue<T> {
 
     /// *W3C says*:
     /// Removes and returns first element in queue
-    pub fn dequeue(&mut self) -> T {
-        self.data.pop_front().unwrap()
+    /// #Actual implementation:
+    /// Returns None if the queue is empty.
+    pub fn dequeue(&mut self) -> Option<T> {
+        self.data.pop_front()
     }
 
     /// *W3C says*:
@@ -514,115 +927,541 @@ impl<T> Queue<T> {
     }
 }
 
-#[derive(Debug)]
-pub struct BlockingQueue<T> {
-    pub sender: Sender<T>,
-    pub receiver: Arc<Mutex<Receiver<T>>>,
+/// Policy of a bounded queue if a value is sent while the queue is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// The new value is discarded.
+    DropNewest,
+    /// The oldest value in the queue is discarded.
+    DropOldest,
+    /// The sender waits until the receiver has taken a value.\
+    /// Values that the receiving thread sends to its own queue are not blocked.
+    /// Sessions wait without holding any lock, see [crate::datamodel::Datamodel::send_with_processor].
+    /// But two sessions that wait for space in the queue of each other deadlock.
+    Block,
+    /// The send fails with [TrySendError::Full].
+    RaiseError,
 }
 
-impl<T> Default for BlockingQueue<T> {
-    fn default() -> Self {
-        BlockingQueue::new()
-    }
+/// Maximum length of a queue and the policy if the limit is reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QueueLimit {
+    pub max_len: usize,
+    pub policy: OverflowPolicy,
 }
 
-impl<T> BlockingQueue<T> {
-    fn new() -> BlockingQueue<T> {
-        let (sender, receiver) = channel();
-        BlockingQueue {
-            receiver: Arc::new(Mutex::new(receiver)),
-            sender,
+/// Shared state of a bounded queue.
+struct QueueBound<T> {
+    limit: QueueLimit,
+    /// Number of values in the channel.
+    len: Mutex<usize>,
+    space: Condvar,
+    dropped: AtomicU64,
+    /// Dropped values that were already reported by [QueueReceiver::take_dropped].
+    reported: AtomicU64,
+    /// Set after the receiver has finished, senders no longer wait.
+    closed: AtomicBool,
+    /// The thread that received last, never blocked by [OverflowPolicy::Block].
+    consumer: Mutex<Option<ThreadId>>,
+    receiver: Arc<Mutex<Receiver<T>>>,
+    /// Selects values that are always accepted, e.g. platform events.
+    unbounded: fn(&T) -> bool,
+    /// See [QueueReceiver::len], decremented for values that are dropped by [OverflowPolicy::DropOldest].
+    #[cfg(feature = "Metrics")]
+    pending: Arc<AtomicUsize>,
+}
+
+impl<T> QueueBound<T> {
+    /// Decides if a new value can be put into the channel.\
+    /// Returns false if the value shall be dropped. Increments the length if the value is accepted.
+    /// Fails if the policy is [OverflowPolicy::RaiseError] or if [OverflowPolicy::Block] would wait but "wait" is not set.
+    fn acquire(&self, sender: &Sender<T>, wait: bool) -> Result<bool, ()> {
+        let mut len = self.len.lock().unwrap();
+        while *len >= self.limit.max_len {
+            match self.limit.policy {
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Ok(false);
+                }
+                OverflowPolicy::DropOldest => {
+                    // If the receiver is currently taking a value, there will be space anyway.
+                    let receiver = match self.receiver.try_lock() {
+                        Ok(receiver) => receiver,
+                        Err(_) => break,
+                    };
+                    // Values that are not counted are put back at the end.
+                    while let Ok(oldest) = receiver.try_recv() {
+                        if (self.unbounded)(&oldest) {
+                            let _ = sender.send(oldest);
+                        } else {
+                            *len -= 1;
+                            self.dropped.fetch_add(1, Ordering::Relaxed);
+                            #[cfg(feature = "Metrics")]
+                            self.pending.fetch_sub(1, Ordering::Relaxed);
+                            break;
+                        }
+                    }
+                    break;
+                }
+                OverflowPolicy::Block => {
+                    if self.closed.load(Ordering::Relaxed)
+                        || *self.consumer.lock().unwrap() == Some(thread::current().id())
+                    {
+                        break;
+                    }
+                    if !wait {
+                        // Not dropped, the caller sends the value later.
+                        return Err(());
+                    }
+                    len = self.space.wait(len).unwrap();
+                }
+                OverflowPolicy::RaiseError => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return Err(());
+                }
+            }
         }
+        *len += 1;
+        Ok(true)
     }
 
-    /// *W3C says*:
-    /// Puts e last in the queue
-    pub fn enqueue(&mut self, e: T) {
-        let _ = self.sender.send(e);
+    fn release(&self, value: &T) {
+        if (self.unbounded)(value) {
+            return;
+        }
+        let mut len = self.len.lock().unwrap();
+        *len = len.saturating_sub(1);
+        self.space.notify_one();
     }
+}
 
-    /// *W3C says*:
-    /// Removes and returns first element in queue, blocks if queue is empty
-    pub fn dequeue(&mut self) -> T {
-        self.receiver.lock().unwrap().recv().unwrap()
-    }
+/// Values of a [BlockingQueue] that are received before all other values, see [BlockingQueue::with_priority_lane].
+struct PriorityLane<T> {
+    values: Mutex<VecDeque<T>>,
+    /// Selects the values that are put into the lane.
+    select: fn(&T) -> bool,
+    /// Creates the value that is sent through the channel to wake up a blocked receiver.
+    wake_up: fn() -> T,
+    /// Selects the wake-up values, they are not returned by the receiver.
+    is_wake_up: fn(&T) -> bool,
 }
 
-/// *W3C says*:
-/// table\[foo] returns the value associated with foo.
-/// table\[foo] = bar sets the value associated with foo to be bar.
-/// #Actual implementation:
-/// Instead of the Operators, methods are used.
-#[derive(Debug, Default)]
-pub struct HashTable<K, T> {
-    data: HashMap<K, T>,
+/// Sending side of a [BlockingQueue].\
+/// If the owning session is executed by a thread pool, each send wakes up the session.
+/// If the queue is bounded, the [OverflowPolicy] is applied.
+pub struct QueueSender<T> {
+    sender: Sender<T>,
+    waker: Option<SessionWaker>,
+    bound: Option<Arc<QueueBound<T>>>,
+    priority: Option<Arc<PriorityLane<T>>>,
+    /// Number of values in the queue, see [QueueReceiver::len].
+    #[cfg(feature = "Metrics")]
+    pending: Arc<AtomicUsize>,
 }
 
-impl<K: Eq + Hash + Clone, T: Clone> HashTable<K, T> {
-    fn new() -> HashTable<K, T> {
-        HashTable {
-            data: HashMap::new(),
+impl<T> QueueSender<T> {
+    pub fn new(sender: Sender<T>) -> QueueSender<T> {
+        QueueSender {
+            sender,
+            waker: None,
+            bound: None,
+            priority: None,
+            #[cfg(feature = "Metrics")]
+            pending: Arc::new(AtomicUsize::new(0)),
         }
     }
-    /// Extension to re-use exiting instances.
-    pub fn clear(&mut self) {
-        self.data.clear();
+
+    /// Puts the value into the queue.\
+    /// Fails if the receiving session has gone or if the queue is full and the policy
+    /// is [OverflowPolicy::RaiseError]. Values that are dropped by the policy are not reported as error.\
+    /// Values selected by the priority lane are not bounded and pass all other values in the queue.
+    pub fn send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.send_or_wait(value, true)
     }
 
-    pub fn put(&mut self, k: K, v: &T) {
-        self.data.insert(k.clone(), v.clone());
+    /// Puts the value into the queue like [QueueSender::send], but doesn't wait if the queue is full
+    /// and the policy is [OverflowPolicy::Block]. In this case the value is returned as [TrySendError::Full]
+    /// and is not counted as dropped.
+    pub fn try_send(&self, value: T) -> Result<(), TrySendError<T>> {
+        self.send_or_wait(value, false)
     }
 
-    pub fn put_move(&mut self, k: K, v: T) {
-        self.data.insert(k.clone(), v);
+    /// Returns true if [QueueSender::send] may wait for space in the queue, see [OverflowPolicy::Block].
+    pub fn may_block(&self) -> bool {
+        self.bound
+            .as_ref()
+            .is_some_and(|bound| bound.limit.policy == OverflowPolicy::Block)
     }
 
-    pub fn put_all(&mut self, t: &HashTable<K, T>) {
-        for (k, v) in &t.data {
-            self.data.insert(k.clone(), v.clone());
+    fn send_or_wait(&self, value: T, wait: bool) -> Result<(), TrySendError<T>> {
+        if let Some(lane) = &self.priority {
+            if (lane.select)(&value) {
+                lane.values.lock().unwrap().push_back(value);
+                // The value has to be in the lane before the receiver wakes up.
+                return self
+                    .send_to_channel((lane.wake_up)())
+                    .map_err(|_| TrySendError::Disconnected(lane.values.lock().unwrap().pop_back().unwrap()));
+            }
         }
+        if let Some(bound) = &self.bound {
+            if !(bound.unbounded)(&value) {
+                match bound.acquire(&self.sender, wait) {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(_) => return Err(TrySendError::Full(value)),
+                }
+            }
+        }
+        self.send_to_channel(value)
     }
 
-    pub fn has(&self, k: K) -> bool {
-        self.data.contains_key(&k)
+    fn send_to_channel(&self, value: T) -> Result<(), TrySendError<T>> {
+        if let Err(SendError(value)) = self.sender.send(value) {
+            if let Some(bound) = &self.bound {
+                bound.release(&value);
+            }
+            return Err(TrySendError::Disconnected(value));
+        }
+        #[cfg(feature = "Metrics")]
+        self.pending.fetch_add(1, Ordering::Relaxed);
+        if let Some(waker) = &self.waker {
+            waker.wake();
+        }
+        Ok(())
     }
 
-    pub fn get(&self, k: K) -> &T {
-        self.data.get(&k).unwrap()
+    /// Number of values that were dropped or rejected because the queue was full.
+    pub fn dropped(&self) -> u64 {
+        match &self.bound {
+            None => 0,
+            Some(bound) => bound.dropped.load(Ordering::Relaxed),
+        }
     }
-}
-
-/////////////////////////////////////////////////////////////
-// FSM model (State etc, representing the XML-data-model)
-
-pub type Name = String;
-pub type StateId = u32;
-pub type DocumentId = u32;
-pub type ExecutableContentId = u32;
-pub type StateVec = Vec<State>;
-pub type StateNameMap = HashMap<Name, StateId>;
-pub type TransitionMap = HashMap<TransitionId, Transition>;
 
-/// Datamodel binding type. See [W3C SCXML Data Binding](/doc/W3C_SCXML_2024_07_13/index.html#DataBinding)
-#[derive(Debug, Clone, PartialEq, Copy, Default)]
-pub enum BindingType {
-    #[default]
-    Early,
-    Late,
+    /// The waker of the session, if executed by a thread pool.
+    pub(crate) fn waker(&self) -> Option<&SessionWaker> {
+        self.waker.as_ref()
+    }
 }
 
-impl BindingType {
-    pub fn from_ordinal(ordinal: u8) -> BindingType {
-        match ordinal {
-            1 => Early,
-            2 => Late,
-            _ => {
-                panic!("Unknown ordinal {} for BindingType", ordinal);
-            }
+impl<T> Clone for QueueSender<T> {
+    fn clone(&self) -> Self {
+        QueueSender {
+            sender: self.sender.clone(),
+            waker: self.waker.clone(),
+            bound: self.bound.clone(),
+            priority: self.priority.clone(),
+            #[cfg(feature = "Metrics")]
+            pending: self.pending.clone(),
         }
     }
+}
 
-    pub fn ordinal(&self) -> u8 {
+impl<T> Debug for QueueSender<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueueSender")
+            .field("pooled", &self.waker.is_some())
+            .field("limit", &self.bound.as_ref().map(|b| b.limit))
+            .finish()
+    }
+}
+
+impl<T> From<Sender<T>> for QueueSender<T> {
+    fn from(sender: Sender<T>) -> Self {
+        QueueSender::new(sender)
+    }
+}
+
+/// Receiving side of a [BlockingQueue].
+pub struct QueueReceiver<T> {
+    receiver: Arc<Mutex<Receiver<T>>>,
+    bound: Option<Arc<QueueBound<T>>>,
+    priority: Option<Arc<PriorityLane<T>>>,
+    #[cfg(feature = "Metrics")]
+    pending: Arc<AtomicUsize>,
+}
+
+impl<T> QueueReceiver<T> {
+    /// Waits for the next value. Returns None if all senders are gone.
+    pub fn recv(&self) -> Option<T> {
+        if let Some(bound) = &self.bound {
+            *bound.consumer.lock().unwrap() = Some(thread::current().id());
+        }
+        loop {
+            if let Some(value) = self.take_priority() {
+                return Some(value);
+            }
+            let value = self.receiver.lock().unwrap().recv().ok()?;
+            self.received(&value);
+            if !self.is_wake_up(&value) {
+                return Some(value);
+            }
+        }
+    }
+
+    /// Returns the next value without waiting. Returns None if the queue is empty.
+    pub fn try_recv(&self) -> Option<T> {
+        if let Some(bound) = &self.bound {
+            *bound.consumer.lock().unwrap() = Some(thread::current().id());
+        }
+        loop {
+            if let Some(value) = self.take_priority() {
+                return Some(value);
+            }
+            let value = self.receiver.lock().unwrap().try_recv().ok()?;
+            self.received(&value);
+            if !self.is_wake_up(&value) {
+                return Some(value);
+            }
+        }
+    }
+
+    fn take_priority(&self) -> Option<T> {
+        self.priority
+            .as_ref()
+            .and_then(|lane| lane.values.lock().unwrap().pop_front())
+    }
+
+    fn is_wake_up(&self, value: &T) -> bool {
+        match &self.priority {
+            None => false,
+            Some(lane) => (lane.is_wake_up)(value),
+        }
+    }
+
+    fn received(&self, value: &T) {
+        #[cfg(feature = "Metrics")]
+        self.pending.fetch_sub(1, Ordering::Relaxed);
+        if let Some(bound) = &self.bound {
+            bound.release(value);
+        }
+    }
+
+    /// Number of values in the queue.
+    #[cfg(feature = "Metrics")]
+    pub fn len(&self) -> usize {
+        self.pending.load(Ordering::Relaxed)
+    }
+
+    #[cfg(feature = "Metrics")]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of values that were dropped since the last call.
+    pub fn take_dropped(&self) -> u64 {
+        match &self.bound {
+            None => 0,
+            Some(bound) => {
+                let dropped = bound.dropped.load(Ordering::Relaxed);
+                dropped - bound.reported.swap(dropped, Ordering::Relaxed)
+            }
+        }
+    }
+
+    /// Stops blocking of senders, called after the receiver has finished.
+    pub fn close(&self) {
+        if let Some(bound) = &self.bound {
+            let _len = bound.len.lock().unwrap();
+            bound.closed.store(true, Ordering::Relaxed);
+            bound.space.notify_all();
+        }
+    }
+}
+
+impl<T> Clone for QueueReceiver<T> {
+    fn clone(&self) -> Self {
+        QueueReceiver {
+            receiver: self.receiver.clone(),
+            bound: self.bound.clone(),
+            priority: self.priority.clone(),
+            #[cfg(feature = "Metrics")]
+            pending: self.pending.clone(),
+        }
+    }
+}
+
+impl<T> Debug for QueueReceiver<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("QueueReceiver")
+            .field("limit", &self.bound.as_ref().map(|b| b.limit))
+            .finish()
+    }
+}
+
+#[derive(Debug)]
+pub struct BlockingQueue<T> {
+    pub sender: QueueSender<T>,
+    pub receiver: QueueReceiver<T>,
+}
+
+impl<T> Default for BlockingQueue<T> {
+    fn default() -> Self {
+        BlockingQueue::new()
+    }
+}
+
+impl<T> BlockingQueue<T> {
+    fn new() -> BlockingQueue<T> {
+        let (sender, receiver) = channel();
+        let sender = QueueSender::new(sender);
+        BlockingQueue {
+            receiver: QueueReceiver {
+                receiver: Arc::new(Mutex::new(receiver)),
+                bound: None,
+                priority: None,
+                #[cfg(feature = "Metrics")]
+                pending: sender.pending.clone(),
+            },
+            sender,
+        }
+    }
+
+    /// Creates a queue that applies the policy of the limit if it contains "max_len" values.\
+    /// Values selected by "unbounded" are always accepted and are not counted.
+    pub fn new_bounded(limit: QueueLimit, unbounded: fn(&T) -> bool) -> BlockingQueue<T> {
+        let mut queue = BlockingQueue::new();
+        let bound = Arc::new(QueueBound {
+            limit,
+            len: Mutex::new(0),
+            space: Condvar::new(),
+            dropped: AtomicU64::new(0),
+            reported: AtomicU64::new(0),
+            closed: AtomicBool::new(false),
+            consumer: Mutex::new(None),
+            receiver: queue.receiver.receiver.clone(),
+            unbounded,
+            #[cfg(feature = "Metrics")]
+            pending: queue.sender.pending.clone(),
+        });
+        queue.sender.bound = Some(bound.clone());
+        queue.receiver.bound = Some(bound);
+        queue
+    }
+
+    /// Creates a queue with a priority lane: values selected by "select" are received before all other values,
+    /// in the order they were sent. For each of them, a value created by "wake_up" is sent through the queue
+    /// to wake up a waiting receiver. These values are identified by "is_wake_up" and are skipped by the receiver,
+    /// they are always accepted by a bounded queue.
+    pub fn with_priority_lane(
+        mut self,
+        select: fn(&T) -> bool,
+        wake_up: fn() -> T,
+        is_wake_up: fn(&T) -> bool,
+    ) -> BlockingQueue<T> {
+        let lane = Arc::new(PriorityLane {
+            values: Mutex::new(VecDeque::new()),
+            select,
+            wake_up,
+            is_wake_up,
+        });
+        self.sender.priority = Some(lane.clone());
+        self.receiver.priority = Some(lane);
+        self
+    }
+
+    /// Creates a queue that wakes up the session via the waker on each send.
+    pub(crate) fn with_waker(mut self, waker: SessionWaker) -> BlockingQueue<T> {
+        self.sender.waker = Some(waker);
+        self
+    }
+
+    /// *W3C says*:
+    /// Puts e last in the queue
+    pub fn enqueue(&mut self, e: T) {
+        let _ = self.sender.send(e);
+    }
+
+    /// *W3C says*:
+    /// Removes and returns first element in queue, blocks if queue is empty
+    /// #Actual implementation:
+    /// Returns None if all senders are gone.
+    pub fn dequeue(&mut self) -> Option<T> {
+        self.receiver.recv()
+    }
+}
+
+/// *W3C says*:
+/// table\[foo] returns the value associated with foo.
+/// table\[foo] = bar sets the value associated with foo to be bar.
+/// #Actual implementation:
+/// Instead of the Operators, methods are used.
+#[derive(Debug, Default)]
+pub struct HashTable<K, T> {
+    data: HashMap<K, T>,
+}
+
+impl<K: Eq + Hash + Clone, T: Clone> HashTable<K, T> {
+    fn new() -> HashTable<K, T> {
+        HashTable {
+            data: HashMap::new(),
+        }
+    }
+    /// Extension to re-use exiting instances.
+    pub fn clear(&mut self) {
+        self.data.clear();
+    }
+
+    pub fn put(&mut self, k: K, v: &T) {
+        self.data.insert(k.clone(), v.clone());
+    }
+
+    pub fn put_move(&mut self, k: K, v: T) {
+        self.data.insert(k.clone(), v);
+    }
+
+    pub fn put_all(&mut self, t: &HashTable<K, T>) {
+        for (k, v) in &t.data {
+            self.data.insert(k.clone(), v.clone());
+        }
+    }
+
+    pub fn has(&self, k: K) -> bool {
+        self.data.contains_key(&k)
+    }
+
+    pub fn get(&self, k: K) -> Option<&T> {
+        self.data.get(&k)
+    }
+
+    pub fn remove(&mut self, k: K) -> Option<T> {
+        self.data.remove(&k)
+    }
+}
+
+/////////////////////////////////////////////////////////////
+// FSM model (State etc, representing the XML-data-model)
+
+pub type Name = String;
+pub type StateId = u32;
+pub type DocumentId = u32;
+/// Line of an element in the source document, starting with 1. 0 if not known.
+pub type SourceLine = u32;
+pub type ExecutableContentId = u32;
+pub type StateVec = Vec<State>;
+pub type StateNameMap = HashMap<Name, StateId>;
+pub type TransitionMap = HashMap<TransitionId, Transition>;
+
+/// Datamodel binding type. See [W3C SCXML Data Binding](/doc/W3C_SCXML_2024_07_13/index.html#DataBinding)
+#[derive(Debug, Clone, PartialEq, Copy, Default)]
+pub enum BindingType {
+    #[default]
+    Early,
+    Late,
+}
+
+impl BindingType {
+    pub fn from_ordinal(ordinal: u8) -> BindingType {
+        match ordinal {
+            1 => Early,
+            2 => Late,
+            _ => {
+                panic!("Unknown ordinal {} for BindingType", ordinal);
+            }
+        }
+    }
+
+    pub fn ordinal(&self) -> u8 {
         match self {
             Early => 1u8,
             Late => 2u8,
@@ -776,14 +1615,19 @@ impl Event {
         }
     }
 
+    /// Control event to enable or disable a trace mode, see [ScxmlSession::set_trace_mode].\
+    /// The event is handled by the FSM itself and is not visible to the state machine.
     #[cfg(feature = "Trace")]
     pub fn trace(t: TraceMode, enable: bool) -> Event {
         Event {
-            name: format!("trace.{}.{}", t, if enable { "on" } else { "off" }),
-            etype: EventType::external,
+            name: EVENT_TRACE_SESSION.to_string(),
+            etype: EventType::platform,
             sendid: None,
             origin: None,
-            param_values: None,
+            param_values: Some(vec![ParamPair::new_moved(
+                t.to_string(),
+                Data::Boolean(enable),
+            )]),
             content: None,
             invoke_id: None,
             origin_type: None,
@@ -806,7 +1650,7 @@ impl Event {
     /// W3C: Indicates that an error internal to the execution of the document has occurred, such as one arising from expression evaluation.
     pub fn error_execution_with_event(event: &Event) -> Event {
         Event {
-            name: "error.execution".to_string(),
+            name: EVENT_ERROR_EXECUTION.to_string(),
             etype: EventType::platform,
             sendid: event.sendid.clone(),
             origin: event.origin.clone(),
@@ -820,7 +1664,7 @@ impl Event {
     /// W3C: Indicates that an error internal to the execution of the document has occurred, such as one arising from expression evaluation.
     pub fn error_execution(send_id: &Option<String>, invoke_id: &Option<InvokeId>) -> Event {
         Event {
-            name: "error.execution".to_string(),
+            name: EVENT_ERROR_EXECUTION.to_string(),
             etype: EventType::platform,
             sendid: send_id.clone(),
             origin: None,
@@ -831,6 +1675,21 @@ impl Event {
         }
     }
 
+    /// Platform specific: Sent to the parent session if an invoked session failed,
+    /// e.g. because the document is not valid or the interpreter was aborted.
+    pub fn error_platform(invoke_id: &InvokeId) -> Event {
+        Event {
+            name: EVENT_ERROR_PLATFORM.to_string(),
+            etype: EventType::platform,
+            sendid: None,
+            origin: None,
+            param_values: None,
+            content: None,
+            invoke_id: Some(invoke_id.clone()),
+            origin_type: None,
+        }
+    }
+
     /// W3C: Indicates that an error has occurred while trying to communicate with an external entity.
     pub fn error_communication(event: &Event) -> Event {
         Event {
@@ -845,6 +1704,20 @@ impl Event {
         }
     }
 
+    /// Raised in the invoking session if the document of an \<invoke\> can't be retrieved.
+    pub fn error_communication_for_invoke(invoke_id: &InvokeId) -> Event {
+        Event {
+            name: "error.communication".to_string(),
+            etype: EventType::platform,
+            sendid: None,
+            origin: None,
+            param_values: None,
+            content: None,
+            invoke_id: Some(invoke_id.clone()),
+            origin_type: None,
+        }
+    }
+
     pub fn get_copy(&self) -> Box<Event> {
         Box::new(Event {
             invoke_id: self.invoke_id.clone(),
@@ -857,11 +1730,93 @@ impl Event {
             origin_type: self.origin_type.clone(),
         })
     }
+
+    /// Creates a builder for an external event, see [ScxmlSession::send_event].
+    pub fn builder(name: &str) -> EventBuilder {
+        EventBuilder {
+            event: Event::new_simple(name),
+        }
+    }
+}
+
+/// Builder for events that are sent to a session from outside, see [Event::builder].\
+/// The event is an external event without "sendid", "origin" and "invokeid".
+/// Data entries and content are exclusive, like \<param\> and \<content\> of \<send\>: the last call wins.
+#[derive(Debug, Clone)]
+pub struct EventBuilder {
+    event: Event,
+}
+
+impl EventBuilder {
+    /// Adds an entry to "_event.data".
+    pub fn data_entry(mut self, name: &str, value: Data) -> EventBuilder {
+        self.event.content = None;
+        self.event
+            .param_values
+            .get_or_insert_with(Vec::new)
+            .push(ParamPair::new_moved(name.to_string(), value));
+        self
+    }
+
+    /// Sets the value of "_event.data".
+    pub fn content(mut self, value: Data) -> EventBuilder {
+        self.event.param_values = None;
+        self.event.content = Some(create_data_arc(value));
+        self
+    }
+
+    /// Sets the value of "_event.data", JSON objects are converted to maps.
+    #[cfg(feature = "json-config")]
+    pub fn content_json(self, value: serde_json::Value) -> EventBuilder {
+        self.content(json_to_data(&value))
+    }
+
+    pub fn build(self) -> Event {
+        self.event
+    }
+}
+
+impl From<EventBuilder> for Event {
+    fn from(builder: EventBuilder) -> Self {
+        builder.build()
+    }
 }
 
 pub type InvokeId = String;
 
-pub type EventSender = Sender<Box<Event>>;
+pub type EventSender = QueueSender<Box<Event>>;
+
+/// Expression and result channel of a pending [ScxmlSession::evaluate].
+pub type EvaluationRequest = (String, Sender<Result<Data, String>>);
+
+/// New model and result channel of a pending [ScxmlSession::reload].
+pub type ReloadRequest = (Box<Fsm>, Sender<Result<(), String>>);
+
+/// Kind of the child content of \<content\> or \<assign\>.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ContentKind {
+    /// Character data, e.g. JSON, a number or plain text. Entities and CDATA sections are already decoded.
+    #[default]
+    Text,
+    /// Markup, e.g. an inline \<scxml\> document. Kept as source and never interpreted as JSON.
+    Markup,
+}
+
+impl ContentKind {
+    pub fn ordinal(&self) -> u8 {
+        match self {
+            ContentKind::Text => 0,
+            ContentKind::Markup => 1,
+        }
+    }
+
+    pub fn from_ordinal(ordinal: u8) -> ContentKind {
+        match ordinal {
+            1 => ContentKind::Markup,
+            _ => ContentKind::Text,
+        }
+    }
+}
 
 #[derive(Clone, PartialEq, Debug, Default)]
 pub struct CommonContent {
@@ -870,6 +1825,9 @@ pub struct CommonContent {
 
     /// expr-attribute of \<content\> child
     pub content_expr: Option<String>,
+
+    /// Kind of "content".
+    pub content_kind: ContentKind,
 }
 
 impl CommonContent {
@@ -877,6 +1835,7 @@ impl CommonContent {
         CommonContent {
             content: None,
             content_expr: None,
+            content_kind: ContentKind::Text,
         }
     }
 }
@@ -891,11 +1850,24 @@ pub fn push_param(params: &mut OptionalParams, param: Parameter) {
     }
 }
 
+/// Returns the first name that is given more than once by "namelist" or \<param\> elements.\
+/// A conformant document must not specify the same name twice, the processor raises
+/// error.execution instead.
+pub fn find_duplicate_param_name(name_list: &[String], params: &OptionalParams) -> Option<String> {
+    let mut names: HashSet<&str> = HashSet::new();
+    name_list
+        .iter()
+        .chain(params.iter().flatten().map(|p| &p.name))
+        .find(|name| !names.insert(name.as_str()))
+        .cloned()
+}
+
 #[derive(Clone, PartialEq, Default)]
 /// *W3C says*:
 /// The \<invoke\> element is used to create an instance of an external service.
 pub struct Invoke {
     pub doc_id: DocumentId,
+    pub source_line: SourceLine,
 
     /// *W3C says*:
     /// Attribute 'idlocation':\
@@ -956,6 +1928,7 @@ impl Invoke {
     pub fn new() -> Invoke {
         Invoke {
             doc_id: 0,
+            source_line: 0,
             invoke_id: "".to_string(),
             parent_state_name: "".to_string(),
             external_id_location: "".to_string(),
@@ -976,6 +1949,7 @@ impl Debug for Invoke {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         f.debug_struct("Invoke")
             .field("invokeId", &self.invoke_id)
+            .field("line", &self.source_line)
             .field("idlocation", &self.external_id_location)
             .field("type", &self.type_name)
             .field("typeexpr", &self.type_expr)
@@ -994,6 +1968,10 @@ pub struct Parameter {
     pub name: String,
     pub expr: String,
     pub location: String,
+    /// rFSM extension for \<invoke\>, set by attribute "rfsm:writeback".\
+    /// If set, the value of "name" in the invoked session is assigned back to "location" when
+    /// the invoked session completes, before the finalize content is executed.
+    pub write_back: bool,
 }
 
 /// *W3C says*:
@@ -1015,10 +1993,13 @@ pub struct GlobalData {
     pub historyValue: HashTable<StateId, OrderedSet<StateId>>,
     pub running: bool,
 
-    internalQueue: Queue<Event>,
+    pub(crate) internalQueue: Queue<Event>,
 
     pub externalQueue: BlockingQueue<Box<Event>>,
 
+    /// Delayed sends whose delay has elapsed, see [DelayedSend].
+    pub delayedQueue: BlockingQueue<DelayedSend>,
+
     /// Invoked Sessions. Key: InvokeId.
     pub child_sessions: HashMap<InvokeId, ScxmlSession>,
 
@@ -1031,15 +2012,108 @@ pub struct GlobalData {
 
     /// Will contain after execution the final configuration, if set before.
     pub final_configuration: Option<Vec<String>>,
+    /// Values of [Fsm::final_value_locations], captured when the session exits.
+    pub final_values: HashMap<String, Data>,
+    /// Set if the FSM exits via a top-level final state.
+    pub final_state_reached: bool,
     pub environment: HashMap<String, DataArc>,
-
-    /// Stores any delayed send (with a "sendid"), Key: sendid
-    pub delayed_send: HashMap<String, Guard>,
+    /// The clock of the FSM, set when the session is started.
+    pub clock: Option<Arc<dyn Clock>>,
+    /// The datamodel options of the session. Inherited by invoked sessions.
+    pub options: HashMap<String, String>,
+    /// The enabled trace modes of the session, see [ScxmlSession::trace_modes].\
+    /// Updated when the session is started and by [ScxmlSession::set_trace_mode].
+    #[cfg(feature = "Trace")]
+    pub trace_modes: Vec<TraceMode>,
+
+    /// Stores any pending delayed send (with a "sendid"), Key: sendid.\
+    /// As multiple sends can use the same "sendid", each entry holds a list of
+    /// (unique timer number, timer guard).
+    pub delayed_send: HashMap<String, Vec<(u32, ClockGuard)>>,
+    /// Stores any pending delayed send without "sendid", Key: unique timer number.
+    pub delayed_send_without_id: HashMap<u32, ClockGuard>,
     pub io_processors: HashMap<String, Arc<Mutex<Box<dyn EventIOProcessor>>>>,
+    /// Events for other sessions whose queues are full, see [OverflowPolicy::Block].\
+    /// Sent by [Datamodel::send_with_processor] after the I/O processor and the global data are unlocked.
+    pub blocked_sends: Vec<(EventSender, Box<Event>)>,
+
+    /// Callers waiting in [ScxmlSession::send_and_sync], Key: sendid of the sync event.\
+    /// Set to None after the FSM has finished.
+    pub sync_acks: Option<HashMap<String, Sender<()>>>,
+
+    /// Callers waiting in [ScxmlSession::take_snapshot], Key: sendid of the snapshot event.\
+    /// Set to None after the FSM has finished.
+    pub snapshot_requests: Option<HashMap<String, Sender<FsmSnapshot>>>,
+
+    /// Callers waiting in [ScxmlSession::evaluate] with the expression to evaluate,
+    /// Key: sendid of the evaluate event.\
+    /// Set to None after the FSM has finished.
+    pub evaluation_requests: Option<HashMap<String, EvaluationRequest>>,
+
+    /// Callers waiting in [ScxmlSession::reload] with the new model, Key: sendid of the reload event.\
+    /// Set to None after the FSM has finished.
+    pub reload_requests: Option<HashMap<String, ReloadRequest>>,
+
+    /// If set, entered and exited states are recorded, see [crate::fsm_stepper::FsmStepper].
+    pub step_record: Option<StepRecord>,
+
+    /// The "sendid" resp. "invokeid" of the \<send\> or \<invoke\> element that is currently evaluated.\
+    /// Attached to error events that are raised during the evaluation, see [GlobalData::enqueue_internal].
+    pub error_send_id: Option<String>,
+    pub error_invoke_id: Option<InvokeId>,
+    /// Source line of the element that is currently evaluated. 0 if not known.\
+    /// Added as parameter "line" to "error.execution" events, see [GlobalData::enqueue_internal].
+    pub error_source_line: SourceLine,
+    /// Number of "error.execution" events that were put in the internal queue.
+    pub execution_errors: u64,
+
+    /// Subscribers for configuration changes, see [ScxmlSession::on_configuration_change].
+    pub configuration_listeners: Vec<SyncSender<ConfigurationUpdate>>,
+    /// Number of configuration updates that were dropped because the queue of a subscriber was full.
+    pub configuration_updates_dropped: u64,
+    /// Number of the last configuration update.
+    pub configuration_step: u64,
+    /// States entered and exited since the last configuration update.
+    /// Only recorded if there are subscribers.
+    pub configuration_change: StepRecord,
+
+    /// Subscribers for events, see [ScxmlSession::subscribe].\
+    /// Each entry holds the stripped event descriptor and the sender of the subscriber.
+    pub event_subscribers: Vec<(String, Sender<Box<Event>>)>,
 
     pub data: DataStore,
 }
 
+/// A delayed \<send\> whose delay has elapsed.\
+/// The timer callbacks don't lock the global data, they only put the send into
+/// [GlobalData::delayedQueue]. The session calls the I/O processor, see [EVENT_DELAYED_SEND].
+pub struct DelayedSend {
+    pub send_id: Option<String>,
+    /// Number of the timer, see [GlobalData::add_delayed_send].
+    pub timer_number: u32,
+    pub target: String,
+    pub event: Event,
+    pub io_processor: Arc<Mutex<Box<dyn EventIOProcessor>>>,
+}
+
+/// The states that were entered and exited during one step.
+#[derive(Debug, Clone, Default)]
+pub struct StepRecord {
+    pub entered: Vec<StateId>,
+    pub exited: Vec<StateId>,
+}
+
+/// Change of the active configuration by one microstep, see [ScxmlSession::on_configuration_change].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ConfigurationUpdate {
+    /// Number of the update, starting with 1.
+    pub step: u64,
+    /// Names of the states that were entered, in entry order.
+    pub entered: Vec<String>,
+    /// Names of the states that were exited, in exit order.
+    pub exited: Vec<String>,
+}
+
 impl GlobalData {
     pub fn new() -> GlobalData {
         GlobalData {
@@ -1051,39 +2125,205 @@ impl GlobalData {
             statesToInvoke: OrderedSet::new(),
             internalQueue: Queue::new(),
             externalQueue: BlockingQueue::new(),
+            delayedQueue: BlockingQueue::new(),
             child_sessions: HashMap::new(),
             caller_invoke_id: None,
             parent_session_id: None,
             session_id: 0,
             final_configuration: None,
+            final_values: HashMap::new(),
+            final_state_reached: false,
             environment: HashMap::new(),
+            clock: None,
+            options: HashMap::new(),
+            #[cfg(feature = "Trace")]
+            trace_modes: Vec::new(),
             delayed_send: HashMap::new(),
+            delayed_send_without_id: HashMap::new(),
             io_processors: HashMap::new(),
+            blocked_sends: Vec::new(),
+            sync_acks: Some(HashMap::new()),
+            snapshot_requests: Some(HashMap::new()),
+            evaluation_requests: Some(HashMap::new()),
+            reload_requests: Some(HashMap::new()),
+            step_record: None,
+            error_send_id: None,
+            execution_errors: 0,
+            error_invoke_id: None,
+            error_source_line: 0,
+            configuration_listeners: Vec::new(),
+            event_subscribers: Vec::new(),
+            configuration_updates_dropped: 0,
+            configuration_step: 0,
+            configuration_change: StepRecord::default(),
             data: DataStore::new(),
         }
     }
 
-    pub fn enqueue_internal(&mut self, event: Event) {
-        self.internalQueue.enqueue(event);
+    /// Adds the state to the configuration.
+    pub fn enter_state(&mut self, state: StateId) {
+        self.configuration.add(state);
+        if let Some(record) = &mut self.step_record {
+            record.entered.push(state);
+        }
+        if !self.configuration_listeners.is_empty() {
+            self.configuration_change.entered.push(state);
+        }
     }
-}
 
-/// Mode how the executor handles the ScxmlSession
-/// if the FSM is finished.
-#[derive(Debug, Clone)]
-pub enum FinishMode {
-    DISPOSE,
-    KEEP_CONFIGURATION,
-    NOTHING,
-}
+    /// Removes the state from the configuration.
+    pub fn exit_state(&mut self, state: StateId) {
+        self.configuration.delete(&state);
+        if let Some(record) = &mut self.step_record {
+            record.exited.push(state);
+        }
+        if !self.configuration_listeners.is_empty() {
+            self.configuration_change.exited.push(state);
+        }
+    }
 
-/// Represents some external session.
-/// Holds thread-id and channel-sender to the external queue of the session.
-pub struct ScxmlSession {
-    pub session_id: SessionId,
-    pub thread: Option<JoinHandle<()>>,
-    pub sender: Sender<Box<Event>>,
-    /// global_data should be access after the FSM is finished to avoid deadlocks.
+    /// Offers a copy of the event to all subscribers with a matching descriptor.\
+    /// Disconnected subscribers are removed.
+    pub fn notify_event_subscribers(&mut self, event: &Event) {
+        self.event_subscribers.retain(|(descriptor, subscriber)| {
+            if descriptor != "*" && !event_descriptor_matches(descriptor, &event.name) {
+                return true;
+            }
+            subscriber.send(Box::new(event.clone())).is_ok()
+        });
+    }
+
+    /// Current time of the clock of the session, see [Clock::now_ms].
+    pub fn now_ms(&self) -> i64 {
+        match &self.clock {
+            Some(clock) => clock.now_ms(),
+            None => chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// Put an event into the internal queue.\
+    /// Error events without "sendid" or "invokeid" get the ids of the element that is currently evaluated.
+    /// "error.execution" events without data get the source line of the element as parameter "line".
+    pub fn enqueue_internal(&mut self, event: Event) {
+        let mut event = event;
+        if event.name.starts_with("error.") {
+            if event.sendid.is_none() {
+                event.sendid.clone_from(&self.error_send_id);
+            }
+            if event.invoke_id.is_none() {
+                event.invoke_id.clone_from(&self.error_invoke_id);
+            }
+            if event.name == EVENT_ERROR_EXECUTION {
+                self.execution_errors += 1;
+            }
+            if event.name == EVENT_ERROR_EXECUTION
+                && self.error_source_line > 0
+                && event.param_values.is_none()
+                && event.content.is_none()
+            {
+                event.param_values = Some(vec![ParamPair::new_moved(
+                    "line".to_string(),
+                    Data::Integer(self.error_source_line as i64),
+                )]);
+            }
+        }
+        self.notify_event_subscribers(&event);
+        self.internalQueue.enqueue(event);
+    }
+
+    /// Source line of the element that is currently evaluated, formatted for log messages.
+    /// Empty if not known.
+    pub fn source_line_info(&self) -> String {
+        if self.error_source_line > 0 {
+            format!(" (line {})", self.error_source_line)
+        } else {
+            String::new()
+        }
+    }
+
+    /// Stores the guard of a pending delayed send.
+    pub fn add_delayed_send(&mut self, send_id: Option<&str>, timer_number: u32, guard: ClockGuard) {
+        match send_id {
+            None => {
+                self.delayed_send_without_id.insert(timer_number, guard);
+            }
+            Some(send_id) => {
+                self.delayed_send
+                    .entry(send_id.to_string())
+                    .or_default()
+                    .push((timer_number, guard));
+            }
+        }
+    }
+
+    /// Removes the guard of a delayed send that was delivered. Other pending sends with the same
+    /// "sendid" are not touched.\
+    /// Returns false if the send is not pending, e.g. because it was cancelled.
+    pub fn remove_delayed_send(&mut self, send_id: Option<&str>, timer_number: u32) -> bool {
+        match send_id {
+            None => match self.delayed_send_without_id.remove(&timer_number) {
+                Some(guard) => {
+                    guard.ignore();
+                    true
+                }
+                None => false,
+            },
+            Some(send_id) => match self.delayed_send.get_mut(send_id) {
+                Some(guards) => {
+                    let count = guards.len();
+                    guards.retain(|(number, _)| *number != timer_number);
+                    let removed = guards.len() < count;
+                    if guards.is_empty() {
+                        self.delayed_send.remove(send_id);
+                    }
+                    removed
+                }
+                None => false,
+            },
+        }
+    }
+
+    /// Cancels all pending delayed sends with the specified "sendid".\
+    /// Dropping the timer guards prevents the scheduled callbacks from being executed.\
+    /// Unknown or already delivered send-ids are ignored.
+    pub fn cancel_delayed_send(&mut self, send_id: &str) {
+        if let Some(guards) = self.delayed_send.remove(send_id) {
+            #[cfg(feature = "Debug")]
+            debug!("cancel {} delayed send(s) '{}'", guards.len(), send_id);
+            drop(guards);
+        }
+    }
+
+    /// Cancels all pending delayed sends.\
+    /// *W3C says*:\
+    /// If the session terminates before the delay has elapsed, the event is discarded.
+    pub fn cancel_all_delayed_sends(&mut self) {
+        #[cfg(feature = "Debug")]
+        debug!(
+            "cancel {} pending delayed send(s)",
+            self.delayed_send.values().map(|g| g.len()).sum::<usize>() + self.delayed_send_without_id.len()
+        );
+        self.delayed_send.clear();
+        self.delayed_send_without_id.clear();
+    }
+}
+
+/// Mode how the executor handles the ScxmlSession
+/// if the FSM is finished.
+#[derive(Debug, Clone)]
+pub enum FinishMode {
+    DISPOSE,
+    KEEP_CONFIGURATION,
+    NOTHING,
+}
+
+/// Represents some external session.
+/// Holds thread-id and channel-sender to the external queue of the session.
+pub struct ScxmlSession {
+    pub session_id: SessionId,
+    pub thread: Option<JoinHandle<()>>,
+    pub sender: EventSender,
+    /// global_data should be access after the FSM is finished to avoid deadlocks.
     pub global_data: GlobalDataArc,
     /// Doc-id of the Invoke element that triggered this session.
     /// InvokeIds are generated if not specified, to identify the invoke element, the doc-id
@@ -1091,6 +2331,114 @@ pub struct ScxmlSession {
     pub invoke_doc_id: DocumentId,
     /// State of the invoke or 0.
     pub state_id: Option<StateId>,
+    /// The handler that started this session as result of some invoke.
+    pub invoke_handler: Option<Arc<Mutex<Box<dyn InvokeHandler>>>>,
+    /// Receives the result of the session after the FSM has finished.
+    pub completion: Arc<SessionCompletion>,
+    /// Counters of the session, see [ScxmlSession::metrics].
+    #[cfg(feature = "Metrics")]
+    pub metrics: Arc<FsmMetrics>,
+}
+
+/// The result of a finished session.
+#[derive(Debug, Clone)]
+pub struct SessionResult {
+    pub session_id: SessionId,
+    /// The final configuration. Only available if the session was started with
+    /// [FinishMode::KEEP_CONFIGURATION].
+    pub final_configuration: Option<Vec<String>>,
+    /// True if the FSM exited via a top-level final state, false if it was cancelled.
+    pub final_state_reached: bool,
+}
+
+/// Shared completion state of a session.\
+/// Set by the worker thread of the session before it exits.
+#[derive(Debug, Default)]
+pub struct SessionCompletion {
+    result: Mutex<Option<SessionResult>>,
+    condition: Condvar,
+    /// Set if the session has stopped all processing, see [SessionCompletion::wait_for_exit].
+    exited: Mutex<bool>,
+    exit_condition: Condvar,
+    /// Receivers of [SessionCompletion::wait_async].
+    #[cfg(feature = "async")]
+    async_waiters: Mutex<Vec<tokio::sync::oneshot::Sender<SessionResult>>>,
+}
+
+impl SessionCompletion {
+    pub fn new() -> SessionCompletion {
+        SessionCompletion {
+            result: Mutex::new(None),
+            condition: Condvar::new(),
+            exited: Mutex::new(false),
+            exit_condition: Condvar::new(),
+            #[cfg(feature = "async")]
+            async_waiters: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Called as last action of the thread of the session, resp. after a session of a thread pool has finished.
+    pub(crate) fn set_exited(&self) {
+        *self.exited.lock().unwrap() = true;
+        self.exit_condition.notify_all();
+    }
+
+    /// Waits until the thread of the session has ended or the timeout elapsed.\
+    /// Unlike [SessionCompletion::wait], this also waits for the cleanup after the result is published.
+    /// Returns true if the thread has ended.
+    pub fn wait_for_exit(&self, timeout: Duration) -> bool {
+        let guard = self.exited.lock().unwrap();
+        let (guard, _timeout_result) = self
+            .exit_condition
+            .wait_timeout_while(guard, timeout, |exited| !*exited)
+            .unwrap();
+        *guard
+    }
+
+    /// Stores the result and wakes up all waiting threads.
+    pub fn complete(&self, result: SessionResult) {
+        #[cfg(feature = "async")]
+        let async_result = result.clone();
+        let _ = self.result.lock().unwrap().insert(result);
+        self.condition.notify_all();
+        #[cfg(feature = "async")]
+        for waiter in self.async_waiters.lock().unwrap().drain(..) {
+            let _ = waiter.send(async_result.clone());
+        }
+    }
+
+    /// Gets the result without blocking. Returns None if the session is still running.
+    pub fn get_result(&self) -> Option<SessionResult> {
+        self.result.lock().unwrap().clone()
+    }
+
+    /// Waits asynchronously until the session is finished.
+    #[cfg(feature = "async")]
+    pub async fn wait_async(&self) -> SessionResult {
+        let receiver = {
+            // Registered while the result is locked, so "complete" can't be missed.
+            let result = self.result.lock().unwrap();
+            if let Some(result) = result.as_ref() {
+                return result.clone();
+            }
+            let (sender, receiver) = tokio::sync::oneshot::channel();
+            self.async_waiters.lock().unwrap().push(sender);
+            receiver
+        };
+        receiver
+            .await
+            .expect("Session completion dropped without result")
+    }
+
+    /// Waits until the session is finished or the timeout elapsed.
+    pub fn wait(&self, timeout: Duration) -> Option<SessionResult> {
+        let guard = self.result.lock().unwrap();
+        let (guard, _timeout_result) = self
+            .condition
+            .wait_timeout_while(guard, timeout, |result| result.is_none())
+            .unwrap();
+        guard.clone()
+    }
 }
 
 impl Debug for ScxmlSession {
@@ -1104,7 +2452,7 @@ impl Debug for ScxmlSession {
 }
 
 impl ScxmlSession {
-    pub fn new_without_join_handle(id: SessionId, sender: Sender<Box<Event>>) -> ScxmlSession {
+    pub fn new_without_join_handle(id: SessionId, sender: EventSender) -> ScxmlSession {
         ScxmlSession {
             session_id: id,
             thread: None,
@@ -1112,6 +2460,344 @@ impl ScxmlSession {
             global_data: GlobalDataArc::new(Mutex::new(GlobalData::new())),
             invoke_doc_id: 0,
             state_id: None,
+            invoke_handler: None,
+            completion: Arc::new(SessionCompletion::new()),
+            #[cfg(feature = "Metrics")]
+            metrics: Arc::new(FsmMetrics::new()),
+        }
+    }
+
+    /// Gets the result of the session without blocking.\
+    /// Returns None if the FSM is still running.
+    pub fn get_result(&self) -> Option<SessionResult> {
+        self.completion.get_result()
+    }
+
+    /// Number of events that were dropped because the external queue was full, see
+    /// [crate::fsm_executor::FsmExecutor::set_queue_limit]. Always 0 for unbounded queues.
+    pub fn dropped_events(&self) -> u64 {
+        self.sender.dropped()
+    }
+
+    /// Gets the current counters of the session. Can be called while the FSM is running.
+    #[cfg(feature = "Metrics")]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot()
+    }
+
+    /// Waits for the FSM to finish.\
+    /// Returns None if the FSM is still running after the timeout.
+    pub fn wait_for_completion(&self, timeout: Duration) -> Option<SessionResult> {
+        self.completion.wait(timeout)
+    }
+
+    /// Clears the stored history of all history states. History states that are entered afterwards
+    /// use their default transition until their parent is exited again.\
+    /// The history is cleared by the FSM in order with the events that were sent before.
+    pub fn clear_history(&self) -> Result<(), String> {
+        let mut event = Event::new_simple(EVENT_CLEAR_HISTORY_SESSION);
+        event.etype = EventType::platform;
+        self.sender
+            .send(Box::new(event))
+            .map_err(|e| format!("Failed to send clear history event: {}", e))
+    }
+
+    /// Sends an external event to the session, e.g. one created by [Event::builder].\
+    /// Returns an error if the session has finished or the queue of the session is full.
+    pub fn send_event(&self, event: impl Into<Event>) -> Result<(), String> {
+        self.sender.send(Box::new(event.into())).map_err(|e| {
+            format!(
+                "Failed to send event to session #{}: {}",
+                self.session_id, e
+            )
+        })
+    }
+
+    /// Sends an external event and waits until the macrostep triggered by it is completed.\
+    /// Returns Ok also if the FSM finished as result of the event.
+    /// Returns an error if the session is not running or the timeout elapsed.
+    pub fn send_and_sync(&self, event: Box<Event>, timeout: Duration) -> Result<(), String> {
+        let sync_id = format!(
+            "sync.{}",
+            PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let (ack_sender, ack_receiver) = channel();
+        match self.global_data.lock().unwrap().sync_acks.as_mut() {
+            None => {
+                return Err(format!("Session #{} has finished", self.session_id));
+            }
+            Some(sync_acks) => {
+                sync_acks.insert(sync_id.clone(), ack_sender);
+            }
+        }
+        let mut sync_event = Event::new_simple(EVENT_SYNC_SESSION);
+        sync_event.etype = EventType::platform;
+        sync_event.sendid = Some(sync_id.clone());
+
+        // The external queue is processed in order, so the sync event is dequeued
+        // after the last microstep of the macrostep triggered by the event.
+        let r = match self.sender.send(event) {
+            Ok(_) => self
+                .sender
+                .send(Box::new(sync_event))
+                .map_err(|e| format!("Failed to send sync event: {}", e)),
+            Err(e) => Err(format!("Failed to send event: {}", e)),
+        };
+        let r = r.and_then(|_| match ack_receiver.recv_timeout(timeout) {
+            // Disconnected: FSM has finished and dropped all pending acks.
+            Ok(_) | Err(RecvTimeoutError::Disconnected) => Ok(()),
+            Err(RecvTimeoutError::Timeout) => Err(format!("Timeout waiting for session #{}", self.session_id)),
+        });
+        if r.is_err() {
+            if let Some(sync_acks) = self.global_data.lock().unwrap().sync_acks.as_mut() {
+                sync_acks.remove(&sync_id);
+            }
+        }
+        r
+    }
+
+    /// Enables or disables a trace mode of the running session.\
+    /// The change is handled by the FSM before all pending external events, see [EVENT_WAKE_UP_SESSION].
+    /// Sessions that are invoked afterwards inherit the trace modes.
+    #[cfg(feature = "Trace")]
+    pub fn set_trace_mode(&self, mode: TraceMode, enable: bool) -> Result<(), String> {
+        self.sender
+            .send(Box::new(Event::trace(mode, enable)))
+            .map_err(|e| format!("Failed to send trace event: {}", e))
+    }
+
+    /// Gets the enabled trace modes of the session.\
+    /// Changes by [ScxmlSession::set_trace_mode] are visible after the FSM has processed them.
+    #[cfg(feature = "Trace")]
+    pub fn trace_modes(&self) -> Vec<TraceMode> {
+        self.global_data.lock().unwrap().trace_modes.clone()
+    }
+
+    /// Subscribes to changes of the active configuration.\
+    /// After each microstep an update with the entered and exited states is sent. The interpreter never
+    /// waits for a subscriber: if the queue of the subscriber is full, the update is dropped and counted in
+    /// [GlobalData::configuration_updates_dropped]. Disconnected subscribers are removed.\
+    /// Changes that happened before the call are not reported, to get all updates
+    /// use [Fsm::add_configuration_listener] before the session is started.\
+    /// The listener is disconnected when the FSM has finished.
+    pub fn on_configuration_change(&self, listener: SyncSender<ConfigurationUpdate>) {
+        let mut global = self.global_data.lock().unwrap();
+        // sync_acks is removed when the FSM has finished, the listener is dropped.
+        if global.sync_acks.is_some() {
+            global.configuration_listeners.push(listener);
+        }
+    }
+
+    /// Subscribes to events of the session.\
+    /// The pattern uses the same token-prefix semantics as the event descriptors of transitions,
+    /// e.g. "done.state" matches "done.state.s1", "\*" matches all events.\
+    /// The receiver gets a copy of each matching event that is placed in the internal queue,
+    /// sent via an I/O processor or returned to the parent session as "done.invoke". The normal
+    /// processing of the events is not affected.\
+    /// The subscription ends if the receiver is dropped or the FSM has finished.
+    pub fn subscribe(&self, pattern: &str) -> Receiver<Box<Event>> {
+        let (sender, receiver) = channel();
+        let mut global = self.global_data.lock().unwrap();
+        // sync_acks is removed when the FSM has finished, the sender is dropped.
+        if global.sync_acks.is_some() {
+            global
+                .event_subscribers
+                .push((strip_event_descriptor(pattern).to_string(), sender));
+        }
+        receiver
+    }
+
+    /// Takes a snapshot of the running FSM.\
+    /// The snapshot is taken by the FSM between two macrosteps, after all previously sent events
+    /// are processed.
+    /// Returns an error if the session is not running or the timeout elapsed.
+    pub fn take_snapshot(&self, timeout: Duration) -> Result<FsmSnapshot, String> {
+        let snapshot_id = format!(
+            "snapshot.{}",
+            PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let (snapshot_sender, snapshot_receiver) = channel();
+        match self.global_data.lock().unwrap().snapshot_requests.as_mut() {
+            None => {
+                return Err(format!("Session #{} has finished", self.session_id));
+            }
+            Some(snapshot_requests) => {
+                snapshot_requests.insert(snapshot_id.clone(), snapshot_sender);
+            }
+        }
+        let mut snapshot_event = Event::new_simple(EVENT_SNAPSHOT_SESSION);
+        snapshot_event.etype = EventType::platform;
+        snapshot_event.sendid = Some(snapshot_id.clone());
+
+        let r = match self.sender.send(Box::new(snapshot_event)) {
+            Ok(_) => match snapshot_receiver.recv_timeout(timeout) {
+                Ok(snapshot) => Ok(snapshot),
+                Err(RecvTimeoutError::Disconnected) => Err(format!("Session #{} has finished", self.session_id)),
+                Err(RecvTimeoutError::Timeout) => Err(format!(
+                    "Timeout waiting for snapshot of session #{}",
+                    self.session_id
+                )),
+            },
+            Err(e) => Err(format!("Failed to send snapshot event: {}", e)),
+        };
+        if r.is_err() {
+            if let Some(snapshot_requests) = self.global_data.lock().unwrap().snapshot_requests.as_mut() {
+                snapshot_requests.remove(&snapshot_id);
+            }
+        }
+        r
+    }
+
+    /// Replaces the model of the running FSM by a modified version of the document, e.g. during development.\
+    /// *Experimental*. The model is replaced by the FSM between two macrosteps, after all previously sent
+    /// events are processed. States are matched by name:
+    /// + Active states that are not part of the new document are exited, the \<onexit\> content of the old
+    ///   document is executed.
+    /// + Ancestors that are new in the new document are entered, the \<onentry\> content is executed.
+    /// + Values of \<data\> elements that are declared in both documents are kept, the data of the new
+    ///   document is initialized otherwise. The global script of the new document is executed.
+    /// + History values and pending internal events are kept.
+    ///
+    /// If the remaining states don't form a legal configuration in the new document, the datamodel
+    /// differs or invoked sessions are running, the reload is aborted and the old model stays active.\
+    /// Returns an error if the reload was aborted, the session is not running or the timeout elapsed.
+    pub fn reload(&self, fsm: Box<Fsm>, timeout: Duration) -> Result<(), String> {
+        let reload_id = format!(
+            "reload.{}",
+            PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let (result_sender, result_receiver) = channel();
+        match self.global_data.lock().unwrap().reload_requests.as_mut() {
+            None => {
+                return Err(format!("Session #{} has finished", self.session_id));
+            }
+            Some(reload_requests) => {
+                reload_requests.insert(reload_id.clone(), (fsm, result_sender));
+            }
+        }
+        let mut reload_event = Event::new_simple(EVENT_RELOAD_SESSION);
+        reload_event.etype = EventType::platform;
+        reload_event.sendid = Some(reload_id.clone());
+
+        let r = match self.sender.send(Box::new(reload_event)) {
+            Ok(_) => match result_receiver.recv_timeout(timeout) {
+                Ok(result) => result,
+                Err(RecvTimeoutError::Disconnected) => Err(format!("Session #{} has finished", self.session_id)),
+                Err(RecvTimeoutError::Timeout) => Err(format!(
+                    "Timeout waiting for reload of session #{}",
+                    self.session_id
+                )),
+            },
+            Err(e) => Err(format!("Failed to send reload event: {}", e)),
+        };
+        if let Some(reload_requests) = self.global_data.lock().unwrap().reload_requests.as_mut() {
+            reload_requests.remove(&reload_id);
+        }
+        r
+    }
+
+    /// Gets the value of a location of [Fsm::final_value_locations], captured when the session exited.\
+    /// Returns None while the FSM is running or if the location couldn't be evaluated.
+    pub fn get_final_value(&self, location: &str) -> Option<Data> {
+        self.global_data
+            .lock()
+            .unwrap()
+            .final_values
+            .get(location)
+            .cloned()
+    }
+
+    /// Evaluates an expression in the datamodel of the running FSM, e.g. for debugging.\
+    /// The expression is evaluated by the FSM between two macrosteps, after all previously sent
+    /// events are processed. Errors of the evaluation are returned, they don't raise
+    /// "error.execution" in the session.
+    /// Returns an error if the session is not running or the timeout elapsed.
+    pub fn evaluate(&self, expression: &str, timeout: Duration) -> Result<Data, String> {
+        self.request_evaluation(expression, timeout)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Like [ScxmlSession::evaluate], but returns the value as string, e.g. to probe a live session.\
+    /// The error tells if the session was not available or if the expression failed.
+    pub fn query(&self, expression: &str, timeout: Duration) -> Result<String, QueryError> {
+        self.request_evaluation(expression, timeout)
+            .map(|value| value.to_string())
+    }
+
+    fn request_evaluation(&self, expression: &str, timeout: Duration) -> Result<Data, QueryError> {
+        let evaluation_id = format!(
+            "evaluate.{}",
+            PLATFORM_ID_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let (result_sender, result_receiver) = channel();
+        match self
+            .global_data
+            .lock()
+            .unwrap()
+            .evaluation_requests
+            .as_mut()
+        {
+            None => {
+                return Err(QueryError::Finished(self.session_id));
+            }
+            Some(evaluation_requests) => {
+                evaluation_requests.insert(
+                    evaluation_id.clone(),
+                    (expression.to_string(), result_sender),
+                );
+            }
+        }
+        let mut evaluate_event = Event::new_simple(EVENT_EVALUATE_SESSION);
+        evaluate_event.etype = EventType::platform;
+        evaluate_event.sendid = Some(evaluation_id.clone());
+
+        let r = match self.sender.send(Box::new(evaluate_event)) {
+            Ok(_) => match result_receiver.recv_timeout(timeout) {
+                Ok(result) => result.map_err(QueryError::Evaluation),
+                Err(RecvTimeoutError::Disconnected) => Err(QueryError::Finished(self.session_id)),
+                Err(RecvTimeoutError::Timeout) => Err(QueryError::Timeout(self.session_id)),
+            },
+            Err(e) => Err(QueryError::Send(e.to_string())),
+        };
+        if let Some(evaluation_requests) = self
+            .global_data
+            .lock()
+            .unwrap()
+            .evaluation_requests
+            .as_mut()
+        {
+            evaluation_requests.remove(&evaluation_id);
+        }
+        r
+    }
+}
+
+/// Errors of [ScxmlSession::query].
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryError {
+    /// The session has finished.
+    Finished(SessionId),
+    /// The session didn't answer in time, e.g. because a macrostep is still running.
+    Timeout(SessionId),
+    /// The request could not be sent to the session.
+    Send(String),
+    /// The datamodel failed to evaluate the expression.
+    Evaluation(String),
+}
+
+impl Display for QueryError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            QueryError::Finished(session_id) => write!(f, "Session #{} has finished", session_id),
+            QueryError::Timeout(session_id) => {
+                write!(
+                    f,
+                    "Timeout waiting for evaluation in session #{}",
+                    session_id
+                )
+            }
+            QueryError::Send(err) => write!(f, "Failed to send evaluate event: {}", err),
+            QueryError::Evaluation(err) => write!(f, "{}", err),
         }
     }
 }
@@ -1125,6 +2811,10 @@ impl Clone for ScxmlSession {
             global_data: self.global_data.clone(),
             state_id: self.state_id,
             invoke_doc_id: self.invoke_doc_id,
+            invoke_handler: self.invoke_handler.clone(),
+            completion: self.completion.clone(),
+            #[cfg(feature = "Metrics")]
+            metrics: self.metrics.clone(),
         }
     }
 
@@ -1134,6 +2824,10 @@ impl Clone for ScxmlSession {
         self.sender = source.sender.clone();
         self.state_id = source.state_id;
         self.invoke_doc_id = source.invoke_doc_id;
+        self.invoke_handler.clone_from(&source.invoke_handler);
+        self.completion.clone_from(&source.completion);
+        #[cfg(feature = "Metrics")]
+        self.metrics.clone_from(&source.metrics);
     }
 }
 
@@ -1163,6 +2857,10 @@ pub struct Fsm {
     pub states: Vec<State>,
     pub transitions: TransitionMap,
 
+    /// Compact copies of the document order and the ancestry of the states, created when the
+    /// session is started. See [StateTables].
+    pub state_tables: StateTables,
+
     pub script: ExecutableContentId,
 
     /// Set if this FSM was created as result of some invoke.
@@ -1170,9 +2868,38 @@ pub struct Fsm {
     pub caller_invoke_id: Option<InvokeId>,
     pub parent_session_id: Option<SessionId>,
 
-    pub timer: timer::Timer,
-
     pub generate_id_count: u32,
+
+    /// Location of the document this FSM was loaded from, a file path or an URL.\
+    /// Relative "src" values of \<invoke\> are resolved against it.
+    pub base_uri: Option<String>,
+
+    /// Subscribers for configuration changes, moved to the session if it is started.
+    /// See [Fsm::add_configuration_listener].
+    pub configuration_listeners: Vec<SyncSender<ConfigurationUpdate>>,
+
+    /// Limit of the external queue of the session. Overrides the limit of the executor,
+    /// see [crate::fsm_executor::FsmExecutor::set_queue_limit].
+    pub queue_limit: Option<QueueLimit>,
+
+    /// Locations of the datamodel whose values are captured when the session exits, e.g. "result".
+    /// See [ScxmlSession::get_final_value].
+    pub final_value_locations: Vec<String>,
+
+    /// Checks external events before they are processed. Replaces the validator of the executor,
+    /// see [crate::fsm_executor::FsmExecutor::set_event_validator].
+    pub event_validator: Option<Arc<dyn EventValidator>>,
+
+    /// Attributes of other namespaces of the \<scxml\> element. Not used for execution.
+    pub annotations: HashMap<String, String>,
+
+    /// If set, the external events and the entered states of the session are recorded,
+    /// see [crate::event_recorder].
+    pub event_recorder: Option<EventRecorder>,
+
+    /// Counters of the session, shared with [ScxmlSession::metrics].
+    #[cfg(feature = "Metrics")]
+    pub metrics: Arc<FsmMetrics>,
 }
 
 impl Default for Fsm {
@@ -1181,6 +2908,43 @@ impl Default for Fsm {
     }
 }
 
+/// Per-state data that is needed for each comparison and ancestry check of states, indexed like [Fsm::states].\
+/// Sorting configurations and walking up the ancestors are done in every microstep. With the compact
+/// vectors this doesn't need to touch the (large) [State] structures.\
+/// Empty until the FSM is started (e.g. by [Fsm::interpret]), the functions fall back to the states in this case.
+#[derive(Debug, Default, Clone)]
+pub struct StateTables {
+    /// Document position of the states, see [State::doc_id].
+    pub document_order: Vec<DocumentId>,
+    pub parent: Vec<StateId>,
+    /// Number of proper ancestors, 0 for the root.
+    pub depth: Vec<u32>,
+}
+
+impl StateTables {
+    pub fn new(states: &[State]) -> StateTables {
+        let mut depth = vec![0u32; states.len()];
+        for (idx, state) in states.iter().enumerate() {
+            let mut d = 0;
+            let mut parent = state.parent;
+            while parent != 0 {
+                d += 1;
+                parent = states[(parent - 1) as usize].parent;
+            }
+            depth[idx] = d;
+        }
+        StateTables {
+            document_order: states.iter().map(|s| s.doc_id).collect(),
+            parent: states.iter().map(|s| s.parent).collect(),
+            depth,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parent.is_empty()
+    }
+}
+
 impl Debug for Fsm {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
@@ -1243,17 +3007,108 @@ impl Fsm {
             binding: BindingType::Early,
             statesNames: StateNameMap::new(),
             executableContent: HashMap::new(),
-            timer: timer::Timer::new(),
             generate_id_count: 0,
+            base_uri: None,
+            configuration_listeners: Vec::new(),
+            queue_limit: None,
+            final_value_locations: Vec::new(),
+            event_validator: None,
+            state_tables: StateTables::default(),
+            annotations: HashMap::new(),
+            event_recorder: None,
+            #[cfg(feature = "Metrics")]
+            metrics: Arc::new(FsmMetrics::new()),
         }
     }
 
-    pub fn get_state_by_name(&self, name: &Name) -> &State {
-        self.get_state_by_id(*self.statesNames.get(name).unwrap())
+    /// Creates a copy of the model that can be executed by another session, see
+    /// [crate::document_cache::FsmTemplate].\
+    /// Settings of the session (tracer, listeners, recorder, caller and parent) are not copied.\
+    /// Returns None if some executable content can't be copied, see [ExecutableContent::get_copy].
+    pub fn copy_model(&self) -> Option<Box<Fsm>> {
+        let mut fsm = Box::new(Fsm::new());
+        fsm.datamodel.clone_from(&self.datamodel);
+        fsm.binding = self.binding;
+        fsm.version.clone_from(&self.version);
+        fsm.statesNames.clone_from(&self.statesNames);
+        for (id, content) in &self.executableContent {
+            let content: Option<Vec<Box<dyn ExecutableContent>>> = content.iter().map(|ec| ec.get_copy()).collect();
+            fsm.executableContent.insert(*id, content?);
+        }
+        fsm.name.clone_from(&self.name);
+        fsm.pseudo_root = self.pseudo_root;
+        fsm.states.clone_from(&self.states);
+        fsm.transitions.clone_from(&self.transitions);
+        fsm.state_tables.clone_from(&self.state_tables);
+        fsm.script = self.script;
+        fsm.generate_id_count = self.generate_id_count;
+        fsm.base_uri.clone_from(&self.base_uri);
+        fsm.queue_limit.clone_from(&self.queue_limit);
+        fsm.final_value_locations
+            .clone_from(&self.final_value_locations);
+        fsm.event_validator.clone_from(&self.event_validator);
+        fsm.annotations.clone_from(&self.annotations);
+        Some(fsm)
+    }
+
+    /// Gets the value of an attribute of another namespace of a state, e.g. "ui:x".\
+    /// Returns None if the state or the attribute doesn't exist.
+    pub fn state_annotation(&self, state_name: &str, name: &str) -> Option<&str> {
+        self.statesNames
+            .get(state_name)
+            .and_then(|sid| self.get_state_by_id(*sid).annotation(name))
+    }
+
+    /// Subscribes to changes of the active configuration of the session that is started with this FSM.\
+    /// See [ScxmlSession::on_configuration_change].
+    pub fn add_configuration_listener(&mut self, listener: SyncSender<ConfigurationUpdate>) {
+        self.configuration_listeners.push(listener);
+    }
+
+    /// Sends the states that were entered and exited since the last call to the subscribers.
+    fn notify_configuration_change(&self, datamodel: &mut dyn Datamodel) {
+        let mut global = get_global!(datamodel);
+        if global.configuration_listeners.is_empty() {
+            return;
+        }
+        let change = std::mem::take(&mut global.configuration_change);
+        if change.entered.is_empty() && change.exited.is_empty() {
+            return;
+        }
+        global.configuration_step += 1;
+        let update = ConfigurationUpdate {
+            step: global.configuration_step,
+            entered: change
+                .entered
+                .iter()
+                .map(|sid| self.get_state_by_id(*sid).name.clone())
+                .collect(),
+            exited: change
+                .exited
+                .iter()
+                .map(|sid| self.get_state_by_id(*sid).name.clone())
+                .collect(),
+        };
+        let mut dropped = 0;
+        global
+            .configuration_listeners
+            .retain(|listener| match listener.try_send(update.clone()) {
+                Ok(_) => true,
+                Err(TrySendError::Full(_)) => {
+                    dropped += 1;
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            });
+        global.configuration_updates_dropped += dropped;
+    }
+
+    pub fn get_state_by_name(&self, name: &Name) -> Option<&State> {
+        Some(self.get_state_by_id(*self.statesNames.get(name)?))
     }
 
-    pub fn get_state_by_name_mut(&mut self, name: &Name) -> &mut State {
-        self.get_state_by_id_mut(*self.statesNames.get(name).unwrap())
+    pub fn get_state_by_name_mut(&mut self, name: &Name) -> Option<&mut State> {
+        Some(self.get_state_by_id_mut(*self.statesNames.get(name)?))
     }
 
     /// Gets a state by id.
@@ -1277,14 +3132,20 @@ impl Fsm {
     }
 
     fn state_document_order(&self, sid1: &StateId, sid2: &StateId) -> std::cmp::Ordering {
-        // TODO: Optimize! Do that state-ids == index in fsm.states.
-        let s1 = self.get_state_by_id(*sid1);
-        let s2 = self.get_state_by_id(*sid2);
+        match self.state_tables.document_order.get((*sid1 - 1) as usize) {
+            Some(d1) => d1.cmp(&self.state_tables.document_order[(*sid2 - 1) as usize]),
+            None => self
+                .get_state_by_id(*sid1)
+                .doc_id
+                .cmp(&self.get_state_by_id(*sid2).doc_id),
+        }
+    }
 
-        match s1.doc_id {
-            di if di > s2.doc_id => std::cmp::Ordering::Greater,
-            di if di == s2.doc_id => std::cmp::Ordering::Equal,
-            _ => std::cmp::Ordering::Less,
+    /// Gets the parent of the state, 0 for the root.
+    fn parent_of(&self, state: StateId) -> StateId {
+        match self.state_tables.parent.get((state - 1) as usize) {
+            Some(parent) => *parent,
+            None => self.get_state_by_id(state).parent,
         }
     }
 
@@ -1359,37 +3220,22 @@ impl Fsm {
     pub fn interpret(&mut self, datamodel: &mut dyn Datamodel) {
         #[cfg(feature = "Trace_Method")]
         self.tracer.enter_method("interpret");
-        if !self.valid() {
-            self.failWithError();
-            return;
+        if self.enter_initial_configuration(datamodel) {
+            self.mainEventLoop(datamodel);
         }
-        self.expandScxmlSource();
-        {
-            datamodel.clear();
-
-            // Initialize session variables "_name" and "_sessionid"
-
-            let session_id = datamodel.global_s().lock().unwrap().session_id;
-            datamodel.initialize_read_only(SESSION_ID_VARIABLE_NAME, Data::Integer(session_id as i64));
-            // TODO :Escape name
-            datamodel.initialize_read_only(SESSION_NAME_VARIABLE_NAME, Data::String(self.name.clone()));
-
-            {
-                let mut gd = get_global!(datamodel);
-                gd.internalQueue.clear();
-                gd.historyValue.clear();
-                gd.running = true;
-            }
-
-            datamodel.add_functions(self);
-            datamodel.set_ioprocessors();
+        #[cfg(feature = "Trace_Method")]
+        self.tracer.exit_method("interpret");
+    }
 
-            self.initialize_data_models_recursive(
-                datamodel,
-                self.pseudo_root,
-                self.binding == BindingType::Early,
-            );
+    /// Part of [Fsm::interpret]: Everything before the main event loop is started.\
+    /// Returns false if the document is not valid.
+    pub(crate) fn enter_initial_configuration(&mut self, datamodel: &mut dyn Datamodel) -> bool {
+        if !self.valid() {
+            self.failWithError(datamodel);
+            return false;
         }
+        self.expandScxmlSource();
+        self.initialize_session(datamodel);
         self.executeGlobalScriptElement(datamodel);
 
         let mut inital_states = List::new();
@@ -1398,42 +3244,409 @@ impl Fsm {
             inital_states.push(itid);
         }
         self.enterStates(datamodel, &inital_states);
-        self.mainEventLoop(datamodel);
+        self.notify_configuration_change(datamodel);
+        true
+    }
+
+    /// Variant of [Fsm::interpret] that resumes the FSM from a snapshot.\
+    /// See [crate::fsm_snapshot] for the restore policy.
+    pub fn interpret_from_snapshot(&mut self, datamodel: &mut dyn Datamodel, snapshot: &FsmSnapshot) {
         #[cfg(feature = "Trace_Method")]
-        self.tracer.exit_method("interpret");
+        self.tracer.enter_method("interpret_from_snapshot");
+        if self.enter_snapshot_configuration(datamodel, snapshot) {
+            self.mainEventLoop(datamodel);
+        }
+        #[cfg(feature = "Trace_Method")]
+        self.tracer.exit_method("interpret_from_snapshot");
     }
 
-    /// #Actual implementation:
-    /// TODO
-    /// * check if all state/transition references are correct (all states have a document-id)
-    /// * check if all special scxml conditions are satisfied.
-    fn valid(&self) -> bool {
-        for state in &self.states {
-            if state.doc_id == 0 {
-                #[cfg(feature = "Trace")]
-                self.tracer
-                    .trace(format!("Referenced state '{}' is not declared", state.name).as_str());
-                return false;
-            }
+    /// Part of [Fsm::interpret_from_snapshot]: Restores the snapshot.\
+    /// Returns false if the document is not valid.
+    pub(crate) fn enter_snapshot_configuration(
+        &mut self,
+        datamodel: &mut dyn Datamodel,
+        snapshot: &FsmSnapshot,
+    ) -> bool {
+        if !self.valid() {
+            self.failWithError(datamodel);
+            return false;
         }
+        self.expandScxmlSource();
+        self.initialize_session(datamodel);
+        self.restore_entered_states(datamodel, snapshot);
+        self.restore_runtime_state(datamodel, snapshot, true);
         true
     }
 
-    /// #Actual implementation:
-    /// Throws a panic
-    #[allow(non_snake_case)]
-    fn failWithError(&self) {
-        error!("FSM has failed");
+    /// Restores the "first entry" flags and initializes the data of these states.
+    fn restore_entered_states(&mut self, datamodel: &mut dyn Datamodel, snapshot: &FsmSnapshot) {
+        for name in &snapshot.entered_states {
+            let sid = self.statesNames[name];
+            self.get_state_by_id_mut(sid).isFirstEntry = false;
+            if self.binding == BindingType::Late {
+                datamodel.initializeDataModel(self, sid, true);
+            }
+        }
     }
 
-    /// #Actual implementation:
-    /// This method is called on the fsm model, after
-    /// the xml document was processed. It should check if all References to states are fulfilled.
-    /// After this method all "StateId" or "TransactionId" shall be valid and have to lead to a panic.
-    #[allow(non_snake_case)]
-    fn expandScxmlSource(&mut self) {}
+    /// Restores data, configuration, history and internal queue of the snapshot.\
+    /// If "restart_invokes" is set, the invokes of the active states are started again.
+    fn restore_runtime_state(&mut self, datamodel: &mut dyn Datamodel, snapshot: &FsmSnapshot, restart_invokes: bool) {
+        let mut data = HashMap::new();
+        for (name, value) in &snapshot.data {
+            data.insert(name.clone(), create_data_arc(value.to_data()));
+        }
+        datamodel.set_from_state_data(&data, true);
 
-    #[allow(non_snake_case)]
+        {
+            let mut gd = get_global!(datamodel);
+            for name in &snapshot.configuration {
+                let sid = self.statesNames[name];
+                gd.configuration.add(sid);
+                if restart_invokes && self.get_state_by_id(sid).invoke.size() > 0 {
+                    gd.statesToInvoke.add(sid);
+                }
+            }
+            for (name, states) in &snapshot.history {
+                let mut history = OrderedSet::new();
+                for state_name in states {
+                    history.add(self.statesNames[state_name]);
+                }
+                gd.historyValue.put_move(self.statesNames[name], history);
+            }
+            for event in &snapshot.internal_queue {
+                gd.internalQueue.enqueue(event.to_event());
+            }
+        }
+    }
+
+    /// Captures the current runtime state.\
+    /// Shall only be called between macrosteps, see [ScxmlSession::take_snapshot].
+    pub fn take_snapshot(&self, datamodel: &mut dyn Datamodel) -> FsmSnapshot {
+        let mut snapshot = FsmSnapshot {
+            name: self.name.clone(),
+            ..Default::default()
+        };
+        for state in &self.states {
+            if !state.isFirstEntry {
+                snapshot.entered_states.push(state.name.clone());
+            }
+            let mut names: Vec<&String> = state.data.keys().collect();
+            names.sort();
+            for name in names {
+                if let Ok(value) = datamodel.get_by_location(name) {
+                    snapshot
+                        .data
+                        .push((name.clone(), SnapshotData::from_data_arc(&value)));
+                }
+            }
+        }
+        let gd = get_global!(datamodel);
+        for sid in gd.configuration.iterator() {
+            snapshot
+                .configuration
+                .push(self.get_state_by_id(*sid).name.clone());
+        }
+        for (sid, states) in &gd.historyValue.data {
+            snapshot.history.push((
+                self.get_state_by_id(*sid).name.clone(),
+                states
+                    .iterator()
+                    .map(|s| self.get_state_by_id(*s).name.clone())
+                    .collect(),
+            ));
+        }
+        snapshot.history.sort_by(|h1, h2| h1.0.cmp(&h2.0));
+        for event in &gd.internalQueue.data {
+            snapshot
+                .internal_queue
+                .push(SnapshotEvent::from_event(event));
+        }
+        snapshot
+    }
+
+    /// Replaces the model by the new document, see [ScxmlSession::reload].\
+    /// Shall only be called between macrosteps. If an error is returned, the old model is unchanged.
+    pub(crate) fn reload(&mut self, datamodel: &mut dyn Datamodel, mut fsm: Box<Fsm>) -> Result<(), String> {
+        if !fsm.datamodel.eq_ignore_ascii_case(&self.datamodel) {
+            return Err(format!(
+                "The datamodel can't be changed from '{}' to '{}'",
+                self.datamodel, fsm.datamodel
+            ));
+        }
+        let errors = validate(&fsm);
+        if !errors.is_empty() {
+            return Err(format!(
+                "The new document is not valid: {}",
+                vec_to_string(&errors)
+            ));
+        }
+        if !get_global!(datamodel).child_sessions.is_empty() {
+            return Err("Invoked sessions are running".to_string());
+        }
+        fsm.expandScxmlSource();
+
+        // The active states of the new document: all states that are kept and their ancestors.
+        let mut removed = Vec::new();
+        let mut kept = Vec::new();
+        for sid in get_global!(datamodel).configuration.iterator() {
+            match fsm.statesNames.get(&self.get_state_by_id(*sid).name) {
+                None => removed.push(*sid),
+                Some(new_sid) => kept.push(*new_sid),
+            }
+        }
+        let mut configuration: HashSet<StateId> = kept.iter().cloned().collect();
+        let mut entered = Vec::new();
+        for sid in &kept {
+            let mut parent = fsm.get_state_by_id(*sid).parent;
+            while parent != 0 && parent != fsm.pseudo_root {
+                if configuration.insert(parent) {
+                    entered.push(parent);
+                }
+                parent = fsm.get_state_by_id(parent).parent;
+            }
+        }
+        fsm.check_reload_configuration(&configuration)?;
+
+        // Exit the states that are not part of the new document with the old model.
+        removed.sort_by(|s1, s2| self.state_exit_order(s1, s2));
+        for sid in &removed {
+            {
+                let mut gd = get_global!(datamodel);
+                gd.statesToInvoke.delete(sid);
+                gd.exit_state(*sid);
+            }
+            for ec in self.get_state_by_id(*sid).onexit.clone() {
+                self.executeContent(datamodel, ec);
+            }
+        }
+        self.notify_configuration_change(datamodel);
+        let mut snapshot = self.take_snapshot(datamodel);
+
+        // Keep the parts of the FSM that belong to the session.
+        #[cfg(feature = "Trace")]
+        std::mem::swap(&mut fsm.tracer, &mut self.tracer);
+        fsm.caller_invoke_id = self.caller_invoke_id.take();
+        fsm.parent_session_id = self.parent_session_id.take();
+        fsm.generate_id_count = fsm.generate_id_count.max(self.generate_id_count);
+        fsm.queue_limit = self.queue_limit.take();
+        fsm.event_validator = self.event_validator.take();
+        fsm.event_recorder = self.event_recorder.take();
+        #[cfg(feature = "Metrics")]
+        {
+            fsm.metrics = self.metrics.clone();
+        }
+        std::mem::swap(self, fsm.as_mut());
+
+        // Restore the remaining runtime state with the new model, like a snapshot.
+        let declared: HashSet<&String> = self.states.iter().flat_map(|s| s.data.keys()).collect();
+        snapshot.data.retain(|(name, _)| declared.contains(name));
+        snapshot
+            .entered_states
+            .retain(|name| self.statesNames.contains_key(name));
+        snapshot.history.retain(|(name, states)| {
+            self.statesNames.contains_key(name) && states.iter().all(|s| self.statesNames.contains_key(s))
+        });
+        self.initialize_session(datamodel);
+        get_global!(datamodel).configuration.clear();
+        self.restore_entered_states(datamodel, &snapshot);
+        self.executeGlobalScriptElement(datamodel);
+        self.restore_runtime_state(datamodel, &snapshot, false);
+
+        entered.sort_by(|s1, s2| self.state_entry_order(s1, s2));
+        for sid in &entered {
+            get_global!(datamodel).enter_state(*sid);
+            if self.binding == BindingType::Late && self.get_state_by_id(*sid).isFirstEntry {
+                datamodel.initializeDataModel(self, *sid, true);
+                self.get_state_by_id_mut(*sid).isFirstEntry = false;
+            }
+            for ec in self.get_state_by_id(*sid).onentry.clone() {
+                self.executeContent(datamodel, ec);
+            }
+            if self.get_state_by_id(*sid).invoke.size() > 0 {
+                get_global!(datamodel).statesToInvoke.add(*sid);
+            }
+        }
+        self.notify_configuration_change(datamodel);
+        Ok(())
+    }
+
+    /// Checks that the states form a legal configuration of this model, see [Fsm::reload].
+    fn check_reload_configuration(&self, configuration: &HashSet<StateId>) -> Result<(), String> {
+        let mut active: Vec<StateId> = configuration.iter().cloned().collect();
+        active.push(self.pseudo_root);
+        for sid in active {
+            let state = self.get_state_by_id(sid);
+            let active_children: Vec<&StateId> = state
+                .states
+                .iter()
+                .filter(|child| configuration.contains(child))
+                .collect();
+            if state.is_parallel {
+                if let Some(region) = state
+                    .states
+                    .iter()
+                    .find(|child| !configuration.contains(child))
+                {
+                    return Err(format!(
+                        "Region '{}' of the active parallel state '{}' would not be active",
+                        self.get_state_by_id(*region).name,
+                        state.name
+                    ));
+                }
+            } else if !state.states.is_empty() && active_children.len() != 1 {
+                return Err(if active_children.is_empty() {
+                    if sid == self.pseudo_root {
+                        "No active state is part of the new document".to_string()
+                    } else {
+                        format!(
+                            "The active state '{}' would have no active child state",
+                            state.name
+                        )
+                    }
+                } else {
+                    format!(
+                        "The active states {} would be siblings in '{}'",
+                        vec_to_string(
+                            &active_children
+                                .iter()
+                                .map(|child| self.get_state_by_id(**child).name.clone())
+                                .collect::<Vec<String>>()
+                        ),
+                        state.name
+                    )
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Initialize the global data structures, including the data model.
+    fn initialize_session(&mut self, datamodel: &mut dyn Datamodel) {
+        {
+            datamodel.clear();
+
+            // Initialize session variables "_name" and "_sessionid"
+
+            let session_id = datamodel.global_s().lock().unwrap().session_id;
+            datamodel.initialize_read_only(
+                SESSION_ID_VARIABLE_NAME,
+                Data::String(session_id.to_string()),
+            );
+            // TODO :Escape name
+            datamodel.initialize_read_only(SESSION_NAME_VARIABLE_NAME, Data::String(self.name.clone()));
+            let platform_data = self.create_platform_data(datamodel);
+            datamodel.initialize_read_only_arc(PLATFORM_VARIABLE_NAME, platform_data);
+
+            {
+                let mut gd = get_global!(datamodel);
+                gd.internalQueue.clear();
+                gd.historyValue.clear();
+                gd.running = true;
+            }
+
+            datamodel.add_functions(self);
+            datamodel.set_ioprocessors();
+            datamodel.precompile(self);
+
+            // A FSM may be interpreted more than once, e.g. by invoking the same document again.
+            for state in &mut self.states {
+                state.isFirstEntry = true;
+            }
+            self.initialize_data_models_recursive(
+                datamodel,
+                self.pseudo_root,
+                self.binding == BindingType::Early,
+            );
+            if self.binding == BindingType::Late {
+                // With late binding all variables are created now as "undefined".
+                // The \<scxml\> element is entered now, so the top-level data is assigned.
+                let root = self.pseudo_root;
+                self.get_state_by_id_mut(root).isFirstEntry = false;
+                datamodel.initializeDataModel(self, root, true);
+            }
+        }
+    }
+
+    /// Creates the content of the system variable "_x".\
+    /// All members are read-only.
+    fn create_platform_data(&self, datamodel: &mut dyn Datamodel) -> DataArc {
+        let gd = get_global!(datamodel);
+        let read_only = |data: Data| {
+            let mut data_arc = create_data_arc(data);
+            data_arc.set_readonly(true);
+            data_arc
+        };
+        let mut io_processors: Vec<&String> = gd.io_processors.keys().collect();
+        io_processors.sort();
+
+        let mut platform_data = HashMap::new();
+        platform_data.insert(
+            PLATFORM_VARIABLE_FIELD_SESSION_ID.to_string(),
+            read_only(Data::Integer(gd.session_id as i64)),
+        );
+        platform_data.insert(
+            PLATFORM_VARIABLE_FIELD_PARENT_SESSION_ID.to_string(),
+            read_only(match gd.parent_session_id {
+                None => Data::Null(),
+                Some(id) => Data::Integer(id as i64),
+            }),
+        );
+        platform_data.insert(
+            PLATFORM_VARIABLE_FIELD_INVOKE_ID.to_string(),
+            read_only(match &gd.caller_invoke_id {
+                None => Data::Null(),
+                Some(id) => Data::String(id.clone()),
+            }),
+        );
+        platform_data.insert(
+            PLATFORM_VARIABLE_FIELD_IO_PROCESSORS.to_string(),
+            read_only(Data::Array(
+                io_processors
+                    .into_iter()
+                    .map(|name| read_only(Data::String(name.clone())))
+                    .collect(),
+            )),
+        );
+        platform_data.insert(
+            PLATFORM_VARIABLE_FIELD_VERSION.to_string(),
+            read_only(Data::String(env!("CARGO_PKG_VERSION").to_string())),
+        );
+        read_only(Data::Map(platform_data))
+    }
+
+    /// #Actual implementation:
+    /// Checks the model with [validate] and logs all violations.
+    fn valid(&self) -> bool {
+        let errors = validate(self);
+        for e in &errors {
+            error!("FSM '{}' is not valid: {}", self.name, e);
+        }
+        errors.is_empty()
+    }
+
+    /// #Actual implementation:
+    /// Logs the failure and sends "error.platform" to the parent session, if the session was invoked.
+    #[allow(non_snake_case)]
+    fn failWithError(&self, datamodel: &mut dyn Datamodel) {
+        error!("FSM has failed");
+        let executor = get_global!(datamodel).executor.clone();
+        if let Some(executor) = executor {
+            notify_parent_of_failure(&executor, self.parent_session_id, &self.caller_invoke_id);
+        }
+    }
+
+    /// #Actual implementation:
+    /// This method is called on the fsm model, after
+    /// the xml document was processed. It should check if all References to states are fulfilled.
+    /// After this method all "StateId" or "TransactionId" shall be valid and have to lead to a panic.\
+    /// Creates the [StateTables] of the model.
+    #[allow(non_snake_case)]
+    fn expandScxmlSource(&mut self) {
+        self.state_tables = StateTables::new(&self.states);
+    }
+
+    #[allow(non_snake_case)]
     fn executeGlobalScriptElement(&mut self, datamodel: &mut dyn Datamodel) {
         #[cfg(feature = "Trace_Method")]
         self.tracer.enter_method("executeGlobalScriptElement");
@@ -1521,46 +3734,74 @@ impl Fsm {
         #[cfg(feature = "Trace_Method")]
         self.tracer.enter_method("mainEventLoop");
 
-        let caller_invoke_id = {
-            match &self.caller_invoke_id {
-                None => "".to_string(),
-                Some(cid) => cid.clone(),
+        while get_global!(datamodel).running {
+            self.complete_macrostep(datamodel);
+            // either we're in a final state, and we break out of the loop
+            if !get_global!(datamodel).running {
+                break;
             }
-        };
+            // or we've completed a macrostep, so we start a new macrostep by waiting for an external event
+            let externalEvent = self.dequeue_external_event(datamodel);
+            self.process_external_event(datamodel, externalEvent);
+        }
+        // End of outer while running loop.  If we get here, we have reached a top-level final state or have been cancelled
+        self.exit_session(datamodel);
+        #[cfg(feature = "Trace_Method")]
+        self.tracer.exit_method("mainEventLoop");
+    }
 
-        while get_global!(datamodel).running {
+    /// Part of [Fsm::mainEventLoop]:\
+    /// Takes all internally enabled transitions and starts the invokes until the macrostep is complete.
+    #[allow(non_snake_case)]
+    pub(crate) fn complete_macrostep(&mut self, datamodel: &mut dyn Datamodel) {
+        #[cfg(feature = "Metrics")]
+        let _macrostep = self.metrics.macrostep();
+        loop {
             let mut enabledTransitions;
             let mut macrostepDone = false;
             // Here we handle eventless transitions and transitions
             // triggered by internal events until macrostep is complete
             while get_global!(datamodel).running && !macrostepDone {
                 enabledTransitions = self.selectEventlessTransitions(datamodel);
+                let mut triggeringEvent = None;
                 if enabledTransitions.isEmpty() {
-                    if get_global!(datamodel).internalQueue.isEmpty() {
-                        macrostepDone = true;
-                    } else {
+                    let internalEvent = {
+                        let mut global = get_global!(datamodel);
+                        #[cfg(feature = "Metrics")]
+                        if !global.internalQueue.isEmpty() {
+                            self.metrics.internal_event(global.internalQueue.data.len());
+                        }
+                        global.internalQueue.dequeue()
+                    };
+                    if let Some(internalEvent) = internalEvent {
                         #[cfg(feature = "Trace_Method")]
                         self.tracer.enter_method("internalQueue.dequeue");
-
-                        let internalEvent = { get_global!(datamodel).internalQueue.dequeue() };
                         #[cfg(feature = "Trace_Method")]
                         self.tracer.exit_method("internalQueue.dequeue");
                         #[cfg(feature = "Trace_Event")]
-                        self.tracer.event_internal_received(&internalEvent);
+                        self.tracer.trace_record(&TraceRecord::EventReceived {
+                            event: internalEvent.clone(),
+                            internal: true,
+                        });
                         // TODO: Optimize it, set event only once
                         datamodel.set_event(&internalEvent);
                         enabledTransitions = self.selectTransitions(datamodel, &internalEvent);
+                        triggeringEvent = Some(internalEvent);
+                    } else {
+                        macrostepDone = true;
                     }
                 }
                 if !enabledTransitions.isEmpty() {
-                    self.microstep(datamodel, &enabledTransitions.toList())
+                    self.microstep(
+                        datamodel,
+                        &enabledTransitions.toList(),
+                        triggeringEvent.as_ref(),
+                    )
                 }
             }
-            // either we're in a final state, and we break out of the loop
             if !get_global!(datamodel).running {
-                break;
+                return;
             }
-            // or we've completed a macrostep, so we start a new macrostep by waiting for an external event
             // Here we invoke whatever needs to be invoked. The implementation of 'invoke' is platform-specific
             let sortedStatesToInvoke = get_global!(datamodel)
                 .statesToInvoke
@@ -1572,139 +3813,449 @@ impl Fsm {
                 }
             }
 
-            let externalEvent;
-            {
-                let externalQueue_receiver = {
-                    let mut global_lock = get_global!(datamodel);
-
-                    // let gdb = datamodel.global();
-                    global_lock.statesToInvoke.clear();
-                    // Invoking may have raised internal error events and we iterate to handle them
-                    if !global_lock.internalQueue.isEmpty() {
-                        continue;
-                    }
-                    global_lock.externalQueue.receiver.clone()
-                };
+            let mut global_lock = get_global!(datamodel);
+            global_lock.statesToInvoke.clear();
+            // Invoking may have raised internal error events and we iterate to handle them
+            if global_lock.internalQueue.isEmpty() {
+                return;
+            }
+        }
+    }
+
+    /// Part of [Fsm::mainEventLoop]:\
+    /// A blocking wait for the next external event that shall be processed.
+    #[allow(non_snake_case)]
+    fn dequeue_external_event(&mut self, datamodel: &mut dyn Datamodel) -> Box<Event> {
+        let externalQueue_receiver = get_global!(datamodel).externalQueue.receiver.clone();
+
+        // W3C says:
+        //   A blocking wait for an external event.  Alternatively, if we have been invoked
+        //   our parent session also might cancel us.  The mechanism for this is platform specific,
+        //   but here we assume it’s a special event we receive
+        #[cfg(feature = "Trace_Method")]
+        self.tracer.enter_method("externalQueue.dequeue");
+        let externalEvent = loop {
+            #[cfg(feature = "Metrics")]
+            self.metrics
+                .external_queue_len(externalQueue_receiver.len());
+            let externalEventTmp = externalQueue_receiver.recv().unwrap();
+            #[cfg(feature = "Trace")]
+            self.trace_dropped_events(&externalQueue_receiver);
+            if self.accept_external_event(datamodel, &externalEventTmp) {
+                self.record_external_event(datamodel, &externalEventTmp);
+                break externalEventTmp;
+            }
+        };
+        #[cfg(feature = "Trace_Method")]
+        self.tracer.exit_method("externalQueue.dequeue");
+        externalEvent
+    }
+
+    /// Gets the next external event that shall be processed without blocking.\
+    /// Returns None if the external queue is empty.
+    pub(crate) fn try_dequeue_external_event(&mut self, datamodel: &mut dyn Datamodel) -> Option<Box<Event>> {
+        let receiver = get_global!(datamodel).externalQueue.receiver.clone();
+        loop {
+            #[cfg(feature = "Metrics")]
+            self.metrics.external_queue_len(receiver.len());
+            let event = receiver.try_recv()?;
+            #[cfg(feature = "Trace")]
+            self.trace_dropped_events(&receiver);
+            if self.accept_external_event(datamodel, &event) {
+                self.record_external_event(datamodel, &event);
+                return Some(event);
+            }
+        }
+    }
+
+    /// Adds the event to the [Fsm::event_recorder].\
+    /// Events that a replay creates again are skipped: control events of the platform, events of
+    /// invoked sessions and events the session has sent to itself.
+    fn record_external_event(&self, datamodel: &mut dyn Datamodel, event: &Event) {
+        if let Some(recorder) = &self.event_recorder {
+            let is_control = event.etype == EventType::platform
+                && [
+                    EVENT_SYNC_SESSION,
+                    EVENT_SNAPSHOT_SESSION,
+                    EVENT_EVALUATE_SESSION,
+                    EVENT_RELOAD_SESSION,
+                    EVENT_TRACE_SESSION,
+                    EVENT_CLEAR_HISTORY_SESSION,
+                    EVENT_DELAYED_SEND,
+                ]
+                .contains(&event.name.as_str());
+            let global = get_global!(datamodel);
+            let own_location = format!("{}{}", SCXML_TARGET_SESSION_ID_PREFIX, global.session_id);
+            if !is_control && event.invoke_id.is_none() && event.origin.as_ref() != Some(&own_location) {
+                recorder.event(global.now_ms(), event);
+            }
+        }
+    }
+
+    /// Reports external events that were dropped by a bounded queue since the last call.
+    #[cfg(feature = "Trace")]
+    fn trace_dropped_events(&self, receiver: &QueueReceiver<Box<Event>>) {
+        let count = receiver.take_dropped();
+        if count > 0 {
+            self.tracer
+                .trace_record(&TraceRecord::EventsDropped { count });
+        }
+    }
 
+    /// Checks if an external event shall be processed.
+    pub(crate) fn accept_external_event(&self, datamodel: &mut dyn Datamodel, event: &Event) -> bool {
+        if let Some(invoke_id) = &event.invoke_id {
+            if self.caller_invoke_id.as_deref().unwrap_or_default() != invoke_id {
                 // W3C says:
-                //   A blocking wait for an external event.  Alternatively, if we have been invoked
-                //   our parent session also might cancel us.  The mechanism for this is platform specific,
-                //   but here we assume it’s a special event we receive
-                #[cfg(feature = "Trace_Method")]
-                self.tracer.enter_method("externalQueue.dequeue");
-                loop {
-                    let externalEventTmp = externalQueue_receiver.lock().unwrap().recv().unwrap();
-                    if externalEventTmp.name.starts_with(EVENT_DONE_INVOKE_PREFIX) {
-                        externalEvent = externalEventTmp;
-                        break;
+                //    Once it cancels the invoked session, the Processor MUST ignore any events
+                //    it receives from that session. In particular it MUST NOT not insert them
+                //    into the external event queue of the invoking session.
+                // Check if the session is active. This includes a "done.invoke" event that
+                // was sent by a child that was already cancelled.
+                if !get_global!(datamodel)
+                    .child_sessions
+                    .contains_key(invoke_id)
+                {
+                    #[cfg(feature = "Debug")]
+                    debug!("Ignore event {} from invoke {}", event.name, invoke_id);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Passes the delayed sends whose delay has elapsed to their I/O processors.\
+    /// Called for [EVENT_DELAYED_SEND] events. The global data is not locked while the I/O processors
+    /// are called.
+    fn send_delayed_events(&mut self, datamodel: &mut dyn Datamodel) {
+        let receiver = get_global!(datamodel).delayedQueue.receiver.clone();
+        loop {
+            let delayed = match receiver.try_recv() {
+                Some(delayed) => delayed,
+                None => return,
+            };
+            // Sends that were cancelled after the delay has elapsed are discarded.
+            if !get_global!(datamodel).remove_delayed_send(delayed.send_id.as_deref(), delayed.timer_number) {
+                #[cfg(feature = "Debug")]
+                debug!("Discard cancelled delayed send '{}'", delayed.event.name);
+                continue;
+            }
+            get_global!(datamodel).notify_event_subscribers(&delayed.event);
+            datamodel.send_with_processor(
+                &delayed.io_processor,
+                delayed.target.as_str(),
+                delayed.event,
+            );
+        }
+    }
+
+    /// Enables or disables the trace modes given by a [EVENT_TRACE_SESSION] event.
+    #[cfg(feature = "Trace")]
+    fn apply_trace_event(&mut self, datamodel: &mut dyn Datamodel, event: &Event) {
+        for param in event.param_values.iter().flatten() {
+            match (TraceMode::from_str(param.name.as_str()), &param.value) {
+                (Ok(mode), Data::Boolean(true)) => self.tracer.enable_trace(mode),
+                (Ok(mode), Data::Boolean(false)) => self.tracer.disable_trace(mode),
+                _ => {
+                    error!("Illegal trace mode '{}'={}", param.name, param.value);
+                }
+            }
+        }
+        get_global!(datamodel).trace_modes = self.tracer.trace_modes();
+    }
+
+    /// Part of [Fsm::mainEventLoop]:\
+    /// Processes an external event, including the \<finalize\> and autoforward handling.
+    #[allow(non_snake_case)]
+    pub(crate) fn process_external_event(&mut self, datamodel: &mut dyn Datamodel, externalEvent: Box<Event>) {
+        #[cfg(feature = "Trace_Event")]
+        self.tracer.event_external_received(&externalEvent);
+        if self.isCancelEvent(&externalEvent) {
+            get_global!(datamodel).running = false;
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_SNAPSHOT_SESSION) {
+            if let Some(snapshot_id) = &externalEvent.sendid {
+                let requester = get_global!(datamodel)
+                    .snapshot_requests
+                    .as_mut()
+                    .and_then(|sr| sr.remove(snapshot_id));
+                if let Some(requester) = requester {
+                    let _ = requester.send(self.take_snapshot(datamodel));
+                }
+            }
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_EVALUATE_SESSION) {
+            if let Some(evaluation_id) = &externalEvent.sendid {
+                let request = get_global!(datamodel)
+                    .evaluation_requests
+                    .as_mut()
+                    .and_then(|er| er.remove(evaluation_id));
+                if let Some((expression, requester)) = request {
+                    let queued = get_global!(datamodel).internalQueue.data.len();
+                    let result = datamodel
+                        .execute(&str_to_source(expression.as_str()))
+                        .map(|value| value.lock().unwrap().clone());
+                    // Errors of the evaluation shall not influence the session. Events that are
+                    // explicitly raised by the expression (e.g. by an action) are kept.
+                    {
+                        let mut global = get_global!(datamodel);
+                        let added = global.internalQueue.data.split_off(queued);
+                        global
+                            .internalQueue
+                            .data
+                            .extend(added.into_iter().filter(|e| !e.name.starts_with("error.")));
                     }
-                    if let Some(invoke_id) = &externalEventTmp.invoke_id {
-                        if caller_invoke_id.ne(invoke_id) {
-                            // W3C says:
-                            //    Once it cancels the invoked session, the Processor MUST ignore any events
-                            //    it receives from that session. In particular it MUST NOT not insert them
-                            //    into the external event queue of the invoking session.
-                            // Check if the session is active.
-                            if get_global!(datamodel)
-                                .child_sessions
-                                .contains_key(invoke_id)
-                            {
-                                externalEvent = externalEventTmp;
-                                break;
-                            } else {
-                                #[cfg(feature = "Debug")]
-                                debug!(
-                                    "Ignore event {} from invoke {}",
-                                    externalEventTmp.name, invoke_id
-                                );
-                            }
-                        } else {
-                            externalEvent = externalEventTmp;
-                            break;
-                        }
-                    } else {
-                        externalEvent = externalEventTmp;
-                        break;
+                    let _ = requester.send(result);
+                }
+            }
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_RELOAD_SESSION) {
+            if let Some(reload_id) = &externalEvent.sendid {
+                let request = get_global!(datamodel)
+                    .reload_requests
+                    .as_mut()
+                    .and_then(|rr| rr.remove(reload_id));
+                if let Some((fsm, requester)) = request {
+                    let result = self.reload(datamodel, fsm);
+                    if let Err(err) = &result {
+                        warn!("Reload of '{}' aborted: {}", self.name, err);
                     }
+                    let _ = requester.send(result);
                 }
-                #[cfg(feature = "Trace_Method")]
-                self.tracer.exit_method("externalQueue.dequeue");
-                #[cfg(feature = "Trace_Event")]
-                self.tracer.event_external_received(&externalEvent);
-                if self.isCancelEvent(&externalEvent) {
-                    get_global!(datamodel).running = false;
-                    continue;
+            }
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_SYNC_SESSION) {
+            // The macrostep of the preceding event is done, inform the waiting caller.
+            if let Some(sync_id) = &externalEvent.sendid {
+                if let Some(ack) = get_global!(datamodel)
+                    .sync_acks
+                    .as_mut()
+                    .and_then(|sa| sa.remove(sync_id))
+                {
+                    let _ = ack.send(());
                 }
+            }
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_DELAYED_SEND) {
+            self.send_delayed_events(datamodel);
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_TRACE_SESSION) {
+            #[cfg(feature = "Trace")]
+            self.apply_trace_event(datamodel, &externalEvent);
+            return;
+        }
+        if externalEvent.etype == EventType::platform && externalEvent.name.eq(EVENT_CLEAR_HISTORY_SESSION) {
+            get_global!(datamodel).historyValue.clear();
+            return;
+        }
+
+        if let Some(validator) = &self.event_validator {
+            if let Err(message) = validator.validate(&externalEvent) {
+                warn!("Event '{}' rejected: {}", externalEvent.name, message);
+                let mut error = Event::error("platform");
+                error.param_values = Some(vec![
+                    ParamPair::new_moved(
+                        "event".to_string(),
+                        Data::String(externalEvent.name.clone()),
+                    ),
+                    ParamPair::new_moved("message".to_string(), Data::String(message)),
+                ]);
+                self.enqueue_internal(datamodel, error);
+                return;
+            }
+        }
+        #[cfg(feature = "Metrics")]
+        self.metrics.external_event();
 
+        let mut toFinalize: ExecutableContentId = 0;
+        let mut toForward: Vec<InvokeId> = Vec::new();
+        let mut toWriteBack: Vec<(ScxmlSession, Vec<Parameter>)> = Vec::new();
+        {
+            let mut global = get_global!(datamodel);
+            if let Some(invokeId) = &externalEvent.invoke_id {
+                let mut writeBack = Vec::new();
+                if let Some(session) = global.child_sessions.get(invokeId) {
+                    // Only the finalize of the invoke that created the session, in the state of the invoke.
+                    if let Some(state_id) = session.state_id {
+                        if let Some(inv) = self
+                            .get_state_by_id(state_id)
+                            .invoke
+                            .iterator()
+                            .find(|inv| inv.doc_id == session.invoke_doc_id)
+                        {
+                            toFinalize = inv.finalize;
+                            writeBack = inv
+                                .params
+                                .iter()
+                                .flatten()
+                                .filter(|p| p.write_back)
+                                .cloned()
+                                .collect();
+                        }
+                    }
+                }
+                // "done.invoke" is the last event of the session, but is also finalized.
                 if externalEvent.name.starts_with(EVENT_DONE_INVOKE_PREFIX) {
-                    if let Some(invoke_id) = &externalEvent.invoke_id {
-                        get_global!(datamodel).child_sessions.remove(invoke_id);
+                    if let Some(session) = global.child_sessions.remove(invokeId) {
+                        if !writeBack.is_empty() {
+                            toWriteBack.push((session, writeBack));
+                        }
                     }
                 }
             }
-            let mut toFinalize: Vec<ExecutableContentId> = Vec::new();
-            let mut toForward: Vec<InvokeId> = Vec::new();
-            {
-                match externalEvent.invoke_id {
-                    None => {}
-                    Some(ref invokeId) => {
-                        match get_global!(datamodel).child_sessions.get(invokeId) {
-                            None => {}
-                            Some(session) => {
-                                // Get state of invokeid
-                                if let Some(state_id) = session.state_id {
-                                    let invoke_doc_id = session.invoke_doc_id;
-                                    let state = self.get_state_by_id(state_id);
-                                    for inv in state.invoke.iterator() {
-                                        if inv.doc_id == invoke_doc_id {
-                                            toFinalize.push(inv.finalize);
-                                        }
-                                        if inv.autoforward {
-                                            toForward.push(invokeId.clone());
-                                        }
-                                    }
-                                }
-                            }
-                        }
+            for (invokeId, session) in &global.child_sessions {
+                // Events that were sent by the invoked session itself are not forwarded, otherwise
+                // two sessions with 'autoforward' would pass the same events back and forth.
+                if externalEvent.invoke_id.as_ref() == Some(invokeId) {
+                    continue;
+                }
+                if let Some(state_id) = session.state_id {
+                    if global.configuration.isMember(&state_id)
+                        && self
+                            .get_state_by_id(state_id)
+                            .invoke
+                            .iterator()
+                            .any(|inv| inv.doc_id == session.invoke_doc_id && inv.autoforward)
+                    {
+                        toForward.push(invokeId.clone());
                     }
-                };
+                }
             }
-            datamodel.set_event(&externalEvent);
-            for finalizeContentId in toFinalize {
-                // applyFinalize
-                self.executeContent(datamodel, finalizeContentId);
+            toForward.sort();
+        }
+        // Values of the completed session are written back before the finalize content runs,
+        // so finalize and the transitions on "done.invoke" see them.
+        for (session, params) in toWriteBack {
+            Self::write_back_invoke_params(datamodel, &session, &params);
+        }
+        // The finalize content uses _event, but runs before autoforward and transition selection.
+        // Validation ensures that it doesn't contain \<raise\> or \<send\>.
+        datamodel.set_event(&externalEvent);
+        if toFinalize != 0 {
+            // applyFinalize
+            self.executeContent(datamodel, toFinalize);
+        }
+        if !toForward.is_empty() {
+            // When the 'autoforward' attribute is set to true, the SCXML Processor must send an
+            // exact copy of every external event it receives to the invoked process.
+            // All the fields specified in 5.10.1 The Internal Structure of Events must have the
+            // same values in the forwarded copy of the event. The SCXML Processor must forward
+            // the event at the point at which it removes it from the external event queue of
+            // the invoking session for processing.
+            // Implementation Note:
+            // Events without origin (e.g. sent via the API) get the location of this session
+            // at the SCXML I/O Processor, so the invoked session is able to answer.
+            let mut forwardedEvent = externalEvent.clone();
+            if forwardedEvent.origin.is_none() {
+                let global = get_global!(datamodel);
+                if let Some(processor) = global.io_processors.get(SCXML_EVENT_PROCESSOR_SHORT_TYPE) {
+                    forwardedEvent.origin = Some(processor.lock().unwrap().get_location(global.session_id));
+                    forwardedEvent.origin_type = Some(SCXML_EVENT_PROCESSOR.to_string());
+                }
             }
             for invokeId in toForward {
-                // When the 'autoforward' attribute is set to true, the SCXML Processor must send an
-                // exact copy of every external event it receives to the invoked process.
-                // All the fields specified in 5.10.1 The Internal Structure of Events must have the
-                // same values in the forwarded copy of the event. The SCXML Processor must forward
-                // the event at the point at which it removes it from the external event queue of
-                // the invoking session for processing.
-                match get_global!(datamodel).child_sessions.get(&invokeId) {
+                let mut global = get_global!(datamodel);
+                let delivered = match global.child_sessions.get(&invokeId) {
                     None => {
-                        // TODO: Clarify, communication error?
+                        error!("Autoforward: InvokeId '{}' is not available.", invokeId);
+                        false
                     }
-                    Some(session) => {
-                        match session.sender.send(externalEvent.clone()) {
-                            Ok(_) => {}
-                            Err(_) => {
-                                // TODO: Clarify, communication error?
-                            }
+                    Some(session) => match session.sender.send(forwardedEvent.clone()) {
+                        Ok(_) => true,
+                        Err(error) => {
+                            error!("Autoforward to '{}' failed. {}", invokeId, error);
+                            false
                         }
-                    }
+                    },
+                };
+                // The event could not be delivered, handled like a failed \<send\> to the
+                // invoked session.
+                if !delivered {
+                    let mut error = Event::error_communication(&forwardedEvent);
+                    error.sendid = None;
+                    error.invoke_id = Some(invokeId);
+                    global.enqueue_internal(error);
                 }
             }
+        }
 
-            enabledTransitions = self.selectTransitions(datamodel, &externalEvent);
-            if !enabledTransitions.isEmpty() {
-                self.microstep(datamodel, &enabledTransitions.toList());
-            }
+        let enabledTransitions = self.selectTransitions(datamodel, &externalEvent);
+        if !enabledTransitions.isEmpty() {
+            self.microstep(
+                datamodel,
+                &enabledTransitions.toList(),
+                Some(&externalEvent),
+            );
         }
-        // End of outer while running loop.  If we get here, we have reached a top-level final state or have been cancelled
-        self.exitInterpreter(datamodel);
-        #[cfg(feature = "Trace_Method")]
-        self.tracer.exit_method("mainEventLoop");
+    }
+
+    /// Assigns the values of an invoked session that completed to the locations of the
+    /// \<param\> elements with [Parameter::write_back].\
+    /// Values that the session couldn't capture are skipped with a warning.
+    fn write_back_invoke_params(datamodel: &mut dyn Datamodel, session: &ScxmlSession, params: &[Parameter]) {
+        for param in params {
+            match session.get_final_value(param.name.as_str()) {
+                Some(value) => {
+                    datamodel.assign(&str_to_source(param.location.as_str()), &value);
+                }
+                None => {
+                    warn!(
+                        "No value of '{}' from session #{} to write back to '{}'",
+                        param.name, session.session_id, param.location
+                    );
+                }
+            }
+        }
+    }
+
+    /// Reads the values of [Fsm::final_value_locations] after all states are exited
+    /// and before "done.invoke" is sent to the invoking session.\
+    /// Locations that can't be evaluated are skipped, they don't raise "error.execution".
+    fn capture_final_values(&mut self, datamodel: &mut dyn Datamodel) {
+        for location in &self.final_value_locations {
+            let queued = get_global!(datamodel).internalQueue.data.len();
+            let result = datamodel.execute(&str_to_source(location.as_str()));
+            get_global!(datamodel).internalQueue.data.truncate(queued);
+            match result {
+                Ok(value) => {
+                    let value = value.lock().unwrap().clone();
+                    get_global!(datamodel)
+                        .final_values
+                        .insert(location.clone(), value);
+                }
+                Err(err) => {
+                    warn!("Failed to capture final value of '{}': {}", location, err);
+                }
+            }
+        }
+    }
+
+    /// Part of [Fsm::mainEventLoop]:\
+    /// Called after the loop has ended, we have reached a top-level final state or have been cancelled.
+    pub(crate) fn exit_session(&mut self, datamodel: &mut dyn Datamodel) {
+        self.exitInterpreter(datamodel);
+        self.capture_final_values(datamodel);
+        let mut global = get_global!(datamodel);
+        if let Some(recorder) = &self.event_recorder {
+            recorder.end(global.now_ms());
+        }
+        global.cancel_all_delayed_sends();
+        // Release all callers that still wait for a sync, a snapshot or an evaluation.
+        global.sync_acks = None;
+        global.snapshot_requests = None;
+        global.evaluation_requests = None;
+        global.reload_requests = None;
+        global.configuration_listeners.clear();
+        global.event_subscribers.clear();
+        // Senders that wait for space in the external queue.
+        global.externalQueue.receiver.close();
     }
 
     /// *W3C says*:
@@ -1747,18 +4298,13 @@ impl Fsm {
                     .sort(&|s1, s2| self.state_exit_order(s1, s2));
             }
 
-            let mut session_id_list = Vec::new();
-            for session in get_global!(datamodel).child_sessions.values() {
-                session_id_list.push(session.session_id);
-            }
-            if !session_id_list.is_empty() {
-                for session_id in session_id_list {
-                    datamodel.send(
-                        SCXML_EVENT_PROCESSOR_SHORT_TYPE,
-                        &Data::String(format!("{}{}", SCXML_TARGET_SESSION_ID_PREFIX, session_id)),
-                        Event::new_simple(EVENT_CANCEL_SESSION),
-                    );
-                }
+            let sessions: Vec<ScxmlSession> = get_global!(datamodel)
+                .child_sessions
+                .values()
+                .cloned()
+                .collect();
+            for session in &sessions {
+                Self::cancel_session(datamodel, session);
             }
         }
         for sid in statesToExit.iterator() {
@@ -1770,14 +4316,16 @@ impl Fsm {
             for ct in content {
                 self.executeContent(datamodel, ct);
             }
-            get_global!(datamodel).configuration.delete(sid);
+            get_global!(datamodel).exit_state(*sid);
             {
                 let s = self.get_state_by_id(*sid);
                 if self.isFinalState(s) && self.isSCXMLElement(s.parent) {
+                    get_global!(datamodel).final_state_reached = true;
                     self.returnDoneEvent(&s.donedata.clone(), datamodel);
                 }
             }
         }
+        self.notify_configuration_change(datamodel);
     }
 
     /// *W3C says*:
@@ -1786,7 +4334,7 @@ impl Fsm {
     /// done.invoke.\<id\> to be placed in the external event queue of that session, where \<id\> is
     /// the id generated in that session when the \<invoke\> was executed.
     #[allow(non_snake_case)]
-    fn returnDoneEvent(&mut self, _done_data: &Option<DoneData>, datamodel: &mut dyn Datamodel) {
+    fn returnDoneEvent(&mut self, done_data: &Option<DoneData>, datamodel: &mut dyn Datamodel) {
         let caller_invoke_id;
         let parent_session_id;
         {
@@ -1798,32 +4346,54 @@ impl Fsm {
             None => {
                 // No parent
             }
-            Some(session_id) => {
-                match caller_invoke_id {
-                    None => {
-                        panic!("Internal Error: Caller-Invoke-Id not available but Parent-Session-Id is set.");
-                    }
-                    Some(invoke_id) => {
-                        // TODO: Evaluate done_data, EventType::external ?
-                        let mut event = Event::new(
-                            EVENT_DONE_INVOKE_PREFIX,
-                            &invoke_id,
-                            None,
-                            None,
-                            EventType::external,
-                        );
-                        event.invoke_id = Some(invoke_id);
-                        datamodel.send(
-                            SCXML_EVENT_PROCESSOR_SHORT_TYPE,
-                            &Data::String(format!("{}{}", SCXML_TARGET_SESSION_ID_PREFIX, session_id)),
-                            event,
-                        );
+            Some(session_id) => match caller_invoke_id {
+                None => {
+                    panic!("Internal Error: Caller-Invoke-Id not available but Parent-Session-Id is set.");
+                }
+                Some(invoke_id) => {
+                    // The parent reads values it writes back when it processes "done.invoke",
+                    // so they are captured before the event is sent.
+                    self.capture_final_values(datamodel);
+                    let mut name_values = Vec::new();
+                    let mut content = None;
+                    if let Some(done_data) = done_data {
+                        Self::evaluate_donedata_params(datamodel, done_data, &mut name_values);
+                        content = datamodel.evaluate_content(&done_data.content);
                     }
+                    let param_values = if name_values.is_empty() {
+                        None
+                    } else {
+                        Some(name_values)
+                    };
+                    let mut event = Event::new(
+                        EVENT_DONE_INVOKE_PREFIX,
+                        &invoke_id,
+                        param_values,
+                        content,
+                        EventType::external,
+                    );
+                    event.invoke_id = Some(invoke_id);
+                    get_global!(datamodel).notify_event_subscribers(&event);
+                    datamodel.send(
+                        SCXML_EVENT_PROCESSOR_SHORT_TYPE,
+                        &Data::String(format!("{}{}", SCXML_TARGET_SESSION_ID_PREFIX, session_id)),
+                        event,
+                    );
                 }
-            }
+            },
         }
     }
 
+    /// Gets the transitions of a state in document order.\
+    /// Returns the ids, so that conditions can be evaluated while iterating.
+    fn sorted_transitions(&self, state: StateId) -> Vec<TransitionId> {
+        self.to_transition_list(&self.get_state_by_id(state).transitions)
+            .sort(&|t1: &&Transition, t2: &&Transition| self.transition_document_order(t1, t2))
+            .iterator()
+            .map(|t| t.id)
+            .collect()
+    }
+
     /// *W3C says*:
     /// # function selectEventlessTransitions()
     /// This function selects all transitions that are enabled in the current configuration that
@@ -1867,25 +4437,14 @@ impl Fsm {
             let mut states: List<StateId> = List::new();
             states.push(*sid);
             states.push_set(&self.getProperAncestors(*sid, 0));
-            let mut condT = Vec::new();
-            for s in states.iterator() {
-                let state = self.get_state_by_id(*s);
-                for t in self
-                    .to_transition_list(&state.transitions)
-                    .sort(&|t1: &&Transition, t2: &&Transition| self.transition_document_order(t1, t2))
-                    .iterator()
-                {
-                    if t.events.is_empty() {
-                        condT.push(t.id);
+            'states: for s in states.iterator() {
+                for t in self.sorted_transitions(*s) {
+                    if self.get_transition_by_id(t).events.is_empty() && self.conditionMatch(datamodel, t) {
+                        enabledTransitions.add(t);
+                        break 'states;
                     }
                 }
             }
-            for ct in condT {
-                if self.conditionMatch(datamodel, ct) {
-                    enabledTransitions.add(ct);
-                    break;
-                }
-            }
         }
         enabledTransitions = self.removeConflictingTransitions(datamodel, &enabledTransitions);
         #[cfg(feature = "Trace_Method")]
@@ -1926,30 +4485,19 @@ impl Fsm {
             .filter_by(&|sid| -> bool { self.isAtomicStateId(sid) })
             .sort(&|s1, s2| self.state_document_order(s1, s2));
         for state in atomicStates.iterator() {
-            let mut condT = Vec::new();
-            for sid in List::from_array(&[*state])
+            'states: for sid in List::from_array(&[*state])
                 .append_set(&self.getProperAncestors(*state, 0))
                 .iterator()
             {
-                let s = self.get_state_by_id(*sid);
-                let mut transition: Vec<&Transition> = Vec::new();
-                for tid in s.transitions.iterator() {
-                    transition.push(self.get_transition_by_id(*tid));
-                }
-
-                transition.sort_by(&|t1: &&Transition, t2: &&Transition| self.transition_document_order(t1, t2));
-                for t in transition {
-                    if (!t.events.is_empty()) && t.nameMatch(event.name.as_str()) {
-                        condT.push(t.id);
+                for tid in self.sorted_transitions(*sid) {
+                    let t = self.get_transition_by_id(tid);
+                    if (!t.events.is_empty()) && t.nameMatch(event.name.as_str()) && self.conditionMatch(datamodel, tid)
+                    {
+                        enabledTransitions.add(tid);
+                        break 'states;
                     }
                 }
             }
-            for ct in condT {
-                if self.conditionMatch(datamodel, ct) {
-                    enabledTransitions.add(ct);
-                    break;
-                }
-            }
         }
         enabledTransitions = self.removeConflictingTransitions(datamodel, &enabledTransitions);
         #[cfg(feature = "Trace_Method")]
@@ -2061,8 +4609,14 @@ impl Fsm {
     ///     executeTransitionContent(enabledTransitions)
     ///     enterStates(enabledTransitions)
     /// ```
+    /// "event" is the event that triggered the transitions, none for eventless transitions.
     #[allow(non_snake_case)]
-    fn microstep(&mut self, datamodel: &mut dyn Datamodel, enabledTransitions: &List<TransitionId>) {
+    fn microstep(
+        &mut self,
+        datamodel: &mut dyn Datamodel,
+        enabledTransitions: &List<TransitionId>,
+        event: Option<&Event>,
+    ) {
         #[cfg(feature = "Trace_Method")]
         self.tracer.enter_method("microstep");
         #[cfg(feature = "Debug")]
@@ -2075,15 +4629,20 @@ impl Fsm {
                     }
                 }
             } else {
-                let t = enabledTransitions.head();
-                if let Some(transition) = self.transitions.get(t) {
+                if let Some(transition) = enabledTransitions
+                    .head()
+                    .and_then(|t| self.transitions.get(t))
+                {
                     debug!("Enabled Transition {}", transition);
                 }
             }
         }
+        #[cfg(feature = "Metrics")]
+        self.metrics.microstep(enabledTransitions.size());
         self.exitStates(datamodel, enabledTransitions);
-        self.executeTransitionContent(datamodel, enabledTransitions);
+        self.executeTransitionContent(datamodel, enabledTransitions, event);
         self.enterStates(datamodel, enabledTransitions);
+        self.notify_configuration_change(datamodel);
         #[cfg(feature = "Trace_Method")]
         self.tracer.exit_method("microstep");
     }
@@ -2173,7 +4732,7 @@ impl Fsm {
             {
                 let s = self.get_state_by_id(*sid);
                 #[cfg(feature = "Trace_State")]
-                self.tracer.trace_exit_state(s);
+                self.tracer.trace_record(&TraceRecord::exit_state(s));
                 for inv in s.invoke.iterator() {
                     invoke_doc_ids.insert(inv.doc_id);
                 }
@@ -2182,14 +4741,14 @@ impl Fsm {
                 }
             }
             if !invoke_doc_ids.is_empty() {
-                let mut session_ids = Vec::new();
+                let mut invoke_ids = Vec::new();
                 for (invoke_id, session) in &get_global!(datamodel).child_sessions {
                     if invoke_doc_ids.contains(&session.invoke_doc_id) {
-                        session_ids.push((invoke_id.clone(), session.session_id));
+                        invoke_ids.push(invoke_id.clone());
                     }
                 }
-                for (invoke_id, session_id) in &session_ids {
-                    self.cancelInvoke(datamodel, invoke_id, *session_id);
+                for invoke_id in &invoke_ids {
+                    self.cancelInvoke(datamodel, invoke_id);
                 }
             }
 
@@ -2197,7 +4756,7 @@ impl Fsm {
                 self.executeContent(datamodel, *ec);
             }
 
-            get_global!(datamodel).configuration.delete(sid)
+            get_global!(datamodel).exit_state(*sid);
         }
         #[cfg(feature = "Trace_Method")]
         self.tracer.exit_method("exitStates");
@@ -2263,6 +4822,12 @@ impl Fsm {
             &mut statesForDefaultEntry,
             &mut defaultHistoryContent,
         );
+        self.reset_unused_histories(
+            datamodel,
+            enabledTransitions,
+            &statesToEnter,
+            &statesForDefaultEntry,
+        );
         for s in statesToEnter
             .toList()
             .sort(&|s1, s2| self.state_entry_order(s1, s2))
@@ -2270,11 +4835,19 @@ impl Fsm {
         {
             #[cfg(feature = "Trace_State")]
             {
-                self.tracer.trace_enter_state(self.get_state_by_id(*s));
+                self.tracer
+                    .trace_record(&TraceRecord::enter_state(self.get_state_by_id(*s)));
+            }
+            if let Some(recorder) = &self.event_recorder {
+                let state = self.get_state_by_id(*s);
+                // The pseudo root state has no name.
+                if !state.name.is_empty() {
+                    recorder.enter_state(get_global!(datamodel).now_ms(), state.name.as_str());
+                }
             }
             {
                 let mut gd = get_global!(datamodel);
-                gd.configuration.add(*s);
+                gd.enter_state(*s);
                 gd.statesToInvoke.add(*s);
             }
             let mut to_init: StateId = 0;
@@ -2295,8 +4868,8 @@ impl Fsm {
                 if statesForDefaultEntry.isMember(s) && state_s.initial > 0 {
                     exe.push(self.get_transition_by_id(state_s.initial).content);
                 }
-                if defaultHistoryContent.has(*s) {
-                    exe.push(*defaultHistoryContent.get(*s));
+                if let Some(content) = defaultHistoryContent.get(*s) {
+                    exe.push(*content);
                 }
             }
 
@@ -2306,71 +4879,149 @@ impl Fsm {
                 }
             }
 
+            // The done events are generated after all executable content of the state was executed,
+            // so the \<donedata\> sees the values that are assigned by \<onentry\>.
             if self.isFinalStateId(*s) {
-                let state_s = self.get_state_by_id(*s);
-                let parent: StateId = state_s.parent;
-                if self.isSCXMLElement(parent) {
-                    get_global!(datamodel).running = false;
-                } else {
-                    let parentS = self.get_state_by_id(parent);
-                    let mut name_values = Vec::new();
-                    let mut content = None;
-                    match &state_s.donedata {
-                        None => {}
-                        Some(done_data) => {
-                            datamodel.evaluate_params(&done_data.params, &mut name_values);
-                            content = datamodel.evaluate_content(&done_data.content);
-                        }
-                    }
-                    let param_values = if name_values.is_empty() {
-                        None
-                    } else {
-                        Some(name_values)
-                    };
-
-                    self.enqueue_internal(
-                        datamodel,
-                        // TODO: EventType::external ?
-                        Event::new(
-                            "done.state.",
-                            &parentS.name,
-                            param_values,
-                            content,
-                            EventType::external,
-                        ),
-                    );
-                    let stateParent = self.get_state_by_id(parent);
-                    let grandparent: StateId = stateParent.parent;
-                    if self.isParallelState(grandparent)
-                        && self
-                            .getChildStates(grandparent)
-                            .every(&|s: &StateId| -> bool { self.isInFinalState(datamodel, *s) })
-                    {
-                        let grandparentS = self.get_state_by_id(grandparent);
-                        self.enqueue_internal(
-                            datamodel,
-                            // TODO: EventType::external ?
-                            Event::new(
-                                "done.state.",
-                                &grandparentS.name,
-                                None,
-                                None,
-                                EventType::external,
-                            ),
-                        );
-                    }
-                }
+                self.enqueue_done_events(datamodel, *s);
             }
         }
         #[cfg(feature = "Trace_Method")]
         self.tracer.exit_method("enterStates");
     }
 
+    /// Part of [Fsm::enterStates], rFSM extension, see [State::reset_on_entry]:\
+    /// Clears the stored history of history states with "reset_on_entry" if their parent or an ancestor
+    /// of it is entered without a history state at this level or above.\
+    /// The used history states are the targets of the transitions, of the initial transitions of the states
+    /// that get their default entry and of the default transitions of used history states.
+    #[allow(non_snake_case)]
+    fn reset_unused_histories(
+        &self,
+        datamodel: &mut dyn Datamodel,
+        enabledTransitions: &List<TransitionId>,
+        statesToEnter: &OrderedSet<StateId>,
+        statesForDefaultEntry: &OrderedSet<StateId>,
+    ) {
+        let ancestors = |sid: StateId| {
+            let mut ancestors = Vec::new();
+            let mut current = sid;
+            while current != 0 {
+                ancestors.push(current);
+                current = self.get_state_by_id(current).parent;
+            }
+            ancestors
+        };
+        let resets: Vec<(StateId, Vec<StateId>)> = self
+            .states
+            .iter()
+            .filter(|h| h.reset_on_entry)
+            .map(|h| (h.id, ancestors(h.parent)))
+            .filter(|(_, scope)| scope.iter().any(|sid| statesToEnter.isMember(sid)))
+            .collect();
+        if resets.is_empty() {
+            return;
+        }
+        let mut used: Vec<StateId> = Vec::new();
+        let mut pending: Vec<TransitionId> = enabledTransitions.iterator().cloned().collect();
+        pending.extend(
+            statesForDefaultEntry
+                .iterator()
+                .map(|sid| self.get_state_by_id(*sid).initial)
+                .filter(|tid| *tid != 0),
+        );
+        while let Some(tid) = pending.pop() {
+            for target in &self.get_transition_by_id(tid).target {
+                if self.isHistoryState(*target) && !used.contains(target) {
+                    used.push(*target);
+                    pending.extend(self.get_state_by_id(*target).transitions.iterator());
+                }
+            }
+        }
+        let mut gd = get_global!(datamodel);
+        for (hid, scope) in resets {
+            if !used
+                .iter()
+                .any(|uid| scope.contains(&self.get_state_by_id(*uid).parent))
+            {
+                gd.historyValue.remove(hid);
+            }
+        }
+    }
+
+    /// Part of [Fsm::enterStates]:\
+    /// Called after the final state was entered and its executable content was executed. Evaluates
+    /// the \<donedata\> and enqueues the "done.state" event of the parent and of all parallel
+    /// ancestors that are completed. For top-level final states, the session stops.
+    #[allow(non_snake_case)]
+    fn enqueue_done_events(&mut self, datamodel: &mut dyn Datamodel, final_state: StateId) {
+        let state_s = self.get_state_by_id(final_state);
+        let parent: StateId = state_s.parent;
+        if self.isSCXMLElement(parent) {
+            get_global!(datamodel).running = false;
+        } else {
+            let parentS = self.get_state_by_id(parent);
+            let mut name_values = Vec::new();
+            let mut content = None;
+            match &state_s.donedata {
+                None => {}
+                Some(done_data) => {
+                    Self::evaluate_donedata_params(datamodel, done_data, &mut name_values);
+                    content = datamodel.evaluate_content(&done_data.content);
+                }
+            }
+            let param_values = if name_values.is_empty() {
+                None
+            } else {
+                Some(name_values)
+            };
+
+            self.enqueue_internal(
+                datamodel,
+                // TODO: EventType::external ?
+                Event::new(
+                    "done.state.",
+                    &parentS.name,
+                    param_values,
+                    content,
+                    EventType::external,
+                ),
+            );
+            // The W3C algorithm checks only the grandparent. But if parallel states are nested,
+            // completing the last region can also complete the enclosing parallel states.
+            let mut ancestor: StateId = self.get_state_by_id(parent).parent;
+            while self.isParallelState(ancestor)
+                && self
+                    .getChildStates(ancestor)
+                    .every(&|s: &StateId| -> bool { self.isInFinalState(datamodel, *s) })
+            {
+                let ancestorS = self.get_state_by_id(ancestor);
+                let next = ancestorS.parent;
+                self.enqueue_internal(
+                    datamodel,
+                    // TODO: EventType::external ?
+                    Event::new(
+                        "done.state.",
+                        &ancestorS.name,
+                        None,
+                        None,
+                        EventType::external,
+                    ),
+                );
+                ancestor = next;
+            }
+        }
+    }
+
     /// Put an event into the internal queue.
     pub fn enqueue_internal(&mut self, datamodel: &mut dyn Datamodel, event: Event) {
         #[cfg(feature = "Trace_Event")]
-        self.tracer.event_internal_send(&event);
-        get_global!(datamodel).internalQueue.enqueue(event);
+        self.tracer.trace_record(&TraceRecord::EventSent {
+            event: event.clone(),
+            internal: true,
+        });
+        let mut global = get_global!(datamodel);
+        global.notify_event_subscribers(&event);
+        global.internalQueue.enqueue(event);
     }
 
     #[allow(non_snake_case)]
@@ -2381,7 +5032,11 @@ impl Fsm {
             self.tracer.trace_argument("contentId", &contentId);
         }
         if contentId != 0 {
+            #[cfg(feature = "Metrics")]
+            let start = Instant::now();
             datamodel.executeContent(self, contentId);
+            #[cfg(feature = "Metrics")]
+            self.metrics.content_time(start);
         }
         #[cfg(feature = "Trace_Method")]
         self.tracer.exit_method("executeContent");
@@ -2479,12 +5134,39 @@ impl Fsm {
     ///         executeContent(t)
     /// ```
     #[allow(non_snake_case)]
-    fn executeTransitionContent(&mut self, datamodel: &mut dyn Datamodel, enabledTransitions: &List<TransitionId>) {
+    /// Implementation Note:\
+    /// After the executable content, the native action of the transition is called (rFSM extension).
+    /// For eventless transitions the action gets an event with an empty name.
+    fn executeTransitionContent(
+        &mut self,
+        datamodel: &mut dyn Datamodel,
+        enabledTransitions: &List<TransitionId>,
+        event: Option<&Event>,
+    ) {
         for tid in enabledTransitions.iterator() {
             let t = self.get_transition_by_id(*tid);
+            #[cfg(feature = "Trace_State")]
+            self.tracer.trace_record(&TraceRecord::TransitionTaken {
+                id: t.id,
+                source: t.source,
+                targets: t.target.clone(),
+            });
+            let action = t.action.clone();
             if t.content > 0 {
                 self.executeContent(datamodel, t.content);
             }
+            if let Some(action) = action {
+                let (session_id, executor) = {
+                    let global = get_global!(datamodel);
+                    (global.session_id, global.executor.clone())
+                };
+                if let Some(executor) = executor {
+                    match event {
+                        Some(event) => executor.call_transition_action(&action, event, session_id),
+                        None => executor.call_transition_action(&action, &Event::new_simple(""), session_id),
+                    }
+                }
+            }
         }
     }
 
@@ -2610,8 +5292,8 @@ impl Fsm {
         if self.isHistoryState(sid) {
             if get_global!(datamodel).historyValue.has(sid) {
                 let mut stateIds: Vec<StateId> = Vec::new();
-                for s in get_global!(datamodel).historyValue.get(sid).iterator() {
-                    stateIds.push(*s);
+                if let Some(history) = get_global!(datamodel).historyValue.get(sid) {
+                    stateIds.extend(history.iterator());
                 }
                 for s in &stateIds {
                     self.addDescendantStatesToEnter(
@@ -2634,7 +5316,11 @@ impl Fsm {
                 }
             } else {
                 // A history state have exactly one transition which specified the default history configuration.
-                let defaultTransition = self.get_transition_by_id(*state.transitions.head());
+                let Some(defaultTransitionId) = state.transitions.head() else {
+                    error!("History state '{}' has no default transition", state.name);
+                    return;
+                };
+                let defaultTransition = self.get_transition_by_id(*defaultTransitionId);
                 defaultHistoryContent.put(state.parent, &defaultTransition.content);
                 for s in &defaultTransition.target {
                     self.addDescendantStatesToEnter(
@@ -2845,8 +5531,11 @@ impl Fsm {
         #[cfg(feature = "Trace_Method")]
         self.tracer.trace_argument("stateList", stateList);
         let mut lcca = 0;
+        let Some(head) = stateList.head() else {
+            return lcca;
+        };
         for anc in self
-            .getProperAncestors(*stateList.head(), 0)
+            .getProperAncestors(*head, 0)
             .toList()
             .filter_by(&|s| self.isCompoundStateOrScxmlElement(*s))
             .iterator()
@@ -2888,14 +5577,18 @@ impl Fsm {
         let mut targets: OrderedSet<StateId> = OrderedSet::new();
         for sid in &transition.target {
             if self.isHistoryState(*sid) {
-                if get_global!(datamodel).historyValue.has(*sid) {
-                    targets.union(get_global!(datamodel).historyValue.get(*sid));
-                } else {
-                    let s = self.get_state_by_id(*sid);
-                    // History states have exactly one "transition"
-                    targets.union(
-                        &self.getEffectiveTargetStates(datamodel, self.get_transition_by_id(*s.transitions.head())),
-                    );
+                let history = get_global!(datamodel).historyValue.get(*sid).cloned();
+                match history {
+                    Some(history) => targets.union(&history),
+                    None => {
+                        let s = self.get_state_by_id(*sid);
+                        // History states have exactly one "transition"
+                        match s.transitions.head() {
+                            Some(tid) => targets
+                                .union(&self.getEffectiveTargetStates(datamodel, self.get_transition_by_id(*tid))),
+                            None => error!("History state '{}' has no default transition", s.name),
+                        }
+                    }
                 }
             } else {
                 targets.add(*sid);
@@ -2928,10 +5621,10 @@ impl Fsm {
 
         let mut properAncestors: OrderedSet<StateId> = OrderedSet::new();
         if !self.isDescendant(state2, state1) {
-            let mut currState = self.get_state_by_id(state1).parent;
+            let mut currState = self.parent_of(state1);
             while currState != 0 && currState != state2 {
                 properAncestors.add(currState);
-                currState = self.get_state_by_id(currState).parent;
+                currState = self.parent_of(currState);
             }
         }
         #[cfg(feature = "Trace_Method")]
@@ -2957,12 +5650,23 @@ impl Fsm {
         let result;
         if state1 == 0 || state2 == 0 || state1 == state2 {
             result = false;
-        } else {
+        } else if self.state_tables.is_empty() {
             let mut currState = self.get_state_by_id(state1).parent;
             while currState != 0 && currState != state2 {
                 currState = self.get_state_by_id(currState).parent;
             }
             result = currState == state2;
+        } else {
+            // Only the ancestor of state1 with the depth of state2 can be state2.
+            let tables = &self.state_tables;
+            let depth2 = tables.depth[(state2 - 1) as usize];
+            let mut currState = state1;
+            let mut depth = tables.depth[(state1 - 1) as usize];
+            while depth > depth2 {
+                currState = tables.parent[(currState - 1) as usize];
+                depth -= 1;
+            }
+            result = currState == state2;
         }
         #[cfg(feature = "Trace_Method")]
         {
@@ -3018,30 +5722,19 @@ impl Fsm {
         l
     }
 
-    fn invoke(&mut self, datamodel: &mut dyn Datamodel, state_id: StateId, inv: &Invoke) {
-        // W3C: if the evaluation of its arguments produces an error, the SCXML Processor must
-        // terminate the processing of the element without further action.
-
-        let type_name_data = match datamodel.get_expression_alternative_value(&inv.type_name, &inv.type_expr) {
-            Ok(value) => value,
-            Err(_) => {
-                // Error -> abort
-                return;
-            }
-        };
-
-        let mut type_name = type_name_data.lock().unwrap().to_string();
-        if type_name.eq(SCXML_INVOKE_TYPE_SHORT) {
-            type_name = SCXML_INVOKE_TYPE.to_string();
-        }
-
-        if !(type_name.is_empty()
-            || (type_name.starts_with(SCXML_INVOKE_TYPE) && type_name.len() <= (SCXML_INVOKE_TYPE.len() + 1)))
-        {
-            error!("Unsupported <invoke> type {}", type_name);
-            return;
+    /// Evaluates the \<param\> elements of \<donedata\>.\
+    /// If a name is given more than once, error.execution is raised and the done-event is sent
+    /// without data.
+    fn evaluate_donedata_params(datamodel: &mut dyn Datamodel, done_data: &DoneData, values: &mut Vec<ParamPair>) {
+        if let Some(name) = find_duplicate_param_name(&[], &done_data.params) {
+            error!("donedata: '{}' is given more than once", name);
+            datamodel.internal_error_execution();
+        } else {
+            datamodel.evaluate_params(&done_data.params, values);
         }
+    }
 
+    fn invoke(&mut self, datamodel: &mut dyn Datamodel, state_id: StateId, inv: &Invoke) {
         #[allow(non_snake_case)]
         let invokeId = if inv.invoke_id.is_empty() {
             // W3C:
@@ -3063,13 +5756,83 @@ impl Fsm {
             inv.invoke_id.clone()
         };
 
+        // Errors raised while the element is evaluated (also by the datamodel) get the invokeid.
+        {
+            let mut global = get_global!(datamodel);
+            global.error_invoke_id = Some(invokeId.clone());
+            global.error_source_line = inv.source_line;
+        }
+        self.start_invoke(datamodel, state_id, inv, invokeId);
+        {
+            let mut global = get_global!(datamodel);
+            global.error_invoke_id = None;
+            global.error_source_line = 0;
+        }
+    }
+
+    /// Evaluates the \<invoke\> element and starts the invoked session.
+    #[allow(non_snake_case)]
+    fn start_invoke(&mut self, datamodel: &mut dyn Datamodel, state_id: StateId, inv: &Invoke, invokeId: InvokeId) {
+        // W3C: if the evaluation of its arguments produces an error, the SCXML Processor must
+        // terminate the processing of the element without further action.
+
+        let type_name_data = match datamodel.get_expression_alternative_value(&inv.type_name, &inv.type_expr) {
+            Ok(value) => value,
+            Err(_) => {
+                // Error -> abort
+                datamodel.internal_error_execution_for_event(&None, &Some(invokeId));
+                return;
+            }
+        };
+
+        let mut type_name = type_name_data.lock().unwrap().to_string();
+        if type_name.is_empty()
+            || type_name.eq(SCXML_INVOKE_TYPE_SHORT)
+            || type_name.strip_suffix('/') == Some(SCXML_INVOKE_TYPE)
+        {
+            type_name = SCXML_INVOKE_TYPE.to_string();
+        }
+
+        let handler_opt = get_global!(datamodel)
+            .executor
+            .as_ref()
+            .and_then(|executor| executor.get_invoke_handler(type_name.as_str()));
+        let handler = match handler_opt {
+            Some(handler) => handler,
+            None => {
+                error!("Unsupported <invoke> type {}", type_name);
+                datamodel.internal_error_execution();
+                return;
+            }
+        };
+
         let src = match datamodel.get_expression_alternative_value(&inv.src, &inv.src_expr) {
             Err(_) => {
                 // Error -> Abort
+                datamodel.internal_error_execution_for_event(&None, &Some(invokeId));
                 return;
             }
             Ok(value) => value.lock().unwrap().clone(),
         };
+        let src = if src.is_empty() {
+            String::new()
+        } else {
+            match self.resolve_uri(src.to_string().as_str()) {
+                Ok(resolved) => resolved,
+                Err(error) => {
+                    error!("Invoke '{}' failed: {}", invokeId, error);
+                    get_global!(datamodel).enqueue_internal(Event::error_communication_for_invoke(&invokeId));
+                    return;
+                }
+            }
+        };
+        if let Some(name) = find_duplicate_param_name(&inv.name_list, &inv.params) {
+            // The names of "namelist" and <param> must be unique. As for other errors in the
+            // arguments, the processing of <invoke> is terminated.
+            error!("Invoke '{}': '{}' is given more than once", invokeId, name);
+            datamodel.internal_error_execution_for_event(&None, &Some(invokeId));
+            return;
+        }
         let mut name_values: Vec<ParamPair> = Vec::new();
         for name in inv.name_list.as_slice() {
             match datamodel.get_by_location(name) {
@@ -3103,74 +5866,148 @@ impl Fsm {
             );
         }
 
-        let result = if src.is_empty() {
-            match datamodel.evaluate_content(&inv.content) {
-                None => Err("No content to execute".to_string()),
-                Some(content) => {
-                    let mut global = get_global!(datamodel);
-                    let session_id = global.session_id;
-
-                    let actions = global.actions.get_copy();
-                    global
-                        .executor
-                        .as_mut()
-                        .unwrap()
-                        .execute_with_data_from_xml(
-                            content.lock().unwrap().to_string().as_str(),
-                            actions,
-                            &name_values,
-                            Some(session_id),
-                            &invokeId,
-                            FinishMode::DISPOSE,
-                            #[cfg(feature = "Trace")]
-                            self.tracer.trace_mode(),
-                        )
-                }
+        let content = if src.is_empty() {
+            let content = datamodel.evaluate_content(&inv.content);
+            if content.is_none()
+                && inv
+                    .content
+                    .as_ref()
+                    .is_some_and(|c| c.content_expr.is_some())
+            {
+                // The evaluation of "expr" failed and error.execution was raised.
+                return;
             }
+            // The result of "expr" is used as document, like static content.
+            content.map(|content| content.lock().unwrap().to_string())
         } else {
-            let mut global = get_global!(datamodel);
-            let session_id = global.session_id;
-            let actions = global.actions.get_copy();
-            global.executor.as_mut().unwrap().execute_with_data(
-                src.to_string().as_str(),
-                actions,
-                &name_values,
-                Some(session_id),
-                &invokeId,
-                #[cfg(feature = "Trace")]
-                self.tracer.trace_mode(),
+            None
+        };
+
+        let (executor, parameters) = {
+            let global = get_global!(datamodel);
+            (
+                global.executor.clone(),
+                InvokeParameters {
+                    invoke_id: invokeId.clone(),
+                    type_name,
+                    src: src.clone(),
+                    content,
+                    params: name_values,
+                    write_back: inv
+                        .params
+                        .iter()
+                        .flatten()
+                        .filter(|p| p.write_back)
+                        .map(|p| p.name.clone())
+                        .collect(),
+                    parent_session_id: global.session_id,
+                    actions: global.actions.get_copy(),
+                    options: global.options.clone(),
+                    #[cfg(feature = "Trace")]
+                    trace_modes: global
+                        .executor
+                        .as_ref()
+                        .and_then(|e| e.get_invoke_trace_modes(&invokeId))
+                        .unwrap_or_else(|| self.tracer.trace_modes()),
+                },
             )
         };
 
+        let result = match executor {
+            None => Err(InvokeError::Execution("Executor not available".to_string())),
+            Some(mut executor) => handler.lock().unwrap().invoke(&mut executor, parameters),
+        };
+
         match result {
             Ok(mut session) => {
                 session.state_id = Some(state_id);
                 session.invoke_doc_id = inv.doc_id;
+                session.invoke_handler = Some(handler);
 
                 get_global!(datamodel)
                     .child_sessions
                     .insert(invokeId, session);
             }
             Err(error) => {
-                error!("Execute of '{}' failed: {}", src, error)
+                error!("Execute of '{}' failed: {}", src, error);
+                // The reason, e.g. the message of the parser, is given as data of the event.
+                let mut failed = match error {
+                    InvokeError::Retrieval(_) => Event::error_communication_for_invoke(&invokeId),
+                    InvokeError::Parse(_) | InvokeError::Execution(_) => Event::error_execution(&None, &Some(invokeId)),
+                };
+                failed.content = Some(create_data_arc(Data::String(error.to_string())));
+                self.enqueue_internal(datamodel, failed);
+            }
+        }
+    }
+
+    /// Resolves a location (e.g. the "src" of \<invoke\>) against the [Fsm::base_uri].\
+    /// Supported are file paths, "file:" URIs and "http(s):" URLs. Other schemes are rejected.
+    pub fn resolve_uri(&self, uri: &str) -> Result<String, String> {
+        let scheme = uri_scheme(uri);
+        let path = match scheme.as_deref() {
+            None => uri,
+            Some("http") | Some("https") => return Ok(uri.to_string()),
+            Some("file") => {
+                let path = &uri[5..];
+                // "file:///path" and "file:/path" are absolute, "file:path" is relative.
+                if let Some(absolute) = path.strip_prefix("//") {
+                    absolute
+                } else {
+                    path
+                }
             }
+            Some(other) => return Err(format!("Unsupported scheme '{}' in '{}'", other, uri)),
+        };
+        if Path::new(path).is_absolute() {
+            return Ok(path.to_string());
+        }
+        match &self.base_uri {
+            None => Ok(path.to_string()),
+            Some(base) => match uri_scheme(base.as_str()).as_deref() {
+                Some("http") | Some("https") => match base.rfind('/') {
+                    Some(idx) => Ok(format!("{}{}", &base[..=idx], path)),
+                    None => Ok(path.to_string()),
+                },
+                _ => match Path::new(base.as_str()).parent() {
+                    Some(parent) => Ok(parent.join(path).to_string_lossy().to_string()),
+                    None => Ok(path.to_string()),
+                },
+            },
         }
     }
 
     #[allow(non_snake_case)]
-    fn cancelInvoke(&mut self, datamodel: &mut dyn Datamodel, invoke_id: &InvokeId, session_id: SessionId) {
+    fn cancelInvoke(&mut self, datamodel: &mut dyn Datamodel, invoke_id: &InvokeId) {
         #[cfg(feature = "Trace_Method")]
         self.tracer.enter_method("cancelInvoke");
-        get_global!(datamodel).child_sessions.remove(invoke_id);
-        datamodel.send(
-            SCXML_EVENT_PROCESSOR_SHORT_TYPE,
-            &Data::String(format!("{}{}", SCXML_TARGET_SESSION_ID_PREFIX, session_id)),
-            Event::new_simple(EVENT_CANCEL_SESSION),
-        );
+        let session = get_global!(datamodel).child_sessions.remove(invoke_id);
+        if let Some(session) = session {
+            Self::cancel_session(datamodel, &session);
+        }
         #[cfg(feature = "Trace_Method")]
         self.tracer.exit_method("cancelInvoke");
     }
 
+    /// Cancels an invoked session via the handler that started it.
+    fn cancel_session(datamodel: &mut dyn Datamodel, session: &ScxmlSession) {
+        match &session.invoke_handler {
+            Some(handler) => {
+                handler.lock().unwrap().cancel(session);
+            }
+            None => {
+                datamodel.send(
+                    SCXML_EVENT_PROCESSOR_SHORT_TYPE,
+                    &Data::String(format!(
+                        "{}{}",
+                        SCXML_TARGET_SESSION_ID_PREFIX, session.session_id
+                    )),
+                    Event::new_simple(EVENT_CANCEL_SESSION),
+                );
+            }
+        }
+    }
+
     /// *W3C says*:
     /// 5.9.1 Conditional Expressions
     /// Conditional expressions are used inside the 'cond' attribute of \<transition\>, \<if\> and \<elseif\>.
@@ -3181,21 +6018,30 @@ impl Fsm {
     /// See [Datamodel::execute_condition]
     #[allow(non_snake_case)]
     fn conditionMatch(&mut self, datamodel: &mut dyn Datamodel, tid: TransitionId) -> bool {
-        let cond;
-        {
-            let t = self.get_transition_by_id_mut(tid);
-            cond = t.cond.clone();
-        }
-        if cond.is_empty() {
+        let t = self.get_transition_by_id(tid);
+        if t.cond.is_empty() {
             true
         } else {
-            match datamodel.execute_condition(&cond) {
+            let previous_line = mem::replace(&mut get_global!(datamodel).error_source_line, t.source_line);
+            #[cfg(feature = "Metrics")]
+            let start = Instant::now();
+            let r = match datamodel.execute_condition(&t.cond) {
                 Ok(v) => v,
-                Err(_e) => {
+                Err(e) => {
+                    warn!(
+                        "Transition condition '{}'{} can't be evaluated. {}",
+                        t.cond,
+                        get_global!(datamodel).source_line_info(),
+                        e
+                    );
                     datamodel.internal_error_execution();
                     false
                 }
-            }
+            };
+            #[cfg(feature = "Metrics")]
+            self.metrics.condition_time(start);
+            get_global!(datamodel).error_source_line = previous_line;
+            r
         }
     }
 
@@ -3225,21 +6071,6 @@ impl Fsm {
         l
     }
 
-    pub fn schedule<F>(&self, delay_ms: i64, mut cb: F) -> Option<Guard>
-    where
-        F: 'static + FnMut() + Send,
-    {
-        if delay_ms > 0 {
-            Some(
-                self.timer
-                    .schedule_with_delay(chrono::Duration::milliseconds(delay_ms), cb),
-            )
-        } else {
-            cb();
-            None
-        }
-    }
-
     /// Very basic compare.
     /// TODO: needs to be extended if the tests get more complex.
     #[cfg(test)]
@@ -3331,6 +6162,9 @@ pub struct State {
     /// "id" is increasing on references to states, not declaration and may not result in correct order.
     pub doc_id: DocumentId,
 
+    /// Line of the element in the source document.
+    pub source_line: SourceLine,
+
     /// The SCXML id.
     pub name: String,
 
@@ -3348,6 +6182,10 @@ pub struct State {
 
     pub history_type: HistoryType,
 
+    /// rFSM extension for history states: the stored history is cleared if the parent or an ancestor is entered
+    /// without a history state, e.g. by its initial transition. See [crate::scxml_reader::ATTR_RFSM_RESET_ON_ENTRY].
+    pub reset_on_entry: bool,
+
     /// The script that is executed if the state is entered. See W3c comments for \<onentry\> above.
     pub onentry: Vec<ExecutableContentId>,
 
@@ -3363,11 +6201,19 @@ pub struct State {
     /// The initial data values on this state.
     pub data: HashMap<String, DataArc>,
 
-    /// True if the state was never entered before.
+    /// Locations of \<data\> elements with a 'src' attribute. Key: the id of the data element.\
+    /// The content is fetched if the data is initialized, see [Datamodel::initializeDataModel].
+    pub data_src: HashMap<String, String>,
+
+    /// True if the state was never entered before. Used for "late" binding, reset if the session starts.
     pub isFirstEntry: bool,
 
     pub parent: StateId,
     pub donedata: Option<DoneData>,
+
+    /// Attributes of other namespaces, e.g. "ui:x". Not used for execution.
+    /// See [crate::scxml_reader::parse_from_xml_strict].
+    pub annotations: HashMap<String, String>,
 }
 
 impl State {
@@ -3375,6 +6221,7 @@ impl State {
         State {
             id: 0,
             doc_id: 0,
+            source_line: 0,
             name: name.to_string(),
             initial: 0,
             states: vec![],
@@ -3384,19 +6231,54 @@ impl State {
             is_parallel: false,
             is_final: false,
             history_type: HistoryType::None,
+            reset_on_entry: false,
             data: HashMap::new(),
+            data_src: HashMap::new(),
             isFirstEntry: true,
             parent: 0,
             donedata: None,
             invoke: List::new(),
             history: List::new(),
+            annotations: HashMap::new(),
         }
     }
+
+    /// Gets the value of an attribute of another namespace, e.g. "ui:x".
+    pub fn annotation(&self, name: &str) -> Option<&str> {
+        self.annotations.get(name).map(|v| v.as_str())
+    }
 }
 
 impl Clone for State {
+    /// Copies the state. The data is copied deeply, so that the copy can be used by another session.
     fn clone(&self) -> Self {
-        todo!()
+        State {
+            id: self.id,
+            doc_id: self.doc_id,
+            source_line: self.source_line,
+            name: self.name.clone(),
+            initial: self.initial,
+            states: self.states.clone(),
+            is_parallel: self.is_parallel,
+            is_final: self.is_final,
+            history_type: self.history_type,
+            reset_on_entry: self.reset_on_entry,
+            onentry: self.onentry.clone(),
+            onexit: self.onexit.clone(),
+            transitions: self.transitions.clone(),
+            invoke: self.invoke.clone(),
+            history: self.history.clone(),
+            data: self
+                .data
+                .iter()
+                .map(|(name, value)| (name.clone(), value.deep_copy()))
+                .collect(),
+            data_src: self.data_src.clone(),
+            isFirstEntry: self.isFirstEntry,
+            parent: self.parent,
+            donedata: self.donedata.clone(),
+            annotations: self.annotations.clone(),
+        }
     }
 }
 
@@ -3441,7 +6323,7 @@ pub fn map_history_type(ts: &String) -> HistoryType {
     }
 }
 
-#[derive(Debug, PartialEq, Default)]
+#[derive(Debug, PartialEq, Default, Clone, Copy)]
 #[repr(u8)]
 pub enum TransitionType {
     Internal,
@@ -3479,14 +6361,18 @@ pub fn map_transition_type(ts: &String) -> TransitionType {
 
 pub(crate) static ID_COUNTER: AtomicU32 = AtomicU32::new(1);
 pub(crate) static SESSION_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
+pub(crate) static DOC_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
+pub(crate) static SOURCE_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
 
 pub type TransitionId = u32;
 
 /// A state to state transition with references to content that shall be executed with the transition.
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Transition {
     pub id: TransitionId,
     pub doc_id: DocumentId,
+    /// Line of the element in the source document.
+    pub source_line: SourceLine,
 
     // TODO: Possibly we need some type to express event ids
     pub events: Vec<String>,
@@ -3496,6 +6382,11 @@ pub struct Transition {
     pub target: Vec<StateId>,
     pub transition_type: TransitionType,
     pub content: ExecutableContentId,
+    /// rFSM extension: Name of a native action that is called after the executable content
+    /// of the transition. See [FsmExecutor::register_transition_action].
+    pub action: Option<String>,
+    /// Attributes of other namespaces. Not used for execution.
+    pub annotations: HashMap<String, String>,
 }
 
 impl PartialEq for Transition {
@@ -3510,6 +6401,7 @@ impl Transition {
         Transition {
             id: idc,
             doc_id: 0,
+            source_line: 0,
             events: vec![],
             wildcard: false,
             cond: Data::Null(),
@@ -3517,9 +6409,16 @@ impl Transition {
             target: vec![],
             transition_type: TransitionType::External,
             content: 0,
+            action: None,
+            annotations: HashMap::new(),
         }
     }
 
+    /// Gets the value of an attribute of another namespace.
+    pub fn annotation(&self, name: &str) -> Option<&str> {
+        self.annotations.get(name).map(|v| v.as_str())
+    }
+
     #[allow(non_snake_case)]
     /// W3C says:\
     /// An event descriptor matches an event name if its string of tokens is an exact match or a prefix
@@ -3536,27 +6435,38 @@ impl Transition {
     /// Implementation Note:
     /// Terminating "." and ".*" are already stripped by the parser.
     fn nameMatch(&self, name: &str) -> bool {
-        if self.wildcard {
-            true
+        self.wildcard
+            || self
+                .events
+                .iter()
+                .any(|e| event_descriptor_matches(e, name))
+    }
+}
+
+/// Strips the redundant "." and ".*" suffixes from an event descriptor.\
+/// "error", "error." and "error.*" are stored as "error".
+pub fn strip_event_descriptor(descriptor: &str) -> &str {
+    let mut rt = descriptor;
+    loop {
+        if let Some(r) = rt.strip_suffix(".*") {
+            rt = r;
+        } else if let Some(r) = rt.strip_suffix('.') {
+            rt = r;
         } else {
-            for e in &self.events {
-                if name.starts_with(e) {
-                    if name.len() == e.len() {
-                        // Full match
-                        return true;
-                    } else if let Some(c) = name.chars().nth(e.len()) {
-                        // partial match, token needs to be terminated with "."
-                        if c == '.' {
-                            return true;
-                        }
-                    }
-                }
-            }
-            false
+            return rt;
         }
     }
 }
 
+/// Checks if the (stripped) descriptor is an exact match or a token prefix of the event name.
+/// Partial tokens don't match, "foo" matches "foo.bar" but not "foobar".
+pub fn event_descriptor_matches(descriptor: &str, name: &str) -> bool {
+    match name.strip_prefix(descriptor) {
+        None => false,
+        Some(rest) => rest.is_empty() || rest.starts_with('.'),
+    }
+}
+
 lazy_static! {
     static ref datamodel_factories: Arc<Mutex<HashMap<String, Box<dyn DatamodelFactory>>>> = {
         let mut hs: HashMap<String, Box<dyn DatamodelFactory>> = HashMap::new();
@@ -3624,8 +6534,9 @@ impl Display for State {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{{#{} states:{} transitions: {}}}",
+            "{{#{} line:{} states:{} transitions: {}}}",
             self.id,
+            self.source_line,
             vec_to_string(&self.states),
             vec_to_string(&self.transitions.data)
         )
@@ -3636,8 +6547,16 @@ impl Display for Transition {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "{{#{} {} {:?} target:{:?}}}",
-            self.id, self.transition_type, &self.events, self.target
+            "{{#{} line:{} {} {:?} target:{:?}{}}}",
+            self.id,
+            self.source_line,
+            self.transition_type,
+            &self.events,
+            self.target,
+            match &self.action {
+                Some(action) => format!(" action:{}", action),
+                None => String::new(),
+            }
         )
     }
 }
@@ -3669,6 +6588,23 @@ impl Display for OrderedSet<u32> {
     }
 }
 
+/// Gets the lower-case scheme of an URI, e.g. "file" or "http".\
+/// Single letters are not considered as scheme, as they are drive letters of windows paths.
+pub(crate) fn uri_scheme(uri: &str) -> Option<String> {
+    let idx = uri.find(':')?;
+    let scheme = &uri[..idx];
+    if scheme.len() > 1
+        && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.')
+    {
+        Some(scheme.to_ascii_lowercase())
+    } else {
+        None
+    }
+}
+
 pub(crate) fn vec_to_string<T: Display>(v: &[T]) -> String {
     let mut s = "[".to_string();
 
@@ -3726,8 +6662,8 @@ impl Action for DebugAction {
 
 #[cfg(test)]
 mod tests {
+    use crate::fsm::List;
     use crate::fsm::OrderedSet;
-    use crate::fsm::{EventType, List};
     #[cfg(feature = "Trace")]
     use crate::tracer::TraceMode;
     use std::collections::HashMap;
@@ -3738,19 +6674,203 @@ mod tests {
 
     #[cfg(feature = "ECMAScript")]
     #[cfg(feature = "xml")]
-    use std::sync::mpsc::Sender;
+    use crate::fsm::ConfigurationUpdate;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::sync::mpsc::sync_channel;
 
     use crate::test::run_test_manual_with_send;
     #[cfg(feature = "ECMAScript")]
     #[cfg(feature = "xml")]
-    use crate::Event;
+    use crate::{actions::Action, datamodel::Data, fsm::GlobalData};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::{
+        actions::ActionWrapper,
+        fsm::{start_fsm_with_data_and_finish_mode, FinishMode, EVENT_CANCEL_SESSION},
+    };
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::{fsm_executor::FsmExecutor, test::run_test_manual_with_executor};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::{Event, EventSender};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::{thread, time::Duration};
 
     #[cfg(feature = "ECMAScript")]
     #[cfg(feature = "xml")]
-    fn test_send(sender: &Sender<Box<Event>>, e: Event) {
+    fn test_send(sender: &EventSender, e: Event) {
         let _r = sender.send(Box::new(e));
     }
 
+    /// Creates a document with a parallel root state of "regions" regions. Each region contains a chain
+    /// of compound states with "depth" levels and two atomic leaves.
+    #[cfg(feature = "xml")]
+    fn generated_document(regions: usize, depth: usize) -> String {
+        let mut xml = String::from("<scxml initial='P' datamodel='null'><parallel id='P'>");
+        for r in 0..regions {
+            for d in 0..depth {
+                xml.push_str(format!("<state id='r{}_{}'>", r, d).as_str());
+            }
+            // Targets refer to states that are defined later, so state ids differ from document order.
+            xml.push_str(
+                format!(
+                    "<state id='r{}_a'><transition event='e' target='r{}_b'/></state><state id='r{}_b'/>",
+                    r, r, r
+                )
+                .as_str(),
+            );
+            for _ in 0..depth {
+                xml.push_str("</state>");
+            }
+        }
+        xml.push_str("</parallel></scxml>");
+        xml
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn state_tables_keep_ordering_semantics() {
+        use crate::fsm::{StateId, StateTables};
+
+        let mut fsm = scxml_reader::parse_from_xml(generated_document(3, 3)).unwrap();
+        let ids: List<StateId> = List::from_array(&(1..=fsm.states.len() as StateId).collect::<Vec<StateId>>());
+        let order_by_states = ids.sort(&|a, b| fsm.state_entry_order(a, b));
+        let mut pairs = Vec::new();
+        for s1 in ids.iterator() {
+            for s2 in ids.iterator() {
+                pairs.push((
+                    fsm.isDescendant(*s1, *s2),
+                    fsm.getProperAncestors(*s1, *s2).toList().data,
+                ));
+            }
+        }
+
+        fsm.state_tables = StateTables::new(&fsm.states);
+        assert!(!fsm.state_tables.is_empty());
+        let order_by_tables = ids.sort(&|a, b| fsm.state_entry_order(a, b));
+        assert_eq!(order_by_tables.data, order_by_states.data);
+        let names: Vec<String> = order_by_tables
+            .iterator()
+            .map(|s| fsm.get_state_by_id(*s).name.clone())
+            .filter(|n| n.starts_with("r0"))
+            .collect();
+        assert_eq!(names, vec!["r0_0", "r0_1", "r0_2", "r0_a", "r0_b"]);
+        let exit_order = ids.sort(&|a, b| fsm.state_exit_order(a, b));
+        assert_eq!(
+            exit_order.data,
+            order_by_tables
+                .data
+                .iter()
+                .rev()
+                .cloned()
+                .collect::<Vec<StateId>>()
+        );
+
+        let mut idx = 0;
+        for s1 in ids.iterator() {
+            for s2 in ids.iterator() {
+                assert_eq!(
+                    (
+                        fsm.isDescendant(*s1, *s2),
+                        fsm.getProperAncestors(*s1, *s2).toList().data
+                    ),
+                    pairs[idx]
+                );
+                idx += 1;
+            }
+        }
+    }
+
+    /// Compares the duration of ordering and ancestry checks with and without [crate::fsm::StateTables].\
+    /// Run with "cargo test --release state_tables_benchmark -- --ignored --nocapture".
+    #[test]
+    #[ignore]
+    #[cfg(feature = "xml")]
+    fn state_tables_benchmark() {
+        use crate::fsm::{Fsm, StateId, StateTables};
+        use std::time::Instant;
+
+        // 1000 regions with 3 compound levels and 2 leaves, ~5000 states.
+        let mut fsm = scxml_reader::parse_from_xml(generated_document(1000, 3)).unwrap();
+        let ids: List<StateId> = List::from_array(&(1..=fsm.states.len() as StateId).collect::<Vec<StateId>>());
+        let leaves: Vec<StateId> = ids
+            .iterator()
+            .filter(|s| fsm.get_state_by_id(**s).states.is_empty())
+            .cloned()
+            .collect();
+
+        let run = |fsm: &Fsm| {
+            let started = Instant::now();
+            for _ in 0..20 {
+                let _ = ids.sort(&|a, b| fsm.state_entry_order(a, b));
+                for leaf in &leaves {
+                    let _ = fsm.getProperAncestors(*leaf, fsm.pseudo_root);
+                    let _ = fsm.isDescendant(*leaf, fsm.pseudo_root);
+                }
+            }
+            started.elapsed()
+        };
+        let without_tables = run(&fsm);
+        fsm.state_tables = StateTables::new(&fsm.states);
+        let with_tables = run(&fsm);
+        println!(
+            "{} states: without tables {:?}, with tables {:?}",
+            fsm.states.len(),
+            without_tables,
+            with_tables
+        );
+    }
+
+    #[test]
+    fn event_descriptor_matching() {
+        use crate::fsm::{event_descriptor_matches, strip_event_descriptor, Transition};
+
+        // Examples from the W3C recommendation, section 3.12.1 "Event Descriptors".
+        let mut transition = Transition::new();
+        transition.events = "error foo"
+            .split_whitespace()
+            .map(|s| strip_event_descriptor(s).to_string())
+            .collect();
+        for name in ["error", "error.send", "error.send.failed", "foo", "foo.bar"] {
+            assert!(transition.nameMatch(name), "'{}' shall match", name);
+        }
+        for name in [
+            "errors.my.custom",
+            "errorhandler.mistake",
+            "errorsend",
+            "foobar",
+            "foobar.x",
+        ] {
+            assert!(!transition.nameMatch(name), "'{}' shall not match", name);
+        }
+
+        // "error", "error." and "error.*" are equivalent.
+        for descriptor in ["error", "error.", "error.*"] {
+            assert_eq!(strip_event_descriptor(descriptor), "error");
+        }
+        assert!(event_descriptor_matches(
+            strip_event_descriptor("foo.*"),
+            "foo.bar.baz"
+        ));
+        assert!(!event_descriptor_matches(
+            strip_event_descriptor("foo.*"),
+            "foobar"
+        ));
+        assert!(event_descriptor_matches("error.send", "error.send.failed"));
+        assert!(!event_descriptor_matches("error.send", "error"));
+        // Token matching is case-sensitive.
+        assert!(!event_descriptor_matches("Error", "error.send"));
+        // Multibyte characters must not break the token boundary check.
+        assert!(event_descriptor_matches("\u{e4}", "\u{e4}.x"));
+        assert!(!event_descriptor_matches("\u{e4}", "\u{e4}\u{f6}.x"));
+
+        transition.events = vec!["*".to_string()];
+        transition.wildcard = true;
+        assert!(transition.nameMatch("anything.at.all"));
+    }
     #[test]
     fn list_can_can_push() {
         let mut l: List<String> = List::new();
@@ -3770,7 +6890,7 @@ mod tests {
         l1.push("def1".to_string());
         l1.push("ghi1".to_string());
 
-        assert_eq!(l1.head(), &"Abc".to_string());
+        assert_eq!(l1.head(), Some(&"Abc".to_string()));
     }
 
     #[test]
@@ -3864,7 +6984,7 @@ mod tests {
         let mut l2 = l1.sort(&|a, b| a.partial_cmp(b).unwrap());
 
         while l1.size() > 0 {
-            let e = l1.head();
+            let e = l1.head().unwrap();
             println!(" {}", e);
             l1v.push(e.clone());
             l1 = l1.tail();
@@ -3876,7 +6996,7 @@ mod tests {
         println!("Sorted ======");
         let mut i = 0;
         while l2.size() > 0 {
-            let h = l2.head().clone();
+            let h = l2.head().unwrap().clone();
             l2 = l2.tail();
             println!(" {}", h);
             assert!(h.eq(l1v.get(i).unwrap()));
@@ -4069,7 +7189,9 @@ mod tests {
 
         assert!(sm.is_ok(), "FSM shall be parsed");
 
-        let fsm = sm.unwrap();
+        let mut fsm = sm.unwrap();
+        let (listener, updates) = sync_channel(16);
+        fsm.add_configuration_listener(listener);
 
         let expected_config = vec!["OuterFinal".to_string()];
 
@@ -4085,35 +7207,2071 @@ mod tests {
                 &expected_config,
                 |sender| {
                     println!("Send Event");
-                    test_send(
-                        &sender,
-                        Event {
-                            name: "ab".to_string(),
-                            etype: EventType::platform,
-                            sendid: Some("0".to_string()),
-                            origin: None,
-                            origin_type: None,
-                            invoke_id: None,
-                            param_values: None,
-                            content: None,
-                        },
-                    );
-                    test_send(
-                        &sender,
-                        Event {
-                            name: "exit".to_string(),
-                            etype: EventType::platform,
-                            sendid: Some("0".to_string()),
-                            origin: None,
-                            origin_type: None,
-                            invoke_id: None,
-                            param_values: None,
-                            content: None,
-                        },
-                    );
+                    test_send(&sender, Event::builder("ab").build());
+                    test_send(&sender, Event::builder("exit").build());
                 },
             ),
             "FSM shall terminate with state 'OuterFinal'"
         );
+
+        let updates: Vec<ConfigurationUpdate> = updates.try_iter().collect();
+        let expected: Vec<(Vec<&str>, Vec<&str>)> = vec![
+            (vec!["Main", "MainA"], vec![]),
+            (vec!["finalMe"], vec!["MainA"]),
+            (vec!["OuterFinal"], vec!["finalMe", "Main"]),
+            (vec![], vec!["OuterFinal"]),
+        ];
+        assert_eq!(updates.len(), expected.len(), "{:?}", updates);
+        for (i, (update, (entered, exited))) in updates.iter().zip(expected).enumerate() {
+            assert_eq!(update.step, (i + 1) as u64);
+            assert_eq!(update.entered, entered);
+            assert_eq!(update.exited, exited);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn nested_parallel_states_are_done() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='P1' datamodel='ecmascript'>
+              <datamodel><data id='done' expr='""'/></datamodel>
+              <parallel id='P1'>
+                <parallel id='P2'>
+                  <parallel id='P3'>
+                    <state id='R1'>
+                      <state id='R1a'><transition event='go' target='R1f'/></state>
+                      <final id='R1f'/>
+                    </state>
+                    <state id='R2'><final id='R2f'/></state>
+                  </parallel>
+                  <state id='R3'><final id='R3f'/></state>
+                </parallel>
+                <state id='R4'><final id='R4f'/></state>
+                <transition event='done.state.P3 done.state.P2'>
+                  <assign location='done' expr='done + _event.name.substring(11)'/>
+                </transition>
+                <transition event='done.state.P1' cond='done == "P3P2"' target='pass'/>
+                <transition event='done.state.P1' target='fail'/>
+              </parallel>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm.unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let _ = session.sender.send(Box::new(Event::new_simple("go")));
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn cancel_invoke_terminates_child() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <send event='leave' delay='200ms'/>
+                </onentry>
+                <invoke id='child' type='scxml'>
+                  <content>
+                    <scxml initial='Wait' datamodel='ecmascript'>
+                      <state id='Wait'>
+                        <onentry>
+                          <send event='timeout' delay='10s'/>
+                        </onentry>
+                        <transition event='timeout' target='Done'/>
+                      </state>
+                      <final id='Done'/>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='leave' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let executor = FsmExecutor::new_without_io_processor();
+        let executor_state = executor.state.clone();
+
+        assert!(run_test_manual_with_executor(
+            "cancel_invoke_terminates_child",
+            executor,
+            sm.unwrap(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+            |_sender| {},
+        ));
+
+        // Only the session of the parent shall survive.
+        let mut sessions = 0;
+        for _ in 0..20 {
+            sessions = executor_state.lock().unwrap().session_infos.len();
+            if sessions == 1 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(sessions, 1, "Child session shall be terminated");
+    }
+
+    #[test]
+    fn uri_is_resolved_against_base() {
+        let mut fsm = crate::fsm::Fsm::new();
+        assert_eq!(
+            fsm.resolve_uri("child.scxml"),
+            Ok("child.scxml".to_string())
+        );
+
+        fsm.base_uri = Some("/data/machines/parent.scxml".to_string());
+        assert_eq!(
+            fsm.resolve_uri("child.scxml"),
+            Ok("/data/machines/child.scxml".to_string())
+        );
+        assert_eq!(
+            fsm.resolve_uri("file:sub/child.scxml"),
+            Ok("/data/machines/sub/child.scxml".to_string())
+        );
+        assert_eq!(
+            fsm.resolve_uri("file:///other/child.scxml"),
+            Ok("/other/child.scxml".to_string())
+        );
+        assert_eq!(
+            fsm.resolve_uri("http://localhost/child.scxml"),
+            Ok("http://localhost/child.scxml".to_string())
+        );
+        assert!(fsm.resolve_uri("ftp://localhost/child.scxml").is_err());
+
+        fsm.base_uri = Some("http://localhost/machines/parent.scxml".to_string());
+        assert_eq!(
+            fsm.resolve_uri("child.scxml"),
+            Ok("http://localhost/machines/child.scxml".to_string())
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn invoke_src_is_relative_to_parent_document() {
+        let dir = std::env::temp_dir().join(format!("rfsm_invoke_src_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("machines")).unwrap();
+        std::fs::write(
+            dir.join("machines").join("child.scxml"),
+            "<scxml initial='Done' datamodel='ecmascript'><final id='Done'/></scxml>",
+        )
+        .unwrap();
+        let parent = dir.join("machines").join("parent.scxml");
+        std::fs::write(
+            &parent,
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <invoke id='child' src='child.scxml'/>
+                <transition event='done.invoke.child' target='Scheme'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Scheme'>
+                <invoke id='unsupported' src='ftp://localhost/child.scxml'/>
+                <transition event='error.communication' cond="_event.invokeid == 'unsupported'" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+        )
+        .unwrap();
+
+        let sm = scxml_reader::parse_from_xml_file(&parent, &[]);
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let result = run_test_manual_with_executor(
+            "invoke_src_is_relative_to_parent_document",
+            FsmExecutor::new_without_io_processor(),
+            sm.unwrap(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+            |_sender| {},
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(result);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn failed_invoke_raises_error_in_parent() {
+        let dir = std::env::temp_dir().join(format!("rfsm_invoke_failed_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("broken.scxml"),
+            "<scxml initial='A' datamodel='ecmascript'><state id='A'></scxml>",
+        )
+        .unwrap();
+        let parent = dir.join("parent.scxml");
+        std::fs::write(
+            &parent,
+            r##"<scxml initial='Missing' datamodel='ecmascript'>
+              <state id='Missing'>
+                <invoke id='missing' src='missing.scxml'/>
+                <transition event='error.communication'
+                    cond="_event.invokeid == 'missing' &amp;&amp; _event.data.length > 0" target='Broken'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Broken'>
+                <invoke id='broken' src='broken.scxml'/>
+                <transition event='error.execution'
+                    cond="_event.invokeid == 'broken' &amp;&amp; _event.data.length > 0" target='Inline'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Inline'>
+                <invoke id='inline'><content>no document</content></invoke>
+                <transition event='error.execution'
+                    cond="_event.invokeid == 'inline' &amp;&amp; _event.data.length > 0" target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+        )
+        .unwrap();
+
+        let sm = scxml_reader::parse_from_xml_file(&parent, &[]).unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        let _ = std::fs::remove_dir_all(&dir);
+        assert_eq!(
+            result.and_then(|r| r.final_configuration),
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn session_result_can_be_awaited() {
+        let xml = r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <transition event='go' target='pass'/>
+              </state>
+              <final id='pass'/>
+            </scxml>"##;
+
+        // Session that reaches a final state.
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        assert!(session
+            .wait_for_completion(Duration::from_millis(50))
+            .is_none());
+        test_send(&session.sender, Event::new_simple("go"));
+
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        let result = result.unwrap();
+        assert_eq!(result.session_id, session.session_id);
+        assert!(result.final_state_reached);
+        assert_eq!(result.final_configuration, Some(vec!["pass".to_string()]));
+        assert!(session.get_result().is_some());
+
+        // Session that is cancelled.
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::DISPOSE,
+        );
+        test_send(&session.sender, Event::new_simple(EVENT_CANCEL_SESSION));
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        let result = result.unwrap();
+        assert!(!result.final_state_reached);
+        assert!(result.final_configuration.is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn cancel_passes_pending_events() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let xml = r##"<scxml initial='Main' datamodel='null' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+              <state id='Main'>
+                <transition event='tick' rfsm:action='slow'/>
+              </state>
+            </scxml>"##;
+
+        let handled = Arc::new(AtomicUsize::new(0));
+        let handled_clone = handled.clone();
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.register_transition_action(
+            "slow",
+            Box::new(move |_: &Event, _| {
+                handled_clone.fetch_add(1, Ordering::Relaxed);
+                thread::sleep(Duration::from_millis(1));
+            }),
+        );
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::DISPOSE,
+        );
+        for _ in 0..10000 {
+            test_send(&session.sender, Event::new_simple("tick"));
+        }
+        test_send(&session.sender, Event::new_simple(EVENT_CANCEL_SESSION));
+
+        // Processing the backlog would take at least 10 seconds.
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall be cancelled");
+        assert!(!result.unwrap().final_state_reached);
+        assert!(handled.load(Ordering::Relaxed) < 10000);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn blocked_send_does_not_block_other_sessions() {
+        use crate::fsm::OverflowPolicy;
+
+        let start = |executor: &FsmExecutor, xml: String| {
+            start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            )
+        };
+        let counting = |event: &str, target: &str| {
+            format!(
+                r##"<scxml initial='Main' datamodel='ecmascript' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+                  <datamodel><data id='count' expr='0'/></datamodel>
+                  <state id='Main'>
+                    <transition cond='count == 6' target='done'/>
+                    <transition event='{event}' rfsm:action='slow'>
+                      <assign location='count' expr='count + 1'/>
+                      {target}
+                    </transition>
+                  </state>
+                  <final id='done'/>
+                </scxml>"##
+            )
+        };
+
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_queue_limit(2, OverflowPolicy::Block);
+        executor.register_transition_action(
+            "slow",
+            Box::new(|_: &Event, _| thread::sleep(Duration::from_millis(20))),
+        );
+
+        // A fills the queue of B and waits, while B sends to C.
+        let c = start(&executor, counting("fromB", ""));
+        let b = start(
+            &executor,
+            counting(
+                "fromA",
+                format!("<send event='fromB' target='#_scxml_{}'/>", c.session_id).as_str(),
+            ),
+        );
+        let a = start(
+            &executor,
+            format!(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <datamodel><data id='items' expr='[1,2,3,4,5,6]'/></datamodel>
+                  <final id='Main'>
+                    <onentry>
+                      <foreach array='items' item='item'>
+                        <send event='fromA' target='#_scxml_{}'/>
+                      </foreach>
+                    </onentry>
+                  </final>
+                </scxml>"##,
+                b.session_id
+            ),
+        );
+
+        for (name, session) in [("A", &a), ("B", &b), ("C", &c)] {
+            let result = session.wait_for_completion(Duration::from_millis(5000));
+            assert!(result.is_some(), "Session {} shall finish", name);
+            assert!(result.unwrap().final_state_reached, "Session {}", name);
+        }
+        assert_eq!(b.dropped_events(), 0);
+        assert_eq!(c.dropped_events(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    #[cfg(feature = "json-config")]
+    #[cfg(feature = "xml")]
+    fn built_events_carry_data_in_both_datamodels() {
+        for datamodel in ["ecmascript", "rfsm-expression"] {
+            let xml = format!(
+                r##"<scxml initial='Main' datamodel='{}'>
+                  <state id='Main'>
+                    <transition event='entries' target='Json'
+                        cond="_event.data.count == 2 &amp;&amp; _event.data.label == 'x' &amp;&amp; _event.type == 'external'"/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <state id='Json'>
+                    <transition event='json' cond='_event.data.a.b == 7' target='pass'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##,
+                datamodel
+            );
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml).unwrap(),
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            session
+                .send_event(
+                    Event::builder("entries")
+                        .data_entry("count", Data::Integer(2))
+                        .data_entry("label", Data::String("x".to_string())),
+                )
+                .unwrap();
+            session
+                .send_event(Event::builder("json").content_json(serde_json::json!({"a": {"b": 7}})))
+                .unwrap();
+            assert_eq!(
+                session
+                    .wait_for_completion(Duration::from_millis(2000))
+                    .and_then(|r| r.final_configuration),
+                Some(vec!["pass".to_string()]),
+                "{}",
+                datamodel
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "RfsmExpressionModel")]
+    #[cfg(feature = "xml")]
+    fn platform_variable_in_both_datamodels() {
+        for datamodel in ["ecmascript", "rfsm-expression"] {
+            let xml = format!(
+                r##"<scxml initial='Main' datamodel='{datamodel}'>
+                  <state id='Main'>
+                    <invoke id='child'>
+                      <content>
+                        <scxml initial='ChildMain' datamodel='{datamodel}'>
+                          <final id='ChildMain'>
+                            <onentry>
+                              <if cond="_x.invoke_id == 'child'">
+                                <if cond='_x.parent_session_id != null'>
+                                  <send target='#_parent' event='childOk'/>
+                                </if>
+                              </if>
+                            </onentry>
+                          </final>
+                        </scxml>
+                      </content>
+                    </invoke>
+                    <transition event='childOk' target='Own'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <state id='Own'>
+                    <onentry>
+                      <if cond='_x.session_id.toString() == _sessionid'>
+                        <if cond='_x.invoke_id == null'>
+                          <if cond="_x.version == '{version}'">
+                            <raise event='ownOk'/>
+                          </if>
+                        </if>
+                      </if>
+                    </onentry>
+                    <transition event='ownOk' target='AssignMember'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <state id='AssignMember'>
+                    <onentry>
+                      <assign location='_x.session_id' expr='0'/>
+                    </onentry>
+                    <transition event='error.execution' cond='_x.session_id.toString() == _sessionid' target='AssignRoot'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <state id='AssignRoot'>
+                    <onentry>
+                      <assign location='_x' expr='0'/>
+                    </onentry>
+                    <transition event='error.execution' cond='_x.session_id.toString() == _sessionid' target='pass'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##,
+                version = env!("CARGO_PKG_VERSION")
+            );
+            assert!(
+                run_test_manual_with_send(
+                    "platform_variable_in_both_datamodels",
+                    &HashMap::new(),
+                    scxml_reader::parse_from_xml(xml).unwrap(),
+                    &Vec::new(),
+                    #[cfg(feature = "Trace")]
+                    TraceMode::STATES,
+                    2000u64,
+                    &vec!["pass".to_string()],
+                    |_sender| {},
+                ),
+                "{}",
+                datamodel
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn history_is_reset_on_entry_only_if_requested() {
+        let run = |reset_on_entry: bool, restart: bool, clear: bool| {
+            let xml = format!(
+                r##"<scxml initial='P' datamodel='null' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+                  <state id='P' initial='Start'>
+                    <state id='Start'>
+                      <transition event='toQ' target='Q'/>
+                    </state>
+                    <state id='Q' initial='Q1'>
+                      <history id='H' type='deep' rfsm:resetonentry='{}'>
+                        <transition target='Q1'/>
+                      </history>
+                      <state id='Q1'><transition event='next' target='Q2'/></state>
+                      <state id='Q2'/>
+                      <transition event='leave' target='Start'/>
+                    </state>
+                    <transition event='restart' target='P'/>
+                    <transition event='resume' target='H'/>
+                    <transition event='check' cond="In('Q2')" target='WasQ2'/>
+                    <transition event='check' target='WasQ1'/>
+                  </state>
+                  <final id='WasQ1'/>
+                  <final id='WasQ2'/>
+                </scxml>"##,
+                reset_on_entry
+            );
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml).unwrap(),
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let events: &[&str] = if restart {
+                &["toQ", "next", "leave", "restart"]
+            } else {
+                &["toQ", "next", "leave"]
+            };
+            for event in events {
+                session
+                    .send_and_sync(
+                        Box::new(Event::new_simple(event)),
+                        Duration::from_millis(2000),
+                    )
+                    .unwrap();
+            }
+            if clear {
+                session.clear_history().unwrap();
+            }
+            session.send_event(Event::builder("resume")).unwrap();
+            session.send_event(Event::builder("check")).unwrap();
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .and_then(|r| r.final_configuration)
+        };
+        // By default, the re-entry of "P" doesn't touch the history of "Q".
+        assert_eq!(run(false, true, false), Some(vec!["WasQ2".to_string()]));
+        assert_eq!(run(true, true, false), Some(vec!["WasQ1".to_string()]));
+        assert_eq!(run(true, false, false), Some(vec!["WasQ2".to_string()]));
+        assert_eq!(run(false, false, true), Some(vec!["WasQ1".to_string()]));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_and_sync_waits_for_macrostep() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <transition event='go' target='Step'/>
+              </state>
+              <state id='Step'>
+                <onentry><raise event='inner'/></onentry>
+                <transition event='inner' target='Done'/>
+              </state>
+              <state id='Done'>
+                <transition event='finish' target='pass'/>
+              </state>
+              <final id='pass'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let done_id = fsm.statesNames["Done"];
+
+        let session = start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::DISPOSE,
+        );
+        let timeout = Duration::from_millis(2000);
+        assert!(session
+            .send_and_sync(Box::new(Event::new_simple("go")), timeout)
+            .is_ok());
+        assert!(
+            session
+                .global_data
+                .lock()
+                .unwrap()
+                .configuration
+                .isMember(&done_id),
+            "Internal event shall be processed before the sync returns"
+        );
+
+        assert!(session
+            .send_and_sync(Box::new(Event::new_simple("finish")), timeout)
+            .is_ok());
+        assert!(session.wait_for_completion(timeout).is_some());
+        assert!(session
+            .send_and_sync(Box::new(Event::new_simple("go")), timeout)
+            .is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn autoforward_without_livelock() {
+        // The child answers every event with "pong". The answer must not be forwarded back.
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel><data id='pongs' expr='0'/></datamodel>
+              <state id='Main'>
+                <onentry>
+                  <send event='ping'/>
+                  <send event='timeout' delay='1s'/>
+                </onentry>
+                <invoke id='child' autoforward='true'>
+                  <content>
+                    <scxml initial='ChildMain' datamodel='ecmascript'>
+                      <state id='ChildMain'>
+                        <invoke id='grandchild' autoforward='true'>
+                          <content>
+                            <scxml initial='GrandChildMain' datamodel='ecmascript'>
+                              <state id='GrandChildMain'>
+                                <transition event='*'>
+                                  <send target='#_parent' event='pong'/>
+                                </transition>
+                              </state>
+                            </scxml>
+                          </content>
+                        </invoke>
+                        <transition event='ping'>
+                          <if cond="_event.origintype == 'http://www.w3.org/TR/scxml/#SCXMLEventProcessor'">
+                            <send target='#_parent' event='pong'/>
+                          </if>
+                        </transition>
+                        <transition event='pong'>
+                          <send target='#_parent' event='pong'/>
+                        </transition>
+                      </state>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='pong'>
+                  <assign location='pongs' expr='pongs + 1'/>
+                </transition>
+                <transition event='timeout' cond='pongs == 2' target='pass'/>
+                <transition event='timeout' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        assert!(run_test_manual_with_send(
+            "autoforward_without_livelock",
+            &HashMap::new(),
+            sm.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            3000,
+            &vec!["pass".to_string()],
+            |_sender| {},
+        ));
+    }
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[derive(Clone)]
+    struct CrashAction {}
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    impl Action for CrashAction {
+        fn execute(&self, _arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+            panic!("Crash requested");
+        }
+
+        fn get_copy(&self) -> Box<dyn Action> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn failed_child_sends_error_platform() {
+        let children = [
+            // Interpreter is aborted.
+            "<scxml initial='C' datamodel='ecmascript'><state id='C'><onentry><script>crash()</script></onentry></state></scxml>",
+            // Document is not valid.
+            "<scxml initial='C' datamodel='ecmascript'><state id='C'><history id='H'/></state></scxml>",
+        ];
+        for child in children {
+            let sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <state id='Main'>
+                    <invoke id='child' type='scxml'><content>{}</content></invoke>
+                    <transition event='error.platform' cond="_event.invokeid == 'child'" target='pass'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##,
+                child
+            ))
+            .unwrap();
+            let mut actions = ActionWrapper::new();
+            actions.add_action("crash", Box::new(CrashAction {}));
+            let executor = FsmExecutor::new_without_io_processor();
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                actions,
+                Box::new(executor.clone()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(3000));
+            assert!(result.is_some(), "Parent shall finish");
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec!["pass".to_string()])
+            );
+            // The child is removed from the executor, only the record of the parent is left.
+            let mut sessions = Vec::new();
+            for _ in 0..100 {
+                sessions = executor
+                    .list_sessions()
+                    .iter()
+                    .map(|s| s.session_id)
+                    .collect();
+                if sessions.len() == 1 {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+            assert_eq!(sessions, vec![session.session_id]);
+        }
+    }
+
+    /// Builds the document of a child session, depending on the mode.
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    #[derive(Clone)]
+    struct ChildBuilderAction {}
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    impl Action for ChildBuilderAction {
+        fn execute(&self, arguments: &[Data], _global: &GlobalData) -> Result<Data, String> {
+            let child = match arguments.first() {
+                Some(Data::String(mode)) if mode == "a" || mode == "b" => format!(
+                    "<scxml initial='C' datamodel='ecmascript'><state id='C'><onentry>\
+                     <send target='#_parent' event='child.{}'/></onentry></state></scxml>",
+                    mode
+                ),
+                Some(Data::String(mode)) if mode == "invalid" => {
+                    "<scxml initial='C'><state id='C' initial='A'><initial><transition/></initial></state></scxml>"
+                        .to_string()
+                }
+                _ => "<scxml initial='C'><state id='C'>".to_string(),
+            };
+            Ok(Data::String(child))
+        }
+
+        fn get_copy(&self) -> Box<dyn Action> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn invoke_content_expr_builds_child_document() {
+        let run = |mode: &str, expected: &str| {
+            let sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <datamodel><data id='mode' expr="'{mode}'"/></datamodel>
+                  <state id='Main'>
+                    <invoke id='child'><content expr='buildChildMachine(mode)'/></invoke>
+                    <transition event='child.a' target='a'/>
+                    <transition event='child.b' target='b'/>
+                    <transition event='error.execution'
+                        cond="_event.invokeid == 'child' &amp;&amp; _event.data.length > 0" target='error'/>
+                    <transition event='*' target='fail'/>
+                  </state>
+                  <final id='a'/>
+                  <final id='b'/>
+                  <final id='error'/>
+                  <final id='fail'/>
+                </scxml>"##
+            ))
+            .unwrap();
+            let mut actions = ActionWrapper::new();
+            actions.add_action("buildChildMachine", Box::new(ChildBuilderAction {}));
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                actions,
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(3000));
+            assert_eq!(
+                result.and_then(|r| r.final_configuration),
+                Some(vec![expected.to_string()]),
+                "mode {}",
+                mode
+            );
+        };
+        run("a", "a");
+        run("b", "b");
+        // Documents that can't be parsed or are rejected by the reader.
+        run("broken", "error");
+        run("invalid", "error");
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn late_binding_assigns_data_only_on_first_entry() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript' binding='late'>
+              <datamodel>
+                <data id='rootValue' expr='1'/>
+              </datamodel>
+              <state id='Main' initial='Outer'>
+                <onentry>
+                  <if cond='typeof counter !== "undefined" || typeof inner !== "undefined" || rootValue !== 1'>
+                    <raise event='fail'/>
+                  </if>
+                </onentry>
+                <state id='Outer' initial='Work'>
+                  <datamodel>
+                    <data id='counter' expr='10'/>
+                  </datamodel>
+                  <history id='H' type='deep'>
+                    <transition target='Work'/>
+                  </history>
+                  <state id='Work' initial='Deep'>
+                    <state id='Deep'>
+                      <datamodel>
+                        <data id='inner' expr='100'/>
+                      </datamodel>
+                      <onentry>
+                        <assign location='counter' expr='counter + 1'/>
+                        <assign location='inner' expr='inner + 1'/>
+                      </onentry>
+                    </state>
+                  </state>
+                  <transition event='leave' target='Away'/>
+                </state>
+                <state id='Away'>
+                  <transition event='back' target='H'/>
+                  <transition event='restart' target='Outer'/>
+                </state>
+                <transition event='check' cond='counter == 13 &amp;&amp; inner == 103 &amp;&amp; rootValue == 1' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='fail'/>
+              <final id='pass'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        // Re-entry via deep history and via a normal transition.
+        for name in ["leave", "back", "leave", "restart", "check"] {
+            test_send(&session.sender, Event::new_simple(name));
+        }
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn finalize_runs_before_transition_selection() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='a' expr='0'/>
+                <data id='b' expr='0'/>
+                <data id='doneFinalized' expr='0'/>
+              </datamodel>
+              <parallel id='Main'>
+                <state id='R1' initial='R1Wait'>
+                  <invoke id='child1'>
+                    <content>
+                      <scxml initial='C' datamodel='ecmascript'>
+                        <final id='C'>
+                          <onentry>
+                            <send event='result' target='#_parent'><param name='x' expr='5'/></send>
+                          </onentry>
+                        </final>
+                      </scxml>
+                    </content>
+                    <finalize>
+                      <if cond="_event.name == 'result'">
+                        <assign location='a' expr='_event.data.x'/>
+                        <elseif cond="_event.name == 'done.invoke.child1'"/>
+                        <assign location='doneFinalized' expr='doneFinalized + 1'/>
+                      </if>
+                    </finalize>
+                  </invoke>
+                  <state id='R1Wait'>
+                    <transition event='result' cond='a == 5' target='R1Ok'/>
+                  </state>
+                  <state id='R1Ok'/>
+                </state>
+                <state id='R2'>
+                  <invoke id='child2'>
+                    <content>
+                      <scxml initial='C' datamodel='ecmascript'><state id='C'/></scxml>
+                    </content>
+                    <finalize>
+                      <assign location='b' expr='b + 1'/>
+                    </finalize>
+                  </invoke>
+                </state>
+                <transition event='done.invoke.child1' cond="In('R1Ok') &amp;&amp; b == 0 &amp;&amp; doneFinalized == 1" target='pass'/>
+                <transition event='*' target='fail'/>
+              </parallel>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn error_execution_contains_source_line() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Expr' datamodel='ecmascript'>
+              <state id='Expr'>
+                <onentry><assign location='_sessionid' expr='1'/></onentry>
+                <transition event='error.execution' cond='_event.data.line == 3' target='Cond'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Cond'>
+                <onentry><raise event='go'/></onentry>
+                <transition event='go' cond='noSuchVariable.x == 1' target='fail'/>
+                <transition event='error.execution' cond='_event.data.line == 9' target='pass'/>
+                <transition event='error.execution' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let cond = sm.get_state_by_name(&"Cond".to_string()).unwrap();
+        assert_eq!(cond.source_line, 7);
+        let broken = sm.get_transition_by_id(*cond.transitions.head().unwrap());
+        assert_eq!(broken.source_line, 9);
+        assert!(format!("{}", broken).contains("line:9"));
+        let log = &sm.executableContent[&sm.get_state_by_name(&"Expr".to_string()).unwrap().onentry[0]][0];
+        assert_eq!(log.get_source_line(), 3);
+        assert!(format!("{}", sm.get_state_by_name(&"pass".to_string()).unwrap()).contains("line:13"));
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn expressions_can_be_evaluated_in_running_session() {
+        use crate::fsm::QueryError;
+
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='counter' expr='1'/>
+              </datamodel>
+              <state id='Main'>
+                <transition event='inc'>
+                  <assign location='counter' expr='counter + 1'/>
+                </transition>
+                <transition event='error.execution' target='fail'/>
+                <transition event='done' target='pass'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let timeout = Duration::from_millis(2000);
+        session
+            .sender
+            .send(Box::new(Event::new_simple("inc")))
+            .unwrap();
+        assert_eq!(
+            session.evaluate("counter * 10", timeout),
+            Ok(Data::Integer(20))
+        );
+        // Failed evaluations don't raise "error.execution" in the session.
+        assert!(session.evaluate("noSuchVariable.x", timeout).is_err());
+
+        // Each query is answered after the events sent before are processed.
+        assert_eq!(session.query("counter", timeout), Ok("2".to_string()));
+        session
+            .sender
+            .send(Box::new(Event::new_simple("inc")))
+            .unwrap();
+        assert_eq!(session.query("counter", timeout), Ok("3".to_string()));
+        assert!(matches!(
+            session.query("noSuchVariable.x", timeout),
+            Err(QueryError::Evaluation(_))
+        ));
+
+        session
+            .sender
+            .send(Box::new(Event::new_simple("done")))
+            .unwrap();
+        let result = session.wait_for_completion(timeout);
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+        assert!(session.evaluate("counter", timeout).is_err());
+        assert_eq!(
+            session.query("counter", timeout),
+            Err(QueryError::Finished(session.session_id))
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn multiple_initial_targets_enter_parallel_regions() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A2 B2' datamodel='ecmascript'>
+              <parallel id='P'>
+                <state id='A'>
+                  <state id='A1'/>
+                  <state id='A2'/>
+                </state>
+                <state id='B'>
+                  <state id='B1'/>
+                  <state id='B2'/>
+                </state>
+                <transition event='check' cond="In('A2') &amp;&amp; In('B2') &amp;&amp; !In('A1') &amp;&amp; !In('B1')" target='pass'/>
+                <transition event='check' target='fail'/>
+              </parallel>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        test_send(&session.sender, Event::new_simple("check"));
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn events_raised_in_parallel_regions_reach_sibling_regions() {
+        let region = |own: &str, other: &str| {
+            format!(
+                r##"<state id='{own}'>
+                  <state id='{own}a'>
+                    <onentry>
+                      <assign location='log' expr="log.concat(['enter {own}'])"/>
+                      <raise event='from{own}'/>
+                    </onentry>
+                    <transition event='from{other}' target='{own}b'>
+                      <assign location='log' expr="log.concat(['{own} got from{other}'])"/>
+                    </transition>
+                  </state>
+                  <final id='{own}b'/>
+                </state>"##
+            )
+        };
+        for (first, second) in [("R1", "R2"), ("R2", "R1")] {
+            let mut sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='P' datamodel='ecmascript'>
+                  <datamodel>
+                    <data id='log' expr='[]'/>
+                  </datamodel>
+                  <parallel id='P'>
+                    {}
+                    {}
+                    <transition event='done.state.P' target='Done'/>
+                  </parallel>
+                  <final id='Done'/>
+                </scxml>"##,
+                region(first, second),
+                region(second, first)
+            ))
+            .unwrap();
+            sm.final_value_locations = vec!["log.join(',')".to_string()];
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(2000));
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec!["Done".to_string()]),
+                "Order {} {}",
+                first,
+                second
+            );
+            // Both regions are entered before the raised events are processed, in the order of raising.
+            assert_eq!(
+                session.get_final_value("log.join(',')"),
+                Some(Data::String(format!(
+                    "enter {first},enter {second},{second} got from{first},{first} got from{second}"
+                )))
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn nested_history_default_content() {
+        let run = |outer_default: &str, events: &[&str]| {
+            let sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='Outer' datamodel='ecmascript'>
+                  <datamodel>
+                    <data id='log' expr='[]'/>
+                  </datamodel>
+                  <state id='Outer' initial='H1'>
+                    <history id='H1'>
+                      <transition target='{}'><script>log.push('H1')</script></transition>
+                    </history>
+                    <state id='Start'>
+                      <transition event='go' target='M1'/>
+                    </state>
+                    <state id='Mid' initial='Sub'>
+                      <state id='M1'/>
+                      <state id='Sub' initial='H2'>
+                        <history id='H2'>
+                          <transition target='S1'><script>log.push('H2')</script></transition>
+                        </history>
+                        <state id='S1'/>
+                      </state>
+                    </state>
+                    <transition event='leave' target='Away'/>
+                    <transition event='check' cond="log.join(',') == 'H1,H2' &amp;&amp; In('S1')" target='pass'/>
+                    <transition event='check' target='fail'/>
+                  </state>
+                  <state id='Away'>
+                    <transition event='back' target='H1'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##,
+                outer_default
+            ))
+            .unwrap();
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            for name in events {
+                test_send(&session.sender, Event::new_simple(name));
+            }
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .unwrap()
+                .final_configuration
+        };
+
+        // Chained defaults: the default of H1 enters a state whose initial is H2.
+        assert_eq!(run("Sub", &["check"]), Some(vec!["pass".to_string()]));
+        // H1 has a value (Mid) but H2 has none. Only the default content of H2 is executed.
+        assert_eq!(
+            run("Start", &["go", "leave", "back", "check"]),
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn rejected_events_are_replaced_by_error_platform() {
+        use crate::event_validator::{EventValidator, RequiredParamsValidator};
+        use crate::fsm::ParamPair;
+        use std::sync::Arc;
+
+        struct RejectAll;
+        impl EventValidator for RejectAll {
+            fn validate(&self, _event: &Event) -> Result<(), String> {
+                Err("not trusted".to_string())
+            }
+        }
+
+        let run = |validator: Option<Arc<dyn EventValidator>>, executor: FsmExecutor| {
+            let mut sm = scxml_reader::parse_from_xml(
+                r##"<scxml initial='Wait' datamodel='ecmascript'>
+                  <state id='Wait'>
+                    <transition event='order' target='Accepted'/>
+                    <transition event='error.platform' cond="_event.data.event == 'order' &amp;&amp; _event.data.message == 'not trusted'" target='Rejected'/>
+                  </state>
+                  <final id='Accepted'/>
+                  <final id='Rejected'/>
+                </scxml>"##
+                    .to_string(),
+            )
+            .unwrap();
+            sm.event_validator = validator;
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                ActionWrapper::new(),
+                Box::new(executor),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let mut event = Event::new_simple("order");
+            event.param_values = Some(vec![ParamPair::new_moved(
+                "id".to_string(),
+                Data::Integer(1),
+            )]);
+            test_send(&session.sender, event);
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .unwrap()
+                .final_configuration
+        };
+
+        let mut executor = FsmExecutor::new_without_io_processor();
+        let mut validator = RequiredParamsValidator::default();
+        validator.require("order", &["id"]);
+        executor.set_event_validator(Arc::new(validator));
+        assert_eq!(
+            run(None, executor.clone()),
+            Some(vec!["Accepted".to_string()])
+        );
+        // The validator of the FSM replaces the validator of the executor.
+        assert_eq!(
+            run(Some(Arc::new(RejectAll)), executor),
+            Some(vec!["Rejected".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn final_values_are_captured() {
+        let mut sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Loop' datamodel='ecmascript'>
+              <datamodel>
+                <data id='n' expr='10'/>
+                <data id='a' expr='0'/>
+                <data id='b' expr='1'/>
+                <data id='result'/>
+              </datamodel>
+              <state id='Loop'>
+                <transition cond='n &gt; 0' target='Loop'>
+                  <assign location='b' expr='a + b'/>
+                  <assign location='a' expr='b - a'/>
+                  <assign location='n' expr='n - 1'/>
+                </transition>
+                <transition target='Done'>
+                  <assign location='result' expr='a'/>
+                </transition>
+              </state>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        sm.final_value_locations = vec!["result".to_string(), "unknown".to_string()];
+        let mut session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        session.thread.take().unwrap().join().unwrap();
+
+        assert_eq!(session.get_final_value("result"), Some(Data::Integer(55)));
+        assert_eq!(session.get_final_value("unknown"), None);
+        assert_eq!(session.get_final_value("n"), None);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn invoke_params_are_written_back() {
+        // The child increments the passed counter. The parent sees the new value already in finalize.
+        // "limit" is passed without write back, the change in the child is not visible in the parent.
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+              <datamodel>
+                <data id='counter' expr='1'/>
+                <data id='limit' expr='5'/>
+                <data id='seen' expr='0'/>
+              </datamodel>
+              <state id='Main'>
+                <invoke id='child'>
+                  <param name='counter' location='counter' rfsm:writeback='true'/>
+                  <param name='limit' location='limit'/>
+                  <content>
+                    <scxml initial='C' datamodel='ecmascript'>
+                      <datamodel>
+                        <data id='counter'/>
+                        <data id='limit'/>
+                      </datamodel>
+                      <state id='C'>
+                        <onentry>
+                          <assign location='counter' expr='Number(counter) + 1'/>
+                          <assign location='limit' expr='0'/>
+                        </onentry>
+                        <transition target='Done'/>
+                      </state>
+                      <final id='Done'/>
+                    </scxml>
+                  </content>
+                  <finalize>
+                    <assign location='seen' expr='counter'/>
+                  </finalize>
+                </invoke>
+                <transition event='done.invoke.child' cond='counter == 2 &amp;&amp; seen == 2 &amp;&amp; limit == 5'
+                            target='pass'/>
+                <transition event='done.invoke.child' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn transition_with_multiple_targets_enters_parallel_regions() {
+        let xml = |targets: &str| {
+            format!(
+                r##"<scxml initial='Start' datamodel='null'>
+                  <state id='Start'>
+                    <transition event='go' target='{targets}'/>
+                  </state>
+                  <parallel id='P'>
+                    <state id='R1'>
+                      <state id='r1s1'/>
+                      <state id='r1s2'>
+                        <transition event='back' target='r2s1 r1s1'/>
+                      </state>
+                    </state>
+                    <state id='R2'>
+                      <state id='r2s1'/>
+                      <state id='r2s2'/>
+                      <state id='r2s3'/>
+                    </state>
+                  </parallel>
+                </scxml>"##
+            )
+        };
+        let mut stepper = crate::fsm_stepper::start_fsm_stepper(
+            scxml_reader::parse_from_xml(xml("r1s2 r2s3")).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        stepper.step(None);
+        assert_eq!(stepper.configuration(), vec!["Start"]);
+        stepper.step(Some(Event::new_simple("go")));
+        assert_eq!(
+            stepper.configuration(),
+            vec!["P", "R1", "r1s2", "R2", "r2s3"]
+        );
+        // Both regions are exited and entered again.
+        stepper.step(Some(Event::new_simple("back")));
+        assert_eq!(
+            stepper.configuration(),
+            vec!["P", "R1", "r1s1", "R2", "r2s1"]
+        );
+
+        // Targets in the same region don't form a legal state specification.
+        for targets in ["r1s1 r1s2", "P r2s3"] {
+            let errors = crate::validation::validate(&scxml_reader::parse_from_xml(xml(targets)).unwrap());
+            assert_eq!(errors.len(), 1, "{:?}", errors);
+            assert_eq!(errors[0].element, "transition");
+            assert_eq!(errors[0].name, "Start");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn donedata_is_evaluated_after_onentry_of_final_state() {
+        let run = |donedata: &str, check: &str| {
+            let sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <datamodel><data id='total' expr='0'/></datamodel>
+                  <state id='Main'>
+                    <initial>
+                      <transition target='Done'><assign location='total' expr='total + 2'/></transition>
+                    </initial>
+                    <final id='Done'>
+                      <onentry><assign location='total' expr='total * 21'/></onentry>
+                      <donedata>{donedata}</donedata>
+                    </final>
+                    <transition event='done.state.Main' cond='{check}' target='pass'/>
+                    <transition event='done.state.Main' target='fail'/>
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##
+            ))
+            .unwrap();
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .and_then(|result| result.final_configuration)
+        };
+        let pass = Some(vec!["pass".to_string()]);
+        assert_eq!(
+            run(
+                "<param name='total' expr='total'/>",
+                "_event.data.total == 42"
+            ),
+            pass
+        );
+        assert_eq!(run("<content expr='total'/>", "_event.data == 42"), pass);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn names_of_namelist_and_params_must_be_unique() {
+        let run = |main: &str| {
+            let sm = scxml_reader::parse_from_xml(format!(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <datamodel>
+                    <data id='a' expr='1'/>
+                    <data id='b' expr='2'/>
+                  </datamodel>
+                  <state id='Main'>
+                    {main}
+                  </state>
+                  <final id='pass'/>
+                  <final id='fail'/>
+                </scxml>"##
+            ))
+            .unwrap();
+            let session = start_fsm_with_data_and_finish_mode(
+                sm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .and_then(|result| result.final_configuration)
+        };
+        let child = r##"<content>
+                <scxml initial='C' datamodel='ecmascript'>
+                  <datamodel><data id='a'/><data id='b'/></datamodel>
+                  <final id='C'>
+                    <donedata><param name='ok' expr='a == 1 &amp;&amp; b == 2'/></donedata>
+                  </final>
+                </scxml>
+              </content>"##;
+        let pass = Some(vec!["pass".to_string()]);
+
+        // Duplicate between namelist and param: the invoke is not executed.
+        assert_eq!(
+            run(&format!(
+                r##"<invoke namelist='a'><param name='a' location='b'/>{child}</invoke>
+                <transition event='error.execution' target='pass'/>
+                <transition event='done.invoke' target='fail'/>"##
+            )),
+            pass
+        );
+        // Distinct names are passed to the child.
+        assert_eq!(
+            run(&format!(
+                r##"<invoke namelist='a'><param name='b' location='b'/>{child}</invoke>
+                <transition event='done.invoke' cond='_event.data.ok' target='pass'/>
+                <transition event='*' target='fail'/>"##
+            )),
+            pass
+        );
+        // Duplicated params of <send>: the event is not sent.
+        assert_eq!(
+            run(r##"<onentry>
+                  <send event='ev'><param name='x' expr='1'/><param name='x' expr='2'/></send>
+                </onentry>
+                <transition event='error.execution' type='internal' target='Sent'/>
+                <transition event='*' target='fail'/>
+                <state id='Start'/>
+                <state id='Sent'>
+                  <onentry>
+                    <send event='ev2'><param name='x' expr='1'/><param name='y' expr='2'/></send>
+                  </onentry>
+                  <transition event='ev' target='fail'/>
+                  <transition event='ev2' cond='_event.data.x == 1 &amp;&amp; _event.data.y == 2' target='pass'/>
+                </state>"##),
+            pass
+        );
+        // Duplicated params of <donedata>: the done-event has no data.
+        assert_eq!(
+            run(r##"<initial><transition target='F'/></initial>
+                <final id='F'>
+                  <donedata><param name='x' expr='1'/><param name='x' expr='2'/></donedata>
+                </final>
+                <transition event='error.execution' type='internal' target='Error'/>
+                <transition event='*' target='fail'/>
+                <state id='Error'>
+                  <transition event='done.state.Main' cond='_event.data === undefined' target='pass'/>
+                </state>"##),
+            pass
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn transition_selection_follows_ancestor_chain() {
+        let run = |xml: &str| {
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .and_then(|result| result.final_configuration)
+        };
+        let pass = Some(vec!["pass".to_string()]);
+
+        // A failing condition on the innermost match lets the transition of an ancestor fire,
+        // also if a later transition of the inner state would match.
+        assert_eq!(
+            run(r##"<scxml initial='Outer' datamodel='ecmascript'>
+              <state id='Outer'>
+                <state id='Inner'>
+                  <onentry><raise event='error.custom'/></onentry>
+                  <transition event='error' cond='false' target='fail'/>
+                  <transition event='*' cond='false' target='fail'/>
+                </state>
+                <transition event='error.custom' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##),
+            pass
+        );
+
+        // Same for eventless transitions.
+        assert_eq!(
+            run(r##"<scxml initial='Outer' datamodel='ecmascript'>
+              <state id='Outer'>
+                <state id='Inner'>
+                  <transition cond='false' target='fail'/>
+                </state>
+                <transition cond='true' target='pass'/>
+                <transition target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##),
+            pass
+        );
+
+        // Inside one state, document order decides, not the specificity of the descriptor.
+        assert_eq!(
+            run(r##"<scxml initial='S' datamodel='ecmascript'>
+              <state id='S'>
+                <onentry><raise event='error.custom'/></onentry>
+                <transition event='*' target='pass'/>
+                <transition event='error.custom' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##),
+            pass
+        );
+
+        // The internal "error.execution" is processed before an external event with the same name.
+        assert_eq!(
+            run(r##"<scxml initial='S' datamodel='ecmascript'>
+              <state id='S'>
+                <onentry>
+                  <send event='error.execution'/>
+                  <assign location='undefined.field' expr='1'/>
+                </onentry>
+                <transition event='error.execution' cond="_event.type == 'platform'" target='pass'/>
+                <transition event='error.execution' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##),
+            pass
+        );
+    }
+
+    #[test]
+    #[cfg(all(
+        feature = "xml",
+        feature = "ECMAScript",
+        feature = "RfsmExpressionModel"
+    ))]
+    fn json_content_is_structured_between_sessions() {
+        for (datamodel, check) in [
+            (
+                "ecmascript",
+                "_event.data.order.id == 7 &amp;&amp; _event.data.items.length == 2 &amp;&amp; _event.data.note == 'a &lt; b'",
+            ),
+            (
+                "rfsm-expression",
+                "_event.data.order.id == 7 &amp;&amp; _event.data.items.length() == 2 &amp;&amp; _event.data.note == 'a &lt; b'",
+            ),
+        ] {
+            let xml = format!(
+                r##"<scxml initial='Main' datamodel='{datamodel}'>
+              <state id='Main'>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='C' datamodel='{datamodel}'>
+                      <state id='C'>
+                        <onentry>
+                          <send target='#_parent' event='result'>
+                            <content>
+                              {{ "order": {{ "id": 7 }}, "items": [1, 2], "note": "a &lt; b" }}
+                            </content>
+                          </send>
+                        </onentry>
+                      </state>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='result' cond="{check}" target='pass'/>
+                <transition event='result' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+            );
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml).unwrap(),
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(2000));
+            assert!(result.is_some(), "Session shall finish ({})", datamodel);
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec!["pass".to_string()]),
+                "{}",
+                datamodel
+            );
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn self_transitions_respect_type() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='P' datamodel='ecmascript'>
+              <datamodel>
+                <data id='log' expr='[]'/>
+              </datamodel>
+              <parallel id='P'>
+                <state id='R1' initial='A'>
+                  <state id='A' initial='A1'>
+                    <onentry><script>log.push('+A')</script></onentry>
+                    <onexit><script>log.push('-A')</script></onexit>
+                    <state id='A1'>
+                      <onentry><script>log.push('+A1')</script></onentry>
+                      <onexit><script>log.push('-A1')</script></onexit>
+                    </state>
+                    <state id='A2'>
+                      <onentry><script>log.push('+A2')</script></onentry>
+                      <onexit><script>log.push('-A2')</script></onexit>
+                    </state>
+                    <transition event='external' target='A' type='external'/>
+                    <transition event='internalChild' target='A2' type='internal'/>
+                    <transition event='internalSelf' target='A' type='internal'/>
+                    <transition event='internalSibling' target='C' type='internal'/>
+                  </state>
+                  <state id='C'>
+                    <onentry><script>log.push('+C')</script></onentry>
+                  </state>
+                </state>
+                <state id='R2'>
+                  <onexit><script>log.push('-R2')</script></onexit>
+                  <transition event='externalRegion' target='R2'/>
+                </state>
+              </parallel>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let timeout = Duration::from_millis(2000);
+        let step = |event: &str| -> Data {
+            session
+                .send_and_sync(Box::new(Event::new_simple(event)), timeout)
+                .unwrap();
+            session
+                .evaluate("log.splice(0).join(',')", timeout)
+                .unwrap()
+        };
+
+        // Initial entry
+        assert_eq!(step("none"), Data::String("+A,+A1".to_string()));
+        // External self-transition exits and re-enters the source, but not the enclosing parallel state.
+        assert_eq!(step("external"), Data::String("-A1,-A,+A,+A1".to_string()));
+        // Internal transition to a descendant doesn't exit the source.
+        assert_eq!(step("internalChild"), Data::String("-A1,+A2".to_string()));
+        // The source is no proper ancestor of the targets, so these are handled as external transitions.
+        assert_eq!(
+            step("internalSelf"),
+            Data::String("-A2,-A,+A,+A1".to_string())
+        );
+        assert_eq!(
+            step("internalSibling"),
+            Data::String("-A1,-A,+C".to_string())
+        );
+        // Parallel states are no compound states, so the domain of a self-transition of a region is above them.
+        assert_eq!(
+            step("externalRegion"),
+            Data::String("-R2,+A,+A1".to_string())
+        );
+
+        let _ = session
+            .sender
+            .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+        session.wait_for_completion(timeout);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn subscribers_receive_matching_events() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Wait' datamodel='null'>
+              <state id='Wait'>
+                <transition event='go' target='P'/>
+              </state>
+              <parallel id='P'>
+                <state id='A'><final id='A1'/></state>
+                <state id='B'><final id='B1'/></state>
+                <transition event='done.state.P' target='End'>
+                  <raise event='finished'/>
+                </transition>
+              </parallel>
+              <final id='End'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let done_events = session.subscribe("done.state.*");
+        let dropped = session.subscribe("*");
+        drop(dropped);
+
+        let _ = session.sender.send(Box::new(Event::new_simple("go")));
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.and_then(|r| r.final_configuration),
+            Some(vec!["End".to_string()])
+        );
+
+        // The sender is dropped when the FSM has finished, so the iterator ends.
+        let names: Vec<String> = done_events.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(names, vec!["done.state.A", "done.state.B", "done.state.P"]);
+        assert!(session
+            .global_data
+            .lock()
+            .unwrap()
+            .event_subscribers
+            .is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn subscribers_receive_internal_sends_once() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Wait' datamodel='null'>
+              <state id='Wait'>
+                <transition event='go' target='Ping'>
+                  <send event='ping' target='#_internal'/>
+                </transition>
+              </state>
+              <state id='Ping'>
+                <transition event='ping' target='End'/>
+              </state>
+              <final id='End'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let pings = session.subscribe("ping");
+
+        let _ = session.sender.send(Box::new(Event::new_simple("go")));
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.and_then(|r| r.final_configuration),
+            Some(vec!["End".to_string()])
+        );
+
+        let names: Vec<String> = pings.iter().map(|e| e.name.clone()).collect();
+        assert_eq!(names, vec!["ping"]);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn reload_keeps_compatible_state() {
+        let timeout = Duration::from_millis(2000);
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(
+                r##"<scxml initial='Main' datamodel='ecmascript'>
+                  <datamodel><data id='counter' expr='0'/></datamodel>
+                  <state id='Main'>
+                    <state id='Gone'>
+                      <onexit><assign location='counter' expr='counter + 10'/></onexit>
+                      <state id='A'>
+                        <transition event='inc'><assign location='counter' expr='counter + 1'/></transition>
+                      </state>
+                    </state>
+                  </state>
+                  <final id='done'/>
+                </scxml>"##
+                    .to_string(),
+            )
+            .unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        session
+            .send_and_sync(Box::new(Event::new_simple("inc")), timeout)
+            .unwrap();
+
+        // "A" is removed, "Main" would have no active child.
+        let incompatible = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'><state id='B'/></state>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let err = session.reload(incompatible, timeout).unwrap_err();
+        assert!(err.contains("'Main' would have no active child"), "{}", err);
+        session
+            .send_and_sync(Box::new(Event::new_simple("inc")), timeout)
+            .unwrap();
+
+        // Adds a transition and data, replaces the parent of "A".
+        let compatible = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='counter' expr='0'/>
+                <data id='added' expr='5'/>
+                <data id='wrapped' expr='false'/>
+              </datamodel>
+              <state id='Main'>
+                <state id='Wrapper'>
+                  <onentry><assign location='wrapped' expr='true'/></onentry>
+                  <state id='A'>
+                    <transition event='finish' cond='counter == 12 &amp;&amp; added == 5 &amp;&amp; wrapped' target='done'/>
+                    <transition event='finish' target='fail'/>
+                  </state>
+                </state>
+              </state>
+              <final id='done'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        session.reload(compatible, timeout).unwrap();
+        let _ = session.sender.send(Box::new(Event::new_simple("finish")));
+        assert_eq!(
+            session
+                .wait_for_completion(timeout)
+                .and_then(|r| r.final_configuration),
+            Some(vec!["done".to_string()])
+        );
     }
 }
@@ -0,0 +1,581 @@
+//! Builder to create a [Fsm] in code, without the need of a SCXML document.\
+//! The builder assigns the ids in the same way as the SCXML reader does, so the resulting FSM
+//! can be started directly via [crate::fsm::start_fsm].
+//!
+//! ```
+//! use rfsm::fsm_builder::FsmBuilder;
+//!
+//! let mut builder = FsmBuilder::new("example");
+//! builder.add_state("a");
+//! builder.add_state("b").final_state();
+//! builder.add_transition("a", &["b"]).event("go");
+//! let fsm = builder.build().unwrap();
+//! assert_eq!(fsm.name, "example");
+//! ```
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::sync::atomic::Ordering;
+
+use crate::datamodel::{create_data_arc, Data, SourceCode};
+use crate::executable_content::{ExecutableContent, Expression};
+use crate::fsm::{
+    push_param, strip_event_descriptor, CommonContent, ContentKind, DoneData, ExecutableContentId, Fsm, Parameter,
+    State, StateId, Transition, TransitionType, DOC_ID_COUNTER, ID_COUNTER, SOURCE_ID_COUNTER,
+};
+
+/// Errors reported by [FsmBuilder::build].
+#[derive(Debug, Clone, PartialEq)]
+pub enum BuildError {
+    /// A state name was added more than once.
+    DuplicateState(String),
+    /// The parent of a state doesn't exist.
+    UnknownParent { state: String, parent: String },
+    /// An initial target of a state (or of the FSM) doesn't exist.
+    UnknownInitialTarget { state: String, target: String },
+    /// The source of a transition doesn't exist.
+    UnknownTransitionSource(String),
+    /// A target of a transition doesn't exist.
+    UnknownTransitionTarget { source: String, target: String },
+    /// The state is not reachable from the root, the parents form a cycle.
+    ParentCycle(String),
+    /// Done data was given for a state that is not final.
+    DoneDataOnNonFinalState(String),
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::DuplicateState(state) => write!(f, "Duplicate state '{}'", state),
+            BuildError::UnknownParent { state, parent } => {
+                write!(f, "Unknown parent '{}' of state '{}'", parent, state)
+            }
+            BuildError::UnknownInitialTarget { state, target } => {
+                write!(f, "Unknown initial state '{}' of state '{}'", target, state)
+            }
+            BuildError::UnknownTransitionSource(source) => {
+                write!(f, "Unknown transition source '{}'", source)
+            }
+            BuildError::UnknownTransitionTarget { source, target } => {
+                write!(
+                    f,
+                    "Unknown target '{}' of transition from '{}'",
+                    target, source
+                )
+            }
+            BuildError::ParentCycle(state) => write!(f, "State '{}' is part of a parent cycle", state),
+            BuildError::DoneDataOnNonFinalState(state) => {
+                write!(f, "Done data given for non-final state '{}'", state)
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Description of a state, see [FsmBuilder::add_state].
+#[derive(Debug, Default)]
+pub struct StateBuilder {
+    name: String,
+    parent: Option<String>,
+    initial: Vec<String>,
+    parallel: bool,
+    is_final: bool,
+    on_entry: Vec<String>,
+    on_exit: Vec<String>,
+    data: Vec<(String, String)>,
+    done_data: Option<DoneData>,
+}
+
+impl StateBuilder {
+    /// Sets the initial states, same as the "initial" attribute.
+    pub fn initial(&mut self, initial: &[&str]) -> &mut Self {
+        self.initial = initial.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Sets the parent state. Without parent, the state is a child of the root.
+    pub fn parent(&mut self, parent: &str) -> &mut Self {
+        self.parent = Some(parent.to_string());
+        self
+    }
+
+    /// Marks the state as \<parallel\>.
+    pub fn parallel(&mut self) -> &mut Self {
+        self.parallel = true;
+        self
+    }
+
+    /// Marks the state as \<final\>.
+    pub fn final_state(&mut self) -> &mut Self {
+        self.is_final = true;
+        self
+    }
+
+    /// Adds a \<onentry\> block with a \<script\>.
+    pub fn on_entry_script(&mut self, script: &str) -> &mut Self {
+        self.on_entry.push(script.to_string());
+        self
+    }
+
+    /// Adds a \<onexit\> block with a \<script\>.
+    pub fn on_exit_script(&mut self, script: &str) -> &mut Self {
+        self.on_exit.push(script.to_string());
+        self
+    }
+
+    /// Adds a \<data\> element to the datamodel of this state.
+    pub fn data(&mut self, id: &str, expr: &str) -> &mut Self {
+        self.data.push((id.to_string(), expr.to_string()));
+        self
+    }
+
+    /// Sets the expression of the \<content\> child of \<donedata\>.
+    pub fn done_data(&mut self, content_expr: &str) -> &mut Self {
+        self.done_data.get_or_insert_with(DoneData::new).content = Some(CommonContent {
+            content: None,
+            content_expr: Some(content_expr.to_string()),
+            content_kind: ContentKind::Text,
+        });
+        self
+    }
+
+    /// Adds a \<param\> child to \<donedata\>.
+    pub fn done_data_param(&mut self, name: &str, expr: &str) -> &mut Self {
+        let mut param = Parameter::new();
+        param.name = name.to_string();
+        param.expr = expr.to_string();
+        push_param(
+            &mut self.done_data.get_or_insert_with(DoneData::new).params,
+            param,
+        );
+        self
+    }
+}
+
+/// Description of a transition, see [FsmBuilder::add_transition].
+#[derive(Debug, Default)]
+pub struct TransitionBuilder {
+    source: String,
+    targets: Vec<String>,
+    events: Vec<String>,
+    cond: Option<String>,
+    transition_type: TransitionType,
+    script: Option<String>,
+}
+
+impl TransitionBuilder {
+    /// Adds event descriptors, same syntax as the "event" attribute.
+    pub fn event(&mut self, event: &str) -> &mut Self {
+        self.events.extend(
+            event
+                .split_whitespace()
+                .map(|s| strip_event_descriptor(s).to_string()),
+        );
+        self
+    }
+
+    /// Sets the guard condition.
+    pub fn cond(&mut self, cond: &str) -> &mut Self {
+        self.cond = Some(cond.to_string());
+        self
+    }
+
+    /// Marks the transition as "internal".
+    pub fn internal(&mut self) -> &mut Self {
+        self.transition_type = TransitionType::Internal;
+        self
+    }
+
+    /// Sets a \<script\> that is executed with the transition.
+    pub fn script(&mut self, script: &str) -> &mut Self {
+        self.script = Some(script.to_string());
+        self
+    }
+}
+
+/// Builds a [Fsm] in code.\
+/// States and transitions are referenced by name and resolved in [FsmBuilder::build].
+/// Child states are ordered as they were added, which defines the document order.
+#[derive(Debug, Default)]
+pub struct FsmBuilder {
+    name: String,
+    datamodel: Option<String>,
+    initial: Vec<String>,
+    data: Vec<(String, String)>,
+    script: Option<String>,
+    states: Vec<StateBuilder>,
+    transitions: Vec<TransitionBuilder>,
+}
+
+impl FsmBuilder {
+    pub fn new(name: &str) -> FsmBuilder {
+        FsmBuilder {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Sets the datamodel, same as the "datamodel" attribute of \<scxml\>.
+    pub fn datamodel(&mut self, datamodel: &str) -> &mut Self {
+        self.datamodel = Some(datamodel.to_string());
+        self
+    }
+
+    /// Sets the initial states of the FSM.
+    pub fn initial(&mut self, initial: &[&str]) -> &mut Self {
+        self.initial = initial.iter().map(|s| s.to_string()).collect();
+        self
+    }
+
+    /// Adds a \<data\> element to the global datamodel.
+    pub fn data(&mut self, id: &str, expr: &str) -> &mut Self {
+        self.data.push((id.to_string(), expr.to_string()));
+        self
+    }
+
+    /// Sets the global \<script\>.
+    pub fn script(&mut self, script: &str) -> &mut Self {
+        self.script = Some(script.to_string());
+        self
+    }
+
+    /// Adds a new state.
+    pub fn add_state(&mut self, name: &str) -> &mut StateBuilder {
+        self.states.push(StateBuilder {
+            name: name.to_string(),
+            ..Default::default()
+        });
+        self.states.last_mut().unwrap()
+    }
+
+    /// Adds a new transition. Without targets the transition is targetless.
+    pub fn add_transition(&mut self, source: &str, targets: &[&str]) -> &mut TransitionBuilder {
+        self.transitions.push(TransitionBuilder {
+            source: source.to_string(),
+            targets: targets.iter().map(|s| s.to_string()).collect(),
+            ..Default::default()
+        });
+        self.transitions.last_mut().unwrap()
+    }
+
+    /// Checks all references and creates the FSM.
+    pub fn build(&self) -> Result<Box<Fsm>, BuildError> {
+        self.validate()?;
+
+        let mut fsm = Box::new(Fsm::new());
+        fsm.name.clone_from(&self.name);
+        if let Some(datamodel) = &self.datamodel {
+            fsm.datamodel.clone_from(datamodel);
+        }
+
+        // Collect the children of each state in the order they were added. Index 0 is the root.
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); self.states.len() + 1];
+        let indices: HashMap<&str, usize> = self
+            .states
+            .iter()
+            .enumerate()
+            .map(|(idx, s)| (s.name.as_str(), idx + 1))
+            .collect();
+        for (idx, state) in self.states.iter().enumerate() {
+            let parent_idx = match &state.parent {
+                None => 0,
+                Some(parent) => indices[parent.as_str()],
+            };
+            children[parent_idx].push(idx + 1);
+        }
+
+        // Create the states in document order, depth first.
+        let root_name = format!("__id{}", 1);
+        fsm.pseudo_root = Self::create_state(&mut fsm, &root_name, 0);
+        let mut state_ids: Vec<StateId> = vec![0; self.states.len() + 1];
+        state_ids[0] = fsm.pseudo_root;
+        let mut stack: Vec<usize> = children[0].iter().rev().cloned().collect();
+        while let Some(idx) = stack.pop() {
+            let state = &self.states[idx - 1];
+            let parent_idx = match &state.parent {
+                None => 0,
+                Some(parent) => indices[parent.as_str()],
+            };
+            state_ids[idx] = Self::create_state(&mut fsm, &state.name, state_ids[parent_idx]);
+            stack.extend(children[idx].iter().rev());
+        }
+        if let Some(state) = self
+            .states
+            .iter()
+            .enumerate()
+            .find(|(idx, _)| state_ids[idx + 1] == 0)
+        {
+            return Err(BuildError::ParentCycle(state.1.name.clone()));
+        }
+
+        // Root
+        let root = fsm.pseudo_root;
+        Self::set_initial(&mut fsm, root, &self.initial);
+        Self::add_data(&mut fsm, root, &self.data);
+        if let Some(script) = &self.script {
+            fsm.script = Self::create_script_region(&mut fsm, script);
+        }
+
+        for (idx, spec) in self.states.iter().enumerate() {
+            let sid = state_ids[idx + 1];
+            Self::set_initial(&mut fsm, sid, &spec.initial);
+            Self::add_data(&mut fsm, sid, &spec.data);
+            let mut on_entry = Vec::new();
+            for script in &spec.on_entry {
+                on_entry.push(Self::create_script_region(&mut fsm, script));
+            }
+            let mut on_exit = Vec::new();
+            for script in &spec.on_exit {
+                on_exit.push(Self::create_script_region(&mut fsm, script));
+            }
+            let state = fsm.get_state_by_id_mut(sid);
+            state.is_parallel = spec.parallel;
+            state.is_final = spec.is_final;
+            state.onentry.extend(on_entry);
+            state.onexit.extend(on_exit);
+            state.donedata.clone_from(&spec.done_data);
+        }
+
+        for spec in &self.transitions {
+            let mut t = Transition::new();
+            t.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            t.events.clone_from(&spec.events);
+            t.wildcard = t.events.contains(&"*".to_string());
+            if let Some(cond) = &spec.cond {
+                t.cond = create_source(cond);
+            }
+            t.source = fsm.statesNames[&spec.source];
+            t.target = spec.targets.iter().map(|n| fsm.statesNames[n]).collect();
+            t.transition_type = TransitionType::from_ordinal(spec.transition_type.ordinal());
+            if let Some(script) = &spec.script {
+                t.content = Self::create_script_region(&mut fsm, script);
+            }
+            fsm.get_state_by_id_mut(t.source).transitions.push(t.id);
+            fsm.transitions.insert(t.id, t);
+        }
+
+        //  W3C: If not specified, the default initial state is the first child state in document order.
+        for sid in state_ids {
+            Self::set_default_initial(&mut fsm, sid);
+        }
+        Ok(fsm)
+    }
+
+    /// Checks all names.
+    fn validate(&self) -> Result<(), BuildError> {
+        let mut names: HashMap<&str, &StateBuilder> = HashMap::new();
+        for state in &self.states {
+            if names.insert(state.name.as_str(), state).is_some() {
+                return Err(BuildError::DuplicateState(state.name.clone()));
+            }
+        }
+        for target in &self.initial {
+            if !names.contains_key(target.as_str()) {
+                return Err(BuildError::UnknownInitialTarget {
+                    state: self.name.clone(),
+                    target: target.clone(),
+                });
+            }
+        }
+        for state in &self.states {
+            if let Some(parent) = &state.parent {
+                if !names.contains_key(parent.as_str()) {
+                    return Err(BuildError::UnknownParent {
+                        state: state.name.clone(),
+                        parent: parent.clone(),
+                    });
+                }
+            }
+            for target in &state.initial {
+                if !names.contains_key(target.as_str()) {
+                    return Err(BuildError::UnknownInitialTarget {
+                        state: state.name.clone(),
+                        target: target.clone(),
+                    });
+                }
+            }
+            if state.done_data.is_some() && !state.is_final {
+                return Err(BuildError::DoneDataOnNonFinalState(state.name.clone()));
+            }
+        }
+        for transition in &self.transitions {
+            if !names.contains_key(transition.source.as_str()) {
+                return Err(BuildError::UnknownTransitionSource(
+                    transition.source.clone(),
+                ));
+            }
+            for target in &transition.targets {
+                if !names.contains_key(target.as_str()) {
+                    return Err(BuildError::UnknownTransitionTarget {
+                        source: transition.source.clone(),
+                        target: target.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn create_state(fsm: &mut Fsm, name: &str, parent: StateId) -> StateId {
+        let mut s = State::new(name);
+        s.id = (fsm.states.len() + 1) as StateId;
+        s.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        s.parent = parent;
+        let sid = s.id;
+        fsm.statesNames.insert(s.name.clone(), sid);
+        fsm.states.push(s);
+        if parent != 0 {
+            fsm.get_state_by_id_mut(parent).states.push(sid);
+        }
+        sid
+    }
+
+    fn set_initial(fsm: &mut Fsm, sid: StateId, initial: &[String]) {
+        if !initial.is_empty() {
+            let mut t = Transition::new();
+            t.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+            t.transition_type = TransitionType::Internal;
+            t.source = sid;
+            t.target = initial.iter().map(|n| fsm.statesNames[n]).collect();
+            fsm.get_state_by_id_mut(sid).initial = t.id;
+            fsm.transitions.insert(t.id, t);
+        }
+    }
+
+    fn set_default_initial(fsm: &mut Fsm, sid: StateId) {
+        let state = fsm.get_state_by_id_mut(sid);
+        if state.initial == 0 {
+            if let Some(first_state) = state.states.first() {
+                let mut t = Transition::new();
+                state.initial = t.id;
+                t.source = state.id;
+                t.target.push(*first_state);
+                fsm.transitions.insert(t.id, t);
+            }
+        }
+    }
+
+    fn add_data(fsm: &mut Fsm, sid: StateId, data: &[(String, String)]) {
+        let state = fsm.get_state_by_id_mut(sid);
+        for (id, expr) in data {
+            state
+                .data
+                .insert(id.clone(), create_data_arc(create_source(expr)));
+        }
+    }
+
+    /// Creates a new executable content region with a single script.
+    fn create_script_region(fsm: &mut Fsm, script: &str) -> ExecutableContentId {
+        let ec_id = ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let mut s = Expression::new();
+        s.content = create_source(script);
+        let content: Vec<Box<dyn ExecutableContent>> = vec![Box::new(s)];
+        fsm.executableContent.insert(ec_id, content);
+        ec_id
+    }
+}
+
+fn create_source(src: &str) -> Data {
+    Data::Source(SourceCode::new(
+        src,
+        SOURCE_ID_COUNTER.fetch_add(1, Ordering::Relaxed),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    use std::collections::HashMap;
+
+    #[cfg(feature = "ECMAScript")]
+    use crate::fsm::Event;
+    use crate::fsm_builder::{BuildError, FsmBuilder};
+    #[cfg(feature = "ECMAScript")]
+    use crate::test::run_test_manual_with_send;
+    #[cfg(feature = "Trace")]
+    #[cfg(feature = "ECMAScript")]
+    use crate::tracer::TraceMode;
+
+    #[test]
+    fn dangling_references_are_reported() {
+        let mut builder = FsmBuilder::new("dangling");
+        builder.add_state("a");
+        builder.add_transition("a", &["b"]);
+        assert_eq!(
+            builder.build().err(),
+            Some(BuildError::UnknownTransitionTarget {
+                source: "a".to_string(),
+                target: "b".to_string()
+            })
+        );
+
+        let mut builder = FsmBuilder::new("cycle");
+        builder.add_state("a").parent("b");
+        builder.add_state("b").parent("a");
+        assert_eq!(
+            builder.build().err(),
+            Some(BuildError::ParentCycle("a".to_string()))
+        );
+    }
+
+    #[test]
+    fn ids_are_assigned_like_the_reader() {
+        let mut builder = FsmBuilder::new("ids");
+        builder.add_state("b").parent("a");
+        builder.add_state("a");
+        let fsm = builder.build().unwrap();
+
+        let a = fsm.statesNames["a"];
+        let b = fsm.statesNames["b"];
+        assert_eq!(fsm.pseudo_root, 1);
+        assert_eq!(fsm.states[(a - 1) as usize].name, "a");
+        assert!(a < b, "Parents shall be created before their children");
+        assert!(fsm.states[(a - 1) as usize].doc_id < fsm.states[(b - 1) as usize].doc_id);
+        assert_eq!(fsm.get_state_by_id(b).parent, a);
+        assert_ne!(fsm.get_state_by_id(fsm.pseudo_root).initial, 0);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    fn built_fsm_is_runnable() {
+        let mut builder = FsmBuilder::new("runnable");
+        builder.datamodel("ecmascript").data("x", "0");
+        builder
+            .add_state("Main")
+            .initial(&["Counting"])
+            .on_entry_script("x = x + 1;");
+        builder.add_state("Counting").parent("Main");
+        builder
+            .add_transition("Counting", &["Done"])
+            .event("go.*")
+            .cond("x == 2");
+        builder.add_transition("Counting", &["fail"]).event("go");
+        builder
+            .add_state("Done")
+            .parent("Main")
+            .on_entry_script("x = x + 1;");
+        builder
+            .add_transition("Done", &["pass"])
+            .cond("x == 3")
+            .script("x = 4;");
+        builder.add_state("pass").final_state();
+        builder.add_state("fail").final_state();
+        builder.script("x = x + 1;");
+
+        let fsm = builder.build().unwrap();
+        assert!(run_test_manual_with_send(
+            "built_fsm_is_runnable",
+            &HashMap::new(),
+            fsm,
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+            |sender| {
+                let _ = sender.send(Box::new(Event::new_simple("go.now")));
+            },
+        ));
+    }
+}
@@ -3,80 +3,398 @@
 extern crate core;
 
 use crate::fsm::Fsm;
-use std::collections::HashMap;
-#[cfg(feature = "serializer")]
-use std::fs::File;
-#[cfg(feature = "serializer")]
-use std::io::BufReader;
+use std::collections::{HashMap, HashSet};
 
+#[cfg(feature = "xml")]
+use std::panic::{self, AssertUnwindSafe};
 use std::path::PathBuf;
-use std::sync::mpsc::{SendError, Sender};
+use std::sync::mpsc::TrySendError;
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 #[cfg(feature = "Debug")]
 use log::debug;
+use log::warn;
 
 use crate::actions::ActionWrapper;
 #[cfg(feature = "BasicHttpEventIOProcessor")]
 use crate::basic_http_event_io_processor::BasicHTTPEventIOProcessor;
-use crate::datamodel::DATAMODEL_OPTION_PREFIX;
+#[cfg(feature = "BasicHttpEventIOProcessor")]
+use crate::basic_http_invoke_handler::BasicHTTPInvokeHandler;
+use crate::clock::{Clock, ClockGuard, TimerClock};
+use crate::datamodel::datamodel_options_from_arguments;
+use crate::document_cache::{DocumentCache, DocumentCacheStats};
 use crate::event_io_processor::EventIOProcessor;
+use crate::event_validator::EventValidator;
 use crate::fsm;
-use crate::fsm::{Event, FinishMode, InvokeId, ParamPair, ScxmlSession, SessionId};
+use crate::fsm::{
+    DelayedSend, Event, EventSender, EventType, FinishMode, InvokeId, OverflowPolicy, ParamPair, QueueLimit,
+    QueueSender, ScxmlSession, SessionId, SessionResult, EVENT_CANCEL_SESSION, EVENT_DELAYED_SEND,
+};
+use crate::fsm_thread_pool::FsmThreadPool;
+use crate::invoke_handler::{InvokeError, InvokeHandler};
+use crate::log_sink::LogSink;
+#[cfg(feature = "Metrics")]
+use crate::metrics::MetricsSnapshot;
 use crate::scxml_event_io_processor::ScxmlEventIOProcessor;
+use crate::scxml_invoke_handler::ScxmlInvokeHandler;
 #[cfg(feature = "xml")]
 use crate::scxml_reader;
 #[cfg(feature = "xml")]
 use crate::scxml_reader::include_path_from_arguments;
 #[cfg(feature = "serializer")]
-use crate::serializer::default_protocol_reader::DefaultProtocolReader;
-#[cfg(feature = "serializer")]
-use crate::serializer::fsm_reader::FsmReader;
+use crate::serializer::fsm_reader::load_precompiled_document;
 #[cfg(feature = "Trace")]
 use crate::tracer::TraceMode;
 #[cfg(feature = "BasicHttpEventIOProcessor")]
 use std::net::{IpAddr, Ipv4Addr};
 
+/// Result of [FsmExecutor::shutdown].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShutdownReport {
+    /// Sessions that were cancelled and have stopped.
+    pub stopped: Vec<SessionId>,
+    /// Sessions that were cancelled, but didn't stop in time.
+    pub not_stopped: Vec<SessionId>,
+}
+
+/// Default of [InvokeLimits::max_depth].
+pub const DEFAULT_MAX_INVOKE_DEPTH: usize = 32;
+/// Default of [InvokeLimits::max_sessions].
+pub const DEFAULT_MAX_SESSIONS: usize = 1024;
+/// Default of [InvokeLimits::max_children].
+pub const DEFAULT_MAX_CHILDREN: usize = 256;
+
+/// Limits for sessions that are started by \<invoke\>, see [FsmExecutor::set_invoke_limits].\
+/// Protects against documents that invoke themselves, directly or via other documents.
+/// If a limit is exceeded, the invoke fails with "error.execution" and no session is started.
+/// "None" disables a limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvokeLimits {
+    /// Maximal number of invoking sessions above an invoked session.
+    pub max_depth: Option<usize>,
+    /// Maximal number of running sessions of the executor.
+    pub max_sessions: Option<usize>,
+    /// Maximal number of running child sessions of one session.
+    pub max_children: Option<usize>,
+}
+
+impl InvokeLimits {
+    /// Limits that are never exceeded.
+    pub fn unlimited() -> InvokeLimits {
+        InvokeLimits {
+            max_depth: None,
+            max_sessions: None,
+            max_children: None,
+        }
+    }
+}
+
+impl Default for InvokeLimits {
+    fn default() -> Self {
+        InvokeLimits {
+            max_depth: Some(DEFAULT_MAX_INVOKE_DEPTH),
+            max_sessions: Some(DEFAULT_MAX_SESSIONS),
+            max_children: Some(DEFAULT_MAX_CHILDREN),
+        }
+    }
+}
+
+/// Registry entry of a session, see [FsmExecutor::list_sessions].
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub session_id: SessionId,
+    /// The invoking session, if the session was started by \<invoke\>.
+    pub parent: Option<SessionId>,
+    /// The invoke id, if the session was started by \<invoke\>.
+    pub invoke_id: Option<InvokeId>,
+    pub finish_mode: FinishMode,
+    /// False after the FSM has finished.
+    pub running: bool,
+    /// The result of the finished session.
+    pub result: Option<SessionResult>,
+}
+
 #[derive(Default)]
 pub struct ExecuteState {
+    /// The registered Event I/O Processors.\
+    /// Processors lock the state while they send, so they must not be locked while the state is locked,
+    /// see [FsmExecutor::processors].
     pub processors: Vec<Arc<Mutex<Box<dyn EventIOProcessor>>>>,
+    /// The sessions that are running (or were started with [FinishMode::NOTHING]).
     pub sessions: HashMap<SessionId, ScxmlSession>,
+    /// Registry of all sessions in [ExecuteState::sessions] and of the finished sessions that were
+    /// started with [FinishMode::KEEP_CONFIGURATION].
+    pub session_infos: HashMap<SessionId, SessionInfo>,
     pub datamodel_options: HashMap<String, String>,
+    /// Trace modes for invoked sessions, overriding the modes of the invoking session. Key: invoke id.
+    #[cfg(feature = "Trace")]
+    pub invoke_trace_modes: HashMap<InvokeId, Vec<TraceMode>>,
+    /// The registered invoke handlers. Key: invoke type.
+    pub invoke_handlers: HashMap<String, Arc<Mutex<Box<dyn InvokeHandler>>>>,
+    /// Clock of all sessions, if no clock was set via [FsmExecutor::set_clock].
+    pub timer_clock: Arc<TimerClock>,
+    /// Queues of the running sessions that receive the delayed sends. See [FsmExecutor::schedule].
+    pub delayed_queues: HashMap<SessionId, (QueueSender<DelayedSend>, EventSender)>,
+    /// The registered transition actions. Key: action name.
+    pub transition_actions: HashMap<String, Arc<TransitionAction>>,
+    /// Names of transition actions that were called, but are not registered. Each is reported once.
+    pub unknown_transition_actions: HashSet<String>,
+    /// Sum of the counters of the sessions that were removed from [ExecuteState::sessions].
+    #[cfg(feature = "Metrics")]
+    pub finished_metrics: MetricsSnapshot,
 }
 
 impl ExecuteState {
+    /// Removes the session from [ExecuteState::sessions]. The counters of the session are kept.
+    fn remove_running_session(&mut self, session_id: SessionId) {
+        let _session = self.sessions.remove(&session_id);
+        #[cfg(feature = "Metrics")]
+        if let Some(session) = _session {
+            self.finished_metrics.add(&session.metrics());
+        }
+    }
+
+    /// Checks if the session "parent" can invoke another session.\
+    /// Returns the exceeded limit and the chain of invoking sessions as error.
+    fn check_invoke_limits(&self, limits: &InvokeLimits, parent: SessionId) -> Result<(), String> {
+        let mut chain = Vec::new();
+        let mut session_id = Some(parent);
+        while let Some(id) = session_id {
+            // Guards against a corrupted registry, a chain can't be longer than the registry.
+            if chain.len() > self.session_infos.len() {
+                break;
+            }
+            let info = self.session_infos.get(&id);
+            chain.push(match info.and_then(|i| i.invoke_id.as_ref()) {
+                Some(invoke_id) => format!("#{} ({})", id, invoke_id),
+                None => format!("#{}", id),
+            });
+            session_id = info.and_then(|i| i.parent);
+        }
+        let running = || self.session_infos.values().filter(|s| s.running);
+        let exceeded = if limits.max_depth.is_some_and(|max| chain.len() > max) {
+            format!("maximal invoke depth {} reached", limits.max_depth.unwrap())
+        } else if limits
+            .max_sessions
+            .is_some_and(|max| running().count() >= max)
+        {
+            format!(
+                "maximal number of sessions {} reached",
+                limits.max_sessions.unwrap()
+            )
+        } else if limits
+            .max_children
+            .is_some_and(|max| running().filter(|s| s.parent == Some(parent)).count() >= max)
+        {
+            format!(
+                "maximal number of child sessions {} reached",
+                limits.max_children.unwrap()
+            )
+        } else {
+            return Ok(());
+        };
+        let message = format!(
+            "Invoke rejected, {}. Invoke chain: {}",
+            exceeded,
+            chain.join(" <- ")
+        );
+        warn!("{}", message);
+        Err(message)
+    }
+
     pub fn new() -> ExecuteState {
         ExecuteState {
             processors: Vec::new(),
             sessions: HashMap::new(),
+            session_infos: HashMap::new(),
             datamodel_options: HashMap::new(),
+            #[cfg(feature = "Trace")]
+            invoke_trace_modes: HashMap::new(),
+            invoke_handlers: HashMap::new(),
+            timer_clock: Arc::new(TimerClock::new()),
+            delayed_queues: HashMap::new(),
+            transition_actions: HashMap::new(),
+            unknown_transition_actions: HashSet::new(),
+            #[cfg(feature = "Metrics")]
+            finished_metrics: MetricsSnapshot::default(),
         }
     }
 }
 
+/// Native action of a transition, called with the triggering event and the id of the session.
+/// See [FsmExecutor::register_transition_action].
+pub type TransitionAction = Box<dyn Fn(&Event, SessionId) + Send + Sync>;
+
 /// Executed FSM in separate threads.
 /// This class maintains IO Processors used by the FSMs and running sessions.
 #[derive(Clone)]
 pub struct FsmExecutor {
     pub state: Arc<Mutex<ExecuteState>>,
     pub include_paths: Vec<PathBuf>,
+    /// If false, documents with XInclude elements are rejected. See [FsmExecutor::set_allow_includes].
+    pub allow_includes: bool,
+    /// If set, sessions are executed by the pool instead of own threads. See [FsmExecutor::with_thread_pool].
+    pub thread_pool: Option<Arc<FsmThreadPool>>,
+    /// If set, replaces the clock of all sessions that are started afterwards. See [FsmExecutor::set_clock].
+    pub clock: Option<Arc<dyn Clock>>,
+    /// If set, bounds the external queues of all sessions that are started afterwards.
+    /// See [FsmExecutor::set_queue_limit].
+    pub queue_limit: Option<QueueLimit>,
+    /// If set, checks the external events of all sessions that are started afterwards.
+    /// See [FsmExecutor::set_event_validator].
+    pub event_validator: Option<Arc<dyn EventValidator>>,
+    /// If set, receives the messages of \<log\> elements. See [FsmExecutor::set_log_sink].
+    pub log_sink: Option<Arc<dyn LogSink>>,
+    /// Limits for invoked sessions. See [FsmExecutor::set_invoke_limits].
+    pub invoke_limits: InvokeLimits,
+    /// Documents that were started by location. Shared by all copies of the executor.
+    /// See [FsmExecutor::set_document_cache_enabled].
+    pub document_cache: Arc<Mutex<DocumentCache>>,
 }
 
 impl FsmExecutor {
+    /// Registers an Event I/O Processor for all the types it supports.\
+    /// Processors that were registered before for one of these types are removed.\
+    /// The processor is used by all sessions that are started afterwards. The same instance is shared
+    /// by these sessions, each session calls it from its own thread (guarded by a mutex).
+    /// [EventIOProcessor::add_fsm] is called once for each new session.
+    /// Already running sessions are not affected.
+    pub fn add_event_io_processor(&mut self, processor: Box<dyn EventIOProcessor>) {
+        let replaced: Vec<Arc<Mutex<Box<dyn EventIOProcessor>>>> = self
+            .processors()
+            .into_iter()
+            .filter(|p| {
+                let guard = p.lock().unwrap();
+                processor
+                    .get_types()
+                    .iter()
+                    .any(|t| guard.get_types().contains(t))
+            })
+            .collect();
+        let mut guard = self.state.lock().unwrap();
+        guard
+            .processors
+            .retain(|p| !replaced.iter().any(|r| Arc::ptr_eq(p, r)));
+        guard.processors.push(Arc::new(Mutex::new(processor)));
+    }
+
+    /// Registers an Event I/O Processor, see [FsmExecutor::add_event_io_processor].
+    #[deprecated(note = "use add_event_io_processor")]
     pub fn add_processor(&mut self, processor: Box<dyn EventIOProcessor>) {
+        self.add_event_io_processor(processor);
+    }
+
+    /// Removes the Event I/O Processor that supports the type.\
+    /// Already running sessions are not affected.
+    pub fn remove_event_io_processor(&mut self, type_name: &str) -> Option<Arc<Mutex<Box<dyn EventIOProcessor>>>> {
+        let removed = self
+            .processors()
+            .into_iter()
+            .find(|p| p.lock().unwrap().get_types().contains(&type_name))?;
         self.state
             .lock()
             .unwrap()
             .processors
-            .push(Arc::new(Mutex::new(processor)));
+            .retain(|p| !Arc::ptr_eq(p, &removed));
+        Some(removed)
+    }
+
+    /// Gets the registered Event I/O Processors.\
+    /// The processors shall not be locked while [FsmExecutor::state] is locked.
+    pub fn processors(&self) -> Vec<Arc<Mutex<Box<dyn EventIOProcessor>>>> {
+        self.state.lock().unwrap().processors.clone()
+    }
+
+    /// Gets the types of all registered Event I/O Processors, see [crate::validation::validate_send_types].
+    pub fn event_io_processor_types(&self) -> Vec<String> {
+        self.processors()
+            .iter()
+            .flat_map(|p| {
+                p.lock()
+                    .unwrap()
+                    .get_types()
+                    .iter()
+                    .map(|t| t.to_string())
+                    .collect::<Vec<String>>()
+            })
+            .collect()
+    }
+
+    /// Registers an invoke handler for all the types it supports.\
+    /// Handlers that were registered before for the same types are replaced.
+    pub fn add_invoke_handler(&mut self, handler: Box<dyn InvokeHandler>) {
+        let types: Vec<String> = handler.get_types().iter().map(|t| t.to_string()).collect();
+        let handler = Arc::new(Mutex::new(handler));
+        let mut guard = self.state.lock().unwrap();
+        for t in types {
+            guard.invoke_handlers.insert(t, handler.clone());
+        }
+    }
+
+    /// Registers a native action for transitions with attribute "rfsm:action" of the same name.\
+    /// The action is called after the executable content of the transition, from the thread of the session.
+    /// An action that was registered before with the same name is replaced.
+    pub fn register_transition_action(&mut self, name: &str, action: TransitionAction) {
+        self.state
+            .lock()
+            .unwrap()
+            .transition_actions
+            .insert(name.to_string(), Arc::new(action));
+    }
+
+    /// Calls the transition action. Unknown actions are reported once and otherwise ignored.
+    pub fn call_transition_action(&self, name: &str, event: &Event, session_id: SessionId) {
+        let action = {
+            let mut guard = self.state.lock().unwrap();
+            let action = guard.transition_actions.get(name).cloned();
+            if action.is_none() && guard.unknown_transition_actions.insert(name.to_string()) {
+                warn!("Transition action '{}' is not registered", name);
+            }
+            action
+        };
+        // Called without lock, the action may use the executor.
+        if let Some(action) = action {
+            action(event, session_id);
+        }
+    }
+
+    /// Gets the invoke handler for the type.
+    pub fn get_invoke_handler(&self, type_name: &str) -> Option<Arc<Mutex<Box<dyn InvokeHandler>>>> {
+        self.state
+            .lock()
+            .unwrap()
+            .invoke_handlers
+            .get(type_name)
+            .cloned()
     }
 
     pub fn new_without_io_processor() -> FsmExecutor {
         let mut e = FsmExecutor {
             state: Arc::new(Mutex::new(ExecuteState::new())),
             include_paths: Vec::new(),
+            allow_includes: true,
+            thread_pool: None,
+            clock: None,
+            queue_limit: None,
+            event_validator: None,
+            log_sink: None,
+            invoke_limits: InvokeLimits::default(),
+            document_cache: Arc::new(Mutex::new(DocumentCache::new())),
         };
-        e.add_processor(Box::new(ScxmlEventIOProcessor::new()));
+        e.add_event_io_processor(Box::new(ScxmlEventIOProcessor::new()));
+        e.add_invoke_handler(Box::new(ScxmlInvokeHandler::new()));
+        #[cfg(feature = "BasicHttpEventIOProcessor")]
+        e.add_invoke_handler(Box::new(BasicHTTPInvokeHandler::new()));
+        e
+    }
+
+    /// Creates an executor like [FsmExecutor::new_without_io_processor] that executes all sessions
+    /// on a pool with the given number of threads. This includes sessions started by \<invoke\>.\
+    /// Sessions don't block while waiting for events, see [crate::fsm_thread_pool].
+    pub fn with_thread_pool(threads: usize) -> FsmExecutor {
+        let mut e = FsmExecutor::new_without_io_processor();
+        e.thread_pool = Some(Arc::new(FsmThreadPool::new(threads)));
         e
     }
 
@@ -84,15 +402,26 @@ impl FsmExecutor {
         let mut e = FsmExecutor {
             state: Arc::new(Mutex::new(ExecuteState::new())),
             include_paths: Vec::new(),
+            allow_includes: true,
+            thread_pool: None,
+            clock: None,
+            queue_limit: None,
+            event_validator: None,
+            log_sink: None,
+            invoke_limits: InvokeLimits::default(),
+            document_cache: Arc::new(Mutex::new(DocumentCache::new())),
         };
         #[cfg(feature = "BasicHttpEventIOProcessor")]
         {
             let w = Box::new(
                 BasicHTTPEventIOProcessor::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), "localhost", 5555).await,
             );
-            e.add_processor(w);
+            e.add_event_io_processor(w);
         }
-        e.add_processor(Box::new(ScxmlEventIOProcessor::new()));
+        e.add_event_io_processor(Box::new(ScxmlEventIOProcessor::new()));
+        e.add_invoke_handler(Box::new(ScxmlInvokeHandler::new()));
+        #[cfg(feature = "BasicHttpEventIOProcessor")]
+        e.add_invoke_handler(Box::new(BasicHTTPInvokeHandler::new()));
         e
     }
 
@@ -102,15 +431,12 @@ impl FsmExecutor {
     }
 
     pub fn set_global_options_from_arguments(&mut self, named_arguments: &HashMap<&str, String>) {
-        let mut guard = self.state.lock().unwrap();
         // Currently only Datamodel options are relevant. Ignore all other stuff.
-        for (name, value) in named_arguments {
-            if let Some(datamodel_option) = name.strip_prefix(DATAMODEL_OPTION_PREFIX) {
-                guard
-                    .datamodel_options
-                    .insert(datamodel_option.to_string(), value.clone());
-            }
-        }
+        self.state
+            .lock()
+            .unwrap()
+            .datamodel_options
+            .extend(datamodel_options_from_arguments(named_arguments));
     }
 
     pub fn set_include_paths(&mut self, include_path: &Vec<PathBuf>) {
@@ -119,14 +445,208 @@ impl FsmExecutor {
         }
     }
 
-    /// Shutdown of all FSMs and IO-Processors.
-    pub fn shutdown(&mut self) {
-        let mut guard = self.state.lock().unwrap();
-        while !guard.processors.is_empty() {
-            if let Some(pp) = guard.processors.pop() {
-                pp.lock().unwrap().shutdown();
+    /// Enables or disables XInclude processing for documents loaded by this executor.\
+    /// Disable includes if the documents come from untrusted sources.
+    pub fn set_allow_includes(&mut self, allow_includes: bool) {
+        self.allow_includes = allow_includes;
+    }
+
+    /// Sets the clock that is used by all sessions that are started afterwards, including invoked sessions.\
+    /// Use a [crate::clock::TestClock] to control delayed sends.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = Some(clock);
+    }
+
+    /// Gets the clock of the sessions. If no clock was set, a [TimerClock] is used that is
+    /// shared by all sessions of this executor, so only one timer thread exists.
+    pub fn clock(&self) -> Arc<dyn Clock> {
+        match &self.clock {
+            Some(clock) => clock.clone(),
+            None => self.state.lock().unwrap().timer_clock.clone(),
+        }
+    }
+
+    /// Registers the queues of a session that receive its delayed sends.\
+    /// Called when the session is started, removed when the session has finished.
+    pub fn register_delayed_queue(
+        &self,
+        session_id: SessionId,
+        delayed_sender: QueueSender<DelayedSend>,
+        external_sender: EventSender,
+    ) {
+        self.state
+            .lock()
+            .unwrap()
+            .delayed_queues
+            .insert(session_id, (delayed_sender, external_sender));
+    }
+
+    /// Passes the send to the session after the delay.\
+    /// Dropping the returned guard cancels the send. The callback doesn't keep the session or the
+    /// executor alive: if the session has finished before the delay has elapsed, the send is discarded.
+    pub fn schedule(&self, session_id: SessionId, delay_ms: i64, delayed: DelayedSend) -> ClockGuard {
+        let state = Arc::downgrade(&self.state);
+        let mut delayed = Some(delayed);
+        self.clock().schedule(
+            delay_ms,
+            Box::new(move || {
+                let queues = match (state.upgrade(), delayed.take()) {
+                    (Some(state), Some(delayed)) => {
+                        let queues = state
+                            .lock()
+                            .unwrap()
+                            .delayed_queues
+                            .get(&session_id)
+                            .cloned();
+                        queues.map(|q| (q, delayed))
+                    }
+                    _ => None,
+                };
+                if let Some(((delayed_sender, external_sender), delayed)) = queues {
+                    if delayed_sender.send(delayed).is_ok() {
+                        let mut wakeup = Event::new_simple(EVENT_DELAYED_SEND);
+                        wakeup.etype = EventType::platform;
+                        let _ = external_sender.send(Box::new(wakeup));
+                    }
+                }
+            }),
+        )
+    }
+
+    /// Bounds the external queue of all sessions that are started afterwards, including invoked sessions.\
+    /// If a queue contains "max_len" events, the policy decides what happens with further events.
+    /// Platform events (e.g. of [ScxmlSession::send_and_sync]) and cancel events are always accepted.
+    /// The number of dropped events is available via [ScxmlSession::dropped_events].
+    pub fn set_queue_limit(&mut self, max_len: usize, policy: OverflowPolicy) {
+        self.queue_limit = Some(QueueLimit {
+            max_len: max_len.max(1),
+            policy,
+        });
+    }
+
+    /// Sets the validator for external events of all sessions that are started afterwards, including
+    /// invoked sessions. A validator of the FSM takes precedence, see [Fsm::event_validator].\
+    /// Rejected events are replaced by "error.platform", see [crate::event_validator].
+    pub fn set_event_validator(&mut self, validator: Arc<dyn EventValidator>) {
+        self.event_validator = Some(validator);
+    }
+
+    /// Sets the sink for the messages of \<log\> elements of all sessions that are started afterwards,
+    /// including invoked sessions. Without a sink, the messages are written via the log crate,
+    /// see [crate::log_sink].
+    pub fn set_log_sink(&mut self, sink: Arc<dyn LogSink>) {
+        self.log_sink = Some(sink);
+    }
+
+    /// Sets the limits for sessions that are invoked afterwards, see [InvokeLimits].\
+    /// Use [InvokeLimits::unlimited] to disable the limits.
+    pub fn set_invoke_limits(&mut self, limits: InvokeLimits) {
+        self.invoke_limits = limits;
+    }
+
+    /// Enables or disables the cache of documents that are started by location, e.g. by \<invoke src\>.
+    /// The cache is disabled by default. Changes of documents that are included via XInclude are not
+    /// detected, see [crate::document_cache].
+    pub fn set_document_cache_enabled(&mut self, enabled: bool) {
+        self.document_cache.lock().unwrap().set_enabled(enabled);
+    }
+
+    /// Removes all documents from the cache, so they are read again on the next start.
+    pub fn clear_document_cache(&mut self) {
+        self.document_cache.lock().unwrap().clear();
+    }
+
+    pub fn document_cache_stats(&self) -> DocumentCacheStats {
+        self.document_cache.lock().unwrap().stats()
+    }
+
+    /// Checks the [InvokeLimits] before a session is started by the session "parent".
+    fn check_invoke_limits(&self, parent: Option<SessionId>) -> Result<(), String> {
+        match parent {
+            None => Ok(()),
+            Some(parent) => self
+                .state
+                .lock()
+                .unwrap()
+                .check_invoke_limits(&self.invoke_limits, parent),
+        }
+    }
+
+    /// Sets the trace modes of sessions that are invoked with the invoke id.\
+    /// By default invoked sessions inherit the trace modes of the invoking session.
+    #[cfg(feature = "Trace")]
+    pub fn set_invoke_trace_modes(&mut self, invoke_id: &str, modes: &[TraceMode]) {
+        self.state
+            .lock()
+            .unwrap()
+            .invoke_trace_modes
+            .insert(invoke_id.to_string(), modes.to_vec());
+    }
+
+    /// Gets the trace modes set by [FsmExecutor::set_invoke_trace_modes].
+    #[cfg(feature = "Trace")]
+    pub fn get_invoke_trace_modes(&self, invoke_id: &str) -> Option<Vec<TraceMode>> {
+        self.state
+            .lock()
+            .unwrap()
+            .invoke_trace_modes
+            .get(invoke_id)
+            .cloned()
+    }
+
+    /// Shutdown of all FSMs, IO-Processors and the thread pool.\
+    /// Each running session is cancelled and its pending delayed sends are discarded. The cancel
+    /// cascades to invoked sessions. Waits until the sessions have stopped or the timeout elapsed.
+    pub fn shutdown(&mut self, timeout: Duration) -> ShutdownReport {
+        let deadline = Instant::now() + timeout;
+        let mut report = ShutdownReport::default();
+        let mut cancelled: HashSet<SessionId> = HashSet::new();
+        loop {
+            // Sessions may be invoked while the others are stopped, repeat until no new session shows up.
+            let sessions: Vec<ScxmlSession> = self
+                .state
+                .lock()
+                .unwrap()
+                .sessions
+                .values()
+                .filter(|s| !cancelled.contains(&s.session_id))
+                .cloned()
+                .collect();
+            if sessions.is_empty() {
+                break;
             }
+            for session in &sessions {
+                #[cfg(feature = "Debug")]
+                debug!("Shutdown: cancel session #{}", session.session_id);
+                cancelled.insert(session.session_id);
+                let _ = session
+                    .sender
+                    .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+                // The FSM may hold the lock, in this case the FSM cancels the timers itself on exit.
+                if let Ok(mut global) = session.global_data.try_lock() {
+                    global.cancel_all_delayed_sends();
+                }
+            }
+            for session in &sessions {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if session.completion.wait_for_exit(remaining) {
+                    report.stopped.push(session.session_id);
+                } else {
+                    report.not_stopped.push(session.session_id);
+                }
+            }
+        }
+        report.stopped.sort();
+        report.not_stopped.sort();
+
+        let processors = std::mem::take(&mut self.state.lock().unwrap().processors);
+        for pp in processors.into_iter().rev() {
+            pp.lock().unwrap().shutdown();
         }
+        if let Some(pool) = &self.thread_pool {
+            pool.shutdown();
+        }
+        report
     }
 
     /// Loads and starts the specified FSM.
@@ -135,76 +655,137 @@ impl FsmExecutor {
         uri: &str,
         actions: ActionWrapper,
         #[cfg(feature = "Trace")] trace: TraceMode,
+    ) -> Result<ScxmlSession, String> {
+        self.execute_with_options(
+            uri,
+            actions,
+            &HashMap::new(),
+            #[cfg(feature = "Trace")]
+            trace,
+        )
+    }
+
+    /// Loads and starts the specified FSM with datamodel options for this session, e.g. "ecma:strict".\
+    /// The options override the global options and are inherited by invoked sessions.
+    pub fn execute_with_options(
+        &mut self,
+        uri: &str,
+        actions: ActionWrapper,
+        options: &HashMap<String, String>,
+        #[cfg(feature = "Trace")] trace: TraceMode,
     ) -> Result<ScxmlSession, String> {
         self.execute_with_data(
             uri,
             actions,
             &Vec::new(),
+            options,
             None,
             &"".to_string(),
+            &[],
             #[cfg(feature = "Trace")]
-            trace,
+            &[trace],
         )
+        .map_err(|err| err.to_string())
     }
 
-    /// Loads and starts the specified FSM with some data set.\
-    /// Normally used if a child-FSM is started from a parent FSM.
+    /// Loads and starts the specified FSM with some data set and datamodel options.\
+    /// Normally used if a child-FSM is started from a parent FSM.\
+    /// "final_value_locations" are added to [Fsm::final_value_locations].
+    #[allow(clippy::too_many_arguments)]
     pub fn execute_with_data(
         &mut self,
         uri: &str,
         actions: ActionWrapper,
         data: &[ParamPair],
+        options: &HashMap<String, String>,
         parent: Option<SessionId>,
         invoke_id: &InvokeId,
-        #[cfg(feature = "Trace")] trace: TraceMode,
-    ) -> Result<ScxmlSession, String> {
+        final_value_locations: &[String],
+        #[cfg(feature = "Trace")] trace: &[TraceMode],
+    ) -> Result<ScxmlSession, InvokeError> {
+        self.check_invoke_limits(parent)?;
+
+        // The document is loaded without the lock of the cache, so other sessions are not blocked.
+        let key = DocumentCache::key(self.document_cache.lock().unwrap().is_enabled(), uri);
+        let cached = key
+            .as_ref()
+            .and_then(|key| self.document_cache.lock().unwrap().get(key));
+        let sm = match (cached, key) {
+            (Some(fsm), _) => Ok(fsm),
+            (None, Some(key)) => self
+                .load_document(uri)
+                .map(|fsm| self.document_cache.lock().unwrap().insert(key, fsm)),
+            (None, None) => self.load_document(uri),
+        };
+
+        match sm {
+            Ok(mut fsm) => {
+                #[cfg(feature = "Trace")]
+                for mode in trace {
+                    fsm.tracer.enable_trace(*mode);
+                }
+                fsm.caller_invoke_id = Some(invoke_id.clone());
+                fsm.parent_session_id = parent;
+                if fsm.base_uri.is_none() {
+                    fsm.base_uri = Some(uri.to_string());
+                }
+                fsm.final_value_locations
+                    .extend_from_slice(final_value_locations);
+                let session = fsm::start_fsm_with_options(
+                    fsm,
+                    actions,
+                    Box::new(self.clone()),
+                    data,
+                    options,
+                    FinishMode::DISPOSE,
+                );
+                Ok(session)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Reads the FSM from a XML or binary ("rfsm") document.
+    #[allow(unused_variables)]
+    fn load_document(&self, uri: &str) -> Result<Box<Fsm>, InvokeError> {
         #[allow(unused_variables)]
         let extension = uri.rsplit('.').next().unwrap_or_default();
 
         #[allow(unused_variables)]
         #[allow(unused_mut)]
-        let mut sm: Result<Box<Fsm>, String> = Err("".to_string());
+        let mut sm: Result<Box<Fsm>, InvokeError> = Err(InvokeError::Execution(format!(
+            "Unsupported document '{}'",
+            uri
+        )));
 
         // Use reader to parse the scxml file:
         #[cfg(feature = "xml")]
         if extension.eq_ignore_ascii_case("scxml") || extension.eq_ignore_ascii_case("xml") {
             #[cfg(feature = "Debug")]
             debug!("Loading FSM from XML {}", uri);
-            sm = scxml_reader::parse_from_uri(uri.to_string(), &self.include_paths);
+            // Documents that the reader rejects with a panic are reported as error.
+            sm = panic::catch_unwind(AssertUnwindSafe(|| {
+                scxml_reader::load_from_uri(uri.to_string(), &self.include_paths, self.allow_includes)
+            }))
+            .unwrap_or_else(|payload| Err(InvokeError::Parse(panic_message(payload))));
         }
 
         #[cfg(feature = "serializer")]
         if extension.eq_ignore_ascii_case("rfsm") {
             #[cfg(feature = "Debug")]
             debug!("Loading FSM from binary {}", uri);
-            sm = match File::open(uri) {
-                Ok(f) => {
-                    let protocol = DefaultProtocolReader::new(BufReader::new(f));
-                    let mut reader = FsmReader::new(Box::new(protocol));
-                    reader.read()
-                }
-                Err(err) => Err(err.to_string()),
-            }
+            sm = load_precompiled_document(uri);
         }
 
         #[cfg(all(not(feature = "xml"), not(feature = "serializer")))]
         let sm = Ok(Box::new(Fsm::new()));
 
-        match sm {
-            Ok(mut fsm) => {
-                #[cfg(feature = "Trace")]
-                fsm.tracer.enable_trace(trace);
-                fsm.caller_invoke_id = Some(invoke_id.clone());
-                fsm.parent_session_id = parent;
-                let session = fsm::start_fsm_with_data(fsm, actions, Box::new(self.clone()), data);
-                Ok(session)
-            }
-            Err(message) => Err(message),
-        }
+        sm
     }
 
     /// Loads and starts the specified FSM with some data set.\
-    /// Normally used if a child-FSM is started from a parent FSM, in this case via inline content.
+    /// Normally used if a child-FSM is started from a parent FSM, in this case via inline content.\
+    /// "final_value_locations" are added to [Fsm::final_value_locations].
     #[allow(clippy::too_many_arguments)]
     #[allow(unused_variables)]
     pub fn execute_with_data_from_xml(
@@ -212,46 +793,137 @@ impl FsmExecutor {
         xml: &str,
         actions: ActionWrapper,
         data: &[ParamPair],
+        options: &HashMap<String, String>,
         parent: Option<SessionId>,
         invoke_id: &InvokeId,
+        final_value_locations: &[String],
         finish_mode: FinishMode,
-        #[cfg(feature = "Trace")] trace: TraceMode,
-    ) -> Result<ScxmlSession, String> {
+        #[cfg(feature = "Trace")] trace: &[TraceMode],
+    ) -> Result<ScxmlSession, InvokeError> {
+        self.check_invoke_limits(parent)?;
+
         #[cfg(feature = "Debug")]
         debug!("Loading FSM from XML");
 
-        // Use reader to parse the XML:
+        // Use reader to parse the XML. The XML may be generated at runtime (e.g. by \<content expr\>),
+        // so documents that the reader rejects with a panic are reported as error.
         #[cfg(feature = "xml")]
-        let sm = scxml_reader::parse_from_xml_with_includes(xml.to_string(), &self.include_paths);
+        let sm = panic::catch_unwind(AssertUnwindSafe(|| {
+            scxml_reader::parse_from_xml_with_options(xml.to_string(), &self.include_paths, self.allow_includes)
+        }))
+        .unwrap_or_else(|payload| Err(panic_message(payload)))
+        .map_err(InvokeError::Parse);
         #[cfg(not(feature = "xml"))]
         let sm = Ok(Box::new(Fsm::new()));
 
         match sm {
             Ok(mut fsm) => {
                 #[cfg(feature = "Trace")]
-                fsm.tracer.enable_trace(trace);
+                for mode in trace {
+                    fsm.tracer.enable_trace(*mode);
+                }
                 fsm.caller_invoke_id = Some(invoke_id.clone());
                 fsm.parent_session_id = parent;
-                let session = fsm::start_fsm_with_data_and_finish_mode(
+                fsm.final_value_locations
+                    .extend_from_slice(final_value_locations);
+                let session = fsm::start_fsm_with_options(
                     fsm,
                     actions.get_copy(),
                     Box::new(self.clone()),
                     data,
+                    options,
                     finish_mode,
                 );
                 Ok(session)
             }
-            Err(message) => Err(message),
+            Err(error) => Err(error),
         }
     }
 
-    /// Called by FSM after session ends and FinishMode::DISPOSE.
+    /// Removes the session and its registry entry, e.g. to drop the record of a finished session.
     pub fn remove_session(&mut self, session_id: SessionId) {
-        self.state.lock().unwrap().sessions.remove(&session_id);
+        let mut guard = self.state.lock().unwrap();
+        guard.delayed_queues.remove(&session_id);
+        guard.remove_running_session(session_id);
+        guard.session_infos.remove(&session_id);
+    }
+
+    /// Called by the FSM after the session has finished, before the result is published.\
+    /// Depending on the finish mode the session is removed ([FinishMode::DISPOSE]), only a record with
+    /// the result is kept ([FinishMode::KEEP_CONFIGURATION]) or the session is kept ([FinishMode::NOTHING]).
+    pub fn session_finished(&mut self, result: &SessionResult) {
+        let mut guard = self.state.lock().unwrap();
+        guard.delayed_queues.remove(&result.session_id);
+        let finish_mode = match guard.session_infos.get_mut(&result.session_id) {
+            None => FinishMode::DISPOSE,
+            Some(info) => {
+                info.running = false;
+                info.result = Some(result.clone());
+                info.finish_mode.clone()
+            }
+        };
+        match finish_mode {
+            FinishMode::DISPOSE => {
+                guard.remove_running_session(result.session_id);
+                guard.session_infos.remove(&result.session_id);
+            }
+            FinishMode::KEEP_CONFIGURATION => {
+                // Drops the sender, events to the session fail from now on.
+                guard.remove_running_session(result.session_id);
+            }
+            FinishMode::NOTHING => {}
+        }
+    }
+
+    /// Lists the registered sessions, ordered by session id.
+    pub fn list_sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .state
+            .lock()
+            .unwrap()
+            .session_infos
+            .values()
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|s| s.session_id);
+        sessions
+    }
+
+    /// Gets the registry entry of the session.
+    pub fn get_session(&self, session_id: SessionId) -> Option<SessionInfo> {
+        self.state
+            .lock()
+            .unwrap()
+            .session_infos
+            .get(&session_id)
+            .cloned()
+    }
+
+    /// Number of sessions that have not terminated.
+    pub fn running_sessions(&self) -> usize {
+        self.state
+            .lock()
+            .unwrap()
+            .session_infos
+            .values()
+            .filter(|s| s.running)
+            .count()
+    }
+
+    /// Gets the counters of all sessions of this executor, including the finished sessions.
+    #[cfg(feature = "Metrics")]
+    pub fn metrics(&self) -> MetricsSnapshot {
+        let guard = self.state.lock().unwrap();
+        let mut metrics = guard.finished_metrics.clone();
+        for session in guard.sessions.values() {
+            metrics.add(&session.metrics());
+        }
+        metrics.active_sessions = guard.session_infos.values().filter(|s| s.running).count() as u64;
+        metrics
     }
 
     /// Gets a clone of the event-sender of the session.
-    pub fn get_session_sender(&self, session_id: SessionId) -> Option<Sender<Box<Event>>> {
+    pub fn get_session_sender(&self, session_id: SessionId) -> Option<EventSender> {
         Some(
             self.state
                 .lock()
@@ -263,13 +935,669 @@ impl FsmExecutor {
         )
     }
 
-    /// Sends some event to a session.
-    pub fn send_to_session(&self, session_id: SessionId, event: Event) -> Result<(), SendError<Box<Event>>> {
+    /// Sends some event to a session.\
+    /// Fails if the session is not available or if its queue is full, see [FsmExecutor::set_queue_limit].
+    pub fn send_to_session(&self, session_id: SessionId, event: Event) -> Result<(), TrySendError<Box<Event>>> {
         match self.get_session_sender(session_id) {
-            None => {
-                todo!("Handling of unknown session")
-            }
+            None => Err(TrySendError::Disconnected(Box::new(event))),
             Some(sender) => sender.send(Box::new(event)),
         }
     }
 }
+
+/// Gets the message of a panic, e.g. of the reader.
+#[cfg(feature = "xml")]
+fn panic_message(payload: Box<dyn std::any::Any + Send>) -> String {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message.to_string(),
+        None => payload
+            .downcast_ref::<String>()
+            .cloned()
+            .unwrap_or_else(|| "Invalid document".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::{thread, time::Duration};
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+
+    use crate::datamodel::GlobalDataArc;
+    use crate::event_io_processor::{EventIOProcessor, EventIOProcessorHandle};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode, ScxmlSession};
+    use crate::fsm::{Event, SessionId};
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::InvokeLimits;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_stepper::start_fsm_stepper;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::test::run_test_manual_with_executor;
+    #[cfg(feature = "Trace")]
+    use crate::tracer::TraceMode;
+
+    const MOCK_TYPES: &[&str] = &["mock-io"];
+
+    /// Processor that only records the events it shall send.
+    #[derive(Debug, Clone)]
+    struct MockEventIOProcessor {
+        handle: EventIOProcessorHandle,
+        sent: Arc<Mutex<Vec<(String, String)>>>,
+    }
+
+    impl EventIOProcessor for MockEventIOProcessor {
+        fn get_location(&self, id: SessionId) -> String {
+            format!("mock:{}", id)
+        }
+
+        fn get_types(&self) -> &[&str] {
+            MOCK_TYPES
+        }
+
+        fn get_handle(&mut self) -> &mut EventIOProcessorHandle {
+            &mut self.handle
+        }
+
+        fn get_copy(&self) -> Box<dyn EventIOProcessor> {
+            Box::new(self.clone())
+        }
+
+        fn send(&mut self, _global: &GlobalDataArc, target: &str, event: Event) -> bool {
+            self.sent
+                .lock()
+                .unwrap()
+                .push((target.to_string(), event.name.clone()));
+            true
+        }
+
+        fn shutdown(&mut self) {}
+    }
+
+    fn mock_processor(sent: &Arc<Mutex<Vec<(String, String)>>>) -> Box<MockEventIOProcessor> {
+        Box::new(MockEventIOProcessor {
+            handle: EventIOProcessorHandle::new(),
+            sent: sent.clone(),
+        })
+    }
+
+    #[test]
+    fn processors_are_replaced_by_type() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.add_event_io_processor(mock_processor(&sent));
+        executor.add_event_io_processor(mock_processor(&sent));
+        assert_eq!(executor.state.lock().unwrap().processors.len(), 2);
+
+        assert!(executor.remove_event_io_processor("mock-io").is_some());
+        assert!(executor.remove_event_io_processor("mock-io").is_none());
+        assert_eq!(executor.state.lock().unwrap().processors.len(), 1);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn custom_processor_is_used() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <send type='mock-io' target='remote' event='hello'/>
+                  <if cond="_ioprocessors['mock-io'].location == 'mock:' + _sessionid">
+                    <raise event='located'/>
+                  </if>
+                </onentry>
+                <transition event='located' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.add_event_io_processor(mock_processor(&sent));
+
+        assert!(run_test_manual_with_executor(
+            "custom_processor_is_used",
+            executor,
+            sm.unwrap(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+            |_sender| {},
+        ));
+        assert_eq!(
+            sent.lock().unwrap().as_slice(),
+            &[("remote".to_string(), "hello".to_string())]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn delayed_send_is_discarded_on_termination() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <final id='Main'>
+                <onentry>
+                  <send type='mock-io' target='remote' event='late' delay='300ms'/>
+                  <send id='withId' type='mock-io' target='remote' event='late' delay='300ms'/>
+                </onentry>
+              </final>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.add_event_io_processor(mock_processor(&sent));
+
+        // The stepper keeps the FSM alive after the session has finished.
+        let mut stepper = start_fsm_stepper(
+            sm.unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::DISPOSE,
+        );
+        assert!(!stepper.step(None).running);
+        thread::sleep(Duration::from_millis(600));
+        assert!(
+            sent.lock().unwrap().is_empty(),
+            "Delayed events shall be discarded"
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn shutdown_cancels_sessions_and_invoked_children() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <invoke id='child1'>
+                  <content>
+                    <scxml initial='Wait' datamodel='ecmascript'>
+                      <state id='Wait'>
+                        <invoke id='grandchild'>
+                          <content>
+                            <scxml initial='Wait' datamodel='ecmascript'>
+                              <state id='Wait'/>
+                            </scxml>
+                          </content>
+                        </invoke>
+                      </state>
+                    </scxml>
+                  </content>
+                </invoke>
+                <invoke id='child2'>
+                  <content>
+                    <scxml initial='Wait' datamodel='ecmascript'>
+                      <state id='Wait'>
+                        <onentry>
+                          <send event='never' delay='1h'/>
+                        </onentry>
+                      </state>
+                    </scxml>
+                  </content>
+                </invoke>
+              </state>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let mut executor = FsmExecutor::new_without_io_processor();
+        let mut parent = start_fsm_with_data_and_finish_mode(
+            sm.unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor.clone()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        // Wait until the parent, both children and the grandchild are running.
+        for _ in 0..200 {
+            if executor.state.lock().unwrap().sessions.len() == 4 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let sessions: Vec<ScxmlSession> = executor
+            .state
+            .lock()
+            .unwrap()
+            .sessions
+            .values()
+            .cloned()
+            .collect();
+        assert_eq!(sessions.len(), 4);
+
+        let report = executor.shutdown(Duration::from_secs(5));
+        assert!(report.not_stopped.is_empty(), "{:?}", report);
+        assert_eq!(report.stopped.len(), 4);
+        for session in &sessions {
+            assert!(session.completion.wait_for_exit(Duration::ZERO));
+        }
+        let thread = parent.thread.take().unwrap();
+        assert!(thread.is_finished() || thread.join().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn finished_sessions_are_removed_from_registry() {
+        let document = r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <invoke id='child'>
+                  <content>
+                    <scxml initial='Wait' datamodel='ecmascript'>
+                      <state id='Wait'>
+                        <transition event='stop' target='Done'/>
+                      </state>
+                      <final id='Done'/>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='stop' target='Done'>
+                  <send target='#_child' event='stop'/>
+                </transition>
+              </state>
+              <final id='Done'/>
+            </scxml>"##;
+        let executor = FsmExecutor::new_without_io_processor();
+        let mut sessions = Vec::new();
+        for idx in 0..10 {
+            let finish_mode = if idx < 5 {
+                FinishMode::DISPOSE
+            } else {
+                FinishMode::KEEP_CONFIGURATION
+            };
+            sessions.push(start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(document.to_string()).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                finish_mode,
+            ));
+        }
+        // Wait until all children are invoked.
+        for _ in 0..200 {
+            if executor.running_sessions() == 20 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(executor.running_sessions(), 20);
+        let first = sessions[0].session_id;
+        let child = executor
+            .list_sessions()
+            .into_iter()
+            .find(|s| s.parent == Some(first))
+            .unwrap();
+        assert_eq!(child.invoke_id, Some("child".to_string()));
+        assert!(child.running);
+        assert!(executor.get_session(first).unwrap().invoke_id.is_none());
+
+        for session in &sessions {
+            session
+                .sender
+                .send(Box::new(Event::new_simple("stop")))
+                .unwrap();
+        }
+        for session in &sessions {
+            assert!(session
+                .wait_for_completion(Duration::from_millis(2000))
+                .is_some());
+        }
+        // Invoked children finish with the parents, wait until they are gone as well.
+        for _ in 0..200 {
+            if executor.running_sessions() == 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(executor.running_sessions(), 0);
+
+        // Only the records of the sessions with KEEP_CONFIGURATION are left, the senders are dropped.
+        let records = executor.list_sessions();
+        let ids: Vec<SessionId> = records.iter().map(|s| s.session_id).collect();
+        let expected: Vec<SessionId> = sessions[5..].iter().map(|s| s.session_id).collect();
+        assert_eq!(ids, expected);
+        for record in &records {
+            assert!(!record.running);
+            assert_eq!(
+                record.result.as_ref().unwrap().final_configuration,
+                Some(vec!["Done".to_string()])
+            );
+        }
+        assert!(executor.state.lock().unwrap().sessions.is_empty());
+        assert!(executor
+            .send_to_session(expected[0], Event::new_simple("stop"))
+            .is_err());
+        assert!(executor.get_session(first).is_none());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn delayed_sends_of_all_sessions_share_one_timer_thread() {
+        use crate::clock::{Clock, ClockCallback, ClockGuard, TimerClock};
+        use std::collections::HashSet;
+        use std::thread::ThreadId;
+
+        /// Records the threads that execute the callbacks.
+        struct RecordingClock {
+            clock: TimerClock,
+            threads: Arc<Mutex<HashSet<ThreadId>>>,
+        }
+
+        impl Clock for RecordingClock {
+            fn schedule(&self, delay_ms: i64, mut cb: ClockCallback) -> ClockGuard {
+                let threads = self.threads.clone();
+                self.clock.schedule(
+                    delay_ms,
+                    Box::new(move || {
+                        threads.lock().unwrap().insert(thread::current().id());
+                        cb();
+                    }),
+                )
+            }
+
+            fn now_ms(&self) -> i64 {
+                self.clock.now_ms()
+            }
+        }
+
+        let xml = r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='ticks' expr='0'/>
+                <data id='delays' expr='[...Array(100).keys()]'/>
+              </datamodel>
+              <state id='Main'>
+                <onentry>
+                  <foreach array='delays' item='d'>
+                    <send event='tick' delayexpr="(d % 20) + 'ms'"/>
+                  </foreach>
+                  <send id='cancelled' event='tick' delay='50ms'/>
+                  <cancel sendid='cancelled'/>
+                </onentry>
+                <transition event='tick' cond='ticks == 99' target='Done'/>
+                <transition event='tick'>
+                  <assign location='ticks' expr='ticks + 1'/>
+                </transition>
+              </state>
+              <final id='Done'/>
+            </scxml>"##;
+
+        let threads = Arc::new(Mutex::new(HashSet::new()));
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_clock(Arc::new(RecordingClock {
+            clock: TimerClock::new(),
+            threads: threads.clone(),
+        }));
+
+        let sessions: Vec<ScxmlSession> = (0..5)
+            .map(|_| {
+                start_fsm_with_data_and_finish_mode(
+                    scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+                    ActionWrapper::new(),
+                    Box::new(executor.clone()),
+                    &Vec::new(),
+                    FinishMode::KEEP_CONFIGURATION,
+                )
+            })
+            .collect();
+        for session in &sessions {
+            let result = session.wait_for_completion(Duration::from_millis(5000));
+            assert_eq!(
+                result.unwrap().final_configuration,
+                Some(vec!["Done".to_string()])
+            );
+        }
+        let threads = threads.lock().unwrap();
+        assert_eq!(
+            threads.len(),
+            1,
+            "All callbacks shall be executed by one thread"
+        );
+        assert!(!threads.contains(&thread::current().id()));
+        assert!(executor.state.lock().unwrap().delayed_queues.is_empty());
+
+        // Without a clock, the sessions share the timer of the executor.
+        let executor = FsmExecutor::new_without_io_processor();
+        let clock_ptr = |clock: Arc<dyn Clock>| Arc::as_ptr(&clock) as *const ();
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor.clone()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        assert!(session
+            .wait_for_completion(Duration::from_millis(5000))
+            .is_some());
+        let session_clock = session.global_data.lock().unwrap().clock.clone().unwrap();
+        assert_eq!(clock_ptr(session_clock), clock_ptr(executor.clock()));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn transition_actions_are_called_once_per_taken_transition() {
+        let xml = r##"<scxml initial='Main' datamodel='ecmascript' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+              <state id='Main'>
+                <transition target='A' rfsm:action='record'/>
+              </state>
+              <state id='A'>
+                <onentry>
+                  <raise event='go'/>
+                </onentry>
+                <transition event='go' rfsm:action='record'>
+                  <raise event='next'/>
+                </transition>
+                <transition event='next' target='B' rfsm:action='unknown'/>
+              </state>
+              <state id='B'>
+                <transition event='ext' target='C' rfsm:action='record'/>
+              </state>
+              <state id='C'>
+                <transition target='Done' rfsm:action='unknown'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##;
+
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let calls_clone = calls.clone();
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.register_transition_action(
+            "record",
+            Box::new(move |event: &Event, session_id: SessionId| {
+                calls_clone
+                    .lock()
+                    .unwrap()
+                    .push((event.name.clone(), session_id));
+            }),
+        );
+
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor.clone()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        session
+            .send_and_sync(
+                Box::new(Event::new_simple("ext")),
+                Duration::from_millis(2000),
+            )
+            .unwrap();
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["Done".to_string()])
+        );
+
+        let id = session.session_id;
+        assert_eq!(
+            *calls.lock().unwrap(),
+            vec![
+                ("".to_string(), id),
+                ("go".to_string(), id),
+                ("ext".to_string(), id)
+            ]
+        );
+        let unknown = &executor.state.lock().unwrap().unknown_transition_actions;
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown.contains("unknown"));
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn recursive_invoke_is_limited() {
+        let dir = std::env::temp_dir().join(format!("rfsm_recursive_invoke_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("self.scxml");
+        std::fs::write(
+            &path,
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <invoke id='self' src='self.scxml'/>
+                <transition event='error.execution' cond="_event.invokeid == 'self'" target='Rejected'/>
+                <transition event='done.invoke.self' target='ChildDone'/>
+              </state>
+              <final id='Rejected'/>
+              <final id='ChildDone'/>
+            </scxml>"##,
+        )
+        .unwrap();
+
+        let run = |limits: Option<InvokeLimits>| {
+            let mut executor = FsmExecutor::new_without_io_processor();
+            if let Some(limits) = limits {
+                executor.set_invoke_limits(limits);
+            }
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml_file(&path, &[]).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let result = session.wait_for_completion(Duration::from_millis(5000));
+            for _ in 0..200 {
+                if executor.running_sessions() == 0 {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+            assert_eq!(executor.running_sessions(), 0);
+            result.unwrap().final_configuration.unwrap()
+        };
+
+        // The deepest child gets "error.execution", all others finish with the done event of their child.
+        assert_eq!(run(None), vec!["ChildDone"]);
+        assert_eq!(
+            run(Some(InvokeLimits {
+                max_depth: Some(3),
+                ..InvokeLimits::unlimited()
+            })),
+            vec!["ChildDone"]
+        );
+        assert_eq!(
+            run(Some(InvokeLimits {
+                max_sessions: Some(1),
+                ..InvokeLimits::unlimited()
+            })),
+            vec!["Rejected"]
+        );
+        assert_eq!(
+            run(Some(InvokeLimits {
+                max_children: Some(0),
+                ..InvokeLimits::unlimited()
+            })),
+            vec!["Rejected"]
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn invoked_document_is_read_once() {
+        let dir = std::env::temp_dir().join(format!("rfsm_document_cache_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("child.scxml"),
+            r##"<scxml initial='Run' datamodel='ecmascript'>
+              <datamodel><data id='count' expr='0'/></datamodel>
+              <state id='Run'>
+                <onentry><assign location='count' expr='count + 1'/></onentry>
+                <transition target='Done'/>
+              </state>
+              <final id='Done'><donedata><param name='count' expr='count'/></donedata></final>
+            </scxml>"##,
+        )
+        .unwrap();
+        let path = dir.join("parent.scxml");
+        std::fs::write(
+            &path,
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel><data id='runs' expr='1'/></datamodel>
+              <state id='Main'>
+                <invoke id='child' src='child.scxml'/>
+                <transition event='done.invoke.child' cond='_event.data.count == 1 &amp;&amp; runs &lt; 50' target='Main'>
+                  <assign location='runs' expr='runs + 1'/>
+                </transition>
+                <transition event='done.invoke.child' cond='_event.data.count == 1' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+        )
+        .unwrap();
+
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_document_cache_enabled(true);
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml_file(&path, &[]).unwrap(),
+            ActionWrapper::new(),
+            Box::new(executor.clone()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(5000));
+        // Each child starts with its own data, so all children report "count" 1.
+        assert_eq!(
+            result.and_then(|r| r.final_configuration),
+            Some(vec!["pass".to_string()])
+        );
+        let stats = executor.document_cache_stats();
+        assert_eq!((stats.misses, stats.hits, stats.documents), (1, 49, 1));
+
+        executor.clear_document_cache();
+        assert_eq!(executor.document_cache_stats().documents, 0);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
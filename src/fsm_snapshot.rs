@@ -0,0 +1,268 @@
+//! Snapshot of the runtime state of a FSM.\
+//! A snapshot is taken via [crate::fsm::ScxmlSession::take_snapshot] and can be used to resume
+//! the FSM later via [crate::fsm::start_fsm_from_snapshot], e.g. after a process restart.\
+//! All states are referenced by name, so the snapshot stays valid as long as the
+//! same SCXML document is used.
+//!
+//! Restore policy:
+//! + The data model is initialized as usual, afterwards the captured data values are assigned.
+//! + The global script and the \<onentry\> handlers of the restored configuration are not executed.
+//! + Invokes of the restored configuration are started again.
+//! + Pending delayed sends and running child sessions are not part of the snapshot.
+
+use std::collections::HashMap;
+use std::ops::Deref;
+
+#[cfg(feature = "json-config")]
+use serde::{Deserialize, Serialize};
+
+use crate::datamodel::{create_data_arc, Data, DataArc};
+use crate::fsm::{Event, EventType, ParamPair};
+
+/// The captured runtime state of a FSM.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "json-config", derive(Serialize, Deserialize))]
+pub struct FsmSnapshot {
+    /// Name of the FSM, only informational.
+    pub name: String,
+    /// The active states.
+    pub configuration: Vec<String>,
+    /// The recorded history. Key: history state, value: the stored states.
+    pub history: Vec<(String, Vec<String>)>,
+    /// States that were already entered once. Needed to restore "late" binding.
+    pub entered_states: Vec<String>,
+    /// Values of all declared data elements.
+    pub data: Vec<(String, SnapshotData)>,
+    /// Pending internal events.
+    pub internal_queue: Vec<SnapshotEvent>,
+}
+
+/// Serializable variant of [Data].\
+/// Sources and errors are not part of a snapshot and are stored as "Null".
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-config", derive(Serialize, Deserialize))]
+pub enum SnapshotData {
+    Integer(i64),
+    Double(f64),
+    String(String),
+    Boolean(bool),
+    Array(Vec<SnapshotData>),
+    Map(HashMap<String, SnapshotData>),
+    Null,
+}
+
+impl SnapshotData {
+    pub fn from_data(data: &Data) -> SnapshotData {
+        match data {
+            Data::Integer(v) => SnapshotData::Integer(*v),
+            Data::Double(v) => SnapshotData::Double(*v),
+            Data::String(v) => SnapshotData::String(v.clone()),
+            Data::Boolean(v) => SnapshotData::Boolean(*v),
+            Data::Array(v) => SnapshotData::Array(v.iter().map(SnapshotData::from_data_arc).collect()),
+            Data::Map(v) => SnapshotData::Map(
+                v.iter()
+                    .map(|(k, v)| (k.clone(), SnapshotData::from_data_arc(v)))
+                    .collect(),
+            ),
+            Data::Null() | Data::Error(_) | Data::Source(_) | Data::None() => SnapshotData::Null,
+        }
+    }
+
+    pub fn from_data_arc(data: &DataArc) -> SnapshotData {
+        SnapshotData::from_data(data.lock().unwrap().deref())
+    }
+
+    pub fn to_data(&self) -> Data {
+        match self {
+            SnapshotData::Integer(v) => Data::Integer(*v),
+            SnapshotData::Double(v) => Data::Double(*v),
+            SnapshotData::String(v) => Data::String(v.clone()),
+            SnapshotData::Boolean(v) => Data::Boolean(*v),
+            SnapshotData::Array(v) => Data::Array(v.iter().map(|d| create_data_arc(d.to_data())).collect()),
+            SnapshotData::Map(v) => Data::Map(
+                v.iter()
+                    .map(|(k, v)| (k.clone(), create_data_arc(v.to_data())))
+                    .collect(),
+            ),
+            SnapshotData::Null => Data::Null(),
+        }
+    }
+}
+
+/// Serializable variant of [Event].
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json-config", derive(Serialize, Deserialize))]
+pub struct SnapshotEvent {
+    pub name: String,
+    /// Name of the [EventType].
+    pub etype: String,
+    pub sendid: Option<String>,
+    pub origin: Option<String>,
+    pub origin_type: Option<String>,
+    pub invoke_id: Option<String>,
+    pub param_values: Option<Vec<(String, SnapshotData)>>,
+    pub content: Option<SnapshotData>,
+}
+
+impl SnapshotEvent {
+    pub fn from_event(event: &Event) -> SnapshotEvent {
+        SnapshotEvent {
+            name: event.name.clone(),
+            etype: event.etype.name().to_string(),
+            sendid: event.sendid.clone(),
+            origin: event.origin.clone(),
+            origin_type: event.origin_type.clone(),
+            invoke_id: event.invoke_id.clone(),
+            param_values: event.param_values.as_ref().map(|pv| {
+                pv.iter()
+                    .map(|p| (p.name.clone(), SnapshotData::from_data(&p.value)))
+                    .collect()
+            }),
+            content: event.content.as_ref().map(SnapshotData::from_data_arc),
+        }
+    }
+
+    pub fn to_event(&self) -> Event {
+        let mut event = Event::new_simple(self.name.as_str());
+        event.etype = match self.etype.as_str() {
+            "platform" => EventType::platform,
+            "internal" => EventType::internal,
+            _ => EventType::external,
+        };
+        event.sendid.clone_from(&self.sendid);
+        event.origin.clone_from(&self.origin);
+        event.origin_type.clone_from(&self.origin_type);
+        event.invoke_id.clone_from(&self.invoke_id);
+        event.param_values = self.param_values.as_ref().map(|pv| {
+            pv.iter()
+                .map(|(name, value)| ParamPair::new_moved(name.clone(), value.to_data()))
+                .collect()
+        });
+        event.content = self.content.as_ref().map(|c| create_data_arc(c.to_data()));
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm::{
+        start_fsm_from_snapshot, start_fsm_with_data_and_finish_mode, Event, FinishMode, EVENT_CANCEL_SESSION,
+    };
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_snapshot::SnapshotData;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    const DEEP_HISTORY_FSM: &str = r##"<scxml initial='Main' datamodel='ecmascript'>
+          <datamodel><data id='counter' expr='0'/></datamodel>
+          <state id='Main' initial='A'>
+            <history id='H' type='deep'><transition target='A'/></history>
+            <state id='A' initial='A1'>
+              <state id='A1'><transition event='next' target='A2'/></state>
+              <state id='A2'>
+                <transition event='check' cond='counter == 1' target='pass'/>
+                <transition event='check' target='fail'/>
+              </state>
+            </state>
+            <transition event='leave' target='Outside'/>
+          </state>
+          <state id='Outside'>
+            <onentry><assign location='counter' expr='counter + 1'/></onentry>
+            <transition event='back' target='H'/>
+          </state>
+          <final id='pass'/>
+          <final id='fail'/>
+        </scxml>"##;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn deep_history_is_restored() {
+        let timeout = Duration::from_millis(2000);
+        let session = start_fsm_with_data_and_finish_mode(
+            scxml_reader::parse_from_xml(DEEP_HISTORY_FSM.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::DISPOSE,
+        );
+        assert!(session
+            .send_and_sync(Box::new(Event::new_simple("next")), timeout)
+            .is_ok());
+        assert!(session
+            .send_and_sync(Box::new(Event::new_simple("leave")), timeout)
+            .is_ok());
+        let snapshot = session.take_snapshot(timeout).unwrap();
+        let _ = session
+            .sender
+            .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+        assert!(session.wait_for_completion(timeout).is_some());
+
+        assert_eq!(snapshot.configuration, vec!["Outside".to_string()]);
+        assert_eq!(
+            snapshot.history,
+            vec![("H".to_string(), vec!["A2".to_string()])]
+        );
+        // ECMAScript numbers are stored as double.
+        assert_eq!(
+            snapshot.data,
+            vec![("counter".to_string(), SnapshotData::Double(1f64))]
+        );
+
+        #[cfg(feature = "json-config")]
+        let snapshot = {
+            let json = serde_json::to_string(&snapshot).unwrap();
+            serde_json::from_str(json.as_str()).unwrap()
+        };
+
+        // "onentry" of "Outside" shall not be executed again, "counter" stays 1.
+        let session = start_fsm_from_snapshot(
+            scxml_reader::parse_from_xml(DEEP_HISTORY_FSM.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            snapshot,
+            FinishMode::KEEP_CONFIGURATION,
+        )
+        .unwrap();
+        let _ = session.sender.send(Box::new(Event::new_simple("back")));
+        let _ = session.sender.send(Box::new(Event::new_simple("check")));
+        let result = session.wait_for_completion(timeout);
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn unknown_states_are_rejected() {
+        let mut snapshot = crate::fsm_snapshot::FsmSnapshot::default();
+        snapshot.configuration.push("NotThere".to_string());
+        assert!(start_fsm_from_snapshot(
+            scxml_reader::parse_from_xml(DEEP_HISTORY_FSM.to_string()).unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            snapshot,
+            FinishMode::DISPOSE,
+        )
+        .is_err());
+    }
+}
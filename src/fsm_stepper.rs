@@ -0,0 +1,405 @@
+//! Step-driven execution of a FSM on the caller's thread.\
+//! Used to embed a FSM into an existing event loop, e.g. of a GUI or a game.
+//! In contrast to [crate::fsm::start_fsm] no worker thread is started and the FSM never blocks
+//! to wait for external events. Instead, the caller drives the FSM via [FsmStepper::step].
+//!
+//! Events sent to the session by other parties (e.g. via the I/O processors, delayed \<send\> or
+//! invoked sessions) are put into the external queue and processed by [FsmStepper::step] if no
+//! event is given.
+
+use std::ops::DerefMut;
+use std::sync::Arc;
+
+use crate::actions::ActionWrapper;
+use crate::datamodel::Datamodel;
+use crate::fsm::{
+    complete_session, create_session, create_session_datamodel, Event, FinishMode, Fsm, ParamPair, ScxmlSession,
+    SessionCompletion, StateId, StepRecord,
+};
+use crate::fsm_executor::FsmExecutor;
+use crate::get_global;
+
+/// The result of one step.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StepResult {
+    /// False if the FSM has finished.
+    pub running: bool,
+    /// Names of the states that were entered, in entry order.
+    pub entered: Vec<String>,
+    /// Names of the states that were exited, in exit order.
+    pub exited: Vec<String>,
+}
+
+/// Handle to a FSM that is executed on the caller's thread.\
+/// Created by [start_fsm_stepper].
+pub struct FsmStepper {
+    fsm: Box<Fsm>,
+    datamodel: Box<dyn Datamodel>,
+    session: ScxmlSession,
+    completion: Arc<SessionCompletion>,
+    started: bool,
+    finished: bool,
+}
+
+/// Creates a step-driven FSM. The FSM is started by the first call of [FsmStepper::step].\
+/// The session is registered at the executor, so it can be reached by other sessions like a threaded one.
+pub fn start_fsm_stepper(
+    sm: Box<Fsm>,
+    actions: ActionWrapper,
+    executor: Box<FsmExecutor>,
+    data: &[ParamPair],
+    finish_mode: FinishMode,
+) -> FsmStepper {
+    let mut sm = sm;
+    let (session, external_queue, options) = create_session(actions, &executor, finish_mode, false, &sm);
+    #[cfg(feature = "Metrics")]
+    {
+        sm.metrics = session.metrics.clone();
+    }
+    let datamodel = create_session_datamodel(
+        &mut sm,
+        session.global_data.clone(),
+        &options,
+        external_queue,
+        session.session_id,
+        executor,
+        data.to_vec(),
+    );
+    FsmStepper {
+        fsm: sm,
+        datamodel,
+        completion: session.completion.clone(),
+        session,
+        started: false,
+        finished: false,
+    }
+}
+
+impl FsmStepper {
+    /// The session of the FSM. Can be used to get the session-id, to send events to the external
+    /// queue or to wait for the result after the FSM has finished.\
+    /// The session has no worker thread.
+    pub fn session(&self) -> &ScxmlSession {
+        &self.session
+    }
+
+    /// True if the FSM has not finished yet.
+    pub fn is_running(&self) -> bool {
+        !self.finished
+    }
+
+    /// Performs one macrostep.
+    /// + The first call starts the FSM and enters the initial configuration. A given event is put
+    ///   into the external queue and handled by the next call.
+    /// + If an event is given, it is processed as external event.
+    /// + Otherwise the next pending event of the external queue is processed, if any.
+    ///
+    /// Returns immediately if there is nothing to do or if the FSM has already finished.
+    pub fn step(&mut self, event: Option<Event>) -> StepResult {
+        if self.finished {
+            return StepResult::default();
+        }
+        get_global!(self.datamodel).step_record = Some(StepRecord::default());
+
+        if !self.started {
+            self.started = true;
+            if let Some(event) = event {
+                let _ = self.session.sender.send(Box::new(event));
+            }
+            if self
+                .fsm
+                .enter_initial_configuration(self.datamodel.deref_mut())
+            {
+                self.fsm.complete_macrostep(self.datamodel.deref_mut());
+            } else {
+                get_global!(self.datamodel).running = false;
+            }
+        } else {
+            let event = match event {
+                Some(event) => Some(Box::new(event)),
+                None => self
+                    .fsm
+                    .try_dequeue_external_event(self.datamodel.deref_mut()),
+            };
+            if let Some(event) = event {
+                self.fsm
+                    .process_external_event(self.datamodel.deref_mut(), event);
+                self.fsm.complete_macrostep(self.datamodel.deref_mut());
+            }
+        }
+
+        let running = get_global!(self.datamodel).running;
+        if !running {
+            self.finished = true;
+            self.fsm.exit_session(self.datamodel.deref_mut());
+            complete_session(self.datamodel.deref_mut(), &self.completion);
+            self.completion.set_exited();
+        }
+        let record = get_global!(self.datamodel)
+            .step_record
+            .take()
+            .unwrap_or_default();
+        StepResult {
+            running,
+            entered: self.state_names(&record.entered),
+            exited: self.state_names(&record.exited),
+        }
+    }
+
+    /// Processes the events of the external queue, one step per event, until the queue is empty
+    /// or the FSM has finished. Starts the FSM if needed.\
+    /// Returns the results of the steps.
+    pub fn run_pending(&mut self) -> Vec<StepResult> {
+        let mut results = Vec::new();
+        if !self.started {
+            results.push(self.step(None));
+        }
+        while !self.finished {
+            match self
+                .fsm
+                .try_dequeue_external_event(self.datamodel.deref_mut())
+            {
+                Some(event) => results.push(self.step(Some(*event))),
+                None => break,
+            }
+        }
+        results
+    }
+
+    /// Names of the active states, in document order.\
+    /// After the FSM has finished, the final configuration is returned (only available if started
+    /// with [FinishMode::KEEP_CONFIGURATION]).
+    pub fn configuration(&self) -> Vec<String> {
+        if self.finished {
+            return self
+                .completion
+                .get_result()
+                .and_then(|result| result.final_configuration)
+                .unwrap_or_default();
+        }
+        let mut states: Vec<StateId> = self
+            .datamodel
+            .global_s()
+            .lock()
+            .unwrap()
+            .configuration
+            .iterator()
+            .cloned()
+            .collect();
+        states.sort_by_key(|sid| self.fsm.get_state_by_id(*sid).doc_id);
+        self.state_names(&states)
+    }
+
+    fn state_names(&self, states: &[StateId]) -> Vec<String> {
+        states
+            .iter()
+            .map(|sid| self.fsm.get_state_by_id(*sid).name.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::sync::mpsc::TrySendError;
+    #[cfg(feature = "xml")]
+    use std::thread;
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{Event, EventType, FinishMode, OverflowPolicy, EVENT_SYNC_SESSION};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::fsm_stepper::{start_fsm_stepper, FsmStepper};
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn fsm_is_driven_by_steps() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Off' datamodel='ecmascript'>
+              <datamodel><data id='switched' expr='0'/></datamodel>
+              <state id='Off'>
+                <transition event='toggle' target='On'>
+                  <assign location='switched' expr='switched + 1'/>
+                </transition>
+              </state>
+              <state id='On'>
+                <onentry><raise event='check'/></onentry>
+                <transition event='check' cond='switched > 1' target='Done'/>
+                <transition event='toggle' target='Off'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let mut stepper = start_fsm_stepper(
+            sm.unwrap(),
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+
+        let result = stepper.step(None);
+        assert!(result.running);
+        assert_eq!(result.entered, vec!["Off".to_string()]);
+        assert!(result.exited.is_empty());
+
+        // Nothing to do.
+        let result = stepper.step(None);
+        assert!(result.running);
+        assert!(result.entered.is_empty());
+
+        let result = stepper.step(Some(Event::new_simple("toggle")));
+        assert!(result.running);
+        assert_eq!(result.exited, vec!["Off".to_string()]);
+        assert_eq!(result.entered, vec!["On".to_string()]);
+
+        // Events in the external queue are processed one per step.
+        let _ = stepper
+            .session()
+            .sender
+            .send(Box::new(Event::new_simple("toggle")));
+        let _ = stepper
+            .session()
+            .sender
+            .send(Box::new(Event::new_simple("toggle")));
+        let result = stepper.step(None);
+        assert_eq!(result.entered, vec!["Off".to_string()]);
+        assert!(stepper.is_running());
+
+        // The internal "check" event is processed in the same macrostep.
+        let result = stepper.step(None);
+        assert!(!result.running);
+        assert_eq!(result.entered, vec!["On".to_string(), "Done".to_string()]);
+        assert_eq!(
+            result.exited,
+            vec!["Off".to_string(), "On".to_string(), "Done".to_string()]
+        );
+        assert!(!stepper.is_running());
+        assert_eq!(
+            stepper
+                .session()
+                .completion
+                .get_result()
+                .unwrap()
+                .final_configuration,
+            Some(vec!["Done".to_string()])
+        );
+
+        let result = stepper.step(Some(Event::new_simple("toggle")));
+        assert!(!result.running);
+    }
+
+    #[cfg(feature = "xml")]
+    fn start_bounded(policy: OverflowPolicy) -> FsmStepper {
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_queue_limit(2, policy);
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='null'>
+              <state id='Main'>
+                <transition event='e1' type='internal' target='E1'/>
+                <transition event='e2' type='internal' target='E2'/>
+                <transition event='e3' type='internal' target='E3'/>
+                <state id='Start'/>
+                <state id='E1'/>
+                <state id='E2'/>
+                <state id='E3'/>
+              </state>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let mut stepper = start_fsm_stepper(
+            sm,
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        stepper.step(None);
+        stepper
+    }
+
+    /// Processes the queued events and returns the entered states.
+    #[cfg(feature = "xml")]
+    fn drain(stepper: &mut FsmStepper) -> Vec<String> {
+        let mut entered = Vec::new();
+        for _ in 0..4 {
+            entered.extend(stepper.step(None).entered);
+        }
+        entered
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn bounded_queue_applies_overflow_policy() {
+        let send = |stepper: &FsmStepper, name: &str| {
+            stepper
+                .session()
+                .sender
+                .send(Box::new(Event::new_simple(name)))
+        };
+
+        let mut stepper = start_bounded(OverflowPolicy::DropNewest);
+        for name in ["e1", "e2", "e3"] {
+            assert!(send(&stepper, name).is_ok());
+        }
+        assert_eq!(stepper.session().dropped_events(), 1);
+        assert_eq!(
+            drain(&mut stepper),
+            vec!["E1".to_string(), "E2".to_string()]
+        );
+
+        let mut stepper = start_bounded(OverflowPolicy::DropOldest);
+        for name in ["e1", "e2", "e3"] {
+            assert!(send(&stepper, name).is_ok());
+        }
+        assert_eq!(stepper.session().dropped_events(), 1);
+        assert_eq!(
+            drain(&mut stepper),
+            vec!["E2".to_string(), "E3".to_string()]
+        );
+        // The dropped event is not counted as pending.
+        #[cfg(feature = "Metrics")]
+        assert_eq!(stepper.session().metrics().max_external_queue_len, 2);
+
+        let mut stepper = start_bounded(OverflowPolicy::RaiseError);
+        assert!(send(&stepper, "e1").is_ok());
+        assert!(send(&stepper, "e2").is_ok());
+        assert!(matches!(send(&stepper, "e3"), Err(TrySendError::Full(_))));
+        assert_eq!(stepper.session().dropped_events(), 1);
+        assert_eq!(
+            drain(&mut stepper),
+            vec!["E1".to_string(), "E2".to_string()]
+        );
+
+        let mut stepper = start_bounded(OverflowPolicy::Block);
+        assert!(send(&stepper, "e1").is_ok());
+        assert!(send(&stepper, "e2").is_ok());
+        let sender = stepper.session().sender.clone();
+        let blocked = thread::spawn(move || sender.send(Box::new(Event::new_simple("e3"))).is_ok());
+        thread::sleep(Duration::from_millis(200));
+        assert!(!blocked.is_finished(), "Sender shall wait for space");
+        // Platform events are not blocked.
+        let mut sync = Event::new_simple(EVENT_SYNC_SESSION);
+        sync.etype = EventType::platform;
+        assert!(stepper.session().sender.send(Box::new(sync)).is_ok());
+        assert_eq!(stepper.step(None).entered, vec!["E1".to_string()]);
+        assert!(blocked.join().unwrap());
+        assert_eq!(
+            drain(&mut stepper),
+            vec!["E2".to_string(), "E3".to_string()]
+        );
+        assert_eq!(stepper.session().dropped_events(), 0);
+    }
+}
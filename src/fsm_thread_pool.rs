@@ -0,0 +1,297 @@
+//! Execution of FSM sessions on a bounded number of worker threads.\
+//! Used by [crate::fsm_executor::FsmExecutor] if created via
+//! [crate::fsm_executor::FsmExecutor::with_thread_pool].
+//!
+//! Instead of blocking on the external queue, a pooled session processes all pending external
+//! events and then parks. Each send to the external queue of a parked session wakes it up via
+//! its [SessionWaker], which puts the session into the job queue of its worker again.
+//!
+//! A session is bound to the worker that started it, because some data models (e.g. ECMAScript)
+//! can't be moved to other threads. New sessions are assigned to the worker with the least sessions.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+#[cfg(feature = "Debug")]
+use log::debug;
+use log::error;
+
+use crate::fsm::SessionId;
+
+/// A session that is executed by the pool.
+pub(crate) trait PooledTask {
+    /// Processes everything that is pending for the session.\
+    /// Shall return without blocking. Returns true if the session has finished.
+    fn run(&mut self) -> bool;
+}
+
+/// Creates the task of a session. Called by the worker the session is bound to.
+pub(crate) type PooledTaskFactory = Box<dyn FnOnce() -> Box<dyn PooledTask> + Send>;
+
+enum Job {
+    Start(Arc<PooledSession>, PooledTaskFactory),
+    Run(Arc<PooledSession>),
+}
+
+#[derive(Default)]
+struct RunState {
+    /// A run-job is in the job queue.
+    scheduled: bool,
+    /// The task is currently executed.
+    running: bool,
+    /// The session was woken up while running.
+    notified: bool,
+}
+
+struct PooledSession {
+    session_id: SessionId,
+    worker: Arc<Worker>,
+    state: Mutex<RunState>,
+}
+
+impl PooledSession {
+    fn wake(self: &Arc<Self>) {
+        let mut state = self.state.lock().unwrap();
+        if state.running {
+            state.notified = true;
+        } else if !state.scheduled {
+            state.scheduled = true;
+            drop(state);
+            self.worker.push(Job::Run(self.clone()));
+        }
+    }
+}
+
+/// Wakes up a parked session of the pool if an event was sent to it.
+#[derive(Clone)]
+pub struct SessionWaker {
+    session: Arc<PooledSession>,
+}
+
+impl SessionWaker {
+    pub fn wake(&self) {
+        self.session.wake();
+    }
+
+    /// Starts the session on its worker.
+    pub(crate) fn start(&self, factory: PooledTaskFactory) {
+        self.session
+            .worker
+            .push(Job::Start(self.session.clone(), factory));
+    }
+}
+
+impl Debug for SessionWaker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionWaker")
+            .field("session_id", &self.session.session_id)
+            .finish()
+    }
+}
+
+#[derive(Default)]
+struct Worker {
+    jobs: Mutex<VecDeque<Job>>,
+    condition: Condvar,
+    /// Number of sessions that are bound to this worker and not finished.
+    sessions: AtomicUsize,
+    shutdown: AtomicBool,
+}
+
+impl Worker {
+    fn push(&self, job: Job) {
+        self.jobs.lock().unwrap().push_back(job);
+        self.condition.notify_one();
+    }
+
+    fn next_job(&self) -> Option<Job> {
+        let mut jobs = self.jobs.lock().unwrap();
+        loop {
+            if let Some(job) = jobs.pop_front() {
+                return Some(job);
+            }
+            if self.shutdown.load(Ordering::Relaxed) {
+                return None;
+            }
+            jobs = self.condition.wait(jobs).unwrap();
+        }
+    }
+
+    fn work(&self) {
+        let mut tasks: HashMap<SessionId, Box<dyn PooledTask>> = HashMap::new();
+        while let Some(job) = self.next_job() {
+            let session = match job {
+                Job::Start(session, factory) => match panic::catch_unwind(AssertUnwindSafe(factory)) {
+                    Ok(task) => {
+                        tasks.insert(session.session_id, task);
+                        session
+                    }
+                    Err(_) => {
+                        error!("Failed to start session #{}", session.session_id);
+                        self.sessions.fetch_sub(1, Ordering::Relaxed);
+                        continue;
+                    }
+                },
+                Job::Run(session) => session,
+            };
+            self.execute(&session, &mut tasks);
+        }
+        #[cfg(feature = "Debug")]
+        debug!("Pool worker stopped with {} sessions", tasks.len());
+    }
+
+    fn execute(&self, session: &Arc<PooledSession>, tasks: &mut HashMap<SessionId, Box<dyn PooledTask>>) {
+        {
+            let mut state = session.state.lock().unwrap();
+            state.scheduled = false;
+            state.running = true;
+            state.notified = false;
+        }
+        let finished = match tasks.get_mut(&session.session_id) {
+            // The session was woken up before it was started, the start job will handle the events.
+            None => false,
+            // Keep the worker alive if a task panics, the task is dropped.
+            Some(task) => panic::catch_unwind(AssertUnwindSafe(|| task.run())).unwrap_or_else(|_| {
+                error!("Session #{} panicked", session.session_id);
+                true
+            }),
+        };
+        if finished {
+            tasks.remove(&session.session_id);
+            self.sessions.fetch_sub(1, Ordering::Relaxed);
+        }
+        let mut state = session.state.lock().unwrap();
+        state.running = false;
+        if state.notified && !finished {
+            state.scheduled = true;
+            drop(state);
+            self.push(Job::Run(session.clone()));
+        }
+    }
+}
+
+/// A fixed number of worker threads that execute FSM sessions.
+pub struct FsmThreadPool {
+    workers: Vec<Arc<Worker>>,
+}
+
+impl FsmThreadPool {
+    /// Starts the worker threads. At least one thread is started.
+    pub fn new(threads: usize) -> FsmThreadPool {
+        let mut workers = Vec::new();
+        for idx in 0..threads.max(1) {
+            let worker = Arc::new(Worker::default());
+            let thread_worker = worker.clone();
+            thread::Builder::new()
+                .name(format!("fsm_pool_{}", idx))
+                .spawn(move || thread_worker.work())
+                .unwrap();
+            workers.push(worker);
+        }
+        FsmThreadPool { workers }
+    }
+
+    /// Number of worker threads.
+    pub fn size(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Number of sessions that are started and not finished.
+    pub fn session_count(&self) -> usize {
+        self.workers
+            .iter()
+            .map(|w| w.sessions.load(Ordering::Relaxed))
+            .sum()
+    }
+
+    /// Binds a new session to the worker with the least sessions.
+    pub(crate) fn register(&self, session_id: SessionId) -> SessionWaker {
+        let worker = self
+            .workers
+            .iter()
+            .min_by_key(|w| w.sessions.load(Ordering::Relaxed))
+            .unwrap()
+            .clone();
+        worker.sessions.fetch_add(1, Ordering::Relaxed);
+        SessionWaker {
+            session: Arc::new(PooledSession {
+                session_id,
+                worker,
+                state: Mutex::new(RunState::default()),
+            }),
+        }
+    }
+
+    /// Stops the workers after all queued jobs are done.
+    /// Sessions that are still parked are dropped.
+    pub fn shutdown(&self) {
+        for worker in &self.workers {
+            worker.shutdown.store(true, Ordering::Relaxed);
+            // Lock to ensure the worker is either waiting or will see the flag.
+            let _jobs = worker.jobs.lock().unwrap();
+            worker.condition.notify_all();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm, Event};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn pool_executes_many_sessions() {
+        let executor = FsmExecutor::with_thread_pool(4);
+        let mut sessions = Vec::new();
+        for _ in 0..1000 {
+            let fsm = scxml_reader::parse_from_xml(
+                r##"<scxml initial='Wait' datamodel='null'>
+                  <state id='Wait'>
+                    <transition event='go' target='Done'/>
+                  </state>
+                  <final id='Done'/>
+                </scxml>"##
+                    .to_string(),
+            )
+            .unwrap();
+            sessions.push(start_fsm(
+                fsm,
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+            ));
+        }
+        assert!(sessions.iter().all(|s| s.thread.is_none()));
+
+        for session in &sessions {
+            session
+                .sender
+                .send(Box::new(Event::new_simple("go")))
+                .unwrap();
+        }
+        for session in &sessions {
+            let result = session.wait_for_completion(Duration::from_secs(30));
+            assert!(
+                result.is_some(),
+                "Session {} shall finish",
+                session.session_id
+            );
+            assert!(result.unwrap().final_state_reached);
+        }
+    }
+}
@@ -0,0 +1,188 @@
+//! Invoke handlers base.\
+//! Handlers start the external services requested by \<invoke\>.\
+//! See [W3C:invoke](/doc/W3C_SCXML_2024_07_13/index.html#invoke).
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+
+use crate::actions::ActionWrapper;
+use crate::datamodel::ToAny;
+use crate::fsm::{InvokeId, ParamPair, ScxmlSession, SessionId};
+use crate::fsm_executor::FsmExecutor;
+#[cfg(feature = "Trace")]
+use crate::tracer::TraceMode;
+
+/// The evaluated arguments of an \<invoke\> element.
+pub struct InvokeParameters {
+    /// The invokeid (given or generated).
+    pub invoke_id: InvokeId,
+    /// The evaluated 'type' or 'typeexpr'.
+    pub type_name: String,
+    /// The evaluated 'src' or 'srcexpr'. Empty if \<content\> shall be used.
+    pub src: String,
+    /// The evaluated \<content\> child, if 'src' is not given.
+    pub content: Option<String>,
+    /// The values of 'namelist' and the \<param\> children.
+    pub params: Vec<ParamPair>,
+    /// Names of the \<param\> children whose values shall be captured when the invoked session completes,
+    /// see [crate::fsm::Parameter::write_back].
+    pub write_back: Vec<String>,
+    /// Session-id of the invoking session.
+    pub parent_session_id: SessionId,
+    /// The actions of the invoking session.
+    pub actions: ActionWrapper,
+    /// The datamodel options of the invoking session.
+    pub options: HashMap<String, String>,
+    /// The trace modes of the invoking session, or the modes set by
+    /// [FsmExecutor::set_invoke_trace_modes] for this invoke id.
+    #[cfg(feature = "Trace")]
+    pub trace_modes: Vec<TraceMode>,
+}
+
+/// Reason why an invoked service can't be started.\
+/// The invoking session gets "error.communication" for [InvokeError::Retrieval], otherwise "error.execution".
+/// The message is given as data of the event.
+#[derive(Debug, Clone, PartialEq)]
+pub enum InvokeError {
+    /// The document of the service can't be retrieved, e.g. because the file doesn't exist.
+    Retrieval(String),
+    /// The document of the service is not valid.
+    Parse(String),
+    /// Other errors, e.g. if the [crate::fsm_executor::InvokeLimits] are exceeded.
+    Execution(String),
+}
+
+impl Display for InvokeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InvokeError::Retrieval(message) | InvokeError::Parse(message) | InvokeError::Execution(message) => {
+                f.write_str(message)
+            }
+        }
+    }
+}
+
+impl From<String> for InvokeError {
+    fn from(message: String) -> Self {
+        InvokeError::Execution(message)
+    }
+}
+
+/// Trait for Invoke Handlers.\
+/// Handlers are registered at the [FsmExecutor] for the types they support.
+/// The SCXML type is handled by [crate::scxml_invoke_handler::ScxmlInvokeHandler].
+pub trait InvokeHandler: ToAny + Debug + Send {
+    /// Returns the invoke types of this handler.
+    fn get_types(&self) -> &[&str];
+
+    /// Starts the invoked service.\
+    /// The returned session is used to communicate with the service and to cancel it.
+    fn invoke(&mut self, executor: &mut FsmExecutor, parameters: InvokeParameters)
+        -> Result<ScxmlSession, InvokeError>;
+
+    /// Cancels a service that was started by this handler.
+    fn cancel(&mut self, session: &ScxmlSession);
+
+    fn get_copy(&self) -> Box<dyn InvokeHandler>;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::channel;
+    use std::sync::Arc;
+
+    use crate::fsm::{Event, ScxmlSession};
+    use crate::fsm_executor::FsmExecutor;
+    use crate::invoke_handler::{InvokeError, InvokeHandler, InvokeParameters};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::test::run_test_manual_with_executor;
+    #[cfg(feature = "Trace")]
+    use crate::tracer::TraceMode;
+
+    const MOCK_TYPES: &[&str] = &["mock"];
+
+    /// Handler that only informs the parent session.
+    #[derive(Debug, Clone)]
+    struct MockInvokeHandler {
+        cancelled: Arc<AtomicBool>,
+    }
+
+    impl InvokeHandler for MockInvokeHandler {
+        fn get_types(&self) -> &[&str] {
+            MOCK_TYPES
+        }
+
+        fn invoke(
+            &mut self,
+            executor: &mut FsmExecutor,
+            parameters: InvokeParameters,
+        ) -> Result<ScxmlSession, InvokeError> {
+            let mut event = Event::new_simple(format!("mock.{}", parameters.src).as_str());
+            event.invoke_id = Some(parameters.invoke_id.clone());
+            if let Some(sender) = executor.get_session_sender(parameters.parent_session_id) {
+                let _ = sender.send(Box::new(event));
+            }
+            let (sender, _receiver) = channel();
+            Ok(ScxmlSession::new_without_join_handle(0, sender.into()))
+        }
+
+        fn cancel(&mut self, _session: &ScxmlSession) {
+            self.cancelled.store(true, Ordering::Relaxed);
+        }
+
+        fn get_copy(&self) -> Box<dyn InvokeHandler> {
+            Box::new(self.clone())
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn custom_invoke_handler_is_used() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Unknown' datamodel='ecmascript'>
+              <state id='Unknown'>
+                <invoke type='unknownType' src='started'/>
+                <transition event='error.execution' target='Main'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Main'>
+                <invoke type='mock' src='started'/>
+                <transition event='mock.started' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        );
+        assert!(sm.is_ok(), "FSM shall be parsed");
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.add_invoke_handler(Box::new(MockInvokeHandler {
+            cancelled: cancelled.clone(),
+        }));
+
+        assert!(run_test_manual_with_executor(
+            "custom_invoke_handler_is_used",
+            executor,
+            sm.unwrap(),
+            #[cfg(feature = "Trace")]
+            TraceMode::STATES,
+            2000u64,
+            &vec!["pass".to_string()],
+            |_sender| {},
+        ));
+        assert!(
+            cancelled.load(Ordering::Relaxed),
+            "Invoke shall be cancelled by the handler"
+        );
+    }
+}
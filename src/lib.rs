@@ -8,35 +8,51 @@ extern crate core;
 
 #[cfg(feature = "EnvLog")]
 use chrono::Local;
+#[cfg(feature = "Trace")]
+#[cfg(feature = "Trace")]
+use log::error;
 use std::collections::HashMap;
 use std::env;
 #[cfg(feature = "EnvLog")]
 use std::io::Write;
 #[cfg(feature = "Trace")]
 use std::str::FromStr;
-#[cfg(feature = "Trace")]
-use std::sync::mpsc::Sender;
-
-#[cfg(feature = "Trace")]
-use log::error;
 
 #[cfg(feature = "Trace")]
 use tracer::TraceMode;
 
+#[cfg(feature = "Trace")]
+use crate::fsm::EventSender;
 use crate::fsm::{Event, EventType};
 
 #[cfg(feature = "xml")]
 pub mod scxml_reader;
 
+pub mod analysis;
+pub mod clock;
+pub mod document_cache;
+pub mod event_recorder;
+pub mod event_validator;
 pub mod executable_content;
 pub mod fsm;
+pub mod fsm_builder;
 pub mod fsm_executor;
+pub mod fsm_snapshot;
+pub mod fsm_stepper;
+pub mod fsm_thread_pool;
+pub mod log_sink;
+pub mod validation;
+pub mod visualize;
 
 #[cfg(feature = "ECMAScript")]
 pub mod ecma_script_datamodel;
+#[cfg(feature = "ECMAScript")]
+pub mod ecma_script_shared_context;
 
 #[cfg(feature = "BasicHttpEventIOProcessor")]
 pub mod basic_http_event_io_processor;
+#[cfg(feature = "BasicHttpEventIOProcessor")]
+pub mod basic_http_invoke_handler;
 
 pub mod scxml_event_io_processor;
 
@@ -45,6 +61,8 @@ pub mod serializer;
 
 pub mod datamodel;
 pub mod event_io_processor;
+pub mod invoke_handler;
+pub mod scxml_invoke_handler;
 
 #[cfg(feature = "Trace")]
 pub mod tracer;
@@ -52,12 +70,24 @@ pub mod tracer;
 #[cfg(feature = "TraceServer")]
 pub mod remote_tracer;
 
+#[cfg(feature = "async")]
+pub mod async_session;
+
+#[cfg(feature = "json-config")]
+pub mod scenario;
+
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "Metrics")]
+pub mod metrics;
+
 pub mod actions;
 pub mod expression_engine;
 pub mod test;
 
 #[cfg(feature = "Trace")]
-pub fn handle_trace(sender: &mut Sender<Box<Event>>, opt: &str, enable: bool) {
+pub fn handle_trace(sender: &mut EventSender, opt: &str, enable: bool) {
     match TraceMode::from_str(opt) {
         Ok(t) => {
             let event = Box::new(Event::trace(t, enable));
@@ -210,5 +240,13 @@ pub fn get_features() -> Vec<&'static str> {
         "Trace_Event",
         #[cfg(feature = "Debug")]
         "Debug",
+        #[cfg(feature = "async")]
+        "async",
+        #[cfg(feature = "wasm")]
+        "wasm",
+        #[cfg(feature = "Metrics")]
+        "Metrics",
+        #[cfg(feature = "MetricsExport")]
+        "MetricsExport",
     ]
 }
@@ -0,0 +1,115 @@
+//! Output of \<log\> elements and [crate::datamodel::Datamodel::log].\
+//! By default, messages are written via the log crate at level "info", with the target
+//! "scxml.session.\<id\>" of the session (see [session_log_target]). So the output of a session
+//! can be filtered, e.g. with "RUST_LOG=scxml.session.2=info".\
+//! Applications that need the messages can register a [LogSink] via
+//! [crate::fsm_executor::FsmExecutor::set_log_sink]. If a sink is set, it receives the messages instead.
+
+#[cfg(feature = "EnvLog")]
+use log::info;
+
+use crate::fsm::SessionId;
+
+/// Prefix of the log targets of sessions.
+pub const SESSION_LOG_TARGET_PREFIX: &str = "scxml.session.";
+
+/// Receives the messages of \<log\> elements.
+pub trait LogSink: Send + Sync {
+    /// Called for each message. "label" is the value of the "label" attribute, empty if not set.
+    fn log(&self, session: SessionId, label: &str, msg: &str);
+}
+
+/// Gets the log target of a session.
+pub fn session_log_target(session: SessionId) -> String {
+    format!("{}{}", SESSION_LOG_TARGET_PREFIX, session)
+}
+
+/// Passes a message to the sink or, if no sink is given, to the log crate.
+pub fn log_message(sink: Option<&dyn LogSink>, session: SessionId, label: &str, msg: &str) {
+    match sink {
+        Some(sink) => sink.log(session, label, msg),
+        None => {
+            let text = if label.is_empty() {
+                msg.to_string()
+            } else {
+                format!("{}: {}", label, msg)
+            };
+            #[cfg(feature = "EnvLog")]
+            info!(target: session_log_target(session).as_str(), "{}", text);
+            #[cfg(not(feature = "EnvLog"))]
+            println!("[{}] {}", session_log_target(session), text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    #[cfg(all(feature = "xml", feature = "ECMAScript"))]
+    fn log_messages_are_attributed_to_sessions() {
+        use std::sync::{Arc, Mutex};
+        use std::time::Duration;
+
+        use crate::actions::ActionWrapper;
+        use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode, SessionId};
+        use crate::fsm_executor::FsmExecutor;
+        use crate::log_sink::LogSink;
+        use crate::scxml_reader;
+
+        #[derive(Default)]
+        struct CollectingSink {
+            messages: Mutex<Vec<(SessionId, String, String)>>,
+        }
+
+        impl LogSink for CollectingSink {
+            fn log(&self, session: SessionId, label: &str, msg: &str) {
+                self.messages
+                    .lock()
+                    .unwrap()
+                    .push((session, label.to_string(), msg.to_string()));
+            }
+        }
+
+        let xml = r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <log label='entered' expr="'session ' + _sessionid"/>
+                  <log expr="'no label'"/>
+                </onentry>
+                <transition target='done'/>
+              </state>
+              <final id='done'/>
+            </scxml>"##;
+
+        let sink = Arc::new(CollectingSink::default());
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_log_sink(sink.clone());
+
+        let mut session_ids = Vec::new();
+        for _ in 0..2 {
+            let session = start_fsm_with_data_and_finish_mode(
+                scxml_reader::parse_from_xml(xml.to_string()).unwrap(),
+                ActionWrapper::new(),
+                Box::new(executor.clone()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            assert!(session
+                .wait_for_completion(Duration::from_millis(2000))
+                .is_some());
+            session_ids.push(session.session_id);
+        }
+
+        let messages = sink.messages.lock().unwrap();
+        let mut expected = Vec::new();
+        for session_id in session_ids {
+            expected.push((
+                session_id,
+                "entered".to_string(),
+                format!("session {}", session_id),
+            ));
+            expected.push((session_id, "".to_string(), "no label".to_string()));
+        }
+        assert_eq!(*messages, expected);
+    }
+}
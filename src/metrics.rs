@@ -0,0 +1,299 @@
+//! Counters and timings of sessions for monitoring (feature "Metrics").\
+//! Each session updates its own [FsmMetrics]. The values are available per session via
+//! [crate::fsm::ScxmlSession::metrics] and summed up over all sessions via
+//! [crate::fsm_executor::FsmExecutor::metrics].
+//!
+//! All values are updated with relaxed atomics by the thread that executes the session. Without the
+//! feature, no code is generated for them.\
+//! With feature "MetricsExport" a [MetricsSnapshot] can be passed to the "metrics" facade crate,
+//! see `MetricsSnapshot::export`.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// The live counters of one session.
+#[derive(Debug, Default)]
+pub struct FsmMetrics {
+    internal_events: AtomicU64,
+    external_events: AtomicU64,
+    transitions: AtomicU64,
+    microsteps: AtomicU64,
+    macrosteps: AtomicU64,
+    max_microsteps_per_macrostep: AtomicU64,
+    condition_nanos: AtomicU64,
+    content_nanos: AtomicU64,
+    max_internal_queue_len: AtomicU64,
+    max_external_queue_len: AtomicU64,
+    /// Values of "microsteps" and "external_events" at the end of the last macrostep.
+    macrostep_end: [AtomicU64; 2],
+}
+
+/// Copy of the counters, see [FsmMetrics::snapshot].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MetricsSnapshot {
+    /// Processed events of the internal queue.
+    pub internal_events: u64,
+    /// Processed events of the external queue. Platform events (e.g. to synchronize or to trace
+    /// the session) are not counted.
+    pub external_events: u64,
+    /// Taken transitions.
+    pub transitions: u64,
+    pub microsteps: u64,
+    pub macrosteps: u64,
+    /// The maximal number of microsteps of one macrostep.
+    pub max_microsteps_per_macrostep: u64,
+    /// Time spent in the evaluation of transition conditions.
+    pub condition_time: Duration,
+    /// Time spent in the execution of executable content.
+    pub content_time: Duration,
+    /// The maximal length of the internal queue.
+    pub max_internal_queue_len: u64,
+    /// The maximal length of the external queue.
+    pub max_external_queue_len: u64,
+    /// Sessions that have not finished yet. Only set by [crate::fsm_executor::FsmExecutor::metrics].
+    pub active_sessions: u64,
+}
+
+impl FsmMetrics {
+    pub fn new() -> FsmMetrics {
+        FsmMetrics::default()
+    }
+
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            internal_events: self.internal_events.load(Ordering::Relaxed),
+            external_events: self.external_events.load(Ordering::Relaxed),
+            transitions: self.transitions.load(Ordering::Relaxed),
+            microsteps: self.microsteps.load(Ordering::Relaxed),
+            macrosteps: self.macrosteps.load(Ordering::Relaxed),
+            max_microsteps_per_macrostep: self.max_microsteps_per_macrostep.load(Ordering::Relaxed),
+            condition_time: Duration::from_nanos(self.condition_nanos.load(Ordering::Relaxed)),
+            content_time: Duration::from_nanos(self.content_nanos.load(Ordering::Relaxed)),
+            max_internal_queue_len: self.max_internal_queue_len.load(Ordering::Relaxed),
+            max_external_queue_len: self.max_external_queue_len.load(Ordering::Relaxed),
+            active_sessions: 0,
+        }
+    }
+
+    /// Counts an event that was taken from the internal queue. "queue_len" is the length of the
+    /// queue before the event was removed.
+    pub(crate) fn internal_event(&self, queue_len: usize) {
+        self.internal_events.fetch_add(1, Ordering::Relaxed);
+        self.max_internal_queue_len
+            .fetch_max(queue_len as u64, Ordering::Relaxed);
+    }
+
+    /// Counts an event of the external queue that is processed by the FSM.
+    pub(crate) fn external_event(&self) {
+        self.external_events.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Records the length of the external queue before an event was removed.
+    pub(crate) fn external_queue_len(&self, queue_len: usize) {
+        self.max_external_queue_len
+            .fetch_max(queue_len as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn microstep(&self, transitions: usize) {
+        self.microsteps.fetch_add(1, Ordering::Relaxed);
+        self.transitions
+            .fetch_add(transitions as u64, Ordering::Relaxed);
+    }
+
+    /// Returns a guard that ends the macrostep if it is dropped.\
+    /// The microsteps of the external event that started the macrostep are taken before,
+    /// so all microsteps since the end of the last macrostep are counted.
+    pub(crate) fn macrostep(self: &Arc<Self>) -> MacrostepGuard {
+        MacrostepGuard {
+            metrics: self.clone(),
+        }
+    }
+
+    fn macrostep_done(&self) {
+        let microsteps = self.microsteps.load(Ordering::Relaxed);
+        let events = self.external_events.load(Ordering::Relaxed);
+        let microsteps = microsteps - self.macrostep_end[0].swap(microsteps, Ordering::Relaxed);
+        let events = events - self.macrostep_end[1].swap(events, Ordering::Relaxed);
+        // Platform events (e.g. to synchronize) don't start a macrostep.
+        if microsteps > 0 || events > 0 || self.macrosteps.load(Ordering::Relaxed) == 0 {
+            self.macrosteps.fetch_add(1, Ordering::Relaxed);
+            self.max_microsteps_per_macrostep
+                .fetch_max(microsteps, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn condition_time(&self, start: Instant) {
+        self.condition_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn content_time(&self, start: Instant) {
+        self.content_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+/// Counts a macrostep and its microsteps, see [FsmMetrics::macrostep].
+pub(crate) struct MacrostepGuard {
+    metrics: Arc<FsmMetrics>,
+}
+
+impl Drop for MacrostepGuard {
+    fn drop(&mut self) {
+        self.metrics.macrostep_done();
+    }
+}
+
+impl MetricsSnapshot {
+    /// Adds the values of the other snapshot. For maximal values the maximum is used.
+    pub fn add(&mut self, other: &MetricsSnapshot) {
+        self.internal_events += other.internal_events;
+        self.external_events += other.external_events;
+        self.transitions += other.transitions;
+        self.microsteps += other.microsteps;
+        self.macrosteps += other.macrosteps;
+        self.max_microsteps_per_macrostep = self
+            .max_microsteps_per_macrostep
+            .max(other.max_microsteps_per_macrostep);
+        self.condition_time += other.condition_time;
+        self.content_time += other.content_time;
+        self.max_internal_queue_len = self
+            .max_internal_queue_len
+            .max(other.max_internal_queue_len);
+        self.max_external_queue_len = self
+            .max_external_queue_len
+            .max(other.max_external_queue_len);
+        self.active_sessions += other.active_sessions;
+    }
+
+    /// Passes the values to the recorder of the "metrics" crate. Counters are set to the
+    /// absolute values, the other values are reported as gauges.\
+    /// The names start with "rfsm.", the labels are added to all values, e.g. the session id.
+    #[cfg(feature = "MetricsExport")]
+    pub fn export(&self, labels: &[(&'static str, String)]) {
+        let counters = [
+            ("rfsm.events.internal", self.internal_events),
+            ("rfsm.events.external", self.external_events),
+            ("rfsm.transitions", self.transitions),
+            ("rfsm.microsteps", self.microsteps),
+            ("rfsm.macrosteps", self.macrosteps),
+        ];
+        for (name, value) in counters {
+            metrics::counter!(name, labels).absolute(value);
+        }
+        let gauges = [
+            (
+                "rfsm.microsteps_per_macrostep.max",
+                self.max_microsteps_per_macrostep as f64,
+            ),
+            ("rfsm.condition.seconds", self.condition_time.as_secs_f64()),
+            ("rfsm.content.seconds", self.content_time.as_secs_f64()),
+            (
+                "rfsm.queue.internal.max",
+                self.max_internal_queue_len as f64,
+            ),
+            (
+                "rfsm.queue.external.max",
+                self.max_external_queue_len as f64,
+            ),
+            ("rfsm.sessions.active", self.active_sessions as f64),
+        ];
+        for (name, value) in gauges {
+            metrics::gauge!(name, labels).set(value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{Event, FinishMode};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::fsm_stepper::start_fsm_stepper;
+    #[cfg(feature = "xml")]
+    use crate::metrics::MetricsSnapshot;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn metrics_count_events_and_transitions() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A' datamodel='ecmascript'>
+              <state id='A'>
+                <onentry><raise event='i1'/><raise event='i2'/></onentry>
+                <transition event='i1' target='P'/>
+              </state>
+              <parallel id='P'>
+                <state id='R1' initial='R1a'>
+                  <state id='R1a'><transition event='e1' cond='true' target='R1b'/></state>
+                  <state id='R1b'><transition target='R1c'/></state>
+                  <state id='R1c'/>
+                </state>
+                <state id='R2' initial='R2a'>
+                  <state id='R2a'><transition event='e1' target='R2b'/></state>
+                  <state id='R2b'/>
+                </state>
+                <transition event='e2' target='Done'/>
+              </parallel>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        let executor = FsmExecutor::new_without_io_processor();
+        let mut stepper = start_fsm_stepper(
+            sm,
+            ActionWrapper::new(),
+            Box::new(executor.clone()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+
+        // Macrostep 1: "i1" selects one transition, "i2" none.
+        stepper.step(None);
+        // Macrostep 2: "e1" selects one transition in each region, followed by the eventless transition.
+        stepper.step(Some(Event::new_simple("e1")));
+        assert_eq!(executor.metrics().active_sessions, 1);
+        // Macrostep 3: "e2" finishes the FSM, "e3" is not processed.
+        for name in ["e2", "e3"] {
+            let _ = stepper
+                .session()
+                .sender
+                .send(Box::new(Event::new_simple(name)));
+        }
+        stepper.step(None);
+        assert!(!stepper.is_running());
+
+        let metrics = stepper.session().metrics();
+        assert!(metrics.condition_time > Duration::ZERO);
+        assert!(metrics.content_time > Duration::ZERO);
+        assert_eq!(
+            metrics,
+            MetricsSnapshot {
+                internal_events: 2,
+                external_events: 2,
+                transitions: 5,
+                microsteps: 4,
+                macrosteps: 3,
+                max_microsteps_per_macrostep: 2,
+                condition_time: metrics.condition_time,
+                content_time: metrics.content_time,
+                max_internal_queue_len: 2,
+                max_external_queue_len: 2,
+                active_sessions: 0,
+            }
+        );
+        // The finished session is still part of the sum of the executor.
+        assert_eq!(executor.metrics(), metrics);
+    }
+}
@@ -1,193 +1,939 @@
-use crate::fsm::{Event, State};
-use crate::tracer::{set_tracer_factory, TraceMode, Tracer, TracerFactory};
-use crate::ArgOption;
-use std::fmt::{Debug, Display};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+//! Remote trace server and client.\
+//! The server is a [Tracer] that sends the structured [TraceRecord]s of all FSMs to the connected
+//! clients. Clients can send commands back, to inject external events into a session or to change
+//! the trace mode.
+//!
+//! Protocol: All messages are frames with a 4 byte length (big endian), followed by the payload.
+//! The first byte of the payload is the message type, see [TraceMessage] and [TraceCommand].
+//!
+//! The FSM threads never wait for clients: Each client has a bounded queue, messages for a
+//! client with a full queue are dropped (see [TraceServer::dropped_messages]).
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::io::{Cursor, Read, Write};
+use std::net::{SocketAddr, TcpStream as StdTcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+#[cfg(not(test))]
+use log::{error, info, warn};
+#[cfg(test)]
+use std::{println as error, println as info, println as warn};
 use tokio::net::{TcpListener, TcpStream};
 use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
 use tokio::task;
 
+use crate::fsm::{Event, EventType, SessionId};
+use crate::fsm_executor::FsmExecutor;
+use crate::tracer::{set_tracer_factory, TraceEntry, TraceMode, TraceRecord, Tracer, TracerFactory};
+use crate::ArgOption;
+
 pub static TRACE_SERVER_ARGUMENT_OPTION: ArgOption = ArgOption {
     name: "traceserver",
     with_value: true,
     required: false,
 };
 
-pub async fn run_trace_server(address: &str) -> tokio::task::JoinHandle<()> {
-    let address_clone = address.to_string();
+/// Maximal number of pending messages per client.
+pub const CLIENT_QUEUE_SIZE: usize = 1024;
+
+/// Maximal size of a frame. Connections that send larger frames are closed.
+pub const MAX_FRAME_SIZE: u32 = 16 * 1024 * 1024;
+
+const MESSAGE_RECORD: u8 = 1;
+const MESSAGE_TEXT: u8 = 2;
+const COMMAND_INJECT_EVENT: u8 = 10;
+const COMMAND_SET_TRACE_MODE: u8 = 11;
+
+const RECORD_METHOD_ENTER: u8 = 1;
+const RECORD_METHOD_EXIT: u8 = 2;
+const RECORD_ARGUMENT: u8 = 3;
+const RECORD_RESULT: u8 = 4;
+const RECORD_ENTER_STATE: u8 = 5;
+const RECORD_EXIT_STATE: u8 = 6;
+const RECORD_EVENT_SENT: u8 = 7;
+const RECORD_EVENT_RECEIVED: u8 = 8;
+const RECORD_TRANSITION_TAKEN: u8 = 9;
+const RECORD_EVENTS_DROPPED: u8 = 10;
+
+/// Message from the server to the clients.
+#[derive(Debug, Clone)]
+pub enum TraceMessage {
+    Record(TraceEntry),
+    /// Textual trace output, see [Tracer::trace].
+    Text {
+        session_id: Option<SessionId>,
+        text: String,
+    },
+}
+
+/// Command from a client to the server.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TraceCommand {
+    /// Puts an external event into the queue of the session.
+    InjectEvent {
+        session_id: SessionId,
+        event: String,
+    },
+    /// Enables or disables a trace mode. If no session is given, all current sessions
+    /// and all sessions that are created later are affected.
+    SetTraceMode {
+        session_id: Option<SessionId>,
+        mode: TraceMode,
+        enable: bool,
+    },
+}
+
+fn write_string(buffer: &mut Vec<u8>, s: &str) -> io::Result<()> {
+    buffer.write_u32::<BigEndian>(s.len() as u32)?;
+    buffer.write_all(s.as_bytes())
+}
+
+fn read_string(reader: &mut Cursor<&[u8]>) -> io::Result<String> {
+    let len = reader.read_u32::<BigEndian>()? as usize;
+    if len > reader.get_ref().len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "String exceeds frame",
+        ));
+    }
+    let mut buffer = vec![0; len];
+    reader.read_exact(&mut buffer)?;
+    String::from_utf8(buffer).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn write_optional_session(buffer: &mut Vec<u8>, session_id: Option<SessionId>) -> io::Result<()> {
+    match session_id {
+        None => buffer.write_u8(0),
+        Some(id) => {
+            buffer.write_u8(1)?;
+            buffer.write_u32::<BigEndian>(id)
+        }
+    }
+}
+
+fn read_optional_session(reader: &mut Cursor<&[u8]>) -> io::Result<Option<SessionId>> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(reader.read_u32::<BigEndian>()?)),
+    }
+}
+
+fn write_optional_string(buffer: &mut Vec<u8>, s: &Option<String>) -> io::Result<()> {
+    match s {
+        None => buffer.write_u8(0),
+        Some(s) => {
+            buffer.write_u8(1)?;
+            write_string(buffer, s)
+        }
+    }
+}
+
+fn read_optional_string(reader: &mut Cursor<&[u8]>) -> io::Result<Option<String>> {
+    match reader.read_u8()? {
+        0 => Ok(None),
+        _ => Ok(Some(read_string(reader)?)),
+    }
+}
+
+const TRACE_MODES: [TraceMode; 8] = [
+    TraceMode::METHODS,
+    TraceMode::STATES,
+    TraceMode::EVENTS,
+    TraceMode::ARGUMENTS,
+    TraceMode::RESULTS,
+    TraceMode::TRANSITIONS,
+    TraceMode::ALL,
+    TraceMode::NONE,
+];
+
+fn trace_mode_to_u8(mode: TraceMode) -> u8 {
+    TRACE_MODES.iter().position(|m| *m == mode).unwrap() as u8
+}
+
+fn trace_mode_from_u8(value: u8) -> io::Result<TraceMode> {
+    match TRACE_MODES.get(value as usize) {
+        Some(mode) => Ok(*mode),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unknown trace mode {}", value),
+        )),
+    }
+}
+
+/// Writes the frame length in front of the payload.
+fn to_frame(mut payload: Vec<u8>) -> Vec<u8> {
+    let len = (payload.len() - 4) as u32;
+    payload[0..4].copy_from_slice(&len.to_be_bytes());
+    payload
+}
+
+fn new_frame(message_type: u8) -> Vec<u8> {
+    let mut buffer = vec![0; 4];
+    buffer.push(message_type);
+    buffer
+}
+
+fn write_record(buffer: &mut Vec<u8>, record: &TraceRecord) -> io::Result<()> {
+    match record {
+        TraceRecord::MethodEnter { name } => {
+            buffer.write_u8(RECORD_METHOD_ENTER)?;
+            write_string(buffer, name)
+        }
+        TraceRecord::MethodExit { name } => {
+            buffer.write_u8(RECORD_METHOD_EXIT)?;
+            write_string(buffer, name)
+        }
+        TraceRecord::Argument { name, value } => {
+            buffer.write_u8(RECORD_ARGUMENT)?;
+            write_string(buffer, name)?;
+            write_string(buffer, value)
+        }
+        TraceRecord::Result { name, value } => {
+            buffer.write_u8(RECORD_RESULT)?;
+            write_string(buffer, name)?;
+            write_string(buffer, value)
+        }
+        TraceRecord::EnterState { id, name } => {
+            buffer.write_u8(RECORD_ENTER_STATE)?;
+            buffer.write_u32::<BigEndian>(*id)?;
+            write_string(buffer, name)
+        }
+        TraceRecord::ExitState { id, name } => {
+            buffer.write_u8(RECORD_EXIT_STATE)?;
+            buffer.write_u32::<BigEndian>(*id)?;
+            write_string(buffer, name)
+        }
+        TraceRecord::EventSent { event, internal } | TraceRecord::EventReceived { event, internal } => {
+            buffer.write_u8(if matches!(record, TraceRecord::EventSent { .. }) {
+                RECORD_EVENT_SENT
+            } else {
+                RECORD_EVENT_RECEIVED
+            })?;
+            buffer.write_u8(*internal as u8)?;
+            write_string(buffer, event.name.as_str())?;
+            write_string(buffer, event.etype.name())?;
+            write_optional_string(buffer, &event.sendid)?;
+            write_optional_string(buffer, &event.invoke_id)
+        }
+        TraceRecord::TransitionTaken {
+            id,
+            source,
+            targets,
+        } => {
+            buffer.write_u8(RECORD_TRANSITION_TAKEN)?;
+            buffer.write_u32::<BigEndian>(*id)?;
+            buffer.write_u32::<BigEndian>(*source)?;
+            buffer.write_u32::<BigEndian>(targets.len() as u32)?;
+            for target in targets {
+                buffer.write_u32::<BigEndian>(*target)?;
+            }
+            Ok(())
+        }
+        TraceRecord::EventsDropped { count } => {
+            buffer.write_u8(RECORD_EVENTS_DROPPED)?;
+            buffer.write_u64::<BigEndian>(*count)
+        }
+    }
+}
+
+fn read_record(reader: &mut Cursor<&[u8]>) -> io::Result<TraceRecord> {
+    let record_type = reader.read_u8()?;
+    Ok(match record_type {
+        RECORD_METHOD_ENTER => TraceRecord::MethodEnter {
+            name: read_string(reader)?,
+        },
+        RECORD_METHOD_EXIT => TraceRecord::MethodExit {
+            name: read_string(reader)?,
+        },
+        RECORD_ARGUMENT => TraceRecord::Argument {
+            name: read_string(reader)?,
+            value: read_string(reader)?,
+        },
+        RECORD_RESULT => TraceRecord::Result {
+            name: read_string(reader)?,
+            value: read_string(reader)?,
+        },
+        RECORD_ENTER_STATE => TraceRecord::EnterState {
+            id: reader.read_u32::<BigEndian>()?,
+            name: read_string(reader)?,
+        },
+        RECORD_EXIT_STATE => TraceRecord::ExitState {
+            id: reader.read_u32::<BigEndian>()?,
+            name: read_string(reader)?,
+        },
+        RECORD_EVENT_SENT | RECORD_EVENT_RECEIVED => {
+            let internal = reader.read_u8()? != 0;
+            let mut event = Event::new_simple(read_string(reader)?.as_str());
+            event.etype = match read_string(reader)?.as_str() {
+                "platform" => EventType::platform,
+                "internal" => EventType::internal,
+                _ => EventType::external,
+            };
+            event.sendid = read_optional_string(reader)?;
+            event.invoke_id = read_optional_string(reader)?;
+            if record_type == RECORD_EVENT_SENT {
+                TraceRecord::EventSent { event, internal }
+            } else {
+                TraceRecord::EventReceived { event, internal }
+            }
+        }
+        RECORD_TRANSITION_TAKEN => {
+            let id = reader.read_u32::<BigEndian>()?;
+            let source = reader.read_u32::<BigEndian>()?;
+            let len = reader.read_u32::<BigEndian>()? as usize;
+            let mut targets = Vec::with_capacity(len.min(1024));
+            for _ in 0..len {
+                targets.push(reader.read_u32::<BigEndian>()?);
+            }
+            TraceRecord::TransitionTaken {
+                id,
+                source,
+                targets,
+            }
+        }
+        RECORD_EVENTS_DROPPED => TraceRecord::EventsDropped {
+            count: reader.read_u64::<BigEndian>()?,
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown trace record type {}", record_type),
+            ))
+        }
+    })
+}
+
+impl TraceMessage {
+    /// Encodes the message as frame.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        match self {
+            TraceMessage::Record(entry) => {
+                let mut buffer = new_frame(MESSAGE_RECORD);
+                write_optional_session(&mut buffer, entry.session_id)?;
+                let millis = entry
+                    .timestamp
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis() as u64;
+                buffer.write_u64::<BigEndian>(millis)?;
+                write_record(&mut buffer, &entry.record)?;
+                Ok(to_frame(buffer))
+            }
+            TraceMessage::Text { session_id, text } => {
+                let mut buffer = new_frame(MESSAGE_TEXT);
+                write_optional_session(&mut buffer, *session_id)?;
+                write_string(&mut buffer, text)?;
+                Ok(to_frame(buffer))
+            }
+        }
+    }
+
+    /// Decodes the payload of a frame (without the length).
+    pub fn decode(payload: &[u8]) -> io::Result<TraceMessage> {
+        let mut reader = Cursor::new(payload);
+        match reader.read_u8()? {
+            MESSAGE_RECORD => {
+                let session_id = read_optional_session(&mut reader)?;
+                let timestamp = UNIX_EPOCH + Duration::from_millis(reader.read_u64::<BigEndian>()?);
+                Ok(TraceMessage::Record(TraceEntry {
+                    timestamp,
+                    session_id,
+                    record: read_record(&mut reader)?,
+                }))
+            }
+            MESSAGE_TEXT => Ok(TraceMessage::Text {
+                session_id: read_optional_session(&mut reader)?,
+                text: read_string(&mut reader)?,
+            }),
+            t => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown message type {}", t),
+            )),
+        }
+    }
+}
+
+impl TraceCommand {
+    /// Encodes the command as frame.
+    pub fn encode(&self) -> io::Result<Vec<u8>> {
+        match self {
+            TraceCommand::InjectEvent { session_id, event } => {
+                let mut buffer = new_frame(COMMAND_INJECT_EVENT);
+                buffer.write_u32::<BigEndian>(*session_id)?;
+                write_string(&mut buffer, event)?;
+                Ok(to_frame(buffer))
+            }
+            TraceCommand::SetTraceMode {
+                session_id,
+                mode,
+                enable,
+            } => {
+                let mut buffer = new_frame(COMMAND_SET_TRACE_MODE);
+                write_optional_session(&mut buffer, *session_id)?;
+                buffer.write_u8(trace_mode_to_u8(*mode))?;
+                buffer.write_u8(*enable as u8)?;
+                Ok(to_frame(buffer))
+            }
+        }
+    }
+
+    /// Decodes the payload of a frame (without the length).
+    pub fn decode(payload: &[u8]) -> io::Result<TraceCommand> {
+        let mut reader = Cursor::new(payload);
+        match reader.read_u8()? {
+            COMMAND_INJECT_EVENT => Ok(TraceCommand::InjectEvent {
+                session_id: reader.read_u32::<BigEndian>()?,
+                event: read_string(&mut reader)?,
+            }),
+            COMMAND_SET_TRACE_MODE => Ok(TraceCommand::SetTraceMode {
+                session_id: read_optional_session(&mut reader)?,
+                mode: trace_mode_from_u8(reader.read_u8()?)?,
+                enable: reader.read_u8()? != 0,
+            }),
+            t => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Unknown command type {}", t),
+            )),
+        }
+    }
+}
+
+type TraceFlags = Arc<Mutex<HashSet<TraceMode>>>;
+
+struct TraceClient {
+    id: u64,
+    sender: mpsc::Sender<Arc<Vec<u8>>>,
+}
+
+#[derive(Default)]
+struct TraceServerState {
+    clients: Vec<TraceClient>,
+    next_client_id: u64,
+    dropped_messages: u64,
+    /// Trace modes for new tracers.
+    default_flags: HashSet<TraceMode>,
+    /// Trace modes of the tracers of running sessions.
+    session_flags: HashMap<SessionId, Weak<Mutex<HashSet<TraceMode>>>>,
+    executor: Option<FsmExecutor>,
+}
+
+/// The trace server. Clones share the same state.
+#[derive(Clone, Default)]
+pub struct TraceServer {
+    state: Arc<Mutex<TraceServerState>>,
+}
+
+impl Debug for TraceServer {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TraceServer")
+            .field("clients", &self.client_count())
+            .finish()
+    }
+}
 
-    set_tracer_factory(Box::new(RemoteTracerFactory {}));
+impl TraceServer {
+    pub fn new() -> TraceServer {
+        TraceServer::default()
+    }
 
-    tokio::task::spawn(async move {
-        let listener = TcpListener::bind(address_clone.as_str()).await;
+    /// Sets the executor that is used to route injected events to the sessions.\
+    /// Without executor, injected events are ignored.
+    pub fn set_executor(&self, executor: FsmExecutor) {
+        self.state.lock().unwrap().executor = Some(executor);
+    }
 
-        if let Ok(listener) = listener {
-            println!("Trace Server runs on {}", address_clone);
+    /// Creates a factory for tracers that report to this server. See [set_tracer_factory].
+    pub fn tracer_factory(&self) -> RemoteTracerFactory {
+        RemoteTracerFactory {
+            server: self.clone(),
+        }
+    }
+
+    /// Creates a new tracer that reports to this server.
+    pub fn create_tracer(&self) -> RemoteTrace {
+        RemoteTrace {
+            server: self.clone(),
+            flags: Arc::new(Mutex::new(self.state.lock().unwrap().default_flags.clone())),
+            session_id: None,
+        }
+    }
+
+    /// Number of connected clients.
+    pub fn client_count(&self) -> usize {
+        self.state.lock().unwrap().clients.len()
+    }
 
+    /// Number of messages that were dropped because the queue of a client was full.
+    pub fn dropped_messages(&self) -> u64 {
+        self.state.lock().unwrap().dropped_messages
+    }
+
+    /// Binds the server to the address and starts to accept clients.
+    /// Returns the bound address and the handle of the accepting task.
+    pub async fn start(&self, address: &str) -> io::Result<(SocketAddr, task::JoinHandle<()>)> {
+        let listener = TcpListener::bind(address).await?;
+        let local_address = listener.local_addr()?;
+        let server = self.clone();
+        let handle = task::spawn(async move {
             loop {
                 match listener.accept().await {
                     Ok((socket, _)) => {
-                        task::spawn(handle_connection(socket));
+                        task::spawn(server.clone().handle_connection(socket));
                     }
                     Err(err) => {
-                        println!("Connection aborted: {}", err);
+                        error!("Trace Server: Connection aborted: {}", err);
                         break;
                     }
                 };
             }
+        });
+        Ok((local_address, handle))
+    }
+
+    fn has_clients(&self) -> bool {
+        !self.state.lock().unwrap().clients.is_empty()
+    }
+
+    /// Puts the message in the queues of all clients. Never blocks.
+    fn publish(&self, message: &TraceMessage) {
+        match message.encode() {
+            Ok(frame) => {
+                let frame = Arc::new(frame);
+                let mut guard = self.state.lock().unwrap();
+                let state = &mut *guard;
+                let mut dropped = 0;
+                state
+                    .clients
+                    .retain(|client| match client.sender.try_send(frame.clone()) {
+                        Ok(_) => true,
+                        Err(TrySendError::Full(_)) => {
+                            dropped += 1;
+                            true
+                        }
+                        Err(TrySendError::Closed(_)) => false,
+                    });
+                state.dropped_messages += dropped;
+            }
+            Err(err) => {
+                error!("Trace Server: Failed to encode message. {}", err);
+            }
         }
-    })
-}
+    }
 
-async fn handle_connection(socket: TcpStream) {
-    let (mut reader, mut writer) = socket.into_split();
-    let (tx, mut rx) = mpsc::channel(32);
+    fn register_session(&self, session_id: SessionId, flags: &TraceFlags) {
+        let mut state = self.state.lock().unwrap();
+        state.session_flags.retain(|_, f| f.strong_count() > 0);
+        state
+            .session_flags
+            .insert(session_id, Arc::downgrade(flags));
+    }
 
-    // Task zum Lesen von Nachrichten vom Client
-    let tx_clone = tx.clone();
-    task::spawn(async move {
-        let mut buf = vec![0; 1024];
-        loop {
-            match reader.read(&mut buf).await {
-                Ok(0) => break, // Verbindung geschlossen
-                Ok(n) => {
-                    let msg = String::from_utf8_lossy(&buf[..n]);
-                    println!("Empfangen: {}", msg);
-                    tx_clone.send(msg.to_string()).await.unwrap();
+    fn add_client(&self, sender: mpsc::Sender<Arc<Vec<u8>>>) -> u64 {
+        let mut state = self.state.lock().unwrap();
+        state.next_client_id += 1;
+        let id = state.next_client_id;
+        state.clients.push(TraceClient { id, sender });
+        id
+    }
+
+    fn remove_client(&self, id: u64) {
+        self.state.lock().unwrap().clients.retain(|c| c.id != id);
+    }
+
+    /// Executes a command of a client.
+    pub fn apply_command(&self, command: TraceCommand) {
+        match command {
+            TraceCommand::InjectEvent { session_id, event } => {
+                let executor = self.state.lock().unwrap().executor.clone();
+                match executor {
+                    Some(executor) => {
+                        if executor
+                            .send_to_session(session_id, Event::new_simple(event.as_str()))
+                            .is_err()
+                        {
+                            warn!(
+                                "Trace Server: Can't inject event '{}', session #{} not found",
+                                event, session_id
+                            );
+                        }
+                    }
+                    None => {
+                        warn!("Trace Server: Can't inject event '{}', no executor", event);
+                    }
+                }
+            }
+            TraceCommand::SetTraceMode {
+                session_id,
+                mode,
+                enable,
+            } => {
+                let mut state = self.state.lock().unwrap();
+                let flags: Vec<TraceFlags> = match session_id {
+                    Some(id) => state
+                        .session_flags
+                        .get(&id)
+                        .and_then(|f| f.upgrade())
+                        .into_iter()
+                        .collect(),
+                    None => {
+                        if enable {
+                            state.default_flags.insert(mode);
+                        } else {
+                            state.default_flags.remove(&mode);
+                        }
+                        state
+                            .session_flags
+                            .values()
+                            .filter_map(|f| f.upgrade())
+                            .collect()
+                    }
+                };
+                drop(state);
+                for f in flags {
+                    if enable {
+                        f.lock().unwrap().insert(mode);
+                    } else {
+                        f.lock().unwrap().remove(&mode);
+                    }
                 }
-                Err(_) => break,
             }
         }
-    });
+    }
+
+    async fn handle_connection(self, socket: TcpStream) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
-    // Task zum Senden von Nachrichten an den Client
-    task::spawn(async move {
-        while let Some(msg) = rx.recv().await {
-            if writer.write_all(msg.as_bytes()).await.is_err() {
+        let (mut reader, mut writer) = socket.into_split();
+        let (sender, mut receiver) = mpsc::channel::<Arc<Vec<u8>>>(CLIENT_QUEUE_SIZE);
+        let id = self.add_client(sender);
+
+        task::spawn(async move {
+            while let Some(frame) = receiver.recv().await {
+                if writer.write_all(&frame).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        while let Ok(len) = reader.read_u32().await {
+            if len > MAX_FRAME_SIZE {
+                warn!("Trace Server: Frame of client #{} exceeds limit", id);
                 break;
             }
+            let mut payload = vec![0; len as usize];
+            if reader.read_exact(&mut payload).await.is_err() {
+                break;
+            }
+            match TraceCommand::decode(&payload) {
+                Ok(command) => self.apply_command(command),
+                Err(err) => {
+                    warn!("Trace Server: Illegal command from client #{}. {}", id, err);
+                }
+            }
         }
-    });
+        // Dropping the sender also terminates the writer task.
+        self.remove_client(id);
+    }
 }
 
-#[derive(Debug)]
-pub struct RemoteTrace {}
+/// Starts a trace server and registers it as tracer factory for all new FSMs.
+pub async fn run_trace_server(address: &str) -> task::JoinHandle<()> {
+    let server = TraceServer::new();
+    set_tracer_factory(Box::new(server.tracer_factory()));
 
-impl RemoteTrace {
-    pub fn new() -> RemoteTrace {
-        RemoteTrace {}
+    match server.start(address).await {
+        Ok((local_address, handle)) => {
+            info!("Trace Server runs on {}", local_address);
+            handle
+        }
+        Err(err) => {
+            error!("Trace Server: Failed to bind to {}. {}", address, err);
+            task::spawn(async {})
+        }
     }
 }
 
-impl Default for RemoteTrace {
-    fn default() -> Self {
-        Self::new()
-    }
+/// Tracer that sends the records to the clients of a [TraceServer].
+#[derive(Debug)]
+pub struct RemoteTrace {
+    server: TraceServer,
+    flags: TraceFlags,
+    session_id: Option<SessionId>,
 }
 
-// TODO: Remove this if finished
-#[allow(unused_variables)]
 impl Tracer for RemoteTrace {
-    fn trace(&self, msg: &str) {}
+    fn trace(&self, msg: &str) {
+        if self.server.has_clients() {
+            self.server.publish(&TraceMessage::Text {
+                session_id: self.session_id,
+                text: msg.to_string(),
+            });
+        }
+    }
 
-    /// Enter a sub-scope, e.g. by increase the log indentation.
     fn enter(&self) {}
 
-    /// Leave the current sub-scope, e.g. by decrease the log indentation.
     fn leave(&self) {}
 
-    /// Enable traces for the specified scope.
-    fn enable_trace(&mut self, flag: TraceMode) {}
+    fn enable_trace(&mut self, flag: TraceMode) {
+        self.flags.lock().unwrap().insert(flag);
+    }
 
-    /// Disable traces for the specified scope.
-    fn disable_trace(&mut self, flag: TraceMode) {}
+    fn disable_trace(&mut self, flag: TraceMode) {
+        self.flags.lock().unwrap().remove(&flag);
+    }
 
-    /// Return true if the given scape is enabled.
     fn is_trace(&self, flag: TraceMode) -> bool {
-        true
+        let flags = self.flags.lock().unwrap();
+        flags.contains(&flag) || flags.contains(&TraceMode::ALL)
     }
 
-    fn enter_method(&self, what: &str) {
-        if self.is_trace(TraceMode::METHODS) {
-            todo!()
+    fn trace_record(&self, record: &TraceRecord) {
+        if self.is_trace(record.mode()) && self.server.has_clients() {
+            self.server.publish(&TraceMessage::Record(TraceEntry {
+                timestamp: SystemTime::now(),
+                session_id: self.session_id,
+                record: record.clone(),
+            }));
         }
     }
 
-    fn exit_method(&self, what: &str) {
-        if self.is_trace(TraceMode::METHODS) {
-            todo!()
-        }
+    fn set_session_id(&mut self, session_id: SessionId) {
+        self.session_id = Some(session_id);
+        self.server.register_session(session_id, &self.flags);
     }
 
-    fn event_internal_send(&self, what: &Event) {
-        if self.is_trace(TraceMode::EVENTS) {
-            todo!()
+    fn trace_mode(&self) -> TraceMode {
+        if self.is_trace(TraceMode::ALL) {
+            TraceMode::ALL
+        } else if self.is_trace(TraceMode::EVENTS) {
+            TraceMode::EVENTS
+        } else if self.is_trace(TraceMode::STATES) {
+            TraceMode::STATES
+        } else if self.is_trace(TraceMode::METHODS) {
+            TraceMode::METHODS
+        } else {
+            TraceMode::NONE
         }
     }
+}
 
-    /// Called by FSM if an internal event is received
-    fn event_internal_received(&self, what: &Event) {
-        if self.is_trace(TraceMode::EVENTS) {
-            todo!()
-        }
+pub struct RemoteTracerFactory {
+    server: TraceServer,
+}
+
+impl TracerFactory for RemoteTracerFactory {
+    fn create(&mut self) -> Box<dyn Tracer> {
+        Box::new(self.server.create_tracer())
     }
+}
 
-    /// Called by FSM if an external event is send
-    fn event_external_send(&self, what: &Event) {
-        if self.is_trace(TraceMode::EVENTS) {
-            todo!()
-        }
+/// Client for a [TraceServer]. Uses blocking I/O.
+pub struct RemoteTraceClient {
+    stream: StdTcpStream,
+}
+
+impl RemoteTraceClient {
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<RemoteTraceClient> {
+        Ok(RemoteTraceClient {
+            stream: StdTcpStream::connect(address)?,
+        })
     }
 
-    /// Called by FSM if an external event is received
-    fn event_external_received(&mut self, what: &Event) {
-        if self.is_trace(TraceMode::EVENTS) {
-            todo!()
-        }
+    /// Sets the timeout for [RemoteTraceClient::receive]. None blocks forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
     }
 
-    /// Called by FSM if a state is entered or left.
-    fn trace_state(&self, what: &str, s: &State) {
-        if self.is_trace(TraceMode::STATES) {
-            todo!()
+    /// Waits for the next message of the server.
+    pub fn receive(&mut self) -> io::Result<TraceMessage> {
+        let len = self.stream.read_u32::<BigEndian>()?;
+        if len > MAX_FRAME_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Frame exceeds limit",
+            ));
         }
+        let mut payload = vec![0; len as usize];
+        self.stream.read_exact(&mut payload)?;
+        TraceMessage::decode(&payload)
     }
 
-    /// Called by FSM if a state is entered. Calls [Tracer::trace_state].
-    fn trace_enter_state(&self, s: &State) {
-        todo!()
+    pub fn send_command(&mut self, command: &TraceCommand) -> io::Result<()> {
+        self.stream.write_all(&command.encode()?)
     }
 
-    /// Called by FSM if a state is left. Calls [Tracer::trace_state].
-    fn trace_exit_state(&self, s: &State) {
-        todo!()
+    /// Puts an external event into the queue of the session.
+    pub fn inject_event(&mut self, session_id: SessionId, event: &str) -> io::Result<()> {
+        self.send_command(&TraceCommand::InjectEvent {
+            session_id,
+            event: event.to_string(),
+        })
     }
 
-    /// Called by FSM for input arguments in methods.
-    fn trace_argument(&self, what: &str, d: &dyn Display) {
-        if self.is_trace(TraceMode::ARGUMENTS) {
-            todo!()
-        }
+    /// Enables or disables a trace mode of one session or of all sessions.
+    pub fn set_trace_mode(&mut self, session_id: Option<SessionId>, mode: TraceMode, enable: bool) -> io::Result<()> {
+        self.send_command(&TraceCommand::SetTraceMode {
+            session_id,
+            mode,
+            enable,
+        })
     }
+}
 
-    /// Called by FSM for results in methods.
-    fn trace_result(&self, what: &str, d: &dyn Display) {
-        if self.is_trace(TraceMode::RESULTS) {
-            todo!()
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::fsm::Event;
+    use crate::remote_tracer::{TraceCommand, TraceMessage};
+    use crate::tracer::{TraceEntry, TraceMode, TraceRecord};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use {
+        crate::actions::ActionWrapper,
+        crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode},
+        crate::fsm_executor::FsmExecutor,
+        crate::remote_tracer::{RemoteTraceClient, TraceServer},
+        crate::scxml_reader,
+        std::thread,
+    };
+
+    #[test]
+    fn messages_are_decoded() {
+        let command = TraceCommand::SetTraceMode {
+            session_id: Some(7),
+            mode: TraceMode::TRANSITIONS,
+            enable: true,
+        };
+        let frame = command.encode().unwrap();
+        assert_eq!(TraceCommand::decode(&frame[4..]).unwrap(), command);
+
+        let mut event = Event::new_simple("go");
+        event.invoke_id = Some("child".to_string());
+        let message = TraceMessage::Record(TraceEntry {
+            timestamp: SystemTime::now(),
+            session_id: Some(3),
+            record: TraceRecord::EventReceived {
+                event,
+                internal: false,
+            },
+        });
+        let frame = message.encode().unwrap();
+        assert_eq!(
+            u32::from_be_bytes(frame[0..4].try_into().unwrap()) as usize,
+            frame.len() - 4
+        );
+        match TraceMessage::decode(&frame[4..]).unwrap() {
+            TraceMessage::Record(TraceEntry {
+                session_id,
+                record: TraceRecord::EventReceived { event, internal },
+                ..
+            }) => {
+                assert_eq!(session_id, Some(3));
+                assert_eq!(event.name, "go");
+                assert_eq!(event.invoke_id, Some("child".to_string()));
+                assert!(!internal);
+            }
+            m => panic!("Unexpected message {:?}", m),
         }
     }
 
-    /// Get trace mode
-    fn trace_mode(&self) -> TraceMode {
-        TraceMode::ALL
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn wait_for_clients(server: &TraceServer, count: usize) {
+        for _ in 0..200 {
+            if server.client_count() == count {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        panic!("Expected {} clients, got {}", count, server.client_count());
     }
-}
-
-pub struct RemoteTracerFactory {}
 
-impl TracerFactory for RemoteTracerFactory {
-    fn create(&mut self) -> Box<dyn Tracer> {
-        println!("Create RemoteTrace");
-        Box::new(RemoteTrace::new())
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn client_injects_event_and_receives_records() {
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let server = TraceServer::new();
+        let executor = FsmExecutor::new_without_io_processor();
+        server.set_executor(executor.clone());
+        let (address, _handle) = runtime.block_on(server.start("127.0.0.1:0")).unwrap();
+
+        let mut client = RemoteTraceClient::connect(address).unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_millis(2000)))
+            .unwrap();
+        // A second client that disconnects shall not disturb the others.
+        let other = RemoteTraceClient::connect(address).unwrap();
+        wait_for_clients(&server, 2);
+        drop(other);
+        wait_for_clients(&server, 1);
+
+        let mut sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A' datamodel='ecmascript'>
+              <state id='A'>
+                <transition event='go' target='B'/>
+              </state>
+              <final id='B'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+        sm.tracer = Box::new(server.create_tracer());
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        // Ensures that the session is initialized.
+        assert!(session
+            .send_and_sync(
+                Box::new(Event::new_simple("ping")),
+                Duration::from_millis(2000)
+            )
+            .is_ok());
+
+        client
+            .set_trace_mode(Some(session.session_id), TraceMode::STATES, true)
+            .unwrap();
+        client
+            .set_trace_mode(Some(session.session_id), TraceMode::EVENTS, true)
+            .unwrap();
+        client.inject_event(session.session_id, "go").unwrap();
+
+        let mut records = Vec::new();
+        loop {
+            match client.receive().unwrap() {
+                TraceMessage::Record(entry) => {
+                    assert_eq!(entry.session_id, Some(session.session_id));
+                    match entry.record {
+                        TraceRecord::EventReceived { event, .. } => records.push(format!("receive {}", event.name)),
+                        TraceRecord::ExitState { name, .. } => records.push(format!("exit {}", name)),
+                        TraceRecord::EnterState { name, .. } => {
+                            records.push(format!("enter {}", name));
+                            if name == "B" {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+                TraceMessage::Text { .. } => {}
+            }
+        }
+        assert_eq!(records, vec!["receive go", "exit A", "enter B"]);
+        assert!(session
+            .wait_for_completion(Duration::from_millis(2000))
+            .is_some());
     }
 }
@@ -0,0 +1,387 @@
+//! Scripted scenarios to test state machines without writing Rust code.\
+//! A scenario lists the external events to send, each with the time it is sent, and the expected outcome:
+//! ```json
+//! {
+//!   "steps": [
+//!     { "at_ms": 0, "send": { "name": "go" } },
+//!     { "at_ms": 100, "send": { "name": "data", "data": { "x": 1 } } }
+//!   ],
+//!   "expected_final": [ "Done" ],
+//!   "expected_data": { "x": 1 },
+//!   "timeout_ms": 2000,
+//!   "virtual_clock": true
+//! }
+//! ```
+//! "data" of an event is set as content, so it is available as "_event.data".
+//! "expected_data" contains locations of the datamodel and their expected values at the end of the session.
+//!
+//! With "virtual_clock" the session uses a [TestClock]. The clock is advanced to the time of the step before
+//! the event is sent, and each event is processed completely before the next step. Delayed sends are delivered
+//! according to the virtual time, so the scenario doesn't depend on the timing of the machine.
+//!
+//! After the last step the FSM has "timeout_ms" to finish (with virtual clock, the virtual time is advanced
+//! by the same amount). Otherwise, the session is cancelled and the scenario fails.
+//!
+//! See [run_scenario] and the binary "scxml_scenario".
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use serde::Deserialize;
+use serde_json::Value;
+#[cfg(feature = "yaml-config")]
+use yaml_rust::{Yaml, YamlLoader};
+
+use crate::actions::ActionWrapper;
+use crate::clock::TestClock;
+use crate::datamodel::{create_data_arc, json_to_data};
+use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, FinishMode, Fsm, EVENT_CANCEL_SESSION};
+use crate::fsm_executor::FsmExecutor;
+
+/// Default for [Scenario::timeout_ms].
+pub const DEFAULT_SCENARIO_TIMEOUT_MS: u64 = 5000;
+
+/// Event to send in a [ScenarioStep].
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioEvent {
+    pub name: String,
+    /// Content of the event.
+    #[serde(default)]
+    pub data: Option<Value>,
+}
+
+/// One step of a [Scenario].
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScenarioStep {
+    /// Time in milliseconds after the start of the session.
+    #[serde(default)]
+    pub at_ms: u64,
+    pub send: ScenarioEvent,
+}
+
+/// A scripted scenario, see [crate::scenario].
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct Scenario {
+    /// Optional path of the FSM, relative to the scenario file.
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub steps: Vec<ScenarioStep>,
+    /// The states the final configuration has to contain.
+    #[serde(default)]
+    pub expected_final: Vec<String>,
+    /// Expected values of datamodel locations at the end of the session.
+    #[serde(default)]
+    pub expected_data: BTreeMap<String, Value>,
+    /// Time in milliseconds the FSM has to finish after the last step.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub virtual_clock: bool,
+}
+
+impl Scenario {
+    pub fn from_json(content: &str) -> Result<Scenario, String> {
+        serde_json::from_str(content).map_err(|err| format!("Illegal scenario. {}", err))
+    }
+
+    #[cfg(feature = "yaml-config")]
+    pub fn from_yaml(content: &str) -> Result<Scenario, String> {
+        let docs = YamlLoader::load_from_str(content).map_err(|err| format!("Illegal scenario. {}", err))?;
+        let json = docs.first().map(yaml_to_json).unwrap_or(Value::Null);
+        serde_json::from_value(json).map_err(|err| format!("Illegal scenario. {}", err))
+    }
+}
+
+#[cfg(feature = "yaml-config")]
+fn yaml_to_json(yaml: &Yaml) -> Value {
+    match yaml {
+        Yaml::Real(r) => r
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map_or(Value::Null, Value::Number),
+        Yaml::Integer(i) => Value::from(*i),
+        Yaml::String(s) => Value::String(s.clone()),
+        Yaml::Boolean(b) => Value::Bool(*b),
+        Yaml::Array(a) => Value::Array(a.iter().map(yaml_to_json).collect()),
+        Yaml::Hash(h) => Value::Object(
+            h.iter()
+                .map(|(k, v)| {
+                    let key = match k {
+                        Yaml::String(s) | Yaml::Real(s) => s.clone(),
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => String::new(),
+                    };
+                    (key, yaml_to_json(v))
+                })
+                .collect(),
+        ),
+        Yaml::Alias(_) | Yaml::Null | Yaml::BadValue => Value::Null,
+    }
+}
+
+/// Loads a scenario from a JSON or (with feature "yaml-config") YAML file.
+pub fn load_scenario(file_path: &str) -> Result<Scenario, String> {
+    let content =
+        fs::read_to_string(file_path).map_err(|err| format!("Can't read scenario '{}'. {}", file_path, err))?;
+    let extension = Path::new(file_path)
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    match extension.as_str() {
+        "yaml" | "yml" => {
+            #[cfg(feature = "yaml-config")]
+            {
+                Scenario::from_yaml(content.as_str())
+            }
+            #[cfg(not(feature = "yaml-config"))]
+            Err(format!(
+                "feature 'yaml-config' is not configured. Can't load '{}'",
+                file_path
+            ))
+        }
+        _ => Scenario::from_json(content.as_str()),
+    }
+}
+
+/// An expectation of the scenario that was not met.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScenarioMismatch {
+    /// What was checked, e.g. "final configuration" or "data 'x'".
+    pub subject: String,
+    pub expected: Vec<String>,
+    pub actual: Vec<String>,
+}
+
+/// Result of [run_scenario].
+#[derive(Debug, Clone, Default)]
+pub struct ScenarioReport {
+    pub final_configuration: Vec<String>,
+    /// True if the FSM didn't finish in time and was cancelled.
+    pub timed_out: bool,
+    /// Steps that could not be executed, e.g. because the FSM had already finished.
+    pub errors: Vec<String>,
+    pub mismatches: Vec<ScenarioMismatch>,
+}
+
+impl ScenarioReport {
+    pub fn passed(&self) -> bool {
+        !self.timed_out && self.errors.is_empty() && self.mismatches.is_empty()
+    }
+
+    /// Creates a report in the style of a unified diff, "-" lines are expected, "+" lines are actual values.
+    pub fn diff(&self) -> String {
+        let mut report = String::new();
+        if self.timed_out {
+            report.push_str("FSM didn't finish in time and was cancelled\n");
+        }
+        for error in &self.errors {
+            report.push_str(format!("{}\n", error).as_str());
+        }
+        if !self.mismatches.is_empty() {
+            report.push_str("--- expected\n+++ actual\n");
+        }
+        for mismatch in &self.mismatches {
+            report.push_str(format!("@@ {} @@\n", mismatch.subject).as_str());
+            for line in &mismatch.expected {
+                report.push_str(format!("-{}\n", line).as_str());
+            }
+            for line in &mismatch.actual {
+                report.push_str(format!("+{}\n", line).as_str());
+            }
+        }
+        report
+    }
+}
+
+/// Executes the scenario with the FSM and checks the expectations.\
+/// The executor can be prepared, e.g. with options or include paths. Its clock is replaced if
+/// [Scenario::virtual_clock] is set.
+pub fn run_scenario(fsm: Box<Fsm>, scenario: &Scenario, executor: FsmExecutor) -> ScenarioReport {
+    let mut fsm = fsm;
+    let mut executor = executor;
+    let mut report = ScenarioReport::default();
+    let timeout = Duration::from_millis(scenario.timeout_ms.unwrap_or(DEFAULT_SCENARIO_TIMEOUT_MS));
+
+    fsm.final_value_locations = scenario.expected_data.keys().cloned().collect();
+    let clock = if scenario.virtual_clock {
+        let clock = TestClock::new(0);
+        executor.set_clock(Arc::new(clock.clone()));
+        Some(clock)
+    } else {
+        None
+    };
+
+    let session = start_fsm_with_data_and_finish_mode(
+        fsm,
+        ActionWrapper::new(),
+        Box::new(executor),
+        &Vec::new(),
+        FinishMode::KEEP_CONFIGURATION,
+    );
+    let started = Instant::now();
+
+    let mut steps: Vec<&ScenarioStep> = scenario.steps.iter().collect();
+    steps.sort_by_key(|step| step.at_ms);
+    let mut now_ms = 0;
+    for step in steps {
+        let mut event = Event::new_simple(step.send.name.as_str());
+        if let Some(data) = &step.send.data {
+            event.content = Some(create_data_arc(json_to_data(data)));
+        }
+        let sent = match &clock {
+            Some(clock) => {
+                clock.advance((step.at_ms - now_ms) as i64);
+                now_ms = step.at_ms;
+                session.send_and_sync(Box::new(event), timeout)
+            }
+            None => {
+                let due = started + Duration::from_millis(step.at_ms);
+                let now = Instant::now();
+                if due > now {
+                    thread::sleep(due - now);
+                }
+                session
+                    .sender
+                    .send(Box::new(event))
+                    .map_err(|err| err.to_string())
+            }
+        };
+        if let Err(err) = sent {
+            report.errors.push(format!(
+                "Event '{}' at {} ms not delivered. {}",
+                step.send.name, step.at_ms, err
+            ));
+        }
+    }
+
+    if let Some(clock) = &clock {
+        clock.advance(timeout.as_millis() as i64);
+    }
+    let result = match session.wait_for_completion(timeout) {
+        Some(result) => Some(result),
+        None => {
+            report.timed_out = true;
+            let _ = session
+                .sender
+                .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+            let result = session.wait_for_completion(timeout);
+            if result.is_none() {
+                warn!("Session #{} didn't react on cancel", session.session_id);
+            }
+            result
+        }
+    };
+    report.final_configuration = result
+        .and_then(|r| r.final_configuration)
+        .unwrap_or_default();
+
+    if !scenario
+        .expected_final
+        .iter()
+        .all(|state| report.final_configuration.contains(state))
+    {
+        report.mismatches.push(ScenarioMismatch {
+            subject: "final configuration".to_string(),
+            expected: scenario.expected_final.clone(),
+            actual: report.final_configuration.clone(),
+        });
+    }
+    for (location, expected) in &scenario.expected_data {
+        let expected = json_to_data(expected);
+        let actual = session.get_final_value(location.as_str());
+        if actual.as_ref() != Some(&expected) {
+            report.mismatches.push(ScenarioMismatch {
+                subject: format!("data '{}'", location),
+                expected: vec![expected.to_string()],
+                actual: actual.map(|a| a.to_string()).into_iter().collect(),
+            });
+        }
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scenario::{run_scenario, Scenario};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn scenario_is_executed_and_verified() {
+        let document = r##"<scxml initial='Idle' datamodel='ecmascript'>
+              <datamodel><data id='count' expr='0'/></datamodel>
+              <state id='Idle'>
+                <transition event='add' target='Idle'>
+                  <assign location='count' expr='count + Number(_event.data.n)'/>
+                </transition>
+                <transition event='go' target='Waiting'/>
+              </state>
+              <state id='Waiting'>
+                <onentry><send event='timeout' delay='10s'/></onentry>
+                <transition event='timeout' target='Done'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##;
+
+        let scenario = Scenario::from_json(
+            r#"{
+              "steps": [
+                { "at_ms": 0, "send": { "name": "add", "data": { "n": 2 } } },
+                { "at_ms": 100, "send": { "name": "add", "data": { "n": 3 } } },
+                { "at_ms": 200, "send": { "name": "go" } }
+              ],
+              "expected_final": [ "Done" ],
+              "expected_data": { "count": 5 },
+              "timeout_ms": 20000,
+              "virtual_clock": true
+            }"#,
+        )
+        .unwrap();
+        let report = run_scenario(
+            scxml_reader::parse_from_xml(document.to_string()).unwrap(),
+            &scenario,
+            FsmExecutor::new_without_io_processor(),
+        );
+        assert!(report.passed(), "{}", report.diff());
+
+        let scenario = Scenario::from_json(
+            r#"{
+              "steps": [ { "send": { "name": "add", "data": { "n": 2 } } } ],
+              "expected_final": [ "Done" ],
+              "expected_data": { "count": 5 },
+              "timeout_ms": 300
+            }"#,
+        )
+        .unwrap();
+        let report = run_scenario(
+            scxml_reader::parse_from_xml(document.to_string()).unwrap(),
+            &scenario,
+            FsmExecutor::new_without_io_processor(),
+        );
+        assert!(!report.passed());
+        assert!(report.timed_out);
+        assert_eq!(
+            report.diff(),
+            "FSM didn't finish in time and was cancelled\n\
+             --- expected\n+++ actual\n\
+             @@ final configuration @@\n-Done\n+Idle\n\
+             @@ data 'count' @@\n-5\n+2\n"
+        );
+    }
+}
@@ -8,6 +8,7 @@ use log::debug;
 
 use log::error;
 use std::fmt::Debug;
+use std::sync::mpsc::TrySendError;
 
 #[cfg(test)]
 #[cfg(feature = "Debug")]
@@ -15,7 +16,7 @@ use std::println as debug;
 
 use crate::datamodel::{GlobalDataArc, GlobalDataLock, SCXML_EVENT_PROCESSOR};
 use crate::event_io_processor::{EventIOProcessor, EventIOProcessorHandle};
-use crate::fsm::{Event, EventType, SessionId};
+use crate::fsm::{Event, EventSender, EventType, SessionId};
 
 /// SCXML Processors specific target:\
 /// If the target is the special term '#_internal', the Processor must add the event to the internal event queue of the sending session.
@@ -58,24 +59,67 @@ impl ScxmlEventIOProcessor {
     }
 
     fn send_to_session(&mut self, global_data_lock: &mut GlobalDataLock, session_id: SessionId, event: Event) -> bool {
-        match &global_data_lock.executor {
+        let sender = match &global_data_lock.executor {
             None => {
-                panic!("Executor not available");
+                error!(
+                    "Executor not available, can't send to session {}",
+                    session_id
+                );
+                global_data_lock.enqueue_internal(Event::error_communication(&event));
+                return false;
             }
-            Some(executor) => {
+            Some(executor) => executor.get_session_sender(session_id),
+        };
+        match sender {
+            None => {
+                error!(
+                    "Can't send to session {}. Session not available",
+                    session_id
+                );
+                global_data_lock.enqueue_internal(Event::error_communication(&event));
+                false
+            }
+            Some(sender) => {
                 #[cfg(feature = "Debug")]
                 debug!("Send '{}' to Session #{}", event, session_id);
-                match executor.send_to_session(session_id, event.clone()) {
-                    Ok(_) => {
-                        // TODO
-                        true
-                    }
-                    Err(error) => {
-                        error!("Can't send to session {}. {}", session_id, error);
-                        global_data_lock.enqueue_internal(Event::error_communication(&event));
-                        false
-                    }
-                }
+                Self::send_to_queue(global_data_lock, sender, event)
+            }
+        }
+    }
+
+    /// Sends the event to the session that was created by \<invoke\> with the invoke id.\
+    /// Uses the sender of the invoked session, services of other invoke handlers are not registered at the executor.
+    fn send_to_child(&mut self, global_data_lock: &mut GlobalDataLock, invoke_id: &str, event: Event) -> bool {
+        let sender = match global_data_lock.child_sessions.get(invoke_id) {
+            None => {
+                error!("Send target: InvokeId '{}' is not available.", invoke_id);
+                global_data_lock.enqueue_internal(Event::error_communication(&event));
+                return false;
+            }
+            Some(session) => session.sender.clone(),
+        };
+        #[cfg(feature = "Debug")]
+        debug!("Send '{}' to invoked session '{}'", event, invoke_id);
+        Self::send_to_queue(global_data_lock, sender, event)
+    }
+
+    /// Puts the event into the external queue of another session.\
+    /// The caller holds the lock of this processor and of the global data. If the queue is full and the
+    /// sender has to wait, the event is sent later, see [crate::fsm::GlobalData::blocked_sends].
+    fn send_to_queue(global_data_lock: &mut GlobalDataLock, sender: EventSender, event: Event) -> bool {
+        match sender.try_send(Box::new(event)) {
+            Ok(_) => true,
+            Err(TrySendError::Full(event)) if sender.may_block() => {
+                global_data_lock.blocked_sends.push((sender, event));
+                true
+            }
+            Err(error) => {
+                error!("Can't send to session. {}", error);
+                let event = match error {
+                    TrySendError::Full(event) | TrySendError::Disconnected(event) => event,
+                };
+                global_data_lock.enqueue_internal(Event::error_communication(&event));
+                false
             }
         }
     }
@@ -137,51 +181,29 @@ impl EventIOProcessor for ScxmlEventIOProcessor {
                 global_lock.enqueue_internal(event);
                 true
             }
-            SCXML_TARGET_PARENT => {
-                let sid = global_lock.parent_session_id.unwrap();
-                self.send_to_session(&mut global_lock, sid, event)
-            }
+            SCXML_TARGET_PARENT => match global_lock.parent_session_id {
+                None => {
+                    error!("Send target '{}': session has no parent.", target);
+                    global_lock.enqueue_internal(Event::error_communication(&event));
+                    false
+                }
+                Some(sid) => self.send_to_session(&mut global_lock, sid, event),
+            },
             _ => {
                 // W3C: If the sending SCXML session specifies a session that does not exist or is inaccessible,
                 //      the SCXML Processor must place the error "error.communication" on the internal event queue of the sending session.
-                if target.starts_with(SCXML_TARGET_SESSION_ID_PREFIX) {
-                    match target.get(SCXML_TARGET_SESSION_ID_PREFIX.len()..) {
-                        None => {
-                            error!("Send target '{}' has wrong format.", target);
-                            global_lock.enqueue_internal(Event::error_communication(&event));
-                            false
-                        }
-                        Some(session_id_s) => match session_id_s.parse::<SessionId>() {
-                            Ok(session_id) => self.send_to_session(&mut global_lock, session_id, event),
-                            Err(_err) => {
-                                error!("Send target '{}' has wrong format.", target);
-                                global_lock.enqueue_internal(Event::error_communication(&event));
-                                false
-                            }
-                        },
-                    }
-                } else if target.starts_with(SCXML_TARGET_INVOKE_ID_PREFIX) {
-                    match target.get(SCXML_TARGET_INVOKE_ID_PREFIX.len()..) {
-                        None => {
-                            error!("Send target '{}' has wrong format.", target);
-                            global_lock.enqueue_internal(Event::error_communication(&event));
-                            false
-                        }
-                        Some(invokeid) => {
-                            let session_id = match global_lock.child_sessions.get(invokeid) {
-                                None => {
-                                    error!(
-                                        "InvokeId of target {} '{}' is not available.",
-                                        invokeid, target
-                                    );
-                                    global_lock.enqueue_internal(Event::error_communication(&event));
-                                    return false;
-                                }
-                                Some(session) => session.session_id,
-                            };
-                            self.send_to_session(&mut global_lock, session_id, event)
-                        }
+                if let Some(session_id_s) = target.strip_prefix(SCXML_TARGET_SESSION_ID_PREFIX) {
+                    match session_id_s.parse::<SessionId>() {
+                        Ok(session_id) => self.send_to_session(&mut global_lock, session_id, event),
+                        // Invoke ids may also start with "scxml_".
+                        Err(_err) => self.send_to_child(
+                            &mut global_lock,
+                            &target[SCXML_TARGET_INVOKE_ID_PREFIX.len()..],
+                            event,
+                        ),
                     }
+                } else if let Some(invoke_id) = target.strip_prefix(SCXML_TARGET_INVOKE_ID_PREFIX) {
+                    self.send_to_child(&mut global_lock, invoke_id, event)
                 } else {
                     // W3C says:
                     // If the value ... is not supported or invalid, the Processor MUST place the
@@ -201,3 +223,189 @@ impl EventIOProcessor for ScxmlEventIOProcessor {
         self.handle.shutdown();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_to_invoked_children() {
+        // child1 replies via "#_parent", child2 via the origin of the event.
+        let child = |name: &str, reply_target: &str| {
+            format!(
+                r##"<scxml initial='C' datamodel='ecmascript'>
+                  <state id='C'>
+                    <onentry><send event='ready' target='#_parent'/></onentry>
+                    <transition event='ping.{name}' target='F'>
+                      <send event='echo.{name}' {reply_target}/>
+                    </transition>
+                    <transition event='*'>
+                      <send eventexpr="'wrong.' + _event.name" target='#_parent'/>
+                    </transition>
+                  </state>
+                  <final id='F'/>
+                </scxml>"##
+            )
+        };
+        let sm = scxml_reader::parse_from_xml(format!(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <datamodel>
+                <data id='ready' expr='0'/>
+                <data id='received' expr='0'/>
+              </datamodel>
+              <state id='Main'>
+                <invoke id='child1' type='scxml'><content>{}</content></invoke>
+                <invoke id='child2' type='scxml'><content>{}</content></invoke>
+                <transition event='ready'>
+                  <assign location='ready' expr='ready + 1'/>
+                  <if cond='ready == 2'>
+                    <send event='ping.child2' target='#_child2'/>
+                    <send event='ping.child1' target='#_child1'/>
+                    <send event='ping.nobody' target='#_nobody'/>
+                  </if>
+                </transition>
+                <transition event='echo.child1' cond="_event.origintype == 'http://www.w3.org/TR/scxml/#SCXMLEventProcessor'">
+                  <assign location='received' expr='received + 1'/>
+                </transition>
+                <transition event='echo.child2'>
+                  <assign location='received' expr='received + 1'/>
+                </transition>
+                <transition event='error.communication'>
+                  <assign location='received' expr='received + 1'/>
+                </transition>
+                <transition event='done.invoke'/>
+                <transition cond='received == 3' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##,
+            child("child1", "target='#_parent'"),
+            child("child2", "targetexpr='_event.origin'"),
+        ))
+        .unwrap();
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(3000));
+        assert!(result.is_some(), "Parent shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn reply_to_origin() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <invoke id='child' type='scxml'>
+                  <content>
+                    <scxml initial='C' datamodel='ecmascript'>
+                      <state id='C'>
+                        <onentry><send event='ready' target='#_parent'/></onentry>
+                        <transition event='ping' cond="_event.origin == '#_scxml_' + _x.parent_session_id" target='F'>
+                          <send event='pong' targetexpr='_event.origin' typeexpr='_event.origintype'/>
+                        </transition>
+                        <transition event='*' target='F'>
+                          <send event='wrongOrigin' target='#_parent'/>
+                        </transition>
+                      </state>
+                      <final id='F'/>
+                    </scxml>
+                  </content>
+                </invoke>
+                <transition event='ready'>
+                  <send event='ping' target='#_child'/>
+                </transition>
+                <transition event='pong' cond="_event.origintype == 'http://www.w3.org/TR/scxml/#SCXMLEventProcessor' &amp;&amp; _event.origin.startsWith('#_scxml_') &amp;&amp; _event.origin != '#_scxml_' + _x.session_id" target='pass'/>
+                <transition event='done.invoke'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(3000));
+        assert!(result.is_some(), "Parent shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn send_to_unknown_session_raises_error_communication() {
+        let sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='ecmascript'>
+              <state id='Main'>
+                <onentry>
+                  <send id='lost' event='hello' target='#_scxml_4294967295'/>
+                </onentry>
+                <transition event='error.communication' cond="_event.sendid == 'lost'" target='Running'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <state id='Running'>
+                <onentry><send event='next'/></onentry>
+                <transition event='next' target='pass'/>
+                <transition event='*' target='fail'/>
+              </state>
+              <final id='pass'/>
+              <final id='fail'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(3000));
+        assert!(result.is_some(), "Session shall finish");
+        assert_eq!(
+            result.unwrap().final_configuration,
+            Some(vec!["pass".to_string()])
+        );
+    }
+}
@@ -0,0 +1,76 @@
+//! Implementation of the SCXML Invoke Handler.\
+//! Handles \<invoke\> type "<http://www.w3.org/TR/scxml/>" (or short-cut "scxml") by starting a child FSM.
+
+#[cfg(feature = "Debug")]
+use log::debug;
+
+use crate::datamodel::{SCXML_INVOKE_TYPE, SCXML_INVOKE_TYPE_SHORT};
+use crate::fsm::{Event, FinishMode, ScxmlSession, EVENT_CANCEL_SESSION};
+use crate::fsm_executor::FsmExecutor;
+use crate::invoke_handler::{InvokeError, InvokeHandler, InvokeParameters};
+
+const TYPES: &[&str] = &[SCXML_INVOKE_TYPE, SCXML_INVOKE_TYPE_SHORT];
+
+#[derive(Debug, Default)]
+pub struct ScxmlInvokeHandler {}
+
+impl ScxmlInvokeHandler {
+    pub fn new() -> ScxmlInvokeHandler {
+        ScxmlInvokeHandler {}
+    }
+}
+
+impl InvokeHandler for ScxmlInvokeHandler {
+    fn get_types(&self) -> &[&str] {
+        TYPES
+    }
+
+    fn invoke(
+        &mut self,
+        executor: &mut FsmExecutor,
+        parameters: InvokeParameters,
+    ) -> Result<ScxmlSession, InvokeError> {
+        if parameters.src.is_empty() {
+            match &parameters.content {
+                None => Err(InvokeError::Execution("No content to execute".to_string())),
+                Some(content) => executor.execute_with_data_from_xml(
+                    content.as_str(),
+                    parameters.actions,
+                    &parameters.params,
+                    &parameters.options,
+                    Some(parameters.parent_session_id),
+                    &parameters.invoke_id,
+                    &parameters.write_back,
+                    FinishMode::DISPOSE,
+                    #[cfg(feature = "Trace")]
+                    &parameters.trace_modes,
+                ),
+            }
+        } else {
+            executor.execute_with_data(
+                parameters.src.as_str(),
+                parameters.actions,
+                &parameters.params,
+                &parameters.options,
+                Some(parameters.parent_session_id),
+                &parameters.invoke_id,
+                &parameters.write_back,
+                #[cfg(feature = "Trace")]
+                &parameters.trace_modes,
+            )
+        }
+    }
+
+    fn cancel(&mut self, session: &ScxmlSession) {
+        #[cfg(feature = "Debug")]
+        debug!("Cancel child session #{}", session.session_id);
+        // The session may already be gone, nothing to do in this case.
+        let _ = session
+            .sender
+            .send(Box::new(Event::new_simple(EVENT_CANCEL_SESSION)));
+    }
+
+    fn get_copy(&self) -> Box<dyn InvokeHandler> {
+        Box::new(ScxmlInvokeHandler::new())
+    }
+}
@@ -5,13 +5,14 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::Read;
 use std::path::{Path, PathBuf};
+use std::rc::Rc;
 
 #[cfg(feature = "Debug_Reader")]
 #[cfg(test)]
 use std::println as debug;
 
 use std::str::FromStr;
-use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+use std::sync::atomic::Ordering;
 use std::time::{SystemTime, UNIX_EPOCH};
 use std::{env, mem, str, string::String};
 
@@ -20,7 +21,8 @@ use crate::ArgOption;
 #[cfg(feature = "Debug_Reader")]
 #[cfg(not(test))]
 use log::debug;
-use log::info;
+use log::{info, warn};
+use quick_xml::escape::unescape;
 use quick_xml::events::attributes::Attributes;
 use quick_xml::events::{BytesStart, Event};
 use quick_xml::Reader;
@@ -29,22 +31,23 @@ use crate::executable_content::{
     get_opt_executable_content_as, get_safe_executable_content_as, parse_duration_to_milliseconds, Assign, Cancel,
     ExecutableContent, Expression, ForEach, If, Log, Raise, SendParameters,
 };
-use crate::fsm::push_param;
+use crate::fsm::uri_scheme;
 #[cfg(feature = "Debug_Reader")]
 use crate::fsm::vec_to_string;
+use crate::fsm::{find_duplicate_param_name, push_param};
 use crate::fsm::{
-    map_history_type, map_transition_type, BindingType, DoneData, ExecutableContentId, Fsm, HistoryType, Invoke,
-    Parameter, State, StateId, Transition, TransitionId, TransitionType, ID_COUNTER,
+    map_history_type, map_transition_type, strip_event_descriptor, BindingType, DoneData, ExecutableContentId, Fsm,
+    HistoryType, Invoke, Parameter, SourceLine, State, StateId, Transition, TransitionId, TransitionType,
+    DOC_ID_COUNTER, ID_COUNTER, SOURCE_ID_COUNTER,
 };
 
-use crate::fsm::CommonContent;
+use crate::fsm::{CommonContent, ContentKind};
+use crate::invoke_handler::InvokeError;
+use crate::scxml_event_io_processor::SCXML_TARGET_INTERNAL;
 
 pub type AttributeMap = HashMap<String, String>;
 pub type XReader<'a> = Reader<&'a [u8]>;
 
-static DOC_ID_COUNTER: AtomicU32 = AtomicU32::new(1);
-static SOURCE_ID_COUNTER: AtomicUsize = AtomicUsize::new(1);
-
 pub static INCLUDE_PATH_ARGUMENT_OPTION: ArgOption = ArgOption {
     name: "includePaths",
     with_value: true,
@@ -239,6 +242,34 @@ pub const ATTR_EXPR: &str = "expr";
 
 pub const NS_XINCLUDE: &str = "http://www.w3.org/2001/XInclude";
 
+/// Namespace of the rFSM extensions. Use prefix "rfsm" for it, the reader doesn't resolve namespace prefixes.
+pub const NS_RFSM: &str = "https://github.com/BWeng20/rFSM";
+
+/// rFSM extension: \<param\> of \<invoke\> with "location" whose value is written back on completion.
+/// See [crate::fsm::Parameter::write_back].
+pub const ATTR_RFSM_WRITE_BACK: &str = "rfsm:writeback";
+
+/// rFSM extension: Name of the native action of a \<transition\>.
+/// See [crate::fsm_executor::FsmExecutor::register_transition_action].
+pub const ATTR_RFSM_ACTION: &str = "rfsm:action";
+
+/// rFSM extension: Timeout of a \<state\> or \<parallel\>, e.g. `<rfsm:timeout delay="5s" event="state.timeout"/>`.\
+/// The reader replaces it by a delayed \<send\> of the event to the session in \<onentry\> and a \<cancel\>
+/// of this send in \<onexit\>. Each element gets a generated "sendid", so multiple timeouts per state are possible.
+pub const TAG_RFSM_TIMEOUT: &str = "timeout";
+
+/// rFSM extension: \<history\> with `rfsm:resetonentry="true"` forgets the stored history if its parent or an
+/// ancestor is entered without a history state, e.g. by a transition to an ancestor that uses the initial transition.
+/// See [crate::fsm::State::reset_on_entry].
+pub const ATTR_RFSM_RESET_ON_ENTRY: &str = "rfsm:resetonentry";
+
+/// Attributes of the rFSM namespace, that are not handled as annotations.
+const RFSM_ATTRIBUTES: &[&str] = &[
+    ATTR_RFSM_WRITE_BACK,
+    ATTR_RFSM_ACTION,
+    ATTR_RFSM_RESET_ON_ENTRY,
+];
+
 struct ReaderStackItem {
     current_state: StateId,
     current_transition: TransitionId,
@@ -260,7 +291,8 @@ struct ReaderState {
     in_scxml: bool,
     id_count: i32,
     file: PathBuf,
-    content: String,
+    // Shared with the XML reader, that needs to borrow the content while the handlers slice it.
+    content: Rc<String>,
 
     // The resulting fsm
     fsm: Box<Fsm>,
@@ -270,6 +302,24 @@ struct ReaderState {
     executable_content_stack: Vec<(ExecutableContentId, &'static str)>,
     current_executable_content: ExecutableContentId,
     include_paths: Vec<PathBuf>,
+
+    // If false, XInclude elements are rejected.
+    allow_includes: bool,
+    // If true, attributes of other namespaces are rejected instead of being kept as annotations.
+    strict: bool,
+    // If true, invalid executable content is read and raises "error.execution" when executed
+    // instead of rejecting the document.
+    lenient: bool,
+    // The files that currently include other files, outermost first. Used to detect include cycles.
+    include_stack: Vec<PathBuf>,
+
+    // Error detected by an element handler. Stops processing, reported as result of the parser.
+    error: Option<String>,
+
+    // Line of the element that is currently processed, starting with 1.
+    current_line: SourceLine,
+    // Byte position in the content up to which lines were counted and the line at this position.
+    line_position: (usize, SourceLine),
 }
 
 impl ReaderState {
@@ -287,8 +337,15 @@ impl ReaderState {
             },
             fsm: Box::new(Fsm::new()),
             file: Path::new("Buffer").to_path_buf(),
-            content: "".to_string(),
+            content: Rc::new(String::new()),
             include_paths: Vec::new(),
+            allow_includes: true,
+            strict: false,
+            lenient: false,
+            include_stack: Vec::new(),
+            error: None,
+            current_line: 0,
+            line_position: (0, 1),
         }
     }
 
@@ -297,30 +354,45 @@ impl ReaderState {
     fn process_file(&mut self, file: &Path) -> Result<&str, String> {
         self.file.clone_from(&file.to_path_buf());
         match File::open(self.file.clone()) {
-            Ok(mut f) => {
-                self.content.clear();
-                match f.read_to_string(&mut self.content) {
-                    Ok(_len) => self.process(),
-                    Err(e) => Err(format!("Failed to read {:?}. {}", self.file, e)),
+            Ok(f) => match read_document(f) {
+                Ok(content) => {
+                    self.content = Rc::new(content);
+                    self.process()
                 }
-            }
+                Err(e) => Err(format!("Failed to read {:?}. {}", self.file, e)),
+            },
             Err(e) => Err(format!("Failed to open {:?}. {}", self.file, e)),
         }
     }
 
+    /// Sets the location of the document, used to resolve relative "src" and XInclude references.    /// For "http" and "https" URLs relative references are resolved against the URL, other values
+    /// are handled as path of a file.
+    fn set_base_uri(&mut self, base_uri: &str) {
+        match uri_scheme(base_uri).as_deref() {
+            Some("http") | Some("https") => {
+                self.fsm.base_uri = Some(base_uri.to_string());
+            }
+            _ => {
+                self.file = PathBuf::from(base_uri.strip_prefix("file://").unwrap_or(base_uri));
+                self.fsm.base_uri = Some(document_location(&self.file));
+            }
+        }
+    }
+
     /// Process all events from current content
     fn process(&mut self) -> Result<&str, String> {
         #[cfg(feature = "Debug_Reader")]
 
         debug!(">>> Reading {:?}", self.file);
 
-        // @TODO: reader needs a mutable reference to "content", for processing user content we need a read-only-ref.
-        // How we can share instead of "clone"?
-        let ct = self.content.clone();
+        // The reader borrows the content, the handlers need it to slice user content.
+        let ct = Rc::clone(&self.content);
         let mut reader = Reader::from_str(ct.as_str());
         reader.config_mut().trim_text(true);
 
         let mut txt = Vec::new();
+        // Included documents are read with the elements of the including document on stack.
+        let depth = self.stack.len();
         loop {
             match reader.read_event() {
                 Err(e) => {
@@ -328,22 +400,46 @@ impl ReaderState {
 
                     debug!("<<< {:?}", self.file);
                     return Err(format!(
-                        "Error at position {}: {:?}",
-                        reader.buffer_position(),
+                        "Error at byte offset {}: {:?}",
+                        reader.error_position(),
                         e
                     ));
                 }
-                Ok(Event::Eof) => break,
+                Ok(Event::Eof) => {
+                    if self.stack.len() > depth {
+                        return Err(format!(
+                            "Unexpected end of document at byte offset {}, <{}> is not closed",
+                            reader.buffer_position(),
+                            self.current.current_tag
+                        ));
+                    }
+                    if depth == 0 && self.fsm.pseudo_root == 0 {
+                        return Err("Document contains no <scxml> element".to_string());
+                    }
+                    break;
+                }
                 Ok(Event::Start(e)) => {
                     self.start_element(&mut reader, &e, true);
+                    if let Some(err) = self.error.take() {
+                        return Err(err);
+                    }
                 }
                 Ok(Event::End(e)) => {
                     self.end_element(str::from_utf8(e.local_name().as_ref()).unwrap());
+                    if let Some(err) = self.error.take() {
+                        return Err(err);
+                    }
                 }
                 Ok(Event::Empty(e)) => {
                     // Element without content.
                     self.start_element(&mut reader, &e, false);
+                    if let Some(err) = self.error.take() {
+                        return Err(err);
+                    }
                     self.end_element(str::from_utf8(e.local_name().as_ref()).unwrap());
+                    if let Some(err) = self.error.take() {
+                        return Err(err);
+                    }
                 }
                 Ok(Event::Text(e)) => txt.push(e.unescape().unwrap().into_owned()),
                 Ok(Event::Comment(_e)) => {
@@ -514,8 +610,26 @@ impl ReaderState {
         }
     }
 
+    /// Updates the current line to the line of the tag that ends at the byte position.\
+    /// Positions need to increase, lines are counted incrementally.
+    fn update_current_line(&mut self, end_position: usize) {
+        let end_position = end_position.min(self.content.len());
+        let (position, line) = self.line_position;
+        if end_position < position {
+            return;
+        }
+        let tag = &self.content.as_bytes()[position..end_position];
+        // The line of the start of the tag, attributes may span multiple lines.
+        let tag_start = tag.iter().rposition(|c| *c == b'<').unwrap_or(0);
+        let line = line + tag[..tag_start].iter().filter(|c| **c == b'\n').count() as SourceLine;
+        self.current_line = line;
+        self.line_position = (position + tag_start, line);
+    }
+
     /// Adds content to the current executable content region.
     fn add_executable_content(&mut self, ec: Box<dyn ExecutableContent>) {
+        let mut ec = ec;
+        ec.set_source_line(self.current_line);
         if self.current_executable_content == 0 {
             panic!("Try to add executable content to unsupported document part.");
         } else {
@@ -610,6 +724,7 @@ impl ReaderState {
                 // Create initial-transition with the initial states
                 let mut t = Transition::new();
                 t.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+                t.source_line = self.current_line;
                 t.transition_type = TransitionType::Internal;
                 t.source = id;
                 initial = t.id;
@@ -627,11 +742,15 @@ impl ReaderState {
             }
         }
 
+        let annotations = self.read_annotations(attr);
+        let source_line = self.current_line;
         let state = self.get_state_by_id_mut(id);
+        state.annotations = annotations;
         if initial != 0 {
             state.initial = initial;
         }
         state.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        state.source_line = source_line;
 
         if parent != 0 {
             state.parent = parent;
@@ -662,6 +781,29 @@ impl ReaderState {
         id
     }
 
+    /// Collects the attributes of other namespaces, e.g. "ui:x".\
+    /// Namespace declarations and the rFSM extensions are not included. In strict mode an error is
+    /// reported instead.
+    fn read_annotations(&mut self, attr: &AttributeMap) -> HashMap<String, String> {
+        let mut annotations = HashMap::new();
+        for (name, value) in attr {
+            if !name.contains(':') || name.starts_with("xmlns") || RFSM_ATTRIBUTES.contains(&name.as_str()) {
+                continue;
+            }
+            if self.strict {
+                if self.error.is_none() {
+                    self.error = Some(format!(
+                        "<{}>: unknown attribute '{}' (line {})",
+                        self.current.current_tag, name, self.current_line
+                    ));
+                }
+            } else {
+                annotations.insert(name.clone(), value.clone());
+            }
+        }
+        annotations
+    }
+
     fn get_required_attr<'a>(tag: &str, attribute: &str, attr: &'a AttributeMap) -> &'a String {
         let attr = attr.get(attribute);
         if attr.is_none() {
@@ -670,6 +812,47 @@ impl ReaderState {
         attr.unwrap()
     }
 
+    /// Reports invalid executable content, e.g. a missing required attribute.\
+    /// The document is rejected, in lenient mode the element is added and raises "error.execution"
+    /// when it is executed.
+    fn invalid_content(&mut self, tag: &str, line: SourceLine, message: &str) {
+        if self.lenient {
+            warn!("<{}>: {} (line {})", tag, message, line);
+        } else if self.error.is_none() {
+            self.error = Some(format!("<{}>: {} (line {})", tag, message, line));
+        }
+    }
+
+    /// Reports two attributes that must not occur both. The second attribute is ignored in lenient mode.
+    fn exclusive_attributes(&mut self, tag: &str, first: &str, second: &str) {
+        self.invalid_content(
+            tag,
+            self.current_line,
+            format!(
+                "attributes '{}' and '{}' must not occur both",
+                first, second
+            )
+            .as_str(),
+        );
+    }
+
+    /// Gets a required attribute of executable content. See [invalid_content](Self::invalid_content)
+    /// for attributes that are missing or empty.
+    fn get_content_attr(&mut self, tag: &str, attribute: &str, attr: &AttributeMap) -> String {
+        match attr.get(attribute) {
+            Some(value) if !value.trim().is_empty() => value.clone(),
+            _ => {
+                self.invalid_content(
+                    tag,
+                    self.current_line,
+                    format!("attribute '{}' is required", attribute).as_str(),
+                );
+                String::new()
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
     fn read_from_uri(&mut self, uri: &String) -> Result<String, String> {
         let url_result = reqwest::Url::parse(uri);
         match url_result {
@@ -693,6 +876,17 @@ impl ReaderState {
                 }
             }
             Err(_e) => {
+                // Relative references in documents that were loaded via HTTP are resolved against the document URL.
+                if let Some(url) = self
+                    .fsm
+                    .base_uri
+                    .as_ref()
+                    .filter(|base| matches!(uri_scheme(base).as_deref(), Some("http") | Some("https")))
+                    .and_then(|base| reqwest::Url::parse(base).ok())
+                    .and_then(|base| base.join(uri).ok())
+                {
+                    return self.read_from_uri(&url.to_string());
+                }
                 #[cfg(feature = "Debug_Reader")]
                 debug!(
                     "{} is not a URI ({}). Try loading as relative path...",
@@ -703,6 +897,39 @@ impl ReaderState {
         }
     }
 
+    /// In WASM builds there is no network access, so URIs are always loaded as relative paths.
+    #[cfg(target_arch = "wasm32")]
+    fn read_from_uri(&mut self, uri: &String) -> Result<String, String> {
+        self.read_from_relative_path(uri.strip_prefix("file:").unwrap_or(uri.as_str()))
+    }
+
+    /// Resolves the location of a data source relative to the current document.\
+    /// Non-file URLs and locations that can't be resolved are returned unchanged. Errors are reported
+    /// if the data is initialized.
+    fn resolve_data_source(&self, uri: &str) -> String {
+        #[cfg(not(target_arch = "wasm32"))]
+        let path = match reqwest::Url::parse(uri) {
+            Ok(url) => {
+                if url.scheme().eq_ignore_ascii_case("file") {
+                    url.path().to_string()
+                } else {
+                    return uri.to_string();
+                }
+            }
+            Err(_) => uri.to_string(),
+        };
+        #[cfg(target_arch = "wasm32")]
+        let path = uri.strip_prefix("file:").unwrap_or(uri).to_string();
+        match self.get_resolved_path(path.as_str()) {
+            Ok(resolved) => resolved.to_string_lossy().to_string(),
+            Err(_e) => {
+                #[cfg(feature = "Debug_Reader")]
+                debug!("Data source {} not resolved: {}", uri, _e);
+                uri.to_string()
+            }
+        }
+    }
+
     fn read_from_relative_path(&mut self, path: &str) -> Result<String, String> {
         let file_src = self.get_resolved_path(path)?;
         self.file.clone_from(&file_src);
@@ -762,6 +989,9 @@ impl ReaderState {
             None => hstate.history_type = HistoryType::Shallow,
             Some(type_name) => hstate.history_type = map_history_type(type_name),
         }
+        hstate.reset_on_entry = attr
+            .get(ATTR_RFSM_RESET_ON_ENTRY)
+            .is_some_and(|reset| reset.eq_ignore_ascii_case("true"));
         self.current.current_state = state_id;
         state_id
     }
@@ -798,7 +1028,7 @@ impl ReaderState {
         // but must not have both. Furthermore, if either attribute is present, the element must not have any children.
         // Thus 'src', 'expr' and children are mutually exclusive in the <data> element.
 
-        let data_value = if src.is_some() {
+        let data_value = if let Some(src) = src {
             if !(expr.is_none() && content.is_empty()) {
                 panic!(
                     "{} shall have only {}, {} or children, but not some combination of it.",
@@ -811,26 +1041,20 @@ impl ReaderState {
             // If the 'src' attribute is present, the Platform must fetch the specified object
             // at the time specified by the 'binding' attribute of \<scxml\> and must assign it as
             // the value of the data element
-
-            match self.read_from_uri(src.unwrap()) {
-                Ok(source) => {
-                    #[cfg(feature = "Debug_Reader")]
-
-                    debug!("src='{}':\n{}", src.unwrap(), source);
-                    source
-                }
-                Err(e) => {
-                    panic!("Can't read data source '{}'. {}", src.unwrap(), e);
-                }
-            }
-        } else if expr.is_some() {
+            // The content is fetched by the datamodel, here only the location is resolved.
+            let location = self.resolve_data_source(src);
+            self.get_current_state()
+                .data_src
+                .insert(id.to_string(), location);
+            String::new()
+        } else if let Some(expr) = expr {
             if !content.is_empty() {
                 panic!(
                     "{} shall have only {}, {} or children, but not some combination of it.",
                     TAG_DATA, ATTR_SRC, ATTR_EXPR
                 );
             }
-            expr.unwrap().clone()
+            expr.clone()
         } else if !content.is_empty() {
             content
         } else {
@@ -858,6 +1082,8 @@ impl ReaderState {
             .verify_parent_tag(TAG_INVOKE, &[TAG_STATE, TAG_PARALLEL])
             .to_string();
         let mut invoke = Invoke::new();
+        invoke.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        invoke.source_line = self.current_line;
 
         if let Some(type_opt) = attr.get(ATTR_TYPE) {
             invoke.type_name = self.create_source(type_opt.as_str());
@@ -917,6 +1143,8 @@ impl ReaderState {
 
         let mut t = Transition::new();
         t.doc_id = DOC_ID_COUNTER.fetch_add(1, Ordering::Relaxed);
+        t.source_line = self.current_line;
+        t.annotations = self.read_annotations(attr);
 
         // Start script.
         self.start_executable_content_region(false, TAG_TRANSITION);
@@ -926,29 +1154,7 @@ impl ReaderState {
             t.events = event
                 .unwrap()
                 .split_whitespace()
-                .map(|s| {
-                    // Strip redundant "." and ".*" suffix
-                    let mut rt = s;
-                    let mut do_it = true;
-                    while do_it {
-                        do_it = false;
-                        match rt.strip_suffix(".*") {
-                            None => {}
-                            Some(r) => {
-                                do_it = true;
-                                rt = r
-                            }
-                        }
-                        match rt.strip_suffix(".") {
-                            None => {}
-                            Some(r) => {
-                                do_it = true;
-                                rt = r
-                            }
-                        }
-                    }
-                    rt.to_string()
-                })
+                .map(|s| strip_event_descriptor(s).to_string())
                 .collect();
             t.wildcard = t.events.contains(&"*".to_string());
         }
@@ -971,6 +1177,8 @@ impl ReaderState {
             t.transition_type = map_transition_type(trans_type.unwrap())
         }
 
+        t.action = attr.get(ATTR_RFSM_ACTION).cloned();
+
         let state = self.get_current_state();
 
         if parent_tag.eq(TAG_INITIAL) {
@@ -1020,9 +1228,22 @@ impl ReaderState {
 
         let mut s = Expression::new();
 
-        let src = attr.get(ATTR_SRC);
-        if src.is_some() {
-            let file_src = src.unwrap();
+        let script_text = if has_content {
+            self.read_content(TAG_SCRIPT, reader)
+        } else {
+            String::new()
+        };
+        let script_text = script_text.trim();
+
+        if let Some(file_src) = attr.get(ATTR_SRC) {
+            // 'src' and child content are mutually exclusive, a document with both is rejected.
+            if !script_text.is_empty() {
+                self.error = Some(format!(
+                    "<{}> with '{}' attribute shall not have content.",
+                    TAG_SCRIPT, ATTR_SRC
+                ));
+                return;
+            }
             // W3C:
             // If the script can not be downloaded within a platform-specific timeout interval,
             // the document is considered non-conformant, and the platform must reject it.
@@ -1033,24 +1254,12 @@ impl ReaderState {
                     s.content = self.create_source_moved(source);
                 }
                 Err(e) => {
-                    panic!("Can't read script '{}'. {}", file_src, e);
+                    self.error = Some(format!("Can't read script '{}'. {}", file_src, e));
+                    return;
                 }
             }
-        }
-
-        let script_text = if has_content {
-            self.read_content(TAG_SCRIPT, reader)
-        } else {
-            String::new()
-        };
-
-        let src = script_text.trim();
-
-        if !src.is_empty() {
-            if !s.content.is_empty() {
-                panic!("<script> with 'src' attribute shall not have content.")
-            }
-            s.content = self.create_source_moved(src.to_string());
+        } else if !script_text.is_empty() {
+            s.content = self.create_source_moved(script_text.to_string());
         }
 
         self.add_executable_content(Box::new(s));
@@ -1074,9 +1283,9 @@ impl ReaderState {
 
         let ec_id = self.current_executable_content;
         let mut fe = ForEach::new();
-        fe.array = self.create_source(Self::get_required_attr(TAG_FOR_EACH, ATTR_ARRAY, attr));
-        fe.item
-            .clone_from(Self::get_required_attr(TAG_FOR_EACH, ATTR_ITEM, attr));
+        let array = self.get_content_attr(TAG_FOR_EACH, ATTR_ARRAY, attr);
+        fe.array = self.create_source_moved(array);
+        fe.item = self.get_content_attr(TAG_FOR_EACH, ATTR_ITEM, attr);
         if let Some(index) = attr.get(ATTR_INDEX) {
             fe.index.clone_from(index);
         }
@@ -1129,14 +1338,55 @@ impl ReaderState {
         } else if let Some(sendidexpr_value) = sendidexpr {
             cancel.send_id_expr = self.create_source(sendidexpr_value);
         } else {
-            panic!(
-                "{}: attribute {} or {} must be given",
-                TAG_CANCEL, ATTR_SENDID, ATTR_SENDIDEXPR
+            self.invalid_content(
+                TAG_CANCEL,
+                self.current_line,
+                format!(
+                    "attribute '{}' or '{}' is required",
+                    ATTR_SENDID, ATTR_SENDIDEXPR
+                )
+                .as_str(),
             );
         }
         self.add_executable_content(Box::new(cancel));
     }
 
+    /// Handles the rFSM extension, see [TAG_RFSM_TIMEOUT].
+    fn start_timeout(&mut self, attr: &AttributeMap) {
+        self.verify_parent_tag(TAG_RFSM_TIMEOUT, &[TAG_STATE, TAG_PARALLEL]);
+        let event = Self::get_required_attr(TAG_RFSM_TIMEOUT, ATTR_EVENT, attr);
+        let delay = Self::get_required_attr(TAG_RFSM_TIMEOUT, ATTR_DELAY, attr);
+        let delay_ms = match parse_duration_to_milliseconds(delay) {
+            Ok(delay_ms) => delay_ms,
+            Err(err) => {
+                panic!("{}: {} with {}", TAG_RFSM_TIMEOUT, ATTR_DELAY, err);
+            }
+        };
+        let send_id = format!("{}.{}", self.generate_name(), TAG_RFSM_TIMEOUT);
+
+        // Without target the event is added to the external queue of the session.
+        let mut send_params = SendParameters::new();
+        send_params.event = self.create_source(event);
+        send_params.name.clone_from(&send_id);
+        send_params.delay_ms = delay_ms;
+        send_params
+            .parent_state_name
+            .clone_from(&self.get_current_state().name);
+        self.start_executable_content_region(false, TAG_RFSM_TIMEOUT);
+        self.add_executable_content(Box::new(send_params));
+        let entry_id = self.end_executable_content_region(TAG_RFSM_TIMEOUT);
+
+        let mut cancel = Cancel::new();
+        cancel.send_id = send_id;
+        self.start_executable_content_region(false, TAG_RFSM_TIMEOUT);
+        self.add_executable_content(Box::new(cancel));
+        let exit_id = self.end_executable_content_region(TAG_RFSM_TIMEOUT);
+
+        let state = self.get_current_state();
+        state.onentry.push(entry_id);
+        state.onexit.push(exit_id);
+    }
+
     fn start_on_entry(&mut self, _attr: &AttributeMap) {
         self.verify_parent_tag(TAG_ON_ENTRY, &[TAG_STATE, TAG_PARALLEL, TAG_FINAL]);
         self.start_executable_content_region(false, TAG_ON_ENTRY);
@@ -1174,7 +1424,8 @@ impl ReaderState {
             ],
         );
 
-        let ec_if = If::new(self.create_source(Self::get_required_attr(TAG_IF, ATTR_COND, attr)));
+        let cond = self.get_content_attr(TAG_IF, ATTR_COND, attr);
+        let ec_if = If::new(self.create_source_moved(cond));
         self.add_executable_content(Box::new(ec_if));
         let if_id = self.current_executable_content;
 
@@ -1212,7 +1463,8 @@ impl ReaderState {
         let else_id = self.current_executable_content;
 
         // Add new "if"
-        let else_if = If::new(self.create_source(Self::get_required_attr(TAG_IF, ATTR_COND, attr)));
+        let cond = self.get_content_attr(TAG_ELSEIF, ATTR_COND, attr);
+        let else_if = If::new(self.create_source_moved(cond));
         self.add_executable_content(Box::new(else_if));
 
         let else_if_content_id = self.start_executable_content_region(true, TAG_ELSEIF);
@@ -1301,10 +1553,7 @@ impl ReaderState {
 
         if let Some(event_value) = event {
             if eventexpr.is_some() {
-                panic!(
-                    "{}: attributes {} and {} must not occur both",
-                    TAG_SEND, ATTR_EVENT, ATTR_EVENTEXPR
-                );
+                self.exclusive_attributes(TAG_SEND, ATTR_EVENT, ATTR_EVENTEXPR);
             }
             send_params.event = self.create_source(event_value);
         } else if let Some(eventexpr_value) = eventexpr {
@@ -1315,10 +1564,7 @@ impl ReaderState {
         let targetexpr = attr.get(ATTR_TARGETEXPR);
         if let Some(target_val) = target {
             if targetexpr.is_some() {
-                panic!(
-                    "{}: attributes {} and {} must not occur both",
-                    TAG_SEND, ATTR_TARGET, ATTR_TARGETEXPR
-                );
+                self.exclusive_attributes(TAG_SEND, ATTR_TARGET, ATTR_TARGETEXPR);
             }
             send_params.target = self.create_source(target_val);
         } else if let Some(targetexpr_value) = targetexpr {
@@ -1329,10 +1575,7 @@ impl ReaderState {
         let typeexpr = attr.get(ATTR_TYPEEXPR);
         if let Some(type_attr_value) = type_attr {
             if typeexpr.is_some() {
-                panic!(
-                    "{}: attributes {} and {} must not occur both",
-                    TAG_SEND, ATTR_TYPE, ATTR_TYPEEXPR
-                );
+                self.exclusive_attributes(TAG_SEND, ATTR_TYPE, ATTR_TYPEEXPR);
             }
             send_params.type_value = self.create_source(type_attr_value);
         } else if let Some(typeexpr_value) = typeexpr {
@@ -1343,10 +1586,7 @@ impl ReaderState {
         let idlocation = attr.get(ATTR_IDLOCATION);
         if let Some(id_value) = id {
             if idlocation.is_some() {
-                panic!(
-                    "{}: attributes {} and {} must not occur both",
-                    TAG_SEND, ATTR_ID, ATTR_IDLOCATION
-                );
+                self.exclusive_attributes(TAG_SEND, ATTR_ID, ATTR_IDLOCATION);
             }
             send_params.name.clone_from(id_value);
         } else if let Some(idlocation_value) = idlocation {
@@ -1358,32 +1598,29 @@ impl ReaderState {
 
         if let Some(delay_expr_attr_value) = delay_expr_attr {
             if delay_attr.is_some() {
-                panic!(
-                    "{}: attributes {} and {} must not occur both",
-                    TAG_SEND, ATTR_DELAY, ATTR_DELAYEXPR
-                );
+                self.exclusive_attributes(TAG_SEND, ATTR_DELAY, ATTR_DELAYEXPR);
             }
             send_params.delay_expr = self.create_source(delay_expr_attr_value);
-        } else if delay_attr.is_some() {
-            if (!delay_attr.unwrap().is_empty()) && type_attr.is_some() && type_attr.unwrap().eq(TARGET_INTERNAL) {
-                panic!(
-                    "{}: {} with {} {} is not possible",
-                    TAG_SEND,
-                    ATTR_DELAY,
-                    ATTR_TARGET,
-                    type_attr.unwrap()
-                );
+        } else if let Some(delay_attr_value) = delay_attr {
+            match parse_duration_to_milliseconds(delay_attr_value) {
+                Err(err) => {
+                    panic!("{}: {} with {}", TAG_SEND, ATTR_DELAY, err);
+                }
+                Ok(delay_ms) => {
+                    send_params.delay_ms = delay_ms;
+                }
             }
-            let delayms = parse_duration_to_milliseconds(delay_attr.unwrap());
-            if delayms < 0 {
-                panic!(
-                    "{}: {} with illegal value '{}'",
+            // In lenient mode the delay is kept, the send raises "error.execution".
+            if send_params.delay_ms > 0 && target.is_some_and(|t| t.eq(SCXML_TARGET_INTERNAL)) {
+                self.invalid_content(
                     TAG_SEND,
-                    ATTR_DELAY,
-                    delay_attr.unwrap()
+                    self.current_line,
+                    format!(
+                        "attribute '{}' is not possible with {} '{}'",
+                        ATTR_DELAY, ATTR_TARGET, SCXML_TARGET_INTERNAL
+                    )
+                    .as_str(),
                 );
-            } else {
-                send_params.delay_ms = delayms as u64;
             }
         }
 
@@ -1396,6 +1633,25 @@ impl ReaderState {
         self.add_executable_content(Box::new(send_params));
     }
 
+    /// Checks the \<send\> element after its \<content\> and \<param\> children are read.
+    fn end_send(&mut self) {
+        let ec = self.get_last_executable_content_entry_for_region(self.current_executable_content);
+        if let Some(send) = get_opt_executable_content_as::<SendParameters>(ec) {
+            if send.misses_event() {
+                let line = send.source_line;
+                self.invalid_content(
+                    TAG_SEND,
+                    line,
+                    format!(
+                        "attribute '{}' or '{}' or <{}> is required",
+                        ATTR_EVENT, ATTR_EVENTEXPR, TAG_CONTENT
+                    )
+                    .as_str(),
+                );
+            }
+        }
+    }
+
     /// Reads the content until an end-tag is encountered.
     fn read_content(&mut self, tag: &str, reader: &mut XReader) -> String {
         let start = BytesStart::new(tag.to_string());
@@ -1421,16 +1677,28 @@ impl ReaderState {
         content
     }
 
+    /// Reads the child content of \<content\> or \<assign\> and detects its kind.\
+    /// Character data is decoded, markup (e.g. an inline \<scxml\> document) is kept as source.
+    fn read_common_content(&mut self, tag: &str, reader: &mut XReader) -> (String, ContentKind) {
+        let content = self.read_content(tag, reader);
+        if content.starts_with('<') && !content.starts_with(CDATA_START) {
+            (content, ContentKind::Markup)
+        } else {
+            (decode_character_data(content.as_str()), ContentKind::Text)
+        }
+    }
+
     fn start_content(&mut self, attr: &AttributeMap, reader: &mut XReader, has_content: bool) {
         self.verify_parent_tag(TAG_CONTENT, &[TAG_SEND, TAG_INVOKE, TAG_DONEDATA]);
 
         let parent_tag = self.get_parent_tag().to_string();
         let expr = attr.get(ATTR_EXPR);
 
-        let content = if has_content {
-            Some(self.read_content(TAG_CONTENT, reader))
+        let (content, content_kind) = if has_content {
+            let (content, content_kind) = self.read_common_content(TAG_CONTENT, reader);
+            (Some(content), content_kind)
         } else {
-            None
+            (None, ContentKind::Text)
         };
 
         // W3C:
@@ -1450,6 +1718,7 @@ impl ReaderState {
                         dd.content = Some(CommonContent {
                             content,
                             content_expr: expr.map(|x| x.to_string()),
+                            content_kind,
                         });
                     }
                     None => {
@@ -1463,6 +1732,7 @@ impl ReaderState {
                 invoke.content = Some(CommonContent {
                     content,
                     content_expr: expr.map(|x| x.to_string()),
+                    content_kind,
                 });
             }
             TAG_SEND => {
@@ -1474,6 +1744,7 @@ impl ReaderState {
                         send.content = Some(CommonContent {
                             content_expr: Option::map(expr, |v| v.clone()),
                             content,
+                            content_kind,
                         });
                     }
                 }
@@ -1511,6 +1782,26 @@ impl ReaderState {
             }
             param.location = location_value.to_string();
         }
+        if let Some(write_back) = attr.get(ATTR_RFSM_WRITE_BACK) {
+            param.write_back = write_back.eq_ignore_ascii_case("true");
+            if param.write_back && (param.location.is_empty() || parent_tag != TAG_INVOKE) {
+                panic!(
+                    "{} is only supported for {} with {} inside {}.",
+                    ATTR_RFSM_WRITE_BACK, TAG_PARAM, ATTR_LOCATION, TAG_INVOKE
+                );
+            }
+        }
+
+        // Names given more than once are reported here, but the document is still accepted.
+        // W3C requires error.execution at runtime.
+        let warn_duplicate = |name_list: &[String], params: &Option<Vec<Parameter>>| {
+            if let Some(name) = find_duplicate_param_name(name_list, params) {
+                warn!(
+                    "<{}>: '{}' is given more than once in '{}' or <{}>",
+                    parent_tag, name, ATTR_NAMELIST, TAG_PARAM
+                );
+            }
+        };
 
         match parent_tag.as_str() {
             TAG_SEND => {
@@ -1518,16 +1809,20 @@ impl ReaderState {
                 let ec = self.get_last_executable_content_entry_for_region(ec_id);
                 let send = get_safe_executable_content_as::<SendParameters>(ec.unwrap());
                 push_param(&mut send.params, param);
+                warn_duplicate(&send.name_list, &send.params);
             }
             TAG_INVOKE => {
                 let state = self.get_current_state();
-                push_param(&mut state.invoke.last_mut().params, param);
+                let invoke = state.invoke.last_mut();
+                push_param(&mut invoke.params, param);
+                warn_duplicate(&invoke.name_list, &invoke.params);
             }
             TAG_DONEDATA => {
                 let state = self.get_current_state();
                 match state.donedata.as_mut() {
                     Some(dd) => {
                         push_param(&mut dd.params, param);
+                        warn_duplicate(&[], &dd.params);
                     }
                     None => {
                         panic!("Internal Error: donedata-Option not initialized")
@@ -1577,31 +1872,24 @@ impl ReaderState {
         );
 
         let mut assign = Assign::new();
-        assign.location = self.create_source(Self::get_required_attr(TAG_ASSIGN, ATTR_LOCATION, attr));
+        let location = self.get_content_attr(TAG_ASSIGN, ATTR_LOCATION, attr);
+        assign.location = self.create_source_moved(location);
 
         let expr = attr.get(ATTR_EXPR);
         if let Some(expr_value) = expr {
             assign.expr = self.create_source(expr_value);
         }
 
-        let assign_text = if has_content {
-            format!(
-                "\"{}\"",
-                self.read_content(TAG_ASSIGN, reader)
-                    .replace("\"", "\\\"")
-                    .replace("\n", " ")
-            )
-        } else {
-            String::new()
-        };
-
-        let assign_src = assign_text.trim();
-
-        if !assign_src.is_empty() {
-            if !assign.expr.is_empty() {
-                panic!("<assign> with 'expr' attribute shall not have content.")
+        if has_content {
+            let (content, content_kind) = self.read_common_content(TAG_ASSIGN, reader);
+            if !content.is_empty() {
+                // Having both 'expr' and content is reported as "error.execution" at runtime.
+                assign.content = Some(CommonContent {
+                    content: Some(content),
+                    content_expr: None,
+                    content_kind,
+                });
             }
-            assign.expr = self.create_source_moved(assign_src.to_string());
         }
 
         self.add_executable_content(Box::new(assign));
@@ -1620,9 +1908,7 @@ impl ReaderState {
         );
 
         let mut raise = Raise::new();
-        raise
-            .event
-            .clone_from(Self::get_required_attr(TAG_RAISE, ATTR_EVENT, attr));
+        raise.event = self.get_content_attr(TAG_RAISE, ATTR_EVENT, attr);
 
         self.add_executable_content(Box::new(raise));
     }
@@ -1632,6 +1918,7 @@ impl ReaderState {
             panic!("Only one <{}> allowed", TAG_SCXML);
         }
         self.in_scxml = true;
+        self.fsm.annotations = self.read_annotations(attr);
         match attr.get(ATTR_NAME) {
             Some(n) => {
                 self.fsm.name.clone_from(n);
@@ -1706,6 +1993,7 @@ impl ReaderState {
         debug!("Start Element {}", name);
 
         let attr = &decode_attributes(reader, &mut e.attributes());
+        self.update_current_line(reader.buffer_position() as usize);
 
         match name {
             TAG_INCLUDE => {
@@ -1780,6 +2068,9 @@ impl ReaderState {
             TAG_CANCEL => {
                 self.start_cancel(attr);
             }
+            TAG_RFSM_TIMEOUT => {
+                self.start_timeout(attr);
+            }
             TAG_IF => {
                 self.start_if(attr);
             }
@@ -1798,6 +2089,10 @@ impl ReaderState {
 
     /// Try to resolve the file name relative to the current file or include paths.
     fn get_resolved_path(&self, ps: &str) -> Result<PathBuf, String> {
+        let absolute = Path::new(ps);
+        if absolute.is_absolute() && absolute.exists() {
+            return Ok(absolute.to_path_buf());
+        }
         let mut ps = ps.to_string();
         while ps.starts_with('\\') || ps.starts_with('/') {
             ps.remove(0);
@@ -1829,38 +2124,68 @@ impl ReaderState {
 
     /// Handle a XInclude include element.
     /// See https://www.w3.org/TR/xinclude/
-    /// Only parse="text" and "href" with a relative path are supported, also no "xpointer" etc.
+    /// The referenced file is parsed and its content spliced into the document at the position of the
+    /// include element. Relative hrefs are resolved against the including file, then against the include paths.\
+    /// Only parse="xml" (the default) is supported, "text" is accepted for compatibility and handled the same way.
+    /// "xpointer" etc. are not supported.
     fn include(&mut self, attr: &AttributeMap) {
         let href = Self::get_required_attr(TAG_INCLUDE, TAG_HREF, attr);
-        let parse = attr.get(ATTR_PARSE);
-        if parse.is_none() || parse.unwrap().ne("text") {
-            panic!("{}: only {}='text' is supported", TAG_INCLUDE, ATTR_PARSE)
+        if let Some(parse) = attr.get(ATTR_PARSE) {
+            if parse.ne("xml") && parse.ne("text") {
+                panic!(
+                    "{}: {}='{}' is not supported",
+                    TAG_INCLUDE, ATTR_PARSE, parse
+                )
+            }
         }
         let xpointer = attr.get(ATTR_XPOINTER);
         if xpointer.is_some() {
             panic!("{}: {} is not supported", TAG_INCLUDE, ATTR_XPOINTER)
         }
+        if !self.allow_includes {
+            self.error = Some(format!("Includes are disabled, can't include '{}'", href));
+            return;
+        }
 
-        // remove "include" from parent-stack as long as we read the content.
-        self.pop();
-
-        match self.get_resolved_path(href.as_str()) {
-            Ok(src) => {
-                let org_file = mem::take(&mut self.file);
-                let org_content = mem::take(&mut self.content);
-                let rs = self.process_file(&src);
-                if rs.is_err() {
-                    panic!("Failed to read {:?}. {}", src, rs.err().unwrap());
-                }
-                self.file = org_file;
-                self.content = org_content;
-            }
+        let src = match self.get_resolved_path(href.as_str()) {
+            Ok(src) => src.canonicalize().unwrap_or(src),
             Err(err) => {
-                panic!("Can resolve path {}: {}", href, err);
+                self.error = Some(format!("Can't resolve include '{}'. {}", href, err));
+                return;
             }
+        };
+        let including = self.file.canonicalize().unwrap_or(self.file.clone());
+        if src == including || self.include_stack.contains(&src) {
+            let mut cycle: Vec<String> = self
+                .include_stack
+                .iter()
+                .skip_while(|p| **p != src)
+                .map(|p| p.to_string_lossy().to_string())
+                .collect();
+            cycle.push(including.to_string_lossy().to_string());
+            cycle.push(src.to_string_lossy().to_string());
+            self.error = Some(format!("Include cycle detected: {}", cycle.join(" -> ")));
+            return;
         }
 
+        // remove "include" from parent-stack as long as we read the content.
+        self.pop();
+        self.include_stack.push(including);
+
+        let org_file = mem::take(&mut self.file);
+        let org_content = mem::take(&mut self.content);
+        let org_line_position = mem::replace(&mut self.line_position, (0, 1));
+        let rs = self.process_file(&src).map(|_| ());
+        self.file = org_file;
+        self.content = org_content;
+        self.line_position = org_line_position;
+
+        self.include_stack.pop();
         self.push(TAG_INCLUDE);
+
+        if let Err(err) = rs {
+            self.error = Some(err);
+        }
     }
 
     /// Called from SAX handler if some end-tag was read.
@@ -1896,6 +2221,9 @@ impl ReaderState {
             TAG_FINALIZE => {
                 self.end_finalize();
             }
+            TAG_SEND => {
+                self.end_send();
+            }
             TAG_STATE => {
                 self.end_state();
             }
@@ -1908,6 +2236,37 @@ impl ReaderState {
 /**
  * Decodes attributes into a hash-map
  */
+const CDATA_START: &str = "<![CDATA[";
+const CDATA_END: &str = "]]>";
+
+/// Decodes character data from the XML source: entities are replaced and CDATA sections are unwrapped.\
+/// If the data can't be decoded, it is returned unchanged.
+fn decode_character_data(source: &str) -> String {
+    let mut result = String::with_capacity(source.len());
+    let mut rest = source;
+    while !rest.is_empty() {
+        let (text, cdata) = match rest.find(CDATA_START) {
+            Some(start) => (&rest[..start], Some(&rest[start + CDATA_START.len()..])),
+            None => (rest, None),
+        };
+        match unescape(text) {
+            Ok(unescaped) => result.push_str(unescaped.as_ref()),
+            Err(_) => result.push_str(text),
+        }
+        rest = "";
+        if let Some(cdata) = cdata {
+            match cdata.find(CDATA_END) {
+                Some(end) => {
+                    result.push_str(&cdata[..end]);
+                    rest = &cdata[end + CDATA_END.len()..];
+                }
+                None => result.push_str(cdata),
+            }
+        }
+    }
+    result.trim().to_string()
+}
+
 fn decode_attributes(reader: &XReader, attr: &mut Attributes) -> AttributeMap {
     attr.map(|attr_result| match attr_result {
         Ok(a) => {
@@ -1936,10 +2295,19 @@ fn decode_attributes(reader: &XReader, attr: &mut Attributes) -> AttributeMap {
     .collect()
 }
 
+/// Gets the absolute location of a document, used as base for relative references.
+fn document_location(file: &Path) -> String {
+    file.canonicalize()
+        .unwrap_or(file.to_path_buf())
+        .to_string_lossy()
+        .to_string()
+}
+
 /// Read and parse the FSM from an XML file
 pub fn parse_from_xml_file(file: &Path, include_paths: &[PathBuf]) -> Result<Box<Fsm>, String> {
     let mut rs = ReaderState::new();
     rs.include_paths = Vec::from(include_paths);
+    rs.fsm.base_uri = Some(document_location(file));
     let r = rs.process_file(file);
     match r {
         Ok(_m) => Ok(rs.fsm),
@@ -1949,12 +2317,36 @@ pub fn parse_from_xml_file(file: &Path, include_paths: &[PathBuf]) -> Result<Box
 
 /// Read and parse the FSM from an URI
 pub fn parse_from_uri(uri: String, include_paths: &[PathBuf]) -> Result<Box<Fsm>, String> {
+    parse_from_uri_with_options(uri, include_paths, true)
+}
+
+/// Read and parse the FSM from an URI.\
+/// If "allow_includes" is false, documents with XInclude elements are rejected. Use this for untrusted input.
+pub fn parse_from_uri_with_options(
+    uri: String,
+    include_paths: &[PathBuf],
+    allow_includes: bool,
+) -> Result<Box<Fsm>, String> {
+    load_from_uri(uri, include_paths, allow_includes).map_err(|err| err.to_string())
+}
+
+/// Reads and parses the FSM like [parse_from_uri_with_options], but reports if the document can't be retrieved.
+pub(crate) fn load_from_uri(
+    uri: String,
+    include_paths: &[PathBuf],
+    allow_includes: bool,
+) -> Result<Box<Fsm>, InvokeError> {
     let start = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
     let mut rs = ReaderState::new();
     rs.include_paths = Vec::from(include_paths);
+    rs.allow_includes = allow_includes;
     match rs.read_from_uri(&uri) {
         Ok(source) => {
-            rs.content = source;
+            rs.fsm.base_uri = Some(match uri_scheme(uri.as_str()).as_deref() {
+                Some("http") | Some("https") => uri.clone(),
+                _ => document_location(&rs.file),
+            });
+            rs.content = Rc::new(source);
             let r = rs.process();
             match r {
                 Ok(_m) => {
@@ -1966,29 +2358,94 @@ pub fn parse_from_uri(uri: String, include_paths: &[PathBuf]) -> Result<Box<Fsm>
                     );
                     Ok(rs.fsm)
                 }
-                Err(e) => Err(e),
+                Err(e) => Err(InvokeError::Parse(e)),
             }
         }
-        Err(err) => Err(err),
+        Err(err) => Err(InvokeError::Retrieval(err)),
     }
 }
 
+/// Reads the whole document into a String.\
+/// The bytes are moved into the String, invalid UTF-8 is reported with the byte offset.
+fn read_document<R: Read>(mut input: R) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    input.read_to_end(&mut bytes).map_err(|e| e.to_string())?;
+    String::from_utf8(bytes).map_err(|e| {
+        format!(
+            "Invalid UTF-8 at byte offset {}",
+            e.utf8_error().valid_up_to()
+        )
+    })
+}
+
 /// Reads the FSM from a XML String
 pub fn parse_from_xml(xml: String) -> Result<Box<Fsm>, String> {
     let mut rs = ReaderState::new();
-    rs.content = xml;
-    let r = rs.process();
-    match r {
-        Ok(_m) => Ok(rs.fsm),
-        Err(e) => Err(e),
+    rs.content = Rc::new(xml);
+    rs.process()?;
+    Ok(rs.fsm)
+}
+
+/// Reads the FSM from any source, e.g. a network stream.\
+/// The content of \<script\>, \<data\> and \<content\> elements is taken from the source text,
+/// so the document is read into one buffer that is parsed in place.\
+/// "base_uri" is the location of the document, relative "src" attributes and XIncludes are resolved against it.
+/// Without "base_uri" they are resolved against the current working directory.
+pub fn parse_from_reader<R: Read>(input: R, base_uri: Option<&str>) -> Result<Box<Fsm>, String> {
+    let mut rs = ReaderState::new();
+    if let Some(base_uri) = base_uri {
+        rs.set_base_uri(base_uri);
     }
+    rs.content = Rc::new(read_document(input)?);
+    rs.process()?;
+    Ok(rs.fsm)
+}
+
+/// Reads the FSM from in-memory bytes, e.g. a memory-mapped file.\
+/// See [parse_from_reader].
+pub fn parse_from_bytes(xml: &[u8]) -> Result<Box<Fsm>, String> {
+    parse_from_reader(xml, None)
+}
+
+/// Reads the FSM from a XML String like [parse_from_xml], but rejects attributes of other namespaces
+/// on \<scxml\>, \<state\>, \<parallel\>, \<final\> and \<transition\>.\
+/// Otherwise these attributes (e.g. "ui:x" or "doc:comment") are kept as annotations,
+/// see [State::annotations], [Transition::annotations] and [Fsm::annotations].
+pub fn parse_from_xml_strict(xml: String) -> Result<Box<Fsm>, String> {
+    let mut rs = ReaderState::new();
+    rs.strict = true;
+    rs.content = Rc::new(xml);
+    rs.process()?;
+    Ok(rs.fsm)
+}
+
+/// Reads the FSM from a XML String like [parse_from_xml], but accepts executable content with missing
+/// required attributes (e.g. \<raise\> without 'event' or \<if\> without 'cond').\
+/// Otherwise such documents are rejected. In lenient mode these elements raise "error.execution" when executed.
+pub fn parse_from_xml_lenient(xml: String) -> Result<Box<Fsm>, String> {
+    let mut rs = ReaderState::new();
+    rs.lenient = true;
+    rs.content = Rc::new(xml);
+    rs.process()?;
+    Ok(rs.fsm)
 }
 
 /// Reads the FSM from a XML String
 pub fn parse_from_xml_with_includes(xml: String, include_paths: &[PathBuf]) -> Result<Box<Fsm>, String> {
+    parse_from_xml_with_options(xml, include_paths, true)
+}
+
+/// Reads the FSM from a XML String.\
+/// If "allow_includes" is false, documents with XInclude elements are rejected. Use this for untrusted input.
+pub fn parse_from_xml_with_options(
+    xml: String,
+    include_paths: &[PathBuf],
+    allow_includes: bool,
+) -> Result<Box<Fsm>, String> {
     let mut rs = ReaderState::new();
     rs.include_paths = Vec::from(include_paths);
-    rs.content = xml;
+    rs.allow_includes = allow_includes;
+    rs.content = Rc::new(xml);
     let r = rs.process();
     match r {
         Ok(_m) => Ok(rs.fsm),
@@ -1998,7 +2455,13 @@ pub fn parse_from_xml_with_includes(xml: String, include_paths: &[PathBuf]) -> R
 
 #[cfg(test)]
 mod tests {
+    use crate::actions::ActionWrapper;
+    use crate::clock::TestClock;
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, Event, FinishMode};
+    use crate::fsm_executor::FsmExecutor;
     use log::debug;
+    use std::sync::Arc;
+    use std::time::Duration;
 
     #[test]
     #[should_panic]
@@ -2011,10 +2474,44 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn script_with_src_and_content_should_panic() {
-        let _r = crate::scxml_reader::parse_from_xml("<scxml initial='Main'><state id='Main'>\
+    fn script_with_src_and_content_should_fail() {
+        let r = crate::scxml_reader::parse_from_xml("<scxml initial='Main'><state id='Main'>\
     <initial><transition><script src='xml/example/script.js'>println();</script></transition></initial></state></scxml>".to_string());
+        assert!(r.is_err(), "Shall result in error");
+    }
+
+    #[test]
+    fn script_with_relative_src_should_load_file() {
+        let dir = std::env::temp_dir().join(format!("rfsm_script_src_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("lib")).unwrap();
+        std::fs::write(dir.join("lib").join("init.js"), "var loaded = 1;").unwrap();
+        let document = dir.join("main.scxml");
+        std::fs::write(
+            &document,
+            "<scxml initial='Main'><script src='lib/init.js'/><state id='Main'/></scxml>",
+        )
+        .unwrap();
+
+        let r = crate::scxml_reader::parse_from_xml_file(&document, &[]);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(r.is_ok(), "{:?}", r.err());
+        let fsm = r.unwrap();
+        assert_ne!(fsm.script, 0);
+        let script = format!("{:?}", fsm.executableContent.get(&fsm.script).unwrap());
+        assert!(script.contains("var loaded = 1;"), "{}", script);
+    }
+
+    #[test]
+    fn script_with_missing_src_should_fail() {
+        let r = crate::scxml_reader::parse_from_xml(
+            "<scxml initial='Main'><state id='Main'><onentry><script src='xml/example/missing.js'/></onentry>\
+            </state></scxml>"
+                .to_string(),
+        );
+        assert!(r.is_err(), "Shall result in error");
+        let err = r.err().unwrap();
+        assert!(err.contains("xml/example/missing.js"), "{}", err);
     }
 
     #[test]
@@ -2062,16 +2559,17 @@ mod tests {
     #[should_panic]
     fn wrong_parse_in_xinclude_should_panic() {
         let _r = crate::scxml_reader::parse_from_xml(
-            "<scxml><state><include href='xml/example/Test2Sub1.xml' parse='xml'/></state></scxml>".to_string(),
+            "<scxml><state><include href='xml/example/Test2Sub1.xml' parse='html'/></state></scxml>".to_string(),
         );
     }
 
     #[test]
-    #[should_panic]
-    fn none_parse_in_xinclude_should_panic() {
-        let _r = crate::scxml_reader::parse_from_xml(
+    fn none_parse_in_xinclude_should_read() {
+        let r = crate::scxml_reader::parse_from_xml(
             "<scxml><state><include href='xml/example/Test2Sub1.xml'/></state></scxml>".to_string(),
         );
+        assert!(r.is_ok(), "{:?}", r.err());
+        assert!(r.unwrap().statesNames.contains_key("Test2Sub1"));
     }
 
     #[test]
@@ -2090,6 +2588,97 @@ mod tests {
         );
     }
 
+    #[test]
+    fn xinclude_states_from_included_files_reach_final() {
+        let dir = std::env::temp_dir().join(format!("rfsm_xinclude_{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("parts")).unwrap();
+        std::fs::write(
+            dir.join("parts").join("first.xml"),
+            "<state id='A' xmlns:xi='http://www.w3.org/2001/XInclude'><transition target='B'/></state>\
+            <xi:include href='second.xml'/>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("parts").join("second.xml"),
+            "<state id='B'><transition target='done'/></state>",
+        )
+        .unwrap();
+        let document = dir.join("main.scxml");
+        std::fs::write(
+            &document,
+            "<scxml initial='A' datamodel='null' xmlns:xi='http://www.w3.org/2001/XInclude'>\
+            <xi:include href='parts/first.xml'/><final id='done'/></scxml>",
+        )
+        .unwrap();
+
+        let r = crate::scxml_reader::parse_from_xml_file(&document, &[]);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(r.is_ok(), "{:?}", r.err());
+
+        assert!(crate::test::run_test_manual(
+            "xinclude_states_from_included_files_reach_final",
+            &std::collections::HashMap::new(),
+            r.unwrap(),
+            &Vec::new(),
+            #[cfg(feature = "Trace")]
+            crate::tracer::TraceMode::STATES,
+            2000u64,
+            &vec!["done".to_string()],
+        ));
+    }
+
+    #[test]
+    fn xinclude_cycle_should_fail() {
+        let dir = std::env::temp_dir().join(format!("rfsm_xinclude_cycle_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("a.xml"),
+            "<state id='A'><include href='b.xml'/></state>",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("b.xml"),
+            "<state id='B'><include href='a.xml'/></state>",
+        )
+        .unwrap();
+        let document = dir.join("main.scxml");
+        std::fs::write(
+            &document,
+            "<scxml initial='A'><include href='a.xml'/></scxml>",
+        )
+        .unwrap();
+
+        let r = crate::scxml_reader::parse_from_xml_file(&document, &[]);
+        let _ = std::fs::remove_dir_all(&dir);
+        assert!(r.is_err(), "Shall result in error");
+        let err = r.err().unwrap();
+        assert!(err.contains("Include cycle detected"), "{}", err);
+        assert!(
+            err.contains(
+                format!(
+                    "{} -> {} -> {}",
+                    dir.join("a.xml").to_string_lossy(),
+                    dir.join("b.xml").to_string_lossy(),
+                    dir.join("a.xml").to_string_lossy()
+                )
+                .as_str()
+            ),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn disabled_xinclude_should_fail() {
+        let r = crate::scxml_reader::parse_from_xml_with_options(
+            "<scxml><state><include href='xml/example/Test2Sub1.xml'/></state></scxml>".to_string(),
+            &[],
+            false,
+        );
+        assert!(r.is_err(), "Shall result in error");
+        assert!(r.err().unwrap().contains("Includes are disabled"));
+    }
+
     #[test]
     #[should_panic]
     fn wrong_transition_type_should_panic() {
@@ -2113,12 +2702,13 @@ mod tests {
     }
 
     #[test]
-    #[should_panic]
-    fn assign_with_expr_and_content_shall_panic() {
-        let _r = crate::scxml_reader::parse_from_xml(
+    fn assign_with_expr_and_content() {
+        // Reported as "error.execution" at runtime.
+        let r = crate::scxml_reader::parse_from_xml(
             "<scxml><state><transition><assign location='x' expr='123'>123</assign></transition></state></scxml>"
                 .to_string(),
         );
+        assert!(r.is_ok());
     }
 
     #[test]
@@ -2127,4 +2717,259 @@ mod tests {
             "<scxml><state><transition><assign location='x'>123</assign></transition></state></scxml>".to_string(),
         );
     }
+
+    #[test]
+    fn attributes_of_other_namespaces_are_kept_as_annotations() {
+        let xml = r##"<scxml xmlns='http://www.w3.org/2005/07/scxml' xmlns:ui='http://example.com/ui'
+                xmlns:doc='http://example.com/doc' initial='A' ui:zoom='2'>
+              <state id='A' ui:x='10' ui:y='20' doc:comment='Start here'>
+                <transition event='go' target='B' ui:color='red'/>
+              </state>
+              <final id='B'/>
+            </scxml>"##;
+        let fsm = crate::scxml_reader::parse_from_xml(xml.to_string()).unwrap();
+
+        assert_eq!(
+            fsm.annotations.get("ui:zoom").map(|s| s.as_str()),
+            Some("2")
+        );
+        assert!(!fsm.annotations.contains_key("xmlns:ui"));
+        assert_eq!(fsm.state_annotation("A", "ui:x"), Some("10"));
+        assert_eq!(fsm.state_annotation("A", "ui:y"), Some("20"));
+        assert_eq!(fsm.state_annotation("A", "doc:comment"), Some("Start here"));
+        assert_eq!(fsm.state_annotation("B", "ui:x"), None);
+
+        let a = fsm.get_state_by_id(*fsm.statesNames.get("A").unwrap());
+        let transition = fsm.get_transition_by_id(*a.transitions.iterator().next().unwrap());
+        assert_eq!(transition.annotation("ui:color"), Some("red"));
+
+        assert!(crate::scxml_reader::parse_from_xml_strict(xml.to_string()).is_err());
+        assert!(crate::scxml_reader::parse_from_xml_strict(
+            "<scxml xmlns:ui='http://example.com/ui'><state id='A'/></scxml>".to_string()
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn parse_from_string_bytes_and_reader_give_same_fsm() {
+        let xml = r##"<scxml initial='A' datamodel='null'>
+              <state id='A'>
+                <transition event='go' target='P'/>
+              </state>
+              <parallel id='P'>
+                <state id='R1'><transition event='done' target='F'/></state>
+                <state id='R2'/>
+              </parallel>
+              <final id='F'/>
+            </scxml>"##;
+        let file = std::env::temp_dir().join(format!("rfsm_reader_{}.scxml", std::process::id()));
+        std::fs::write(&file, xml).unwrap();
+
+        let structure = |fsm: Box<crate::fsm::Fsm>| {
+            let mut names: Vec<String> = fsm.states.iter().map(|s| s.name.clone()).collect();
+            names.sort();
+            (fsm.states.len(), fsm.transitions.len(), names)
+        };
+        let from_string = structure(crate::scxml_reader::parse_from_xml(xml.to_string()).unwrap());
+        let from_bytes = structure(crate::scxml_reader::parse_from_bytes(xml.as_bytes()).unwrap());
+        let from_reader = structure(
+            crate::scxml_reader::parse_from_reader(std::fs::File::open(&file).unwrap(), file.to_str()).unwrap(),
+        );
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(from_string, from_bytes);
+        assert_eq!(from_string, from_reader);
+
+        // Relative XIncludes are resolved against the base URI.
+        let main = std::path::Path::new("xml/example/Main.scxml");
+        let included = crate::scxml_reader::parse_from_reader(
+            std::fs::File::open(main).unwrap(),
+            Some("xml/example/Main.scxml"),
+        )
+        .unwrap();
+        let from_file = crate::scxml_reader::parse_from_xml_file(main, &[]).unwrap();
+        assert_eq!(structure(included), structure(from_file));
+
+        let err = crate::scxml_reader::parse_from_bytes(b"<scxml>\xff</scxml>").unwrap_err();
+        assert!(err.contains("byte offset 7"), "{}", err);
+        let err = crate::scxml_reader::parse_from_bytes(b"<scxml><state id='A'></scxml>").unwrap_err();
+        assert!(err.contains("byte offset"), "{}", err);
+    }
+
+    #[test]
+    fn state_timeout_is_sent_and_cancelled_on_exit() {
+        let run = |leave: bool| {
+            let fsm = crate::scxml_reader::parse_from_xml(
+                r##"<scxml initial='A' datamodel='null' xmlns:rfsm='https://github.com/BWeng20/rFSM'>
+                  <state id='A'>
+                    <rfsm:timeout delay='100ms' event='A.timeout'/>
+                    <rfsm:timeout delay='1s' event='A.late'/>
+                    <transition event='A.timeout' target='expired'/>
+                    <transition event='leave' target='B'/>
+                  </state>
+                  <state id='B'>
+                    <transition event='A' target='expired'/>
+                    <transition event='finish' target='done'/>
+                  </state>
+                  <final id='expired'/>
+                  <final id='done'/>
+                </scxml>"##
+                    .to_string(),
+            )
+            .unwrap();
+            let timeout = Duration::from_millis(2000);
+            let clock = TestClock::new(0);
+            let mut executor = FsmExecutor::new_without_io_processor();
+            executor.set_clock(Arc::new(clock.clone()));
+            let session = start_fsm_with_data_and_finish_mode(
+                fsm,
+                ActionWrapper::new(),
+                Box::new(executor),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            let sync = if leave { "leave" } else { "idle" };
+            session
+                .send_and_sync(Box::new(Event::new_simple(sync)), timeout)
+                .unwrap();
+            if leave {
+                assert_eq!(clock.pending(), 0, "Timeouts shall be cancelled on exit");
+            } else {
+                assert_eq!(clock.pending(), 2);
+            }
+            clock.advance(2000);
+            let _ = session.sender.send(Box::new(Event::new_simple("finish")));
+            session
+                .wait_for_completion(timeout)
+                .and_then(|r| r.final_configuration)
+        };
+        assert_eq!(run(false), Some(vec!["expired".to_string()]));
+        assert_eq!(run(true), Some(vec!["done".to_string()]));
+    }
+
+    #[test]
+    fn invalid_executable_content_is_rejected_or_raises_error_in_lenient_mode() {
+        let snippets = [
+            (
+                "<raise/>",
+                "<raise>: attribute 'event' is required (line 3)",
+            ),
+            (
+                "<raise event=' '/>",
+                "<raise>: attribute 'event' is required (line 3)",
+            ),
+            (
+                "<if><raise event='x'/></if>",
+                "<if>: attribute 'cond' is required (line 3)",
+            ),
+            (
+                "<if cond='false'><elseif/></if>",
+                "<elseif>: attribute 'cond' is required (line 3)",
+            ),
+            (
+                "<foreach array='[1]'/>",
+                "<foreach>: attribute 'item' is required (line 3)",
+            ),
+            (
+                "<assign expr='1'/>",
+                "<assign>: attribute 'location' is required (line 3)",
+            ),
+            (
+                "<cancel/>",
+                "<cancel>: attribute 'sendid' or 'sendidexpr' is required (line 3)",
+            ),
+            (
+                "<send target='#_parent'>\n<param name='a' expr='1'/></send>",
+                "<send>: attribute 'event' or 'eventexpr' or <content> is required (line 3)",
+            ),
+        ];
+        let document = |content: &str| {
+            format!(
+                "<scxml initial='A' datamodel='null'>\n<state id='A'>\n<onentry>{}</onentry>\n\
+                 <transition event='error.execution' target='error'/>\n<transition event='*' target='fail'/>\n\
+                 </state>\n<final id='error'/><final id='fail'/>\n</scxml>",
+                content
+            )
+        };
+        for (content, expected) in snippets {
+            match crate::scxml_reader::parse_from_xml(document(content)) {
+                Ok(_) => panic!("'{}' shall be rejected", content),
+                Err(err) => assert_eq!(err, expected),
+            }
+
+            let fsm = crate::scxml_reader::parse_from_xml_lenient(document(content)).unwrap();
+            let session = start_fsm_with_data_and_finish_mode(
+                fsm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+                &Vec::new(),
+                FinishMode::KEEP_CONFIGURATION,
+            );
+            assert_eq!(
+                session
+                    .wait_for_completion(Duration::from_millis(2000))
+                    .and_then(|r| r.final_configuration),
+                Some(vec!["error".to_string()]),
+                "'{}' shall raise error.execution",
+                content
+            );
+        }
+        // Other I/O processors don't need an event name.
+        assert!(crate::scxml_reader::parse_from_xml(document(
+            "<send type='http://www.w3.org/TR/scxml/#BasicHTTPEventProcessor' target='http://localhost'/>"
+        ))
+        .is_ok());
+    }
+
+    #[test]
+    fn conflicting_send_attributes_are_rejected() {
+        let document = |content: &str| {
+            format!(
+                "<scxml initial='A' datamodel='null'>\n<state id='A'>\n<onentry>{}</onentry>\n\
+                 <transition event='error.execution' target='error'/>\n<transition event='*' target='fail'/>\n\
+                 </state>\n<final id='error'/><final id='fail'/>\n</scxml>",
+                content
+            )
+        };
+        for (content, expected) in [
+            (
+                "<send event='a' eventexpr='b'/>",
+                "<send>: attributes 'event' and 'eventexpr' must not occur both (line 3)",
+            ),
+            (
+                "<send event='a' target='#_parent' targetexpr='b'/>",
+                "<send>: attributes 'target' and 'targetexpr' must not occur both (line 3)",
+            ),
+            (
+                "<send event='a' target='#_internal' delay='1s'/>",
+                "<send>: attribute 'delay' is not possible with target '#_internal' (line 3)",
+            ),
+        ] {
+            match crate::scxml_reader::parse_from_xml(document(content)) {
+                Ok(_) => panic!("'{}' shall be rejected", content),
+                Err(err) => assert_eq!(err, expected),
+            }
+        }
+        assert!(
+            crate::scxml_reader::parse_from_xml(document("<send event='a' target='#_internal' delay='0s'/>")).is_ok()
+        );
+
+        // In lenient mode, the delayed send to the internal queue fails when executed.
+        let fsm =
+            crate::scxml_reader::parse_from_xml_lenient(document("<send event='a' target='#_internal' delay='1s'/>"))
+                .unwrap();
+        let session = start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        assert_eq!(
+            session
+                .wait_for_completion(Duration::from_millis(2000))
+                .and_then(|r| r.final_configuration),
+            Some(vec!["error".to_string()])
+        );
+    }
 }
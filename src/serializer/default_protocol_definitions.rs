@@ -26,4 +26,6 @@ pub const FSM_PROTOCOL_FLAG_DONE_DATA: u16 = 0x80;
 pub const FSM_PROTOCOL_FLAG_INVOKE: u16 = 0x100;
 pub const FSM_PROTOCOL_FLAG_DATA: u16 = 0x200;
 pub const FSM_PROTOCOL_FLAG_HISTORY: u16 = 0x400;
+pub const FSM_PROTOCOL_FLAG_DATA_SRC: u16 = 0x800;
+pub const FSM_PROTOCOL_FLAG_RESET_HISTORY: u16 = 0x1000;
 pub const FSM_PROTOCOL_TYPE_OPT_DATA_VALUE_NONE: u8 = 0x0A;
@@ -2,7 +2,8 @@
 //! The format is independent of the platform byte-order
 
 use std::collections::HashMap;
-use std::io::Read;
+use std::fs::File;
+use std::io::{BufReader, Read};
 use std::time::{SystemTime, UNIX_EPOCH};
 
 #[cfg(all(feature = "Debug_Serializer", not(feature = "EnvLog")))]
@@ -23,18 +24,36 @@ use crate::executable_content::{
     Assign, Cancel, ExecutableContent, Expression, ForEach, If, Log, Raise, Script, SendParameters,
 };
 use crate::fsm::{
-    BindingType, CommonContent, DocumentId, DoneData, ExecutableContentId, Fsm, HistoryType, Invoke, Parameter, State,
-    StateId, Transition, TransitionId, TransitionType,
+    BindingType, CommonContent, ContentKind, DocumentId, DoneData, ExecutableContentId, Fsm, HistoryType, Invoke,
+    Parameter, SourceLine, State, StateId, Transition, TransitionId, TransitionType,
 };
+use crate::invoke_handler::InvokeError;
 use crate::serializer::default_protocol_definitions::{
-    FSM_PROTOCOL_FLAG_DATA, FSM_PROTOCOL_FLAG_DONE_DATA, FSM_PROTOCOL_FLAG_HISTORY,
+    FSM_PROTOCOL_FLAG_DATA, FSM_PROTOCOL_FLAG_DATA_SRC, FSM_PROTOCOL_FLAG_DONE_DATA, FSM_PROTOCOL_FLAG_HISTORY,
     FSM_PROTOCOL_FLAG_HISTORY_TYPE_MASK, FSM_PROTOCOL_FLAG_INVOKE, FSM_PROTOCOL_FLAG_IS_FINAL,
-    FSM_PROTOCOL_FLAG_IS_PARALLEL, FSM_PROTOCOL_FLAG_ON_ENTRY, FSM_PROTOCOL_FLAG_ON_EXIT, FSM_PROTOCOL_FLAG_STATES,
+    FSM_PROTOCOL_FLAG_IS_PARALLEL, FSM_PROTOCOL_FLAG_ON_ENTRY, FSM_PROTOCOL_FLAG_ON_EXIT,
+    FSM_PROTOCOL_FLAG_RESET_HISTORY, FSM_PROTOCOL_FLAG_STATES,
 };
+use crate::serializer::default_protocol_reader::DefaultProtocolReader;
 use crate::serializer::protocol_reader::ProtocolReader;
 
 /// The reader version, must natch the corresponding writer version
-pub const FSM_READER_VERSION: &str = "fsmW1.1";
+pub const FSM_READER_VERSION: &str = "fsmW1.7";
+
+/// Loads a FSM that was written by [crate::serializer::fsm_writer::save_precompiled]
+/// (or the "scxml_to_fsm" tool). Doesn't need the XML reader.
+pub fn load_precompiled(path: &str) -> Result<Box<Fsm>, String> {
+    load_precompiled_document(path).map_err(|err| err.to_string())
+}
+
+/// Loads the FSM like [load_precompiled], but reports if the file can't be opened.
+pub(crate) fn load_precompiled_document(path: &str) -> Result<Box<Fsm>, InvokeError> {
+    let file = File::open(path).map_err(|err| InvokeError::Retrieval(format!("Failed to open '{}': {}", path, err)))?;
+    let mut reader = FsmReader::new(Box::new(DefaultProtocolReader::new(BufReader::new(file))));
+    let fsm = reader.read();
+    reader.close();
+    fsm.map_err(InvokeError::Parse)
+}
 
 pub struct FsmReader<'a, R>
 where
@@ -57,6 +76,7 @@ where
         let version = self.reader.read_string();
         if version.as_str() == FSM_READER_VERSION {
             fsm.name = self.reader.read_string();
+            fsm.version = self.reader.read_string();
             fsm.datamodel = self.reader.read_string();
             fsm.binding = BindingType::from_ordinal(self.reader.read_u8());
             fsm.pseudo_root = self.read_state_id();
@@ -66,6 +86,7 @@ where
             for _idx in 0..states_len {
                 let mut state = State::new("");
                 self.read_state(&mut state);
+                fsm.statesNames.insert(state.name.clone(), state.id);
                 fsm.states.push(state);
             }
 
@@ -81,11 +102,15 @@ where
                 let content_len = self.reader.read_usize();
                 let mut content = Vec::new();
                 for _idx2 in 0..content_len {
-                    content.push(self.read_executable_content());
+                    content.push(self.read_executable_content()?);
                 }
                 fsm.executableContent.insert(content_id, content);
             }
 
+            if self.reader.has_error() {
+                return Err(format!("Can't read '{}'", fsm.name));
+            }
+
             let end = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
             info!(
                 "'{}' (RFSM) loaded in {}ms",
@@ -116,6 +141,10 @@ where
         self.reader.read_uint() as DocumentId
     }
 
+    pub fn read_source_line(&mut self) -> SourceLine {
+        self.reader.read_uint() as SourceLine
+    }
+
     pub fn read_transition_id(&mut self) -> TransitionId {
         self.reader.read_uint() as TransitionId
     }
@@ -136,12 +165,14 @@ where
     pub fn read_common_content(&mut self, value: &mut CommonContent) {
         value.content = self.reader.read_option_string();
         value.content_expr = self.reader.read_option_string();
+        value.content_kind = ContentKind::from_ordinal(self.reader.read_u8());
     }
 
     pub fn read_parameter(&mut self, value: &mut Parameter) {
         value.name = self.reader.read_string();
         value.expr = self.reader.read_string();
         value.location = self.reader.read_string();
+        value.write_back = self.reader.read_boolean();
     }
 
     pub fn read_done_data(&mut self, value: &mut DoneData) {
@@ -185,6 +216,7 @@ where
             invoke.parent_state_name = self.reader.read_string();
         }
         invoke.doc_id = self.read_doc_id();
+        invoke.source_line = self.read_source_line();
         invoke.src_expr = self.reader.read_data();
         invoke.src = self.reader.read_data();
         invoke.type_expr = self.reader.read_data();
@@ -212,6 +244,7 @@ where
 
         transition.id = self.read_transition_id();
         transition.doc_id = self.read_doc_id();
+        transition.source_line = self.read_source_line();
         transition.source = self.read_state_id();
 
         let target_len = self.reader.read_usize();
@@ -239,6 +272,9 @@ where
         } else {
             0
         };
+        if (flags & 16) != 0 {
+            transition.action = Some(self.reader.read_string());
+        }
 
         #[cfg(feature = "Debug_Serializer")]
         debug!("<<Transition");
@@ -252,6 +288,7 @@ where
 
         state.id = self.read_state_id();
         state.doc_id = self.read_doc_id();
+        state.source_line = self.read_source_line();
         state.name = self.reader.read_string();
 
         let flags = self.reader.read_u16();
@@ -259,6 +296,7 @@ where
         state.history_type = HistoryType::from_ordinal((flags & FSM_PROTOCOL_FLAG_HISTORY_TYPE_MASK) as u8);
         state.is_parallel = (flags & FSM_PROTOCOL_FLAG_IS_PARALLEL) != 0;
         state.is_final = (flags & FSM_PROTOCOL_FLAG_IS_FINAL) != 0;
+        state.reset_on_entry = (flags & FSM_PROTOCOL_FLAG_RESET_HISTORY) != 0;
 
         if (flags & FSM_PROTOCOL_FLAG_STATES) != 0 {
             state.initial = self.read_transition_id();
@@ -306,6 +344,14 @@ where
             self.read_data_map(&mut state.data);
         }
 
+        if (flags & FSM_PROTOCOL_FLAG_DATA_SRC) != 0 {
+            let data_src_len = self.reader.read_usize();
+            for _i in 0..data_src_len {
+                let key = self.reader.read_string();
+                state.data_src.insert(key, self.reader.read_string());
+            }
+        }
+
         state.parent = self.read_state_id();
 
         if (flags & FSM_PROTOCOL_FLAG_DONE_DATA) != 0 {
@@ -320,10 +366,11 @@ where
         debug!("<<State");
     }
 
-    pub fn read_executable_content(&mut self) -> Box<dyn ExecutableContent> {
+    pub fn read_executable_content(&mut self) -> Result<Box<dyn ExecutableContent>, String> {
         let ec_type = self.reader.read_u8();
+        let source_line = self.read_source_line();
 
-        match ec_type {
+        let mut ec = match ec_type {
             executable_content::TYPE_IF => self.read_executable_content_if(),
             executable_content::TYPE_EXPRESSION => self.read_executable_content_expression(),
             executable_content::TYPE_SCRIPT => self.read_executable_content_script(),
@@ -334,9 +381,11 @@ where
             executable_content::TYPE_CANCEL => self.read_executable_content_cancel(),
             executable_content::TYPE_ASSIGN => self.read_executable_content_assign(),
             ut => {
-                panic!("Unknown Executable Content: {}", ut)
+                return Err(format!("Unknown Executable Content: {}", ut));
             }
-        }
+        };
+        ec.set_source_line(source_line);
+        Ok(ec)
     }
 
     pub fn read_executable_content_if(&mut self) -> Box<dyn ExecutableContent> {
@@ -396,6 +445,7 @@ where
         }
         ec.name_list = self.read_string_list();
         ec.name_location = self.reader.read_string();
+        ec.parent_state_name = self.reader.read_string();
         self.read_parameters(&mut ec.params);
 
         ec.event = self.reader.read_data();
@@ -427,6 +477,11 @@ where
         let mut ec = Assign::new();
         ec.expr = self.reader.read_data();
         ec.location = self.reader.read_data();
+        if self.reader.read_boolean() {
+            let mut c = CommonContent::new();
+            self.read_common_content(&mut c);
+            ec.content = Some(c);
+        }
         Box::new(ec)
     }
 }
@@ -489,4 +544,146 @@ mod tests {
 
         assert!(env.fsm.compare_to(result.unwrap().as_ref()))
     }
+
+    #[cfg(feature = "ECMAScript")]
+    pub const FSM_BEHAVIOUR_SRC: &str = r###"
+<scxml initial="Main" version="1.0" datamodel="ecmascript" name="RoundTrip">
+ <datamodel>
+  <data id="items" expr="[1, 2, 3]"/>
+  <data id="sum" expr="0"/>
+  <data id="trace" expr="''"/>
+  <data id="sendId"/>
+ </datamodel>
+ <script>function mark(t) { trace = trace + t; }</script>
+ <parallel id="Main">
+  <state id="Counter" initial="Idle">
+   <history id="CounterHistory" type="deep">
+    <transition target="Idle"/>
+   </history>
+   <state id="Idle">
+    <onentry>
+     <foreach array="items" item="item" index="idx">
+      <assign location="sum" expr="sum + item * (idx + 1)"/>
+     </foreach>
+    </onentry>
+    <transition event="go" target="Busy">
+     <script>mark('g')</script>
+     <send idlocation="sendId" event="late" delay="10s"/>
+    </transition>
+   </state>
+   <state id="Busy">
+    <onexit><log label="exit" expr="sum"/></onexit>
+    <transition event="stop" target="Idle">
+     <cancel sendidexpr="sendId"/>
+    </transition>
+   </state>
+  </state>
+  <state id="Worker">
+   <invoke id="child" type="scxml">
+    <param name="start" expr="sum"/>
+    <content>
+     <scxml initial="C" datamodel="ecmascript">
+      <datamodel><data id="start"/></datamodel>
+      <final id="C">
+       <onentry>
+        <send event="result" target="#_parent"><param name="result" expr="start * 2"/></send>
+       </onentry>
+      </final>
+     </scxml>
+    </content>
+    <finalize><assign location="trace" expr="trace + 'f'"/></finalize>
+   </invoke>
+   <transition event="result">
+    <if cond="_event.data.result == 28">
+     <raise event="childOk"/>
+     <elseif cond="_event.data.result > 28"/>
+     <raise event="childTooBig"/>
+     <else/>
+     <raise event="childTooSmall"/>
+    </if>
+   </transition>
+   <transition event="childOk">
+    <send event="external" target="#_internal">
+     <param name="value" expr="sum"/>
+    </send>
+   </transition>
+   <transition event="external" cond="_event.data.value == 14">
+    <script>mark('x')</script>
+   </transition>
+  </state>
+ </parallel>
+</scxml>"###;
+
+    #[cfg(feature = "ECMAScript")]
+    fn trace_of(snapshot: &crate::fsm_snapshot::FsmSnapshot) -> Option<String> {
+        snapshot
+            .data
+            .iter()
+            .find(|(name, _)| name == "trace")
+            .map(|(_, value)| value.to_data().to_string())
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    fn precompiled_fsm_behaves_like_parsed_fsm() {
+        use crate::actions::ActionWrapper;
+        use crate::fsm::{start_fsm, Event};
+        use crate::fsm_executor::FsmExecutor;
+        use crate::fsm_snapshot::SnapshotData;
+        use crate::serializer::fsm_writer::save_precompiled;
+        use std::time::Duration;
+
+        let parsed = scxml_reader::parse_from_xml(FSM_BEHAVIOUR_SRC.to_string()).unwrap();
+        let path = std::env::temp_dir().join(format!("rfsm_round_trip_{}.rfsm", std::process::id()));
+        let path = path.to_str().unwrap();
+        save_precompiled(&parsed, path).unwrap();
+        let loaded = load_precompiled(path);
+        let _ = std::fs::remove_file(path);
+        let loaded = loaded.unwrap();
+
+        assert!(parsed.compare_to(&loaded));
+        assert_eq!(parsed.statesNames, loaded.statesNames);
+        assert_eq!(parsed.version, loaded.version);
+
+        let mut snapshots = Vec::new();
+        for fsm in [parsed, loaded] {
+            let session = start_fsm(
+                fsm,
+                ActionWrapper::new(),
+                Box::new(FsmExecutor::new_without_io_processor()),
+            );
+            // Wait for the result of the child and its "done.invoke", both are finalized.
+            for _ in 0..100 {
+                let snapshot = session.take_snapshot(Duration::from_millis(2000)).unwrap();
+                if trace_of(&snapshot) == Some("fxf".to_string()) {
+                    break;
+                }
+                std::thread::sleep(Duration::from_millis(20));
+            }
+            for name in ["go", "stop", "go"] {
+                session
+                    .send_and_sync(
+                        Box::new(Event::new_simple(name)),
+                        Duration::from_millis(2000),
+                    )
+                    .unwrap();
+            }
+            snapshots.push(session.take_snapshot(Duration::from_millis(2000)).unwrap());
+            let _ = session.sender.send(Box::new(Event::new_simple(
+                crate::fsm::EVENT_CANCEL_SESSION,
+            )));
+        }
+        assert_eq!(trace_of(&snapshots[0]), Some("fxfgg".to_string()));
+        // Generated send ids are unique per process.
+        for snapshot in &mut snapshots {
+            let (_, send_id) = snapshot
+                .data
+                .iter_mut()
+                .find(|(name, _)| name == "sendId")
+                .unwrap();
+            assert!(send_id.to_data().to_string().starts_with("Idle."));
+            *send_id = SnapshotData::from_data(&Data::Null());
+        }
+        assert_eq!(snapshots[0], snapshots[1]);
+    }
 }
@@ -5,24 +5,39 @@ use crate::datamodel::DataArc;
 #[cfg(feature = "Debug_Serializer")]
 use log::debug;
 use std::collections::HashMap;
-use std::io::Write;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 
 use crate::executable_content;
 use crate::executable_content::{
     Assign, Cancel, ExecutableContent, Expression, ForEach, If, Log, Raise, Script, SendParameters,
 };
 use crate::fsm::{
-    CommonContent, DocumentId, DoneData, ExecutableContentId, Fsm, Invoke, Parameter, State, StateId, Transition,
-    TransitionId,
+    CommonContent, DocumentId, DoneData, ExecutableContentId, Fsm, Invoke, Parameter, SourceLine, State, StateId,
+    Transition, TransitionId,
 };
 use crate::serializer::default_protocol_definitions::{
-    FSM_PROTOCOL_FLAG_DATA, FSM_PROTOCOL_FLAG_DONE_DATA, FSM_PROTOCOL_FLAG_HISTORY, FSM_PROTOCOL_FLAG_INVOKE,
-    FSM_PROTOCOL_FLAG_IS_FINAL, FSM_PROTOCOL_FLAG_IS_PARALLEL, FSM_PROTOCOL_FLAG_ON_ENTRY, FSM_PROTOCOL_FLAG_ON_EXIT,
-    FSM_PROTOCOL_FLAG_STATES,
+    FSM_PROTOCOL_FLAG_DATA, FSM_PROTOCOL_FLAG_DATA_SRC, FSM_PROTOCOL_FLAG_DONE_DATA, FSM_PROTOCOL_FLAG_HISTORY,
+    FSM_PROTOCOL_FLAG_INVOKE, FSM_PROTOCOL_FLAG_IS_FINAL, FSM_PROTOCOL_FLAG_IS_PARALLEL, FSM_PROTOCOL_FLAG_ON_ENTRY,
+    FSM_PROTOCOL_FLAG_ON_EXIT, FSM_PROTOCOL_FLAG_RESET_HISTORY, FSM_PROTOCOL_FLAG_STATES,
 };
+use crate::serializer::default_protocol_writer::DefaultProtocolWriter;
 use crate::serializer::protocol_writer::ProtocolWriter;
 
-pub const FSM_PROTOCOL_WRITER_VERSION: &str = "fsmW1.1";
+pub const FSM_PROTOCOL_WRITER_VERSION: &str = "fsmW1.7";
+
+/// Writes the FSM to a binary file that can be loaded by [crate::serializer::fsm_reader::load_precompiled].
+pub fn save_precompiled(fsm: &Fsm, path: &str) -> Result<(), String> {
+    let file = File::create(path).map_err(|err| format!("Failed to create '{}': {}", path, err))?;
+    let mut writer = FsmWriter::new(Box::new(DefaultProtocolWriter::new(BufWriter::new(file))));
+    writer.write(fsm);
+    writer.close();
+    if writer.writer.has_error() {
+        Err(format!("Failed to write '{}'", path))
+    } else {
+        Ok(())
+    }
+}
 
 fn get_executable_content_as<T: 'static>(ec: &dyn crate::executable_content::ExecutableContent) -> &T {
     let va = ec.as_any();
@@ -52,6 +67,7 @@ where
     pub fn write(&mut self, fsm: &Fsm) {
         self.writer.write_str(FSM_PROTOCOL_WRITER_VERSION);
         self.writer.write_str(fsm.name.as_str());
+        self.writer.write_str(&fsm.version);
         self.writer.write_str(&fsm.datamodel);
         self.writer.write_u8(fsm.binding.ordinal());
         self.write_state_id(fsm.pseudo_root);
@@ -89,6 +105,10 @@ where
         self.writer.write_uint(value as u64);
     }
 
+    pub fn write_source_line(&mut self, value: SourceLine) {
+        self.writer.write_uint(value as u64);
+    }
+
     pub fn write_transition_id(&mut self, value: TransitionId) {
         self.writer.write_uint(value as u64);
     }
@@ -108,12 +128,14 @@ where
     pub fn write_common_content(&mut self, value: &CommonContent) {
         self.writer.write_option_string(&value.content);
         self.writer.write_option_string(&value.content_expr);
+        self.writer.write_u8(value.content_kind.ordinal());
     }
 
     pub fn write_parameter(&mut self, value: &Parameter) {
         self.writer.write_str(value.name.as_str());
         self.writer.write_str(value.expr.as_str());
         self.writer.write_str(value.location.as_str());
+        self.writer.write_boolean(value.write_back);
     }
 
     pub fn write_done_data(&mut self, value: &DoneData) {
@@ -149,6 +171,7 @@ where
             self.writer.write_str(&invoke.parent_state_name);
         }
         self.write_doc_id(invoke.doc_id);
+        self.write_source_line(invoke.source_line);
         self.writer.write_data(&invoke.src_expr);
         self.writer.write_data(&invoke.src);
         self.writer.write_data(&invoke.type_expr);
@@ -176,6 +199,7 @@ where
         debug!(">>Transition #{}", transition.id);
         self.write_transition_id(transition.id);
         self.write_doc_id(transition.doc_id);
+        self.write_source_line(transition.source_line);
         self.write_state_id(transition.source);
         self.writer.write_usize(transition.target.len());
         for t in &transition.target {
@@ -189,7 +213,8 @@ where
             transition.transition_type.ordinal() // 0 - 1
             | if transition.wildcard {2u8} else {0u8}
             | if transition.cond.is_empty() {0u8} else {4u8}
-            | if transition.content != 0 {8u8} else {0u8},
+            | if transition.content != 0 {8u8} else {0u8}
+            | if transition.action.is_some() {16u8} else {0u8},
         );
 
         if !transition.cond.is_empty() {
@@ -198,6 +223,9 @@ where
         if transition.content != 0 {
             self.write_executable_content_id(transition.content);
         }
+        if let Some(action) = &transition.action {
+            self.writer.write_str(action);
+        }
 
         #[cfg(feature = "Debug_Serializer")]
         debug!("<<Transition");
@@ -209,6 +237,7 @@ where
 
         self.write_state_id(state.id);
         self.write_doc_id(state.doc_id);
+        self.write_source_line(state.source_line);
         self.writer.write_str(state.name.as_str());
 
         let flags = state.history_type.ordinal() as u16 // 0 - 2
@@ -220,7 +249,9 @@ where
                 | if state.donedata.is_some() {FSM_PROTOCOL_FLAG_DONE_DATA} else {0}
                 | if state.invoke.size()>0 {FSM_PROTOCOL_FLAG_INVOKE} else {0}
                 | if !state.data.is_empty()  {FSM_PROTOCOL_FLAG_DATA} else {0}
-                | if state.history.size() > 0 {FSM_PROTOCOL_FLAG_HISTORY} else {0};
+                | if state.history.size() > 0 {FSM_PROTOCOL_FLAG_HISTORY} else {0}
+                | if !state.data_src.is_empty() {FSM_PROTOCOL_FLAG_DATA_SRC} else {0}
+                | if state.reset_on_entry {FSM_PROTOCOL_FLAG_RESET_HISTORY} else {0};
         self.writer.write_uint(flags as u64);
 
         if !state.states.is_empty() {
@@ -267,6 +298,14 @@ where
             self.write_data_map(&state.data);
         }
 
+        if !state.data_src.is_empty() {
+            self.writer.write_usize(state.data_src.len());
+            for (key, location) in &state.data_src {
+                self.writer.write_str(key.as_str());
+                self.writer.write_str(location.as_str());
+            }
+        }
+
         self.write_state_id(state.parent);
 
         if state.donedata.is_some() {
@@ -280,6 +319,7 @@ where
     pub fn write_executable_content(&mut self, executable_content: &dyn ExecutableContent) {
         let ec_type = executable_content.get_type();
         self.writer.write_u8(ec_type);
+        self.write_source_line(executable_content.get_source_line());
 
         match ec_type {
             executable_content::TYPE_IF => {
@@ -359,6 +399,8 @@ where
         self.write_string_list(&executable_content_send.name_list);
         self.writer
             .write_str(&executable_content_send.name_location);
+        self.writer
+            .write_str(&executable_content_send.parent_state_name);
         self.write_parameters(&executable_content_send.params);
 
         self.writer.write_data(&executable_content_send.event);
@@ -383,5 +425,10 @@ where
     pub fn write_executable_content_assign(&mut self, executable_content_assign: &Assign) {
         self.writer.write_data(&executable_content_assign.expr);
         self.writer.write_data(&executable_content_assign.location);
+        self.writer
+            .write_boolean(executable_content_assign.content.is_some());
+        if let Some(content) = &executable_content_assign.content {
+            self.write_common_content(content);
+        }
     }
 }
@@ -1,42 +1,46 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs::File;
 use std::io::BufReader;
 #[cfg(feature = "yaml-config")]
 use std::io::Read;
-use std::path::PathBuf;
+use std::ops::DerefMut;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use std::{process, thread};
 
 use crate::actions::ActionWrapper;
 use log::warn;
 
-#[cfg(all(not(test), feature = "Debug", not(feature = "EnvLog")))]
+#[cfg(all(not(test), not(feature = "EnvLog")))]
 use std::{println as error, println as info};
 
-#[cfg(all(not(test), feature = "Debug", feature = "EnvLog"))]
+#[cfg(all(not(test), feature = "EnvLog"))]
 use log::{error, info};
 
 #[cfg(test)]
 use std::{println as error, println as info};
 
 #[cfg(feature = "json-config")]
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 #[cfg(feature = "yaml-config")]
 use yaml_rust::YamlLoader;
 
 use crate::fsm;
-use crate::fsm::{Event, FinishMode, Fsm};
+#[cfg(feature = "Trace_Event")]
+use crate::fsm::SessionId;
+use crate::fsm::{Event, EventSender, FinishMode, Fsm};
 use crate::fsm_executor::FsmExecutor;
 #[cfg(feature = "xml")]
 use crate::scxml_reader;
 #[cfg(feature = "serializer")]
-use crate::serializer::default_protocol_reader::DefaultProtocolReader;
-#[cfg(feature = "serializer")]
-use crate::serializer::fsm_reader::FsmReader;
+use crate::serializer::fsm_reader::load_precompiled;
 #[cfg(feature = "Trace")]
 use crate::tracer::TraceMode;
+#[cfg(feature = "Trace_Event")]
+use crate::tracer::{DefaultTracer, TraceRecord, Tracer};
 
 #[cfg_attr(feature = "json-config", derive(Deserialize))]
 #[allow(unused)]
@@ -84,14 +88,7 @@ pub fn load_fsm(file_path: &str, include_paths: &[PathBuf]) -> Result<Box<Fsm>,
     }
     #[cfg(feature = "serializer")]
     if extension.eq_ignore_ascii_case("rfsm") {
-        return match File::open(file_path) {
-            Ok(f) => {
-                let protocol = DefaultProtocolReader::new(BufReader::new(f));
-                let mut reader = FsmReader::new(Box::new(protocol));
-                reader.read()
-            }
-            Err(err) => Err(err.to_string()),
-        };
+        return load_precompiled(file_path);
     }
     Err(format!("No reader to load '{}'", file_path))
 }
@@ -221,24 +218,46 @@ pub fn run_test_manual(
 pub fn run_test_manual_with_send(
     test_name: &str,
     options: &HashMap<&str, String>,
-    #[cfg(feature = "Trace")] mut fsm: Box<Fsm>,
-    #[cfg(not(feature = "Trace"))] fsm: Box<Fsm>,
+    fsm: Box<Fsm>,
     include_paths: &Vec<PathBuf>,
     #[cfg(feature = "Trace")] trace_mode: TraceMode,
     timeout: u64,
     expected_final_configuration: &Vec<String>,
-    mut cb: impl FnMut(Sender<Box<Event>>),
+    cb: impl FnMut(EventSender),
 ) -> bool {
-    #[cfg(feature = "Trace")]
-    fsm.tracer.enable_trace(trace_mode);
-
     let mut executor = FsmExecutor::new_without_io_processor();
     executor.set_global_options_from_arguments(options);
 
-    let executor_state = executor.state.clone();
     for ip in include_paths {
         executor.include_paths.push(ip.clone());
     }
+    run_test_manual_with_executor(
+        test_name,
+        executor,
+        fsm,
+        #[cfg(feature = "Trace")]
+        trace_mode,
+        timeout,
+        expected_final_configuration,
+        cb,
+    )
+}
+
+/// Runs a test with a prepared executor, e.g. with custom invoke handlers.
+pub fn run_test_manual_with_executor(
+    test_name: &str,
+    executor: FsmExecutor,
+    #[cfg(feature = "Trace")] mut fsm: Box<Fsm>,
+    #[cfg(not(feature = "Trace"))] fsm: Box<Fsm>,
+    #[cfg(feature = "Trace")] trace_mode: TraceMode,
+    timeout: u64,
+    expected_final_configuration: &Vec<String>,
+    mut cb: impl FnMut(EventSender),
+) -> bool {
+    #[cfg(feature = "Trace")]
+    fsm.tracer.enable_trace(trace_mode);
+
+    let executor_state = executor.state.clone();
     let session = fsm::start_fsm_with_data_and_finish_mode(
         fsm,
         ActionWrapper::new(),
@@ -253,13 +272,21 @@ pub fn run_test_manual_with_send(
     }
 
     // Sending some event
-    cb(session.sender);
+    cb(session.sender.clone());
 
     info!("FSM started. Waiting to terminate...");
-    if session.thread.is_none() {
-        panic!("Internal error: session_thread not available")
+    match session.thread {
+        Some(thread) => {
+            let _ = thread.join();
+        }
+        // Sessions of a thread pool have no own thread, the watchdog aborts if needed.
+        None => {
+            while session
+                .wait_for_completion(Duration::from_secs(1))
+                .is_none()
+            {}
+        }
     }
-    let _ = session.thread.unwrap().join();
 
     match &watchdog_sender {
         Some(sender) => {
@@ -272,17 +299,18 @@ pub fn run_test_manual_with_send(
     if expected_final_configuration.is_empty() {
         true
     } else {
-        match executor_state
+        let result = executor_state
             .lock()
             .unwrap()
-            .sessions
+            .session_infos
             .get(&session.session_id)
-        {
+            .and_then(|info| info.result.clone());
+        match result {
             None => {
                 error!("FSM Session lost");
                 false
             }
-            Some(session) => match &session.global_data.lock().unwrap().final_configuration {
+            Some(result) => match &result.final_configuration {
                 None => {
                     error!("Final Configuration not available");
                     false
@@ -364,3 +392,444 @@ pub fn abort_test(message: String) -> ! {
     error!("Fatal Error: {}", message);
     process::exit(1);
 }
+
+/// Outcome of a W3C test, see [run_w3c_test].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "json-config", derive(Serialize))]
+pub enum W3cTestOutcome {
+    /// The FSM reached the expected final configuration.
+    Pass,
+    /// The FSM finished without the expected final configuration.
+    Fail,
+    /// The FSM didn't finish in time and was cancelled.
+    Timeout,
+    /// The FSM could not be loaded.
+    LoadError,
+}
+
+/// Result of one W3C test.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "json-config", derive(Serialize))]
+pub struct W3cTestResult {
+    /// File name of the test.
+    pub name: String,
+    /// Number of the test, taken from the file name (e.g. 144 for "test144.scxml").
+    pub number: Option<u32>,
+    pub outcome: W3cTestOutcome,
+    /// True if the test is in the list of known failures.
+    pub known_failure: bool,
+    /// The final configuration of the FSM, empty if the FSM didn't finish.
+    pub final_configuration: Vec<String>,
+    /// Names of the error events the FSM received, e.g. "error.execution".\
+    /// Only collected if feature "Trace_Event" is active.
+    pub errors: Vec<String>,
+    /// Load errors or other details about the outcome.
+    pub message: Option<String>,
+    pub duration_ms: u64,
+}
+
+impl W3cTestResult {
+    /// True if the outcome matches the expectation, i.e. the test passed or failed as known.
+    pub fn is_expected(&self) -> bool {
+        (self.outcome == W3cTestOutcome::Pass) != self.known_failure
+    }
+}
+
+/// Settings for [run_w3c_tests].
+#[derive(Debug, Clone)]
+pub struct W3cTestRunConfig {
+    pub include_paths: Vec<PathBuf>,
+    /// Global options for the executor, e.g. datamodel options.
+    pub options: HashMap<String, String>,
+    pub timeout: Duration,
+    /// The states the final configuration has to contain. W3C tests use "pass".
+    pub final_configuration: Vec<String>,
+    /// Number of tests to execute in parallel.
+    pub threads: usize,
+    /// If not empty, only tests with these numbers are executed.
+    pub selected: HashSet<u32>,
+    /// Numbers of tests that are known to fail.
+    pub known_failures: HashSet<u32>,
+}
+
+impl Default for W3cTestRunConfig {
+    fn default() -> Self {
+        W3cTestRunConfig {
+            include_paths: Vec::new(),
+            options: HashMap::new(),
+            timeout: Duration::from_millis(5000),
+            final_configuration: vec!["pass".to_string()],
+            threads: 1,
+            selected: HashSet::new(),
+            known_failures: HashSet::new(),
+        }
+    }
+}
+
+impl W3cTestRunConfig {
+    /// Takes timeout, final configuration and options from a test specification (e.g. "test_config.json").
+    pub fn apply_specification(&mut self, specification: &TestSpecification) {
+        if let Some(timeout) = specification.timeout_milliseconds {
+            if timeout > 0 {
+                self.timeout = Duration::from_millis(timeout as u64);
+            }
+        }
+        if let Some(final_configuration) = &specification.final_configuration {
+            self.final_configuration.clone_from(final_configuration);
+        }
+        if let Some(options) = &specification.options {
+            for (name, value) in options {
+                self.options.insert(name.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// Gets the test number from a file name, e.g. 144 for "test144.scxml" or 403 for "test403a.scxml".
+pub fn w3c_test_number(file: &Path) -> Option<u32> {
+    let stem = file.file_stem()?.to_string_lossy();
+    let digits: String = stem
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Parses a list of test numbers, separated by whitespace or commas. Content after '#' is ignored.
+pub fn parse_w3c_test_numbers(list: &str) -> Result<HashSet<u32>, String> {
+    let mut numbers = HashSet::new();
+    for line in list.lines() {
+        let line = line.split('#').next().unwrap_or_default();
+        for item in line.split(|c: char| c == ',' || c.is_whitespace()) {
+            if !item.is_empty() {
+                match item.parse::<u32>() {
+                    Ok(number) => {
+                        numbers.insert(number);
+                    }
+                    Err(_) => {
+                        return Err(format!("Illegal test number '{}'", item));
+                    }
+                }
+            }
+        }
+    }
+    Ok(numbers)
+}
+
+/// Tracer that records the names of all received error events.\
+/// All other calls are forwarded to the original tracer of the FSM.
+#[cfg(feature = "Trace_Event")]
+#[derive(Debug)]
+struct ErrorRecordingTracer {
+    inner: Box<dyn Tracer>,
+    errors: Arc<Mutex<Vec<String>>>,
+}
+
+#[cfg(feature = "Trace_Event")]
+impl Tracer for ErrorRecordingTracer {
+    fn trace(&self, msg: &str) {
+        self.inner.trace(msg);
+    }
+
+    fn enter(&self) {
+        self.inner.enter();
+    }
+
+    fn leave(&self) {
+        self.inner.leave();
+    }
+
+    fn enable_trace(&mut self, flag: TraceMode) {
+        self.inner.enable_trace(flag);
+    }
+
+    fn disable_trace(&mut self, flag: TraceMode) {
+        self.inner.disable_trace(flag);
+    }
+
+    fn is_trace(&self, flag: TraceMode) -> bool {
+        self.inner.is_trace(flag)
+    }
+
+    fn trace_record(&self, record: &TraceRecord) {
+        if let TraceRecord::EventReceived {
+            event,
+            internal: true,
+        } = record
+        {
+            if event.name.starts_with("error.") {
+                self.errors.lock().unwrap().push(event.name.clone());
+            }
+        }
+        self.inner.trace_record(record);
+    }
+
+    fn set_session_id(&mut self, session_id: SessionId) {
+        self.inner.set_session_id(session_id);
+    }
+
+    fn trace_mode(&self) -> TraceMode {
+        self.inner.trace_mode()
+    }
+}
+
+/// Executes one W3C test.\
+/// The test passes if the FSM finishes with a configuration that contains all states of
+/// [W3cTestRunConfig::final_configuration]. If the FSM doesn't finish in time, the session is cancelled.
+pub fn run_w3c_test(file: &Path, config: &W3cTestRunConfig) -> W3cTestResult {
+    let started = Instant::now();
+    let mut result = W3cTestResult {
+        name: file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        number: w3c_test_number(file),
+        outcome: W3cTestOutcome::LoadError,
+        known_failure: false,
+        final_configuration: Vec::new(),
+        errors: Vec::new(),
+        message: None,
+        duration_ms: 0,
+    };
+    result.known_failure = result
+        .number
+        .is_some_and(|n| config.known_failures.contains(&n));
+
+    // Dependencies of the test are resolved relative to the directory of the test.
+    let mut include_paths = Vec::with_capacity(config.include_paths.len() + 1);
+    if let Some(dir) = file.parent() {
+        include_paths.push(dir.to_path_buf());
+    }
+    include_paths.extend(config.include_paths.iter().cloned());
+
+    #[allow(unused_mut)]
+    let mut fsm = match load_fsm(file.to_string_lossy().as_ref(), &include_paths) {
+        Ok(fsm) => fsm,
+        Err(err) => {
+            result.message = Some(err);
+            return result;
+        }
+    };
+
+    #[cfg(feature = "Trace_Event")]
+    let errors = {
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let inner = std::mem::replace(&mut fsm.tracer, Box::new(DefaultTracer::new()));
+        fsm.tracer = Box::new(ErrorRecordingTracer {
+            inner,
+            errors: errors.clone(),
+        });
+        errors
+    };
+
+    let mut executor = FsmExecutor::new_without_io_processor();
+    let options: HashMap<&str, String> = config
+        .options
+        .iter()
+        .map(|(name, value)| (name.as_str(), value.clone()))
+        .collect();
+    executor.set_global_options_from_arguments(&options);
+    executor.include_paths = include_paths;
+
+    let session = fsm::start_fsm_with_data_and_finish_mode(
+        fsm,
+        ActionWrapper::new(),
+        Box::new(executor),
+        &Vec::new(),
+        FinishMode::KEEP_CONFIGURATION,
+    );
+
+    match session.wait_for_completion(config.timeout) {
+        Some(session_result) => {
+            result.final_configuration = session_result.final_configuration.unwrap_or_default();
+            match verify_final_configuration(&config.final_configuration, &result.final_configuration) {
+                Ok(_) => {
+                    result.outcome = W3cTestOutcome::Pass;
+                }
+                Err(state) => {
+                    result.outcome = W3cTestOutcome::Fail;
+                    result.message = Some(format!("Expected final state '{}' not reached", state));
+                }
+            }
+        }
+        None => {
+            let _ = session
+                .sender
+                .send(Box::new(Event::new_simple(fsm::EVENT_CANCEL_SESSION)));
+            if session.wait_for_completion(config.timeout).is_none() {
+                warn!("[{}] Session didn't react on cancel", result.name);
+            }
+            result.outcome = W3cTestOutcome::Timeout;
+            result.message = Some(format!(
+                "Timed out after {} milliseconds",
+                config.timeout.as_millis()
+            ));
+        }
+    }
+
+    #[cfg(feature = "Trace_Event")]
+    result.errors.clone_from(&errors.lock().unwrap());
+
+    result.duration_ms = started.elapsed().as_millis() as u64;
+    result
+}
+
+/// Executes the W3C tests in parallel by [W3cTestRunConfig::threads] worker threads.\
+/// Tests that are not selected are ignored. The results are sorted by test number.
+pub fn run_w3c_tests(files: &[PathBuf], config: &W3cTestRunConfig) -> Vec<W3cTestResult> {
+    let pending: VecDeque<PathBuf> = files
+        .iter()
+        .filter(|file| {
+            config.selected.is_empty() || w3c_test_number(file).is_some_and(|n| config.selected.contains(&n))
+        })
+        .cloned()
+        .collect();
+    let pending = Arc::new(Mutex::new(pending));
+    let results = Arc::new(Mutex::new(Vec::new()));
+
+    let mut workers = Vec::new();
+    for _ in 0..config.threads.max(1) {
+        let pending = pending.clone();
+        let results = results.clone();
+        let config = config.clone();
+        workers.push(thread::spawn(move || loop {
+            let file = pending.lock().unwrap().pop_front();
+            match file {
+                None => break,
+                Some(file) => {
+                    let result = run_w3c_test(&file, &config);
+                    info!("{} ==> {:?}", result.name, result.outcome);
+                    results.lock().unwrap().push(result);
+                }
+            }
+        }));
+    }
+    for worker in workers {
+        let _ = worker.join();
+    }
+
+    let mut results = std::mem::take(results.lock().unwrap().deref_mut());
+    results.sort_by(|a, b| (a.number, &a.name).cmp(&(b.number, &b.name)));
+    results
+}
+
+/// Creates a markdown summary table of the results.
+pub fn w3c_summary_table(results: &[W3cTestResult]) -> String {
+    let mut table = String::new();
+    table.push_str("| Test                 | Result   | Known Failure | Errors                         |\n");
+    table.push_str("|----------------------|----------|---------------|--------------------------------|\n");
+    for result in results {
+        table.push_str(
+            format!(
+                "| {:<21}| {:<9}| {:<14}| {:<31}|\n",
+                result.name,
+                format!("{:?}", result.outcome),
+                if result.known_failure { "yes" } else { "" },
+                result.errors.join(", ")
+            )
+            .as_str(),
+        );
+    }
+    let passed = results
+        .iter()
+        .filter(|r| r.outcome == W3cTestOutcome::Pass)
+        .count();
+    let unexpected = results.iter().filter(|r| !r.is_expected()).count();
+    table.push_str(
+        format!(
+            "\n__Result__: {} of {} tests succeeded, {} unexpected results\n",
+            passed,
+            results.len(),
+            unexpected
+        )
+        .as_str(),
+    );
+    table
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::collections::HashSet;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::fs;
+    use std::path::Path;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    use crate::test::{parse_w3c_test_numbers, w3c_test_number};
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::test::{run_w3c_tests, W3cTestOutcome, W3cTestRunConfig};
+
+    #[test]
+    fn w3c_test_numbers() {
+        assert_eq!(w3c_test_number(Path::new("scxml/test144.scxml")), Some(144));
+        assert_eq!(w3c_test_number(Path::new("test403a.scxml")), Some(403));
+        assert_eq!(w3c_test_number(Path::new("other.scxml")), None);
+
+        let numbers = parse_w3c_test_numbers("144, 147\n# comment\n200 # other comment\n").unwrap();
+        assert_eq!(numbers.len(), 3);
+        assert!(numbers.contains(&144) && numbers.contains(&147) && numbers.contains(&200));
+        assert!(parse_w3c_test_numbers("144,abc").is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn w3c_tests_are_executed() {
+        let dir = std::env::temp_dir().join(format!("rfsm_w3c_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let pass = dir.join("test1.scxml");
+        fs::write(
+            &pass,
+            r##"<scxml initial='s0' datamodel='ecmascript'>
+              <state id='s0'>
+                <onentry><assign location='undefined.x' expr='1'/></onentry>
+                <transition event='error.execution' target='pass'/>
+              </state>
+              <final id='pass'/>
+            </scxml>"##,
+        )
+        .unwrap();
+        let timeout = dir.join("test2.scxml");
+        fs::write(
+            &timeout,
+            r##"<scxml initial='s0' datamodel='ecmascript'>
+              <state id='s0'/>
+              <final id='pass'/>
+            </scxml>"##,
+        )
+        .unwrap();
+        let ignored = dir.join("test3.scxml");
+
+        let config = W3cTestRunConfig {
+            timeout: Duration::from_millis(300),
+            threads: 2,
+            selected: HashSet::from([1, 2]),
+            known_failures: HashSet::from([2]),
+            ..Default::default()
+        };
+
+        let results = run_w3c_tests(&[ignored, timeout, pass], &config);
+        let _ = fs::remove_dir_all(&dir);
+
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].number, Some(1));
+        assert_eq!(
+            results[0].outcome,
+            W3cTestOutcome::Pass,
+            "{:?}",
+            results[0].message
+        );
+        assert_eq!(results[0].final_configuration, vec!["pass".to_string()]);
+        #[cfg(feature = "Trace_Event")]
+        assert_eq!(results[0].errors, vec!["error.execution".to_string()]);
+        assert_eq!(results[1].outcome, W3cTestOutcome::Timeout);
+        assert!(results.iter().all(|r| r.is_expected()));
+    }
+}
@@ -8,11 +8,12 @@ use std::ops::DerefMut;
 use std::println as info;
 use std::str::FromStr;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
 
 #[cfg(not(test))]
 use log::info;
 
-use crate::fsm::{Event, OrderedSet, State};
+use crate::fsm::{Event, OrderedSet, SessionId, State, StateId, TransitionId};
 use crate::{fsm, ArgOption};
 
 /// Trace mode for FSM Tracer.
@@ -23,10 +24,21 @@ pub enum TraceMode {
     EVENTS,
     ARGUMENTS,
     RESULTS,
+    TRANSITIONS,
     ALL,
     NONE,
 }
 
+/// The trace modes that select a single kind of records.
+pub const TRACE_MODES: [TraceMode; 6] = [
+    TraceMode::METHODS,
+    TraceMode::STATES,
+    TraceMode::EVENTS,
+    TraceMode::ARGUMENTS,
+    TraceMode::RESULTS,
+    TraceMode::TRANSITIONS,
+];
+
 pub static TRACE_ARGUMENT_OPTION: ArgOption = ArgOption {
     name: "trace",
     with_value: true,
@@ -69,12 +81,148 @@ impl FromStr for TraceMode {
             "events" => Ok(TraceMode::EVENTS),
             "arguments" => Ok(TraceMode::ARGUMENTS),
             "results" => Ok(TraceMode::RESULTS),
+            "transitions" => Ok(TraceMode::TRANSITIONS),
             "all" => Ok(TraceMode::ALL),
             _ => Err(()),
         }
     }
 }
 
+/// Structured trace record, given to [Tracer::trace_record].\
+/// The [Display] implementation produces the textual trace output.
+#[derive(Debug, Clone)]
+pub enum TraceRecord {
+    MethodEnter {
+        name: String,
+    },
+    MethodExit {
+        name: String,
+    },
+    Argument {
+        name: String,
+        value: String,
+    },
+    Result {
+        name: String,
+        value: String,
+    },
+    EnterState {
+        id: StateId,
+        name: String,
+    },
+    ExitState {
+        id: StateId,
+        name: String,
+    },
+    EventSent {
+        event: Event,
+        internal: bool,
+    },
+    EventReceived {
+        event: Event,
+        internal: bool,
+    },
+    TransitionTaken {
+        id: TransitionId,
+        source: StateId,
+        targets: Vec<StateId>,
+    },
+    /// External events that were dropped because the external queue was full.
+    EventsDropped {
+        count: u64,
+    },
+}
+
+impl TraceRecord {
+    /// The trace mode that controls this record.
+    pub fn mode(&self) -> TraceMode {
+        match self {
+            TraceRecord::MethodEnter { .. } | TraceRecord::MethodExit { .. } => TraceMode::METHODS,
+            TraceRecord::Argument { .. } => TraceMode::ARGUMENTS,
+            TraceRecord::Result { .. } => TraceMode::RESULTS,
+            TraceRecord::EnterState { .. } | TraceRecord::ExitState { .. } => TraceMode::STATES,
+            TraceRecord::EventSent { .. } | TraceRecord::EventReceived { .. } | TraceRecord::EventsDropped { .. } => {
+                TraceMode::EVENTS
+            }
+            TraceRecord::TransitionTaken { .. } => TraceMode::TRANSITIONS,
+        }
+    }
+
+    pub fn enter_state(s: &State) -> TraceRecord {
+        TraceRecord::EnterState {
+            id: s.id,
+            name: s.name.clone(),
+        }
+    }
+
+    pub fn exit_state(s: &State) -> TraceRecord {
+        TraceRecord::ExitState {
+            id: s.id,
+            name: s.name.clone(),
+        }
+    }
+}
+
+impl Display for TraceRecord {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            TraceRecord::MethodEnter { name } => write!(f, ">>> {}", name),
+            TraceRecord::MethodExit { name } => write!(f, "<<< {}", name),
+            TraceRecord::Argument { name, value } => write!(f, "Argument:{}={}", name, value),
+            TraceRecord::Result { name, value } => write!(f, "Result:{}={}", name, value),
+            TraceRecord::EnterState { id, name } | TraceRecord::ExitState { id, name } => {
+                let what = if matches!(self, TraceRecord::EnterState { .. }) {
+                    "Enter"
+                } else {
+                    "Exit"
+                };
+                if name.is_empty() {
+                    write!(f, "{} #{}", what, id)
+                } else {
+                    write!(f, "{} <{}> #{}", what, name, id)
+                }
+            }
+            TraceRecord::EventSent { event, internal } => write!(
+                f,
+                "Send {} Event: {} #{:?}",
+                if *internal { "Internal" } else { "External" },
+                event.name,
+                event.invoke_id
+            ),
+            TraceRecord::EventReceived {
+                event,
+                internal: true,
+            } => write!(
+                f,
+                "Received Internal Event: {}, invokeId {:?}, content {:?}, param {:?}",
+                event.name, event.invoke_id, event.content, event.param_values
+            ),
+            TraceRecord::EventReceived {
+                event,
+                internal: false,
+            } => {
+                write!(
+                    f,
+                    "Received External Event: {} #{:?}",
+                    event.name, event.invoke_id
+                )
+            }
+            TraceRecord::TransitionTaken {
+                id,
+                source,
+                targets,
+            } => write!(
+                f,
+                "Take Transition #{} #{} -> {}",
+                id,
+                source,
+                fsm::vec_to_string(targets)
+            ),
+            TraceRecord::EventsDropped { count } => write!(f, "Dropped {} External Event(s): queue is full", count),
+        }
+    }
+}
+
 /// Trait used to trace methods and
 /// states inside the FSM. What is traced can be controlled by
 /// [Tracer::enable_trace] and [Tracer::disable_trace], see [TraceMode].
@@ -98,95 +246,84 @@ pub trait Tracer: Send + Debug {
     /// Return true if the given scape is enabled.
     fn is_trace(&self, flag: TraceMode) -> bool;
 
+    /// Called by FSM for each trace record.\
+    /// The default implementation checks the trace mode of the record and calls [Tracer::trace] with
+    /// the textual representation.
+    fn trace_record(&self, record: &TraceRecord) {
+        if self.is_trace(record.mode()) {
+            match record {
+                TraceRecord::MethodEnter { .. } => {
+                    self.trace(record.to_string().as_str());
+                    self.enter();
+                }
+                TraceRecord::MethodExit { .. } => {
+                    self.leave();
+                    self.trace(record.to_string().as_str());
+                }
+                _ => {
+                    self.trace(record.to_string().as_str());
+                }
+            }
+        }
+    }
+
+    /// Called by FSM if the session of the traced FSM is created.
+    fn set_session_id(&mut self, _session_id: SessionId) {}
+
     /// Called by FSM if a method is entered
     fn enter_method(&self, what: &str) {
         if self.is_trace(TraceMode::METHODS) {
-            self.trace(format!(">>> {}", what).as_str());
-            self.enter();
+            self.trace_record(&TraceRecord::MethodEnter {
+                name: what.to_string(),
+            });
         }
     }
 
     /// Called by FSM if a method is exited
     fn exit_method(&self, what: &str) {
         if self.is_trace(TraceMode::METHODS) {
-            self.leave();
-            self.trace(format!("<<< {}", what).as_str());
+            self.trace_record(&TraceRecord::MethodExit {
+                name: what.to_string(),
+            });
         }
     }
 
     /// Called by FSM if an internal event is sent
     fn event_internal_send(&self, what: &Event) {
         if self.is_trace(TraceMode::EVENTS) {
-            self.trace(format!("Send Internal Event: {} #{:?}", what.name, what.invoke_id).as_str());
+            self.trace_record(&TraceRecord::EventSent {
+                event: what.clone(),
+                internal: true,
+            });
         }
     }
 
     /// Called by FSM if an internal event is received
     fn event_internal_received(&self, what: &Event) {
         if self.is_trace(TraceMode::EVENTS) {
-            self.trace(
-                format!(
-                    "Received Internal Event: {}, invokeId {:?}, content {:?}, param {:?}",
-                    what.name, what.invoke_id, what.content, what.param_values
-                )
-                .as_str(),
-            );
+            self.trace_record(&TraceRecord::EventReceived {
+                event: what.clone(),
+                internal: true,
+            });
         }
     }
 
     /// Called by FSM if an external event is send
     fn event_external_send(&self, what: &Event) {
         if self.is_trace(TraceMode::EVENTS) {
-            self.trace(format!("Send External Event: {} #{:?}", what.name, what.invoke_id).as_str());
+            self.trace_record(&TraceRecord::EventSent {
+                event: what.clone(),
+                internal: false,
+            });
         }
     }
 
     /// Called by FSM if an external event is received
     fn event_external_received(&mut self, what: &Event) {
-        if what.name.starts_with("trace.") {
-            let p = what.name.as_str().split('.').collect::<Vec<&str>>();
-            if p.len() == 3 {
-                match TraceMode::from_str(p.get(1).unwrap()) {
-                    Ok(t) => match *p.get(2).unwrap() {
-                        "on" | "ON" | "On" => {
-                            self.enable_trace(t);
-                        }
-                        "off" | "OFF" | "Off" => {
-                            self.disable_trace(t);
-                        }
-                        _ => {
-                            self.trace(
-                                format!(
-                                    "Trace event '{}' with illegal flag '{}'. Use 'On' or 'Off'.",
-                                    what.name,
-                                    *p.get(2).unwrap()
-                                )
-                                .as_str(),
-                            );
-                        }
-                    },
-                    Err(_e) => {
-                        self.trace(
-                            format!(
-                                "Trace event '{}' has unknown trace flag '{}'",
-                                what.name,
-                                p.get(1).unwrap()
-                            )
-                            .as_str(),
-                        );
-                    }
-                }
-            }
-        }
-        if self.is_trace(TraceMode::EVENTS) {
-            self.trace(
-                format!(
-                    "Received External Event: {} #{:?}",
-                    what.name, what.invoke_id
-                )
-                .as_str(),
-            );
-        }
+        self.trace_record(&TraceRecord::EventReceived {
+            event: what.clone(),
+            internal: false,
+        });
     }
 
     /// Called by FSM if a state is entered or left.
@@ -200,27 +337,33 @@ pub trait Tracer: Send + Debug {
         }
     }
 
-    /// Called by FSM if a state is entered. Calls [Tracer::trace_state].
+    /// Called by FSM if a state is entered.
     fn trace_enter_state(&self, s: &State) {
-        self.trace_state("Enter", s);
+        self.trace_record(&TraceRecord::enter_state(s));
     }
 
-    /// Called by FSM if a state is left. Calls [Tracer::trace_state].
+    /// Called by FSM if a state is left.
     fn trace_exit_state(&self, s: &State) {
-        self.trace_state("Exit", s);
+        self.trace_record(&TraceRecord::exit_state(s));
     }
 
     /// Called by FSM for input arguments in methods.
     fn trace_argument(&self, what: &str, d: &dyn Display) {
         if self.is_trace(TraceMode::ARGUMENTS) {
-            self.trace(format!("Argument:{}={}", what, d).as_str());
+            self.trace_record(&TraceRecord::Argument {
+                name: what.to_string(),
+                value: d.to_string(),
+            });
         }
     }
 
     /// Called by FSM for results in methods.
     fn trace_result(&self, what: &str, d: &dyn Display) {
         if self.is_trace(TraceMode::RESULTS) {
-            self.trace(format!("Result:{}={}", what, d).as_str());
+            self.trace_record(&TraceRecord::Result {
+                name: what.to_string(),
+                value: d.to_string(),
+            });
         }
     }
 
@@ -236,6 +379,19 @@ pub trait Tracer: Send + Debug {
 
     /// Get trace mode
     fn trace_mode(&self) -> TraceMode;
+
+    /// Gets all enabled trace modes. If all modes are enabled, only [TraceMode::ALL] is returned.
+    fn trace_modes(&self) -> Vec<TraceMode> {
+        if self.is_trace(TraceMode::ALL) {
+            vec![TraceMode::ALL]
+        } else {
+            TRACE_MODES
+                .iter()
+                .filter(|mode| self.is_trace(**mode))
+                .copied()
+                .collect()
+        }
+    }
 }
 
 impl Tracer for DefaultTracer {
@@ -318,6 +474,88 @@ thread_local! {
    static TRACE_PREFIX: RefCell<String> = RefCell::new("".to_string());
 }
 
+/// A collected [TraceRecord] with time stamp and session.
+#[derive(Debug, Clone)]
+pub struct TraceEntry {
+    pub timestamp: SystemTime,
+    pub session_id: Option<SessionId>,
+    pub record: TraceRecord,
+}
+
+/// Tracer that collects all enabled records, e.g. to check the exact sequence in tests.\
+/// Clones share the same record list, so a clone can be kept to access the records after
+/// the tracer was moved into the FSM.
+#[derive(Debug, Clone, Default)]
+pub struct CollectingTracer {
+    pub entries: Arc<Mutex<Vec<TraceEntry>>>,
+    pub trace_flags: HashSet<TraceMode>,
+    pub session_id: Option<SessionId>,
+}
+
+impl CollectingTracer {
+    pub fn new() -> CollectingTracer {
+        CollectingTracer::default()
+    }
+
+    /// Returns a copy of the collected records.
+    pub fn records(&self) -> Vec<TraceRecord> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|e| e.record.clone())
+            .collect()
+    }
+}
+
+impl Tracer for CollectingTracer {
+    fn trace(&self, _msg: &str) {}
+
+    fn enter(&self) {}
+
+    fn leave(&self) {}
+
+    fn enable_trace(&mut self, flag: TraceMode) {
+        self.trace_flags.insert(flag);
+    }
+
+    fn disable_trace(&mut self, flag: TraceMode) {
+        self.trace_flags.remove(&flag);
+    }
+
+    fn is_trace(&self, flag: TraceMode) -> bool {
+        self.trace_flags.contains(&flag) || self.trace_flags.contains(&TraceMode::ALL)
+    }
+
+    fn trace_record(&self, record: &TraceRecord) {
+        if self.is_trace(record.mode()) {
+            self.entries.lock().unwrap().push(TraceEntry {
+                timestamp: SystemTime::now(),
+                session_id: self.session_id,
+                record: record.clone(),
+            });
+        }
+    }
+
+    fn set_session_id(&mut self, session_id: SessionId) {
+        self.session_id = Some(session_id);
+    }
+
+    fn trace_mode(&self) -> TraceMode {
+        if self.is_trace(TraceMode::ALL) {
+            TraceMode::ALL
+        } else if self.is_trace(TraceMode::EVENTS) {
+            TraceMode::EVENTS
+        } else if self.is_trace(TraceMode::STATES) {
+            TraceMode::STATES
+        } else if self.is_trace(TraceMode::METHODS) {
+            TraceMode::METHODS
+        } else {
+            TraceMode::NONE
+        }
+    }
+}
+
 pub trait TracerFactory: Send {
     fn create(&mut self) -> Box<dyn Tracer>;
 }
@@ -353,3 +591,162 @@ pub fn set_tracer_factory(tracer_factory: Box<dyn TracerFactory>) {
 pub fn create_tracer() -> Box<dyn Tracer> {
     tracer_factory_arc.lock().unwrap().create()
 }
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "Trace_State")]
+    #[cfg(feature = "xml")]
+    use crate::fsm::Event;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "xml")]
+    use crate::tracer::CollectingTracer;
+    #[cfg(feature = "xml")]
+    use crate::tracer::TraceMode;
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    use crate::tracer::{TraceRecord, Tracer};
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    #[cfg(feature = "xml")]
+    fn collecting_tracer_records_exact_sequence() {
+        let mut sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A' datamodel='ecmascript'>
+              <state id='A'>
+                <onentry><raise event='go'/></onentry>
+                <transition event='go' target='B'/>
+              </state>
+              <final id='B'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let mut tracer = CollectingTracer::new();
+        tracer.enable_trace(TraceMode::STATES);
+        tracer.enable_trace(TraceMode::EVENTS);
+        tracer.enable_trace(TraceMode::TRANSITIONS);
+        sm.tracer = Box::new(tracer.clone());
+
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        assert!(session
+            .wait_for_completion(Duration::from_millis(2000))
+            .is_some());
+
+        let entries = tracer.entries.lock().unwrap().clone();
+        assert!(entries
+            .iter()
+            .all(|e| e.session_id == Some(session.session_id)));
+
+        let records: Vec<String> = tracer
+            .records()
+            .iter()
+            .filter(|r| match r {
+                // Ignore the pseudo root state, it has no name.
+                TraceRecord::EnterState { name, .. } | TraceRecord::ExitState { name, .. } => !name.is_empty(),
+                _ => true,
+            })
+            .map(|r| match r {
+                TraceRecord::EnterState { name, .. } => format!("enter {}", name),
+                TraceRecord::ExitState { name, .. } => format!("exit {}", name),
+                TraceRecord::EventSent { event, internal } => format!("send {} {}", event.name, internal),
+                TraceRecord::EventReceived { event, internal } => format!("receive {} {}", event.name, internal),
+                TraceRecord::TransitionTaken { targets, .. } => format!("transition to {} state(s)", targets.len()),
+                r => r.to_string(),
+            })
+            .collect();
+        assert_eq!(
+            records,
+            vec![
+                "enter A",
+                "receive go true",
+                "exit A",
+                "transition to 1 state(s)",
+                "enter B",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "Trace_State")]
+    #[cfg(feature = "xml")]
+    fn set_trace_mode_switches_tracing_of_running_session() {
+        let mut sm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A' datamodel='null'>
+              <state id='A'>
+                <transition event='next' target='B'/>
+              </state>
+              <state id='B'>
+                <transition event='next' target='C'/>
+              </state>
+              <state id='C'>
+                <transition event='next' target='D'/>
+              </state>
+              <state id='D'>
+                <!-- Events that look like the old trace events are normal events -->
+                <transition event='trace.states.on' target='Done'/>
+              </state>
+              <final id='Done'/>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let tracer = CollectingTracer::new();
+        sm.tracer = Box::new(tracer.clone());
+        let session = start_fsm_with_data_and_finish_mode(
+            sm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        assert!(session.trace_modes().is_empty());
+
+        let timeout = Duration::from_millis(2000);
+        session
+            .send_and_sync(Box::new(Event::new_simple("next")), timeout)
+            .unwrap();
+        assert!(tracer.records().is_empty());
+
+        session.set_trace_mode(TraceMode::STATES, true).unwrap();
+        session
+            .send_and_sync(Box::new(Event::new_simple("next")), timeout)
+            .unwrap();
+        assert_eq!(session.trace_modes(), vec![TraceMode::STATES]);
+
+        session.set_trace_mode(TraceMode::STATES, false).unwrap();
+        session
+            .send_and_sync(Box::new(Event::new_simple("next")), timeout)
+            .unwrap();
+        assert!(session.trace_modes().is_empty());
+
+        session
+            .sender
+            .send(Box::new(Event::new_simple("trace.states.on")))
+            .unwrap();
+        let result = session.wait_for_completion(timeout).unwrap();
+        assert_eq!(result.final_configuration, Some(vec!["Done".to_string()]));
+
+        let records: Vec<String> = tracer.records().iter().map(|r| r.to_string()).collect();
+        assert_eq!(records.len(), 2, "{:?}", records);
+        assert!(records[0].starts_with("Exit <B>"));
+        assert!(records[1].starts_with("Enter <C>"));
+    }
+}
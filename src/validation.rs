@@ -0,0 +1,519 @@
+//! Static validation of a FSM model.\
+//! Checks structural constraints of the SCXML specification that the interpreter relies on.
+//! [Fsm::interpret] refuses to start a FSM that has validation errors.
+//! Use [validate] to check documents without running them.
+//!
+//! The "initial" attribute together with an \<initial\> element and the exclusive attributes
+//! of \<send\> are already rejected by the reader, \<send\> is checked here again for FSMs that
+//! are created by other means.
+//! Static types of \<send\> can be checked with [validate_send_types].\
+//! \<raise\> and \<send\> inside \<finalize\> are rejected by the reader only if they are direct
+//! children, nested content (e.g. inside \<if\>) is checked here.
+
+use std::fmt;
+use std::fmt::{Display, Formatter};
+
+use crate::executable_content::{
+    ForEach, If, Script, SendParameters, TYPE_FOREACH, TYPE_IF, TYPE_NAMES, TYPE_RAISE, TYPE_SCRIPT, TYPE_SEND,
+};
+use crate::fsm::{DocumentId, ExecutableContentId, Fsm, HistoryType, State, StateId, Transition};
+
+/// A violated constraint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationError {
+    /// Name of the element, e.g. "transition".
+    pub element: String,
+    /// Id of the element or of the enclosing state.
+    pub name: String,
+    /// Position of the element in document order. 0 if not known.
+    pub doc_id: DocumentId,
+    pub message: String,
+}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(
+            f,
+            "<{}> '{}' (#{}): {}",
+            self.element, self.name, self.doc_id, self.message
+        )
+    }
+}
+
+fn add_error(errors: &mut Vec<ValidationError>, element: &str, name: &str, doc_id: DocumentId, message: String) {
+    errors.push(ValidationError {
+        element: element.to_string(),
+        name: name.to_string(),
+        doc_id,
+        message,
+    });
+}
+
+/// W3C says:\
+/// Executable content inside \<finalize\> MUST NOT raise events or invoke external actions.
+/// In particular, the \<send\> and \<raise\> elements MUST NOT occur.
+fn check_finalize_content(
+    fsm: &Fsm,
+    errors: &mut Vec<ValidationError>,
+    state_name: &str,
+    doc_id: DocumentId,
+    content_id: ExecutableContentId,
+) {
+    let content = match fsm.executableContent.get(&content_id) {
+        None => return,
+        Some(content) => content,
+    };
+    for ec in content {
+        let any = ec.as_ref().as_any();
+        match ec.get_type() {
+            TYPE_RAISE | TYPE_SEND => add_error(
+                errors,
+                "finalize",
+                state_name,
+                doc_id,
+                format!(
+                    "<{}> must not occur inside <finalize>",
+                    TYPE_NAMES[ec.get_type() as usize]
+                ),
+            ),
+            TYPE_IF => {
+                if let Some(if_content) = any.downcast_ref::<If>() {
+                    check_finalize_content(fsm, errors, state_name, doc_id, if_content.content);
+                    check_finalize_content(fsm, errors, state_name, doc_id, if_content.else_content);
+                }
+            }
+            TYPE_FOREACH => {
+                if let Some(for_each) = any.downcast_ref::<ForEach>() {
+                    check_finalize_content(fsm, errors, state_name, doc_id, for_each.content);
+                }
+            }
+            TYPE_SCRIPT => {
+                if let Some(script) = any.downcast_ref::<Script>() {
+                    for id in &script.content {
+                        check_finalize_content(fsm, errors, state_name, doc_id, *id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// True if "state" is a proper descendant of "ancestor".
+fn is_descendant(fsm: &Fsm, state: StateId, ancestor: StateId) -> bool {
+    let mut current = fsm.get_state_by_id(state).parent;
+    while current != 0 {
+        if current == ancestor {
+            return true;
+        }
+        current = fsm.get_state_by_id(current).parent;
+    }
+    false
+}
+
+/// Gets the nearest state that is the state itself or an ancestor of both states.
+fn common_ancestor(fsm: &Fsm, state1: StateId, state2: StateId) -> StateId {
+    let mut current = state1;
+    while current != 0 {
+        if current == state2 || is_descendant(fsm, state2, current) {
+            return current;
+        }
+        current = fsm.get_state_by_id(current).parent;
+    }
+    0
+}
+
+/// W3C says:\
+/// \<scxml\> initial: A legal state specification. See 3.11 Legal State Configurations and
+/// Specifications for details.\
+/// \<state\> initial: ... MUST NOT be specified in conjunction with the \<initial\> element.
+/// MUST NOT occur in atomic states.\
+/// The initial states must be descendants of the state and must not exclude each other,
+/// i.e. two initial states need to be in different regions of a parallel state.
+fn check_initial_targets(fsm: &Fsm, errors: &mut Vec<ValidationError>, state: &State) {
+    let transition = fsm.get_transition_by_id(state.initial);
+    let element = if state.id == fsm.pseudo_root {
+        "scxml"
+    } else {
+        "state"
+    };
+    for target in &transition.target {
+        let target_state = fsm.get_state_by_id(*target);
+        if !is_descendant(fsm, *target, state.id) {
+            add_error(
+                errors,
+                element,
+                state.name.as_str(),
+                transition.doc_id,
+                format!("Initial state '{}' is not a descendant", target_state.name),
+            );
+            continue;
+        }
+    }
+    check_exclusive_targets(
+        fsm,
+        errors,
+        element,
+        state.name.as_str(),
+        transition,
+        "Initial",
+    );
+}
+
+/// W3C says:\
+/// We therefore define a legal state specification to be a set of states such that 1) no state is an
+/// ancestor of any other state on the list, and 2) a full legal state configuration results when all
+/// ancestors and default initial descendants have been added.\
+/// So each pair of states needs to be in different regions of a parallel state.
+fn check_exclusive_targets(
+    fsm: &Fsm,
+    errors: &mut Vec<ValidationError>,
+    element: &str,
+    name: &str,
+    transition: &Transition,
+    kind: &str,
+) {
+    for (idx, target) in transition.target.iter().enumerate() {
+        for other in &transition.target[idx + 1..] {
+            let ancestor = common_ancestor(fsm, *target, *other);
+            if ancestor == 0 || ancestor == *target || ancestor == *other || !fsm.get_state_by_id(ancestor).is_parallel
+            {
+                add_error(
+                    errors,
+                    element,
+                    name,
+                    transition.doc_id,
+                    format!(
+                        "{} states '{}' and '{}' are not in different regions of a parallel state",
+                        kind,
+                        fsm.get_state_by_id(*target).name,
+                        fsm.get_state_by_id(*other).name
+                    ),
+                );
+            }
+        }
+    }
+}
+
+/// Checks the FSM and returns all violations. An empty list means the FSM is valid.
+pub fn validate(fsm: &Fsm) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+
+    for state in &fsm.states {
+        if state.doc_id == 0 {
+            // The state was only referenced, report the referencing transitions.
+            let mut referenced = false;
+            for t in fsm.transitions.values() {
+                if t.target.contains(&state.id) {
+                    referenced = true;
+                    let source = fsm.get_state_by_id(t.source);
+                    add_error(
+                        &mut errors,
+                        "transition",
+                        source.name.as_str(),
+                        t.doc_id,
+                        format!("Target state '{}' is not declared", state.name),
+                    );
+                }
+            }
+            if !referenced {
+                add_error(
+                    &mut errors,
+                    "state",
+                    state.name.as_str(),
+                    0,
+                    "Referenced state is not declared".to_string(),
+                );
+            }
+            continue;
+        }
+        if state.is_final && !state.states.is_empty() {
+            add_error(
+                &mut errors,
+                "final",
+                state.name.as_str(),
+                state.doc_id,
+                "Final states must not contain states".to_string(),
+            );
+        }
+        if state.initial > 0 {
+            check_initial_targets(fsm, &mut errors, state);
+        }
+        for tid in state.transitions.iterator() {
+            check_exclusive_targets(
+                fsm,
+                &mut errors,
+                "transition",
+                state.name.as_str(),
+                fsm.get_transition_by_id(*tid),
+                "Target",
+            );
+        }
+        if state.history_type != HistoryType::None && state.transitions.size() != 1 {
+            add_error(
+                &mut errors,
+                "history",
+                state.name.as_str(),
+                state.doc_id,
+                format!(
+                    "History states need exactly one default transition, found {}",
+                    state.transitions.size()
+                ),
+            );
+        }
+        for invoke in state.invoke.iterator() {
+            if !invoke.invoke_id.is_empty() && !invoke.external_id_location.is_empty() {
+                add_error(
+                    &mut errors,
+                    "invoke",
+                    state.name.as_str(),
+                    invoke.doc_id,
+                    "Attributes 'id' and 'idlocation' must not occur both".to_string(),
+                );
+            }
+            check_finalize_content(
+                fsm,
+                &mut errors,
+                state.name.as_str(),
+                invoke.doc_id,
+                invoke.finalize,
+            );
+        }
+    }
+
+    let mut content_ids: Vec<&ExecutableContentId> = fsm.executableContent.keys().collect();
+    content_ids.sort();
+    for content_id in content_ids {
+        for ec in &fsm.executableContent[content_id] {
+            if ec.get_type() != TYPE_SEND {
+                continue;
+            }
+            if let Some(send) = ec.as_ref().as_any().downcast_ref::<SendParameters>() {
+                let exclusive = [
+                    (
+                        !send.event.is_empty(),
+                        !send.event_expr.is_empty(),
+                        "event",
+                        "eventexpr",
+                    ),
+                    (
+                        !send.target.is_empty(),
+                        !send.target_expr.is_empty(),
+                        "target",
+                        "targetexpr",
+                    ),
+                    (
+                        !send.type_value.is_empty(),
+                        !send.type_expr.is_empty(),
+                        "type",
+                        "typeexpr",
+                    ),
+                    (
+                        !send.name.is_empty(),
+                        !send.name_location.is_empty(),
+                        "id",
+                        "idlocation",
+                    ),
+                    (
+                        send.delay_ms > 0,
+                        !send.delay_expr.is_empty(),
+                        "delay",
+                        "delayexpr",
+                    ),
+                ];
+                for (first, second, first_name, second_name) in exclusive {
+                    if first && second {
+                        add_error(
+                            &mut errors,
+                            "send",
+                            send.parent_state_name.as_str(),
+                            0,
+                            format!(
+                                "Attributes '{}' and '{}' must not occur both (content #{})",
+                                first_name, second_name, content_id
+                            ),
+                        );
+                    }
+                }
+            }
+        }
+    }
+    errors
+}
+
+/// Checks the static "type" of all \<send\> elements against the types of the known I/O processors,
+/// see [crate::fsm_executor::FsmExecutor::event_io_processor_types].\
+/// Not part of [validate]: W3C test 199 expects that an unknown type raises "error.execution" when the
+/// \<send\> is executed, so this check is only applied on request.
+pub fn validate_send_types(fsm: &Fsm, io_processor_types: &[String]) -> Vec<ValidationError> {
+    let mut errors = Vec::new();
+    let mut content_ids: Vec<&ExecutableContentId> = fsm.executableContent.keys().collect();
+    content_ids.sort();
+    for content_id in content_ids {
+        for ec in &fsm.executableContent[content_id] {
+            if ec.get_type() != TYPE_SEND {
+                continue;
+            }
+            if let Some(send) = ec.as_ref().as_any().downcast_ref::<SendParameters>() {
+                let type_value = send.type_value.to_string();
+                if !send.type_value.is_empty() && !io_processor_types.contains(&type_value) {
+                    add_error(
+                        &mut errors,
+                        "send",
+                        send.parent_state_name.as_str(),
+                        0,
+                        format!(
+                            "Type '{}' is not supported (content #{})",
+                            type_value, content_id
+                        ),
+                    );
+                }
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use std::time::Duration;
+
+    #[cfg(feature = "xml")]
+    use crate::actions::ActionWrapper;
+    #[cfg(feature = "xml")]
+    use crate::fsm::{start_fsm_with_data_and_finish_mode, FinishMode};
+    #[cfg(feature = "xml")]
+    use crate::fsm_executor::FsmExecutor;
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "xml")]
+    use crate::validation::{validate, validate_send_types};
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn invalid_documents_are_reported_and_not_started() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='null'>
+              <state id='Main'>
+                <history id='H'/>
+                <invoke id='child' idlocation='childId' type='scxml'/>
+                <invoke id='other' type='scxml'>
+                  <finalize>
+                    <if cond='true'><raise event='notAllowed'/></if>
+                  </finalize>
+                </invoke>
+                <state id='A'>
+                  <transition event='go' target='Nowhere'/>
+                </state>
+              </state>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let errors = validate(&fsm);
+        let mut messages: Vec<String> = errors
+            .iter()
+            .map(|e| format!("{} {}: {}", e.element, e.name, e.message))
+            .collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            vec![
+                "finalize Main: <raise> must not occur inside <finalize>",
+                "history H: History states need exactly one default transition, found 0",
+                "invoke Main: Attributes 'id' and 'idlocation' must not occur both",
+                "transition A: Target state 'Nowhere' is not declared",
+            ]
+        );
+        assert!(errors.iter().all(|e| e.doc_id > 0));
+
+        let session = start_fsm_with_data_and_finish_mode(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(FsmExecutor::new_without_io_processor()),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        );
+        let result = session.wait_for_completion(Duration::from_millis(2000));
+        assert!(result.is_some(), "Session shall finish");
+        let result = result.unwrap();
+        assert!(!result.final_state_reached);
+        assert_eq!(result.final_configuration, Some(Vec::new()));
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn illegal_initial_states_are_reported() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='A1 B2' datamodel='null'>
+              <parallel id='P'>
+                <state id='A' initial='A1 A2'>
+                  <state id='A1'/>
+                  <state id='A2'/>
+                </state>
+                <state id='B' initial='B2 B2x'>
+                  <state id='B1'/>
+                  <state id='B2'>
+                    <state id='B2x'/>
+                  </state>
+                </state>
+                <state id='C' initial='A1'>
+                  <state id='C1'/>
+                </state>
+              </parallel>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        let mut messages: Vec<String> = validate(&fsm)
+            .iter()
+            .map(|e| format!("{} {}: {}", e.element, e.name, e.message))
+            .collect();
+        messages.sort();
+        assert_eq!(
+            messages,
+            vec![
+                "state A: Initial states 'A1' and 'A2' are not in different regions of a parallel state",
+                "state B: Initial states 'B2' and 'B2x' are not in different regions of a parallel state",
+                "state C: Initial state 'A1' is not a descendant",
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn unknown_send_types_are_reported() {
+        let fsm = scxml_reader::parse_from_xml(
+            r##"<scxml initial='Main' datamodel='null'>
+              <state id='Main'>
+                <onentry>
+                  <send event='a' type='scxml'/>
+                  <send event='b' type='http://www.w3.org/TR/scxml/#SCXMLEventProcessor'/>
+                  <send event='c'/>
+                  <send event='d' type='unknown'/>
+                </onentry>
+              </state>
+            </scxml>"##
+                .to_string(),
+        )
+        .unwrap();
+
+        // Unknown types are reported when the send is executed.
+        assert!(validate(&fsm).is_empty());
+
+        let types = FsmExecutor::new_without_io_processor().event_io_processor_types();
+        let messages: Vec<String> = validate_send_types(&fsm, &types)
+            .iter()
+            .map(|e| format!("{} {}: {}", e.element, e.name, e.message))
+            .collect();
+        assert_eq!(messages.len(), 1);
+        assert!(
+            messages[0].starts_with("send Main: Type 'unknown' is not supported"),
+            "{}",
+            messages[0]
+        );
+    }
+}
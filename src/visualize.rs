@@ -0,0 +1,395 @@
+//! Export of a FSM as diagram for documentation and reviews.\
+//! [to_dot] creates a Graphviz DOT graph, [to_mermaid] a Mermaid state diagram.
+//!
+//! Compound states are drawn as clusters (DOT) or composite states (Mermaid), parallel states
+//! with dashed borders (DOT) or separated regions (Mermaid).
+//! Initial states are marked by an edge from a start point, final states by a double border (DOT)
+//! or an edge to the end point (Mermaid). History states are drawn as "H" (shallow) or "H*" (deep).
+//! Transitions are labeled with the event descriptors and the condition.
+//!
+//! The "highlighted" variants colour the given states, e.g. the active configuration.
+
+use std::fmt::Write;
+
+use crate::fsm::{Fsm, HistoryType, State, StateId, Transition};
+
+/// Fill colour of highlighted states.
+const HIGHLIGHT_COLOR: &str = "#99ccff";
+
+/// Creates a Graphviz DOT graph of the FSM.
+pub fn to_dot(fsm: &Fsm) -> String {
+    to_dot_highlighted(fsm, &[])
+}
+
+/// Creates a Graphviz DOT graph of the FSM with the given states coloured.
+pub fn to_dot_highlighted(fsm: &Fsm, highlight: &[StateId]) -> String {
+    let mut dot = String::new();
+    let _ = writeln!(dot, "digraph \"{}\" {{", escape_dot(fsm.name.as_str()));
+    dot.push_str("  compound=true;\n");
+    dot.push_str("  node [shape=box, style=rounded];\n");
+    if fsm.pseudo_root > 0 {
+        let root = fsm.get_state_by_id(fsm.pseudo_root);
+        write_dot_content(fsm, root, highlight, 1, &mut dot);
+        for state in &fsm.states {
+            for transition_id in state.transitions.iterator() {
+                write_dot_transition(fsm, fsm.get_transition_by_id(*transition_id), &mut dot);
+            }
+        }
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Creates a Mermaid state diagram of the FSM.
+pub fn to_mermaid(fsm: &Fsm) -> String {
+    to_mermaid_highlighted(fsm, &[])
+}
+
+/// Creates a Mermaid state diagram of the FSM with the given states coloured.
+pub fn to_mermaid_highlighted(fsm: &Fsm, highlight: &[StateId]) -> String {
+    let mut mermaid = String::from("stateDiagram-v2\n");
+    if fsm.pseudo_root > 0 {
+        let root = fsm.get_state_by_id(fsm.pseudo_root);
+        write_mermaid_content(fsm, root, 1, &mut mermaid);
+        for state in &fsm.states {
+            for transition_id in state.transitions.iterator() {
+                let transition = fsm.get_transition_by_id(*transition_id);
+                let label = escape_mermaid(transition_label(transition).as_str());
+                let source = node_id(transition.source);
+                let targets = if transition.target.is_empty() {
+                    // Targetless transitions don't change the configuration.
+                    vec![transition.source]
+                } else {
+                    transition.target.clone()
+                };
+                for target in targets {
+                    let _ = write!(mermaid, "  {} --> {}", source, node_id(target));
+                    if !label.is_empty() {
+                        let _ = write!(mermaid, " : {}", label);
+                    }
+                    mermaid.push('\n');
+                }
+            }
+        }
+        let highlighted: Vec<String> = highlight
+            .iter()
+            .filter(|id| **id != fsm.pseudo_root)
+            .map(|id| node_id(*id))
+            .collect();
+        if !highlighted.is_empty() {
+            let _ = writeln!(mermaid, "  classDef highlight fill:{}", HIGHLIGHT_COLOR);
+            let _ = writeln!(mermaid, "  class {} highlight", highlighted.join(","));
+        }
+    }
+    mermaid
+}
+
+fn node_id(state_id: StateId) -> String {
+    format!("s{}", state_id)
+}
+
+fn is_compound(state: &State) -> bool {
+    !state.states.is_empty() || state.history.size() > 0
+}
+
+/// Events and condition of the transition, e.g. "go error.* \[x > 1\]".
+fn transition_label(transition: &Transition) -> String {
+    let mut label = transition.events.join(" ");
+    if !transition.cond.is_empty() {
+        if !label.is_empty() {
+            label.push(' ');
+        }
+        let _ = write!(label, "[{}]", transition.cond.as_script());
+    }
+    label
+}
+
+fn history_label(state: &State) -> &'static str {
+    match state.history_type {
+        HistoryType::Deep => "H*",
+        _ => "H",
+    }
+}
+
+fn indent(depth: usize) -> String {
+    "  ".repeat(depth)
+}
+
+/// Escapes a text for a quoted DOT string.
+fn escape_dot(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => {}
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escapes a text for Mermaid labels via entity codes.
+fn escape_mermaid(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '#' => escaped.push_str("#35;"),
+            ';' => escaped.push_str("#59;"),
+            '"' => escaped.push_str("#quot;"),
+            '<' => escaped.push_str("#lt;"),
+            '>' => escaped.push_str("#gt;"),
+            '{' => escaped.push_str("#123;"),
+            '}' => escaped.push_str("#125;"),
+            '\n' | '\r' => escaped.push(' '),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn dot_fill(state: &State, highlight: &[StateId]) -> &'static str {
+    if highlight.contains(&state.id) {
+        ", style=\"rounded,filled\", fillcolor=\"#99ccff\""
+    } else {
+        ""
+    }
+}
+
+/// Writes the initial marker, the child states and the history states of the state.
+fn write_dot_content(fsm: &Fsm, state: &State, highlight: &[StateId], depth: usize, dot: &mut String) {
+    let pad = indent(depth);
+    if !state.is_parallel && state.initial > 0 {
+        let initial_id = format!("{}_initial", node_id(state.id));
+        let _ = writeln!(dot, "{}\"{}\" [shape=point];", pad, initial_id);
+        for target in &fsm.get_transition_by_id(state.initial).target {
+            let _ = write!(dot, "{}\"{}\" -> \"{}\"", pad, initial_id, node_id(*target));
+            let target_state = fsm.get_state_by_id(*target);
+            if is_compound(target_state) {
+                let _ = write!(dot, " [lhead=\"cluster_{}\"]", node_id(*target));
+            }
+            dot.push_str(";\n");
+        }
+    }
+    for child in &state.states {
+        write_dot_state(fsm, fsm.get_state_by_id(*child), highlight, depth, dot);
+    }
+    for history in state.history.iterator() {
+        let history_state = fsm.get_state_by_id(*history);
+        let _ = writeln!(
+            dot,
+            "{}\"{}\" [shape=circle, label=\"{}\"];",
+            pad,
+            node_id(history_state.id),
+            history_label(history_state)
+        );
+    }
+}
+
+fn write_dot_state(fsm: &Fsm, state: &State, highlight: &[StateId], depth: usize, dot: &mut String) {
+    let pad = indent(depth);
+    let name = escape_dot(state.name.as_str());
+    if is_compound(state) {
+        let _ = writeln!(dot, "{}subgraph \"cluster_{}\" {{", pad, node_id(state.id));
+        let _ = writeln!(dot, "{}  label=\"{}\";", pad, name);
+        let style = if state.is_parallel {
+            "rounded,dashed"
+        } else {
+            "rounded"
+        };
+        if highlight.contains(&state.id) {
+            let _ = writeln!(
+                dot,
+                "{}  style=\"{},filled\";\n{}  fillcolor=\"{}\";",
+                pad, style, pad, HIGHLIGHT_COLOR
+            );
+        } else {
+            let _ = writeln!(dot, "{}  style=\"{}\";", pad, style);
+        }
+        // Anchor for transitions from and to the cluster.
+        let _ = writeln!(
+            dot,
+            "{}  \"{}\" [shape=point, style=invis];",
+            pad,
+            node_id(state.id)
+        );
+        write_dot_content(fsm, state, highlight, depth + 1, dot);
+        let _ = writeln!(dot, "{}}}", pad);
+    } else if state.is_final {
+        let _ = writeln!(
+            dot,
+            "{}\"{}\" [label=\"{}\", peripheries=2{}];",
+            pad,
+            node_id(state.id),
+            name,
+            dot_fill(state, highlight)
+        );
+    } else {
+        let _ = writeln!(
+            dot,
+            "{}\"{}\" [label=\"{}\"{}];",
+            pad,
+            node_id(state.id),
+            name,
+            dot_fill(state, highlight)
+        );
+    }
+}
+
+fn write_dot_transition(fsm: &Fsm, transition: &Transition, dot: &mut String) {
+    let label = escape_dot(transition_label(transition).as_str());
+    let source = fsm.get_state_by_id(transition.source);
+    if transition.target.is_empty() {
+        // Targetless transitions don't change the configuration.
+        let _ = writeln!(
+            dot,
+            "  \"{}\" -> \"{}\" [label=\"{}\", style=dotted];",
+            node_id(source.id),
+            node_id(source.id),
+            label
+        );
+        return;
+    }
+    for target_id in &transition.target {
+        let target = fsm.get_state_by_id(*target_id);
+        let mut attributes = vec![format!("label=\"{}\"", label)];
+        if is_compound(source) && source.id != target.id {
+            attributes.push(format!("ltail=\"cluster_{}\"", node_id(source.id)));
+        }
+        if is_compound(target) && source.id != target.id {
+            attributes.push(format!("lhead=\"cluster_{}\"", node_id(target.id)));
+        }
+        let _ = writeln!(
+            dot,
+            "  \"{}\" -> \"{}\" [{}];",
+            node_id(source.id),
+            node_id(target.id),
+            attributes.join(", ")
+        );
+    }
+}
+
+/// Writes the declarations of the child states, the initial marker and the final markers.
+fn write_mermaid_content(fsm: &Fsm, state: &State, depth: usize, mermaid: &mut String) {
+    let pad = indent(depth);
+    if !state.is_parallel && state.initial > 0 {
+        for target in &fsm.get_transition_by_id(state.initial).target {
+            let _ = writeln!(mermaid, "{}[*] --> {}", pad, node_id(*target));
+        }
+    }
+    for (idx, child) in state.states.iter().enumerate() {
+        if state.is_parallel && idx > 0 {
+            // Separates the regions of a parallel state.
+            let _ = writeln!(mermaid, "{}--", pad);
+        }
+        let child_state = fsm.get_state_by_id(*child);
+        let _ = writeln!(
+            mermaid,
+            "{}state \"{}\" as {}",
+            pad,
+            escape_mermaid(child_state.name.as_str()),
+            node_id(child_state.id)
+        );
+        if is_compound(child_state) {
+            let _ = writeln!(mermaid, "{}state {} {{", pad, node_id(child_state.id));
+            write_mermaid_content(fsm, child_state, depth + 1, mermaid);
+            let _ = writeln!(mermaid, "{}}}", pad);
+        } else if child_state.is_final {
+            let _ = writeln!(mermaid, "{}{} --> [*]", pad, node_id(child_state.id));
+        }
+    }
+    for history in state.history.iterator() {
+        let history_state = fsm.get_state_by_id(*history);
+        let _ = writeln!(
+            mermaid,
+            "{}state \"{}\" as {}",
+            pad,
+            history_label(history_state),
+            node_id(history_state.id)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "xml")]
+    use crate::scxml_reader;
+    #[cfg(feature = "xml")]
+    use crate::visualize::{to_dot, to_dot_highlighted, to_mermaid};
+
+    #[cfg(feature = "xml")]
+    const DOCUMENT: &str = r##"<scxml name='Sample' initial='Main' datamodel='ecmascript'>
+      <state id='Main'>
+        <history id='Hist' type='deep'>
+          <transition target='Work'/>
+        </history>
+        <parallel id='Work'>
+          <state id='Left'>
+            <state id='LeftBusy'>
+              <transition event='left.done' cond='x &lt; "a"' target='LeftDone'/>
+            </state>
+            <final id='LeftDone'/>
+          </state>
+          <state id='Right'>
+            <transition event='tick'/>
+          </state>
+        </parallel>
+        <transition event='stop error.*' target='Done'/>
+      </state>
+      <final id='Done'/>
+    </scxml>"##;
+
+    #[cfg(feature = "xml")]
+    const EXPECTED_DOT: &str = r##"digraph "Sample" {
+  compound=true;
+  node [shape=box, style=rounded];
+  "s1_initial" [shape=point];
+  "s1_initial" -> "s2" [lhead="cluster_s2"];
+  subgraph "cluster_s2" {
+    label="Main";
+    style="rounded";
+    "s2" [shape=point, style=invis];
+    "s2_initial" [shape=point];
+    "s2_initial" -> "s4" [lhead="cluster_s4"];
+    subgraph "cluster_s4" {
+      label="Work";
+      style="rounded,dashed";
+      "s4" [shape=point, style=invis];
+      subgraph "cluster_s5" {
+        label="Left";
+        style="rounded";
+        "s5" [shape=point, style=invis];
+        "s5_initial" [shape=point];
+        "s5_initial" -> "s6";
+        "s6" [label="LeftBusy", style="rounded,filled", fillcolor="#99ccff"];
+        "s7" [label="LeftDone", peripheries=2];
+      }
+      "s8" [label="Right"];
+    }
+    "s3" [shape=circle, label="H*"];
+  }
+  "s9" [label="Done", peripheries=2];
+  "s2" -> "s9" [label="stop error", ltail="cluster_s2"];
+  "s3" -> "s4" [label="", lhead="cluster_s4"];
+  "s6" -> "s7" [label="left.done [x < \"a\"]"];
+  "s8" -> "s8" [label="tick", style=dotted];
+}
+"##;
+
+    #[test]
+    #[cfg(feature = "xml")]
+    fn dot_output_of_parallel_and_history() {
+        let fsm = scxml_reader::parse_from_xml(DOCUMENT.to_string()).unwrap();
+        let busy = *fsm.statesNames.get("LeftBusy").unwrap();
+
+        assert_eq!(to_dot_highlighted(&fsm, &[busy]), EXPECTED_DOT);
+        assert!(!to_dot(&fsm).contains("fillcolor"));
+
+        let mermaid = to_mermaid(&fsm);
+        assert!(mermaid.starts_with("stateDiagram-v2\n  [*] --> s2\n"));
+        assert!(mermaid.contains("      --\n      state \"Right\" as s8\n"));
+        assert!(mermaid.contains("    state \"H*\" as s3\n"));
+        assert!(mermaid.contains("  s6 --> s7 : left.done [x #lt; #quot;a#quot;]\n"));
+    }
+}
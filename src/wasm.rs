@@ -0,0 +1,183 @@
+//! JavaScript API to execute SCXML documents in the browser (feature "wasm").\
+//! Build for example with
+//! `cargo build --lib --target wasm32-unknown-unknown --no-default-features --features wasm,ECMAScript`
+//! and create the bindings with `wasm-bindgen`.
+//!
+//! Browsers don't allow to block threads, so the FSM is executed on the caller's thread
+//! (see [crate::fsm_stepper]). All pending events are processed before a method returns.\
+//! Delayed \<send\> uses a [TestClock]. The host drives the time via [WasmFsm::advance],
+//! e.g. from "requestAnimationFrame" or "setInterval".
+//!
+//! ```js
+//! const fsm = new WasmFsm();
+//! fsm.load(xml);
+//! fsm.onTrace((entered, exited) => console.log("entered", entered, "exited", exited));
+//! fsm.start();
+//! fsm.send("go", '{"speed": 5}');
+//! fsm.advance(100);
+//! console.log(fsm.configuration());
+//! ```
+
+use std::sync::Arc;
+
+use wasm_bindgen::prelude::*;
+
+use crate::actions::ActionWrapper;
+use crate::clock::TestClock;
+use crate::datamodel::{create_data_arc, json_to_data};
+use crate::fsm::{Event, FinishMode, Fsm};
+use crate::fsm_executor::FsmExecutor;
+use crate::fsm_stepper::{start_fsm_stepper, FsmStepper, StepResult};
+use crate::scxml_reader;
+
+/// A FSM, executed step-driven on the thread of the caller.
+#[wasm_bindgen]
+pub struct WasmFsm {
+    fsm: Option<Box<Fsm>>,
+    stepper: Option<FsmStepper>,
+    clock: TestClock,
+    trace: Option<js_sys::Function>,
+}
+
+impl Default for WasmFsm {
+    fn default() -> Self {
+        WasmFsm::new()
+    }
+}
+
+#[wasm_bindgen]
+impl WasmFsm {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmFsm {
+        WasmFsm {
+            fsm: None,
+            stepper: None,
+            clock: TestClock::new(chrono::Utc::now().timestamp_millis()),
+            trace: None,
+        }
+    }
+
+    /// Parses the SCXML document. Replaces a FSM that was loaded before, but not yet started.
+    pub fn load(&mut self, xml: &str) -> Result<(), String> {
+        if self.stepper.is_some() {
+            return Err("FSM is already started".to_string());
+        }
+        self.fsm = Some(scxml_reader::parse_from_xml(xml.to_string())?);
+        Ok(())
+    }
+
+    /// Starts the loaded FSM and enters the initial configuration.
+    pub fn start(&mut self) -> Result<(), String> {
+        let fsm = self.fsm.take().ok_or("No FSM loaded")?;
+        let mut executor = FsmExecutor::new_without_io_processor();
+        executor.set_clock(Arc::new(self.clock.clone()));
+        self.stepper = Some(start_fsm_stepper(
+            fsm,
+            ActionWrapper::new(),
+            Box::new(executor),
+            &Vec::new(),
+            FinishMode::KEEP_CONFIGURATION,
+        ));
+        self.run_pending();
+        Ok(())
+    }
+
+    /// Sends an external event. "json_data" is optional, if given it is used as "_event.data".
+    pub fn send(&mut self, name: &str, json_data: Option<String>) -> Result<(), String> {
+        let mut event = Event::new_simple(name);
+        if let Some(json_data) = json_data {
+            let value: serde_json::Value =
+                serde_json::from_str(json_data.as_str()).map_err(|e| format!("Invalid data: {}", e))?;
+            event.content = Some(create_data_arc(json_to_data(&value)));
+        }
+        let stepper = self.stepper.as_mut().ok_or("FSM is not started")?;
+        let result = stepper.step(Some(event));
+        self.trace_step(&result);
+        self.run_pending();
+        Ok(())
+    }
+
+    /// Advances the time of delayed sends by "delta_ms" milliseconds.
+    pub fn advance(&mut self, delta_ms: f64) {
+        self.clock.advance(delta_ms as i64);
+        self.run_pending();
+    }
+
+    /// Sets the callback that is called with the names of the entered and the exited states
+    /// of each step.
+    #[wasm_bindgen(js_name = onTrace)]
+    pub fn on_trace(&mut self, callback: js_sys::Function) {
+        self.trace = Some(callback);
+    }
+
+    /// Names of the active states. After the FSM has finished, the final configuration.
+    pub fn configuration(&self) -> Vec<String> {
+        match &self.stepper {
+            Some(stepper) => stepper.configuration(),
+            None => Vec::new(),
+        }
+    }
+
+    /// True if the FSM is started and has not finished yet.
+    #[wasm_bindgen(js_name = isRunning)]
+    pub fn is_running(&self) -> bool {
+        self.stepper.as_ref().is_some_and(|s| s.is_running())
+    }
+
+    fn run_pending(&mut self) {
+        if let Some(stepper) = self.stepper.as_mut() {
+            for result in stepper.run_pending() {
+                self.trace_step(&result);
+            }
+        }
+    }
+
+    fn trace_step(&self, result: &StepResult) {
+        if let Some(trace) = &self.trace {
+            if !result.entered.is_empty() || !result.exited.is_empty() {
+                let entered: js_sys::Array = result.entered.iter().map(JsValue::from).collect();
+                let exited: js_sys::Array = result.exited.iter().map(JsValue::from).collect();
+                let _ = trace.call2(&JsValue::NULL, &entered, &exited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::wasm::WasmFsm;
+
+    #[test]
+    #[cfg(feature = "ECMAScript")]
+    fn fsm_is_driven_by_calls() {
+        let mut fsm = WasmFsm::new();
+        assert!(fsm.start().is_err(), "Start requires a loaded FSM");
+        assert!(fsm
+            .load(
+                r##"<scxml initial='Idle' datamodel='ecmascript'>
+                  <state id='Idle'>
+                    <transition event='go' cond='_event.data.speed == 5' target='Moving'/>
+                  </state>
+                  <state id='Moving'>
+                    <onentry><send event='stop' delay='100ms'/></onentry>
+                    <transition event='stop' target='Stopped'/>
+                  </state>
+                  <final id='Stopped'/>
+                </scxml>"##
+            )
+            .is_ok());
+        assert!(fsm.start().is_ok());
+        assert!(fsm.is_running());
+        assert_eq!(fsm.configuration(), vec!["Idle".to_string()]);
+
+        assert!(fsm.send("go", Some("{speed".to_string())).is_err());
+        assert!(fsm.send("go", Some(r#"{"speed": 5}"#.to_string())).is_ok());
+        assert_eq!(fsm.configuration(), vec!["Moving".to_string()]);
+
+        fsm.advance(50.0);
+        assert_eq!(fsm.configuration(), vec!["Moving".to_string()]);
+        fsm.advance(50.0);
+        assert!(!fsm.is_running());
+        assert_eq!(fsm.configuration(), vec!["Stopped".to_string()]);
+    }
+}